@@ -60,8 +60,8 @@ impl FF14Crafter {
         })
     }
 
-    fn win(&self) -> &WindowsController {
-        self.ap.controller_ref::<WindowsController>().unwrap()
+    fn with_win<R>(&self, f: impl FnOnce(&WindowsController) -> R) -> R {
+        self.ap.with_controller(f).unwrap()
     }
 
     fn detect_state(&self) -> anyhow::Result<CraftState> {
@@ -101,7 +101,7 @@ impl FF14Crafter {
         };
         let click_x = rect.x + rect.width / 2;
         let click_y = rect.y + rect.height / 2;
-        self.win().focus_click(click_x, click_y)?;
+        self.with_win(|win| win.focus_click(click_x, click_y))?;
         info!("点击 ({click_x}, {click_y})");
 
         std::thread::sleep(Duration::from_millis(500));
@@ -117,8 +117,7 @@ impl FF14Crafter {
 
         std::thread::sleep(Duration::from_millis(300));
         pb.set_message("执行宏 (R)...");
-        self.win()
-            .focus_press(auto_play::controller::Key::Unicode('r'))?;
+        self.with_win(|win| win.focus_press(auto_play::controller::Key::Unicode('r')))?;
 
         if !self.wait_for_state(CraftState::Ready, CRAFT_FINISH_TIMEOUT, pb, "制作中")? {
             warn!("制作超时");
@@ -10,3 +10,15 @@ pub trait AdbCommand {
 
     fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output>;
 }
+
+impl<C: AdbCommand + ?Sized> AdbCommand for &C {
+    type Output = C::Output;
+
+    fn raw_command(&self) -> String {
+        (**self).raw_command()
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        (**self).handle_response(stream)
+    }
+}
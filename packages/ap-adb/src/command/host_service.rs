@@ -1,4 +1,8 @@
-use crate::{AdbTcpStream, DeviceInfo, error::AdbResult, utils::read_payload_to_string};
+use crate::{
+    AdbTcpStream, DeviceInfo,
+    error::{AdbError, AdbResult},
+    utils::read_payload_to_string,
+};
 
 use super::AdbCommand;
 
@@ -24,6 +28,164 @@ impl AdbCommand for Version {
     }
 }
 
+/// host:kill
+///
+/// The server answers and exits, the connection is closed then.
+#[derive(Default)]
+pub struct Kill;
+
+impl Kill {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl AdbCommand for Kill {
+    type Output = ();
+
+    fn raw_command(&self) -> String {
+        "host:kill".to_string()
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()
+    }
+}
+
+/// host:features or host-serial:<serial-number>:features
+///
+/// The features of the server, i.e. `shell_v2`, `cmd` or `stat_v2`, or the ones
+/// both the server and a device support.
+#[derive(Default)]
+pub struct Features {
+    serial_number: Option<String>,
+}
+
+impl Features {
+    /// Features of the server.
+    pub fn new() -> Self {
+        Self {
+            serial_number: None,
+        }
+    }
+
+    pub fn of_device(serial_number: impl AsRef<str>) -> Self {
+        Self {
+            serial_number: Some(serial_number.as_ref().to_string()),
+        }
+    }
+}
+
+impl AdbCommand for Features {
+    type Output = Vec<String>;
+
+    fn raw_command(&self) -> String {
+        match &self.serial_number {
+            Some(serial_number) => format!("host-serial:{serial_number}:features"),
+            None => "host:features".to_string(),
+        }
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()?;
+        Ok(read_payload_to_string(stream)?
+            .trim()
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+}
+
+/// host:reconnect, host-serial:<serial-number>:reconnect or host:reconnect-offline
+///
+/// Drop the connection to a device so it comes back, i.e. when it went
+/// `offline`. The server answers with what it did, i.e.
+/// `reconnecting emulator-5554 [device]`.
+#[derive(Default)]
+pub struct Reconnect {
+    target: ReconnectTarget,
+}
+
+#[derive(Default)]
+enum ReconnectTarget {
+    /// The only device
+    #[default]
+    Any,
+    Device(String),
+    Offline,
+}
+
+impl Reconnect {
+    /// Reconnect the only device, failing if there are several.
+    pub fn new() -> Self {
+        Self {
+            target: ReconnectTarget::Any,
+        }
+    }
+
+    pub fn of_device(serial_number: impl AsRef<str>) -> Self {
+        Self {
+            target: ReconnectTarget::Device(serial_number.as_ref().to_string()),
+        }
+    }
+
+    /// Reconnect every `offline` device.
+    pub fn offline() -> Self {
+        Self {
+            target: ReconnectTarget::Offline,
+        }
+    }
+}
+
+impl AdbCommand for Reconnect {
+    type Output = String;
+
+    fn raw_command(&self) -> String {
+        match &self.target {
+            ReconnectTarget::Any => "host:reconnect".to_string(),
+            ReconnectTarget::Device(serial_number) => {
+                format!("host-serial:{serial_number}:reconnect")
+            }
+            ReconnectTarget::Offline => "host:reconnect-offline".to_string(),
+        }
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()?;
+        Ok(read_payload_to_string(stream)?.trim().to_string())
+    }
+}
+
+/// host-serial:<serial-number>:get-devpath
+///
+/// Where the device is plugged in on the host, i.e. `usb:1-4`. Devices
+/// connected over the network have none, the server answers `unknown`.
+pub struct DevicePath {
+    serial_number: String,
+}
+
+impl DevicePath {
+    pub fn new(serial_number: impl AsRef<str>) -> Self {
+        Self {
+            serial_number: serial_number.as_ref().to_string(),
+        }
+    }
+}
+
+impl AdbCommand for DevicePath {
+    type Output = String;
+
+    fn raw_command(&self) -> String {
+        format!("host-serial:{}:get-devpath", self.serial_number)
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()?;
+        Ok(read_payload_to_string(stream)?.trim().to_string())
+    }
+}
+
 /// host:devices-l
 pub struct DeviceLong;
 
@@ -76,6 +238,286 @@ impl AdbCommand for Transport {
     }
 }
 
+/// host:track-devices
+///
+/// The server keeps the stream open and sends the whole device list again every
+/// time it changes, see [`Host::track_devices`](crate::host::Host::track_devices).
+#[derive(Default)]
+pub struct TrackDevices;
+
+impl TrackDevices {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl AdbCommand for TrackDevices {
+    type Output = ();
+
+    fn raw_command(&self) -> String {
+        "host:track-devices".to_string()
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()
+    }
+}
+
+/// A forward listed by [`ListForwards`], sockets are in the form of `tcp:<port>`,
+/// `localabstract:<name>`, ...
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForwardInfo {
+    pub serial: String,
+    pub local: String,
+    pub remote: String,
+}
+
+impl TryFrom<&str> for ForwardInfo {
+    type Error = AdbError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        // "serial local remote"
+        let mut parts = value.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(serial), Some(local), Some(remote)) => Ok(Self {
+                serial: serial.to_string(),
+                local: local.to_string(),
+                remote: remote.to_string(),
+            }),
+            _ => Err(AdbError::ParseError(format!(
+                "failed to parse forward from {value}"
+            ))),
+        }
+    }
+}
+
+/// Read the response to a (reverse) forward request, the port allocated if the
+/// listening socket was `tcp:0`.
+fn read_forward_response(stream: &mut AdbTcpStream, listen: &str) -> AdbResult<Option<u16>> {
+    // The first OKAY is for the connection, the second one for the forward
+    stream.check_response_status()?;
+    stream.check_response_status()?;
+    if listen == "tcp:0" {
+        let port = read_payload_to_string(stream)?;
+        let port = port
+            .trim()
+            .parse()
+            .map_err(|_| AdbError::ParseError(format!("invalid port {port}")))?;
+        Ok(Some(port))
+    } else {
+        Ok(None)
+    }
+}
+
+/// host-serial:<serial-number>:forward[:norebind]:<local>;<remote>
+///
+/// Connections to `local` on the host are forwarded to `remote` on the device,
+/// `tcp:0` as `local` picks a free port.
+pub struct Forward {
+    serial_number: String,
+    local: String,
+    remote: String,
+    no_rebind: bool,
+}
+
+impl Forward {
+    pub fn new(
+        serial_number: impl AsRef<str>,
+        local: impl AsRef<str>,
+        remote: impl AsRef<str>,
+    ) -> Self {
+        Self {
+            serial_number: serial_number.as_ref().to_string(),
+            local: local.as_ref().to_string(),
+            remote: remote.as_ref().to_string(),
+            no_rebind: false,
+        }
+    }
+
+    /// Fail if `local` is already forwarded instead of replacing it.
+    pub fn no_rebind(mut self) -> Self {
+        self.no_rebind = true;
+        self
+    }
+}
+
+impl AdbCommand for Forward {
+    /// The port allocated for `tcp:0`
+    type Output = Option<u16>;
+
+    fn raw_command(&self) -> String {
+        format!(
+            "host-serial:{}:forward{}:{};{}",
+            self.serial_number,
+            if self.no_rebind { ":norebind" } else { "" },
+            self.local,
+            self.remote
+        )
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        read_forward_response(stream, &self.local)
+    }
+}
+
+/// host:list-forward or host-serial:<serial-number>:list-forward
+#[derive(Default)]
+pub struct ListForwards {
+    serial_number: Option<String>,
+}
+
+impl ListForwards {
+    /// Forwards of all devices.
+    pub fn new() -> Self {
+        Self {
+            serial_number: None,
+        }
+    }
+
+    pub fn of_device(serial_number: impl AsRef<str>) -> Self {
+        Self {
+            serial_number: Some(serial_number.as_ref().to_string()),
+        }
+    }
+}
+
+impl AdbCommand for ListForwards {
+    type Output = Vec<ForwardInfo>;
+
+    fn raw_command(&self) -> String {
+        match &self.serial_number {
+            Some(serial_number) => format!("host-serial:{serial_number}:list-forward"),
+            None => "host:list-forward".to_string(),
+        }
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()?;
+        read_payload_to_string(stream)?
+            .lines()
+            .map(ForwardInfo::try_from)
+            .collect()
+    }
+}
+
+/// host-serial:<serial-number>:killforward:<local> or
+/// host-serial:<serial-number>:killforward-all
+pub struct KillForward {
+    serial_number: String,
+    local: Option<String>,
+}
+
+impl KillForward {
+    pub fn new(serial_number: impl AsRef<str>, local: impl AsRef<str>) -> Self {
+        Self {
+            serial_number: serial_number.as_ref().to_string(),
+            local: Some(local.as_ref().to_string()),
+        }
+    }
+
+    pub fn all(serial_number: impl AsRef<str>) -> Self {
+        Self {
+            serial_number: serial_number.as_ref().to_string(),
+            local: None,
+        }
+    }
+}
+
+impl AdbCommand for KillForward {
+    type Output = ();
+
+    fn raw_command(&self) -> String {
+        match &self.local {
+            Some(local) => format!("host-serial:{}:killforward:{local}", self.serial_number),
+            None => format!("host-serial:{}:killforward-all", self.serial_number),
+        }
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()?;
+        stream.check_response_status()
+    }
+}
+
+/// reverse:forward[:norebind]:<remote>;<local>
+///
+/// Connections to `remote` on the device are forwarded to `local` on the host,
+/// `tcp:0` as `remote` picks a free port on the device. Unlike the other host
+/// services it has to be executed on a stream transported to the device.
+pub struct ReverseForward {
+    remote: String,
+    local: String,
+    no_rebind: bool,
+}
+
+impl ReverseForward {
+    pub fn new(remote: impl AsRef<str>, local: impl AsRef<str>) -> Self {
+        Self {
+            remote: remote.as_ref().to_string(),
+            local: local.as_ref().to_string(),
+            no_rebind: false,
+        }
+    }
+
+    /// Fail if `remote` is already forwarded instead of replacing it.
+    pub fn no_rebind(mut self) -> Self {
+        self.no_rebind = true;
+        self
+    }
+}
+
+impl AdbCommand for ReverseForward {
+    /// The port allocated for `tcp:0`
+    type Output = Option<u16>;
+
+    fn raw_command(&self) -> String {
+        format!(
+            "reverse:forward{}:{};{}",
+            if self.no_rebind { ":norebind" } else { "" },
+            self.remote,
+            self.local
+        )
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        read_forward_response(stream, &self.remote)
+    }
+}
+
+/// reverse:killforward:<remote> or reverse:killforward-all, executed on a stream
+/// transported to the device like [`ReverseForward`].
+pub struct KillReverse {
+    remote: Option<String>,
+}
+
+impl KillReverse {
+    pub fn new(remote: impl AsRef<str>) -> Self {
+        Self {
+            remote: Some(remote.as_ref().to_string()),
+        }
+    }
+
+    pub fn all() -> Self {
+        Self { remote: None }
+    }
+}
+
+impl AdbCommand for KillReverse {
+    type Output = ();
+
+    fn raw_command(&self) -> String {
+        match &self.remote {
+            Some(remote) => format!("reverse:killforward:{remote}"),
+            None => "reverse:killforward-all".to_string(),
+        }
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()?;
+        stream.check_response_status()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::host;
@@ -95,4 +537,57 @@ mod test {
         let res = host.execute_command(DeviceLong::new());
         println!("{:?}", res)
     }
+
+    #[test]
+    fn test_forward() {
+        use std::{
+            io::{Read, Write},
+            net::TcpListener,
+            thread,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = b"003dhost-serial:emulator-5554:forward:tcp:0;localabstract:minicap";
+            let mut buf = [0; 128];
+            stream.read_exact(&mut buf[..request.len()]).unwrap();
+            assert_eq!(&buf[..request.len()], request);
+            stream.write_all(b"OKAYOKAY000527171").unwrap();
+
+            let (mut stream, _) = listener.accept().unwrap();
+            let request = b"0026host-serial:emulator-5554:list-forward";
+            stream.read_exact(&mut buf[..request.len()]).unwrap();
+            assert_eq!(&buf[..request.len()], request);
+            let forwards = "emulator-5554 tcp:27171 localabstract:minicap\n";
+            write!(stream, "OKAY{:04x}{forwards}", forwards.len()).unwrap();
+        });
+
+        let std::net::SocketAddr::V4(addr) = addr else {
+            unreachable!()
+        };
+        let port = AdbTcpStream::connect(addr)
+            .unwrap()
+            .execute_command(Forward::new(
+                "emulator-5554",
+                "tcp:0",
+                "localabstract:minicap",
+            ))
+            .unwrap();
+        assert_eq!(port, Some(27171));
+        let forwards = AdbTcpStream::connect(addr)
+            .unwrap()
+            .execute_command(ListForwards::of_device("emulator-5554"))
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(
+            forwards,
+            [ForwardInfo {
+                serial: "emulator-5554".to_string(),
+                local: "tcp:27171".to_string(),
+                remote: "localabstract:minicap".to_string(),
+            }]
+        );
+    }
 }
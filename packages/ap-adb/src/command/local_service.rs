@@ -1,9 +1,13 @@
-use std::time::Duration;
+use std::{
+    io::{Read, Write},
+    time::Duration,
+};
 
 use crate::{
     AdbTcpStream,
-    error::AdbResult,
-    utils::{read_to_end, read_to_end_to_string},
+    error::{AdbError, AdbResult},
+    shell::{self, ShellOutput, ShellPacket},
+    utils::{read_exact_to_string, read_to_end, read_to_end_to_string},
 };
 
 use super::AdbCommand;
@@ -36,6 +40,157 @@ impl AdbCommand for ShellCommand {
     }
 }
 
+/// exec:command
+///
+/// Unlike [`ShellCommand`] it does not wait for the command to exit, the stream
+/// stays connected to its stdin and stdout without a pty in between, see
+/// [`Device::exec`](crate::Device::exec).
+pub struct Exec {
+    command: String,
+}
+
+impl Exec {
+    pub fn new(command: impl AsRef<str>) -> Self {
+        Self {
+            command: command.as_ref().to_string(),
+        }
+    }
+}
+
+impl AdbCommand for Exec {
+    type Output = ();
+
+    fn raw_command(&self) -> String {
+        format!("exec:{}", self.command)
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()
+    }
+}
+
+/// exec:logcat -v threadtime [-t lines] [filters]
+///
+/// The stream carries the lines of the log afterwards, read by a
+/// [`LogcatReader`](crate::logcat::LogcatReader). It follows the log unless
+/// [`Logcat::tail`] is set.
+pub struct Logcat {
+    filters: Vec<String>,
+    tail: Option<usize>,
+}
+
+impl Logcat {
+    /// `filter` is a list of `tag:priority` specs separated by spaces, i.e.
+    /// `"Unity:V *:E"`.
+    pub fn new(filter: impl AsRef<str>) -> Self {
+        Self {
+            filters: filter
+                .as_ref()
+                .split_whitespace()
+                .map(str::to_string)
+                .collect(),
+            tail: None,
+        }
+    }
+
+    /// Only print the last `lines` lines and exit.
+    pub fn tail(mut self, lines: usize) -> Self {
+        self.tail = Some(lines);
+        self
+    }
+}
+
+impl AdbCommand for Logcat {
+    type Output = ();
+
+    fn raw_command(&self) -> String {
+        let mut command = "exec:logcat -v threadtime".to_string();
+        if let Some(lines) = self.tail {
+            command.push_str(&format!(" -t {lines}"));
+        }
+        for filter in &self.filters {
+            // `*:E` is globbed by the shell otherwise
+            command.push_str(&format!(" '{filter}'"));
+        }
+        command
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()
+    }
+}
+
+/// shell,v2,raw:command
+///
+/// The stream carries [`shell`](crate::shell) packets afterwards, an empty
+/// command starts an interactive shell, see [`Device::open_shell`](crate::Device::open_shell).
+pub struct ShellV2 {
+    command: String,
+}
+
+impl ShellV2 {
+    pub fn new(command: impl AsRef<str>) -> Self {
+        Self {
+            command: command.as_ref().to_string(),
+        }
+    }
+}
+
+impl AdbCommand for ShellV2 {
+    type Output = ();
+
+    fn raw_command(&self) -> String {
+        format!("shell,v2,raw:{}", self.command)
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()
+    }
+}
+
+/// shell,v2,raw:command
+///
+/// Like [`ShellCommand`] but keeps stderr and the exit code, a non zero exit code
+/// is not an error by itself, see [`ShellOutput::check`].
+pub struct ShellCommandV2 {
+    command: String,
+}
+
+impl ShellCommandV2 {
+    pub fn new(command: impl AsRef<str>) -> Self {
+        Self {
+            command: command.as_ref().to_string(),
+        }
+    }
+}
+
+impl AdbCommand for ShellCommandV2 {
+    type Output = ShellOutput;
+
+    fn raw_command(&self) -> String {
+        format!("shell,v2,raw:{}", self.command)
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()?;
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        loop {
+            match shell::read_packet(stream)? {
+                ShellPacket::Stdout(data) => stdout.extend(data),
+                ShellPacket::Stderr(data) => stderr.extend(data),
+                ShellPacket::Exit(code) => {
+                    return Ok(ShellOutput {
+                        stdout: String::from_utf8_lossy(&stdout).to_string(),
+                        stderr: String::from_utf8_lossy(&stderr).to_string(),
+                        exit_code: code as i32,
+                    });
+                }
+                ShellPacket::Other(..) => {}
+            }
+        }
+    }
+}
+
 /// Png decoded screencap
 ///
 /// `shell:screencap -p`
@@ -69,15 +224,23 @@ impl AdbCommand for ScreenCapPng {
 ///
 /// ## Data Format
 /// ```text
-/// Header (12 bytes, Little Endian):
+/// Header (12 bytes, Little Endian, 16 bytes since Android 9):
 ///   [0..4]   Width  (u32)
 ///   [4..8]   Height (u32)
 ///   [8..12]  Format (u32)
 ///            - 1 = RGBA_8888 (Red, Green, Blue, Alpha)
+///            - 2 = RGBX_8888
+///            - 3 = RGB_888
+///            - 4 = RGB_565
+///            - 5 = BGRA_8888
+///   [12..16] Color space (u32), only in the 16 bytes header
 ///
-/// Pixel Data (width * height * 4 bytes):
+/// Pixel Data (width * height * bytes per pixel):
 ///   Raw pixel bytes
 /// ```
+///
+/// The pixels are converted to RGBA8, other formats fail with
+/// [`AdbError::UnsupportedPixelFormat`].
 pub struct ScreenCapRaw;
 
 impl ScreenCapRaw {
@@ -95,17 +258,72 @@ impl AdbCommand for ScreenCapRaw {
 
     fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
         stream.check_response_status()?;
-        let data = read_to_end(stream)?;
-        let width = u32::from_le_bytes(data[0..4].try_into().unwrap());
-        let height = u32::from_le_bytes(data[4..8].try_into().unwrap());
-        let format = u32::from_le_bytes(data[8..12].try_into().unwrap());
-        assert_eq!(format, 1);
-        let pixel_data = data[12..].to_vec();
-        Ok((width, height, pixel_data))
+        parse_raw_screencap(read_to_end(stream)?)
     }
 }
 
+/// Turn the output of `screencap` into RGBA8 pixels, see [`ScreenCapRaw`].
+pub fn parse_raw_screencap(mut data: Vec<u8>) -> AdbResult<(u32, u32, Vec<u8>)> {
+    if data.len() < 12 {
+        return Err(AdbError::ProtocolError(format!(
+            "screencap output is only {} bytes",
+            data.len()
+        )));
+    }
+    let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+    let (width, height, format) = (u32_at(0), u32_at(4), u32_at(8));
+    let bytes_per_pixel = match format {
+        1 | 2 | 5 => 4,
+        3 => 3,
+        4 => 2,
+        format => return Err(AdbError::UnsupportedPixelFormat(format)),
+    };
+    let len = width as usize * height as usize * bytes_per_pixel;
+    let header = match data.len().checked_sub(len) {
+        Some(header @ (12 | 16)) => header,
+        _ => {
+            return Err(AdbError::ProtocolError(format!(
+                "screencap output of {} bytes does not fit {width}x{height} in format {format}",
+                data.len()
+            )));
+        }
+    };
+    data.drain(..header);
+
+    let pixels = match format {
+        1 => data,
+        2 => {
+            data.chunks_exact_mut(4).for_each(|pixel| pixel[3] = 255);
+            data
+        }
+        5 => {
+            data.chunks_exact_mut(4).for_each(|pixel| pixel.swap(0, 2));
+            data
+        }
+        3 => data
+            .chunks_exact(3)
+            .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+            .collect(),
+        _ => data
+            .chunks_exact(2)
+            .flat_map(|pixel| {
+                let pixel = u16::from_le_bytes([pixel[0], pixel[1]]);
+                let (r, g, b) = (pixel >> 11, (pixel >> 5) & 0x3f, pixel & 0x1f);
+                [
+                    (r << 3 | r >> 2) as u8,
+                    (g << 2 | g >> 4) as u8,
+                    (b << 3 | b >> 2) as u8,
+                    255,
+                ]
+            })
+            .collect(),
+    };
+    Ok((width, height, pixels))
+}
+
 pub enum Input {
+    /// shell:input tap x y
+    Tap(u32, u32),
     /// shell:input swipe x1 y1 x2 y2 duration
     Swipe {
         p1: (u32, u32),
@@ -116,6 +334,28 @@ pub enum Input {
     ///
     /// shell:input keyevent <keycode>
     Keyevent(String),
+    /// .0 is the text, which `input text` only types if it is ASCII
+    ///
+    /// shell:input text <text>
+    Text(String),
+}
+
+/// Escape `text` for `input text`, it goes through the device shell and `input`
+/// itself turns `%s` into a space.
+pub fn escape_input_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            ' ' => escaped.push_str("%s"),
+            '\\' | '\'' | '"' | '`' | '$' | '&' | '|' | ';' | '<' | '>' | '(' | ')' | '*' | '?'
+            | '~' | '#' | '!' | '[' | ']' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
 }
 
 impl AdbCommand for Input {
@@ -123,6 +363,7 @@ impl AdbCommand for Input {
 
     fn raw_command(&self) -> String {
         match self {
+            Input::Tap(x, y) => format!("shell:input tap {x} {y}"),
             Input::Swipe { p1, p2, duration } => {
                 format!(
                     "shell:input swipe {} {} {} {} {}",
@@ -134,6 +375,7 @@ impl AdbCommand for Input {
                 )
             }
             Input::Keyevent(keycode) => format!("shell:input keyevent {}", keycode),
+            Input::Text(text) => format!("shell:input text {}", escape_input_text(text)),
         }
     }
 
@@ -142,11 +384,485 @@ impl AdbCommand for Input {
     }
 }
 
+// ===== sync: =====
+//
+// After `sync:` is accepted, every request is a 4 bytes id, a little endian u32
+// length and that many bytes of payload. Responses start with an id as well.
+
+/// Max length of a `DATA` chunk
+const SYNC_DATA_MAX: usize = 64 * 1024;
+
+/// `mode`, `size` and `mtime` of a file on the device, see [`SyncStat`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileStat {
+    pub mode: u32,
+    pub size: u32,
+    /// Seconds since unix epoch
+    pub mtime: u32,
+}
+
+impl FileStat {
+    /// `STAT` of a missing file is all zeros.
+    pub fn exists(&self) -> bool {
+        self.mode != 0
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & 0o170000 == 0o040000
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.mode & 0o170000 == 0o100000
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub name: String,
+    pub stat: FileStat,
+}
+
+fn write_sync_request<T: Write>(target: &mut T, id: &[u8; 4], payload: &[u8]) -> AdbResult<()> {
+    target.write_all(id)?;
+    target.write_all(&(payload.len() as u32).to_le_bytes())?;
+    target.write_all(payload)?;
+    Ok(())
+}
+
+fn read_u32<T: Read>(source: &mut T) -> AdbResult<u32> {
+    let mut buf = [0; 4];
+    source.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_sync_bytes<T: Read>(source: &mut T, len: usize) -> AdbResult<Vec<u8>> {
+    let mut buf = vec![0; len];
+    source.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// Read the id of a response, turning `FAIL` into an error.
+fn read_sync_id<T: Read>(source: &mut T) -> AdbResult<String> {
+    let id = read_exact_to_string(source, 4)?;
+    if id == "FAIL" {
+        let len = read_u32(source)? as usize;
+        let reason = String::from_utf8_lossy(&read_sync_bytes(source, len)?).to_string();
+        return Err(AdbError::ResponseError(reason));
+    }
+    Ok(id)
+}
+
+fn expect_sync_id(id: &str, expected: &str) -> AdbResult<()> {
+    if id != expected {
+        return Err(AdbError::ProtocolError(format!(
+            "expected {expected}, got {id}"
+        )));
+    }
+    Ok(())
+}
+
+fn read_file_stat<T: Read>(source: &mut T) -> AdbResult<FileStat> {
+    Ok(FileStat {
+        mode: read_u32(source)?,
+        size: read_u32(source)?,
+        mtime: read_u32(source)?,
+    })
+}
+
+fn quit_sync<T: Write>(target: &mut T) -> AdbResult<()> {
+    write_sync_request(target, b"QUIT", &[])
+}
+
+/// sync: STAT <path>
+pub struct SyncStat {
+    path: String,
+}
+
+impl SyncStat {
+    pub fn new(path: impl AsRef<str>) -> Self {
+        Self {
+            path: path.as_ref().to_string(),
+        }
+    }
+}
+
+impl AdbCommand for SyncStat {
+    type Output = FileStat;
+
+    fn raw_command(&self) -> String {
+        "sync:".to_string()
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()?;
+        write_sync_request(stream, b"STAT", self.path.as_bytes())?;
+        expect_sync_id(&read_sync_id(stream)?, "STAT")?;
+        let stat = read_file_stat(stream)?;
+        quit_sync(stream)?;
+        Ok(stat)
+    }
+}
+
+/// sync: LIST <path>
+pub struct SyncList {
+    path: String,
+}
+
+impl SyncList {
+    pub fn new(path: impl AsRef<str>) -> Self {
+        Self {
+            path: path.as_ref().to_string(),
+        }
+    }
+}
+
+impl AdbCommand for SyncList {
+    type Output = Vec<DirEntry>;
+
+    fn raw_command(&self) -> String {
+        "sync:".to_string()
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()?;
+        write_sync_request(stream, b"LIST", self.path.as_bytes())?;
+        let mut entries = Vec::new();
+        loop {
+            let id = read_sync_id(stream)?;
+            let stat = read_file_stat(stream)?;
+            let len = read_u32(stream)? as usize;
+            let name = read_sync_bytes(stream, len)?;
+            if id == "DONE" {
+                break;
+            }
+            expect_sync_id(&id, "DENT")?;
+            let name = String::from_utf8_lossy(&name).to_string();
+            if name != "." && name != ".." {
+                entries.push(DirEntry { name, stat });
+            }
+        }
+        quit_sync(stream)?;
+        Ok(entries)
+    }
+}
+
+/// sync: SEND <path>,<mode>
+pub struct SyncSend<'a> {
+    path: String,
+    mode: u32,
+    mtime: u32,
+    data: &'a [u8],
+    on_progress: Option<&'a dyn Fn(usize, usize)>,
+}
+
+impl<'a> SyncSend<'a> {
+    /// `mode` is the permission bits of the file, like `0o644`.
+    pub fn new(path: impl AsRef<str>, mode: u32, data: &'a [u8]) -> Self {
+        let mtime = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as u32;
+        Self {
+            path: path.as_ref().to_string(),
+            mode,
+            mtime,
+            data,
+            on_progress: None,
+        }
+    }
+
+    /// Call `on_progress` with the bytes sent so far and the total after each chunk.
+    pub fn with_progress(mut self, on_progress: &'a dyn Fn(usize, usize)) -> Self {
+        self.on_progress = Some(on_progress);
+        self
+    }
+}
+
+impl AdbCommand for SyncSend<'_> {
+    type Output = ();
+
+    fn raw_command(&self) -> String {
+        "sync:".to_string()
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()?;
+        // The mode is sent in decimal, with the regular file type bits
+        let header = format!("{},{}", self.path, 0o100000 | (self.mode & 0o7777));
+        write_sync_request(stream, b"SEND", header.as_bytes())?;
+        let mut sent = 0;
+        for chunk in self.data.chunks(SYNC_DATA_MAX) {
+            write_sync_request(stream, b"DATA", chunk)?;
+            sent += chunk.len();
+            if let Some(on_progress) = self.on_progress {
+                on_progress(sent, self.data.len());
+            }
+        }
+        stream.write_all(b"DONE")?;
+        stream.write_all(&self.mtime.to_le_bytes())?;
+        expect_sync_id(&read_sync_id(stream)?, "OKAY")?;
+        read_u32(stream)?;
+        quit_sync(stream)?;
+        Ok(())
+    }
+}
+
+/// sync: RECV <path>
+pub struct SyncRecv {
+    path: String,
+}
+
+impl SyncRecv {
+    pub fn new(path: impl AsRef<str>) -> Self {
+        Self {
+            path: path.as_ref().to_string(),
+        }
+    }
+}
+
+impl AdbCommand for SyncRecv {
+    type Output = Vec<u8>;
+
+    fn raw_command(&self) -> String {
+        "sync:".to_string()
+    }
+
+    fn handle_response(&self, stream: &mut AdbTcpStream) -> AdbResult<Self::Output> {
+        stream.check_response_status()?;
+        write_sync_request(stream, b"RECV", self.path.as_bytes())?;
+        let mut data = Vec::new();
+        loop {
+            let id = read_sync_id(stream)?;
+            let len = read_u32(stream)? as usize;
+            if id == "DONE" {
+                break;
+            }
+            expect_sync_id(&id, "DATA")?;
+            data.extend(read_sync_bytes(stream, len)?);
+        }
+        quit_sync(stream)?;
+        Ok(data)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::host;
 
-    use super::{ScreenCapPng, ShellCommand};
+    use std::{
+        io::{Read, Write},
+        net::{SocketAddr, TcpListener},
+        thread,
+    };
+
+    use crate::{AdbTcpStream, command::AdbCommand};
+
+    use super::*;
+
+    /// Serve a single `sync:` connection, answering a `RECV` with `file` and
+    /// returning what was sent with `SEND`.
+    fn fake_sync_server(file: &'static [u8]) -> (SocketAddr, thread::JoinHandle<Vec<u8>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 9];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request, b"0005sync:");
+            stream.write_all(b"OKAY").unwrap();
+
+            let mut received = Vec::new();
+            loop {
+                let mut id = [0; 4];
+                stream.read_exact(&mut id).unwrap();
+                let len = read_u32(&mut stream).unwrap() as usize;
+                match &id {
+                    b"SEND" => {
+                        read_sync_bytes(&mut stream, len).unwrap();
+                        loop {
+                            let mut id = [0; 4];
+                            stream.read_exact(&mut id).unwrap();
+                            let len = read_u32(&mut stream).unwrap() as usize;
+                            if &id == b"DONE" {
+                                break;
+                            }
+                            received.extend(read_sync_bytes(&mut stream, len).unwrap());
+                        }
+                        stream.write_all(b"OKAY\0\0\0\0").unwrap();
+                    }
+                    b"RECV" => {
+                        read_sync_bytes(&mut stream, len).unwrap();
+                        write_sync_request(&mut stream, b"DATA", file).unwrap();
+                        stream.write_all(b"DONE\0\0\0\0").unwrap();
+                    }
+                    b"STAT" => {
+                        read_sync_bytes(&mut stream, len).unwrap();
+                        write_sync_request(&mut stream, b"FAIL", b"no such file").unwrap();
+                        // The connection is over after a failure
+                        break;
+                    }
+                    b"QUIT" => break,
+                    id => panic!("unexpected request {id:?}"),
+                }
+            }
+            received
+        });
+        (addr, handle)
+    }
+
+    fn connect(addr: SocketAddr) -> AdbTcpStream {
+        match addr {
+            SocketAddr::V4(addr) => AdbTcpStream::connect(addr).unwrap(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_escape_input_text() {
+        assert_eq!(escape_input_text("admin"), "admin");
+        assert_eq!(escape_input_text("a b"), "a%sb");
+        assert_eq!(escape_input_text("p@ss'w$rd;"), "p@ss\\'w\\$rd\\;");
+        assert_eq!(
+            Input::Text("it's me".to_string()).raw_command(),
+            "shell:input text it\\'s%sme"
+        );
+    }
+
+    #[test]
+    fn test_parse_raw_screencap() {
+        let header = |format: u32, extra: &[u8]| {
+            let mut data = Vec::new();
+            for value in [2u32, 1, format] {
+                data.extend(value.to_le_bytes());
+            }
+            data.extend(extra);
+            data
+        };
+
+        let mut data = header(1, &[]);
+        data.extend([1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(
+            parse_raw_screencap(data).unwrap(),
+            (2, 1, vec![1, 2, 3, 4, 5, 6, 7, 8])
+        );
+
+        // 16 bytes header with a color space
+        let mut data = header(5, &[1, 0, 0, 0]);
+        data.extend([1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(
+            parse_raw_screencap(data).unwrap(),
+            (2, 1, vec![3, 2, 1, 4, 7, 6, 5, 8])
+        );
+
+        let mut data = header(4, &[]);
+        data.extend(0xf800u16.to_le_bytes());
+        data.extend(0x07e0u16.to_le_bytes());
+        assert_eq!(
+            parse_raw_screencap(data).unwrap(),
+            (2, 1, vec![255, 0, 0, 255, 0, 255, 0, 255])
+        );
+
+        assert!(matches!(
+            parse_raw_screencap(header(42, &[0; 8])),
+            Err(AdbError::UnsupportedPixelFormat(42))
+        ));
+        assert!(matches!(
+            parse_raw_screencap(header(1, &[0; 3])),
+            Err(AdbError::ProtocolError(_))
+        ));
+    }
+
+    #[test]
+    fn test_shell_command_v2() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 22];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request, b"0012shell,v2,raw:false");
+            stream.write_all(b"OKAY").unwrap();
+            for (id, data) in [(1, &b"out"[..]), (2, b"err\n"), (3, &[1])] {
+                stream.write_all(&[id]).unwrap();
+                stream
+                    .write_all(&(data.len() as u32).to_le_bytes())
+                    .unwrap();
+                stream.write_all(data).unwrap();
+            }
+        });
+
+        let output = connect(addr)
+            .execute_command(ShellCommandV2::new("false"))
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(output.stdout, "out");
+        assert_eq!(output.stderr, "err\n");
+        assert_eq!(output.exit_code, 1);
+        let err = output.check("false").unwrap_err();
+        assert_eq!(err.to_string(), "`false` exited with code 1: err");
+    }
+
+    #[test]
+    fn test_logcat() {
+        let command = Logcat::new("Unity:V  *:E").tail(2);
+        assert_eq!(
+            command.raw_command(),
+            "exec:logcat -v threadtime -t 2 'Unity:V' '*:E'"
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4];
+            stream.read_exact(&mut request).unwrap();
+            let len = usize::from_str_radix(std::str::from_utf8(&request).unwrap(), 16).unwrap();
+            let mut request = vec![0; len];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(b"OKAY").unwrap();
+            stream
+                .write_all(
+                    b"--------- beginning of main\n\
+                    05-14 12:31:08.001 23456 23470 E Unity   : NullReferenceException\n\
+                    05-14 12:31:08.002 23456 23470 V Unity   : at Game.Update ()\n",
+                )
+                .unwrap();
+        });
+
+        let mut stream = connect(addr);
+        stream.execute_command(command).unwrap();
+        let entries = crate::logcat::LogcatReader::new(stream).collect::<Vec<_>>();
+        server.join().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].message, "NullReferenceException");
+        assert_eq!(entries[1].message, "at Game.Update ()");
+    }
+
+    #[test]
+    fn test_sync() {
+        let data = (0..100_000).map(|i| i as u8).collect::<Vec<_>>();
+        let (addr, server) = fake_sync_server(b"");
+        connect(addr)
+            .execute_command(SyncSend::new("/data/local/tmp/test", 0o644, &data))
+            .unwrap();
+        assert_eq!(server.join().unwrap(), data);
+
+        let (addr, server) = fake_sync_server(b"hello");
+        let res = connect(addr)
+            .execute_command(SyncRecv::new("/data/local/tmp/test"))
+            .unwrap();
+        assert_eq!(res, b"hello");
+        server.join().unwrap();
+
+        let (addr, server) = fake_sync_server(b"");
+        let mut stream = connect(addr);
+        let res = SyncStat::new("/missing");
+        assert_eq!(res.raw_command(), "sync:");
+        assert!(matches!(
+            stream.execute_command(res),
+            Err(AdbError::ResponseError(reason)) if reason == "no such file"
+        ));
+        server.join().unwrap();
+    }
 
     #[test]
     fn test_screencap() {
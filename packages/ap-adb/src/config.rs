@@ -0,0 +1,142 @@
+//! Where the adb server is and how long to wait for it
+//!
+//! ```ignore
+//! let config = AdbServerConfig::from_env().with_read_timeout(Some(Duration::from_secs(10)));
+//! let device = ap_adb::connect_with(&config, "127.0.0.1:16384")?;
+//! ```
+
+use std::{
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs},
+    time::Duration,
+};
+
+use tracing::warn;
+
+use crate::error::{AdbError, AdbResult};
+
+/// The environment variable adb itself reads the server socket from, in the
+/// form of `tcp:<host>:<port>` or `tcp:<port>`
+pub const ADB_SERVER_SOCKET: &str = "ADB_SERVER_SOCKET";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdbServerConfig {
+    pub addr: SocketAddr,
+    /// `None` blocks until there is something to read
+    pub read_timeout: Option<Duration>,
+    pub write_timeout: Option<Duration>,
+    /// `None` leaves it to the OS
+    pub connect_timeout: Option<Duration>,
+    /// Run `adb start-server` if no server answers, see
+    /// [`server::ensure_running`](crate::server::ensure_running)
+    pub auto_start: bool,
+    /// How long to wait for the server to come up before giving up
+    pub start_timeout: Duration,
+}
+
+impl Default for AdbServerConfig {
+    /// `127.0.0.1:5037` with 2 second read and write timeouts, started if it is
+    /// not running.
+    fn default() -> Self {
+        Self {
+            addr: SocketAddrV4::new(Ipv4Addr::LOCALHOST, 5037).into(),
+            read_timeout: Some(Duration::from_secs(2)),
+            write_timeout: Some(Duration::from_secs(2)),
+            connect_timeout: None,
+            auto_start: true,
+            start_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl AdbServerConfig {
+    /// The default config, with the address taken from [`ADB_SERVER_SOCKET`] if it is set.
+    pub fn from_env() -> Self {
+        let config = Self::default();
+        match std::env::var(ADB_SERVER_SOCKET) {
+            Ok(spec) => match parse_socket_spec(&spec) {
+                Ok(addr) => config.with_addr(addr),
+                Err(err) => {
+                    warn!("ignoring {ADB_SERVER_SOCKET}: {err}");
+                    config
+                }
+            },
+            Err(_) => config,
+        }
+    }
+
+    pub fn with_addr(mut self, addr: impl Into<SocketAddr>) -> Self {
+        self.addr = addr.into();
+        self
+    }
+
+    pub fn with_read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    pub fn with_write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn with_auto_start(mut self, auto_start: bool) -> Self {
+        self.auto_start = auto_start;
+        self
+    }
+
+    pub fn with_start_timeout(mut self, timeout: Duration) -> Self {
+        self.start_timeout = timeout;
+        self
+    }
+
+    /// `tcp:<host>:<port>`, as taken by `adb -L`.
+    pub fn socket_spec(&self) -> String {
+        format!("tcp:{}", self.addr)
+    }
+}
+
+/// Parse `tcp:<host>:<port>` or `tcp:<port>`, resolving the host.
+pub fn parse_socket_spec(spec: &str) -> AdbResult<SocketAddr> {
+    let rest = spec
+        .strip_prefix("tcp:")
+        .ok_or_else(|| AdbError::ParseError(format!("unsupported socket spec {spec}")))?;
+    let (host, port) = match rest.rsplit_once(':') {
+        Some((host, port)) => (host.trim_start_matches('[').trim_end_matches(']'), port),
+        None => ("127.0.0.1", rest),
+    };
+    let port = port
+        .parse::<u16>()
+        .map_err(|_| AdbError::ParseError(format!("invalid port in {spec}")))?;
+    (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| AdbError::ParseError(format!("failed to resolve {spec}")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_socket_spec() {
+        assert_eq!(
+            parse_socket_spec("tcp:5038").unwrap(),
+            "127.0.0.1:5038".parse().unwrap()
+        );
+        assert_eq!(
+            parse_socket_spec("tcp:192.168.1.2:5037").unwrap(),
+            "192.168.1.2:5037".parse().unwrap()
+        );
+        assert_eq!(
+            parse_socket_spec("tcp:[::1]:5037").unwrap(),
+            "[::1]:5037".parse().unwrap()
+        );
+        assert!(parse_socket_spec("localfilesystem:/tmp/adb").is_err());
+        assert!(parse_socket_spec("tcp:host:port").is_err());
+    }
+}
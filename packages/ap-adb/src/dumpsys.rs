@@ -0,0 +1,273 @@
+//! Typed states of system services, parsed from `dumpsys`
+//!
+//! `dumpsys` prints a service as free-form `key=value` or `key: value` lines,
+//! whose layout changes between Android versions. The parsers here only look
+//! for the lines they need and leave a field `None` (or its default) when they
+//! do not find it, so a new Android version degrades instead of failing.
+//!
+//! | Service    | State             | Device method                                                 |
+//! |------------|-------------------|---------------------------------------------------------------|
+//! | `power`    | [`PowerState`]    | [`Device::dumpsys_power`](crate::Device::dumpsys_power)       |
+//! | `window`   | [`WindowState`]   | [`Device::dumpsys_window`](crate::Device::dumpsys_window)     |
+//! | `battery`  | [`BatteryState`]  | [`Device::dumpsys_battery`](crate::Device::dumpsys_battery)   |
+//! | `activity` | [`ActivityState`] | [`Device::dumpsys_activity`](crate::Device::dumpsys_activity) |
+
+/// The value of the first `key=value` or `key: value` of `output`, up to the end
+/// of the line.
+fn find_value<'a>(output: &'a str, key: &str) -> Option<&'a str> {
+    output.lines().find_map(|line| {
+        let line = line.trim_start();
+        let rest = line.strip_prefix(key)?;
+        let value = rest.strip_prefix('=').or_else(|| rest.strip_prefix(':'))?;
+        Some(value.trim())
+    })
+}
+
+/// A `package/activity` component, as in `am start -n`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Component {
+    pub package: String,
+    /// As reported, i.e. `.MainActivity` for a class of the package
+    pub activity: String,
+}
+
+impl Component {
+    /// The last `package/activity` token of `record`, i.e. of
+    /// `Window{3bd4b1a u0 com.example/com.example.MainActivity}` or
+    /// `ActivityRecord{a1b2c3 u0 com.example/.MainActivity t12}`.
+    fn from_record(record: &str) -> Option<Self> {
+        let record = record.trim();
+        let body = record
+            .split_once('{')
+            .map(|(_, body)| body.trim_end_matches('}'))
+            .unwrap_or(record);
+        body.split_whitespace().rev().find_map(|token| {
+            let (package, activity) = token.split_once('/')?;
+            (!package.is_empty() && !activity.is_empty()).then(|| Self {
+                package: package.to_string(),
+                activity: activity.trim_end_matches('}').to_string(),
+            })
+        })
+    }
+
+    /// The full class name of the activity, with the package prepended to a
+    /// relative one
+    pub fn class_name(&self) -> String {
+        match self.activity.starts_with('.') {
+            true => format!("{}{}", self.package, self.activity),
+            false => self.activity.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Wakefulness {
+    Awake,
+    Asleep,
+    Dreaming,
+    Dozing,
+    Unknown(String),
+}
+
+impl Wakefulness {
+    fn parse(s: &str) -> Self {
+        match s {
+            "Awake" => Wakefulness::Awake,
+            "Asleep" => Wakefulness::Asleep,
+            "Dreaming" => Wakefulness::Dreaming,
+            "Dozing" => Wakefulness::Dozing,
+            s => Wakefulness::Unknown(s.to_string()),
+        }
+    }
+}
+
+/// `dumpsys power`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PowerState {
+    pub wakefulness: Option<Wakefulness>,
+    /// Whether it is plugged in
+    pub is_powered: Option<bool>,
+    /// Whether the screen is kept on while plugged in
+    pub stay_on: Option<bool>,
+}
+
+impl PowerState {
+    pub fn parse(output: &str) -> Self {
+        Self {
+            wakefulness: find_value(output, "mWakefulness").map(Wakefulness::parse),
+            is_powered: find_value(output, "mIsPowered").and_then(|v| v.parse().ok()),
+            stay_on: find_value(output, "mStayOn").and_then(|v| v.parse().ok()),
+        }
+    }
+
+    pub fn is_screen_on(&self) -> bool {
+        self.wakefulness == Some(Wakefulness::Awake)
+    }
+}
+
+/// `dumpsys window`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowState {
+    /// The app of the window with the input focus, `None` while no window has it
+    /// (i.e. between two activities) or when it is not an app (the status bar)
+    pub current_focus: Option<Component>,
+    /// The activity that gets the focus next, set a bit before `current_focus`
+    pub focused_app: Option<Component>,
+    /// Quarter turns of the display, from `0` to `3`
+    pub rotation: Option<u32>,
+}
+
+impl WindowState {
+    pub fn parse(output: &str) -> Self {
+        // `mCurrentRotation=ROTATION_90` since Android 10, `mRotation=1` before
+        let rotation = find_value(output, "mCurrentRotation")
+            .and_then(|v| v.strip_prefix("ROTATION_"))
+            .and_then(|v| v.parse::<u32>().ok())
+            .map(|degrees| degrees / 90)
+            .or_else(|| {
+                find_value(output, "mRotation")
+                    .and_then(|v| v.split_whitespace().next())
+                    .and_then(|v| v.parse().ok())
+            });
+        Self {
+            current_focus: find_value(output, "mCurrentFocus").and_then(Component::from_record),
+            focused_app: find_value(output, "mFocusedApp").and_then(Component::from_record),
+            rotation,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatteryStatus {
+    Unknown,
+    Charging,
+    Discharging,
+    NotCharging,
+    Full,
+}
+
+impl BatteryStatus {
+    /// From a `BatteryManager.BATTERY_STATUS_*` value
+    fn from_code(code: u32) -> Self {
+        match code {
+            2 => BatteryStatus::Charging,
+            3 => BatteryStatus::Discharging,
+            4 => BatteryStatus::NotCharging,
+            5 => BatteryStatus::Full,
+            _ => BatteryStatus::Unknown,
+        }
+    }
+}
+
+/// `dumpsys battery`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatteryState {
+    pub level: Option<u32>,
+    /// What `level` is out of, usually `100`
+    pub scale: Option<u32>,
+    pub status: BatteryStatus,
+    pub ac_powered: bool,
+    pub usb_powered: bool,
+    pub wireless_powered: bool,
+    /// In degrees Celsius
+    pub temperature: Option<f32>,
+    /// In millivolts
+    pub voltage: Option<u32>,
+}
+
+impl BatteryState {
+    pub fn parse(output: &str) -> Self {
+        let number = |key| find_value(output, key).and_then(|v| v.parse::<u32>().ok());
+        let flag = |key| find_value(output, key) == Some("true");
+        Self {
+            level: number("level"),
+            scale: number("scale"),
+            status: number("status").map_or(BatteryStatus::Unknown, BatteryStatus::from_code),
+            ac_powered: flag("AC powered"),
+            usb_powered: flag("USB powered"),
+            wireless_powered: flag("Wireless powered"),
+            // In tenths of a degree
+            temperature: number("temperature").map(|t| t as f32 / 10.0),
+            voltage: number("voltage"),
+        }
+    }
+
+    /// The charge from `0.0` to `1.0`
+    pub fn fraction(&self) -> Option<f32> {
+        let scale = self.scale.unwrap_or(100).max(1);
+        Some(self.level? as f32 / scale as f32)
+    }
+
+    pub fn is_plugged(&self) -> bool {
+        self.ac_powered || self.usb_powered || self.wireless_powered
+    }
+}
+
+/// `dumpsys activity activities`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivityState {
+    /// The activity in the foreground, `None` while none is resumed
+    pub resumed_activity: Option<Component>,
+}
+
+impl ActivityState {
+    pub fn parse(output: &str) -> Self {
+        // `topResumedActivity=` since Android 10, `mResumedActivity:` before
+        let resumed_activity = ["topResumedActivity", "mResumedActivity", "ResumedActivity"]
+            .into_iter()
+            .find_map(|key| find_value(output, key).and_then(Component::from_record));
+        Self { resumed_activity }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_dumpsys() {
+        let power = PowerState::parse(
+            "POWER MANAGER (dumpsys power)\n\nPower Manager State:\n  mDirty=0x0\n  mWakefulness=Awake\n  mWakefulnessChanging=false\n  mIsPowered=true\n  mStayOn=false\n",
+        );
+        assert!(power.is_screen_on());
+        assert_eq!(power.is_powered, Some(true));
+        assert!(!PowerState::parse("  mWakefulness=Asleep\n").is_screen_on());
+
+        let window = WindowState::parse(
+            "  mCurrentFocus=Window{3bd4b1a u0 com.example.game/com.unity3d.player.UnityPlayerActivity}\n  mFocusedApp=ActivityRecord{a1b2c3 u0 com.example.game/.MainActivity t12}\n  mCurrentRotation=ROTATION_90\n",
+        );
+        let focus = window.current_focus.unwrap();
+        assert_eq!(focus.package, "com.example.game");
+        assert_eq!(focus.activity, "com.unity3d.player.UnityPlayerActivity");
+        let app = window.focused_app.unwrap();
+        assert_eq!(app.class_name(), "com.example.game.MainActivity");
+        assert_eq!(window.rotation, Some(1));
+        let window = WindowState::parse(
+            "  mCurrentFocus=null\n  mCurrentFocus=Window{1 u0 StatusBar}\n  mRotation=3 mAltOrientation=false\n",
+        );
+        assert_eq!(window.current_focus, None);
+        assert_eq!(window.rotation, Some(3));
+
+        let battery = BatteryState::parse(
+            "Current Battery Service state:\n  AC powered: false\n  USB powered: true\n  Wireless powered: false\n  status: 2\n  health: 2\n  present: true\n  level: 85\n  scale: 100\n  voltage: 4123\n  temperature: 251\n",
+        );
+        assert_eq!(battery.status, BatteryStatus::Charging);
+        assert!(battery.is_plugged());
+        assert_eq!(battery.fraction(), Some(0.85));
+        assert_eq!(battery.temperature, Some(25.1));
+        assert_eq!(battery.voltage, Some(4123));
+
+        let activity = ActivityState::parse(
+            "ACTIVITY MANAGER ACTIVITIES (dumpsys activity activities)\n  topResumedActivity=ActivityRecord{f00 u0 com.example.game/.MainActivity t5}\n",
+        );
+        assert_eq!(
+            activity.resumed_activity.unwrap().package,
+            "com.example.game"
+        );
+        let activity = ActivityState::parse(
+            "    mResumedActivity: ActivityRecord{f00 u0 com.android.launcher3/.Launcher t1}\n",
+        );
+        assert_eq!(activity.resumed_activity.unwrap().activity, ".Launcher");
+        assert_eq!(ActivityState::parse("").resumed_activity, None);
+    }
+}
@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::shell::ShellOutput;
+
 /// Unified ADB error type
 #[derive(Error, Debug)]
 pub enum AdbError {
@@ -19,6 +21,14 @@ pub enum AdbError {
     #[error("ADB server not connected")]
     ServerNotConnected,
 
+    /// `adb pair` was given a pairing code the device did not accept
+    #[error("Wrong pairing code for {0}")]
+    WrongPairingCode(String),
+
+    /// Nothing answered on the address given to `adb pair`
+    #[error("Host unreachable: {0}")]
+    HostUnreachable(String),
+
     /// ADB response error
     #[error("ADB response error: {0}")]
     ResponseError(String),
@@ -51,9 +61,36 @@ pub enum AdbError {
     #[error("Operation timed out")]
     Timeout,
 
+    /// `screencap` returned pixels in a format that is not converted to RGBA8
+    #[error("Unsupported pixel format: {0}")]
+    UnsupportedPixelFormat(u32),
+
     /// Protocol error
     #[error("Protocol error: {0}")]
     ProtocolError(String),
+
+    /// `pm install` failed with an `INSTALL_FAILED_*` code
+    #[error("Failed to install {apk}: {code} {message}")]
+    InstallFailed {
+        apk: String,
+        code: String,
+        message: String,
+    },
+
+    /// `pm uninstall` failed with a `DELETE_FAILED_*` code
+    #[error("Failed to uninstall {package}: {code} {message}")]
+    UninstallFailed {
+        package: String,
+        code: String,
+        message: String,
+    },
+
+    /// A shell command exited with a non zero code
+    #[error("`{command}` exited with code {}: {}", output.exit_code, output.stderr.trim_end())]
+    NonZeroExit {
+        command: String,
+        output: ShellOutput,
+    },
 }
 
 /// ADB result type alias
@@ -1,4 +1,9 @@
-use std::net::{Ipv4Addr, SocketAddrV4};
+use std::{
+    collections::{BTreeSet, VecDeque},
+    net::{Ipv4Addr, SocketAddrV4, TcpStream, ToSocketAddrs},
+    process::Command,
+    time::Duration,
+};
 
 use tracing::{info, trace};
 
@@ -6,12 +11,14 @@ use super::{
     AdbTcpStream,
     command::{
         AdbCommand,
-        host_service::{self, DeviceLong},
+        host_service::{self, DeviceLong, DevicePath, Features, Kill, Reconnect, TrackDevices},
     },
+    config::AdbServerConfig,
     error::{AdbError, AdbResult},
+    server,
 };
 
-use super::DeviceInfo;
+use super::{DeviceInfo, utils::read_payload_to_string};
 
 #[allow(dead_code)]
 mod command {
@@ -28,37 +35,113 @@ mod command {
 }
 
 pub struct Host {
-    socket_addr: SocketAddrV4,
+    config: AdbServerConfig,
     adb_tcp_stream: Option<AdbTcpStream>,
     transported_serial: Option<String>,
 }
 
+/// Connect to the server of [`AdbServerConfig::from_env`], `127.0.0.1:5037`
+/// unless `ADB_SERVER_SOCKET` says otherwise, starting it if needed.
 pub fn connect_default() -> AdbResult<Host> {
-    connect(Ipv4Addr::new(127, 0, 0, 1), 5037)
+    connect_with(AdbServerConfig::from_env())
 }
 
 /// Get an ADB host connection
 pub fn connect(ip: Ipv4Addr, port: u16) -> AdbResult<Host> {
-    // TODO: if the daemon is not started first start the daemon
     // TODO: or else just use process, don't use socket
     // TODO: or, separate them?
-    let mut host = Host::new(SocketAddrV4::new(ip, port));
+    connect_with(AdbServerConfig::default().with_addr(SocketAddrV4::new(ip, port)))
+}
+
+/// Fails with [`AdbError::ServerNotConnected`] if the server does not come up,
+/// see [`server::ensure_running`].
+pub fn connect_with(config: AdbServerConfig) -> AdbResult<Host> {
+    server::ensure_running(&config)?;
+    let mut host = Host::with_config(config);
     host.reconnect()?;
     Ok(host)
 }
 
+/// How long to wait for the pairing port of a device to answer
+const PAIR_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Pair with a device for wireless debugging (Android 11+), through the server
+/// of [`AdbServerConfig::from_env`], see [`pair_with`].
+pub fn pair(host_port: &str, pairing_code: &str) -> AdbResult<()> {
+    pair_with(&AdbServerConfig::from_env(), host_port, pairing_code)
+}
+
+/// Pair with a device by running `adb pair`, with the `<host>:<port>` and the
+/// six digit code shown under "Pair device with pairing code" on the device.
+/// The device is then authorized, and [`connect`](crate::connect)s to the
+/// `<host>:<port>` of the wireless debugging screen, not the pairing one.
+///
+/// Fails with [`AdbError::ParseError`] if the code is not digits,
+/// [`AdbError::HostUnreachable`] if nothing answers on `host_port` and
+/// [`AdbError::WrongPairingCode`] if the device refuses the code.
+pub fn pair_with(config: &AdbServerConfig, host_port: &str, pairing_code: &str) -> AdbResult<()> {
+    let pairing_code = pairing_code.trim();
+    if pairing_code.is_empty() || !pairing_code.chars().all(|c| c.is_ascii_digit()) {
+        return Err(AdbError::ParseError(format!(
+            "invalid pairing code {pairing_code:?}, expected digits"
+        )));
+    }
+    let addr = host_port
+        .to_socket_addrs()
+        .map_err(|err| AdbError::ParseError(format!("invalid address {host_port}: {err}")))?
+        .next()
+        .ok_or_else(|| AdbError::HostUnreachable(host_port.to_string()))?;
+    // adb takes a while to give up on an address, and says the same as for a wrong code
+    TcpStream::connect_timeout(&addr, PAIR_CONNECT_TIMEOUT)
+        .map_err(|err| AdbError::HostUnreachable(format!("{host_port}: {err}")))?;
+
+    server::ensure_running(config)?;
+    info!("pairing with {host_port}...");
+    let output = Command::new("adb")
+        .args(["-L", &config.socket_spec(), "pair", host_port, pairing_code])
+        .output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    parse_pair_output(host_port, &format!("{stdout}{stderr}"))
+}
+
+/// The result of `adb pair` from what it printed.
+fn parse_pair_output(host_port: &str, output: &str) -> AdbResult<()> {
+    if output.contains("Successfully paired") {
+        return Ok(());
+    }
+    if output.contains("Wrong password") {
+        return Err(AdbError::WrongPairingCode(host_port.to_string()));
+    }
+    if output.contains("Unable to start pairing client") || output.contains("failed to connect") {
+        return Err(AdbError::HostUnreachable(host_port.to_string()));
+    }
+    Err(AdbError::CommandFailed(format!(
+        "adb pair {host_port}: {}",
+        output.trim()
+    )))
+}
+
 impl Host {
     pub fn new(socket_addr: SocketAddrV4) -> Self {
+        Self::with_config(AdbServerConfig::default().with_addr(socket_addr))
+    }
+
+    pub fn with_config(config: AdbServerConfig) -> Self {
         Self {
-            socket_addr,
+            config,
             adb_tcp_stream: None,
             transported_serial: None,
         }
     }
 
+    pub fn config(&self) -> &AdbServerConfig {
+        &self.config
+    }
+
     pub fn reconnect(&mut self) -> AdbResult<()> {
         self.transported_serial = None;
-        self.adb_tcp_stream = AdbTcpStream::connect(self.socket_addr).ok();
+        self.adb_tcp_stream = AdbTcpStream::connect_with(&self.config).ok();
         Ok(())
     }
 
@@ -67,6 +150,71 @@ impl Host {
         self.execute_command(DeviceLong::new())
     }
 
+    /// Run a host service on a connection of its own, the server closes it
+    /// once it answered.
+    fn execute_host_command<T>(&self, command: impl AdbCommand<Output = T>) -> AdbResult<T> {
+        AdbTcpStream::connect_with(&self.config)?.execute_command(command)
+    }
+
+    /// The protocol version of the server, i.e. `41`, see [`server::version`].
+    pub fn version(&self) -> AdbResult<u32> {
+        server::version(&self.config)
+    }
+
+    /// Stop the server, like `adb kill-server`. The next command fails unless
+    /// the server is started again, i.e. by [`connect_with`].
+    pub fn kill(&mut self) -> AdbResult<()> {
+        self.execute_host_command(Kill::new())?;
+        self.adb_tcp_stream = None;
+        self.transported_serial = None;
+        Ok(())
+    }
+
+    /// The features of the server, i.e. `shell_v2` or `cmd`.
+    pub fn features(&self) -> AdbResult<Vec<String>> {
+        self.execute_host_command(Features::new())
+    }
+
+    /// The features both the server and the device `serial` support, the ones
+    /// commands can rely on.
+    pub fn device_features(&self, serial: &str) -> AdbResult<Vec<String>> {
+        self.execute_host_command(Features::of_device(serial))
+    }
+
+    /// Reconnect the device `serial`, i.e. when it went `offline`, and return
+    /// what the server did, i.e. `reconnecting emulator-5554 [device]`.
+    pub fn reconnect_device(&self, serial: &str) -> AdbResult<String> {
+        self.execute_host_command(Reconnect::of_device(serial))
+    }
+
+    /// Reconnect every `offline` device, see [`Host::reconnect_device`].
+    pub fn reconnect_offline(&self) -> AdbResult<String> {
+        self.execute_host_command(Reconnect::offline())
+    }
+
+    /// Where the device `serial` is plugged in, i.e. `usb:1-4`, `unknown` for a
+    /// device connected over the network.
+    pub fn device_path(&self, serial: &str) -> AdbResult<String> {
+        self.execute_host_command(DevicePath::new(serial))
+    }
+
+    /// Watch devices coming and going, on a connection of its own.
+    ///
+    /// ```ignore
+    /// for event in host.track_devices()? {
+    ///     match event? {
+    ///         DeviceEvent::Connected(info) => println!("{} connected", info.serial),
+    ///         DeviceEvent::Disconnected(serial) => println!("{serial} disconnected"),
+    ///     }
+    /// }
+    /// ```
+    pub fn track_devices(&self) -> AdbResult<DeviceTracker> {
+        let mut stream = AdbTcpStream::connect_with(&self.config)?;
+        stream.execute_command(TrackDevices::new())?;
+        stream.set_read_timeout(None)?;
+        Ok(DeviceTracker::new(stream))
+    }
+
     pub fn execute_command<T>(&mut self, command: impl AdbCommand<Output = T>) -> AdbResult<T> {
         // TODO: maybe reconnect every time is a good choice?
         // TODO: no, for transport
@@ -83,7 +231,7 @@ impl Host {
 
     fn transport<S: AsRef<str>>(&mut self, serial_number: S) -> AdbResult<()> {
         let serial_number = serial_number.as_ref();
-        trace!("transporting to {}...", serial_number);
+        trace!(serial = serial_number, "transporting");
         if let Some(serial) = &self.transported_serial {
             if serial == serial_number {
                 info!("already transported, skipped");
@@ -110,6 +258,93 @@ impl Host {
     }
 }
 
+/// A change reported by [`Host::track_devices`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeviceEvent {
+    /// The device came online, devices already online are reported first
+    Connected(DeviceInfo),
+    /// The device went away or is no longer online (`offline`, `unauthorized`, ...)
+    Disconnected(String),
+}
+
+/// Blocks on the next [`DeviceEvent`], ends after the first error other than
+/// [`AdbError::Timeout`].
+pub struct DeviceTracker {
+    stream: Option<AdbTcpStream>,
+    online: BTreeSet<String>,
+    pending: VecDeque<DeviceEvent>,
+}
+
+impl DeviceTracker {
+    fn new(stream: AdbTcpStream) -> Self {
+        Self {
+            stream: Some(stream),
+            online: BTreeSet::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// How long to wait for the next change before yielding [`AdbError::Timeout`],
+    /// `None` (the default) waits forever.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> AdbResult<()> {
+        match &self.stream {
+            Some(stream) => stream.set_read_timeout(timeout),
+            None => Ok(()),
+        }
+    }
+
+    /// Turn a device list into the events since the last one.
+    fn update(&mut self, devices: &str) {
+        let online = devices
+            .lines()
+            .filter_map(|line| DeviceInfo::try_from(line).ok())
+            .filter(DeviceInfo::is_online)
+            .collect::<Vec<_>>();
+        let serials = online
+            .iter()
+            .map(|info| info.serial.clone())
+            .collect::<BTreeSet<_>>();
+        for serial in self.online.difference(&serials) {
+            self.pending
+                .push_back(DeviceEvent::Disconnected(serial.clone()));
+        }
+        for info in online {
+            if !self.online.contains(&info.serial) {
+                self.pending.push_back(DeviceEvent::Connected(info));
+            }
+        }
+        self.online = serials;
+    }
+}
+
+impl Iterator for DeviceTracker {
+    type Item = AdbResult<DeviceEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(Ok(event));
+            }
+            let stream = self.stream.as_mut()?;
+            match read_payload_to_string(stream) {
+                Ok(devices) => self.update(&devices),
+                Err(AdbError::Io(err))
+                    if matches!(
+                        err.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Some(Err(AdbError::Timeout));
+                }
+                Err(err) => {
+                    self.stream = None;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::command::local_service::ShellCommand;
@@ -120,6 +355,150 @@ mod test {
         let _ = tracing_subscriber::fmt::try_init();
     }
 
+    #[test]
+    fn test_track_devices() {
+        use std::{
+            io::{Read, Write},
+            net::{SocketAddr, TcpListener},
+            thread,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let SocketAddr::V4(addr) = listener.local_addr().unwrap() else {
+            unreachable!()
+        };
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 22];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request, b"0012host:track-devices");
+            stream.write_all(b"OKAY").unwrap();
+            for devices in [
+                "emulator-5554\tdevice\n127.0.0.1:16384\toffline\n",
+                "emulator-5554\tdevice\n127.0.0.1:16384\tdevice\n",
+                "127.0.0.1:16384\tdevice\n",
+                "",
+            ] {
+                write!(stream, "{:04x}{devices}", devices.len()).unwrap();
+            }
+        });
+
+        let events = Host::new(addr)
+            .track_devices()
+            .unwrap()
+            .take(4)
+            .map(|event| match event.unwrap() {
+                DeviceEvent::Connected(info) => format!("+{}", info.serial),
+                DeviceEvent::Disconnected(serial) => format!("-{serial}"),
+            })
+            .collect::<Vec<_>>();
+        server.join().unwrap();
+        assert_eq!(
+            events,
+            [
+                "+emulator-5554",
+                "+127.0.0.1:16384",
+                "-emulator-5554",
+                "-127.0.0.1:16384",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_host_services() {
+        use std::{
+            io::{Read, Write},
+            net::{SocketAddr, TcpListener},
+            thread,
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let SocketAddr::V4(addr) = listener.local_addr().unwrap() else {
+            unreachable!()
+        };
+        let server = thread::spawn(move || {
+            for (request, response) in [
+                ("host:version", Some("0029")),
+                ("host:features", Some("shell_v2,cmd,stat_v2\n")),
+                ("host-serial:emulator-5554:features", Some("shell_v2")),
+                (
+                    "host-serial:emulator-5554:reconnect",
+                    Some("reconnecting emulator-5554 [device]\n"),
+                ),
+                ("host-serial:emulator-5554:get-devpath", Some("usb:1-4")),
+                ("host:kill", None),
+            ] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let expected = format!("{:04x}{request}", request.len());
+                let mut buf = vec![0; expected.len()];
+                stream.read_exact(&mut buf).unwrap();
+                assert_eq!(String::from_utf8(buf).unwrap(), expected);
+                match response {
+                    Some(response) => {
+                        write!(stream, "OKAY{:04x}{response}", response.len()).unwrap()
+                    }
+                    None => stream.write_all(b"OKAY").unwrap(),
+                }
+            }
+        });
+
+        let mut host = Host::new(addr);
+        assert_eq!(host.version().unwrap(), 41);
+        assert_eq!(host.features().unwrap(), ["shell_v2", "cmd", "stat_v2"]);
+        assert_eq!(host.device_features("emulator-5554").unwrap(), ["shell_v2"]);
+        assert_eq!(
+            host.reconnect_device("emulator-5554").unwrap(),
+            "reconnecting emulator-5554 [device]"
+        );
+        assert_eq!(host.device_path("emulator-5554").unwrap(), "usb:1-4");
+        host.kill().unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_pair() {
+        let output = "Successfully paired to 192.168.1.20:37099 [guid=adb-R58M-xyz]\n";
+        assert!(parse_pair_output("192.168.1.20:37099", output).is_ok());
+        assert!(matches!(
+            parse_pair_output(
+                "192.168.1.20:37099",
+                "Failed: Wrong password or connection was dropped.\n"
+            ),
+            Err(AdbError::WrongPairingCode(_))
+        ));
+        assert!(matches!(
+            parse_pair_output(
+                "192.168.1.20:37099",
+                "Failed: Unable to start pairing client.\n"
+            ),
+            Err(AdbError::HostUnreachable(_))
+        ));
+        assert!(matches!(
+            parse_pair_output("192.168.1.20:37099", "error: no adb\n"),
+            Err(AdbError::CommandFailed(_))
+        ));
+
+        let config = AdbServerConfig::default();
+        assert!(matches!(
+            pair_with(&config, "127.0.0.1:1", "12ab56"),
+            Err(AdbError::ParseError(_))
+        ));
+        assert!(matches!(
+            pair_with(&config, "not an address", "123456"),
+            Err(AdbError::ParseError(_))
+        ));
+        // Nothing listens on the port of a listener that was dropped
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        assert!(matches!(
+            pair_with(&config, &format!("127.0.0.1:{port}"), "123456"),
+            Err(AdbError::HostUnreachable(_))
+        ));
+    }
+
     #[test]
     fn test_host_devices() -> AdbResult<()> {
         init();
@@ -3,15 +3,16 @@
 //! Provides functionality for communicating with Android devices
 use std::{
     collections::BTreeMap,
-    io::{Read, Write},
-    net::{Ipv4Addr, SocketAddrV4, TcpStream},
+    io::{ErrorKind, Read, Write},
+    net::{Shutdown, SocketAddrV4, TcpStream},
+    path::Path,
     process::Command,
     sync::Mutex,
     time::Duration,
 };
 
 use image::{DynamicImage, ImageBuffer};
-use tracing::{error, trace};
+use tracing::{error, trace, trace_span, warn};
 
 use utils::{ResponseStatus, read_payload_to_string, read_response_status};
 
@@ -22,28 +23,50 @@ use self::{
 };
 
 pub mod command;
+pub mod config;
+pub mod dumpsys;
 pub mod error;
 pub mod host;
+pub mod logcat;
+pub mod pm;
+pub mod pool;
+pub mod props;
+pub mod server;
+pub mod shell;
 pub mod utils;
 
 // Re-export commonly used types
+pub use config::AdbServerConfig;
 pub use error::{AdbError, AdbResult};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DeviceInfo {
     pub serial: String,
+    /// `device`, `offline`, `unauthorized`, ...
+    pub state: String,
     pub info: BTreeMap<String, String>,
 }
 
+impl DeviceInfo {
+    /// Whether the device is online and authorized.
+    pub fn is_online(&self) -> bool {
+        self.state == "device"
+    }
+
+    pub fn model(&self) -> Option<&str> {
+        self.info.get("model").map(String::as_str)
+    }
+}
+
 impl TryFrom<&str> for DeviceInfo {
     type Error = AdbError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        // Turn "serial\tdevice key1:value1 key2:value2 ..." into a `DeviceInfo`.
+        // Turn "serial\tstate key1:value1 key2:value2 ..." into a `DeviceInfo`.
         let mut pairs = value.split_whitespace();
         let serial = pairs.next();
         let state = pairs.next();
-        if let (Some(serial), Some("device")) = (serial, state) {
+        if let (Some(serial), Some(state)) = (serial, state) {
             let info: BTreeMap<String, String> = pairs
                 .filter_map(|pair| {
                     let mut kv = pair.split(':');
@@ -57,6 +80,7 @@ impl TryFrom<&str> for DeviceInfo {
 
             Ok(DeviceInfo {
                 serial: serial.to_owned(),
+                state: state.to_owned(),
                 info,
             })
         } else {
@@ -73,31 +97,76 @@ pub struct AdbTcpStream {
 }
 
 impl AdbTcpStream {
+    /// Connect to `socket_addr` with the default timeouts.
     pub fn connect(socket_addr: SocketAddrV4) -> AdbResult<Self> {
-        trace!("connecting to {:?}...", socket_addr);
-        let stream = TcpStream::connect(socket_addr)?;
-        stream.set_read_timeout(Some(Duration::from_secs(2)))?;
-        stream.set_write_timeout(Some(Duration::from_secs(2)))?;
+        Self::connect_with(&AdbServerConfig::default().with_addr(socket_addr))
+    }
+
+    pub fn connect_with(config: &AdbServerConfig) -> AdbResult<Self> {
+        trace!("connecting to {:?}...", config.addr);
+        let stream = match config.connect_timeout {
+            Some(timeout) => TcpStream::connect_timeout(&config.addr, timeout)?,
+            None => TcpStream::connect(config.addr)?,
+        };
+        stream.set_read_timeout(config.read_timeout)?;
+        stream.set_write_timeout(config.write_timeout)?;
         let res = Self { inner: stream };
         trace!("connected");
         Ok(res)
     }
 
+    /// Connect to the server of [`AdbServerConfig::from_env`].
     pub fn connect_host() -> AdbResult<Self> {
-        Self::connect(SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 5037))
+        Self::connect_with(&AdbServerConfig::from_env())
     }
 
     pub fn connect_device<S: AsRef<str>>(serial: S) -> AdbResult<Self> {
+        Self::connect_device_with(&AdbServerConfig::from_env(), serial)
+    }
+
+    pub fn connect_device_with<S: AsRef<str>>(
+        config: &AdbServerConfig,
+        serial: S,
+    ) -> AdbResult<Self> {
         let serial = serial.as_ref();
-        let mut stream = Self::connect_host()?;
+        let mut stream = Self::connect_with(config)?;
         stream.execute_command(host_service::Transport::new(serial.to_string()))?;
         Ok(stream)
     }
 
+    pub fn try_clone(&self) -> AdbResult<Self> {
+        Ok(Self {
+            inner: self.inner.try_clone()?,
+        })
+    }
+
+    /// `None` blocks until there is something to read.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> AdbResult<()> {
+        Ok(self.inner.set_read_timeout(timeout)?)
+    }
+
+    /// Close the writing half, the other side reads an EOF.
+    pub fn shutdown_write(&self) -> AdbResult<()> {
+        Ok(self.inner.shutdown(Shutdown::Write)?)
+    }
+
+    /// Whether the other side hung up, without blocking or consuming anything.
+    pub fn is_closed(&self) -> bool {
+        if self.inner.set_nonblocking(true).is_err() {
+            return true;
+        }
+        let res = self.inner.peek(&mut [0]);
+        let _ = self.inner.set_nonblocking(false);
+        match res {
+            Ok(len) => len == 0,
+            Err(err) => err.kind() != ErrorKind::WouldBlock,
+        }
+    }
+
     pub fn execute_command<T>(&mut self, command: impl AdbCommand<Output = T>) -> AdbResult<T> {
         // TODO: maybe reconnect every time is a good choice?
         // TODO: no, for transport
-        trace!("executing command: {:?}...", command.raw_command());
+        trace!(command = ?command.raw_command(), "executing command");
         write_request(self, command.raw_command())?;
 
         command.handle_response(self)
@@ -120,21 +189,27 @@ impl AdbTcpStream {
 ///
 /// Returns [`AdbError::DeviceNotFound`] if connection fails
 pub fn connect<S: AsRef<str>>(serial: S) -> AdbResult<Device> {
+    connect_with(&AdbServerConfig::from_env(), serial)
+}
+
+/// [`connect`] through the server of `config`
+pub fn connect_with<S: AsRef<str>>(config: &AdbServerConfig, serial: S) -> AdbResult<Device> {
     let serial = serial.as_ref();
 
     let _adb_connect = Command::new("adb")
-        .args(["connect", serial])
+        .args(["-L", &config.socket_spec(), "connect", serial])
         .output()
         .map_err(|err| AdbError::DeviceNotFound(format!("{:?}", err)))?;
     // TODO: check stdout of it to find whether the connect is success or not
     // TODO: or, actually the following code can already check?
 
-    let mut host = host::connect_default().expect("failed to connect to adb server");
+    let mut host = host::connect_with(config.clone())?;
 
     let serial = serial.to_string();
     let serials = host
         .devices_long()?
         .iter()
+        .filter(|device_info| device_info.is_online())
         .map(|device_info| device_info.serial.clone())
         .collect::<Vec<String>>();
 
@@ -153,13 +228,31 @@ pub struct Device {
 
     /// ADB device serial number
     serial: String,
+
+    /// Transported connections to execute commands on
+    pool: pool::ConnectionPool,
+
+    config: AdbServerConfig,
 }
 
+/// Number of idle connections a [`Device`] keeps
+const POOL_SIZE: usize = 2;
+
 impl Device {
+    /// A device on the server `host` is connected to.
     pub fn new(host: Host, serial: String) -> Self {
+        let config = host.config().clone();
+        let pool = {
+            let (config, serial) = (config.clone(), serial.clone());
+            pool::ConnectionPool::new(POOL_SIZE, move || {
+                AdbTcpStream::connect_device_with(&config, &serial)
+            })
+        };
         Self {
             host: Mutex::new(host),
             serial,
+            pool,
+            config,
         }
     }
 
@@ -167,12 +260,229 @@ impl Device {
         self.serial.clone()
     }
 
+    /// The ADB server this device is reached through
+    pub fn config(&self) -> &AdbServerConfig {
+        &self.config
+    }
+
     pub fn input(&self, input: local_service::Input) -> AdbResult<()> {
         self.execute_command_by_socket(input)
     }
 
+    /// A connection transported to the device, from the pool if there is one.
     pub fn connect_adb_tcp_stream(&self) -> AdbResult<AdbTcpStream> {
-        AdbTcpStream::connect_device(&self.serial).map_err(AdbError::from)
+        self.pool.get()
+    }
+
+    /// Run `command` on the device, the returned stream is connected to its stdin
+    /// and stdout. The command gets an EOF on stdin once the stream is dropped.
+    pub fn exec(&self, command: impl AsRef<str>) -> AdbResult<AdbTcpStream> {
+        let mut stream = self.connect_adb_tcp_stream()?;
+        stream.execute_command(local_service::Exec::new(command))?;
+        Ok(stream)
+    }
+
+    /// Run `command` and fail with [`AdbError::NonZeroExit`] if it does, the
+    /// device has to support `shell_v2` (Android 7.0+).
+    pub fn shell(&self, command: impl AsRef<str>) -> AdbResult<shell::ShellOutput> {
+        let command = command.as_ref();
+        self.execute_command_by_socket(local_service::ShellCommandV2::new(command))?
+            .check(command)
+    }
+
+    /// Start an interactive shell to run many commands on a single connection,
+    /// the device has to support `shell_v2` (Android 7.0+).
+    pub fn open_shell(&self) -> AdbResult<shell::ShellSession> {
+        let mut stream = self.connect_adb_tcp_stream()?;
+        stream.execute_command(local_service::ShellV2::new(""))?;
+        shell::ShellSession::new(stream)
+    }
+
+    /// Every system property of the device, see [`props`].
+    pub fn properties(&self) -> AdbResult<props::Properties> {
+        Ok(props::Properties::parse(&self.shell("getprop")?.stdout))
+    }
+
+    /// A single system property, `None` if it is not set.
+    pub fn property(&self, name: impl AsRef<str>) -> AdbResult<Option<String>> {
+        let value = self.shell(format!("getprop {}", name.as_ref()))?.stdout;
+        let value = value.trim_end_matches(['\r', '\n']);
+        Ok((!value.is_empty()).then(|| value.to_string()))
+    }
+
+    /// `dumpsys <service>` through `grep -E <pattern>`, the lines the parsers of
+    /// [`dumpsys`] need out of a service that may print a lot. No line matching
+    /// is not an error.
+    fn dumpsys_grep(&self, service: &str, pattern: &str) -> AdbResult<String> {
+        self.execute_command_by_socket(local_service::ShellCommand::new(format!(
+            "dumpsys {service} | grep -E '{pattern}'"
+        )))
+    }
+
+    pub fn dumpsys_power(&self) -> AdbResult<dumpsys::PowerState> {
+        let output = self.dumpsys_grep("power", "mWakefulness=|mIsPowered=|mStayOn=")?;
+        Ok(dumpsys::PowerState::parse(&output))
+    }
+
+    pub fn dumpsys_window(&self) -> AdbResult<dumpsys::WindowState> {
+        let output = self.dumpsys_grep(
+            "window",
+            "mCurrentFocus=|mFocusedApp=|mCurrentRotation=|mRotation=",
+        )?;
+        Ok(dumpsys::WindowState::parse(&output))
+    }
+
+    pub fn dumpsys_battery(&self) -> AdbResult<dumpsys::BatteryState> {
+        Ok(dumpsys::BatteryState::parse(
+            &self.shell("dumpsys battery")?.stdout,
+        ))
+    }
+
+    pub fn dumpsys_activity(&self) -> AdbResult<dumpsys::ActivityState> {
+        let output = self.dumpsys_grep("activity activities", "ResumedActivity")?;
+        Ok(dumpsys::ActivityState::parse(&output))
+    }
+
+    /// Follow the log of the device, from the entries already in it, see
+    /// [`logcat`] for `filter`.
+    pub fn logcat(&self, filter: impl AsRef<str>) -> AdbResult<logcat::LogcatReader> {
+        let mut stream = self.connect_adb_tcp_stream()?;
+        stream.execute_command(local_service::Logcat::new(filter))?;
+        // The log may stay quiet for a while
+        stream.set_read_timeout(None)?;
+        Ok(logcat::LogcatReader::new(stream))
+    }
+
+    /// The last `lines` entries of the log matching `filter`, see [`logcat`].
+    pub fn logcat_dump(
+        &self,
+        filter: impl AsRef<str>,
+        lines: usize,
+    ) -> AdbResult<Vec<logcat::LogEntry>> {
+        let mut stream = self.connect_adb_tcp_stream()?;
+        stream.execute_command(local_service::Logcat::new(filter).tail(lines))?;
+        Ok(logcat::LogcatReader::new(stream).collect())
+    }
+
+    /// `adb forward <local> <remote>`, i.e. `tcp:1313` to `localabstract:minicap`.
+    pub fn forward(&self, local: impl AsRef<str>, remote: impl AsRef<str>) -> AdbResult<()> {
+        self.execute_host_command(host_service::Forward::new(&self.serial, local, remote))?;
+        Ok(())
+    }
+
+    /// Forward a free port of the host to `remote`, returns the port.
+    pub fn forward_free_port(&self, remote: impl AsRef<str>) -> AdbResult<u16> {
+        self.execute_host_command(host_service::Forward::new(&self.serial, "tcp:0", remote))?
+            .ok_or_else(|| AdbError::ProtocolError("no port allocated".to_string()))
+    }
+
+    pub fn list_forwards(&self) -> AdbResult<Vec<host_service::ForwardInfo>> {
+        self.execute_host_command(host_service::ListForwards::of_device(&self.serial))
+    }
+
+    pub fn kill_forward(&self, local: impl AsRef<str>) -> AdbResult<()> {
+        self.execute_host_command(host_service::KillForward::new(&self.serial, local))
+    }
+
+    pub fn kill_all_forwards(&self) -> AdbResult<()> {
+        self.execute_host_command(host_service::KillForward::all(&self.serial))
+    }
+
+    /// `adb reverse <remote> <local>`, to let the device connect to a server on the host.
+    pub fn reverse(&self, remote: impl AsRef<str>, local: impl AsRef<str>) -> AdbResult<()> {
+        self.execute_command_by_socket(host_service::ReverseForward::new(remote, local))?;
+        Ok(())
+    }
+
+    pub fn kill_reverse(&self, remote: impl AsRef<str>) -> AdbResult<()> {
+        self.execute_command_by_socket(host_service::KillReverse::new(remote))
+    }
+
+    pub fn stat(&self, remote: impl AsRef<str>) -> AdbResult<local_service::FileStat> {
+        self.execute_command_by_socket(local_service::SyncStat::new(remote))
+    }
+
+    /// Entries of the directory `remote`, without `.` and `..`.
+    pub fn list_dir(&self, remote: impl AsRef<str>) -> AdbResult<Vec<local_service::DirEntry>> {
+        self.execute_command_by_socket(local_service::SyncList::new(remote))
+    }
+
+    /// Write `data` to the file `remote` with the permission bits `mode`, like `0o644`.
+    pub fn push(&self, data: &[u8], remote: impl AsRef<str>, mode: u32) -> AdbResult<()> {
+        self.execute_command_by_socket(local_service::SyncSend::new(remote, mode, data))
+    }
+
+    /// `adb push <local> <remote>`, `remote` is the path of the file, not its directory.
+    pub fn push_file(&self, local: impl AsRef<Path>, remote: impl AsRef<str>) -> AdbResult<()> {
+        let local = local.as_ref();
+        let data = std::fs::read(local)?;
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::metadata(local)?.permissions().mode()
+        };
+        #[cfg(not(unix))]
+        let mode = 0o644;
+        self.push(&data, remote, mode)
+    }
+
+    /// `adb install -r <path>`, fails with [`AdbError::InstallFailed`].
+    pub fn install_apk(&self, path: impl AsRef<Path>) -> AdbResult<()> {
+        self.install_apk_with_progress(path, |_| {})
+    }
+
+    pub fn install_apk_with_progress(
+        &self,
+        path: impl AsRef<Path>,
+        on_progress: impl Fn(pm::InstallProgress),
+    ) -> AdbResult<()> {
+        let path = path.as_ref();
+        let apk = path.display().to_string();
+        let data = std::fs::read(path)?;
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().replace(['\'', ' '], "_"))
+            .unwrap_or_else(|| "install.apk".to_string());
+        let remote = format!("{}/{name}", pm::INSTALL_DIR);
+
+        let on_push = |sent, total| on_progress(pm::InstallProgress::Pushing { sent, total });
+        self.execute_command_by_socket(
+            local_service::SyncSend::new(&remote, 0o644, &data).with_progress(&on_push),
+        )?;
+        on_progress(pm::InstallProgress::Installing);
+        let output = self.execute_command_by_socket(local_service::ShellCommandV2::new(format!(
+            "pm install -r '{remote}'"
+        )));
+        let _ = self.shell(format!("rm -f '{remote}'"));
+
+        let output = output?;
+        match pm::parse_failure(&format!("{}{}", output.stdout, output.stderr)) {
+            None => Ok(()),
+            Some((code, message)) => Err(AdbError::InstallFailed { apk, code, message }),
+        }
+    }
+
+    /// `adb uninstall <package>`, fails with [`AdbError::UninstallFailed`].
+    pub fn uninstall(&self, package: impl AsRef<str>) -> AdbResult<()> {
+        let package = package.as_ref();
+        let output = self.execute_command_by_socket(local_service::ShellCommandV2::new(
+            format!("pm uninstall {package}"),
+        ))?;
+        match pm::parse_failure(&format!("{}{}", output.stdout, output.stderr)) {
+            None => Ok(()),
+            Some((code, message)) => Err(AdbError::UninstallFailed {
+                package: package.to_string(),
+                code,
+                message,
+            }),
+        }
+    }
+
+    /// `adb pull <remote> <local>`
+    pub fn pull_file(&self, remote: impl AsRef<str>, local: impl AsRef<Path>) -> AdbResult<()> {
+        let data = self.execute_command_by_socket(local_service::SyncRecv::new(remote))?;
+        std::fs::write(local, data)?;
+        Ok(())
     }
 
     // pub fn get_screen_size(&self) -> Result<(u32, u32), MyError> {
@@ -181,18 +491,28 @@ impl Device {
     // }
 
     /// Get the raw screencap data in bytes (RGBA8)
+    ///
+    /// Falls back to decoding `screencap -p` if the device uses a pixel format
+    /// that is not supported.
     pub fn screencap_raw(&self) -> AdbResult<(u32, u32, Vec<u8>)> {
-        let bytes = self
-            .execute_command_by_socket(local_service::ScreenCapRaw::new())
-            .expect("failed to screencap");
-        Ok(bytes)
+        match self.execute_command_by_socket(local_service::ScreenCapRaw::new()) {
+            Err(AdbError::UnsupportedPixelFormat(format)) => {
+                warn!("unsupported pixel format {format}, falling back to png");
+                let png = self.execute_command_by_socket(local_service::ScreenCapPng::new())?;
+                let image = image::load_from_memory_with_format(&png, image::ImageFormat::Png)?;
+                let image = image.into_rgba8();
+                Ok((image.width(), image.height(), image.into_raw()))
+            }
+            res => res,
+        }
     }
 
-    /// Get the decoded screencap image
+    /// Get the screencap as an image, from the raw pixels without encoding it
     pub fn screencap(&self) -> AdbResult<image::DynamicImage> {
         let (width, height, bytes) = self.screencap_raw()?;
 
-        let image = ImageBuffer::from_raw(width, height, bytes).unwrap();
+        let image = ImageBuffer::from_raw(width, height, bytes)
+            .ok_or_else(|| AdbError::ProtocolError(format!("screencap is not {width}x{height}")))?;
         Ok(DynamicImage::ImageRgba8(image))
     }
 
@@ -209,14 +529,17 @@ impl Device {
         Ok(res)
     }
 
+    /// Execute a host service on a new connection to the adb server.
+    fn execute_host_command<T>(&self, command: impl AdbCommand<Output = T>) -> AdbResult<T> {
+        AdbTcpStream::connect_with(&self.config)?.execute_command(command)
+    }
+
     pub fn execute_command_by_socket<T>(
         &self,
         command: impl AdbCommand<Output = T>,
     ) -> AdbResult<T> {
-        let mut adb_tcp_stream = self.connect_adb_tcp_stream()?;
-        adb_tcp_stream
-            .execute_command(command)
-            .map_err(AdbError::from)
+        let _span = trace_span!("adb", serial = %self.serial).entered();
+        self.pool.execute(command)
     }
 }
 
@@ -257,6 +580,50 @@ mod test {
 
         // assert_eq!(bytes, bytes2);
     }
+
+    #[test]
+    fn test_stream_closed() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = match listener.local_addr().unwrap() {
+            std::net::SocketAddr::V4(addr) => addr,
+            _ => unreachable!(),
+        };
+        let stream = AdbTcpStream::connect(addr).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        assert!(!stream.is_closed());
+
+        peer.write_all(b"OKAY").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!stream.is_closed());
+
+        drop(peer);
+        std::thread::sleep(Duration::from_millis(50));
+        let mut stream = stream;
+        // Pending data is still readable before the hang up shows
+        assert!(matches!(
+            read_response_status(&mut stream).unwrap(),
+            ResponseStatus::Okay
+        ));
+        assert!(stream.is_closed());
+    }
+
+    #[test]
+    fn test_parse_device_info() {
+        let info = DeviceInfo::try_from(
+            "127.0.0.1:16384\tdevice product:SM-S9080 model:SM_S9080 transport_id:1",
+        )
+        .unwrap();
+        assert_eq!(info.serial, "127.0.0.1:16384");
+        assert!(info.is_online());
+        assert_eq!(info.model(), Some("SM_S9080"));
+
+        let info = DeviceInfo::try_from("emulator-5554 offline transport_id:2").unwrap();
+        assert_eq!(info.state, "offline");
+        assert!(!info.is_online());
+        assert_eq!(info.model(), None);
+
+        assert!(DeviceInfo::try_from("").is_err());
+    }
 }
 
 impl Read for AdbTcpStream {
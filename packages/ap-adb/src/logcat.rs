@@ -0,0 +1,192 @@
+//! Reading the log of a device with `logcat`
+//!
+//! Lines are read in the `threadtime` format and parsed into [`LogEntry`]s, either
+//! following the log as it grows with [`Device::logcat`](crate::Device::logcat)
+//! or as the last lines of it with [`Device::logcat_dump`](crate::Device::logcat_dump).
+//! Both take a filter as given to `logcat`, i.e. `"Unity:V *:E"` for everything
+//! Unity logs and the errors of the others, an empty one keeps everything.
+
+use std::{
+    fmt,
+    io::{BufRead, BufReader},
+};
+
+use tracing::warn;
+
+use crate::AdbTcpStream;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogPriority {
+    Verbose,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+    Silent,
+}
+
+impl LogPriority {
+    /// The priority of its letter in a log line, `I` for [`LogPriority::Info`]
+    pub fn from_char(c: char) -> Option<Self> {
+        Some(match c {
+            'V' => LogPriority::Verbose,
+            'D' => LogPriority::Debug,
+            'I' => LogPriority::Info,
+            'W' => LogPriority::Warn,
+            'E' => LogPriority::Error,
+            'F' | 'A' => LogPriority::Fatal,
+            'S' => LogPriority::Silent,
+            _ => return None,
+        })
+    }
+
+    pub fn as_char(self) -> char {
+        match self {
+            LogPriority::Verbose => 'V',
+            LogPriority::Debug => 'D',
+            LogPriority::Info => 'I',
+            LogPriority::Warn => 'W',
+            LogPriority::Error => 'E',
+            LogPriority::Fatal => 'F',
+            LogPriority::Silent => 'S',
+        }
+    }
+}
+
+/// A line of the log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogEntry {
+    /// `MM-DD hh:mm:ss.mmm` in the timezone of the device
+    pub timestamp: String,
+    pub pid: u32,
+    pub tid: u32,
+    pub priority: LogPriority,
+    pub tag: String,
+    pub message: String,
+}
+
+/// Split off the next whitespace separated token of `s`.
+fn next_token(s: &str) -> Option<(&str, &str)> {
+    let s = s.trim_start();
+    let end = s.find(char::is_whitespace)?;
+    Some((&s[..end], &s[end..]))
+}
+
+impl LogEntry {
+    /// Parse a line of `logcat -v threadtime`, `None` for the lines that are not
+    /// entries, i.e. `--------- beginning of main`.
+    ///
+    /// ```text
+    /// 05-14 12:31:07.205  1532  1587 I ActivityManager: Start proc 8823:com.example/u0a190
+    /// ```
+    pub fn parse(line: &str) -> Option<Self> {
+        let (date, rest) = next_token(line)?;
+        let (time, rest) = next_token(rest)?;
+        let (pid, rest) = next_token(rest)?;
+        let (tid, rest) = next_token(rest)?;
+        let (priority, rest) = next_token(rest)?;
+        let mut priority = priority.chars();
+        let (Some(priority), None) = (priority.next(), priority.next()) else {
+            return None;
+        };
+        // The tag is padded, and may be followed by an empty message
+        let rest = rest.trim_start().trim_end_matches(['\r', '\n']);
+        let (tag, message) = match rest.find(": ") {
+            Some(pos) => (&rest[..pos], &rest[pos + 2..]),
+            None => (rest.strip_suffix(':')?, ""),
+        };
+        Some(Self {
+            timestamp: format!("{date} {time}"),
+            pid: pid.parse().ok()?,
+            tid: tid.parse().ok()?,
+            priority: LogPriority::from_char(priority)?,
+            tag: tag.trim_end().to_string(),
+            message: message.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:5} {:5} {} {}: {}",
+            self.timestamp,
+            self.pid,
+            self.tid,
+            self.priority.as_char(),
+            self.tag,
+            self.message
+        )
+    }
+}
+
+/// The entries of a [`Logcat`](crate::command::local_service::Logcat) stream,
+/// blocking on the next one. Ends when the stream does, the error it ends on is
+/// logged.
+pub struct LogcatReader {
+    reader: BufReader<AdbTcpStream>,
+    line: Vec<u8>,
+}
+
+impl LogcatReader {
+    pub fn new(stream: AdbTcpStream) -> Self {
+        Self {
+            reader: BufReader::new(stream),
+            line: Vec::new(),
+        }
+    }
+}
+
+impl Iterator for LogcatReader {
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.line.clear();
+            match self.reader.read_until(b'\n', &mut self.line) {
+                Ok(0) => return None,
+                Ok(_) => {
+                    if let Some(entry) = LogEntry::parse(&String::from_utf8_lossy(&self.line)) {
+                        return Some(entry);
+                    }
+                }
+                Err(err) => {
+                    warn!("logcat stream ended: {err}");
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_entry() {
+        let entry = LogEntry::parse(
+            "05-14 12:31:07.205  1532  1587 I ActivityManager: Start proc 8823:com.example/u0a190\n",
+        )
+        .unwrap();
+        assert_eq!(entry.timestamp, "05-14 12:31:07.205");
+        assert_eq!((entry.pid, entry.tid), (1532, 1587));
+        assert_eq!(entry.priority, LogPriority::Info);
+        assert_eq!(entry.tag, "ActivityManager");
+        assert_eq!(entry.message, "Start proc 8823:com.example/u0a190");
+        assert_eq!(LogEntry::parse(&entry.to_string()), Some(entry));
+
+        let entry =
+            LogEntry::parse("05-14 12:31:08.001 23456 23470 E Unity   : NullReferenceException")
+                .unwrap();
+        assert_eq!(entry.tag, "Unity");
+        assert!(entry.priority >= LogPriority::Error);
+        let entry = LogEntry::parse("05-14 12:31:08.001   100   100 W chatty  :").unwrap();
+        assert_eq!((entry.tag.as_str(), entry.message.as_str()), ("chatty", ""));
+
+        assert_eq!(LogEntry::parse("--------- beginning of crash"), None);
+        assert_eq!(LogEntry::parse(""), None);
+    }
+}
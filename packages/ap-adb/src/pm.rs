@@ -0,0 +1,74 @@
+//! Installing and uninstalling packages with `pm`
+//!
+//! An APK is pushed to [`INSTALL_DIR`] with the sync protocol, installed with
+//! `pm install` and removed again, see [`Device::install_apk`](crate::Device::install_apk).
+
+/// Where APKs are pushed to before installing them
+pub const INSTALL_DIR: &str = "/data/local/tmp";
+
+/// Reported by [`Device::install_apk_with_progress`](crate::Device::install_apk_with_progress).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallProgress {
+    /// `sent` of `total` bytes of the APK are pushed
+    Pushing { sent: usize, total: usize },
+    /// The APK is pushed and `pm install` is running
+    Installing,
+}
+
+/// The `(code, message)` of a failed `pm` command, `None` if it succeeded.
+///
+/// `pm` prints `Success`, or `Failure [INSTALL_FAILED_ALREADY_EXISTS: Attempt to
+/// re-install ...]` on either stdout or stderr depending on the Android version.
+pub fn parse_failure(output: &str) -> Option<(String, String)> {
+    if output.lines().any(|line| line.trim() == "Success") {
+        return None;
+    }
+    let Some(failure) = output
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("Failure"))
+    else {
+        return Some(("UNKNOWN".to_string(), output.trim().to_string()));
+    };
+    let failure = failure
+        .trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .trim();
+    Some(match failure.split_once(':') {
+        Some((code, message)) => (code.trim().to_string(), message.trim().to_string()),
+        None => (failure.to_string(), String::new()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_failure() {
+        assert_eq!(
+            parse_failure("Performing Streamed Install\nSuccess\n"),
+            None
+        );
+        assert_eq!(
+            parse_failure(
+                "Failure [INSTALL_FAILED_VERSION_DOWNGRADE: Downgrade detected: Update version code 1 is older than current 2]\n"
+            ),
+            Some((
+                "INSTALL_FAILED_VERSION_DOWNGRADE".to_string(),
+                "Downgrade detected: Update version code 1 is older than current 2".to_string()
+            ))
+        );
+        assert_eq!(
+            parse_failure("Failure [DELETE_FAILED_INTERNAL_ERROR]"),
+            Some(("DELETE_FAILED_INTERNAL_ERROR".to_string(), String::new()))
+        );
+        assert_eq!(
+            parse_failure("Error: Unknown option: -x"),
+            Some((
+                "UNKNOWN".to_string(),
+                "Error: Unknown option: -x".to_string()
+            ))
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Connections made ahead of time
+//!
+//! A stream is bound to the service it executes and the device closes it once
+//! the service ends, so a connection can not be reused. What can be saved is
+//! connecting to the server and `host:transport`, by keeping a few transported
+//! connections idle and topping them up in the background after each one taken.
+
+use std::{
+    io::ErrorKind,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+};
+
+use tracing::trace;
+
+use crate::{
+    AdbTcpStream,
+    command::AdbCommand,
+    error::{AdbError, AdbResult},
+};
+
+type Connect = dyn Fn() -> AdbResult<AdbTcpStream> + Send + Sync;
+
+pub struct ConnectionPool {
+    idle: Arc<Mutex<Vec<AdbTcpStream>>>,
+    /// Connections being made in the background
+    pending: Arc<AtomicUsize>,
+    size: usize,
+    connect: Arc<Connect>,
+}
+
+impl ConnectionPool {
+    /// Keep up to `size` connections made by `connect` idle, starting empty.
+    pub fn new(
+        size: usize,
+        connect: impl Fn() -> AdbResult<AdbTcpStream> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            idle: Arc::new(Mutex::new(Vec::new())),
+            pending: Arc::new(AtomicUsize::new(0)),
+            size,
+            connect: Arc::new(connect),
+        }
+    }
+
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    /// An idle connection the server has not hung up on.
+    fn take_idle(&self) -> Option<AdbTcpStream> {
+        let mut idle = self.idle.lock().unwrap();
+        while let Some(stream) = idle.pop() {
+            if !stream.is_closed() {
+                return Some(stream);
+            }
+            trace!("dropping a closed idle connection");
+        }
+        None
+    }
+
+    /// Make connections in the background until there are `size` of them.
+    fn refill(&self) {
+        let missing = self
+            .size
+            .saturating_sub(self.idle_count() + self.pending.load(Ordering::SeqCst));
+        for _ in 0..missing {
+            self.pending.fetch_add(1, Ordering::SeqCst);
+            let (idle, pending, connect) = (
+                self.idle.clone(),
+                self.pending.clone(),
+                self.connect.clone(),
+            );
+            thread::spawn(move || {
+                if let Ok(stream) = connect() {
+                    idle.lock().unwrap().push(stream);
+                }
+                pending.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    }
+
+    /// An idle connection, or a new one if there is none.
+    pub fn get(&self) -> AdbResult<AdbTcpStream> {
+        let stream = self.take_idle();
+        self.refill();
+        match stream {
+            Some(stream) => Ok(stream),
+            None => (self.connect)(),
+        }
+    }
+
+    /// Execute `command` on an idle connection, or a new one if the idle one turns
+    /// out to be dropped by the server.
+    pub fn execute<T>(&self, command: impl AdbCommand<Output = T>) -> AdbResult<T> {
+        if let Some(mut stream) = self.take_idle() {
+            self.refill();
+            match stream.execute_command(&command) {
+                // A connection dropped while idle fails before the service
+                // starts, so it is safe to execute the command again.
+                Err(AdbError::Io(err)) if is_disconnected(err.kind()) => {
+                    trace!("idle connection was dropped ({err}), reconnecting...")
+                }
+                res => return res,
+            }
+        } else {
+            self.refill();
+        }
+        (self.connect)()?.execute_command(command)
+    }
+}
+
+fn is_disconnected(kind: ErrorKind) -> bool {
+    matches!(
+        kind,
+        ErrorKind::BrokenPipe
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::UnexpectedEof
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::{SocketAddr, TcpListener},
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+    use crate::command::host_service::Version;
+
+    #[test]
+    fn test_pool() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let SocketAddr::V4(addr) = listener.local_addr().unwrap() else {
+            unreachable!()
+        };
+        // Answers `host:version` on every connection
+        let accepted = Arc::new(Mutex::new(Vec::new()));
+        let server_accepted = accepted.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                server_accepted
+                    .lock()
+                    .unwrap()
+                    .push(stream.try_clone().unwrap());
+                thread::spawn(move || {
+                    let mut request = [0; 16];
+                    if stream.read_exact(&mut request).is_ok() {
+                        stream.write_all(b"OKAY00040029").unwrap();
+                    }
+                });
+            }
+        });
+
+        let pool = ConnectionPool::new(2, move || AdbTcpStream::connect(addr));
+        let wait_refilled = || {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while pool.idle_count() < 2 {
+                assert!(Instant::now() < deadline, "pool is not refilled");
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+        assert_eq!(pool.execute(Version::new()).unwrap(), "0029");
+        wait_refilled();
+        assert_eq!(pool.execute(Version::new()).unwrap(), "0029");
+        wait_refilled();
+
+        // The server hangs up on the idle connections
+        for stream in accepted.lock().unwrap().drain(..) {
+            let _ = stream.shutdown(std::net::Shutdown::Both);
+        }
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(pool.execute(Version::new()).unwrap(), "0029");
+    }
+}
@@ -0,0 +1,139 @@
+//! System properties of a device, as listed by `getprop`
+//!
+//! `getprop` prints every property as `[name]: [value]`, a value may span
+//! several lines. [`Properties`] keeps them by name with typed getters for the
+//! ones commonly needed, see [`Device::properties`](crate::Device::properties).
+
+use std::{collections::BTreeMap, str::FromStr};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Properties(BTreeMap<String, String>);
+
+impl Properties {
+    /// Parse the output of `getprop`, lines that are not properties are skipped.
+    pub fn parse(output: &str) -> Self {
+        let mut props = BTreeMap::new();
+        let mut lines = output.lines();
+        while let Some(line) = lines.next() {
+            let Some((name, value)) = line.trim_start().split_once("]: [") else {
+                continue;
+            };
+            let Some(name) = name.strip_prefix('[') else {
+                continue;
+            };
+            let mut value = value.to_string();
+            while !value.ends_with(']') {
+                let Some(line) = lines.next() else {
+                    break;
+                };
+                value.push('\n');
+                value.push_str(line);
+            }
+            let value = value.strip_suffix(']').unwrap_or(&value);
+            props.insert(name.to_string(), value.to_string());
+        }
+        Self(props)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    /// The property parsed as a `T`, `None` if it is missing or does not parse.
+    pub fn get_parsed<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name)?.trim().parse().ok()
+    }
+
+    /// A flag, set as `1` or `true` and unset as `0` or `false`
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.get(name)?.trim() {
+            "1" | "true" => Some(true),
+            "0" | "false" => Some(false),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // ===== Common properties =====
+
+    /// `ro.product.cpu.abi`, i.e. `arm64-v8a`
+    pub fn abi(&self) -> Option<&str> {
+        self.get("ro.product.cpu.abi")
+    }
+
+    /// `ro.build.version.sdk`, the API level, i.e. `34`
+    pub fn sdk(&self) -> Option<u32> {
+        self.get_parsed("ro.build.version.sdk")
+    }
+
+    /// `ro.build.version.release`, i.e. `14`
+    pub fn release(&self) -> Option<&str> {
+        self.get("ro.build.version.release")
+    }
+
+    /// `ro.sf.lcd_density`, the density of the display in dpi, before any
+    /// `wm density` override
+    pub fn lcd_density(&self) -> Option<u32> {
+        self.get_parsed("ro.sf.lcd_density")
+    }
+
+    pub fn model(&self) -> Option<&str> {
+        self.get("ro.product.model")
+    }
+
+    pub fn manufacturer(&self) -> Option<&str> {
+        self.get("ro.product.manufacturer")
+    }
+
+    /// `sys.boot_completed`, set once Android finished booting
+    pub fn boot_completed(&self) -> bool {
+        self.get_bool("sys.boot_completed").unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_properties() {
+        let props = Properties::parse(
+            "[dalvik.vm.heapsize]: [512m]\n\
+             [ro.build.version.sdk]: [34]\n\
+             [ro.product.cpu.abi]: [arm64-v8a]\n\
+             [ro.product.model]: [Pixel 7]\n\
+             [ro.sf.lcd_density]: [440]\n\
+             [sys.boot_completed]: [1]\n\
+             [persist.sys.motd]: [first line\n\
+             second line]\n\
+             [ro.empty]: []\n",
+        );
+        assert_eq!(props.len(), 8);
+        assert_eq!(props.abi(), Some("arm64-v8a"));
+        assert_eq!(props.sdk(), Some(34));
+        assert_eq!(props.model(), Some("Pixel 7"));
+        assert_eq!(props.lcd_density(), Some(440));
+        assert!(props.boot_completed());
+        assert_eq!(
+            props.get("persist.sys.motd"),
+            Some("first line\nsecond line")
+        );
+        assert_eq!(props.get("ro.empty"), Some(""));
+        assert_eq!(props.get_parsed::<u32>("dalvik.vm.heapsize"), None);
+        assert_eq!(props.release(), None);
+        assert!(!Properties::parse("").boot_completed());
+    }
+}
@@ -0,0 +1,112 @@
+//! Making sure an adb server is running
+//!
+//! [`ensure_running`] checks the server with `host:version`, starts one with
+//! `adb start-server` if there is none and waits for it with an exponential
+//! backoff, see [`AdbServerConfig::auto_start`].
+
+use std::{
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::{info, trace, warn};
+
+use crate::{
+    AdbTcpStream,
+    command::host_service::Version,
+    config::AdbServerConfig,
+    error::{AdbError, AdbResult},
+};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(50);
+const MAX_BACKOFF: Duration = Duration::from_secs(1);
+
+/// The protocol version of the server, i.e. `41`.
+pub fn version(config: &AdbServerConfig) -> AdbResult<u32> {
+    let version = AdbTcpStream::connect_with(config)?.execute_command(Version::new())?;
+    Ok(u32::from_str_radix(version.trim(), 16)?)
+}
+
+pub fn is_running(config: &AdbServerConfig) -> bool {
+    version(config).is_ok()
+}
+
+/// Spawn `adb start-server` for the server of `config`, without waiting for it.
+pub fn start(config: &AdbServerConfig) -> AdbResult<()> {
+    info!("starting adb server on {}...", config.addr);
+    Command::new("adb")
+        .args(["-L", &config.socket_spec(), "start-server"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+/// Wait for the server of `config` to answer, starting it first if it does not
+/// and [`AdbServerConfig::auto_start`] is set. Fails with
+/// [`AdbError::ServerNotConnected`] after [`AdbServerConfig::start_timeout`].
+pub fn ensure_running(config: &AdbServerConfig) -> AdbResult<()> {
+    if is_running(config) {
+        return Ok(());
+    }
+    // Only a server on this machine can be started
+    if config.auto_start
+        && config.addr.ip().is_loopback()
+        && let Err(err) = start(config)
+    {
+        warn!("failed to run adb start-server: {err}");
+    }
+
+    let deadline = Instant::now() + config.start_timeout;
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(AdbError::ServerNotConnected);
+        }
+        thread::sleep(backoff.min(remaining));
+        match version(config) {
+            Ok(_) => return Ok(()),
+            Err(err) => trace!("adb server is not up yet: {err}"),
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_ensure_running() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = AdbServerConfig::default()
+            .with_addr(listener.local_addr().unwrap())
+            .with_auto_start(false)
+            .with_start_timeout(Duration::from_millis(200));
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 16];
+            stream.read_exact(&mut request).unwrap();
+            assert_eq!(&request, b"000chost:version");
+            stream.write_all(b"OKAY00040029").unwrap();
+        });
+        assert_eq!(version(&config).unwrap(), 0x29);
+        server.join().unwrap();
+
+        // Nothing is listening anymore
+        let start = Instant::now();
+        assert!(matches!(
+            ensure_running(&config),
+            Err(AdbError::ServerNotConnected)
+        ));
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
+}
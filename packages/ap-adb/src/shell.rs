@@ -0,0 +1,275 @@
+//! Interactive shell sessions over the `shell,v2` protocol
+//!
+//! Every packet is a 1 byte id, a little endian u32 length and that many bytes,
+//! which keeps stdout, stderr and the exit code apart on a single connection.
+//! A [`ShellSession`] keeps one shell running to execute many commands without
+//! opening a new transport for each of them, and keeps its state (working
+//! directory, variables) in between.
+
+use std::{
+    io::{Read, Write},
+    time::Duration,
+};
+
+use crate::{
+    AdbTcpStream,
+    error::{AdbError, AdbResult},
+};
+
+pub const ID_STDIN: u8 = 0;
+pub const ID_STDOUT: u8 = 1;
+pub const ID_STDERR: u8 = 2;
+pub const ID_EXIT: u8 = 3;
+pub const ID_CLOSE_STDIN: u8 = 4;
+pub const ID_WINDOW_SIZE_CHANGE: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShellPacket {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    Exit(u8),
+    /// Packets the host is not expected to receive
+    Other(u8, Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShellOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+}
+
+impl ShellOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+
+    /// Turn a non zero exit code of `command` into [`AdbError::NonZeroExit`].
+    pub fn check(self, command: impl Into<String>) -> AdbResult<Self> {
+        if self.success() {
+            Ok(self)
+        } else {
+            Err(AdbError::NonZeroExit {
+                command: command.into(),
+                output: self,
+            })
+        }
+    }
+}
+
+/// Read a packet from a `shell,v2` stream.
+pub fn read_packet<T: Read>(source: &mut T) -> AdbResult<ShellPacket> {
+    let mut header = [0; 5];
+    source.read_exact(&mut header)?;
+    let len = u32::from_le_bytes(header[1..5].try_into().unwrap()) as usize;
+    let mut data = vec![0; len];
+    source.read_exact(&mut data)?;
+    Ok(match header[0] {
+        ID_STDOUT => ShellPacket::Stdout(data),
+        ID_STDERR => ShellPacket::Stderr(data),
+        ID_EXIT => ShellPacket::Exit(data.first().copied().unwrap_or_default()),
+        id => ShellPacket::Other(id, data),
+    })
+}
+
+/// A shell running on the device, see [`Device::open_shell`](crate::Device::open_shell).
+pub struct ShellSession {
+    stream: AdbTcpStream,
+    /// Number of commands run so far, to tell their end markers apart
+    commands: u64,
+    exit_code: Option<u8>,
+}
+
+impl ShellSession {
+    /// `stream` should have been accepted by a `shell,v2` service.
+    pub fn new(stream: AdbTcpStream) -> AdbResult<Self> {
+        stream.set_read_timeout(None)?;
+        Ok(Self {
+            stream,
+            commands: 0,
+            exit_code: None,
+        })
+    }
+
+    /// How long to wait for output before giving up, `None` (the default) waits forever.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> AdbResult<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    /// The exit code of the shell, once it has exited.
+    pub fn exit_code(&self) -> Option<u8> {
+        self.exit_code
+    }
+
+    pub fn write_packet(&mut self, id: u8, data: &[u8]) -> AdbResult<()> {
+        self.stream.write_all(&[id])?;
+        self.stream.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.stream.write_all(data)?;
+        Ok(())
+    }
+
+    pub fn write_stdin(&mut self, data: &[u8]) -> AdbResult<()> {
+        self.write_packet(ID_STDIN, data)
+    }
+
+    pub fn read_packet(&mut self) -> AdbResult<ShellPacket> {
+        let packet = read_packet(&mut self.stream)?;
+        if let ShellPacket::Exit(code) = packet {
+            self.exit_code = Some(code);
+        }
+        Ok(packet)
+    }
+
+    /// Run `command` in the shell and wait for it to finish.
+    pub fn run(&mut self, command: impl AsRef<str>) -> AdbResult<ShellOutput> {
+        if let Some(code) = self.exit_code {
+            return Err(AdbError::CommandFailed(format!(
+                "shell already exited with code {code}"
+            )));
+        }
+        self.commands += 1;
+        let marker = format!("__AP_END_{}__", self.commands);
+        // The end marker is printed on its own line to both streams, with the exit
+        // code of the command on stdout.
+        let script = format!(
+            "{}\nprintf '\\n{marker} %d\\n' $?; printf '\\n{marker}\\n' >&2\n",
+            command.as_ref()
+        );
+        self.write_stdin(script.as_bytes())?;
+
+        let stdout_end = format!("\n{marker} ");
+        let stderr_end = format!("\n{marker}\n");
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let mut exit_code = None;
+        let mut stderr_done = false;
+        while exit_code.is_none() || !stderr_done {
+            match self.read_packet()? {
+                ShellPacket::Stdout(data) => {
+                    stdout.extend(data);
+                    if let Some((output, code)) = split_marker(&stdout, &stdout_end) {
+                        exit_code = Some(code);
+                        stdout.truncate(output);
+                    }
+                }
+                ShellPacket::Stderr(data) => {
+                    stderr.extend(data);
+                    if stderr.ends_with(stderr_end.as_bytes()) {
+                        stderr_done = true;
+                        stderr.truncate(stderr.len() - stderr_end.len());
+                    }
+                }
+                ShellPacket::Exit(code) => {
+                    return Err(AdbError::CommandFailed(format!(
+                        "shell exited with code {code}"
+                    )));
+                }
+                ShellPacket::Other(..) => {}
+            }
+        }
+        Ok(ShellOutput {
+            stdout: String::from_utf8_lossy(&stdout).to_string(),
+            stderr: String::from_utf8_lossy(&stderr).to_string(),
+            exit_code: exit_code.unwrap(),
+        })
+    }
+
+    /// Close the stdin of the shell and wait for it to exit.
+    pub fn close(mut self) -> AdbResult<u8> {
+        if let Some(code) = self.exit_code {
+            return Ok(code);
+        }
+        self.write_packet(ID_CLOSE_STDIN, &[])?;
+        loop {
+            if let ShellPacket::Exit(code) = self.read_packet()? {
+                return Ok(code);
+            }
+        }
+    }
+}
+
+/// If `output` ends with `marker` followed by an exit code and a newline, the
+/// length of the output before it and the exit code.
+fn split_marker(output: &[u8], marker: &str) -> Option<(usize, i32)> {
+    if output.last() != Some(&b'\n') {
+        return None;
+    }
+    let start = output
+        .windows(marker.len())
+        .rposition(|window| window == marker.as_bytes())?;
+    let code = std::str::from_utf8(&output[start + marker.len()..output.len() - 1]).ok()?;
+    Some((start, code.parse().ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{SocketAddr, TcpListener},
+        thread,
+    };
+
+    use super::*;
+
+    fn write_packet(stream: &mut impl Write, id: u8, data: &[u8]) {
+        stream.write_all(&[id]).unwrap();
+        stream
+            .write_all(&(data.len() as u32).to_le_bytes())
+            .unwrap();
+        stream.write_all(data).unwrap();
+    }
+
+    #[test]
+    fn test_split_marker() {
+        assert_eq!(split_marker(b"hi\n__END__ 0\n", "\n__END__ "), Some((2, 0)));
+        assert_eq!(
+            split_marker(b"\n__END__ 127\n", "\n__END__ "),
+            Some((0, 127))
+        );
+        assert_eq!(split_marker(b"hi\n__END__ 1", "\n__END__ "), None);
+        assert_eq!(split_marker(b"hi\n", "\n__END__ "), None);
+    }
+
+    #[test]
+    fn test_run() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let SocketAddr::V4(addr) = listener.local_addr().unwrap() else {
+            unreachable!()
+        };
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            for _ in 0..2 {
+                let mut header = [0; 5];
+                stream.read_exact(&mut header).unwrap();
+                assert_eq!(header[0], ID_STDIN);
+                let mut script =
+                    vec![0; u32::from_le_bytes(header[1..].try_into().unwrap()) as usize];
+                stream.read_exact(&mut script).unwrap();
+                let script = String::from_utf8(script).unwrap();
+                let marker = &script[script.find("__AP_END_").unwrap()..];
+                let marker = &marker[..marker.find("__ ").unwrap() + 2];
+
+                // Output split across packets, stderr finishing after stdout
+                write_packet(&mut stream, ID_STDOUT, b"hello");
+                write_packet(&mut stream, ID_STDOUT, format!("\n{marker} 3\n").as_bytes());
+                write_packet(
+                    &mut stream,
+                    ID_STDERR,
+                    format!("oops\n\n{marker}\n").as_bytes(),
+                );
+            }
+            let mut header = [0; 5];
+            stream.read_exact(&mut header).unwrap();
+            assert_eq!(header[0], ID_CLOSE_STDIN);
+            write_packet(&mut stream, ID_EXIT, &[0]);
+        });
+
+        let mut session = ShellSession::new(AdbTcpStream::connect(addr).unwrap()).unwrap();
+        for _ in 0..2 {
+            let output = session.run("echo hello; echo oops >&2; false").unwrap();
+            assert_eq!(output.stdout, "hello");
+            assert_eq!(output.stderr, "oops\n");
+            assert_eq!(output.exit_code, 3);
+        }
+        assert_eq!(session.close().unwrap(), 0);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,325 @@
+//! Coordinates that follow the layout of a game across screen sizes
+//!
+//! Scaling 1920x1080 coordinates by the height, as
+//! [`click_scaled`](crate::ControllerTrait::click_scaled) does, only works on
+//! 16:9 screens: on a wider one the game keeps its buttons at the edges, so
+//! everything right of the center moves. An anchored coordinate is given
+//! relative to the 1920x1080 layout, from `0.0` to `1.0` across it, with the
+//! [`Anchor`] it keeps its distance to. A button in the bottom right corner is
+//! anchored there and stays in the corner on any screen.
+//!
+//! The layout is fitted in the area the game draws to, the
+//! [content area](crate::Controller::content_area), which leaves out the black
+//! bars of a letterboxed game.
+
+use image::{RgbaImage, math::Rect};
+use serde::{Deserialize, Serialize};
+
+use crate::DEFAULT_HEIGHT;
+
+/// Reference width of the layout (1080p)
+pub const DEFAULT_WIDTH: u32 = 1920;
+
+/// The brightest a pixel of a black bar may be, on its brightest channel
+const BAR_BRIGHTNESS: u8 = 16;
+
+/// The point of the screen an anchored coordinate is relative to, `"top-left"`
+/// or `"center"` in a task.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anchor {
+    #[default]
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Where the anchor is across the width and the height, from `0.0` to `1.0`
+    pub fn position(self) -> (f32, f32) {
+        match self {
+            Anchor::TopLeft => (0.0, 0.0),
+            Anchor::Top => (0.5, 0.0),
+            Anchor::TopRight => (1.0, 0.0),
+            Anchor::Left => (0.0, 0.5),
+            Anchor::Center => (0.5, 0.5),
+            Anchor::Right => (1.0, 0.5),
+            Anchor::BottomLeft => (0.0, 1.0),
+            Anchor::Bottom => (0.5, 1.0),
+            Anchor::BottomRight => (1.0, 1.0),
+        }
+    }
+}
+
+/// `(x, y)` of the 1920x1080 layout in `content`, as a fraction of the layout,
+/// kept at the same distance from `anchor` scaled to fit.
+fn place(anchor: Anchor, (x, y): (f32, f32), content: Rect) -> (f32, f32) {
+    let (ax, ay) = anchor.position();
+    let (width, height) = (DEFAULT_WIDTH as f32, DEFAULT_HEIGHT as f32);
+    let scale = (content.width as f32 / width).min(content.height as f32 / height);
+    (
+        content.x as f32 + ax * content.width as f32 + (x - ax) * width * scale,
+        content.y as f32 + ay * content.height as f32 + (y - ay) * height * scale,
+    )
+}
+
+/// A point anchored to the layout, `{ anchor = "bottom-right", x = 0.95, y = 0.9 }`
+/// in a task.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AnchoredPoint {
+    pub anchor: Anchor,
+    pub x: f32,
+    pub y: f32,
+}
+
+impl AnchoredPoint {
+    /// The point on a screen where the game is drawn in `content`. It is off the
+    /// screen, possibly negative, when out of `0.0..=1.0`.
+    pub fn resolve(&self, content: Rect) -> (i32, i32) {
+        let (x, y) = place(self.anchor, (self.x, self.y), content);
+        (x.round() as i32, y.round() as i32)
+    }
+}
+
+/// A rect anchored to the layout, with its size as a fraction of the layout too.
+/// A task with a size that is not positive fails to load.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "AnchoredRectRepr")]
+pub struct AnchoredRect {
+    pub anchor: Anchor,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Deserialize)]
+struct AnchoredRectRepr {
+    anchor: Anchor,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+}
+
+impl TryFrom<AnchoredRectRepr> for AnchoredRect {
+    type Error = String;
+
+    fn try_from(repr: AnchoredRectRepr) -> Result<Self, Self::Error> {
+        // Written so that NaN fails too
+        if !(repr.width > 0.0 && repr.height > 0.0) {
+            return Err(format!(
+                "the size of an anchored rect should be positive, got {}x{}",
+                repr.width, repr.height
+            ));
+        }
+        Ok(Self {
+            anchor: repr.anchor,
+            x: repr.x,
+            y: repr.y,
+            width: repr.width,
+            height: repr.height,
+        })
+    }
+}
+
+impl AnchoredRect {
+    /// Whether it is within the layout, and so on the screen
+    pub fn is_within_layout(&self) -> bool {
+        self.x >= 0.0
+            && self.y >= 0.0
+            && self.width > 0.0
+            && self.height > 0.0
+            && self.x + self.width <= 1.0
+            && self.y + self.height <= 1.0
+    }
+
+    /// The rect on a screen where the game is drawn in `content`, cut to it. It
+    /// is empty if the size is not positive.
+    pub fn resolve(&self, content: Rect) -> Rect {
+        let (x0, y0) = place(self.anchor, (self.x, self.y), content);
+        let (x1, y1) = place(
+            self.anchor,
+            (self.x + self.width, self.y + self.height),
+            content,
+        );
+        let clamp_x =
+            |x: f32| (x.round().max(content.x as f32) as u32).min(content.x + content.width);
+        let clamp_y =
+            |y: f32| (y.round().max(content.y as f32) as u32).min(content.y + content.height);
+        let (x0, x1) = (clamp_x(x0), clamp_x(x1));
+        let (y0, y1) = (clamp_y(y0), clamp_y(y1));
+        Rect {
+            x: x0,
+            y: y0,
+            width: x1.saturating_sub(x0),
+            height: y1.saturating_sub(y0),
+        }
+    }
+}
+
+/// Whether every sampled pixel of the pixels given is dark enough for a bar
+fn is_bar(mut pixels: impl Iterator<Item = [u8; 4]>) -> bool {
+    pixels.all(|[r, g, b, _]| r.max(g).max(b) <= BAR_BRIGHTNESS)
+}
+
+/// The area of `screen` inside the black bars of a letterboxed or pillarboxed
+/// game, `None` if it is all black, i.e. during a loading screen.
+///
+/// Bars are only cut off in pairs of about the same size, so a dark edge of the
+/// game itself is kept.
+pub fn detect_content_area(screen: &RgbaImage) -> Option<Rect> {
+    let (width, height) = screen.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    // Every pixel is not needed to tell a bar, a few dozen per line are
+    let step_x = (width / 64).max(1) as usize;
+    let step_y = (height / 64).max(1) as usize;
+    let row = |y: u32| is_bar((0..width).step_by(step_x).map(|x| screen.get_pixel(x, y).0));
+    let column = |x: u32| {
+        is_bar(
+            (0..height)
+                .step_by(step_y)
+                .map(|y| screen.get_pixel(x, y).0),
+        )
+    };
+
+    let top = (0..height).take_while(|&y| row(y)).count() as u32;
+    if top == height {
+        return None;
+    }
+    let bottom = (0..height).rev().take_while(|&y| row(y)).count() as u32;
+    let left = (0..width).take_while(|&x| column(x)).count() as u32;
+    let right = (0..width).rev().take_while(|&x| column(x)).count() as u32;
+
+    // Centered bars differ by a pixel or two of rounding
+    let pair = |a: u32, b: u32, size: u32| {
+        let tolerance = (size / 100).max(2);
+        match a.abs_diff(b) <= tolerance {
+            true => (a, b),
+            false => (0, 0),
+        }
+    };
+    let (top, bottom) = pair(top, bottom, height);
+    let (left, right) = pair(left, right, width);
+    Some(Rect {
+        x: left,
+        y: top,
+        width: width - left - right,
+        height: height - top - bottom,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let point = |anchor, x, y| AnchoredPoint { anchor, x, y };
+        // 16:9 is the layout scaled
+        let content = rect(0, 0, 1280, 720);
+        assert_eq!(
+            point(Anchor::TopLeft, 0.5, 0.5).resolve(content),
+            (640, 360)
+        );
+        assert_eq!(
+            point(Anchor::BottomRight, 0.5, 0.5).resolve(content),
+            (640, 360)
+        );
+
+        // 21:9 keeps the height, each anchor keeps its distance
+        let content = rect(0, 0, 2560, 1080);
+        assert_eq!(
+            point(Anchor::TopLeft, 0.1, 0.1).resolve(content),
+            (192, 108)
+        );
+        assert_eq!(
+            point(Anchor::Center, 0.6, 0.5).resolve(content),
+            (1472, 540)
+        );
+        assert_eq!(
+            point(Anchor::BottomRight, 0.9, 0.9).resolve(content),
+            (2368, 972)
+        );
+
+        // 16:10 keeps the width
+        let content = rect(0, 0, 1920, 1200);
+        assert_eq!(
+            point(Anchor::Bottom, 0.5, 1.0).resolve(content),
+            (960, 1200)
+        );
+        assert_eq!(point(Anchor::Top, 0.5, 0.0).resolve(content), (960, 0));
+
+        // Inside black bars
+        let content = rect(160, 0, 1600, 900);
+        assert_eq!(point(Anchor::TopLeft, 0.0, 0.0).resolve(content), (160, 0));
+
+        let region = AnchoredRect {
+            anchor: Anchor::BottomRight,
+            x: 0.75,
+            y: 0.5,
+            width: 0.25,
+            height: 0.5,
+        };
+        assert!(region.is_within_layout());
+        assert_eq!(
+            region.resolve(rect(0, 0, 2560, 1080)),
+            rect(2080, 540, 480, 540)
+        );
+
+        // Empty, not wrapped around, with a negative size
+        let flipped = AnchoredRect {
+            width: -0.25,
+            height: -0.5,
+            ..region
+        };
+        let resolved = flipped.resolve(rect(0, 0, 1920, 1080));
+        assert_eq!((resolved.width, resolved.height), (0, 0));
+    }
+
+    #[test]
+    fn test_deserialize_anchored_rect() {
+        let rect = |width: f32| {
+            serde_json::from_value::<AnchoredRect>(serde_json::json!({
+                "anchor": "center", "x": 0.25, "y": 0.25, "width": width, "height": 0.5,
+            }))
+        };
+        assert_eq!(rect(0.5).unwrap().width, 0.5);
+        assert!(rect(0.0).is_err());
+        assert!(rect(-0.5).is_err());
+    }
+
+    #[test]
+    fn test_detect_content_area() {
+        let mut screen = RgbaImage::from_pixel(400, 300, image::Rgba([0, 0, 0, 255]));
+        assert_eq!(detect_content_area(&screen), None);
+
+        // Pillarboxed, with a dark top edge in the game
+        for (x, y, pixel) in screen.enumerate_pixels_mut() {
+            if (50..350).contains(&x) && y >= 20 {
+                *pixel = image::Rgba([200, 100, 50, 255]);
+            }
+        }
+        assert_eq!(detect_content_area(&screen), Some(rect(50, 0, 300, 300)));
+
+        let screen = RgbaImage::from_pixel(400, 300, image::Rgba([90, 90, 90, 255]));
+        assert_eq!(detect_content_area(&screen), Some(rect(0, 0, 400, 300)));
+    }
+}
@@ -1,7 +1,8 @@
 //! An [`App`] is an application that will be pushed to the device and run.
 
-// pub mod minicap;
 pub mod maatouch;
+pub mod minicap;
+pub mod minitouch;
 
 use ap_adb::Device;
 
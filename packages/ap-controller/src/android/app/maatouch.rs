@@ -1,26 +1,28 @@
 use std::{
     io::{BufRead, Write},
-    process::{Child, ChildStdin, Command, Stdio},
-    thread::{self, sleep},
+    thread,
     time::Duration,
 };
 
 use anyhow::Context;
 use color_print::cformat;
-use tempfile::NamedTempFile;
 use tracing::{debug, info, trace};
 
-use ap_adb::{command::local_service::ShellCommand, utils::execute_adb_command, Device};
+use ap_adb::{AdbTcpStream, Device, command::local_service::ShellCommand};
 
 const MAATOUCH: &[u8] = include_bytes!("./maatouch");
+const MAATOUCH_PATH: &str = "/data/local/tmp/maatouch";
 
 use super::App;
+use crate::{
+    gesture::{FRAME_INTERVAL, Gesture},
+    profile::TouchInfo,
+};
 
-/// After initialized, hold a child-stdin to write commands to maatouch
-/// If disconnected during using, it should be reconstructed
+/// After initialized, hold an adb stream connected to the stdin of maatouch to write commands to.
+/// If disconnected during using, it should be reconstructed, see [`MaaTouch::is_connected`].
 pub struct MaaTouch {
-    child: Child,
-    child_in: ChildStdin,
+    stream: AdbTcpStream,
     state: MaaTouchState,
 }
 
@@ -30,9 +32,9 @@ impl Drop for MaaTouch {
         // Before that we should not drop the controller, or the maatouch process will be killed.
         //
         // Ideally, maatouch should accept a "q" command to quit, and we wait for the process to quit here.
-        // Now we just wait for a short time to ensure the commands are executed.
+        // Now we just wait for a short time to ensure the commands are executed, maatouch exits
+        // once the stream is closed.
         thread::sleep(Duration::from_millis(100));
-        self.child.kill().unwrap()
     }
 }
 
@@ -44,6 +46,8 @@ pub struct MaaTouchState {
     max_x: u32, // 横屏的 x!
     max_y: u32,
     max_pressure: u32,
+    /// From screen to touch device coordinates, `None` if they are the same
+    scale: Option<(f32, f32)>,
 }
 
 impl App for MaaTouch {
@@ -54,9 +58,7 @@ impl App for MaaTouch {
 
         info!("[Minitouch]: checking maatouch...");
         let res = device_adb_stream
-            .execute_command(ShellCommand::new(
-                "file /data/local/tmp/maatouch".to_string(),
-            ))
+            .execute_command(ShellCommand::new(format!("file {MAATOUCH_PATH}")))
             .map_err(|err| anyhow::anyhow!("maatouch test failed: {err}"))?;
         info!("[Minitouch]: test output: {res}");
 
@@ -68,42 +70,13 @@ impl App for MaaTouch {
     }
 
     fn push(device: &Device) -> anyhow::Result<()> {
-        let mut tmpfile = NamedTempFile::new().context("failed to create tempfile")?;
-        tmpfile
-            .write_all(MAATOUCH)
-            .context("failed to write maatouch to tempfile")?;
-
         info!(
             "{}",
             cformat!("<dim>[Minitouch]: pushing maatouch to device...</dim>")
         );
-        let cmd = format!("push {} /data/local/tmp", tmpfile.path().to_str().unwrap());
-        let res = execute_adb_command(&device.serial(), &cmd)
-            .map_err(|err| anyhow::anyhow!("maatouch push failed: {:?}", err))?;
-        info!("{:?}", String::from_utf8(res));
-
-        info!(
-            "{}",
-            cformat!(
-                "<dim>[Minitouch]: renaming {:?} to maatouch...</dim>",
-                tmpfile.path().file_name()
-            )
-        );
-        let cmd = format!(
-            "shell mv /data/local/tmp/{} /data/local/tmp/maatouch",
-            tmpfile.path().file_name().unwrap().to_str().unwrap()
-        );
-        let res = execute_adb_command(&device.serial(), &cmd)
-            .map_err(|err| anyhow::anyhow!("maatouch rename failed: {:?}", err))?;
-        info!("<dim>[Minitouch]: {:?}</dim>", String::from_utf8(res));
-
-        info!(
-            "{}",
-            cformat!("<dim>[Minitouch]: adding execute permission to maatouch...</dim>")
-        );
-        let res = execute_adb_command(&device.serial(), "shell chmod +x /data/local/tmp/maatouch")
-            .map_err(|err| anyhow::anyhow!("maatouch push failed: {:?}", err))?;
-        info!("{:?}", String::from_utf8(res));
+        device
+            .push(MAATOUCH, MAATOUCH_PATH, 0o755)
+            .map_err(|err| anyhow::anyhow!("maatouch push failed: {err}"))?;
         Ok(())
     }
 
@@ -115,31 +88,30 @@ impl App for MaaTouch {
             "{}",
             cformat!("<dim>[Minitouch]: spawning maatouch...</dim>")
         );
-        let mut child = Command::new("adb")
-            .args(vec![
-                "-s",
-                device.serial().as_str(),
-                "shell",
-                "app_process -Djava.class.path=/data/local/tmp/maatouch /data/local/tmp com.shxyke.MaaTouch.App",
-            ])
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
+        let stream = device
+            .exec(format!(
+                "app_process -Djava.class.path={MAATOUCH_PATH} /data/local/tmp com.shxyke.MaaTouch.App"
+            ))
             .context("failed to spawn maatouch")?;
-        sleep(Duration::from_secs_f32(0.5));
+        Self::from_stream(stream)
+    }
+}
 
-        let child_in = child
-            .stdin
-            .take()
-            .ok_or(anyhow::anyhow!("cannot get stdin of maatouch"))?;
-        let child_out = child
-            .stdout
-            .take()
-            .ok_or(anyhow::anyhow!("cannot get stdout of maatouch"))?;
+const SWIPE_DELAY_MS: u32 = 5;
+const CLICK_DELAY_MS: u32 = 50;
+/// Between the two clicks of a double click
+const DOUBLE_CLICK_INTERVAL_MS: u32 = 100;
+
+impl MaaTouch {
+    /// Read the header from `stream`, connected to the stdin and stdout of a
+    /// process speaking the maatouch (or minitouch) protocol.
+    pub fn from_stream(stream: AdbTcpStream) -> anyhow::Result<Self> {
+        // Starting the jvm of maatouch takes a while
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
 
         let mut state = MaaTouchState::default();
         debug!("reading maatouch info...");
-        let mut reader = std::io::BufReader::new(child_out);
+        let mut reader = std::io::BufReader::new(stream.try_clone()?);
         loop {
             let mut buf = String::new();
             match reader.read_line(&mut buf) {
@@ -153,7 +125,7 @@ impl App for MaaTouch {
                 Ok(sz) => {
                     trace!("readed {sz} len: {buf:?}");
                     if sz == 0 {
-                        continue;
+                        anyhow::bail!("maatouch exited before it was ready");
                     }
                     buf = buf
                         .replace("\r\n", "\n")
@@ -182,6 +154,7 @@ impl App for MaaTouch {
                             max_x,
                             max_y,
                             max_pressure,
+                            scale: None,
                         };
                         info!(
                             "{}",
@@ -205,27 +178,44 @@ impl App for MaaTouch {
             "{}",
             cformat!("<dim>[Minitouch]: maatouch initialized</dim>")
         );
-        Ok(MaaTouch {
-            child,
-            child_in,
-            state,
-        })
+        stream.set_read_timeout(None)?;
+        Ok(MaaTouch { stream, state })
     }
-}
 
-const SWIPE_DELAY_MS: u32 = 5;
-const CLICK_DELAY_MS: u32 = 50;
+    /// Scale coordinates on a `width`x`height` screen to the touch device, for
+    /// minitouch, which takes touch device coordinates unlike maatouch.
+    pub fn with_screen_size(mut self, width: u32, height: u32) -> Self {
+        self.state.scale = Some((
+            self.state.max_x as f32 / width as f32,
+            self.state.max_y as f32 / height as f32,
+        ));
+        self
+    }
 
-impl MaaTouch {
     fn write_command(&mut self, command: &str) -> anyhow::Result<()> {
         trace!("[MaaTouch]: writing command {:?}", command);
         let mut command = command.to_string();
         if !command.ends_with('\n') {
             command.push('\n');
         }
-        self.child_in
+        self.stream
             .write_all(command.as_bytes())
-            .context("failed to write command")
+            .context("failed to write command, maatouch is disconnected")
+    }
+
+    /// Whether maatouch is still running, it stops when the device disconnects.
+    pub fn is_connected(&self) -> bool {
+        !self.stream.is_closed()
+    }
+
+    /// What maatouch reported when it started, see [`crate::profile`].
+    pub fn info(&self) -> TouchInfo {
+        TouchInfo {
+            max_contact: self.state.max_contact,
+            max_x: self.state.max_x,
+            max_y: self.state.max_y,
+            max_pressure: self.state.max_pressure,
+        }
     }
 
     pub fn commit(&mut self) -> anyhow::Result<()> {
@@ -237,6 +227,10 @@ impl MaaTouch {
     }
 
     pub fn down(&mut self, contact: u32, x: u32, y: u32, pressure: u32) -> anyhow::Result<()> {
+        let (x, y) = match self.state.scale {
+            Some((sx, sy)) => ((x as f32 * sx) as u32, (y as f32 * sy) as u32),
+            None => (x, y),
+        };
         // On MuMu emulator, the x-y is flipped and the y is also flipped (???)
         let (x, y) = if self.state.flip_xy {
             (self.state.max_y.saturating_add_signed(-(y as i32)), x)
@@ -247,6 +241,10 @@ impl MaaTouch {
     }
 
     pub fn mv(&mut self, contact: u32, x: i32, y: i32, pressure: u32) -> anyhow::Result<()> {
+        let (x, y) = match self.state.scale {
+            Some((sx, sy)) => ((x as f32 * sx) as i32, (y as f32 * sy) as i32),
+            None => (x, y),
+        };
         // On MuMu emulator, the x-y is flipped and the y is also flipped (???)
         let (x, y) = if self.state.flip_xy {
             (self.state.max_y as i32 - y, x)
@@ -275,6 +273,54 @@ impl MaaTouch {
         Ok(())
     }
 
+    pub fn long_press(&mut self, x: u32, y: u32, duration: Duration) -> anyhow::Result<()> {
+        debug!("[MaaTouch/long_press]: press at {x},{y} for {duration:?}");
+        self.down(0, x, y, self.state.max_pressure)?;
+        self.commit()?;
+        self.wait(duration)?;
+        self.up(0)?;
+        self.commit()?;
+        Ok(())
+    }
+
+    pub fn double_click(&mut self, x: u32, y: u32) -> anyhow::Result<()> {
+        self.click(x, y)?;
+        self.wait(Duration::from_millis(DOUBLE_CLICK_INTERVAL_MS as u64))?;
+        self.click(x, y)
+    }
+
+    /// Contact `i` of the gesture is contact `i` of maatouch.
+    pub fn multi_touch(&mut self, gesture: &Gesture) -> anyhow::Result<()> {
+        let contacts = gesture.contacts.len() as u32;
+        if contacts > self.state.max_contact {
+            anyhow::bail!(
+                "{contacts} contacts, maatouch supports {}",
+                self.state.max_contact
+            );
+        }
+        debug!(
+            "[MaaTouch/multi_touch]: {contacts} contacts for {:?}",
+            gesture.duration
+        );
+        let frames = gesture.frames(FRAME_INTERVAL)?;
+        let pressure = self.state.max_pressure;
+        for (contact, &(x, y)) in frames[0].iter().enumerate() {
+            self.down(contact as u32, x.max(0) as u32, y.max(0) as u32, pressure)?;
+        }
+        self.commit()?;
+        for frame in &frames[1..] {
+            self.wait(FRAME_INTERVAL)?;
+            for (contact, &(x, y)) in frame.iter().enumerate() {
+                self.mv(contact as u32, x, y, pressure)?;
+            }
+            self.commit()?;
+        }
+        for contact in 0..contacts {
+            self.up(contact)?;
+        }
+        self.commit()
+    }
+
     pub fn swipe(
         &mut self,
         start: (u32, u32),
@@ -0,0 +1,358 @@
+//! Screen capture with [minicap](https://github.com/DeviceFarmer/minicap)
+//!
+//! minicap streams JPEG frames over a local socket on the device as fast as the
+//! screen changes, which is forwarded to the host. A thread keeps the latest
+//! frame so [`Minicap::screencap`] returns a fresh screen without the ~800ms of
+//! `screencap -p`. Frames are only decoded when they are asked for, or for the
+//! subscribers of the [`FrameCache`].
+//!
+//! The binaries are not bundled, they depend on the ABI and SDK of the device.
+//! [`Minicap::init`] takes a directory laid out like the minicap prebuilt:
+//!
+//! ```text
+//! <prebuilt>/libs/<abi>/minicap
+//! <prebuilt>/jni/minicap-shared/aosp/libs/android-<sdk>/<abi>/minicap.so
+//! ```
+
+use std::{
+    io::Read,
+    net::TcpStream,
+    path::Path,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use color_print::cformat;
+use tracing::{info, trace, warn};
+
+use ap_adb::{AdbServerConfig, AdbTcpStream, Device, command::host_service::KillForward};
+
+use crate::capture::{Frame, FrameCache};
+
+const MINICAP_DIR: &str = "/data/local/tmp";
+const MINICAP_SOCKET: &str = "localabstract:minicap";
+/// How long minicap gets to start listening
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long [`Minicap::screencap`] waits for the first frame
+const FRAME_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// The global header minicap sends once a client connects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinicapHeader {
+    pub version: u8,
+    pub pid: u32,
+    pub real_width: u32,
+    pub real_height: u32,
+    pub virtual_width: u32,
+    pub virtual_height: u32,
+    /// In multiples of 90 degrees
+    pub orientation: u8,
+    pub quirks: u8,
+}
+
+impl MinicapHeader {
+    pub fn read<T: Read>(source: &mut T) -> anyhow::Result<Self> {
+        let mut prefix = [0; 2];
+        source.read_exact(&mut prefix)?;
+        let [version, len] = prefix;
+        if len < 24 {
+            anyhow::bail!("minicap header is too short: {len} bytes");
+        }
+        let mut rest = vec![0; len as usize - 2];
+        source.read_exact(&mut rest)?;
+        let u32_at = |i: usize| u32::from_le_bytes(rest[i..i + 4].try_into().unwrap());
+        Ok(Self {
+            version,
+            pid: u32_at(0),
+            real_width: u32_at(4),
+            real_height: u32_at(8),
+            virtual_width: u32_at(12),
+            virtual_height: u32_at(16),
+            orientation: rest[20],
+            quirks: rest[21],
+        })
+    }
+}
+
+/// Read a frame, a little endian u32 length followed by a JPEG.
+pub fn read_frame<T: Read>(source: &mut T) -> anyhow::Result<Vec<u8>> {
+    let mut len = [0; 4];
+    source.read_exact(&mut len)?;
+    let mut jpeg = vec![0; u32::from_le_bytes(len) as usize];
+    source.read_exact(&mut jpeg)?;
+    Ok(jpeg)
+}
+
+#[derive(Default)]
+struct JpegCache {
+    /// The latest JPEG and when it arrived, decoded only when it is asked for
+    jpeg: Option<(Arc<Vec<u8>>, Instant)>,
+    error: Option<String>,
+}
+
+fn decode(jpeg: &[u8], arrived_at: Instant) -> anyhow::Result<Frame> {
+    let image = image::load_from_memory_with_format(jpeg, image::ImageFormat::Jpeg)?;
+    Ok(Frame {
+        image: Arc::new(image.into_rgba8()),
+        captured_at: arrived_at,
+    })
+}
+
+pub struct Minicap {
+    header: MinicapHeader,
+    cache: Arc<(Mutex<JpegCache>, Condvar)>,
+    /// Decoded frames
+    frames: Arc<FrameCache>,
+    socket: TcpStream,
+    /// Connected to the minicap process, which exits when it is closed
+    _process: AdbTcpStream,
+    _forward: PortForward,
+}
+
+impl Drop for Minicap {
+    fn drop(&mut self) {
+        let _ = self.socket.shutdown(std::net::Shutdown::Both);
+    }
+}
+
+/// A port forwarded to the minicap socket, removed once dropped
+struct PortForward {
+    config: AdbServerConfig,
+    serial: String,
+    port: u16,
+}
+
+impl Drop for PortForward {
+    fn drop(&mut self) {
+        let local = format!("tcp:{}", self.port);
+        let res = AdbTcpStream::connect_with(&self.config)
+            .and_then(|mut stream| stream.execute_command(KillForward::new(&self.serial, &local)));
+        if let Err(err) = res {
+            warn!("[Minicap]: failed to remove the forward of {local}: {err}");
+        }
+    }
+}
+
+impl Minicap {
+    /// Whether minicap is already on the device and runs.
+    pub fn check(device: &Device) -> anyhow::Result<()> {
+        let output = device
+            .shell(format!(
+                "LD_LIBRARY_PATH={MINICAP_DIR} {MINICAP_DIR}/minicap -i"
+            ))
+            .context("minicap failed to run")?;
+        trace!("[Minicap]: display info: {}", output.stdout);
+        Ok(())
+    }
+
+    /// Push the binaries matching the ABI and SDK of the device from `prebuilt`.
+    pub fn push(device: &Device, prebuilt: &Path) -> anyhow::Result<()> {
+        let props = device.properties()?;
+        let abi = props.abi().context("ro.product.cpu.abi is not set")?;
+        let sdk = props.sdk().context("ro.build.version.sdk is not set")?;
+        info!(
+            "{}",
+            cformat!("<dim>[Minicap]: pushing minicap for {abi} android-{sdk}...</dim>")
+        );
+        let minicap = prebuilt.join("libs").join(abi).join("minicap");
+        let so = prebuilt
+            .join("jni/minicap-shared/aosp/libs")
+            .join(format!("android-{sdk}"))
+            .join(abi)
+            .join("minicap.so");
+        for (local, name) in [(&minicap, "minicap"), (&so, "minicap.so")] {
+            let data = std::fs::read(local)
+                .with_context(|| format!("failed to read {}", local.display()))?;
+            device.push(&data, format!("{MINICAP_DIR}/{name}"), 0o755)?;
+        }
+        Ok(())
+    }
+
+    /// Start minicap streaming the screen at `width`x`height` into `frames`.
+    pub fn build(
+        device: &Device,
+        width: u32,
+        height: u32,
+        frames: Arc<FrameCache>,
+    ) -> anyhow::Result<Self> {
+        info!("{}", cformat!("<dim>[Minicap]: spawning minicap...</dim>"));
+        // Only one minicap can listen on the socket
+        let _ = device.shell("pkill -f minicap");
+        let process = device.exec(format!(
+            "LD_LIBRARY_PATH={MINICAP_DIR} {MINICAP_DIR}/minicap -P {width}x{height}@{width}x{height}/0"
+        ))?;
+        let port = device.forward_free_port(MINICAP_SOCKET)?;
+        let forward = PortForward {
+            config: device.config().clone(),
+            serial: device.serial(),
+            port,
+        };
+
+        // The forward accepts connections before minicap listens and drops them
+        let deadline = Instant::now() + START_TIMEOUT;
+        let (socket, header) = loop {
+            let res = TcpStream::connect(("127.0.0.1", port))
+                .map_err(anyhow::Error::from)
+                .and_then(|mut socket| {
+                    socket.set_read_timeout(Some(START_TIMEOUT))?;
+                    let header = MinicapHeader::read(&mut socket)?;
+                    Ok((socket, header))
+                });
+            match res {
+                Ok(res) => break res,
+                Err(err) if Instant::now() < deadline => {
+                    trace!("[Minicap]: not ready yet: {err}");
+                    thread::sleep(Duration::from_millis(100));
+                }
+                Err(err) => return Err(err.context("minicap did not start")),
+            }
+        };
+        socket.set_read_timeout(None)?;
+        info!(
+            "{}",
+            cformat!(
+                "<dim>[Minicap]: streaming {}x{} (pid {})</dim>",
+                header.virtual_width,
+                header.virtual_height,
+                header.pid
+            )
+        );
+
+        let cache = Arc::new((Mutex::new(JpegCache::default()), Condvar::new()));
+        let reader_cache = cache.clone();
+        let reader_frames = frames.clone();
+        let mut reader = socket.try_clone()?;
+        thread::spawn(move || {
+            let (cache, frame_arrived) = &*reader_cache;
+            loop {
+                let res = read_frame(&mut reader).map(|jpeg| (Arc::new(jpeg), Instant::now()));
+                if let Ok((jpeg, arrived_at)) = &res
+                    && reader_frames.has_subscribers()
+                {
+                    match decode(jpeg, *arrived_at) {
+                        Ok(frame) => reader_frames.push_frame(frame),
+                        Err(err) => warn!("[Minicap]: failed to decode a frame: {err}"),
+                    }
+                }
+                let mut cache = cache.lock().unwrap();
+                match res {
+                    Ok(jpeg) => cache.jpeg = Some(jpeg),
+                    Err(err) => {
+                        warn!("[Minicap]: stream ended: {err}");
+                        cache.error = Some(err.to_string());
+                    }
+                }
+                frame_arrived.notify_all();
+                if cache.error.is_some() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            header,
+            cache,
+            frames,
+            socket,
+            _process: process,
+            _forward: forward,
+        })
+    }
+
+    pub fn init(
+        device: &Device,
+        prebuilt: &Path,
+        width: u32,
+        height: u32,
+        frames: Arc<FrameCache>,
+    ) -> anyhow::Result<Self> {
+        if Self::check(device).is_err() {
+            Self::push(device, prebuilt)?;
+            Self::check(device)?;
+        }
+        Self::build(device, width, height, frames)
+    }
+
+    pub fn header(&self) -> &MinicapHeader {
+        &self.header
+    }
+
+    /// Whether frames are still coming, it stops when the device disconnects.
+    pub fn is_connected(&self) -> bool {
+        self.cache.0.lock().unwrap().error.is_none()
+    }
+
+    /// The latest frame, waiting for the first one if there is none yet.
+    pub fn latest_jpeg(&self) -> anyhow::Result<(Arc<Vec<u8>>, Instant)> {
+        let (cache, frame_arrived) = &*self.cache;
+        let (cache, _) = frame_arrived
+            .wait_timeout_while(cache.lock().unwrap(), FRAME_TIMEOUT, |cache| {
+                cache.jpeg.is_none() && cache.error.is_none()
+            })
+            .unwrap();
+        if let Some(error) = &cache.error {
+            anyhow::bail!("minicap disconnected: {error}");
+        }
+        cache
+            .jpeg
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("no frame from minicap in {FRAME_TIMEOUT:?}"))
+    }
+
+    /// The latest frame decoded, or the same one if it was already.
+    pub fn latest_frame(&self) -> anyhow::Result<Frame> {
+        let (jpeg, arrived_at) = self.latest_jpeg()?;
+        if let Some(frame) = self.frames.latest()
+            && frame.captured_at >= arrived_at
+        {
+            return Ok(frame);
+        }
+        let frame = decode(&jpeg, arrived_at)?;
+        self.frames.push_frame(frame.clone());
+        Ok(frame)
+    }
+
+    pub fn screencap(&self) -> anyhow::Result<image::DynamicImage> {
+        Ok(image::DynamicImage::ImageRgba8(
+            self.latest_frame()?.into_image(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_read_stream() {
+        let mut stream = vec![1, 24];
+        for value in [1234u32, 1080, 1920, 540, 960] {
+            stream.extend(value.to_le_bytes());
+        }
+        stream.extend([1, 0b10]);
+        stream.extend(3u32.to_le_bytes());
+        stream.extend([0xff, 0xd8, 0xff]);
+
+        let mut stream = Cursor::new(stream);
+        let header = MinicapHeader::read(&mut stream).unwrap();
+        assert_eq!(
+            header,
+            MinicapHeader {
+                version: 1,
+                pid: 1234,
+                real_width: 1080,
+                real_height: 1920,
+                virtual_width: 540,
+                virtual_height: 960,
+                orientation: 1,
+                quirks: 0b10,
+            }
+        );
+        assert_eq!(read_frame(&mut stream).unwrap(), [0xff, 0xd8, 0xff]);
+        assert!(read_frame(&mut stream).is_err());
+    }
+}
@@ -0,0 +1,77 @@
+//! Touch input with [minitouch](https://github.com/DeviceFarmer/minitouch)
+//!
+//! For devices maatouch does not run on. minitouch speaks the same protocol
+//! through its stdin with `-i`, but in touch device coordinates, so it is driven
+//! by a [`MaaTouch`] scaling from the screen size.
+//!
+//! The binary is not bundled, it depends on the ABI of the device.
+//! [`Minitouch::init`] takes a directory laid out like the minitouch prebuilt:
+//!
+//! ```text
+//! <prebuilt>/libs/<abi>/minitouch
+//! ```
+
+use std::path::Path;
+
+use anyhow::Context;
+use color_print::cformat;
+use tracing::info;
+
+use ap_adb::Device;
+
+use super::maatouch::MaaTouch;
+
+const MINITOUCH_PATH: &str = "/data/local/tmp/minitouch";
+
+pub struct Minitouch;
+
+impl Minitouch {
+    /// Whether minitouch is already on the device.
+    pub fn check(device: &Device) -> anyhow::Result<()> {
+        device
+            .shell(format!("test -x {MINITOUCH_PATH}"))
+            .context("minitouch is not on the device")?;
+        Ok(())
+    }
+
+    /// Push the binary matching the ABI of the device from `prebuilt`.
+    pub fn push(device: &Device, prebuilt: &Path) -> anyhow::Result<()> {
+        let abi = device
+            .property("ro.product.cpu.abi")?
+            .context("ro.product.cpu.abi is not set")?;
+        info!(
+            "{}",
+            cformat!("<dim>[Minitouch]: pushing minitouch for {abi}...</dim>")
+        );
+        let local = prebuilt.join("libs").join(abi).join("minitouch");
+        let data =
+            std::fs::read(&local).with_context(|| format!("failed to read {}", local.display()))?;
+        device.push(&data, MINITOUCH_PATH, 0o755)?;
+        Ok(())
+    }
+
+    /// Spawn minitouch for a `width`x`height` screen.
+    pub fn build(device: &Device, width: u32, height: u32) -> anyhow::Result<MaaTouch> {
+        info!(
+            "{}",
+            cformat!("<dim>[Minitouch]: spawning minitouch...</dim>")
+        );
+        let stream = device
+            .exec(format!("{MINITOUCH_PATH} -i"))
+            .context("failed to spawn minitouch")?;
+        Ok(MaaTouch::from_stream(stream)?.with_screen_size(width, height))
+    }
+
+    pub fn init(
+        device: &Device,
+        prebuilt: &Path,
+        width: u32,
+        height: u32,
+    ) -> anyhow::Result<MaaTouch> {
+        if Self::check(device).is_err() {
+            Self::push(device, prebuilt)?;
+            Self::check(device)?;
+        }
+        Self::build(device, width, height)
+    }
+}
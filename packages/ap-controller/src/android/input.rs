@@ -0,0 +1,245 @@
+//! Ways to send touch input to the device
+//!
+//! maatouch is preferred, it is bundled and supports multi touch. Some devices
+//! can not run it (SELinux, old Android), where minitouch or plain `input`
+//! commands are used instead, see [`AndroidOptions::input_backend`].
+
+use std::{path::PathBuf, time::Duration};
+
+use ap_adb::{Device, command::local_service::Input};
+use tracing::warn;
+
+use super::app::{App, maatouch::MaaTouch, minitouch::Minitouch};
+use crate::{
+    Gesture,
+    profile::{ProfileCache, TouchInfo},
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum InputBackend {
+    /// [`app::maatouch`](super::app::maatouch)
+    #[default]
+    MaaTouch,
+    /// [`app::minitouch`](super::app::minitouch), with the binaries in `prebuilt`
+    Minitouch { prebuilt: PathBuf },
+    /// `input tap` and `input swipe`, which work everywhere but spawn a process
+    /// for each input and have no multi touch
+    AdbInput,
+}
+
+impl InputBackend {
+    /// `self`, then maatouch, then `input` commands as the last resort.
+    pub fn fallbacks(&self) -> Vec<InputBackend> {
+        let mut backends = vec![self.clone()];
+        for backend in [InputBackend::MaaTouch, InputBackend::AdbInput] {
+            if !backends.contains(&backend) {
+                backends.push(backend);
+            }
+        }
+        backends
+    }
+
+    /// With `installed`, maatouch is known to be on the device and started
+    /// without checking, it is pushed again only if it fails to start.
+    pub(super) fn init(
+        &self,
+        device: &Device,
+        width: u32,
+        height: u32,
+        installed: bool,
+    ) -> anyhow::Result<Touch> {
+        Ok(match self {
+            InputBackend::MaaTouch if installed => {
+                Touch::Stream(MaaTouch::build(device).or_else(|err| {
+                    warn!("failed to start maatouch, pushing it again: {err:#}");
+                    MaaTouch::init(device)
+                })?)
+            }
+            InputBackend::MaaTouch => Touch::Stream(MaaTouch::init(device)?),
+            InputBackend::Minitouch { prebuilt } => {
+                Touch::Stream(Minitouch::init(device, prebuilt, width, height)?)
+            }
+            InputBackend::AdbInput => Touch::AdbInput,
+        })
+    }
+
+    /// Initialize the first backend of [`InputBackend::fallbacks`] that works.
+    pub(super) fn init_with_fallbacks(
+        &self,
+        device: &Device,
+        width: u32,
+        height: u32,
+        installed: bool,
+    ) -> anyhow::Result<(InputBackend, Touch)> {
+        let mut errors = Vec::new();
+        for backend in self.fallbacks() {
+            match backend.init(device, width, height, installed) {
+                Ok(touch) => return Ok((backend, touch)),
+                Err(err) => {
+                    warn!("failed to initialize {backend:?}: {err:#}");
+                    errors.push(format!("{backend:?}: {err:#}"));
+                }
+            }
+        }
+        anyhow::bail!("no input backend works: {}", errors.join(", "))
+    }
+}
+
+/// How [`AndroidController::connect_with`](super::AndroidController::connect_with)
+/// connects.
+#[derive(Debug, Clone)]
+pub struct AndroidOptions {
+    pub server: ap_adb::AdbServerConfig,
+    /// Preferred backend, falling back to the others if it fails to initialize
+    pub input_backend: InputBackend,
+    /// Where the profile of the device is cached, `None` to probe it every time,
+    /// see [`crate::profile`]
+    pub profile_cache: Option<ProfileCache>,
+    /// How old a `screencap` may be to be reused, see
+    /// [`FrameCache::max_staleness`](crate::FrameCache::max_staleness). Zero by
+    /// default, which captures every time; around 100ms lets the checks of a
+    /// retry loop share a capture without missing an animation.
+    pub max_frame_age: Duration,
+}
+
+impl Default for AndroidOptions {
+    fn default() -> Self {
+        Self {
+            server: Default::default(),
+            input_backend: Default::default(),
+            profile_cache: Some(ProfileCache::default()),
+            max_frame_age: Duration::ZERO,
+        }
+    }
+}
+
+impl AndroidOptions {
+    pub fn with_server(mut self, server: ap_adb::AdbServerConfig) -> Self {
+        self.server = server;
+        self
+    }
+
+    pub fn with_input_backend(mut self, input_backend: InputBackend) -> Self {
+        self.input_backend = input_backend;
+        self
+    }
+
+    pub fn with_profile_cache(mut self, profile_cache: Option<ProfileCache>) -> Self {
+        self.profile_cache = profile_cache;
+        self
+    }
+
+    pub fn with_max_frame_age(mut self, max_frame_age: Duration) -> Self {
+        self.max_frame_age = max_frame_age;
+        self
+    }
+}
+
+/// An initialized [`InputBackend`].
+pub(super) enum Touch {
+    /// maatouch or minitouch
+    Stream(MaaTouch),
+    AdbInput,
+}
+
+impl Touch {
+    pub fn is_connected(&self) -> bool {
+        match self {
+            Touch::Stream(toucher) => toucher.is_connected(),
+            Touch::AdbInput => true,
+        }
+    }
+
+    pub fn info(&self) -> Option<TouchInfo> {
+        match self {
+            Touch::Stream(toucher) => Some(toucher.info()),
+            Touch::AdbInput => None,
+        }
+    }
+
+    pub fn click(&mut self, device: &Device, x: u32, y: u32) -> anyhow::Result<()> {
+        match self {
+            Touch::Stream(toucher) => toucher.click(x, y),
+            Touch::AdbInput => Ok(device.input(Input::Tap(x, y))?),
+        }
+    }
+
+    pub fn swipe(
+        &mut self,
+        device: &Device,
+        start: (u32, u32),
+        end: (i32, i32),
+        duration: Duration,
+        slope_in: f32,
+        slope_out: f32,
+    ) -> anyhow::Result<()> {
+        match self {
+            Touch::Stream(toucher) => toucher.swipe(start, end, duration, slope_in, slope_out),
+            // `input swipe` only moves linearly
+            Touch::AdbInput => Ok(device.input(Input::Swipe {
+                p1: start,
+                p2: end,
+                duration,
+            })?),
+        }
+    }
+
+    pub fn long_press(
+        &mut self,
+        device: &Device,
+        x: u32,
+        y: u32,
+        duration: Duration,
+    ) -> anyhow::Result<()> {
+        match self {
+            Touch::Stream(toucher) => toucher.long_press(x, y, duration),
+            Touch::AdbInput => Ok(device.input(Input::Swipe {
+                p1: (x, y),
+                p2: (x as i32, y as i32),
+                duration,
+            })?),
+        }
+    }
+
+    pub fn double_click(&mut self, device: &Device, x: u32, y: u32) -> anyhow::Result<()> {
+        match self {
+            Touch::Stream(toucher) => toucher.double_click(x, y),
+            // Each tap spawns a process, so this may be too slow to be a double tap
+            Touch::AdbInput => {
+                device.input(Input::Tap(x, y))?;
+                Ok(device.input(Input::Tap(x, y))?)
+            }
+        }
+    }
+
+    pub fn multi_touch(&mut self, gesture: &Gesture) -> anyhow::Result<()> {
+        match self {
+            Touch::Stream(toucher) => toucher.multi_touch(gesture),
+            Touch::AdbInput => anyhow::bail!("multi touch is not supported by adb input"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fallbacks() {
+        assert_eq!(
+            InputBackend::MaaTouch.fallbacks(),
+            [InputBackend::MaaTouch, InputBackend::AdbInput]
+        );
+        let minitouch = InputBackend::Minitouch {
+            prebuilt: PathBuf::from("minitouch"),
+        };
+        assert_eq!(
+            minitouch.fallbacks(),
+            [minitouch, InputBackend::MaaTouch, InputBackend::AdbInput]
+        );
+        assert_eq!(
+            InputBackend::AdbInput.fallbacks(),
+            [InputBackend::AdbInput, InputBackend::MaaTouch]
+        );
+    }
+}
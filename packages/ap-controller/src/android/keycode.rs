@@ -0,0 +1,193 @@
+//! Android key codes, see [`KeyEvent`](https://developer.android.com/reference/android/view/KeyEvent)
+//!
+//! [`enigo::Key`]s are mapped to them for [`ControllerTrait::press`](crate::ControllerTrait::press),
+//! keys without an [`enigo::Key`] are pressed with
+//! [`AndroidController::press_keycode`](super::AndroidController::press_keycode).
+
+pub const KEYCODE_HOME: u32 = 3;
+pub const KEYCODE_BACK: u32 = 4;
+pub const KEYCODE_CALL: u32 = 5;
+pub const KEYCODE_ENDCALL: u32 = 6;
+pub const KEYCODE_0: u32 = 7;
+pub const KEYCODE_STAR: u32 = 17;
+pub const KEYCODE_POUND: u32 = 18;
+pub const KEYCODE_DPAD_UP: u32 = 19;
+pub const KEYCODE_DPAD_DOWN: u32 = 20;
+pub const KEYCODE_DPAD_LEFT: u32 = 21;
+pub const KEYCODE_DPAD_RIGHT: u32 = 22;
+pub const KEYCODE_DPAD_CENTER: u32 = 23;
+pub const KEYCODE_VOLUME_UP: u32 = 24;
+pub const KEYCODE_VOLUME_DOWN: u32 = 25;
+pub const KEYCODE_POWER: u32 = 26;
+pub const KEYCODE_CAMERA: u32 = 27;
+pub const KEYCODE_A: u32 = 29;
+pub const KEYCODE_COMMA: u32 = 55;
+pub const KEYCODE_PERIOD: u32 = 56;
+pub const KEYCODE_ALT_LEFT: u32 = 57;
+pub const KEYCODE_ALT_RIGHT: u32 = 58;
+pub const KEYCODE_SHIFT_LEFT: u32 = 59;
+pub const KEYCODE_SHIFT_RIGHT: u32 = 60;
+pub const KEYCODE_TAB: u32 = 61;
+pub const KEYCODE_SPACE: u32 = 62;
+pub const KEYCODE_ENTER: u32 = 66;
+/// Backspace
+pub const KEYCODE_DEL: u32 = 67;
+pub const KEYCODE_GRAVE: u32 = 68;
+pub const KEYCODE_MINUS: u32 = 69;
+pub const KEYCODE_EQUALS: u32 = 70;
+pub const KEYCODE_LEFT_BRACKET: u32 = 71;
+pub const KEYCODE_RIGHT_BRACKET: u32 = 72;
+pub const KEYCODE_BACKSLASH: u32 = 73;
+pub const KEYCODE_SEMICOLON: u32 = 74;
+pub const KEYCODE_APOSTROPHE: u32 = 75;
+pub const KEYCODE_SLASH: u32 = 76;
+pub const KEYCODE_AT: u32 = 77;
+pub const KEYCODE_PLUS: u32 = 81;
+pub const KEYCODE_MENU: u32 = 82;
+pub const KEYCODE_SEARCH: u32 = 84;
+pub const KEYCODE_MEDIA_PLAY_PAUSE: u32 = 85;
+pub const KEYCODE_MEDIA_STOP: u32 = 86;
+pub const KEYCODE_MEDIA_NEXT: u32 = 87;
+pub const KEYCODE_MEDIA_PREVIOUS: u32 = 88;
+pub const KEYCODE_PAGE_UP: u32 = 92;
+pub const KEYCODE_PAGE_DOWN: u32 = 93;
+pub const KEYCODE_ESCAPE: u32 = 111;
+/// Delete
+pub const KEYCODE_FORWARD_DEL: u32 = 112;
+pub const KEYCODE_CTRL_LEFT: u32 = 113;
+pub const KEYCODE_CTRL_RIGHT: u32 = 114;
+pub const KEYCODE_CAPS_LOCK: u32 = 115;
+pub const KEYCODE_META_LEFT: u32 = 117;
+pub const KEYCODE_MOVE_HOME: u32 = 122;
+pub const KEYCODE_MOVE_END: u32 = 123;
+pub const KEYCODE_F1: u32 = 131;
+pub const KEYCODE_NUMPAD_0: u32 = 144;
+pub const KEYCODE_NUMPAD_DIVIDE: u32 = 154;
+pub const KEYCODE_NUMPAD_MULTIPLY: u32 = 155;
+pub const KEYCODE_NUMPAD_SUBTRACT: u32 = 156;
+pub const KEYCODE_NUMPAD_ADD: u32 = 157;
+pub const KEYCODE_NUMPAD_DOT: u32 = 158;
+pub const KEYCODE_VOLUME_MUTE: u32 = 164;
+pub const KEYCODE_APP_SWITCH: u32 = 187;
+pub const KEYCODE_SLEEP: u32 = 223;
+pub const KEYCODE_WAKEUP: u32 = 224;
+
+pub(super) trait AdbKeyEvent {
+    fn event_num(&self) -> Option<u32>;
+}
+
+/// Only keys every platform of enigo has are mapped. [`enigo::Key::Other`] is
+/// taken as an android key code as is.
+impl AdbKeyEvent for enigo::Key {
+    fn event_num(&self) -> Option<u32> {
+        use enigo::Key;
+
+        Some(match *self {
+            Key::Escape => KEYCODE_ESCAPE,
+            Key::Return => KEYCODE_ENTER,
+            Key::Backspace => KEYCODE_DEL,
+            Key::Delete => KEYCODE_FORWARD_DEL,
+            Key::Tab => KEYCODE_TAB,
+            Key::Space => KEYCODE_SPACE,
+            Key::UpArrow => KEYCODE_DPAD_UP,
+            Key::DownArrow => KEYCODE_DPAD_DOWN,
+            Key::LeftArrow => KEYCODE_DPAD_LEFT,
+            Key::RightArrow => KEYCODE_DPAD_RIGHT,
+            Key::Home => KEYCODE_MOVE_HOME,
+            Key::End => KEYCODE_MOVE_END,
+            Key::PageUp => KEYCODE_PAGE_UP,
+            Key::PageDown => KEYCODE_PAGE_DOWN,
+            Key::Shift | Key::LShift => KEYCODE_SHIFT_LEFT,
+            Key::RShift => KEYCODE_SHIFT_RIGHT,
+            Key::Control | Key::LControl => KEYCODE_CTRL_LEFT,
+            Key::RControl => KEYCODE_CTRL_RIGHT,
+            Key::Alt | Key::Option => KEYCODE_ALT_LEFT,
+            Key::Meta => KEYCODE_META_LEFT,
+            Key::CapsLock => KEYCODE_CAPS_LOCK,
+            Key::VolumeUp => KEYCODE_VOLUME_UP,
+            Key::VolumeDown => KEYCODE_VOLUME_DOWN,
+            Key::VolumeMute => KEYCODE_VOLUME_MUTE,
+            Key::MediaPlayPause => KEYCODE_MEDIA_PLAY_PAUSE,
+            Key::MediaNextTrack => KEYCODE_MEDIA_NEXT,
+            Key::MediaPrevTrack => KEYCODE_MEDIA_PREVIOUS,
+            Key::F1 => KEYCODE_F1,
+            Key::F2 => KEYCODE_F1 + 1,
+            Key::F3 => KEYCODE_F1 + 2,
+            Key::F4 => KEYCODE_F1 + 3,
+            Key::F5 => KEYCODE_F1 + 4,
+            Key::F6 => KEYCODE_F1 + 5,
+            Key::F7 => KEYCODE_F1 + 6,
+            Key::F8 => KEYCODE_F1 + 7,
+            Key::F9 => KEYCODE_F1 + 8,
+            Key::F10 => KEYCODE_F1 + 9,
+            Key::F11 => KEYCODE_F1 + 10,
+            Key::F12 => KEYCODE_F1 + 11,
+            Key::Numpad0 => KEYCODE_NUMPAD_0,
+            Key::Numpad1 => KEYCODE_NUMPAD_0 + 1,
+            Key::Numpad2 => KEYCODE_NUMPAD_0 + 2,
+            Key::Numpad3 => KEYCODE_NUMPAD_0 + 3,
+            Key::Numpad4 => KEYCODE_NUMPAD_0 + 4,
+            Key::Numpad5 => KEYCODE_NUMPAD_0 + 5,
+            Key::Numpad6 => KEYCODE_NUMPAD_0 + 6,
+            Key::Numpad7 => KEYCODE_NUMPAD_0 + 7,
+            Key::Numpad8 => KEYCODE_NUMPAD_0 + 8,
+            Key::Numpad9 => KEYCODE_NUMPAD_0 + 9,
+            Key::Add => KEYCODE_NUMPAD_ADD,
+            Key::Subtract => KEYCODE_NUMPAD_SUBTRACT,
+            Key::Multiply => KEYCODE_NUMPAD_MULTIPLY,
+            Key::Divide => KEYCODE_NUMPAD_DIVIDE,
+            Key::Decimal => KEYCODE_NUMPAD_DOT,
+            Key::Unicode(c) => return char_keycode(c),
+            Key::Other(keycode) => keycode,
+            _ => return None,
+        })
+    }
+}
+
+/// The key typing `c` without modifiers, letters of either case are the same key.
+fn char_keycode(c: char) -> Option<u32> {
+    Some(match c {
+        'a'..='z' => KEYCODE_A + (c as u32 - 'a' as u32),
+        'A'..='Z' => KEYCODE_A + (c as u32 - 'A' as u32),
+        '0'..='9' => KEYCODE_0 + (c as u32 - '0' as u32),
+        ' ' => KEYCODE_SPACE,
+        '\n' => KEYCODE_ENTER,
+        '\t' => KEYCODE_TAB,
+        ',' => KEYCODE_COMMA,
+        '.' => KEYCODE_PERIOD,
+        '`' => KEYCODE_GRAVE,
+        '-' => KEYCODE_MINUS,
+        '=' => KEYCODE_EQUALS,
+        '[' => KEYCODE_LEFT_BRACKET,
+        ']' => KEYCODE_RIGHT_BRACKET,
+        '\\' => KEYCODE_BACKSLASH,
+        ';' => KEYCODE_SEMICOLON,
+        '\'' => KEYCODE_APOSTROPHE,
+        '/' => KEYCODE_SLASH,
+        '@' => KEYCODE_AT,
+        '+' => KEYCODE_PLUS,
+        '*' => KEYCODE_STAR,
+        '#' => KEYCODE_POUND,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use enigo::Key;
+
+    use super::*;
+
+    #[test]
+    fn test_event_num() {
+        assert_eq!(Key::Escape.event_num(), Some(111));
+        assert_eq!(Key::Return.event_num(), Some(66));
+        assert_eq!(Key::F12.event_num(), Some(142));
+        assert_eq!(Key::Numpad9.event_num(), Some(153));
+        assert_eq!(Key::Unicode('a').event_num(), Some(29));
+        assert_eq!(Key::Unicode('Z').event_num(), Some(54));
+        assert_eq!(Key::Unicode('7').event_num(), Some(14));
+        assert_eq!(Key::Unicode('é').event_num(), None);
+        assert_eq!(Key::Other(KEYCODE_APP_SWITCH).event_num(), Some(187));
+    }
+}
@@ -1,22 +1,51 @@
 use std::{
-    sync::{Arc, Mutex},
-    time::Duration,
+    path::Path,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
 };
 
-use ap_adb::command::local_service::Input;
+use anyhow::Context;
+use ap_adb::{AdbError, command::local_service::Input};
 
-use app::App;
-use regex::Regex;
+use app::minicap::Minicap;
+use input::Touch;
+use keycode::AdbKeyEvent;
+use tracing::{info, warn};
 pub mod app;
+pub mod input;
+pub mod keycode;
 
-use crate::ControllerTrait;
+pub use input::{AndroidOptions, InputBackend};
+
+use crate::{
+    ControllerTrait, Gesture,
+    capability::{AppManagement, KeyEvents},
+    capture::{CaptureProvider, Frame, FrameCache},
+    profile::{DeviceProfile, ProfileCache, display_size},
+};
+
+/// The [ADBKeyboard](https://github.com/senzhk/ADBKeyBoard) IME
+const ADB_KEYBOARD_IME: &str = "com.android.adbkeyboard/.AdbIME";
 
 /// Android controller structure
 pub struct AndroidController {
     device: ap_adb::Device,
     width: u32,
     height: u32,
-    maa_touch: Arc<Mutex<app::maatouch::MaaTouch>>,
+    /// The backend in use, which may be a fallback of the preferred one
+    input_backend: InputBackend,
+    touch: Arc<Mutex<Touch>>,
+    /// Streams the screen if enabled, see [`AndroidController::with_minicap`]
+    minicap: Option<Minicap>,
+    /// Frames from minicap or `screencap`
+    frames: Arc<FrameCache>,
+    profile: DeviceProfile,
+    profile_cache: Option<ProfileCache>,
+    /// Whether the resolution of the profile was checked on a capture
+    profile_checked: AtomicBool,
 }
 
 impl AndroidController {
@@ -25,28 +54,158 @@ impl AndroidController {
         Self::from_device(device)
     }
 
+    /// Connect through the adb server of `options`, i.e. a remote one, with the
+    /// input backend of `options`.
+    pub fn connect_with(options: &AndroidOptions, serial: &str) -> anyhow::Result<Self> {
+        let device = ap_adb::connect_with(&options.server, serial)?;
+        Self::from_device_with(device, options)
+    }
+
+    /// Connect to `serial` as soon as it is online, i.e. after the emulator
+    /// restarted, giving up after `timeout`.
+    pub fn wait_for(serial: &str, timeout: Duration) -> anyhow::Result<Self> {
+        let deadline = Instant::now() + timeout;
+        let mut tracker = ap_adb::host::connect_default()?.track_devices()?;
+        loop {
+            match Self::connect(serial) {
+                Ok(controller) => return Ok(controller),
+                Err(err) => warn!("{serial} is not ready: {err:#}"),
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                anyhow::bail!("{serial} is not online after {timeout:?}");
+            }
+            // The adb server does not reconnect network devices on its own, so retry
+            // `adb connect` every second besides waiting for the device to show up.
+            tracker.set_timeout(Some(remaining.min(Duration::from_secs(1))))?;
+            match tracker.next() {
+                Some(Ok(_)) | Some(Err(AdbError::Timeout)) => {}
+                Some(Err(err)) => return Err(err.into()),
+                None => anyhow::bail!("device tracking ended"),
+            }
+        }
+    }
+
     pub fn from_device(device: ap_adb::Device) -> anyhow::Result<Self> {
-        let screen = device.screencap()?;
-        let (width, height) = (screen.width(), screen.height());
-        let maa_touch = app::maatouch::MaaTouch::init(&device)?;
-        let maa_touch = Arc::new(Mutex::new(maa_touch));
+        Self::from_device_with(device, &AndroidOptions::default())
+    }
+
+    /// Only the input backend and the profile cache of `options` are used, the
+    /// device is already connected.
+    ///
+    /// The profile of the device is probed unless it is cached for the display
+    /// the device has, see [`crate::profile`].
+    pub fn from_device_with(
+        device: ap_adb::Device,
+        options: &AndroidOptions,
+    ) -> anyhow::Result<Self> {
+        let serial = device.serial();
+        let cached = options
+            .profile_cache
+            .as_ref()
+            .and_then(|cache| cache.load(&serial));
+        let mut fresh = cached.is_none();
+        let mut profile = match cached {
+            Some(profile) => match display_size(&device) {
+                Ok(size) if !profile.fits_display(size) => {
+                    warn!(
+                        "the display of {serial} is {}x{} since it was probed, probing it again",
+                        size.0, size.1
+                    );
+                    fresh = true;
+                    DeviceProfile::probe(&device)?
+                }
+                Ok(_) => profile,
+                Err(err) => {
+                    warn!("failed to check the display of {serial}: {err:#}");
+                    profile
+                }
+            },
+            None => DeviceProfile::probe(&device)?,
+        };
+        let (width, height) = profile.resolution;
+        let (input_backend, touch) = options.input_backend.init_with_fallbacks(
+            &device,
+            width,
+            height,
+            profile.touch.is_some(),
+        )?;
+        info!("using input backend {input_backend:?}");
+        if let Some(cache) = &options.profile_cache
+            && (fresh || profile.touch.is_none() && touch.info().is_some())
+        {
+            profile.touch = touch.info();
+            if let Err(err) = cache.store(&profile) {
+                warn!("failed to cache the profile of {serial}: {err:#}");
+            }
+        }
+        let frames = FrameCache::new();
+        frames.set_max_staleness(options.max_frame_age);
         Ok(Self {
             device,
             width,
             height,
-            maa_touch,
+            input_backend,
+            touch: Arc::new(Mutex::new(touch)),
+            minicap: None,
+            frames: Arc::new(frames),
+            profile,
+            profile_cache: options.profile_cache.clone(),
+            profile_checked: AtomicBool::new(false),
         })
     }
 
+    /// What the device is, see [`crate::profile`].
+    pub fn profile(&self) -> &DeviceProfile {
+        &self.profile
+    }
+
+    /// Drop the profile from the cache if the first capture does not have its
+    /// resolution, i.e. the device was rotated since it was probed.
+    fn check_profile(&self, resolution: (u32, u32)) {
+        if self.profile_checked.swap(true, Ordering::Relaxed)
+            || resolution == self.profile.resolution
+        {
+            return;
+        }
+        let (width, height) = self.profile.resolution;
+        warn!(
+            "the screen of {} is {}x{}, not {width}x{height} as in its profile, reconnect to use it",
+            self.profile.serial, resolution.0, resolution.1
+        );
+        if let Some(cache) = &self.profile_cache {
+            cache.remove(&self.profile.serial);
+        }
+    }
+
+    /// Capture the screen from a minicap stream instead of `screencap`, with the
+    /// binaries in `prebuilt`, see [`app::minicap`].
+    pub fn with_minicap(mut self, prebuilt: impl AsRef<Path>) -> anyhow::Result<Self> {
+        self.minicap = Some(Minicap::init(
+            &self.device,
+            prebuilt.as_ref(),
+            self.width,
+            self.height,
+            self.frames.clone(),
+        )?);
+        Ok(self)
+    }
+
+    /// The minicap stream, if it is enabled and still connected.
+    fn minicap(&self) -> Option<&Minicap> {
+        let minicap = self.minicap.as_ref()?;
+        if minicap.is_connected() {
+            Some(minicap)
+        } else {
+            warn!("minicap disconnected, falling back to screencap");
+            None
+        }
+    }
+
     // ===== Android-specific methods =====
 
     pub fn is_screen_on(&self) -> anyhow::Result<bool> {
-        let output = self.device.execute_command_by_socket(
-            ap_adb::command::local_service::ShellCommand::new(
-                "dumpsys power | grep mWakefulness".to_string(),
-            ),
-        )?;
-        Ok(output.contains("mWakefulness=Awake"))
+        Ok(self.device.dumpsys_power()?.is_screen_on())
     }
 
     pub fn ensure_screen_on(&self) -> anyhow::Result<()> {
@@ -59,21 +218,15 @@ impl AndroidController {
     }
 
     pub fn get_abi(&self) -> anyhow::Result<String> {
-        let res = self.device.execute_command_by_socket(
-            ap_adb::command::local_service::ShellCommand::new(
-                "getprop ro.product.cpu.abi".to_string(),
-            ),
-        )?;
-        Ok(res.strip_suffix("\n").unwrap_or(&res).to_string())
+        self.device
+            .property("ro.product.cpu.abi")?
+            .context("ro.product.cpu.abi is not set")
     }
 
     pub fn get_sdk(&self) -> anyhow::Result<String> {
-        let res = self.device.execute_command_by_socket(
-            ap_adb::command::local_service::ShellCommand::new(
-                "getprop ro.build.version.sdk".to_string(),
-            ),
-        )?;
-        Ok(res.strip_suffix("\n").unwrap_or(&res).to_string())
+        self.device
+            .property("ro.build.version.sdk")?
+            .context("ro.build.version.sdk is not set")
     }
 
     pub fn press_home(&self) -> anyhow::Result<()> {
@@ -92,43 +245,91 @@ impl AndroidController {
         Ok(())
     }
 
+    pub fn has_adb_keyboard(&self) -> anyhow::Result<bool> {
+        let output = self.device.shell("ime list -s")?;
+        Ok(output
+            .stdout
+            .lines()
+            .any(|ime| ime.trim() == ADB_KEYBOARD_IME))
+    }
+
+    /// Type `text` with ADBKeyboard, which takes any unicode. The current IME is
+    /// switched to ADBKeyboard for it and restored afterwards.
+    pub fn input_text_with_adb_keyboard(&self, text: &str) -> anyhow::Result<()> {
+        if !self.has_adb_keyboard()? {
+            anyhow::bail!("ADBKeyboard ({ADB_KEYBOARD_IME}) is not installed");
+        }
+        let previous = self
+            .device
+            .shell("settings get secure default_input_method")?
+            .stdout
+            .trim()
+            .to_string();
+        if previous != ADB_KEYBOARD_IME {
+            self.device.shell(format!("ime set {ADB_KEYBOARD_IME}"))?;
+        }
+
+        let quoted = format!("'{}'", text.replace('\'', r"'\''"));
+        let res = self
+            .device
+            .shell(format!("am broadcast -a ADB_INPUT_TEXT --es msg {quoted}"));
+
+        if previous != ADB_KEYBOARD_IME && !previous.is_empty() && previous != "null" {
+            self.device.shell(format!("ime set {previous}"))?;
+        }
+        res?;
+        Ok(())
+    }
+
+    /// Press a key by its android key code, i.e. [`keycode::KEYCODE_APP_SWITCH`].
+    pub fn press_keycode(&self, keycode: u32) -> anyhow::Result<()> {
+        self.device
+            .input(Input::Keyevent(keycode.to_string()))
+            .map_err(|err| anyhow::anyhow!("failed to press keycode {keycode}: {err:?}"))
+    }
+
     pub fn launch_app(&self, intent: impl AsRef<str>) -> anyhow::Result<()> {
         let intent = intent.as_ref();
-        self.device.execute_command_by_socket(
-            ap_adb::command::local_service::ShellCommand::new(if intent.find("/").is_some() {
-                format!("am start -n {intent}")
-            } else {
-                format!("monkey -p {intent} 1")
-            }),
-        )?;
+        self.device.shell(if intent.find("/").is_some() {
+            format!("am start -n {intent}")
+        } else {
+            format!("monkey -p {intent} 1")
+        })?;
         Ok(())
     }
 
     pub fn stop_app(&self, intent: impl AsRef<str>) -> anyhow::Result<()> {
         let intent = intent.as_ref();
-        self.device.execute_command_by_socket(
-            ap_adb::command::local_service::ShellCommand::new(format!("am force-stop {intent}")),
-        )?;
+        self.device.shell(format!("am force-stop {intent}"))?;
         Ok(())
     }
 
-    /// `(<package>, <activity>)`
+    /// `(<package>, <activity>)`, `None` while no window has the focus, i.e.
+    /// between two activities.
     pub fn current_focus(&self) -> anyhow::Result<Option<(String, String)>> {
-        let res = self.device.execute_command_by_socket(
-            ap_adb::command::local_service::ShellCommand::new(
-                "dumpsys window | grep mCurrentFocus",
-            ),
-        )?;
-        let re =
-            Regex::new(r"mCurrentFocus=Window\{.*\s+(?P<package>[^\s/]+)/(?P<activity>[^\s\}]+)\}")
-                .unwrap();
-        let res = re
-            .captures(&res)
-            .ok_or(anyhow::anyhow!("Failed to parse current focus"))?;
-        Ok(res
-            .name("package")
-            .zip(res.name("activity"))
-            .map(|(p, a)| (p.as_str().to_string(), a.as_str().to_string())))
+        Ok(self
+            .device
+            .dumpsys_window()?
+            .current_focus
+            .map(|focus| (focus.package, focus.activity)))
+    }
+
+    /// The backend the inputs are sent with, the first of the
+    /// [fallbacks](InputBackend::fallbacks) of the one asked for that started.
+    pub fn input_backend(&self) -> &InputBackend {
+        &self.input_backend
+    }
+
+    /// The touch instance, restarted if it got disconnected.
+    fn touch(&self) -> anyhow::Result<MutexGuard<'_, Touch>> {
+        let mut touch = self.touch.lock().unwrap();
+        if !touch.is_connected() {
+            warn!("{:?} disconnected, restarting it...", self.input_backend);
+            *touch = self
+                .input_backend
+                .init(&self.device, self.width, self.height, true)?;
+        }
+        Ok(touch)
     }
 
     /// Get the underlying ADB device
@@ -137,25 +338,95 @@ impl AndroidController {
     }
 }
 
+impl CaptureProvider for AndroidController {
+    fn frames(&self) -> &FrameCache {
+        &self.frames
+    }
+
+    fn is_streaming(&self) -> bool {
+        self.minicap.as_ref().is_some_and(Minicap::is_connected)
+    }
+
+    fn latest_frame(&self) -> anyhow::Result<Frame> {
+        if let Some(minicap) = self.minicap() {
+            let frame = minicap.latest_frame()?;
+            self.check_profile(frame.image.dimensions());
+            return Ok(frame);
+        }
+        self.frames.get_or_capture(|| {
+            let (width, height, rgba) = self
+                .device
+                .screencap_raw()
+                .map_err(|err| anyhow::anyhow!("failed to get raw screencap: {err:?}"))?;
+            self.check_profile((width, height));
+            image::RgbaImage::from_raw(width, height, rgba)
+                .ok_or_else(|| anyhow::anyhow!("screencap data does not match {width}x{height}"))
+        })
+    }
+}
+
+impl KeyEvents for AndroidController {
+    // Used without the `Controller` around it, which invalidates the frame
+    // after the other inputs
+    fn press_keycode(&self, keycode: u32) -> anyhow::Result<()> {
+        let result = AndroidController::press_keycode(self, keycode);
+        self.frames.invalidate();
+        result
+    }
+}
+
+impl AppManagement for AndroidController {
+    fn launch_app(&self, app: &str) -> anyhow::Result<()> {
+        let result = AndroidController::launch_app(self, app);
+        self.frames.invalidate();
+        result
+    }
+
+    fn stop_app(&self, app: &str) -> anyhow::Result<()> {
+        let result = AndroidController::stop_app(self, app);
+        self.frames.invalidate();
+        result
+    }
+
+    fn current_app(&self) -> anyhow::Result<Option<String>> {
+        Ok(self.current_focus()?.map(|(package, _)| package))
+    }
+}
+
 impl ControllerTrait for AndroidController {
     fn screen_size(&self) -> (u32, u32) {
         (self.width, self.height)
     }
 
     fn screencap_raw(&self) -> anyhow::Result<(u32, u32, Vec<u8>)> {
-        self.device
-            .screencap_raw()
-            .map_err(|err| anyhow::anyhow!("failed to get raw screencap: {err:?}"))
+        let screen = self.latest_frame()?.into_image();
+        Ok((screen.width(), screen.height(), screen.into_raw()))
     }
 
     fn screencap(&self) -> anyhow::Result<image::DynamicImage> {
-        self.device
-            .screencap()
-            .map_err(|err| anyhow::anyhow!("failed to get screencap: {err:?}"))
+        Ok(image::DynamicImage::ImageRgba8(
+            self.latest_frame()?.into_image(),
+        ))
+    }
+
+    fn capture_provider(&self) -> Option<&dyn CaptureProvider> {
+        Some(self)
+    }
+
+    fn key_events(&self) -> Option<&dyn KeyEvents> {
+        Some(self)
+    }
+
+    fn app_management(&self) -> Option<&dyn AppManagement> {
+        Some(self)
+    }
+
+    fn device_profile(&self) -> Option<&DeviceProfile> {
+        Some(&self.profile)
     }
 
     fn click(&self, x: u32, y: u32) -> anyhow::Result<()> {
-        self.maa_touch.lock().unwrap().click(x, y)
+        self.touch()?.click(&self.device, x, y)
     }
 
     fn swipe(
@@ -166,11 +437,33 @@ impl ControllerTrait for AndroidController {
         slope_in: f32,
         slope_out: f32,
     ) -> anyhow::Result<()> {
-        self.maa_touch
-            .lock()
-            .unwrap()
-            .swipe(start, end, duration, slope_in, slope_out)
+        self.touch()?
+            .swipe(&self.device, start, end, duration, slope_in, slope_out)
+    }
+
+    fn long_press(&self, x: u32, y: u32, duration: Duration) -> anyhow::Result<()> {
+        self.touch()?.long_press(&self.device, x, y, duration)
+    }
+
+    fn double_click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+        self.touch()?.double_click(&self.device, x, y)
+    }
+
+    fn multi_touch(&self, gesture: Gesture) -> anyhow::Result<()> {
+        self.touch()?.multi_touch(&gesture)
+    }
+
+    /// ASCII is typed with `input text`, anything else needs ADBKeyboard, see
+    /// [`AndroidController::input_text_with_adb_keyboard`].
+    fn input_text(&self, text: &str) -> anyhow::Result<()> {
+        if !text.is_ascii() {
+            return self.input_text_with_adb_keyboard(text);
+        }
+        self.device
+            .input(Input::Text(text.to_string()))
+            .map_err(|err| anyhow::anyhow!("failed to input text: {err:?}"))
     }
+
     fn press(&self, key: enigo::Key) -> anyhow::Result<()> {
         self.device()
             .execute_command_by_socket(ap_adb::command::local_service::Input::Keyevent(
@@ -182,19 +475,6 @@ impl ControllerTrait for AndroidController {
     }
 }
 
-trait AdbKeyEvent {
-    fn event_num(&self) -> Option<u32>;
-}
-
-impl AdbKeyEvent for enigo::Key {
-    fn event_num(&self) -> Option<u32> {
-        Some(match self {
-            Self::Escape => 111,
-            _ => return None,
-        })
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use std::thread;
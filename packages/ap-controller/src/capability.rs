@@ -0,0 +1,25 @@
+//! What only some controllers can do
+//!
+//! A controller tells what it can do through
+//! [`ControllerTrait::key_events`](crate::ControllerTrait::key_events) and the
+//! like, so actions ask for what they need instead of downcasting to a controller
+//! type, and a backend of another crate gets them by implementing the traits.
+
+/// Pressing keys by their Android key code, i.e.
+/// [`KEYCODE_BACK`](crate::android::keycode::KEYCODE_BACK).
+///
+/// A controller with key events takes [`Key::Other`](crate::Key::Other) in
+/// [`ControllerTrait::press`](crate::ControllerTrait::press) as such a key code.
+pub trait KeyEvents {
+    fn press_keycode(&self, keycode: u32) -> anyhow::Result<()>;
+}
+
+/// Starting and stopping apps, by package name or as `<package>/<activity>`.
+pub trait AppManagement {
+    fn launch_app(&self, app: &str) -> anyhow::Result<()>;
+
+    fn stop_app(&self, app: &str) -> anyhow::Result<()>;
+
+    /// The package of the app in the foreground, if any.
+    fn current_app(&self) -> anyhow::Result<Option<String>>;
+}
@@ -0,0 +1,327 @@
+//! Frame capture shared by the controllers
+//!
+//! Some backends stream frames as the screen changes (Windows Graphics Capture,
+//! minicap), the others capture on demand (`screencap`, X11, CoreGraphics). Both
+//! keep their frames in a [`FrameCache`] and expose them as a [`CaptureProvider`],
+//! so [`ControllerTrait::screencap`](crate::ControllerTrait::screencap) means the
+//! same everywhere: the screen as of at most [`FrameCache::max_staleness`] ago.
+//!
+//! A stream only sends a frame when the screen changes, so while it is connected
+//! its latest frame is the current screen however old it is.
+
+use std::{
+    io::Cursor,
+    sync::{Arc, Mutex, mpsc},
+    time::{Duration, Instant},
+};
+
+use image::{buffer::ConvertBuffer, codecs::jpeg::JpegEncoder};
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub image: Arc<image::RgbaImage>,
+    pub captured_at: Instant,
+}
+
+impl Frame {
+    pub fn new(image: image::RgbaImage) -> Self {
+        Self {
+            image: Arc::new(image),
+            captured_at: Instant::now(),
+        }
+    }
+
+    pub fn age(&self) -> Duration {
+        self.captured_at.elapsed()
+    }
+
+    /// The image, without copying it if no one else holds the frame.
+    pub fn into_image(self) -> image::RgbaImage {
+        Arc::unwrap_or_clone(self.image)
+    }
+}
+
+#[derive(Default)]
+struct CacheState {
+    frame: Option<Frame>,
+    subscribers: Vec<mpsc::Sender<Frame>>,
+    max_staleness: Duration,
+    /// Bumped by every invalidation, when the screen may have changed
+    generation: u64,
+    /// The generation `frame` was captured on demand in, `None` for a frame
+    /// pushed, which is never reused
+    frame_generation: Option<u64>,
+}
+
+/// The latest frame of a controller and who wants the next ones.
+#[derive(Default)]
+pub struct FrameCache {
+    state: Mutex<CacheState>,
+}
+
+impl FrameCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep `image` as the latest frame and send it to the subscribers.
+    pub fn push(&self, image: image::RgbaImage) -> Frame {
+        let frame = Frame::new(image);
+        self.push_frame(frame.clone());
+        frame
+    }
+
+    /// [`FrameCache::push`] a frame captured earlier.
+    pub fn push_frame(&self, frame: Frame) {
+        self.push_captured(frame, None);
+    }
+
+    fn push_captured(&self, frame: Frame, generation: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .subscribers
+            .retain(|subscriber| subscriber.send(frame.clone()).is_ok());
+        state.frame = Some(frame);
+        state.frame_generation = generation;
+    }
+
+    pub fn latest(&self) -> Option<Frame> {
+        self.state.lock().unwrap().frame.clone()
+    }
+
+    pub fn frame_age(&self) -> Option<Duration> {
+        self.state.lock().unwrap().frame.as_ref().map(Frame::age)
+    }
+
+    pub fn subscribe(&self) -> mpsc::Receiver<Frame> {
+        let (tx, rx) = mpsc::channel();
+        self.state.lock().unwrap().subscribers.push(tx);
+        rx
+    }
+
+    pub fn has_subscribers(&self) -> bool {
+        !self.state.lock().unwrap().subscribers.is_empty()
+    }
+
+    /// How old a frame captured on demand may be to be reused, as of when its
+    /// capture started. Zero by default, which captures every time.
+    pub fn max_staleness(&self) -> Duration {
+        self.state.lock().unwrap().max_staleness
+    }
+
+    pub fn set_max_staleness(&self, max_staleness: Duration) {
+        self.state.lock().unwrap().max_staleness = max_staleness;
+    }
+
+    /// Capture a new frame on the next [`FrameCache::get_or_capture`] however
+    /// recent the latest one is, i.e. after an input that changed the screen.
+    pub fn invalidate(&self) {
+        self.state.lock().unwrap().generation += 1;
+    }
+
+    /// The latest frame if it was captured by this function at most
+    /// [`FrameCache::max_staleness`] ago and not [invalidated](FrameCache::invalidate)
+    /// since, or a new one from `capture`. A frame invalidated while it is being
+    /// captured is not reused, the input may have come before the capture ended.
+    pub fn get_or_capture(
+        &self,
+        capture: impl FnOnce() -> anyhow::Result<image::RgbaImage>,
+    ) -> anyhow::Result<Frame> {
+        let generation = {
+            let state = self.state.lock().unwrap();
+            if let Some(frame) = &state.frame
+                && frame.age() <= state.max_staleness
+                && !state.max_staleness.is_zero()
+                && state.frame_generation == Some(state.generation)
+            {
+                return Ok(frame.clone());
+            }
+            state.generation
+        };
+        let started = Instant::now();
+        let frame = Frame {
+            image: Arc::new(capture()?),
+            captured_at: started,
+        };
+        self.push_captured(frame.clone(), Some(generation));
+        Ok(frame)
+    }
+}
+
+/// Frames as they are captured, see [`CaptureProvider::subscribe`]. Ends when
+/// the controller stops capturing.
+pub struct Frames {
+    receiver: mpsc::Receiver<Frame>,
+}
+
+impl Frames {
+    /// The next frame, or `None` if none arrives within `timeout`.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<Frame> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// Skip to the most recent frame that already arrived, for consumers slower
+    /// than the stream.
+    pub fn latest(&mut self) -> Option<Frame> {
+        self.receiver.try_iter().last()
+    }
+}
+
+impl From<mpsc::Receiver<Frame>> for Frames {
+    fn from(receiver: mpsc::Receiver<Frame>) -> Self {
+        Self { receiver }
+    }
+}
+
+impl Iterator for Frames {
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        self.receiver.recv().ok()
+    }
+}
+
+/// Where the frames of a controller come from, see the [module docs](self).
+pub trait CaptureProvider {
+    fn frames(&self) -> &FrameCache;
+
+    /// Whether frames arrive by themselves as the screen changes. Otherwise they
+    /// are only captured by [`CaptureProvider::latest_frame`].
+    fn is_streaming(&self) -> bool {
+        false
+    }
+
+    /// The current screen, captured if the cached frame is too old.
+    fn latest_frame(&self) -> anyhow::Result<Frame>;
+
+    /// How long ago the latest frame was captured, `None` before the first one.
+    fn frame_age(&self) -> Option<Duration> {
+        self.frames().frame_age()
+    }
+
+    /// Receive every frame captured from now on.
+    fn subscribe(&self) -> Frames {
+        self.frames().subscribe().into()
+    }
+}
+
+/// Encode `image` as a JPEG with `quality` from 1 to 100, dropping its alpha
+/// channel.
+pub fn encode_jpeg(image: &image::RgbaImage, quality: u8) -> anyhow::Result<Vec<u8>> {
+    let rgb: image::RgbImage = image.convert();
+    let mut jpeg = Vec::new();
+    JpegEncoder::new_with_quality(Cursor::new(&mut jpeg), quality.clamp(1, 100))
+        .encode_image(&rgb)?;
+    Ok(jpeg)
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn test_get_or_capture() {
+        let cache = FrameCache::new();
+        let captures = Cell::new(0);
+        let capture = || {
+            captures.set(captures.get() + 1);
+            Ok(image::RgbaImage::new(2, 1))
+        };
+
+        // Captured every time by default
+        cache.get_or_capture(capture).unwrap();
+        cache.get_or_capture(capture).unwrap();
+        assert_eq!(captures.get(), 2);
+
+        cache.set_max_staleness(Duration::from_secs(60));
+        let frame = cache.get_or_capture(capture).unwrap();
+        assert_eq!(captures.get(), 2);
+        assert_eq!(frame.image.dimensions(), (2, 1));
+        assert!(cache.frame_age().unwrap() < Duration::from_secs(60));
+
+        cache.invalidate();
+        cache.get_or_capture(capture).unwrap();
+        cache.get_or_capture(capture).unwrap();
+        assert_eq!(captures.get(), 3);
+
+        // Invalidated while capturing, so not reused
+        cache.invalidate();
+        cache
+            .get_or_capture(|| {
+                cache.invalidate();
+                capture()
+            })
+            .unwrap();
+        cache.get_or_capture(capture).unwrap();
+        assert_eq!(captures.get(), 5);
+
+        // Dated from when the capture started
+        cache.invalidate();
+        let before = Instant::now();
+        let frame = cache
+            .get_or_capture(|| {
+                std::thread::sleep(Duration::from_millis(20));
+                capture()
+            })
+            .unwrap();
+        assert!(frame.captured_at - before < Duration::from_millis(20));
+
+        // A frame pushed is not reused
+        cache.push(image::RgbaImage::new(2, 1));
+        cache.get_or_capture(capture).unwrap();
+        assert_eq!(captures.get(), 7);
+    }
+
+    #[test]
+    fn test_encode() {
+        let image = image::RgbaImage::from_pixel(16, 8, image::Rgba([200, 100, 50, 255]));
+        let jpeg = encode_jpeg(&image, 80).unwrap();
+        assert_eq!(
+            image::guess_format(&jpeg).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+        assert_eq!(
+            image::load_from_memory(&jpeg)
+                .unwrap()
+                .to_rgba8()
+                .dimensions(),
+            (16, 8)
+        );
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let cache = FrameCache::new();
+        assert!(!cache.has_subscribers());
+        let frames = cache.subscribe();
+        cache.push(image::RgbaImage::new(1, 1));
+        cache.push(image::RgbaImage::new(2, 2));
+        let sizes = frames
+            .try_iter()
+            .map(|frame| frame.image.dimensions())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes, [(1, 1), (2, 2)]);
+
+        drop(frames);
+        cache.push(image::RgbaImage::new(1, 1));
+        assert!(!cache.has_subscribers());
+    }
+
+    #[test]
+    fn test_frames() {
+        let cache = FrameCache::new();
+        let mut frames = Frames::from(cache.subscribe());
+        assert!(frames.latest().is_none());
+        for width in 1..=3 {
+            cache.push(image::RgbaImage::new(width, 1));
+        }
+        assert_eq!(frames.next().unwrap().image.width(), 1);
+        assert_eq!(frames.latest().unwrap().image.width(), 3);
+        assert!(frames.next_timeout(Duration::from_millis(1)).is_none());
+
+        drop(cache);
+        assert!(frames.next().is_none());
+    }
+}
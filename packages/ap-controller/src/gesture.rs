@@ -0,0 +1,158 @@
+//! Multi-touch gestures, see [`ControllerTrait::multi_touch`](crate::ControllerTrait::multi_touch).
+
+use std::{f32::consts::PI, time::Duration};
+
+/// Interval between two frames of a gesture
+pub const FRAME_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Contacts moving at the same time, each along its own path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gesture {
+    /// Waypoints of each contact, all of them are traversed at a constant speed
+    /// over `duration`. A single waypoint is a contact held in place.
+    pub contacts: Vec<Vec<(i32, i32)>>,
+    pub duration: Duration,
+}
+
+impl Gesture {
+    pub fn new(contacts: Vec<Vec<(i32, i32)>>, duration: Duration) -> Self {
+        Self { contacts, duration }
+    }
+
+    /// Two contacts on a horizontal line through `center`, `from` apart at the
+    /// start and `to` apart at the end, zooming in if `to > from`.
+    pub fn pinch(center: (i32, i32), from: u32, to: u32, duration: Duration) -> Self {
+        let (from, to) = (from as i32 / 2, to as i32 / 2);
+        Self::new(
+            vec![
+                vec![(center.0 - from, center.1), (center.0 - to, center.1)],
+                vec![(center.0 + from, center.1), (center.0 + to, center.1)],
+            ],
+            duration,
+        )
+    }
+
+    /// Two contacts on opposite sides of `center`, `radius` away from it, turning
+    /// by `degrees`, clockwise on the screen if positive.
+    pub fn rotate(center: (i32, i32), radius: u32, degrees: f32, duration: Duration) -> Self {
+        // A waypoint every 5 degrees keeps the arc round
+        let steps = (degrees.abs() / 5.0).ceil().max(1.0) as usize;
+        let arc = |start: f32| {
+            (0..=steps)
+                .map(|i| {
+                    let angle = start + degrees.to_radians() * i as f32 / steps as f32;
+                    (
+                        center.0 + (radius as f32 * angle.cos()).round() as i32,
+                        center.1 + (radius as f32 * angle.sin()).round() as i32,
+                    )
+                })
+                .collect()
+        };
+        Self::new(vec![arc(PI), arc(0.0)], duration)
+    }
+
+    /// Where `contact` is at `t`, from `0.0` (start) to `1.0` (end), `None` if
+    /// there is no such contact or it has no waypoints.
+    pub fn position(&self, contact: usize, t: f32) -> Option<(i32, i32)> {
+        let path = self.contacts.get(contact)?;
+        let first = *path.first()?;
+        let lengths = path
+            .windows(2)
+            .map(|segment| {
+                let (dx, dy) = (segment[1].0 - segment[0].0, segment[1].1 - segment[0].1);
+                ((dx * dx + dy * dy) as f32).sqrt()
+            })
+            .collect::<Vec<_>>();
+        let total = lengths.iter().sum::<f32>();
+        if total == 0.0 {
+            return Some(first);
+        }
+
+        let mut distance = total * t.clamp(0.0, 1.0);
+        for (segment, length) in path.windows(2).zip(lengths) {
+            if distance <= length && length > 0.0 {
+                let progress = distance / length;
+                let lerp = |a: i32, b: i32| a + ((b - a) as f32 * progress).round() as i32;
+                return Some((
+                    lerp(segment[0].0, segment[1].0),
+                    lerp(segment[0].1, segment[1].1),
+                ));
+            }
+            distance -= length;
+        }
+        path.last().copied()
+    }
+
+    /// Positions of all contacts every `interval`, from the start to the end. A
+    /// gesture without contacts, or with a contact without waypoints, is an error.
+    pub fn frames(&self, interval: Duration) -> anyhow::Result<Vec<Vec<(i32, i32)>>> {
+        anyhow::ensure!(!self.contacts.is_empty(), "the gesture has no contacts");
+        if let Some(contact) = self.contacts.iter().position(Vec::is_empty) {
+            anyhow::bail!("contact {contact} of the gesture has no waypoints");
+        }
+        let count = (self.duration.as_secs_f32() / interval.as_secs_f32().max(f32::EPSILON))
+            .ceil()
+            .max(1.0) as usize;
+        let frames = (0..=count)
+            .map(|i| {
+                let t = i as f32 / count as f32;
+                (0..self.contacts.len())
+                    .filter_map(|contact| self.position(contact, t))
+                    .collect()
+            })
+            .collect();
+        Ok(frames)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pinch() {
+        let gesture = Gesture::pinch((500, 300), 100, 300, Duration::from_millis(20));
+        let frames = gesture.frames(Duration::from_millis(10)).unwrap();
+        assert_eq!(
+            frames,
+            [
+                vec![(450, 300), (550, 300)],
+                vec![(400, 300), (600, 300)],
+                vec![(350, 300), (650, 300)],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rotate() {
+        let gesture = Gesture::rotate((0, 0), 100, 90.0, Duration::from_millis(100));
+        assert_eq!(gesture.position(0, 0.0), Some((-100, 0)));
+        assert_eq!(gesture.position(1, 0.0), Some((100, 0)));
+        assert_eq!(gesture.position(0, 1.0), Some((0, -100)));
+        assert_eq!(gesture.position(1, 1.0), Some((0, 100)));
+        let (x, y) = gesture.position(1, 0.5).unwrap();
+        assert!((x - 71).abs() <= 1 && (y - 71).abs() <= 1, "{x}, {y}");
+    }
+
+    #[test]
+    fn test_position() {
+        let gesture = Gesture::new(
+            vec![vec![(0, 0), (100, 0), (100, 100)], vec![(7, 7)]],
+            Duration::from_secs(1),
+        );
+        assert_eq!(gesture.position(0, 0.25), Some((50, 0)));
+        assert_eq!(gesture.position(0, 0.75), Some((100, 50)));
+        assert_eq!(gesture.position(0, 2.0), Some((100, 100)));
+        assert_eq!(gesture.position(1, 0.5), Some((7, 7)));
+        assert_eq!(gesture.position(2, 0.5), None);
+
+        let empty = Gesture::new(vec![vec![(0, 0)], vec![]], Duration::from_secs(1));
+        assert_eq!(empty.position(1, 0.5), None);
+        assert!(empty.frames(FRAME_INTERVAL).is_err());
+        assert!(
+            Gesture::new(Vec::new(), Duration::from_secs(1))
+                .frames(FRAME_INTERVAL)
+                .is_err()
+        );
+    }
+}
@@ -1,22 +1,63 @@
-use std::{any::Any, time::Duration};
+use std::{any::Any, sync::RwLock, time::Duration};
 
+pub use anchor::{Anchor, AnchoredPoint, AnchoredRect};
+pub use capability::{AppManagement, KeyEvents};
+pub use capture::{CaptureProvider, Frame, FrameCache, Frames};
 pub use enigo::Key;
+pub use gesture::Gesture;
+pub use mock::MockController;
 use image::math::Rect;
+pub use profile::{DeviceProfile, ProfileCache};
+use tracing::instrument;
 
+pub mod anchor;
 pub mod android;
-
+pub mod capability;
+pub mod capture;
+pub mod gesture;
+pub mod mock;
+pub mod profile;
+pub mod recorder;
+pub mod registry;
+
+#[cfg(feature = "linux")]
+pub mod linux;
+#[cfg(target_os = "macos")]
+pub mod macos;
 #[cfg(feature = "windows")]
 pub mod windows;
 
 // Re-export controllers for convenience
 pub use android::AndroidController;
 
+#[cfg(feature = "linux")]
+pub use linux::LinuxController;
+#[cfg(target_os = "macos")]
+pub use macos::MacController;
+#[cfg(feature = "windows")]
+pub use windows::{KeyInputMode, MouseInputMode, WindowsController};
+
+/// The desktop window controller of the platform, for attaching to a window by
+/// its title without caring which one it is.
 #[cfg(feature = "windows")]
-pub use windows::WindowsController;
+pub type DesktopController = WindowsController;
+#[cfg(target_os = "macos")]
+pub type DesktopController = MacController;
+#[cfg(all(feature = "linux", not(any(feature = "windows", target_os = "macos"))))]
+pub type DesktopController = LinuxController;
 
 /// Default reference height for coordinate scaling (1080p)
 pub const DEFAULT_HEIGHT: u32 = 1080;
 
+/// Progress of a swipe at `t` from `0.0` to `1.0`, eased with a cubic spline.
+#[cfg(any(feature = "windows", feature = "linux", target_os = "macos"))]
+pub(crate) fn swipe_progress(slope_in: f32, slope_out: f32, t: f32) -> f32 {
+    let a = slope_in;
+    let b = -(2.0 * slope_in + slope_out - 3.0);
+    let c = -(-slope_in - slope_out + 2.0);
+    (a * t + b * t.powi(2) + c * t.powi(3)).clamp(0.0, 1.0)
+}
+
 /// A trait for device/window controllers that provide screen capture and input simulation.
 ///
 /// This trait abstracts common operations across different platforms (Android, Windows, etc.),
@@ -37,6 +78,46 @@ pub trait ControllerTrait {
         self.screen_size().1 as f32 / DEFAULT_HEIGHT as f32
     }
 
+    /// The new screen size if it changed since the last call, i.e. the window was
+    /// resized or went fullscreen. Checked after every capture.
+    ///
+    /// [`screen_size`](Self::screen_size) and [`scale_factor`](Self::scale_factor)
+    /// follow the change by themselves, this only reports it.
+    fn resolution_changed(&self) -> Option<(u32, u32)> {
+        None
+    }
+
+    /// The frame cache behind [`screencap`](Self::screencap), to subscribe to the
+    /// frames or tune how stale they may be, see [`capture`].
+    fn capture_provider(&self) -> Option<&dyn CaptureProvider> {
+        None
+    }
+
+    // ===== Capabilities =====
+
+    /// Pressing keys by their Android key code, see [`KeyEvents`].
+    fn key_events(&self) -> Option<&dyn KeyEvents> {
+        None
+    }
+
+    /// Starting and stopping apps, see [`AppManagement`].
+    fn app_management(&self) -> Option<&dyn AppManagement> {
+        None
+    }
+
+    /// What the device is, for controllers of devices that probe it, see [`profile`].
+    fn device_profile(&self) -> Option<&DeviceProfile> {
+        None
+    }
+
+    fn supports_keyevents(&self) -> bool {
+        self.key_events().is_some()
+    }
+
+    fn supports_app_management(&self) -> bool {
+        self.app_management().is_some()
+    }
+
     // ===== Screenshot Methods =====
 
     /// Get the raw screenshot data as (width, height, rgba_bytes)
@@ -45,6 +126,38 @@ pub trait ControllerTrait {
     /// Get the decoded screenshot as a DynamicImage
     fn screencap(&self) -> anyhow::Result<image::DynamicImage>;
 
+    /// Get the part of the screen in `rect`, i.e. to match a template only where
+    /// it can be. Cropped from the latest frame without copying the rest of it if
+    /// the controller has a [`capture_provider`](Self::capture_provider).
+    fn screencap_region(&self, rect: Rect) -> anyhow::Result<image::DynamicImage> {
+        let crop = |screen: &image::RgbaImage| {
+            let (width, height) = screen.dimensions();
+            if rect.width == 0
+                || rect.height == 0
+                || rect.x.checked_add(rect.width).is_none_or(|right| right > width)
+                || rect.y.checked_add(rect.height).is_none_or(|bottom| bottom > height)
+            {
+                anyhow::bail!("region {rect:?} is not within the {width}x{height} screen");
+            }
+            let region = image::imageops::crop_imm(screen, rect.x, rect.y, rect.width, rect.height);
+            Ok(image::DynamicImage::ImageRgba8(region.to_image()))
+        };
+        match self.capture_provider() {
+            Some(provider) => crop(&provider.latest_frame()?.image),
+            None => crop(&self.screencap()?.into_rgba8()),
+        }
+    }
+
+    /// A JPEG of the screen with `quality` from 1 to 100, smaller and quicker to
+    /// encode than a PNG, i.e. for a live view over a slow link. Encoded on the
+    /// host from the latest frame, see [`capture::encode_jpeg`].
+    fn screencap_jpeg(&self, quality: u8) -> anyhow::Result<Vec<u8>> {
+        match self.capture_provider() {
+            Some(provider) => capture::encode_jpeg(&provider.latest_frame()?.image, quality),
+            None => capture::encode_jpeg(&self.screencap()?.into_rgba8(), quality),
+        }
+    }
+
     /// Get a screenshot scaled to DEFAULT_HEIGHT (1080p).
     ///
     /// This is useful for template matching with templates designed for 1080p.
@@ -102,6 +215,20 @@ pub trait ControllerTrait {
         self.click_in_rect(rect)
     }
 
+    /// Press at the specified coordinates and hold for `duration`, by default a
+    /// [swipe](Self::swipe) that does not move
+    fn long_press(&self, x: u32, y: u32, duration: Duration) -> anyhow::Result<()> {
+        self.swipe((x, y), (x as i32, y as i32), duration, 1.0, 1.0)
+    }
+
+    /// Click twice at the specified coordinates, quick enough to be a double click
+    fn double_click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+        self.click(x, y)?;
+        // Well within the double click time of toolkits
+        std::thread::sleep(Duration::from_millis(50));
+        self.click(x, y)
+    }
+
     // ===== Swipe Methods =====
 
     /// Perform a swipe gesture from start to end.
@@ -142,6 +269,18 @@ pub trait ControllerTrait {
         self.swipe(start, end, duration, slope_in, slope_out)
     }
 
+    /// Perform a gesture with several contacts at once, like a pinch.
+    fn multi_touch(&self, gesture: Gesture) -> anyhow::Result<()> {
+        let _ = gesture;
+        anyhow::bail!("multi touch is not supported by this controller")
+    }
+
+    /// Type `text` into the focused field.
+    fn input_text(&self, text: &str) -> anyhow::Result<()> {
+        let _ = text;
+        anyhow::bail!("text input is not supported by this controller")
+    }
+
     fn press(&self, key: Key) -> anyhow::Result<()>;
 }
 
@@ -160,11 +299,21 @@ mod tests {
     }
 }
 
-pub trait AnyControllerTrait: Any + Send + ControllerTrait {}
-impl<T: ControllerTrait + Any + Send> AnyControllerTrait for T {}
+pub trait AnyControllerTrait: Any + Send + Sync + ControllerTrait {}
+impl<T: ControllerTrait + Any + Send + Sync> AnyControllerTrait for T {}
 
 pub struct Controller {
     inner: Box<dyn AnyControllerTrait>,
+    content_area: RwLock<Option<ContentArea>>,
+}
+
+/// The content area known for a screen size
+#[derive(Clone, Copy)]
+struct ContentArea {
+    screen_size: (u32, u32),
+    area: Rect,
+    /// Not given with [`Controller::set_content_area`]
+    detected: bool,
 }
 
 impl ControllerTrait for Controller {
@@ -172,18 +321,52 @@ impl ControllerTrait for Controller {
         self.inner.screen_size()
     }
 
+    fn resolution_changed(&self) -> Option<(u32, u32)> {
+        self.inner.resolution_changed()
+    }
+
+    fn capture_provider(&self) -> Option<&dyn CaptureProvider> {
+        self.inner.capture_provider()
+    }
+
+    fn key_events(&self) -> Option<&dyn KeyEvents> {
+        self.inner.key_events()
+    }
+
+    fn app_management(&self) -> Option<&dyn AppManagement> {
+        self.inner.app_management()
+    }
+
+    fn device_profile(&self) -> Option<&DeviceProfile> {
+        self.inner.device_profile()
+    }
+
+    #[instrument(level = "trace", skip_all)]
     fn screencap_raw(&self) -> anyhow::Result<(u32, u32, Vec<u8>)> {
         self.inner.screencap_raw()
     }
 
+    #[instrument(level = "trace", skip(self))]
+    fn screencap_region(&self, rect: Rect) -> anyhow::Result<image::DynamicImage> {
+        self.inner.screencap_region(rect)
+    }
+
+    #[instrument(level = "trace", skip_all)]
     fn screencap(&self) -> anyhow::Result<image::DynamicImage> {
         self.inner.screencap()
     }
 
+    #[instrument(level = "trace", skip(self))]
+    fn screencap_jpeg(&self, quality: u8) -> anyhow::Result<Vec<u8>> {
+        self.inner.screencap_jpeg(quality)
+    }
+
+    #[instrument(level = "debug", skip(self))]
     fn click(&self, x: u32, y: u32) -> anyhow::Result<()> {
-        self.inner.click(x, y)
+        self.after_input(self.inner.click(x, y))
     }
 
+    #[instrument(level = "debug", skip(self))]
     fn swipe(
         &self,
         start: (u32, u32),
@@ -192,21 +375,153 @@ impl ControllerTrait for Controller {
         slope_in: f32,
         slope_out: f32,
     ) -> anyhow::Result<()> {
-        self.inner.swipe(start, end, duration, slope_in, slope_out)
+        self.after_input(self.inner.swipe(start, end, duration, slope_in, slope_out))
+    }
+
+    #[instrument(level = "debug", skip(self))]
+    fn long_press(&self, x: u32, y: u32, duration: Duration) -> anyhow::Result<()> {
+        self.after_input(self.inner.long_press(x, y, duration))
     }
 
+    #[instrument(level = "debug", skip(self))]
+    fn double_click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+        self.after_input(self.inner.double_click(x, y))
+    }
+
+    fn multi_touch(&self, gesture: Gesture) -> anyhow::Result<()> {
+        self.after_input(self.inner.multi_touch(gesture))
+    }
+
+    #[instrument(level = "debug", skip_all, fields(len = text.len()))]
+    fn input_text(&self, text: &str) -> anyhow::Result<()> {
+        self.after_input(self.inner.input_text(text))
+    }
+
+    #[instrument(level = "debug", skip(self))]
     fn press(&self, key: Key) -> anyhow::Result<()> {
-        self.inner.press(key)
+        self.after_input(self.inner.press(key))
     }
 }
 
 impl Controller {
-    pub fn new<T: ControllerTrait + Any + Send>(inner: T) -> Self {
+    pub fn new<T: ControllerTrait + Any + Send + Sync>(inner: T) -> Self {
+        // A controller of the registry is wrapped already
+        let mut inner = Some(inner);
+        if let Some(controller) = (&mut inner as &mut dyn Any).downcast_mut::<Option<Controller>>()
+        {
+            return controller.take().unwrap();
+        }
         Self {
-            inner: Box::new(inner),
+            inner: Box::new(inner.unwrap()),
+            content_area: RwLock::new(None),
         }
     }
+
     pub fn downcast_ref<T: ControllerTrait + 'static>(&self) -> Option<&T> {
         (self.inner.as_ref() as &dyn Any).downcast_ref::<T>()
     }
+
+    /// Every frame from now on with when it was captured, to process each one
+    /// instead of polling [`screencap`](ControllerTrait::screencap).
+    ///
+    /// Streaming backends (Windows Graphics Capture, Android with minicap) send
+    /// frames as the screen changes, the others only the ones they capture, see
+    /// [`CaptureProvider::is_streaming`].
+    pub fn frames(&self) -> anyhow::Result<Frames> {
+        let provider = self
+            .capture_provider()
+            .ok_or_else(|| anyhow::anyhow!("the controller does not provide frames"))?;
+        Ok(provider.subscribe())
+    }
+
+    /// How old a frame may be for [`screencap`](ControllerTrait::screencap) to
+    /// reuse it instead of capturing again, zero if it always captures or the
+    /// controller has no [`CaptureProvider`].
+    pub fn max_frame_age(&self) -> Duration {
+        self.capture_provider()
+            .map_or(Duration::ZERO, |provider| provider.frames().max_staleness())
+    }
+
+    /// See [`Controller::max_frame_age`], a frame is never reused past an input
+    /// whatever its age. Does nothing without a [`CaptureProvider`].
+    pub fn set_max_frame_age(&self, max_age: Duration) {
+        if let Some(provider) = self.capture_provider() {
+            provider.frames().set_max_staleness(max_age);
+        }
+    }
+
+    /// Capture a new frame on the next [`screencap`](ControllerTrait::screencap),
+    /// done after every input of the controller already.
+    pub fn invalidate_frame(&self) {
+        if let Some(provider) = self.capture_provider() {
+            provider.frames().invalidate();
+        }
+    }
+
+    fn after_input<T>(&self, result: anyhow::Result<T>) -> anyhow::Result<T> {
+        self.invalidate_frame();
+        result
+    }
+
+    /// The area of the screen the game is drawn to, without the black bars of a
+    /// letterboxed game, see [`anchor`].
+    ///
+    /// It is detected on a capture the first time, again when the screen size
+    /// changes and after [`Controller::redetect_content_area`]. The whole screen
+    /// is used while the screen is all black.
+    pub fn content_area(&self) -> anyhow::Result<Rect> {
+        let screen_size = self.screen_size();
+        if let Some(known) = *self.content_area.read().unwrap()
+            && known.screen_size == screen_size
+        {
+            return Ok(known.area);
+        }
+        let screen = self.screencap()?.into_rgba8();
+        let (width, height) = screen.dimensions();
+        let full = Rect {
+            x: 0,
+            y: 0,
+            width,
+            height,
+        };
+        match anchor::detect_content_area(&screen) {
+            Some(area) => {
+                *self.content_area.write().unwrap() = Some(ContentArea {
+                    screen_size,
+                    area,
+                    detected: true,
+                });
+                Ok(area)
+            }
+            None => Ok(full),
+        }
+    }
+
+    /// Use `area` as the content area instead of detecting it, or detect it
+    /// again with `None`.
+    pub fn set_content_area(&self, area: Option<Rect>) {
+        *self.content_area.write().unwrap() = area.map(|area| ContentArea {
+            screen_size: self.screen_size(),
+            area,
+            detected: false,
+        });
+    }
+
+    /// Detect the content area again the next time it is needed, as it may have
+    /// been detected on a screen being drawn, unless it was set with
+    /// [`Controller::set_content_area`].
+    pub fn redetect_content_area(&self) {
+        let mut content_area = self.content_area.write().unwrap();
+        if content_area.is_some_and(|known| known.detected) {
+            *content_area = None;
+        }
+    }
+
+    pub fn resolve_point(&self, point: AnchoredPoint) -> anyhow::Result<(i32, i32)> {
+        Ok(point.resolve(self.content_area()?))
+    }
+
+    pub fn resolve_rect(&self, rect: AnchoredRect) -> anyhow::Result<Rect> {
+        Ok(rect.resolve(self.content_area()?))
+    }
 }
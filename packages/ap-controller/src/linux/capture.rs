@@ -0,0 +1,186 @@
+//! Capturing the content of a window
+//!
+//! With MIT-SHM the server writes the image into a segment shared with us, which
+//! saves sending ~8MB through the socket for every frame. Servers without it
+//! (i.e. a remote display) fall back to the core `GetImage`.
+
+use std::fs::File;
+
+use memmap2::{Mmap, MmapOptions};
+use tracing::{info, warn};
+use x11rb::connection::Connection;
+use x11rb::protocol::shm::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::{ConnectionExt as _, ImageFormat, ImageOrder, Window};
+use x11rb::rust_connection::RustConnection;
+
+/// A shared memory segment attached to the server
+struct Segment {
+    seg: shm::Seg,
+    mmap: Mmap,
+}
+
+impl Segment {
+    fn create(conn: &RustConnection, len: usize) -> anyhow::Result<Self> {
+        let seg = conn.generate_id()?;
+        let reply = conn.shm_create_segment(seg, len as u32, false)?.reply()?;
+        let file = File::from(reply.shm_fd);
+        let mmap = unsafe { MmapOptions::new().len(len).map(&file)? };
+        Ok(Self { seg, mmap })
+    }
+}
+
+pub(super) struct Capturer {
+    /// Whether the server has MIT-SHM 1.2, which creates the segments
+    shm_supported: bool,
+    segment: Option<Segment>,
+    /// Size of the last capture
+    size: Option<(u32, u32)>,
+    /// Set when a capture has a different size than the previous one, i.e. the
+    /// window was resized, until [`Capturer::take_resized`]
+    resized: bool,
+}
+
+impl Capturer {
+    pub fn new(conn: &RustConnection) -> Self {
+        let shm_supported = conn
+            .shm_query_version()
+            .ok()
+            .and_then(|cookie| cookie.reply().ok())
+            .is_some_and(|version| (version.major_version, version.minor_version) >= (1, 2));
+        if !shm_supported {
+            info!("MIT-SHM is not available, capturing with GetImage");
+        }
+        Self {
+            shm_supported,
+            segment: None,
+            size: None,
+            resized: false,
+        }
+    }
+
+    pub fn take_resized(&mut self) -> bool {
+        std::mem::take(&mut self.resized)
+    }
+
+    /// Capture the content of `window`, which has to be mapped and not covered
+    /// by other windows.
+    pub fn capture(
+        &mut self,
+        conn: &RustConnection,
+        window: Window,
+    ) -> anyhow::Result<image::RgbaImage> {
+        let geometry = conn.get_geometry(window)?.reply()?;
+        let (width, height) = (geometry.width as u32, geometry.height as u32);
+        if let Some(prev) = self.size
+            && prev != (width, height)
+        {
+            info!("Window resized: {}x{} -> {width}x{height}", prev.0, prev.1);
+            self.resized = true;
+        }
+        self.size = Some((width, height));
+
+        let setup = conn.setup();
+        let bits_per_pixel = setup
+            .pixmap_formats
+            .iter()
+            .find(|format| format.depth == geometry.depth)
+            .map(|format| format.bits_per_pixel);
+        if bits_per_pixel != Some(32) {
+            anyhow::bail!(
+                "Unsupported window depth {} ({bits_per_pixel:?} bits per pixel)",
+                geometry.depth
+            );
+        }
+        let msb_first = setup.image_byte_order == ImageOrder::MSB_FIRST;
+
+        if self.shm_supported {
+            match self.get_image_shm(conn, window, geometry.width, geometry.height) {
+                Ok(data) => return Ok(to_rgba_image(width, height, data, msb_first)),
+                Err(err) => {
+                    warn!("MIT-SHM capture failed, falling back to GetImage: {err:#}");
+                    self.shm_supported = false;
+                    self.segment = None;
+                }
+            }
+        }
+
+        let reply = conn
+            .get_image(
+                ImageFormat::Z_PIXMAP,
+                window,
+                0,
+                0,
+                geometry.width,
+                geometry.height,
+                !0,
+            )?
+            .reply()?;
+        Ok(to_rgba_image(width, height, &reply.data, msb_first))
+    }
+
+    fn get_image_shm(
+        &mut self,
+        conn: &RustConnection,
+        window: Window,
+        width: u16,
+        height: u16,
+    ) -> anyhow::Result<&[u8]> {
+        let len = width as usize * height as usize * 4;
+        // Reuse the segment until the window grows beyond it
+        if self
+            .segment
+            .as_ref()
+            .is_none_or(|segment| segment.mmap.len() < len)
+        {
+            if let Some(segment) = self.segment.take() {
+                conn.shm_detach(segment.seg)?;
+            }
+            self.segment = Some(Segment::create(conn, len)?);
+        }
+
+        let segment = self.segment.as_ref().unwrap();
+        conn.shm_get_image(
+            window,
+            0,
+            0,
+            width,
+            height,
+            !0,
+            ImageFormat::Z_PIXMAP.into(),
+            segment.seg,
+            0,
+        )?
+        .reply()?;
+        Ok(&segment.mmap[..len])
+    }
+}
+
+/// 32 bits per pixel `ZPixmap` data, `0x00RRGGBB` in the byte order of the
+/// server, to an opaque RGBA image.
+fn to_rgba_image(width: u32, height: u32, data: &[u8], msb_first: bool) -> image::RgbaImage {
+    let rgba = data
+        .chunks_exact(4)
+        .flat_map(|pixel| match msb_first {
+            true => [pixel[1], pixel[2], pixel[3], 255],
+            false => [pixel[2], pixel[1], pixel[0], 255],
+        })
+        .collect();
+    image::RgbaImage::from_raw(width, height, rgba).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_rgba_image() {
+        // A red and a blue pixel
+        let lsb_first = [0, 0, 255, 0, 255, 0, 0, 0];
+        let image = to_rgba_image(2, 1, &lsb_first, false);
+        assert_eq!(image.as_raw(), &[255, 0, 0, 255, 0, 0, 255, 255]);
+
+        let msb_first = [0, 255, 0, 0, 0, 0, 0, 255];
+        let image = to_rgba_image(2, 1, &msb_first, true);
+        assert_eq!(image.as_raw(), &[255, 0, 0, 255, 0, 0, 255, 255]);
+    }
+}
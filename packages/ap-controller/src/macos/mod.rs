@@ -0,0 +1,429 @@
+//! macOS desktop controller on CoreGraphics
+//!
+//! Windows are captured with `CGWindowListCreateImage` and input is posted with
+//! `CGEventPost` through enigo, at screen positions like the cursor mode of the
+//! Windows controller, so the window should be frontmost. The process needs the
+//! Screen Recording permission to capture other apps and to see their window
+//! titles, and the Accessibility permission to send input.
+//!
+//! Local coordinates are pixels of the captured window, title bar included, which
+//! are twice the points of the screen on a Retina display.
+
+use std::{sync::Mutex, thread, time::Duration};
+
+use core_foundation::base::{CFType, ConcreteCFType, TCFType};
+use core_foundation::dictionary::{CFDictionary, CFDictionaryRef};
+use core_foundation::number::CFNumber;
+use core_foundation::string::{CFString, CFStringRef};
+use core_graphics::geometry::{CGPoint, CGRect, CGSize};
+use core_graphics::window::{
+    self, CGWindowID, kCGNullWindowID, kCGWindowBounds, kCGWindowImageBestResolution,
+    kCGWindowImageBoundsIgnoreFraming, kCGWindowLayer, kCGWindowListExcludeDesktopElements,
+    kCGWindowListOptionIncludingWindow, kCGWindowListOptionOnScreenOnly, kCGWindowName,
+    kCGWindowNumber,
+};
+use enigo::{Axis, Button, Coordinate, Enigo, Keyboard, Mouse, Settings};
+use tracing::info;
+
+use crate::capture::{CaptureProvider, Frame, FrameCache};
+use crate::{ControllerTrait, swipe_progress};
+
+const SWIPE_DELAY_MS: u32 = 5;
+
+type WindowInfo = CFDictionary<CFString, CFType>;
+
+/// The value of `key` in the description of a window.
+fn info_value<T: ConcreteCFType>(info: &WindowInfo, key: CFStringRef) -> Option<T> {
+    let key = unsafe { CFString::wrap_under_get_rule(key) };
+    info.find(&key)?.downcast::<T>()
+}
+
+/// Descriptions of the windows `option` selects, relative to `window`.
+fn window_infos(option: u32, window: CGWindowID) -> Vec<WindowInfo> {
+    window::copy_window_info(option, window)
+        .map(|infos| {
+            infos
+                .iter()
+                .map(|info| unsafe { WindowInfo::wrap_under_get_rule(*info as CFDictionaryRef) })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Local coordinates to screen points, in a window at `bounds` captured in `size`.
+fn to_screen(bounds: CGRect, size: (u32, u32), x: i32, y: i32) -> (i32, i32) {
+    let scale = size.0 as f64 / bounds.size.width.max(1.0);
+    (
+        (bounds.origin.x + x as f64 / scale).round() as i32,
+        (bounds.origin.y + y as f64 / scale).round() as i32,
+    )
+}
+
+/// Window images are 32 bits per pixel BGRA, with rows of `bytes_per_row`.
+fn to_rgba_image(width: u32, height: u32, bytes_per_row: usize, data: &[u8]) -> image::RgbaImage {
+    let rgba = data
+        .chunks(bytes_per_row)
+        .take(height as usize)
+        .flat_map(|row| row[..width as usize * 4].chunks_exact(4))
+        .flat_map(|pixel| [pixel[2], pixel[1], pixel[0], pixel[3]])
+        .collect();
+    image::RgbaImage::from_raw(width, height, rgba).unwrap()
+}
+
+struct CaptureState {
+    /// Size of the last capture
+    size: (u32, u32),
+    /// Set when a capture has a different size than the previous one, i.e. the
+    /// window was resized, until [`ControllerTrait::resolution_changed`]
+    resized: bool,
+}
+
+/// A macOS controller for window capture and input simulation.
+pub struct MacController {
+    window: CGWindowID,
+    window_title: String,
+    enigo: Mutex<Enigo>,
+    capture_state: Mutex<CaptureState>,
+    frames: FrameCache,
+}
+
+impl MacController {
+    /// Titles of all the windows that can be captured.
+    pub fn window_titles() -> anyhow::Result<Vec<String>> {
+        Ok(Self::enumerate_windows()?
+            .into_iter()
+            .map(|(title, _)| title)
+            .collect())
+    }
+
+    /// Enumerate all available windows, the on screen ones of apps
+    pub fn enumerate_windows() -> anyhow::Result<Vec<(String, CGWindowID)>> {
+        let infos = window_infos(
+            kCGWindowListOptionOnScreenOnly | kCGWindowListExcludeDesktopElements,
+            kCGNullWindowID,
+        );
+        if infos.is_empty() {
+            anyhow::bail!("Failed to list windows");
+        }
+
+        Ok(infos
+            .iter()
+            // Layer 0 are normal windows, above it are menus, the dock and so on
+            .filter(|info| {
+                info_value::<CFNumber>(info, unsafe { kCGWindowLayer })
+                    .and_then(|layer| layer.to_i64())
+                    == Some(0)
+            })
+            .filter_map(|info| {
+                let title = info_value::<CFString>(info, unsafe { kCGWindowName })?.to_string();
+                let id = info_value::<CFNumber>(info, unsafe { kCGWindowNumber })?.to_i64()?;
+                Some((title, id as CGWindowID))
+            })
+            .filter(|(t, _)| !t.is_empty())
+            .collect())
+    }
+
+    /// Create a new controller by window title (exact match).
+    pub fn from_window_title(title: &str) -> anyhow::Result<Self> {
+        let window = Self::enumerate_windows()?
+            .into_iter()
+            .find(|(t, _)| t == title)
+            .map(|(_, w)| w)
+            .ok_or_else(|| anyhow::anyhow!("Window with title '{}' not found", title))?;
+
+        Self::from_window(window)
+    }
+
+    /// Create a new controller from a window number.
+    pub fn from_window(window: CGWindowID) -> anyhow::Result<Self> {
+        let window_title = window_infos(kCGWindowListOptionIncludingWindow, window)
+            .first()
+            .and_then(|info| info_value::<CFString>(info, unsafe { kCGWindowName }))
+            .map(|title| title.to_string())
+            .unwrap_or_default();
+
+        let enigo = Enigo::new(&Settings::default())
+            .map_err(|e| anyhow::anyhow!("Failed to create enigo instance: {e}"))?;
+
+        // Capture once to ensure capture works
+        let image = Self::capture(window)?;
+        info!("Connected to window {window} '{window_title}'");
+
+        Ok(Self {
+            window,
+            window_title,
+            enigo: Mutex::new(enigo),
+            capture_state: Mutex::new(CaptureState {
+                size: image.dimensions(),
+                resized: false,
+            }),
+            frames: FrameCache::new(),
+        })
+    }
+
+    fn capture(window: CGWindowID) -> anyhow::Result<image::RgbaImage> {
+        // The null rect is the bounds of the window
+        let null = CGRect::new(
+            &CGPoint::new(f64::INFINITY, f64::INFINITY),
+            &CGSize::new(0.0, 0.0),
+        );
+        let image = window::create_image(
+            null,
+            kCGWindowListOptionIncludingWindow,
+            window,
+            kCGWindowImageBoundsIgnoreFraming | kCGWindowImageBestResolution,
+        )
+        .ok_or_else(|| anyhow::anyhow!("Failed to capture window {window}"))?;
+        if image.bits_per_pixel() != 32 {
+            anyhow::bail!("Unsupported {} bits per pixel", image.bits_per_pixel());
+        }
+
+        let (width, height) = (image.width() as u32, image.height() as u32);
+        if width == 0 || height == 0 {
+            anyhow::bail!("Window {window} is not on screen");
+        }
+        Ok(to_rgba_image(
+            width,
+            height,
+            image.bytes_per_row(),
+            image.data().bytes(),
+        ))
+    }
+
+    /// Capture the window and track its size.
+    fn screencap_image(&self) -> anyhow::Result<image::RgbaImage> {
+        let image = Self::capture(self.window)?;
+        let mut state = self.capture_state.lock().unwrap();
+        if state.size != image.dimensions() {
+            info!(
+                "Window resized: {}x{} -> {}x{}",
+                state.size.0,
+                state.size.1,
+                image.width(),
+                image.height()
+            );
+            state.size = image.dimensions();
+            state.resized = true;
+        }
+        Ok(image)
+    }
+
+    /// Get the window title
+    pub fn window_title(&self) -> &str {
+        &self.window_title
+    }
+
+    /// The number of the window
+    pub fn window(&self) -> CGWindowID {
+        self.window
+    }
+
+    /// Get the current bounds of the window on the screen, in points.
+    pub fn window_bounds(&self) -> anyhow::Result<CGRect> {
+        window_infos(kCGWindowListOptionIncludingWindow, self.window)
+            .first()
+            .and_then(|info| info_value::<CFDictionary>(info, unsafe { kCGWindowBounds }))
+            .and_then(|bounds| CGRect::from_dict_representation(&bounds))
+            .ok_or_else(|| anyhow::anyhow!("Failed to get bounds of window {}", self.window))
+    }
+
+    /// Convert local coordinates to screen coordinates
+    fn local_to_screen(&self, x: i32, y: i32) -> anyhow::Result<(i32, i32)> {
+        let size = self.capture_state.lock().unwrap().size;
+        Ok(to_screen(self.window_bounds()?, size, x, y))
+    }
+
+    /// Move the cursor to the local coordinates.
+    fn move_to(&self, enigo: &mut Enigo, x: i32, y: i32) -> anyhow::Result<()> {
+        let (screen_x, screen_y) = self.local_to_screen(x, y)?;
+        enigo
+            .move_mouse(screen_x, screen_y, Coordinate::Abs)
+            .map_err(|e| anyhow::anyhow!("Failed to move mouse: {e}"))
+    }
+
+    /// Scroll the mouse wheel, down if `delta` is positive
+    pub fn scroll(&self, x: u32, y: u32, delta: i32) -> anyhow::Result<()> {
+        let mut enigo = self.enigo.lock().unwrap();
+        self.move_to(&mut enigo, x as i32, y as i32)?;
+
+        thread::sleep(Duration::from_millis(10));
+
+        enigo
+            .scroll(delta, Axis::Vertical)
+            .map_err(|e| anyhow::anyhow!("Failed to scroll: {e}"))
+    }
+}
+
+impl CaptureProvider for MacController {
+    fn frames(&self) -> &FrameCache {
+        &self.frames
+    }
+
+    fn latest_frame(&self) -> anyhow::Result<Frame> {
+        self.frames.get_or_capture(|| self.screencap_image())
+    }
+}
+
+impl ControllerTrait for MacController {
+    fn screen_size(&self) -> (u32, u32) {
+        self.capture_state.lock().unwrap().size
+    }
+
+    fn resolution_changed(&self) -> Option<(u32, u32)> {
+        let mut state = self.capture_state.lock().unwrap();
+        std::mem::take(&mut state.resized).then_some(state.size)
+    }
+
+    fn screencap_raw(&self) -> anyhow::Result<(u32, u32, Vec<u8>)> {
+        let image = self.latest_frame()?.into_image();
+        Ok((image.width(), image.height(), image.into_raw()))
+    }
+
+    fn screencap(&self) -> anyhow::Result<image::DynamicImage> {
+        Ok(image::DynamicImage::ImageRgba8(
+            self.latest_frame()?.into_image(),
+        ))
+    }
+
+    fn capture_provider(&self) -> Option<&dyn CaptureProvider> {
+        Some(self)
+    }
+
+    fn click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+        let mut enigo = self.enigo.lock().unwrap();
+        self.move_to(&mut enigo, x as i32, y as i32)?;
+
+        thread::sleep(Duration::from_millis(10));
+
+        enigo
+            .button(Button::Left, enigo::Direction::Click)
+            .map_err(|e| anyhow::anyhow!("Failed to click: {e}"))
+    }
+
+    fn long_press(&self, x: u32, y: u32, duration: Duration) -> anyhow::Result<()> {
+        let mut enigo = self.enigo.lock().unwrap();
+        self.move_to(&mut enigo, x as i32, y as i32)?;
+
+        thread::sleep(Duration::from_millis(10));
+
+        enigo
+            .button(Button::Left, enigo::Direction::Press)
+            .map_err(|e| anyhow::anyhow!("Failed to press mouse: {e}"))?;
+        thread::sleep(duration);
+        enigo
+            .button(Button::Left, enigo::Direction::Release)
+            .map_err(|e| anyhow::anyhow!("Failed to release mouse: {e}"))
+    }
+
+    fn double_click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+        let mut enigo = self.enigo.lock().unwrap();
+        self.move_to(&mut enigo, x as i32, y as i32)?;
+
+        thread::sleep(Duration::from_millis(10));
+
+        // enigo sets the click count of the second click within the system
+        // double click interval, which makes it a double click
+        for _ in 0..2 {
+            enigo
+                .button(Button::Left, enigo::Direction::Click)
+                .map_err(|e| anyhow::anyhow!("Failed to click: {e}"))?;
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        Ok(())
+    }
+
+    fn swipe(
+        &self,
+        start: (u32, u32),
+        end: (i32, i32),
+        duration: Duration,
+        slope_in: f32,
+        slope_out: f32,
+    ) -> anyhow::Result<()> {
+        // The window is not expected to move during the swipe
+        let bounds = self.window_bounds()?;
+        let size = self.capture_state.lock().unwrap().size;
+
+        let mut enigo = self.enigo.lock().unwrap();
+        let (start_x, start_y) = to_screen(bounds, size, start.0 as i32, start.1 as i32);
+        enigo
+            .move_mouse(start_x, start_y, Coordinate::Abs)
+            .map_err(|e| anyhow::anyhow!("Failed to move mouse: {e}"))?;
+
+        thread::sleep(Duration::from_millis(10));
+
+        enigo
+            .button(Button::Left, enigo::Direction::Press)
+            .map_err(|e| anyhow::anyhow!("Failed to press mouse button: {e}"))?;
+
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+
+        let duration_ms = duration.as_millis() as u32;
+        for t in (SWIPE_DELAY_MS..duration_ms).step_by(SWIPE_DELAY_MS as usize) {
+            let progress = swipe_progress(slope_in, slope_out, t as f32 / duration_ms as f32);
+
+            let cur_x = lerp(start.0 as f32, end.0 as f32, progress) as i32;
+            let cur_y = lerp(start.1 as f32, end.1 as f32, progress) as i32;
+            let (cur_x, cur_y) = to_screen(bounds, size, cur_x, cur_y);
+            enigo
+                .move_mouse(cur_x, cur_y, Coordinate::Abs)
+                .map_err(|e| anyhow::anyhow!("Failed to move mouse during swipe: {e}"))?;
+
+            thread::sleep(Duration::from_millis(SWIPE_DELAY_MS as u64));
+        }
+
+        let (end_x, end_y) = to_screen(bounds, size, end.0, end.1);
+        enigo
+            .move_mouse(end_x, end_y, Coordinate::Abs)
+            .map_err(|e| anyhow::anyhow!("Failed to move mouse to end position: {e}"))?;
+
+        thread::sleep(Duration::from_millis(50));
+
+        enigo
+            .button(Button::Left, enigo::Direction::Release)
+            .map_err(|e| anyhow::anyhow!("Failed to release mouse button: {e}"))
+    }
+
+    fn input_text(&self, text: &str) -> anyhow::Result<()> {
+        let mut enigo = self.enigo.lock().unwrap();
+        enigo
+            .text(text)
+            .map_err(|e| anyhow::anyhow!("Failed to input text: {e}"))
+    }
+
+    fn press(&self, key: enigo::Key) -> anyhow::Result<()> {
+        let mut enigo = self.enigo.lock().unwrap();
+        enigo
+            .key(key, enigo::Direction::Press)
+            .map_err(|e| anyhow::anyhow!("Failed to press key: {e}"))?;
+
+        thread::sleep(Duration::from_millis(30));
+
+        enigo
+            .key(key, enigo::Direction::Release)
+            .map_err(|e| anyhow::anyhow!("Failed to release key: {e}"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_screen() {
+        let bounds = CGRect::new(&CGPoint::new(100.0, 50.0), &CGSize::new(800.0, 600.0));
+        // Retina, captured in twice the points
+        assert_eq!(to_screen(bounds, (1600, 1200), 0, 0), (100, 50));
+        assert_eq!(to_screen(bounds, (1600, 1200), 800, 600), (500, 350));
+        assert_eq!(to_screen(bounds, (800, 600), 800, 600), (900, 650));
+    }
+
+    #[test]
+    fn test_to_rgba_image() {
+        // A red and a blue pixel in rows padded to 12 bytes
+        let data = [0, 0, 255, 255, 255, 0, 0, 255, 0, 0, 0, 0];
+        let image = to_rgba_image(2, 1, 12, &data);
+        assert_eq!(image.as_raw(), &[255, 0, 0, 255, 0, 0, 255, 255]);
+    }
+}
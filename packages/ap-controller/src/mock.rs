@@ -0,0 +1,475 @@
+//! A controller replaying recorded screens, to test tasks without a device
+//!
+//! A [`MockController`] shows one of its screens at a time and moves to another
+//! when an input matches one of its [`Transition`]s, i.e. a click in the rect of
+//! a button leading to the next screen. Every input is kept, see
+//! [`MockController::inputs`], and nothing depends on timing, so a task or a
+//! navigation graph runs the same way every time.
+//!
+//! A mock can be built in code, or loaded from a directory of screenshots, named
+//! after their screen, and a `mock.json` of the transitions:
+//!
+//! ```json
+//! {
+//!   "initial": "main",
+//!   "transitions": [
+//!     { "from": "main", "on": { "click": { "x": 1600, "y": 900, "width": 200, "height": 100 } }, "to": "battle" },
+//!     { "from": "battle", "on": { "keycode": 4 }, "to": "main" },
+//!     { "on": { "launch": "com.example.game" }, "to": "main" }
+//!   ]
+//! }
+//! ```
+//!
+//! It is also the `mock` backend of the [`registry`](crate::registry), to a
+//! directory, i.e. `mock:tests/screens`.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::Context;
+use image::math::Rect;
+use serde::{Deserialize, Serialize};
+
+use crate::{AppManagement, ControllerTrait, Key, KeyEvents};
+
+/// The file of the transitions in a mock directory
+pub const MOCK_FILE: &str = "mock.json";
+
+/// What makes a [`Transition`] happen
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trigger {
+    /// A click, long press or double click in the rect, or a swipe starting in it
+    Click {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// A [`Key`] by its `Debug` name, i.e. `Escape`
+    Key(String),
+    /// An Android key code, see [`KeyEvents`]
+    Keycode(u32),
+    /// Launching the app, see [`AppManagement`]
+    Launch(String),
+}
+
+impl Trigger {
+    pub fn click(rect: Rect) -> Self {
+        Trigger::Click {
+            x: rect.x,
+            y: rect.y,
+            width: rect.width,
+            height: rect.height,
+        }
+    }
+
+    fn is_triggered_by(&self, input: &MockInput) -> bool {
+        let point = match input {
+            MockInput::Click(x, y)
+            | MockInput::LongPress(x, y)
+            | MockInput::DoubleClick(x, y)
+            | MockInput::Swipe { start: (x, y), .. } => Some((*x, *y)),
+            _ => None,
+        };
+        match (self, input) {
+            (
+                Trigger::Click {
+                    x,
+                    y,
+                    width,
+                    height,
+                },
+                _,
+            ) => point.is_some_and(|(px, py)| {
+                (*x..x + width).contains(&px) && (*y..y + height).contains(&py)
+            }),
+            (Trigger::Key(name), MockInput::Key(key)) => format!("{key:?}") == *name,
+            (Trigger::Keycode(code), MockInput::Keycode(pressed)) => code == pressed,
+            (Trigger::Launch(app), MockInput::Launch(launched)) => app == launched,
+            _ => false,
+        }
+    }
+}
+
+/// Go to the screen `to` on `on`, from the screen `from` or from any screen if
+/// it is `None`. The first transition matching an input is taken.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Transition {
+    #[serde(default)]
+    pub from: Option<String>,
+    pub on: Trigger,
+    pub to: String,
+}
+
+impl Transition {
+    pub fn new(from: Option<&str>, on: Trigger, to: impl Into<String>) -> Self {
+        Self {
+            from: from.map(str::to_string),
+            on,
+            to: to.into(),
+        }
+    }
+}
+
+/// An input received by a [`MockController`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockInput {
+    Click(u32, u32),
+    LongPress(u32, u32),
+    DoubleClick(u32, u32),
+    Swipe { start: (u32, u32), end: (i32, i32) },
+    Text(String),
+    Key(Key),
+    Keycode(u32),
+    Launch(String),
+    Stop(String),
+}
+
+#[derive(Deserialize)]
+struct MockFile {
+    initial: String,
+    #[serde(default)]
+    transitions: Vec<Transition>,
+}
+
+struct MockState {
+    screen: String,
+    /// The app launched last and not stopped since
+    app: Option<String>,
+    inputs: Vec<MockInput>,
+}
+
+/// See the [module](self) docs.
+pub struct MockController {
+    screens: HashMap<String, Arc<image::RgbaImage>>,
+    transitions: Vec<Transition>,
+    state: Mutex<MockState>,
+}
+
+impl MockController {
+    /// A mock showing `screen` as `name`, with no transitions yet.
+    pub fn new(name: impl Into<String>, screen: image::RgbaImage) -> Self {
+        let name = name.into();
+        Self {
+            screens: HashMap::from([(name.clone(), Arc::new(screen))]),
+            transitions: Vec::new(),
+            state: Mutex::new(MockState {
+                screen: name,
+                app: None,
+                inputs: Vec::new(),
+            }),
+        }
+    }
+
+    /// Load the screenshots of `dir` (PNG or JPEG) and its [`MOCK_FILE`], see
+    /// the [module](self) docs.
+    pub fn load(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref();
+        let path = dir.join(MOCK_FILE);
+        let file: MockFile = serde_json::from_str(
+            &std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?,
+        )
+        .with_context(|| format!("failed to parse {}", path.display()))?;
+
+        let mut screens = HashMap::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_image = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| matches!(ext, "png" | "jpg" | "jpeg"));
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            if is_image {
+                let screen = image::open(&path)
+                    .with_context(|| format!("failed to open {}", path.display()))?;
+                screens.insert(name.to_string(), Arc::new(screen.into_rgba8()));
+            }
+        }
+        let mock = Self {
+            screens,
+            transitions: file.transitions,
+            state: Mutex::new(MockState {
+                screen: file.initial,
+                app: None,
+                inputs: Vec::new(),
+            }),
+        };
+        mock.check()?;
+        Ok(mock)
+    }
+
+    /// Every screen named by a transition or as the initial one exists.
+    fn check(&self) -> anyhow::Result<()> {
+        let initial = self.screen();
+        let names = self.transitions.iter().flat_map(|transition| {
+            transition
+                .from
+                .iter()
+                .chain([&transition.to])
+                .map(String::as_str)
+        });
+        for name in [initial.as_str()].into_iter().chain(names) {
+            anyhow::ensure!(self.screens.contains_key(name), "no screen named {name}");
+        }
+        Ok(())
+    }
+
+    pub fn with_screen(mut self, name: impl Into<String>, screen: image::RgbaImage) -> Self {
+        self.screens.insert(name.into(), Arc::new(screen));
+        self
+    }
+
+    /// Add `transition` after the others, see [`Transition`].
+    pub fn with_transition(mut self, transition: Transition) -> Self {
+        self.transitions.push(transition);
+        self
+    }
+
+    /// The name of the screen shown
+    pub fn screen(&self) -> String {
+        self.state.lock().unwrap().screen.clone()
+    }
+
+    /// Show the screen `name`, i.e. to start a test elsewhere.
+    pub fn set_screen(&self, name: &str) -> anyhow::Result<()> {
+        anyhow::ensure!(self.screens.contains_key(name), "no screen named {name}");
+        self.state.lock().unwrap().screen = name.to_string();
+        Ok(())
+    }
+
+    /// The inputs received so far, the oldest first
+    pub fn inputs(&self) -> Vec<MockInput> {
+        self.state.lock().unwrap().inputs.clone()
+    }
+
+    /// Keep `input` and take the transition it triggers, if any.
+    fn input(&self, input: MockInput) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let transition = self.transitions.iter().find(|transition| {
+            transition
+                .from
+                .as_ref()
+                .is_none_or(|from| *from == state.screen)
+                && transition.on.is_triggered_by(&input)
+        });
+        if let Some(transition) = transition {
+            anyhow::ensure!(
+                self.screens.contains_key(&transition.to),
+                "no screen named {}",
+                transition.to
+            );
+            state.screen = transition.to.clone();
+        }
+        state.inputs.push(input);
+        Ok(())
+    }
+
+    fn current(&self) -> Arc<image::RgbaImage> {
+        let state = self.state.lock().unwrap();
+        self.screens[&state.screen].clone()
+    }
+}
+
+impl KeyEvents for MockController {
+    fn press_keycode(&self, keycode: u32) -> anyhow::Result<()> {
+        self.input(MockInput::Keycode(keycode))
+    }
+}
+
+impl AppManagement for MockController {
+    fn launch_app(&self, app: &str) -> anyhow::Result<()> {
+        self.input(MockInput::Launch(app.to_string()))?;
+        self.state.lock().unwrap().app = Some(app.to_string());
+        Ok(())
+    }
+
+    fn stop_app(&self, app: &str) -> anyhow::Result<()> {
+        self.input(MockInput::Stop(app.to_string()))?;
+        let mut state = self.state.lock().unwrap();
+        if state.app.as_deref() == Some(app) {
+            state.app = None;
+        }
+        Ok(())
+    }
+
+    fn current_app(&self) -> anyhow::Result<Option<String>> {
+        let app = self.state.lock().unwrap().app.clone();
+        // As a package, without the activity it was launched with
+        Ok(app.map(|app| app.split('/').next().unwrap_or_default().to_string()))
+    }
+}
+
+impl ControllerTrait for MockController {
+    fn screen_size(&self) -> (u32, u32) {
+        self.current().dimensions()
+    }
+
+    fn key_events(&self) -> Option<&dyn KeyEvents> {
+        Some(self)
+    }
+
+    fn app_management(&self) -> Option<&dyn AppManagement> {
+        Some(self)
+    }
+
+    fn screencap_raw(&self) -> anyhow::Result<(u32, u32, Vec<u8>)> {
+        let screen = self.current();
+        Ok((screen.width(), screen.height(), screen.as_raw().clone()))
+    }
+
+    fn screencap(&self) -> anyhow::Result<image::DynamicImage> {
+        Ok(image::DynamicImage::ImageRgba8(Arc::unwrap_or_clone(
+            self.current(),
+        )))
+    }
+
+    fn click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+        self.input(MockInput::Click(x, y))
+    }
+
+    fn swipe(
+        &self,
+        start: (u32, u32),
+        end: (i32, i32),
+        _duration: Duration,
+        _slope_in: f32,
+        _slope_out: f32,
+    ) -> anyhow::Result<()> {
+        self.input(MockInput::Swipe { start, end })
+    }
+
+    fn long_press(&self, x: u32, y: u32, _duration: Duration) -> anyhow::Result<()> {
+        self.input(MockInput::LongPress(x, y))
+    }
+
+    fn double_click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+        self.input(MockInput::DoubleClick(x, y))
+    }
+
+    fn input_text(&self, text: &str) -> anyhow::Result<()> {
+        self.input(MockInput::Text(text.to_string()))
+    }
+
+    fn press(&self, key: Key) -> anyhow::Result<()> {
+        // Like the controllers with key events, which take other keys as key codes
+        match key {
+            Key::Other(keycode) => self.input(MockInput::Keycode(keycode)),
+            key => self.input(MockInput::Key(key)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn screen(value: u8) -> image::RgbaImage {
+        image::RgbaImage::from_pixel(8, 4, image::Rgba([value, value, value, 255]))
+    }
+
+    #[test]
+    fn test_mock_controller() {
+        let button = Rect {
+            x: 4,
+            y: 0,
+            width: 4,
+            height: 2,
+        };
+        let mock = MockController::new("main", screen(0))
+            .with_screen("battle", screen(1))
+            .with_transition(Transition::new(
+                Some("main"),
+                Trigger::click(button),
+                "battle",
+            ))
+            .with_transition(Transition::new(None, Trigger::Keycode(4), "main"))
+            .with_transition(Transition::new(
+                None,
+                Trigger::Key("Escape".to_string()),
+                "main",
+            ));
+        mock.check().unwrap();
+
+        mock.click(1, 1).unwrap();
+        assert_eq!(mock.screen(), "main");
+        mock.click(5, 1).unwrap();
+        assert_eq!(mock.screen(), "battle");
+        assert_eq!(mock.screencap().unwrap().to_rgba8(), screen(1));
+        // Not from the battle screen
+        mock.swipe((5, 1), (0, 0), Duration::ZERO, 1.0, 1.0)
+            .unwrap();
+        assert_eq!(mock.screen(), "battle");
+        mock.press(Key::Other(4)).unwrap();
+        assert_eq!(mock.screen(), "main");
+        mock.long_press(6, 1, Duration::ZERO).unwrap();
+        assert_eq!(mock.screen(), "battle");
+        mock.press(Key::Escape).unwrap();
+        assert_eq!(mock.screen(), "main");
+
+        assert_eq!(
+            mock.inputs(),
+            [
+                MockInput::Click(1, 1),
+                MockInput::Click(5, 1),
+                MockInput::Swipe {
+                    start: (5, 1),
+                    end: (0, 0)
+                },
+                MockInput::Keycode(4),
+                MockInput::LongPress(6, 1),
+                MockInput::Key(Key::Escape),
+            ]
+        );
+
+        mock.launch_app("com.example/.Main").unwrap();
+        assert_eq!(mock.current_app().unwrap().as_deref(), Some("com.example"));
+        mock.stop_app("com.example/.Main").unwrap();
+        assert_eq!(mock.current_app().unwrap(), None);
+        assert!(mock.set_screen("shop").is_err());
+
+        let broken = MockController::new("main", screen(0)).with_transition(Transition::new(
+            None,
+            Trigger::Keycode(4),
+            "shop",
+        ));
+        assert!(broken.check().is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = std::env::temp_dir().join(format!("ap-mock-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        screen(0).save(dir.join("main.png")).unwrap();
+        screen(1).save(dir.join("battle.png")).unwrap();
+        std::fs::write(
+            dir.join(MOCK_FILE),
+            r#"{
+                "initial": "main",
+                "transitions": [
+                    { "from": "main", "on": { "click": { "x": 0, "y": 0, "width": 8, "height": 4 } }, "to": "battle" },
+                    { "on": { "launch": "com.example" }, "to": "main" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let mock = MockController::load(&dir).unwrap();
+        assert_eq!(mock.screen_size(), (8, 4));
+        mock.click(2, 2).unwrap();
+        assert_eq!(mock.screen(), "battle");
+        mock.launch_app("com.example").unwrap();
+        assert_eq!(mock.screen(), "main");
+
+        std::fs::write(dir.join(MOCK_FILE), r#"{ "initial": "shop" }"#).unwrap();
+        assert!(MockController::load(&dir).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+//! What a device is, probed once and cached on disk
+//!
+//! Connecting an [`AndroidController`](crate::AndroidController) takes a
+//! screenshot for the resolution, checks that maatouch is on the device and asks
+//! for a few properties. A [`DeviceProfile`] keeps all of that in a
+//! [`ProfileCache`], a JSON file per serial, so the next connection skips them
+//! until the profile is older than [`ProfileCache::max_age`]. A cached profile
+//! is checked against the much cheaper `wm size` on connection, and probed again
+//! if the display changed. One rotated since it was probed is dropped from the
+//! cache on the first capture, to be probed again on the next connection.
+//!
+//! The profile is also how tasks learn about the device they run on, see
+//! [`ControllerTrait::device_profile`](crate::ControllerTrait::device_profile).
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use ap_adb::props::Properties;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+/// What the maatouch (or minitouch) of a device reported when it started
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TouchInfo {
+    pub max_contact: u32,
+    pub max_x: u32,
+    pub max_y: u32,
+    pub max_pressure: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceProfile {
+    pub serial: String,
+    /// i.e. `arm64-v8a`
+    pub abi: String,
+    pub sdk: u32,
+    /// The size of a screenshot, in the orientation it was taken in
+    pub resolution: (u32, u32),
+    /// In dpi, `None` if the device did not tell
+    pub density: Option<u32>,
+    /// `None` until maatouch ran on the device
+    pub touch: Option<TouchInfo>,
+    /// Seconds since the unix epoch
+    pub probed_at: u64,
+}
+
+impl DeviceProfile {
+    /// Ask `device` what it is, with a screenshot for its resolution.
+    pub fn probe(device: &ap_adb::Device) -> anyhow::Result<Self> {
+        let screen = device.screencap()?;
+        let props = device.properties()?;
+        let density = device.shell("wm density")?.stdout;
+        let profile = Self::parse(
+            device.serial(),
+            (screen.width(), screen.height()),
+            &props,
+            &density,
+        )?;
+        debug!("probed {profile:?}");
+        Ok(profile)
+    }
+
+    /// The profile from the properties of the device and the output of `wm density`.
+    fn parse(
+        serial: String,
+        resolution: (u32, u32),
+        props: &Properties,
+        density: &str,
+    ) -> anyhow::Result<Self> {
+        let abi = props.abi().unwrap_or_default().to_string();
+        let sdk = props.sdk().with_context(|| {
+            format!(
+                "invalid sdk version {:?}",
+                props.get("ro.build.version.sdk").unwrap_or_default()
+            )
+        })?;
+        let density = parse_density(density).or_else(|| props.lcd_density());
+        Ok(Self {
+            serial,
+            abi,
+            sdk,
+            resolution,
+            density,
+            touch: None,
+            probed_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        })
+    }
+
+    /// Whether it was probed on a display of `size`, in either orientation, see
+    /// [`display_size`].
+    pub fn fits_display(&self, size: (u32, u32)) -> bool {
+        let (width, height) = self.resolution;
+        size == (width, height) || size == (height, width)
+    }
+
+    /// How long ago it was probed
+    pub fn age(&self) -> Duration {
+        let probed_at = UNIX_EPOCH + Duration::from_secs(self.probed_at);
+        SystemTime::now()
+            .duration_since(probed_at)
+            .unwrap_or_default()
+    }
+}
+
+/// The size of the display of `device` in its natural orientation, from `wm size`.
+pub fn display_size(device: &ap_adb::Device) -> anyhow::Result<(u32, u32)> {
+    let output = device.shell("wm size")?.stdout;
+    parse_display_size(&output).with_context(|| format!("unexpected output of wm size {output:?}"))
+}
+
+/// `Physical size: 1080x2400`, then `Override size: 720x1600` if it is changed
+fn parse_display_size(output: &str) -> Option<(u32, u32)> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once("size:"))
+        .filter_map(|(_, size)| {
+            let (width, height) = size.trim().split_once('x')?;
+            Some((width.parse().ok()?, height.parse().ok()?))
+        })
+        .next_back()
+}
+
+/// `Physical density: 440`, then `Override density: 400` if it is changed
+fn parse_density(output: &str) -> Option<u32> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once("density:"))
+        .filter_map(|(_, density)| density.trim().parse().ok())
+        .next_back()
+}
+
+/// Where [`DeviceProfile`]s are kept, see the [module](self) docs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProfileCache {
+    pub dir: PathBuf,
+    /// Profiles older than this are probed again
+    pub max_age: Duration,
+}
+
+impl Default for ProfileCache {
+    fn default() -> Self {
+        Self::new(Self::default_dir())
+    }
+}
+
+impl ProfileCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_age: Duration::from_secs(7 * 24 * 60 * 60),
+        }
+    }
+
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    /// `auto-play/devices` in the cache directory of the user, of the system
+    /// temporary directory if there is none.
+    pub fn default_dir() -> PathBuf {
+        let cache = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("LOCALAPPDATA").map(PathBuf::from))
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+            .unwrap_or_else(std::env::temp_dir);
+        cache.join("auto-play").join("devices")
+    }
+
+    fn path(&self, serial: &str) -> PathBuf {
+        // Network serials are `<host>:<port>`
+        let name = serial
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+        self.dir.join(format!("{name}.json"))
+    }
+
+    /// The profile of `serial`, if it is cached and not older than `max_age`.
+    pub fn load(&self, serial: &str) -> Option<DeviceProfile> {
+        let path = self.path(serial);
+        let content = std::fs::read_to_string(&path).ok()?;
+        match serde_json::from_str::<DeviceProfile>(&content) {
+            Ok(profile) if profile.serial == serial && profile.age() <= self.max_age => {
+                Some(profile)
+            }
+            Ok(_) => None,
+            Err(err) => {
+                warn!("ignoring invalid device profile {}: {err}", path.display());
+                None
+            }
+        }
+    }
+
+    pub fn store(&self, profile: &DeviceProfile) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        let path = self.path(&profile.serial);
+        std::fs::write(&path, serde_json::to_string_pretty(profile)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Forget the profile of `serial`, to probe it again on the next connection.
+    pub fn remove(&self, serial: &str) {
+        let _ = std::fs::remove_file(self.path(serial));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_cache() {
+        let props = Properties::parse(
+            "[ro.product.cpu.abi]: [arm64-v8a]\n\
+             [ro.build.version.sdk]: [34]\n\
+             [ro.sf.lcd_density]: [440]\n",
+        );
+        let density = "Physical density: 440\nOverride density: 400\n";
+        let mut profile =
+            DeviceProfile::parse("127.0.0.1:5555".to_string(), (2400, 1080), &props, density)
+                .unwrap();
+        assert_eq!(profile.abi, "arm64-v8a");
+        assert_eq!(profile.sdk, 34);
+        assert_eq!(profile.density, Some(400));
+        // The density of the properties if wm does not tell
+        let fallback = DeviceProfile::parse(String::new(), (0, 0), &props, "error").unwrap();
+        assert_eq!(fallback.density, Some(440));
+        let props = Properties::parse("[ro.product.cpu.abi]: [x86]\n");
+        assert!(DeviceProfile::parse(String::new(), (0, 0), &props, "").is_err());
+
+        let size = parse_display_size("Physical size: 1080x2400\nOverride size: 720x1600\n");
+        assert_eq!(size, Some((720, 1600)));
+        assert_eq!(
+            parse_display_size("Physical size: 1080x2400\n"),
+            Some((1080, 2400))
+        );
+        assert_eq!(parse_display_size("error"), None);
+        assert!(profile.fits_display((1080, 2400)));
+        assert!(!profile.fits_display((720, 1600)));
+
+        let dir = std::env::temp_dir().join(format!("ap-profiles-{}", std::process::id()));
+        let cache = ProfileCache::new(&dir);
+        assert_eq!(cache.load("127.0.0.1:5555"), None);
+        profile.touch = Some(TouchInfo {
+            max_contact: 10,
+            max_x: 2400,
+            max_y: 1080,
+            max_pressure: 100,
+        });
+        cache.store(&profile).unwrap();
+        assert!(dir.join("127.0.0.1_5555.json").exists());
+        assert_eq!(cache.load("127.0.0.1:5555"), Some(profile.clone()));
+        // Another serial with the same file name
+        assert_eq!(cache.load("127.0.0.1_5555"), None);
+
+        profile.probed_at -= 60;
+        cache.store(&profile).unwrap();
+        let stale = cache.clone().with_max_age(Duration::from_secs(30));
+        assert_eq!(stale.load("127.0.0.1:5555"), None);
+
+        cache.remove("127.0.0.1:5555");
+        assert_eq!(cache.load("127.0.0.1:5555"), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,307 @@
+//! Encode the screen of a controller to an MP4
+//!
+//! [`Recorder`] records any [`ControllerTrait`] on a background thread, from the
+//! frames its [`CaptureProvider`] already captures when it has one, i.e. the
+//! stream of an Android device or the frames of a Windows window.
+//! [`VideoEncoder`] is the encoding alone, to draw on the frames first.
+//!
+//! Encoding is done by `ffmpeg`, which has to be in `PATH` (or set with
+//! [`RecorderOptions::ffmpeg`]).
+//!
+//! ```ignore
+//! let controller = Arc::new(AndroidController::connect(serial)?);
+//! let recorder = Recorder::start(controller.clone(), "run.mp4", RecorderOptions::default())?;
+//! // ...
+//! recorder.stop()?;
+//! ```
+
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use image::{RgbaImage, imageops::FilterType};
+use tracing::warn;
+
+use crate::ControllerTrait;
+
+#[derive(Debug, Clone)]
+pub struct RecorderOptions {
+    pub fps: u32,
+    /// Path of the ffmpeg executable
+    pub ffmpeg: PathBuf,
+}
+
+impl Default for RecorderOptions {
+    fn default() -> Self {
+        Self {
+            fps: 10,
+            ffmpeg: PathBuf::from("ffmpeg"),
+        }
+    }
+}
+
+/// Frames piped to `ffmpeg` at a constant frame rate, in sync with the wall
+/// clock from when it started.
+pub struct VideoEncoder {
+    ffmpeg: Child,
+    stdin: Option<ChildStdin>,
+    size: (u32, u32),
+    fps: u32,
+    start: Instant,
+    written: u64,
+}
+
+impl VideoEncoder {
+    /// Encode frames of `size` to the MP4 at `path`.
+    pub fn start(path: &Path, size: (u32, u32), options: &RecorderOptions) -> anyhow::Result<Self> {
+        anyhow::ensure!(options.fps > 0, "fps should be greater than 0");
+        let mut ffmpeg = Command::new(&options.ffmpeg)
+            .args(["-y", "-loglevel", "error"])
+            .args(["-f", "rawvideo", "-pix_fmt", "rgba"])
+            .args(["-s", &format!("{}x{}", size.0, size.1)])
+            .args(["-r", &options.fps.to_string()])
+            .args(["-i", "-"])
+            // yuv420p needs even dimensions
+            .args(["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2"])
+            .args(["-c:v", "libx264", "-pix_fmt", "yuv420p"])
+            .arg(path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to start {}", options.ffmpeg.display()))?;
+        let stdin = ffmpeg.stdin.take();
+        Ok(Self {
+            ffmpeg,
+            stdin,
+            size,
+            fps: options.fps,
+            start: Instant::now(),
+            written: 0,
+        })
+    }
+
+    /// The size of the video, frames of another size are resized to it, i.e.
+    /// when a window gets resized.
+    pub fn size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// How long since the video started
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// When the next frame is due, since the video started
+    pub fn next_frame(&self) -> Duration {
+        Duration::from_secs_f64(self.written as f64 / self.fps as f64)
+    }
+
+    /// Write `frame` as the current frame. The video has a constant frame rate,
+    /// so a frame that took long to capture is repeated to keep it in sync.
+    pub fn write(&mut self, frame: &RgbaImage) -> anyhow::Result<()> {
+        let resized;
+        let frame = if frame.dimensions() == self.size {
+            frame
+        } else {
+            resized =
+                image::imageops::resize(frame, self.size.0, self.size.1, FilterType::Triangle);
+            &resized
+        };
+        let due = (self.elapsed().as_secs_f64() * self.fps as f64) as u64 + 1;
+        let stdin = self
+            .stdin
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("the video is finished"))?;
+        while self.written < due {
+            stdin.write_all(frame)?;
+            self.written += 1;
+        }
+        Ok(())
+    }
+
+    /// Sleep until the next frame is due.
+    pub fn wait_next_frame(&self) {
+        thread::sleep(self.next_frame().saturating_sub(self.elapsed()));
+    }
+
+    /// Close the input and wait for `ffmpeg` to write the video.
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        drop(self.stdin.take());
+        let status = self.ffmpeg.wait()?;
+        anyhow::ensure!(status.success(), "ffmpeg exited with {status}");
+        Ok(())
+    }
+}
+
+impl Drop for VideoEncoder {
+    fn drop(&mut self) {
+        if self.stdin.take().is_some() {
+            let _ = self.ffmpeg.wait();
+        }
+    }
+}
+
+/// The screen of `controller`, from its capture provider when it has one.
+pub fn capture<C: ControllerTrait + ?Sized>(controller: &C) -> anyhow::Result<RgbaImage> {
+    if let Some(provider) = controller.capture_provider() {
+        return Ok(provider.latest_frame()?.into_image());
+    }
+    let (width, height, rgba) = controller.screencap_raw()?;
+    RgbaImage::from_raw(width, height, rgba)
+        .ok_or_else(|| anyhow::anyhow!("screencap size mismatch"))
+}
+
+/// Records the screen of a controller on a background thread until stopped.
+pub struct Recorder {
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<anyhow::Result<()>>>,
+}
+
+impl Recorder {
+    pub fn start<C: ControllerTrait + Send + Sync + ?Sized + 'static>(
+        controller: Arc<C>,
+        path: impl Into<PathBuf>,
+        options: RecorderOptions,
+    ) -> anyhow::Result<Self> {
+        let path = path.into();
+        let frame = capture(controller.as_ref())?;
+        let mut encoder = VideoEncoder::start(&path, frame.dimensions(), &options)?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("recorder".to_string())
+                .spawn(move || {
+                    let mut frame = frame;
+                    while !stop.load(Ordering::Relaxed) {
+                        encoder.write(&frame)?;
+                        encoder.wait_next_frame();
+                        frame = capture(controller.as_ref())?;
+                    }
+                    encoder.finish()
+                })?
+        };
+        Ok(Self {
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// Stop recording and wait for the video to be written.
+    pub fn stop(mut self) -> anyhow::Result<()> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.handle.take() {
+            Some(handle) => handle
+                .join()
+                .map_err(|_| anyhow::anyhow!("recorder thread panicked"))?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            warn!("failed to finish recording: {err:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+    use crate::MockController;
+
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+    /// An `ffmpeg` writing the raw frames it is given to its output, the last
+    /// argument
+    #[cfg(unix)]
+    fn fake_ffmpeg(dir: &Path) -> PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        std::fs::create_dir_all(dir).unwrap();
+        let ffmpeg = dir.join("ffmpeg");
+        std::fs::write(
+            &ffmpeg,
+            "#!/bin/sh\nfor last; do :; done\ncat > \"$last\"\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&ffmpeg, std::fs::Permissions::from_mode(0o755)).unwrap();
+        ffmpeg
+    }
+
+    #[test]
+    fn test_capture() {
+        let screen = RgbaImage::from_pixel(4, 2, RED);
+        let mock = MockController::new("main", screen.clone());
+        assert_eq!(capture(&mock).unwrap(), screen);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_video_encoder() {
+        let dir = std::env::temp_dir().join(format!("ap-video-encoder-{}", std::process::id()));
+        let options = RecorderOptions {
+            fps: 10,
+            ffmpeg: fake_ffmpeg(&dir),
+        };
+        let path = dir.join("video.mp4");
+        let mut encoder = VideoEncoder::start(&path, (4, 2), &options).unwrap();
+        assert_eq!(encoder.size(), (4, 2));
+        encoder.write(&RgbaImage::from_pixel(4, 2, RED)).unwrap();
+        assert_eq!(encoder.written, 1);
+        // Frames of another size are resized, missed ones repeated
+        thread::sleep(Duration::from_millis(250));
+        encoder.write(&RgbaImage::from_pixel(2, 2, RED)).unwrap();
+        let written = encoder.written;
+        assert!(written >= 3, "{written} frames");
+        encoder.finish().unwrap();
+
+        let video = std::fs::read(&path).unwrap();
+        assert_eq!(video.len() as u64, written * 4 * 2 * 4);
+        assert!(video.chunks(4).all(|pixel| pixel == RED.0));
+
+        let options = RecorderOptions { fps: 0, ..options };
+        assert!(VideoEncoder::start(&path, (4, 2), &options).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_recorder() {
+        let dir = std::env::temp_dir().join(format!("ap-controller-recorder-{}", std::process::id()));
+        let options = RecorderOptions {
+            fps: 50,
+            ffmpeg: fake_ffmpeg(&dir),
+        };
+        let path = dir.join("video.mp4");
+        let mock = Arc::new(MockController::new(
+            "main",
+            RgbaImage::from_pixel(4, 2, RED),
+        ));
+        let recorder = Recorder::start(mock, &path, options).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        recorder.stop().unwrap();
+
+        let video = std::fs::read(&path).unwrap();
+        assert!(!video.is_empty());
+        assert_eq!(video.len() % (4 * 2 * 4), 0);
+        assert!(video.chunks(4).all(|pixel| pixel == RED.0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+//! Controller backends by name
+//!
+//! A backend connects to a target given as a string, i.e. `android` to the
+//! serial of a device, so a controller can be picked from the command line or a
+//! config file without knowing its type. The backends of this crate are
+//! registered as `android`, `mock` (to a directory of screens, see
+//! [`mock`](crate::mock)) and, when built with one, `desktop` (to a window
+//! title); a crate adding a backend registers it with [`register_backend`].
+//!
+//! ```ignore
+//! ap_controller::registry::register_backend("scrcpy", |serial| {
+//!     Ok(Controller::new(ScrcpyController::connect(serial)?))
+//! });
+//! let controller = ap_controller::registry::connect_spec("scrcpy:emulator-5554")?;
+//! ```
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use crate::{AndroidController, Controller, MockController};
+
+pub type BackendFn = Arc<dyn Fn(&str) -> anyhow::Result<Controller> + Send + Sync>;
+
+static BACKENDS: LazyLock<RwLock<HashMap<String, BackendFn>>> = LazyLock::new(|| {
+    let mut backends = HashMap::<String, BackendFn>::new();
+    backends.insert(
+        "android".to_string(),
+        Arc::new(|serial| Ok(Controller::new(AndroidController::connect(serial)?))),
+    );
+    backends.insert(
+        "mock".to_string(),
+        Arc::new(|dir| Ok(Controller::new(MockController::load(dir)?))),
+    );
+    #[cfg(any(feature = "windows", feature = "linux", target_os = "macos"))]
+    backends.insert(
+        "desktop".to_string(),
+        Arc::new(|title| {
+            Ok(Controller::new(
+                crate::DesktopController::from_window_title(title)?,
+            ))
+        }),
+    );
+    RwLock::new(backends)
+});
+
+/// Register `f` as the backend `name`, replacing any backend previously
+/// registered with the same name.
+pub fn register_backend(
+    name: impl Into<String>,
+    f: impl Fn(&str) -> anyhow::Result<Controller> + Send + Sync + 'static,
+) {
+    BACKENDS.write().unwrap().insert(name.into(), Arc::new(f));
+}
+
+pub fn unregister_backend(name: &str) -> bool {
+    BACKENDS.write().unwrap().remove(name).is_some()
+}
+
+/// The names of the backends, sorted
+pub fn backends() -> Vec<String> {
+    let mut names = BACKENDS.read().unwrap().keys().cloned().collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+/// Connect to `target` with the backend `name`.
+pub fn connect(name: &str, target: &str) -> anyhow::Result<Controller> {
+    let backend = BACKENDS.read().unwrap().get(name).cloned().ok_or_else(|| {
+        anyhow::anyhow!(
+            "unknown controller backend {name:?}, expected one of {}",
+            backends().join(", ")
+        )
+    })?;
+    backend(target)
+}
+
+/// Connect to a target given as `<backend>:<target>`, i.e. `android:emulator-5554`.
+pub fn connect_spec(spec: &str) -> anyhow::Result<Controller> {
+    let (name, target) = spec
+        .split_once(':')
+        .ok_or_else(|| anyhow::anyhow!("invalid controller {spec:?}, expected backend:target"))?;
+    connect(name, target)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{ControllerTrait, Key};
+
+    struct DummyController(String);
+
+    impl ControllerTrait for DummyController {
+        fn screen_size(&self) -> (u32, u32) {
+            (1280, 720)
+        }
+
+        fn screencap_raw(&self) -> anyhow::Result<(u32, u32, Vec<u8>)> {
+            unimplemented!()
+        }
+
+        fn screencap(&self) -> anyhow::Result<image::DynamicImage> {
+            unimplemented!()
+        }
+
+        fn click(&self, _x: u32, _y: u32) -> anyhow::Result<()> {
+            unimplemented!()
+        }
+
+        fn swipe(
+            &self,
+            _start: (u32, u32),
+            _end: (i32, i32),
+            _duration: Duration,
+            _slope_in: f32,
+            _slope_out: f32,
+        ) -> anyhow::Result<()> {
+            unimplemented!()
+        }
+
+        fn press(&self, _key: Key) -> anyhow::Result<()> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        register_backend("dummy", |target| {
+            Ok(Controller::new(DummyController(target.to_string())))
+        });
+        assert!(backends().contains(&"android".to_string()));
+
+        let controller = connect_spec("dummy:a:b").unwrap();
+        assert_eq!(
+            controller.downcast_ref::<DummyController>().unwrap().0,
+            "a:b"
+        );
+        assert!(!controller.supports_keyevents());
+        assert!(!controller.supports_app_management());
+        // Wrapping it again keeps the controller it holds
+        let controller = Controller::new(controller);
+        assert!(controller.downcast_ref::<DummyController>().is_some());
+
+        assert!(connect("missing", "").is_err());
+        assert!(connect_spec("dummy").is_err());
+        assert!(unregister_backend("dummy"));
+        assert!(connect("dummy", "").is_err());
+    }
+}
@@ -0,0 +1,171 @@
+//! Mapping between the coordinates of the controller and the screen
+//!
+//! The controller works in physical pixels of the client area, the rendered
+//! content without the title bar and borders. The captured frame covers the
+//! whole window, and the input APIs take logical coordinates when the process is
+//! not DPI aware, so both are mapped through a [`CoordinateMapper`].
+
+use std::mem::size_of;
+
+use image::math::Rect;
+use windows::Win32::Foundation::{HWND, POINT, RECT};
+use windows::Win32::Graphics::Dwm::{DWMWA_EXTENDED_FRAME_BOUNDS, DwmGetWindowAttribute};
+use windows::Win32::Graphics::Gdi::ClientToScreen;
+use windows::Win32::UI::HiDpi::{
+    DPI_AWARENESS_UNAWARE, GetAwarenessFromDpiAwarenessContext, GetDpiForWindow,
+    GetThreadDpiAwarenessContext,
+};
+use windows::Win32::UI::WindowsAndMessaging::GetClientRect;
+
+/// DPI of a monitor at 100% scaling
+const DEFAULT_DPI: f32 = 96.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoordinateMapper {
+    /// Screen position of the top left of the client area, in input API units
+    pub client_origin: (i32, i32),
+    /// Where the client area is in the captured frame, in physical pixels
+    pub client_in_frame: Rect,
+    /// Physical pixels per input API unit, above 1.0 on a high-DPI monitor if
+    /// the process is not DPI aware
+    pub scale: f32,
+}
+
+impl CoordinateMapper {
+    /// Query the current position of the client area of `hwnd`.
+    pub fn from_hwnd(hwnd: HWND) -> anyhow::Result<Self> {
+        let scale = unsafe {
+            let awareness = GetAwarenessFromDpiAwarenessContext(GetThreadDpiAwarenessContext());
+            if awareness == DPI_AWARENESS_UNAWARE {
+                GetDpiForWindow(hwnd) as f32 / DEFAULT_DPI
+            } else {
+                1.0
+            }
+        };
+
+        let mut client_rect = RECT::default();
+        unsafe { GetClientRect(hwnd, &mut client_rect) }
+            .map_err(|e| anyhow::anyhow!("Failed to get client rect: {e}"))?;
+        let mut client_origin = POINT::default();
+        if !unsafe { ClientToScreen(hwnd, &mut client_origin) }.as_bool() {
+            anyhow::bail!("Failed to get client area position");
+        }
+
+        // The capture covers the extended frame bounds, which are always physical
+        let mut frame = RECT::default();
+        unsafe {
+            DwmGetWindowAttribute(
+                hwnd,
+                DWMWA_EXTENDED_FRAME_BOUNDS,
+                &mut frame as *mut RECT as *mut _,
+                size_of::<RECT>() as u32,
+            )
+        }
+        .map_err(|e| anyhow::anyhow!("Failed to get window frame bounds: {e}"))?;
+
+        Ok(Self::new(
+            (client_origin.x, client_origin.y),
+            (client_rect.right, client_rect.bottom),
+            (frame.left, frame.top),
+            scale,
+        ))
+    }
+
+    /// `client_origin` and `client_size` in input API units, `frame_origin` in
+    /// physical pixels, both on the screen.
+    pub fn new(
+        client_origin: (i32, i32),
+        client_size: (i32, i32),
+        frame_origin: (i32, i32),
+        scale: f32,
+    ) -> Self {
+        let physical = |v: i32| (v as f32 * scale).round() as i32;
+        let client_in_frame = Rect {
+            x: (physical(client_origin.0) - frame_origin.0).max(0) as u32,
+            y: (physical(client_origin.1) - frame_origin.1).max(0) as u32,
+            width: physical(client_size.0).max(0) as u32,
+            height: physical(client_size.1).max(0) as u32,
+        };
+        Self {
+            client_origin,
+            client_in_frame,
+            scale,
+        }
+    }
+
+    /// Client area position to the coordinates of the input APIs, relative to the
+    /// client area, as mouse messages take.
+    pub fn to_client(&self, x: i32, y: i32) -> (i32, i32) {
+        (
+            (x as f32 / self.scale).round() as i32,
+            (y as f32 / self.scale).round() as i32,
+        )
+    }
+
+    /// Client area position to the screen, as SendInput takes.
+    pub fn to_screen(&self, x: i32, y: i32) -> (i32, i32) {
+        let (x, y) = self.to_client(x, y);
+        (self.client_origin.0 + x, self.client_origin.1 + y)
+    }
+
+    /// Screen position to the client area, the inverse of [`Self::to_screen`].
+    pub fn from_screen(&self, x: i32, y: i32) -> (i32, i32) {
+        (
+            ((x - self.client_origin.0) as f32 * self.scale).round() as i32,
+            ((y - self.client_origin.1) as f32 * self.scale).round() as i32,
+        )
+    }
+
+    /// The client area of a captured frame of `width`x`height`, clamped to it.
+    pub fn client_rect_in(&self, width: u32, height: u32) -> Rect {
+        let x = self.client_in_frame.x.min(width);
+        let y = self.client_in_frame.y.min(height);
+        Rect {
+            x,
+            y,
+            width: self.client_in_frame.width.min(width - x),
+            height: self.client_in_frame.height.min(height - y),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_coordinate_mapper() {
+        // A 1280x720 client area under a 31px title bar, DPI aware
+        let mapper = CoordinateMapper::new((100, 231), (1280, 720), (100, 200), 1.0);
+        assert_eq!(mapper.to_screen(0, 0), (100, 231));
+        assert_eq!(mapper.to_screen(640, 360), (740, 591));
+        assert_eq!(mapper.to_client(640, 360), (640, 360));
+        assert_eq!(
+            mapper.client_rect_in(1280, 751),
+            Rect {
+                x: 0,
+                y: 31,
+                width: 1280,
+                height: 720
+            }
+        );
+
+        // The same window at 150% scaling without DPI awareness
+        let mapper = CoordinateMapper::new((100, 220), (1280, 720), (150, 300), 1.5);
+        assert_eq!(mapper.to_screen(0, 0), (100, 220));
+        assert_eq!(mapper.to_screen(1920, 1080), (1380, 940));
+        assert_eq!(mapper.to_client(300, 150), (200, 100));
+        assert_eq!(mapper.from_screen(1380, 940), (1920, 1080));
+        assert_eq!(
+            mapper.client_rect_in(1920, 1110),
+            Rect {
+                x: 0,
+                y: 30,
+                width: 1920,
+                height: 1080
+            }
+        );
+        // Clamped to a frame that is smaller than expected
+        assert_eq!(mapper.client_rect_in(1900, 1000).height, 970);
+    }
+}
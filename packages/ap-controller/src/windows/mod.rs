@@ -1,12 +1,30 @@
+mod mapper;
 pub mod ocr;
 
+pub use mapper::CoordinateMapper;
+
 use std::{sync::Arc, thread, time::Duration};
 
 use enigo::{Axis, Button, Coordinate, Enigo, Keyboard, Mouse, Settings};
 use parking_lot::Mutex;
 use tracing::info;
-use windows::Win32::Foundation::HWND;
-use windows::Win32::UI::WindowsAndMessaging::SetForegroundWindow;
+use windows::Win32::Foundation::{HWND, LPARAM, POINT, RECT, WPARAM};
+use windows::Win32::UI::HiDpi::{
+    DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2, SetProcessDpiAwarenessContext,
+};
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    GetAsyncKeyState, MAPVK_VK_TO_VSC, MapVirtualKeyW, VIRTUAL_KEY, VK_LBUTTON,
+};
+use windows::Win32::UI::Input::Pointer::{
+    InitializeTouchInjection, InjectTouchInput, POINTER_FLAG_DOWN, POINTER_FLAG_INCONTACT,
+    POINTER_FLAG_INRANGE, POINTER_FLAG_UP, POINTER_FLAG_UPDATE, POINTER_FLAGS, POINTER_INFO,
+    POINTER_TOUCH_INFO, TOUCH_FEEDBACK_DEFAULT,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    GetCursorPos, GetForegroundWindow, PT_TOUCH, PostMessageW, SetForegroundWindow,
+    TOUCH_MASK_CONTACTAREA, TOUCH_MASK_PRESSURE, WHEEL_DELTA, WM_CHAR, WM_KEYDOWN, WM_KEYUP,
+    WM_LBUTTONDBLCLK, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_MOUSEMOVE, WM_MOUSEWHEEL,
+};
 use windows_capture::{
     capture::{Context, GraphicsCaptureApiHandler},
     frame::Frame,
@@ -18,31 +36,63 @@ use windows_capture::{
     window::Window,
 };
 
-use crate::ControllerTrait;
+use crate::{
+    ControllerTrait,
+    capture::{CaptureProvider, FrameCache},
+    gesture::{FRAME_INTERVAL, Gesture},
+    swipe_progress,
+};
+
+/// Most contacts Windows injects at once
+const MAX_TOUCH_CONTACTS: u32 = 10;
+/// `wParam` of mouse messages while the left button is down
+const MK_LBUTTON: usize = 0x0001;
+const SWIPE_DELAY_MS: u32 = 5;
+
+/// How keyboard input reaches the window, see [`WindowsController::with_key_input_mode`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyInputMode {
+    /// Bring the window to the foreground and send the input with SendInput (enigo).
+    /// Every window accepts it, but it takes the keyboard focus.
+    #[default]
+    Foreground,
+    /// Post `WM_KEYDOWN`/`WM_KEYUP`/`WM_CHAR` to the window, leaving the focus
+    /// alone. Games reading raw input do not see it.
+    Background,
+}
 
-/// Frame data captured from the window
-struct FrameData {
-    image: image::RgbaImage,
-    width: u32,
-    height: u32,
+/// How mouse input reaches the window, see [`WindowsController::with_mouse_input_mode`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MouseInputMode {
+    /// Move the real cursor and click with SendInput (enigo)
+    #[default]
+    Cursor,
+    /// Post `WM_LBUTTONDOWN`, `WM_MOUSEMOVE` and so on to the window, leaving the
+    /// cursor and the focus alone so the machine stays usable. Games reading raw
+    /// input do not see it.
+    Background,
 }
 
 /// Shared state between capture thread and controller
 struct SharedCaptureState {
-    /// The latest captured frame (Arc to avoid cloning ~8MB image data)
-    latest_frame: Option<Arc<FrameData>>,
+    /// Size of the client area in the latest frame
+    frame_size: Option<(u32, u32)>,
     /// Whether capture should stop
     should_stop: bool,
     /// Capture error, if any
     error: Option<String>,
+    /// Set when a frame arrives in a different size than the previous one, i.e.
+    /// the window was resized, until [`ControllerTrait::resolution_changed`]
+    resized: bool,
 }
 
 impl Default for SharedCaptureState {
     fn default() -> Self {
         Self {
-            latest_frame: None,
+            frame_size: None,
             should_stop: false,
             error: None,
+            resized: false,
         }
     }
 }
@@ -51,6 +101,9 @@ impl Default for SharedCaptureState {
 #[derive(Clone)]
 struct CaptureContext {
     state: Arc<Mutex<SharedCaptureState>>,
+    frames: Arc<FrameCache>,
+    /// The captured window, as an integer since `HWND` is not `Send`
+    hwnd: usize,
 }
 
 /// Handler for windows-capture
@@ -73,9 +126,7 @@ impl GraphicsCaptureApiHandler for CaptureHandler {
         frame: &mut Frame,
         capture_control: InternalCaptureControl,
     ) -> Result<(), Self::Error> {
-        let mut state = self.context.state.lock();
-
-        if state.should_stop {
+        if self.context.state.lock().should_stop {
             capture_control.stop();
             return Ok(());
         }
@@ -85,16 +136,35 @@ impl GraphicsCaptureApiHandler for CaptureHandler {
 
         let mut buffer = frame.buffer()?;
         let buffer_data: Vec<u8> = buffer.as_nopadding_buffer()?.to_vec();
+        let Some(mut image) = image::RgbaImage::from_raw(width, height, buffer_data) else {
+            return Ok(());
+        };
 
-        if let Some(image) = image::RgbaImage::from_raw(width, height, buffer_data) {
-            // Always overwrite with the latest frame (Arc avoids cloning on read)
-            state.latest_frame = Some(Arc::new(FrameData {
-                image,
-                width,
-                height,
-            }));
+        // Crop to the client area, the frame covers the whole window
+        if let Ok(mapper) = CoordinateMapper::from_hwnd(HWND(self.context.hwnd as *mut _)) {
+            let rect = mapper.client_rect_in(width, height);
+            image = image::imageops::crop_imm(&image, rect.x, rect.y, rect.width, rect.height)
+                .to_image();
         }
 
+        {
+            let mut state = self.context.state.lock();
+            if let Some(prev) = state.frame_size
+                && prev != image.dimensions()
+            {
+                info!(
+                    "Window resized: {}x{} -> {}x{}",
+                    prev.0,
+                    prev.1,
+                    image.width(),
+                    image.height()
+                );
+                state.resized = true;
+            }
+            state.frame_size = Some(image.dimensions());
+        }
+        self.context.frames.push(image);
+
         Ok(())
     }
 
@@ -110,9 +180,29 @@ pub struct WindowsController {
     window_title: String,
     enigo: Arc<Mutex<Enigo>>,
     capture_state: Arc<Mutex<SharedCaptureState>>,
+    /// Client area frames from the capture thread
+    frames: Arc<FrameCache>,
+    key_input_mode: KeyInputMode,
+    mouse_input_mode: MouseInputMode,
 }
 
+// SAFETY: `Window` only wraps an HWND, which can be used from any thread,
+// everything else is behind a `Mutex`.
+unsafe impl Sync for WindowsController {}
+
 impl WindowsController {
+    /// Titles of all the windows that can be captured.
+    pub fn window_titles() -> anyhow::Result<Vec<String>> {
+        let windows =
+            Window::enumerate().map_err(|e| anyhow::anyhow!("Failed to enumerate windows: {e}"))?;
+
+        Ok(windows
+            .into_iter()
+            .filter_map(|w| w.title().ok())
+            .filter(|t| !t.is_empty())
+            .collect())
+    }
+
     /// Create a new controller by window title (exact match).
     pub fn from_window_title(title: &str) -> anyhow::Result<Self> {
         let windows =
@@ -132,22 +222,52 @@ impl WindowsController {
             .title()
             .map_err(|e| anyhow::anyhow!("Failed to get window title: {e}"))?;
 
+        // Work in physical pixels like the captured frames. This fails if the
+        // awareness is already set, in which case `CoordinateMapper` scales.
+        unsafe {
+            let _ = SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2);
+        }
+
         let enigo = Enigo::new(&Settings::default())
             .map_err(|e| anyhow::anyhow!("Failed to create enigo instance: {e}"))?;
 
         let capture_state = Arc::new(Mutex::new(SharedCaptureState::default()));
+        let frames = Arc::new(FrameCache::new());
 
         // Start capture and wait for first frame to ensure capture works
-        Self::start_capture_and_wait(&window, &capture_state)?;
+        Self::start_capture_and_wait(&window, &capture_state, &frames)?;
 
         Ok(Self {
             window,
             window_title,
             enigo: Arc::new(Mutex::new(enigo)),
             capture_state,
+            frames,
+            key_input_mode: KeyInputMode::default(),
+            mouse_input_mode: MouseInputMode::default(),
         })
     }
 
+    /// Send clicks, swipes and scrolls with `mode`.
+    pub fn with_mouse_input_mode(mut self, mode: MouseInputMode) -> Self {
+        self.mouse_input_mode = mode;
+        self
+    }
+
+    pub fn mouse_input_mode(&self) -> MouseInputMode {
+        self.mouse_input_mode
+    }
+
+    /// Send [`ControllerTrait::press`] and [`ControllerTrait::input_text`] with `mode`.
+    pub fn with_key_input_mode(mut self, mode: KeyInputMode) -> Self {
+        self.key_input_mode = mode;
+        self
+    }
+
+    pub fn key_input_mode(&self) -> KeyInputMode {
+        self.key_input_mode
+    }
+
     /// Enumerate all available windows
     pub fn enumerate_windows() -> anyhow::Result<Vec<(String, Window)>> {
         let windows =
@@ -169,6 +289,7 @@ impl WindowsController {
     fn start_capture_and_wait(
         window: &Window,
         capture_state: &Arc<Mutex<SharedCaptureState>>,
+        frames: &Arc<FrameCache>,
     ) -> anyhow::Result<()> {
         // Reset state
         {
@@ -178,6 +299,8 @@ impl WindowsController {
 
         let context = CaptureContext {
             state: capture_state.clone(),
+            frames: frames.clone(),
+            hwnd: window.as_raw_hwnd() as usize,
         };
         let window = window.clone();
 
@@ -209,7 +332,7 @@ impl WindowsController {
                 if let Some(err) = &state.error {
                     return Err(anyhow::anyhow!("Capture failed to start: {err}"));
                 }
-                if state.latest_frame.is_some() {
+                if state.frame_size.is_some() {
                     return Ok(());
                 }
             }
@@ -255,16 +378,26 @@ impl WindowsController {
         Ok((rect.left, rect.top))
     }
 
-    /// Convert local coordinates to screen coordinates
-    fn local_to_screen(&self, x: u32, y: u32) -> anyhow::Result<(i32, i32)> {
-        let (ox, oy) = self.window_position()?;
-        Ok((x as i32 + ox, y as i32 + oy))
+    /// The current mapping of local coordinates, which are in the client area.
+    pub fn coordinate_mapper(&self) -> anyhow::Result<CoordinateMapper> {
+        CoordinateMapper::from_hwnd(self.hwnd())
     }
 
-    /// Get a reference to the latest frame (cheap Arc::clone, no image data copy).
-    fn get_latest_frame(&self) -> Option<Arc<FrameData>> {
-        let state = self.capture_state.lock();
-        state.latest_frame.as_ref().map(Arc::clone)
+    /// Where the cursor is in the client area, which may be outside of it, and
+    /// whether the left button is down, to follow what the user does.
+    pub fn pointer(&self) -> anyhow::Result<((i32, i32), bool)> {
+        let mut point = POINT::default();
+        unsafe { GetCursorPos(&mut point) }
+            .map_err(|e| anyhow::anyhow!("Failed to get the cursor position: {e}"))?;
+        let position = self.coordinate_mapper()?.from_screen(point.x, point.y);
+        // The most significant bit is set while the button is down
+        let down = unsafe { GetAsyncKeyState(VK_LBUTTON.0 as i32) } < 0;
+        Ok((position, down))
+    }
+
+    /// Convert local coordinates to screen coordinates
+    fn local_to_screen(&self, x: u32, y: u32) -> anyhow::Result<(i32, i32)> {
+        Ok(self.coordinate_mapper()?.to_screen(x as i32, y as i32))
     }
 
     // ===== Windows-specific methods =====
@@ -276,6 +409,9 @@ impl WindowsController {
 
     /// Bring the target window to the foreground
     pub fn focus(&self) -> anyhow::Result<()> {
+        if unsafe { GetForegroundWindow() } == self.hwnd() {
+            return Ok(());
+        }
         unsafe {
             let _ = SetForegroundWindow(self.hwnd());
         }
@@ -328,10 +464,131 @@ impl WindowsController {
         Ok(())
     }
 
-    /// Scroll the mouse wheel
+    /// Press a key by posting `WM_KEYDOWN` and `WM_KEYUP` to the window, which
+    /// works without focusing it.
+    pub fn post_press(&self, key: enigo::Key) -> anyhow::Result<()> {
+        let vk = VIRTUAL_KEY::try_from(key)
+            .map_err(|e| anyhow::anyhow!("Failed to map {key:?} to a virtual key: {e}"))?;
+        let scan_code = unsafe { MapVirtualKeyW(vk.0 as u32, MAPVK_VK_TO_VSC) } as isize;
+        // Repeat count 1 and the scan code, key up also sets the previous state
+        // and transition bits
+        let down = LPARAM(1 | (scan_code << 16));
+        let up = LPARAM(down.0 | (1 << 30) | (1 << 31));
+        let hwnd = Some(self.hwnd());
+        unsafe { PostMessageW(hwnd, WM_KEYDOWN, WPARAM(vk.0 as usize), down) }
+            .map_err(|e| anyhow::anyhow!("Failed to post key down: {e}"))?;
+        thread::sleep(Duration::from_millis(30));
+        unsafe { PostMessageW(hwnd, WM_KEYUP, WPARAM(vk.0 as usize), up) }
+            .map_err(|e| anyhow::anyhow!("Failed to post key up: {e}"))
+    }
+
+    /// Type `text` by posting a `WM_CHAR` for each UTF-16 unit to the window.
+    pub fn post_text(&self, text: &str) -> anyhow::Result<()> {
+        let hwnd = Some(self.hwnd());
+        for unit in text.encode_utf16() {
+            unsafe { PostMessageW(hwnd, WM_CHAR, WPARAM(unit as usize), LPARAM(1)) }
+                .map_err(|e| anyhow::anyhow!("Failed to post char: {e}"))?;
+        }
+        Ok(())
+    }
+
+    /// Convert local coordinates to the client coordinates mouse messages take.
+    fn local_to_client(&self, x: i32, y: i32) -> anyhow::Result<(i32, i32)> {
+        Ok(self.coordinate_mapper()?.to_client(x, y))
+    }
+
+    /// Post a mouse message at the local coordinates to the window.
+    fn post_mouse(&self, msg: u32, wparam: usize, x: i32, y: i32) -> anyhow::Result<()> {
+        let (x, y) = self.local_to_client(x, y)?;
+        let lparam = ((y as u16 as isize) << 16) | (x as u16 as isize);
+        unsafe { PostMessageW(Some(self.hwnd()), msg, WPARAM(wparam), LPARAM(lparam)) }
+            .map_err(|e| anyhow::anyhow!("Failed to post mouse message: {e}"))
+    }
+
+    fn post_click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+        let (x, y) = (x as i32, y as i32);
+        self.post_mouse(WM_MOUSEMOVE, 0, x, y)?;
+        self.post_mouse(WM_LBUTTONDOWN, MK_LBUTTON, x, y)?;
+        thread::sleep(Duration::from_millis(30));
+        self.post_mouse(WM_LBUTTONUP, 0, x, y)
+    }
+
+    fn post_swipe(
+        &self,
+        start: (u32, u32),
+        end: (i32, i32),
+        duration: Duration,
+        slope_in: f32,
+        slope_out: f32,
+    ) -> anyhow::Result<()> {
+        let start = (start.0 as i32, start.1 as i32);
+        self.post_mouse(WM_MOUSEMOVE, 0, start.0, start.1)?;
+        self.post_mouse(WM_LBUTTONDOWN, MK_LBUTTON, start.0, start.1)?;
+
+        let lerp = |a: i32, b: i32, t: f32| a + ((b - a) as f32 * t) as i32;
+        let duration_ms = duration.as_millis() as u32;
+        for t in (SWIPE_DELAY_MS..duration_ms).step_by(SWIPE_DELAY_MS as usize) {
+            let progress = swipe_progress(slope_in, slope_out, t as f32 / duration_ms as f32);
+            let (x, y) = (
+                lerp(start.0, end.0, progress),
+                lerp(start.1, end.1, progress),
+            );
+            self.post_mouse(WM_MOUSEMOVE, MK_LBUTTON, x, y)?;
+            thread::sleep(Duration::from_millis(SWIPE_DELAY_MS as u64));
+        }
+
+        self.post_mouse(WM_MOUSEMOVE, MK_LBUTTON, end.0, end.1)?;
+        thread::sleep(Duration::from_millis(50));
+        self.post_mouse(WM_LBUTTONUP, 0, end.0, end.1)
+    }
+
+    /// Inject a touch frame, every contact at the screen position with `flags`.
+    fn inject_touch(&self, positions: &[(i32, i32)], flags: POINTER_FLAGS) -> anyhow::Result<()> {
+        let contacts = positions
+            .iter()
+            .enumerate()
+            .map(|(id, &(x, y))| POINTER_TOUCH_INFO {
+                pointerInfo: POINTER_INFO {
+                    pointerType: PT_TOUCH,
+                    pointerId: id as u32,
+                    pointerFlags: flags,
+                    ptPixelLocation: POINT { x, y },
+                    ..Default::default()
+                },
+                touchMask: TOUCH_MASK_CONTACTAREA | TOUCH_MASK_PRESSURE,
+                rcContact: RECT {
+                    left: x - 2,
+                    top: y - 2,
+                    right: x + 2,
+                    bottom: y + 2,
+                },
+                pressure: 32000,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        unsafe { InjectTouchInput(&contacts) }
+            .map_err(|e| anyhow::anyhow!("Failed to inject touch input: {e}"))
+    }
+
+    /// Scroll the mouse wheel, down if `delta` is positive
     pub fn scroll(&self, x: u32, y: u32, delta: i32) -> anyhow::Result<()> {
         let (screen_x, screen_y) = self.local_to_screen(x, y)?;
 
+        if self.mouse_input_mode == MouseInputMode::Background {
+            // Unlike other mouse messages, the wheel takes screen coordinates
+            let wheel = (-delta * WHEEL_DELTA as i32) as u16 as usize;
+            let lparam = ((screen_y as u16 as isize) << 16) | (screen_x as u16 as isize);
+            return unsafe {
+                PostMessageW(
+                    Some(self.hwnd()),
+                    WM_MOUSEWHEEL,
+                    WPARAM(wheel << 16),
+                    LPARAM(lparam),
+                )
+            }
+            .map_err(|e| anyhow::anyhow!("Failed to post mouse wheel: {e}"));
+        }
+
         let mut enigo = self.enigo.lock();
         enigo
             .move_mouse(screen_x, screen_y, Coordinate::Abs)
@@ -347,38 +604,57 @@ impl WindowsController {
     }
 }
 
-impl ControllerTrait for WindowsController {
-    fn screen_size(&self) -> (u32, u32) {
-        self.get_latest_frame()
-            .map(|f| (f.width, f.height))
-            .unwrap_or((1920, 1080))
+impl CaptureProvider for WindowsController {
+    fn frames(&self) -> &FrameCache {
+        &self.frames
     }
 
-    fn screencap_raw(&self) -> anyhow::Result<(u32, u32, Vec<u8>)> {
+    fn is_streaming(&self) -> bool {
+        self.capture_error().is_none()
+    }
+
+    /// The latest frame of the stream, which is the current screen until the
+    /// window changes.
+    fn latest_frame(&self) -> anyhow::Result<crate::Frame> {
         if let Some(err) = self.capture_error() {
             return Err(anyhow::anyhow!("Capture error: {err}"));
         }
+        self.frames
+            .latest()
+            .ok_or_else(|| anyhow::anyhow!("No frame available"))
+    }
+}
 
-        let frame = self
-            .get_latest_frame()
-            .ok_or_else(|| anyhow::anyhow!("No frame available"))?;
+impl ControllerTrait for WindowsController {
+    /// Size of the client area
+    fn screen_size(&self) -> (u32, u32) {
+        self.capture_state.lock().frame_size.unwrap_or((1920, 1080))
+    }
 
-        Ok((frame.width, frame.height, frame.image.clone().into_raw()))
+    fn resolution_changed(&self) -> Option<(u32, u32)> {
+        let resized = std::mem::take(&mut self.capture_state.lock().resized);
+        resized.then(|| self.screen_size())
     }
 
-    fn screencap(&self) -> anyhow::Result<image::DynamicImage> {
-        if let Some(err) = self.capture_error() {
-            return Err(anyhow::anyhow!("Capture error: {err}"));
-        }
+    fn screencap_raw(&self) -> anyhow::Result<(u32, u32, Vec<u8>)> {
+        let image = self.latest_frame()?.into_image();
+        Ok((image.width(), image.height(), image.into_raw()))
+    }
 
-        let frame = self
-            .get_latest_frame()
-            .ok_or_else(|| anyhow::anyhow!("No frame available"))?;
+    fn screencap(&self) -> anyhow::Result<image::DynamicImage> {
+        Ok(image::DynamicImage::ImageRgba8(
+            self.latest_frame()?.into_image(),
+        ))
+    }
 
-        Ok(image::DynamicImage::ImageRgba8(frame.image.clone()))
+    fn capture_provider(&self) -> Option<&dyn CaptureProvider> {
+        Some(self)
     }
 
     fn click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+        if self.mouse_input_mode == MouseInputMode::Background {
+            return self.post_click(x, y);
+        }
         let (screen_x, screen_y) = self.local_to_screen(x, y)?;
 
         let mut enigo = self.enigo.lock();
@@ -395,6 +671,62 @@ impl ControllerTrait for WindowsController {
         Ok(())
     }
 
+    fn long_press(&self, x: u32, y: u32, duration: Duration) -> anyhow::Result<()> {
+        if self.mouse_input_mode == MouseInputMode::Background {
+            let (x, y) = (x as i32, y as i32);
+            self.post_mouse(WM_MOUSEMOVE, 0, x, y)?;
+            self.post_mouse(WM_LBUTTONDOWN, MK_LBUTTON, x, y)?;
+            thread::sleep(duration);
+            return self.post_mouse(WM_LBUTTONUP, 0, x, y);
+        }
+        let (screen_x, screen_y) = self.local_to_screen(x, y)?;
+
+        let mut enigo = self.enigo.lock();
+        enigo
+            .move_mouse(screen_x, screen_y, Coordinate::Abs)
+            .map_err(|e| anyhow::anyhow!("Failed to move mouse: {e}"))?;
+
+        thread::sleep(Duration::from_millis(10));
+
+        enigo
+            .button(Button::Left, enigo::Direction::Press)
+            .map_err(|e| anyhow::anyhow!("Failed to press mouse: {e}"))?;
+        thread::sleep(duration);
+        enigo
+            .button(Button::Left, enigo::Direction::Release)
+            .map_err(|e| anyhow::anyhow!("Failed to release mouse: {e}"))?;
+
+        Ok(())
+    }
+
+    fn double_click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+        if self.mouse_input_mode == MouseInputMode::Background {
+            // The system turns the second down into a double click only for real input
+            self.post_click(x, y)?;
+            let (x, y) = (x as i32, y as i32);
+            self.post_mouse(WM_LBUTTONDBLCLK, MK_LBUTTON, x, y)?;
+            return self.post_mouse(WM_LBUTTONUP, 0, x, y);
+        }
+        let (screen_x, screen_y) = self.local_to_screen(x, y)?;
+
+        let mut enigo = self.enigo.lock();
+        enigo
+            .move_mouse(screen_x, screen_y, Coordinate::Abs)
+            .map_err(|e| anyhow::anyhow!("Failed to move mouse: {e}"))?;
+
+        thread::sleep(Duration::from_millis(10));
+
+        for _ in 0..2 {
+            enigo
+                .button(Button::Left, enigo::Direction::Click)
+                .map_err(|e| anyhow::anyhow!("Failed to click: {e}"))?;
+            // Well within the system double click time
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        Ok(())
+    }
+
     fn swipe(
         &self,
         start: (u32, u32),
@@ -403,10 +735,12 @@ impl ControllerTrait for WindowsController {
         slope_in: f32,
         slope_out: f32,
     ) -> anyhow::Result<()> {
-        const SWIPE_DELAY_MS: u32 = 5;
+        if self.mouse_input_mode == MouseInputMode::Background {
+            return self.post_swipe(start, end, duration, slope_in, slope_out);
+        }
 
-        let (ox, oy) = self.window_position()?;
-        let (start_screen_x, start_screen_y) = (start.0 as i32 + ox, start.1 as i32 + oy);
+        let mapper = self.coordinate_mapper()?;
+        let (start_screen_x, start_screen_y) = mapper.to_screen(start.0 as i32, start.1 as i32);
 
         let mut enigo = self.enigo.lock();
 
@@ -420,32 +754,26 @@ impl ControllerTrait for WindowsController {
             .button(Button::Left, enigo::Direction::Press)
             .map_err(|e| anyhow::anyhow!("Failed to press mouse button: {e}"))?;
 
-        let cubic_spline = |slope_0: f32, slope_1: f32, t: f32| -> f32 {
-            let a = slope_0;
-            let b = -(2.0 * slope_0 + slope_1 - 3.0);
-            let c = -(-slope_0 - slope_1 + 2.0);
-            a * t + b * t.powi(2) + c * t.powi(3)
-        };
-
         let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
 
         let duration_ms = duration.as_millis() as u32;
         for t in (SWIPE_DELAY_MS..duration_ms).step_by(SWIPE_DELAY_MS as usize) {
-            let progress = cubic_spline(slope_in, slope_out, t as f32 / duration_ms as f32);
-            let progress = progress.clamp(0.0, 1.0);
+            let progress = swipe_progress(slope_in, slope_out, t as f32 / duration_ms as f32);
 
             let cur_x = lerp(start.0 as f32, end.0 as f32, progress) as i32;
             let cur_y = lerp(start.1 as f32, end.1 as f32, progress) as i32;
+            let (cur_x, cur_y) = mapper.to_screen(cur_x, cur_y);
 
             enigo
-                .move_mouse(cur_x + ox, cur_y + oy, Coordinate::Abs)
+                .move_mouse(cur_x, cur_y, Coordinate::Abs)
                 .map_err(|e| anyhow::anyhow!("Failed to move mouse during swipe: {e}"))?;
 
             thread::sleep(Duration::from_millis(SWIPE_DELAY_MS as u64));
         }
 
+        let (end_x, end_y) = mapper.to_screen(end.0, end.1);
         enigo
-            .move_mouse(end.0 + ox, end.1 + oy, Coordinate::Abs)
+            .move_mouse(end_x, end_y, Coordinate::Abs)
             .map_err(|e| anyhow::anyhow!("Failed to move mouse to end position: {e}"))?;
 
         thread::sleep(Duration::from_millis(50));
@@ -457,11 +785,55 @@ impl ControllerTrait for WindowsController {
         Ok(())
     }
 
+    /// Synthesized with touch injection, which goes to whatever window is at the
+    /// position, so the target window should be in the foreground.
+    fn multi_touch(&self, gesture: Gesture) -> anyhow::Result<()> {
+        let contacts = gesture.contacts.len() as u32;
+        if contacts > MAX_TOUCH_CONTACTS {
+            anyhow::bail!("{contacts} contacts, at most {MAX_TOUCH_CONTACTS} are supported");
+        }
+        unsafe { InitializeTouchInjection(MAX_TOUCH_CONTACTS, TOUCH_FEEDBACK_DEFAULT) }
+            .map_err(|e| anyhow::anyhow!("Failed to initialize touch injection: {e}"))?;
+
+        let mapper = self.coordinate_mapper()?;
+        let frames = gesture
+            .frames(FRAME_INTERVAL)?
+            .into_iter()
+            .map(|frame| {
+                frame
+                    .into_iter()
+                    .map(|(x, y)| mapper.to_screen(x, y))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let in_contact = POINTER_FLAG_INRANGE | POINTER_FLAG_INCONTACT;
+        self.inject_touch(&frames[0], POINTER_FLAG_DOWN | in_contact)?;
+        for frame in &frames[1..] {
+            thread::sleep(FRAME_INTERVAL);
+            self.inject_touch(frame, POINTER_FLAG_UPDATE | in_contact)?;
+        }
+        self.inject_touch(frames.last().unwrap(), POINTER_FLAG_UP)
+    }
+
+    fn input_text(&self, text: &str) -> anyhow::Result<()> {
+        match self.key_input_mode {
+            KeyInputMode::Foreground => {
+                self.focus()?;
+                let mut enigo = self.enigo.lock();
+                enigo
+                    .text(text)
+                    .map_err(|e| anyhow::anyhow!("Failed to input text: {e}"))
+            }
+            KeyInputMode::Background => self.post_text(text),
+        }
+    }
+
     fn press(&self, key: enigo::Key) -> anyhow::Result<()> {
-        let mut enigo = self.enigo.lock();
-        enigo
-            .key(key, enigo::Direction::Press)
-            .map_err(|e| anyhow::anyhow!("Failed to press key: {e}"))
+        match self.key_input_mode {
+            KeyInputMode::Foreground => self.focus_press(key),
+            KeyInputMode::Background => self.post_press(key),
+        }
     }
 }
 
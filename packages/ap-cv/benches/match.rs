@@ -3,6 +3,7 @@ use ap_cv::{
     matcher::MatcherOptions,
 };
 use criterion::{Criterion, criterion_group, criterion_main};
+use image::{ImageBuffer, Luma};
 use imageproc::template_matching::find_extremes;
 
 fn bench_template_matching(c: &mut Criterion) {
@@ -52,5 +53,30 @@ fn bench_template_matching(c: &mut Criterion) {
     }
 }
 
-criterion_group!(benches, bench_template_matching);
+/// [`find_matches`] on a 1080p result map on one thread and on all of them.
+fn bench_find_matches_1080p(c: &mut Criterion) {
+    let method = MatchTemplateMethod::CrossCorrelationNormed;
+    // Noise with a few peaks, like the result of a template on a busy screen
+    let res = ImageBuffer::<Luma<f32>, Vec<f32>>::from_fn(1920, 1080, |x, y| {
+        let noise = ((x * 7919 + y * 104729) % 1000) as f32 / 1000.0 * 0.7;
+        match (x % 240, y % 135) {
+            (120, 60) => Luma([0.99]),
+            _ => Luma([noise]),
+        }
+    });
+    let single = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+
+    let mut group = c.benchmark_group("find_matches_1080p");
+    group.bench_function("sequential", |b| {
+        b.iter(|| single.install(|| find_matches(&res, 64, 64, method, 0.9)));
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| find_matches(&res, 64, 64, method, 0.9));
+    });
+}
+
+criterion_group!(benches, bench_template_matching, bench_find_matches_1080p);
 criterion_main!(benches);
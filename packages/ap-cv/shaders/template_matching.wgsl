@@ -3,6 +3,8 @@ struct Uniforms {
     input_height: u32,
     template_width: u32,
     template_height: u32,
+    // Interleaved f32 channels of each pixel, combined into one score
+    channels: u32,
 };
 
 @group(0)
@@ -27,12 +29,17 @@ var<uniform> uniforms: Uniforms;
 fn main_sqdiff(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var x = global_id.x;
     var y = global_id.y;
+    // The workgroups overhang the result, which would write into the next row
+    if (x + uniforms.template_width > uniforms.input_width || y + uniforms.template_height > uniforms.input_height) {
+        return;
+    }
 
     var input_width = uniforms.input_width;
     var input_height = uniforms.input_height;
 
     var template_width = uniforms.template_width;
     var template_height = uniforms.template_height;
+    var channels = uniforms.channels;
 
     var match_width = min(template_width, input_width - x);
     var match_height = min(template_height, input_height - y);
@@ -40,15 +47,18 @@ fn main_sqdiff(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var total_sum = 0.0;
     for (var i = 0u; i < match_width; i++) {
         for (var j = 0u; j < match_height; j++) {
-            var input_idx = (y + j) * input_width + (i + x);
-            var template_idx = j * template_width + i;
+            var input_idx = ((y + j) * input_width + (i + x)) * channels;
+            var template_idx = (j * template_width + i) * channels;
 
-            var input_val = input_buf[input_idx];
-            var template_val = template_buf[template_idx];
+            for (var c = 0u; c < channels; c++) {
+                var input_val = input_buf[input_idx + c];
+                var template_val = template_buf[template_idx + c];
 
-            var sqdiff = pow(input_val - template_val, 2.0);
+                var diff = input_val - template_val;
+                var sqdiff = diff * diff;
 
-            total_sum += sqdiff;
+                total_sum += sqdiff;
+            }
         }
     }
 
@@ -62,12 +72,17 @@ fn main_sqdiff(@builtin(global_invocation_id) global_id: vec3<u32>) {
 fn main_sqdiff_normed(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var x = global_id.x;
     var y = global_id.y;
+    // The workgroups overhang the result, which would write into the next row
+    if (x + uniforms.template_width > uniforms.input_width || y + uniforms.template_height > uniforms.input_height) {
+        return;
+    }
 
     var input_width = uniforms.input_width;
     var input_height = uniforms.input_height;
 
     var template_width = uniforms.template_width;
     var template_height = uniforms.template_height;
+    var channels = uniforms.channels;
 
     var match_width = min(template_width, input_width - x);
     var match_height = min(template_height, input_height - y);
@@ -77,17 +92,20 @@ fn main_sqdiff_normed(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var template_sq_sum = 0.0;
     for (var i = 0u; i < match_width; i++) {
         for (var j = 0u; j < match_height; j++) {
-            var input_idx = (y + j) * input_width + (i + x);
-            var template_idx = j * template_width + i;
+            var input_idx = ((y + j) * input_width + (i + x)) * channels;
+            var template_idx = (j * template_width + i) * channels;
 
-            var input_val = input_buf[input_idx];
-            var template_val = template_buf[template_idx];
+            for (var c = 0u; c < channels; c++) {
+                var input_val = input_buf[input_idx + c];
+                var template_val = template_buf[template_idx + c];
 
-            var sqdiff = pow(input_val - template_val, 2.0);
+                var diff = input_val - template_val;
+                var sqdiff = diff * diff;
 
-            total_sum += sqdiff;
-            input_sq_sum += input_val * input_val;
-            template_sq_sum += template_val * template_val;
+                total_sum += sqdiff;
+                input_sq_sum += input_val * input_val;
+                template_sq_sum += template_val * template_val;
+            }
         }
     }
 
@@ -101,12 +119,17 @@ fn main_sqdiff_normed(@builtin(global_invocation_id) global_id: vec3<u32>) {
 fn main_ccorr(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var x = global_id.x;
     var y = global_id.y;
+    // The workgroups overhang the result, which would write into the next row
+    if (x + uniforms.template_width > uniforms.input_width || y + uniforms.template_height > uniforms.input_height) {
+        return;
+    }
 
     var input_width = uniforms.input_width;
     var input_height = uniforms.input_height;
 
     var template_width = uniforms.template_width;
     var template_height = uniforms.template_height;
+    var channels = uniforms.channels;
 
     var match_width = min(template_width, input_width - x);
     var match_height = min(template_height, input_height - y);
@@ -114,15 +137,17 @@ fn main_ccorr(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var total_sum = 0.0;
     for (var i = 0u; i < match_width; i++) {
         for (var j = 0u; j < match_height; j++) {
-            var input_idx = (y + j) * input_width + (i + x);
-            var template_idx = j * template_width + i;
+            var input_idx = ((y + j) * input_width + (i + x)) * channels;
+            var template_idx = (j * template_width + i) * channels;
 
-            var input_val = input_buf[input_idx];
-            var template_val = template_buf[template_idx];
+            for (var c = 0u; c < channels; c++) {
+                var input_val = input_buf[input_idx + c];
+                var template_val = template_buf[template_idx + c];
 
-            var cc = input_val * template_val;
+                var cc = input_val * template_val;
 
-            total_sum += cc;
+                total_sum += cc;
+            }
         }
     }
 
@@ -136,12 +161,17 @@ fn main_ccorr(@builtin(global_invocation_id) global_id: vec3<u32>) {
 fn main_ccorr_normed(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var x = global_id.x;
     var y = global_id.y;
+    // The workgroups overhang the result, which would write into the next row
+    if (x + uniforms.template_width > uniforms.input_width || y + uniforms.template_height > uniforms.input_height) {
+        return;
+    }
 
     var input_width = uniforms.input_width;
     var input_height = uniforms.input_height;
 
     var template_width = uniforms.template_width;
     var template_height = uniforms.template_height;
+    var channels = uniforms.channels;
 
     var match_width = min(template_width, input_width - x);
     var match_height = min(template_height, input_height - y);
@@ -151,17 +181,19 @@ fn main_ccorr_normed(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var template_sq_sum = 0.0;
     for (var i = 0u; i < match_width; i++) {
         for (var j = 0u; j < match_height; j++) {
-            var input_idx = (y + j) * input_width + (i + x);
-            var template_idx = j * template_width + i;
+            var input_idx = ((y + j) * input_width + (i + x)) * channels;
+            var template_idx = (j * template_width + i) * channels;
 
-            var input_val = input_buf[input_idx];
-            var template_val = template_buf[template_idx];
+            for (var c = 0u; c < channels; c++) {
+                var input_val = input_buf[input_idx + c];
+                var template_val = template_buf[template_idx + c];
 
-            var cc = input_val * template_val;
-            input_sq_sum += input_val * input_val;
-            template_sq_sum += template_val * template_val;
+                var cc = input_val * template_val;
+                input_sq_sum += input_val * input_val;
+                template_sq_sum += template_val * template_val;
 
-            total_sum += cc;
+                total_sum += cc;
+            }
         }
     }
 
@@ -175,12 +207,17 @@ fn main_ccorr_normed(@builtin(global_invocation_id) global_id: vec3<u32>) {
 fn main_ccoeff(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var x = global_id.x;
     var y = global_id.y;
+    // The workgroups overhang the result, which would write into the next row
+    if (x + uniforms.template_width > uniforms.input_width || y + uniforms.template_height > uniforms.input_height) {
+        return;
+    }
 
     var input_width = uniforms.input_width;
     var input_height = uniforms.input_height;
 
     var template_width = uniforms.template_width;
     var template_height = uniforms.template_height;
+    var channels = uniforms.channels;
 
     var match_width = min(template_width, input_width - x);
     var match_height = min(template_height, input_height - y);
@@ -188,15 +225,17 @@ fn main_ccoeff(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var total_sum = 0.0;
     for (var i = 0u; i < match_width; i++) {
         for (var j = 0u; j < match_height; j++) {
-            var input_idx = (y + j) * input_width + (i + x);
-            var template_idx = j * template_width + i;
+            var input_idx = ((y + j) * input_width + (i + x)) * channels;
+            var template_idx = (j * template_width + i) * channels;
 
-            var input_val = input_buf[input_idx];
-            var template_val = template_buf[template_idx];
+            for (var c = 0u; c < channels; c++) {
+                var input_val = input_buf[input_idx + c];
+                var template_val = template_buf[template_idx + c];
 
-            var cc = input_val * template_val;
+                var cc = input_val * template_val;
 
-            total_sum += cc;
+                total_sum += cc;
+            }
         }
     }
 
@@ -210,12 +249,17 @@ fn main_ccoeff(@builtin(global_invocation_id) global_id: vec3<u32>) {
 fn main_ccoeff_normed(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var x = global_id.x;
     var y = global_id.y;
+    // The workgroups overhang the result, which would write into the next row
+    if (x + uniforms.template_width > uniforms.input_width || y + uniforms.template_height > uniforms.input_height) {
+        return;
+    }
 
     var input_width = uniforms.input_width;
     var input_height = uniforms.input_height;
 
     var template_width = uniforms.template_width;
     var template_height = uniforms.template_height;
+    var channels = uniforms.channels;
 
     var match_width = min(template_width, input_width - x);
     var match_height = min(template_height, input_height - y);
@@ -225,17 +269,19 @@ fn main_ccoeff_normed(@builtin(global_invocation_id) global_id: vec3<u32>) {
     var total_sum = 0.0;
     for (var i = 0u; i < match_width; i++) {
         for (var j = 0u; j < match_height; j++) {
-            var input_idx = (y + j) * input_width + (i + x);
-            var template_idx = j * template_width + i;
+            var input_idx = ((y + j) * input_width + (i + x)) * channels;
+            var template_idx = (j * template_width + i) * channels;
 
-            var input_val = input_buf[input_idx];
-            var template_val = template_buf[template_idx];
+            for (var c = 0u; c < channels; c++) {
+                var input_val = input_buf[input_idx + c];
+                var template_val = template_buf[template_idx + c];
 
-            var cc = input_val * template_val;
+                var cc = input_val * template_val;
 
-            total_sum += cc;
-            sum_squared_i += input_val * input_val;
-            sum_squared_template += template_val * template_val;
+                total_sum += cc;
+                sum_squared_i += input_val * input_val;
+                sum_squared_template += template_val * template_val;
+            }
         }
     }
 
@@ -0,0 +1,236 @@
+//! Reuse the results of matching on a screen that did not change
+//!
+//! [`MatchCache`]: An LRU of match results keyed by a perceptual hash of the image,
+//! the template and the options.
+
+use std::{
+    collections::VecDeque,
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use image::{DynamicImage, GenericImageView};
+
+use crate::{
+    core::template_matching::Match,
+    diff::downsample,
+    matcher::{MatcherOptions, SingleMatcher},
+};
+
+/// Width the image is shrunk to before hashing
+const HASH_WIDTH: u32 = 160;
+/// Low bits of the luma dropped before hashing, the noise of the captures
+const HASH_NOISE_BITS: u32 = 3;
+
+/// A hash of `image` that stays the same through capture noise and compression
+/// artifacts, computed on a shrunk grayscale copy with the low bits dropped.
+pub fn perceptual_hash(image: &DynamicImage) -> u64 {
+    let small = downsample(image, HASH_WIDTH);
+    let quantized = small
+        .as_raw()
+        .iter()
+        .map(|v| v >> HASH_NOISE_BITS)
+        .collect::<Vec<_>>();
+    let mut hasher = DefaultHasher::new();
+    image.dimensions().hash(&mut hasher);
+    quantized.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// What a match is cached by: the screen, the template and the options
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchKey {
+    image: u64,
+    template: u64,
+    options: u64,
+}
+
+impl MatchKey {
+    pub fn new(image: &DynamicImage, template: &DynamicImage, options: &MatcherOptions) -> Self {
+        Self::with_image_hash(perceptual_hash(image), template, options)
+    }
+
+    /// The key with the [`perceptual_hash`] of the image already computed, to
+    /// hash the screen once for many templates.
+    pub fn with_image_hash(
+        image_hash: u64,
+        template: &DynamicImage,
+        options: &MatcherOptions,
+    ) -> Self {
+        // Templates are exact, they come from files
+        let mut hasher = DefaultHasher::new();
+        template.dimensions().hash(&mut hasher);
+        template.color().hash(&mut hasher);
+        template.as_bytes().hash(&mut hasher);
+        let template = hasher.finish();
+
+        // The options are plain data, their debug output covers all of them
+        let mut hasher = DefaultHasher::new();
+        format!("{options:?}").hash(&mut hasher);
+        let options = hasher.finish();
+
+        Self {
+            image: image_hash,
+            template,
+            options,
+        }
+    }
+}
+
+/// An LRU of the results of [`SingleMatcher::match_image`], so a template is not
+/// matched again on a screen that did not change between two steps.
+///
+/// A capacity of 0 disables it.
+#[derive(Debug)]
+pub struct MatchCache {
+    capacity: usize,
+    /// The most recently used first
+    entries: VecDeque<(MatchKey, Option<Match>)>,
+    hits: u64,
+    misses: u64,
+}
+
+impl Default for MatchCache {
+    fn default() -> Self {
+        Self::new(64)
+    }
+}
+
+impl MatchCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Change the capacity, dropping the least recently used results over it.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.entries.truncate(capacity);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Lookups that found a result and ones that did not, since the creation.
+    pub fn stats(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+
+    /// The cached result of `key`, `None` if there is none. The result itself is
+    /// `None` if the template was not found.
+    pub fn get(&mut self, key: &MatchKey) -> Option<Option<Match>> {
+        let Some(i) = self.entries.iter().position(|(k, _)| k == key) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        let entry = self.entries.remove(i).unwrap();
+        let res = entry.1;
+        self.entries.push_front(entry);
+        Some(res)
+    }
+
+    pub fn insert(&mut self, key: MatchKey, result: Option<Match>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.entries.retain(|(k, _)| *k != key);
+        self.entries.push_front((key, result));
+        self.entries.truncate(self.capacity);
+    }
+
+    /// [`SingleMatcher::match_image`] through the cache.
+    pub fn match_image(
+        &mut self,
+        image: &DynamicImage,
+        template: &DynamicImage,
+        options: &MatcherOptions,
+    ) -> Option<Match> {
+        let key = MatchKey::new(image, template, options);
+        if let Some(res) = self.get(&key) {
+            return res;
+        }
+        let res = SingleMatcher::match_image(image, template, options).result;
+        self.insert(key, res);
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    fn screen(offset: u32, noise: u8) -> DynamicImage {
+        let mut image = RgbImage::from_pixel(64, 48, Rgb([96 + noise, 96 + noise, 96 + noise]));
+        for y in 10..20 {
+            for x in offset..offset + 12 {
+                image.put_pixel(x, y, Rgb([240, 200, 40]));
+            }
+        }
+        DynamicImage::ImageRgb8(image)
+    }
+
+    #[test]
+    fn test_match_cache() {
+        let template = screen(20, 0).crop_imm(16, 6, 20, 18);
+        let options = MatcherOptions::default();
+        let mut cache = MatchCache::new(2);
+
+        let found = cache
+            .match_image(&screen(20, 0), &template, &options)
+            .unwrap();
+        assert_eq!((found.rect.x, found.rect.y), (16, 6));
+        assert_eq!(cache.stats(), (0, 1));
+
+        // A little noise is the same screen
+        let again = cache
+            .match_image(&screen(20, 1), &template, &options)
+            .unwrap();
+        assert_eq!(again.rect, found.rect);
+        assert_eq!(cache.stats(), (1, 1));
+
+        // The button moved
+        let moved = cache
+            .match_image(&screen(30, 0), &template, &options)
+            .unwrap();
+        assert_eq!((moved.rect.x, moved.rect.y), (26, 6));
+        assert_eq!(cache.stats(), (1, 2));
+
+        // Other options are another match, which evicts the least recent one
+        cache.match_image(&screen(30, 0), &template, &options.with_threshold(0.5));
+        assert_eq!(cache.len(), 2);
+        assert!(
+            cache
+                .get(&MatchKey::new(&screen(20, 0), &template, &options))
+                .is_none()
+        );
+        assert!(
+            cache
+                .get(&MatchKey::new(&screen(30, 0), &template, &options))
+                .is_some()
+        );
+
+        cache.set_capacity(0);
+        assert!(cache.is_empty());
+        cache.match_image(&screen(20, 0), &template, &options);
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,105 @@
+//! Pixel color checks, the cheapest way to tell what is on the screen
+//!
+//! [`check_color`]: Whether a pixel is approximately a color.
+//! [`check_region_color`]: Whether the average color of a region is approximately a color.
+
+use image::{DynamicImage, GenericImageView, Rgb, math::Rect};
+
+/// Parse a `#RRGGBB` color, the `#` is optional.
+pub fn parse_hex(hex: &str) -> Option<Rgb<u8>> {
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(Rgb([channel(0)?, channel(2)?, channel(4)?]))
+}
+
+/// The largest difference between a channel of `a` and the same one of `b`.
+pub fn color_distance(a: Rgb<u8>, b: Rgb<u8>) -> u8 {
+    a.0.iter()
+        .zip(b.0)
+        .map(|(a, b)| a.abs_diff(b))
+        .max()
+        .unwrap_or(0)
+}
+
+/// Whether the pixel at `point` is `expected`, each channel within `tolerance`.
+/// A point outside of `image` never is.
+pub fn check_color(
+    image: &DynamicImage,
+    point: (u32, u32),
+    expected: Rgb<u8>,
+    tolerance: u8,
+) -> bool {
+    let (x, y) = point;
+    if !image.in_bounds(x, y) {
+        return false;
+    }
+    let [r, g, b, _] = image.get_pixel(x, y).0;
+    color_distance(Rgb([r, g, b]), expected) <= tolerance
+}
+
+/// The average color of `rect` of `image`, `None` if it is empty or outside of it.
+pub fn average_color(image: &DynamicImage, rect: Rect) -> Option<Rgb<u8>> {
+    let x1 = (rect.x + rect.width).min(image.width());
+    let y1 = (rect.y + rect.height).min(image.height());
+    if rect.x >= x1 || rect.y >= y1 {
+        return None;
+    }
+    let mut sum = [0u64; 3];
+    for y in rect.y..y1 {
+        for x in rect.x..x1 {
+            let pixel = image.get_pixel(x, y).0;
+            for (sum, value) in sum.iter_mut().zip(pixel) {
+                *sum += value as u64;
+            }
+        }
+    }
+    let count = ((x1 - rect.x) * (y1 - rect.y)) as u64;
+    Some(Rgb(sum.map(|sum| (sum / count) as u8)))
+}
+
+/// Like [`check_color`] on the average color of `rect`, which does not mind noise
+/// or a slight misalignment as much as a single pixel.
+pub fn check_region_color(
+    image: &DynamicImage,
+    rect: Rect,
+    expected: Rgb<u8>,
+    tolerance: u8,
+) -> bool {
+    average_color(image, rect).is_some_and(|color| color_distance(color, expected) <= tolerance)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    #[test]
+    fn test_check_color() {
+        assert_eq!(parse_hex("#ff8000"), Some(Rgb([255, 128, 0])));
+        assert_eq!(parse_hex("FF8000"), Some(Rgb([255, 128, 0])));
+        assert_eq!(parse_hex("#ff80"), None);
+        assert_eq!(parse_hex("#gg8000"), None);
+
+        let mut image = RgbImage::from_pixel(10, 10, Rgb([200, 40, 40]));
+        image.put_pixel(0, 0, Rgb([0, 0, 0]));
+        let image = DynamicImage::ImageRgb8(image);
+        assert!(check_color(&image, (5, 5), Rgb([205, 35, 40]), 5));
+        assert!(!check_color(&image, (5, 5), Rgb([205, 35, 40]), 4));
+        assert!(!check_color(&image, (10, 5), Rgb([200, 40, 40]), 255));
+
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            width: 2,
+            height: 2,
+        };
+        assert_eq!(average_color(&image, rect), Some(Rgb([150, 30, 30])));
+        assert!(check_region_color(&image, rect, Rgb([150, 30, 30]), 0));
+        let outside = Rect { x: 10, ..rect };
+        assert!(!check_region_color(&image, outside, Rgb([0, 0, 0]), 255));
+    }
+}
@@ -1,9 +1,16 @@
 //! Template matching implementation based on compute shader through wgpu.
 //!
-//! Currently only supports grayscale image.
+//! Grayscale images are matched by [`match_template`], RGB images by
+//! [`match_template_rgb`] which combines the channels into one score, so shapes
+//! only differing in color can be told apart.
 use std::{
+    borrow::Cow,
     fmt::Display,
-    sync::{Arc, Mutex, OnceLock},
+    str::FromStr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
 };
 
 #[cfg(feature = "profiling")]
@@ -17,7 +24,7 @@ static PUFFIN_GPU_PROFILER: std::sync::LazyLock<Mutex<puffin::GlobalProfiler>> =
     std::sync::LazyLock::new(|| Mutex::new(puffin::GlobalProfiler::default()));
 
 use bytemuck::{Pod, Zeroable};
-use image::{ImageBuffer, Luma, math::Rect};
+use image::{ImageBuffer, Luma, Rgb32FImage, math::Rect};
 use wgpu::{
     BindGroup, BindGroupDescriptor, BindGroupLayoutDescriptor, BufferDescriptor, BufferUsages,
     CommandEncoderDescriptor, PipelineLayoutDescriptor, include_wgsl, util::DeviceExt,
@@ -29,10 +36,22 @@ use crate::gpu::Context;
 pub struct Match {
     pub rect: Rect,
     pub value: f32,
+    /// The scale of the template that matched, see [`ScaleRange`](crate::matcher::ScaleRange)
+    pub scale: f32,
 }
 
 pub use imageproc::template_matching::find_extremes;
 
+/// Rows of the result scanned by each rayon task of [`find_matches`], raised to
+/// the height of the template so a match spans two bands at most
+const FIND_MATCHES_BAND_ROWS: u32 = 64;
+
+/// The matches of `input` past `threshold`, the best first, one per cluster of
+/// values closer than the size of the template.
+///
+/// The result is scanned in bands of rows in parallel, then the matches of
+/// every band are merged in order, so the output does not depend on how the
+/// bands were scheduled.
 pub fn find_matches(
     input: &ImageBuffer<Luma<f32>, Vec<f32>>,
     template_width: u32,
@@ -40,47 +59,76 @@ pub fn find_matches(
     method: MatchTemplateMethod,
     threshold: f32,
 ) -> Vec<Match> {
-    let mut matches: Vec<Match> = Vec::new();
-
-    for (x, y, p) in input.enumerate_pixels() {
-        let value = p.0[0];
-        if is_a_more_match_than_b(value, threshold, method) {
-            if let Some(m) = matches.iter_mut().rev().find(|m| {
-                ((m.rect.x as i32 - x as i32).abs() as u32) < template_width
-                    && ((m.rect.y as i32 - y as i32).abs() as u32) < template_height
-            }) {
-                if is_a_more_match_than_b(value, m.value, method) {
-                    m.rect.x = x;
-                    m.rect.y = y;
-                    m.value = value;
-                }
-                continue;
-            } else {
-                matches.push(Match {
-                    rect: Rect {
-                        x: x,
-                        y: y,
+    use rayon::prelude::*;
+
+    profiling::scope!("find_matches");
+    let width = input.width();
+    if width == 0 {
+        return Vec::new();
+    }
+    let band_rows = FIND_MATCHES_BAND_ROWS.max(template_height);
+    let bands = input
+        .as_raw()
+        .par_chunks((width * band_rows) as usize)
+        .enumerate()
+        .map(|(band, values)| {
+            let mut matches = Vec::new();
+            for (i, &value) in values.iter().enumerate() {
+                if is_a_more_match_than_b(value, threshold, method) {
+                    let (x, y) = (i as u32 % width, band as u32 * band_rows + i as u32 / width);
+                    let rect = Rect {
+                        x,
+                        y,
                         width: template_width,
                         height: template_height,
-                    },
-                    value,
-                });
+                    };
+                    merge_match(&mut matches, rect, value, method);
+                }
             }
-        }
+            matches
+        })
+        .collect::<Vec<_>>();
+
+    let mut matches = Vec::new();
+    for m in bands.into_iter().flatten() {
+        merge_match(&mut matches, m.rect, m.value, method);
     }
 
-    // sort matches by value (is_x_more_match_than_y)
+    // The best first, ties in reading order
     matches.sort_by(|a, b| {
         if is_a_more_match_than_b(a.value, b.value, method) {
             std::cmp::Ordering::Less
-        } else {
+        } else if is_a_more_match_than_b(b.value, a.value, method) {
             std::cmp::Ordering::Greater
+        } else {
+            (a.rect.y, a.rect.x).cmp(&(b.rect.y, b.rect.x))
         }
     });
 
     matches
 }
 
+/// Keep the match at `rect` in `matches`, or move the last one closer to it
+/// than the size of the template there if it is a better one.
+fn merge_match(matches: &mut Vec<Match>, rect: Rect, value: f32, method: MatchTemplateMethod) {
+    if let Some(m) = matches
+        .iter_mut()
+        .rev()
+        .find(|m| m.rect.x.abs_diff(rect.x) < rect.width && m.rect.y.abs_diff(rect.y) < rect.height)
+    {
+        if is_a_more_match_than_b(value, m.value, method) {
+            m.rect = rect;
+            m.value = value;
+        }
+    } else {
+        matches.push(Match {
+            rect,
+            value,
+            scale: 1.0,
+        });
+    }
+}
+
 pub fn is_a_more_match_than_b(a: f32, b: f32, method: MatchTemplateMethod) -> bool {
     if matches!(
         method,
@@ -128,20 +176,548 @@ impl Display for MatchTemplateMethod {
     }
 }
 
+impl FromStr for MatchTemplateMethod {
+    type Err = String;
+
+    /// The names of [`Display`], i.e. `ccoeff_normed`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MatchTemplateMethod::ALL
+            .into_iter()
+            .find(|method| method.to_string() == s)
+            .ok_or_else(|| format!("unknown match method {s:?}"))
+    }
+}
+
 pub fn match_template(
     image: &ImageBuffer<Luma<f32>, Vec<f32>>,
     template: &ImageBuffer<Luma<f32>, Vec<f32>>,
     method: MatchTemplateMethod,
     padding: bool,
 ) -> ImageBuffer<Luma<f32>, Vec<f32>> {
-    let mut matcher = matcher().lock().unwrap();
-    matcher.match_template(image, template, method, padding)
+    with_matcher(|matcher| matcher.match_template(image.into(), template.into(), method, padding))
+}
+
+/// [`match_template`] on all three channels, the score of a position is the
+/// one of the channels combined.
+pub fn match_template_rgb(
+    image: &Rgb32FImage,
+    template: &Rgb32FImage,
+    method: MatchTemplateMethod,
+    padding: bool,
+) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+    with_matcher(|matcher| matcher.match_template(image.into(), template.into(), method, padding))
+}
+
+/// [`match_template`] of each of `templates` on `image`, in one dispatch.
+///
+/// The image is uploaded once and all the templates are matched in a single
+/// submission, which is much faster than matching them one by one when
+/// checking many templates on each frame. Templates larger than the image
+/// without `padding` get an empty result.
+pub fn match_templates(
+    image: &ImageBuffer<Luma<f32>, Vec<f32>>,
+    templates: &[&ImageBuffer<Luma<f32>, Vec<f32>>],
+    method: MatchTemplateMethod,
+    padding: bool,
+) -> Vec<ImageBuffer<Luma<f32>, Vec<f32>>> {
+    let templates = templates.iter().map(|&t| t.into()).collect::<Vec<_>>();
+    with_matcher(|matcher| matcher.match_templates(image.into(), &templates, method, padding))
+}
+
+/// [`match_templates`] on all three channels, see [`match_template_rgb`].
+pub fn match_templates_rgb(
+    image: &Rgb32FImage,
+    templates: &[&Rgb32FImage],
+    method: MatchTemplateMethod,
+    padding: bool,
+) -> Vec<ImageBuffer<Luma<f32>, Vec<f32>>> {
+    let templates = templates.iter().map(|&t| t.into()).collect::<Vec<_>>();
+    with_matcher(|matcher| matcher.match_templates(image.into(), &templates, method, padding))
+}
+
+/// A template uploaded to the GPU once, to match it again and again without
+/// uploading it each time, see [`match_template_uploaded`].
+///
+/// It is only uploaded if matching runs on the GPU at the time, the data is kept
+/// for the CPU backend and the correlation coefficient methods, which subtract
+/// the mean of the template before matching.
+pub struct UploadedTemplate {
+    width: u32,
+    height: u32,
+    channels: u32,
+    data: Vec<f32>,
+    /// The buffer and the generation of the matcher it is on
+    buffer: Option<(wgpu::Buffer, u64)>,
+}
+
+impl UploadedTemplate {
+    pub fn luma(template: &ImageBuffer<Luma<f32>, Vec<f32>>) -> Self {
+        Self::upload(template.into())
+    }
+
+    pub fn rgb(template: &Rgb32FImage) -> Self {
+        Self::upload(template.into())
+    }
+
+    fn upload(template: Channels) -> Self {
+        let buffer = match backend() {
+            MatcherBackend::Gpu => gpu_matcher().map(|matcher| {
+                let matcher = matcher.lock().unwrap();
+                let buffer =
+                    matcher
+                        .ctx
+                        .device
+                        .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some("Uploaded template"),
+                            contents: bytemuck::cast_slice(&template.data),
+                            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+                        });
+                (buffer, matcher.generation)
+            }),
+            _ => None,
+        };
+        Self {
+            width: template.width,
+            height: template.height,
+            channels: template.channels,
+            data: template.data.into_owned(),
+            buffer,
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Whether it is on the GPU, it is not on the CPU backend
+    pub fn is_uploaded(&self) -> bool {
+        self.buffer.is_some()
+    }
+}
+
+/// [`match_template`] with a template already on the GPU.
+pub fn match_template_uploaded(
+    image: &ImageBuffer<Luma<f32>, Vec<f32>>,
+    template: &UploadedTemplate,
+    method: MatchTemplateMethod,
+    padding: bool,
+) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+    with_matcher(|matcher| matcher.match_template(image.into(), template.into(), method, padding))
+}
+
+/// [`match_template_rgb`] with a template already on the GPU.
+pub fn match_template_uploaded_rgb(
+    image: &Rgb32FImage,
+    template: &UploadedTemplate,
+    method: MatchTemplateMethod,
+    padding: bool,
+) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+    with_matcher(|matcher| matcher.match_template(image.into(), template.into(), method, padding))
+}
+
+/// An image of `channels` interleaved f32 channels, as the shader takes it
+#[derive(Clone)]
+struct Channels<'a> {
+    width: u32,
+    height: u32,
+    channels: u32,
+    data: Cow<'a, [f32]>,
+    /// A copy of `data` already on the GPU and the generation of the matcher it
+    /// is on, see [`UploadedTemplate`]
+    gpu: Option<(&'a wgpu::Buffer, u64)>,
+}
+
+impl<'a> From<&'a ImageBuffer<Luma<f32>, Vec<f32>>> for Channels<'a> {
+    fn from(image: &'a ImageBuffer<Luma<f32>, Vec<f32>>) -> Self {
+        Self {
+            width: image.width(),
+            height: image.height(),
+            channels: 1,
+            data: Cow::Borrowed(image.as_raw()),
+            gpu: None,
+        }
+    }
+}
+
+impl<'a> From<&'a Rgb32FImage> for Channels<'a> {
+    fn from(image: &'a Rgb32FImage) -> Self {
+        Self {
+            width: image.width(),
+            height: image.height(),
+            channels: 3,
+            data: Cow::Borrowed(image.as_raw()),
+            gpu: None,
+        }
+    }
+}
+
+impl<'a> From<&'a UploadedTemplate> for Channels<'a> {
+    fn from(template: &'a UploadedTemplate) -> Self {
+        Self {
+            width: template.width,
+            height: template.height,
+            channels: template.channels,
+            data: Cow::Borrowed(&template.data),
+            gpu: template
+                .buffer
+                .as_ref()
+                .map(|(buffer, generation)| (buffer, *generation)),
+        }
+    }
+}
+
+impl Channels<'_> {
+    /// Channel `c` as a grayscale image
+    fn channel(&self, c: u32) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+        let data = self
+            .data
+            .iter()
+            .skip(c as usize)
+            .step_by(self.channels as usize)
+            .copied()
+            .collect();
+        ImageBuffer::from_vec(self.width, self.height, data).unwrap()
+    }
+
+    /// Pad the right and bottom with zeros to `width`x`height`.
+    fn padded(&self, width: u32, height: u32) -> Channels<'static> {
+        let channels = self.channels as usize;
+        let mut data = vec![0.0; width as usize * height as usize * channels];
+        for (y, row) in self
+            .data
+            .chunks_exact(self.width as usize * channels)
+            .enumerate()
+        {
+            let start = y * width as usize * channels;
+            data[start..start + row.len()].copy_from_slice(row);
+        }
+        Channels {
+            width,
+            height,
+            channels: self.channels,
+            data: Cow::Owned(data),
+            gpu: None,
+        }
+    }
 }
 
-/// internal
-fn matcher() -> &'static Arc<Mutex<Matcher>> {
-    static MATCHER: OnceLock<Arc<Mutex<Matcher>>> = OnceLock::new();
-    MATCHER.get_or_init(|| Arc::new(Mutex::new(Matcher::new())))
+/// The implementation template matching runs on, see [`set_backend`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatcherBackend {
+    /// The GPU if there is a hardware adapter, the CPU otherwise. A software
+    /// adapter like llvmpipe is several times slower than [`MatcherBackend::Cpu`].
+    #[default]
+    Auto,
+    /// Compute shaders through wgpu, matching panics without a usable adapter
+    Gpu,
+    /// Parallel over the rows with rayon, available everywhere
+    Cpu,
+}
+
+static BACKEND: Mutex<MatcherBackend> = Mutex::new(MatcherBackend::Auto);
+
+/// Force the backend of all the matching from now on, i.e. [`MatcherBackend::Cpu`]
+/// for results that do not depend on the GPU.
+pub fn set_backend(backend: MatcherBackend) {
+    *BACKEND.lock().unwrap() = backend;
+}
+
+/// The backend matching runs on, [`MatcherBackend::Gpu`] or [`MatcherBackend::Cpu`].
+pub fn backend() -> MatcherBackend {
+    match *BACKEND.lock().unwrap() {
+        MatcherBackend::Auto => match gpu_matcher() {
+            Some(matcher) if matcher.lock().unwrap().preferred => MatcherBackend::Gpu,
+            _ => MatcherBackend::Cpu,
+        },
+        backend => backend,
+    }
+}
+
+/// The GPU matcher, see [`crate::gpu`]
+enum GpuState {
+    /// Created with the default options on the first match
+    Uninit,
+    Ready(Arc<Mutex<Matcher>>),
+    /// There is no usable adapter
+    Unavailable,
+}
+
+static GPU: Mutex<GpuState> = Mutex::new(GpuState::Uninit);
+/// Incremented each time the GPU matcher is replaced, the buffers of a previous
+/// one can not be used with the new one
+static GPU_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// The GPU matcher, `None` without a usable adapter
+fn gpu_matcher() -> Option<Arc<Mutex<Matcher>>> {
+    let mut gpu = GPU.lock().unwrap();
+    if let GpuState::Uninit = *gpu {
+        *gpu = match pollster::block_on(Context::try_new()) {
+            Ok(ctx) => {
+                // A software adapter is slower than the CPU backend, it is only
+                // used if asked for
+                let preferred = ctx.adapter.get_info().device_type != wgpu::DeviceType::Cpu;
+                let generation = GPU_GENERATION.load(Ordering::SeqCst);
+                GpuState::Ready(Arc::new(Mutex::new(Matcher::new(
+                    GpuDevice::from(ctx),
+                    generation,
+                    preferred,
+                ))))
+            }
+            Err(err) => {
+                tracing::warn!("no usable GPU, template matching falls back to the CPU: {err}");
+                GpuState::Unavailable
+            }
+        };
+    }
+    match &*gpu {
+        GpuState::Ready(matcher) => Some(matcher.clone()),
+        _ => None,
+    }
+}
+
+/// Replace the GPU matcher with one on `ctx`, or drop it for `None` so the next
+/// match creates it again, see [`crate::gpu::init`].
+pub(crate) fn set_gpu_device(ctx: Option<GpuDevice>) {
+    let generation = GPU_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    let state = match ctx {
+        Some(ctx) => GpuState::Ready(Arc::new(Mutex::new(Matcher::new(ctx, generation, true)))),
+        None => GpuState::Uninit,
+    };
+    *GPU.lock().unwrap() = state;
+}
+
+/// Call `f` with the matcher of the [`backend`].
+fn with_matcher<R>(f: impl FnOnce(&mut dyn MatchRaw) -> R) -> R {
+    match backend() {
+        MatcherBackend::Gpu => {
+            let matcher = gpu_matcher().expect("no usable GPU for MatcherBackend::Gpu");
+            f(&mut *matcher.lock().unwrap())
+        }
+        _ => f(&mut CpuMatcher),
+    }
+}
+
+/// A backend of template matching, which only has to implement
+/// [`MatchRaw::match_raw`].
+trait MatchRaw {
+    /// Match `template` at every position of `image` it fits in whole, the means
+    /// are already subtracted for the correlation coefficient methods.
+    fn match_raw(
+        &mut self,
+        image: &Channels,
+        template: &Channels,
+        match_method: MatchTemplateMethod,
+    ) -> ImageBuffer<Luma<f32>, Vec<f32>>;
+
+    /// Subtract from each channel of `image` its mean over the `width`x`height`
+    /// window at every position.
+    fn subtract_mean(&mut self, image: &Channels, width: u32, height: u32) -> Channels<'static> {
+        let avg_kernel =
+            ImageBuffer::from_pixel(width, height, Luma([1.0 / (width * height) as f32]));
+        let mut data = image.data.to_vec();
+        for c in 0..image.channels {
+            let channel = image.channel(c);
+            let avg = self.match_template(
+                (&channel).into(),
+                (&avg_kernel).into(),
+                MatchTemplateMethod::CrossCorrelation,
+                true,
+            );
+            for (v, avg) in data
+                .iter_mut()
+                .skip(c as usize)
+                .step_by(image.channels as usize)
+                .zip(avg.as_raw())
+            {
+                *v -= avg;
+            }
+        }
+        Channels {
+            width: image.width,
+            height: image.height,
+            channels: image.channels,
+            data: Cow::Owned(data),
+            gpu: None,
+        }
+    }
+
+    fn match_template(
+        &mut self,
+        image: Channels,
+        template: Channels,
+        match_method: MatchTemplateMethod,
+        padding: bool,
+    ) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+        profiling::scope!("match_template");
+        assert_eq!(image.channels, template.channels);
+        if !padding && (template.width > image.width || template.height > image.height) {
+            return ImageBuffer::new(0, 0);
+        }
+
+        let (image, template) = if matches!(
+            match_method,
+            MatchTemplateMethod::CorrelationCoefficient
+                | MatchTemplateMethod::CorrelationCoefficientNormed
+        ) {
+            let (width, height) = (template.width, template.height);
+            (
+                self.subtract_mean(&image, width, height),
+                self.subtract_mean(&template, width, height),
+            )
+        } else {
+            (image, template)
+        };
+        let image = if padding {
+            image.padded(
+                image.width + template.width - 1,
+                image.height + template.height - 1,
+            )
+        } else {
+            image
+        };
+        self.match_raw(&image, &template, match_method)
+    }
+
+    /// Match every template on `image`, see [`match_templates`].
+    fn match_templates(
+        &mut self,
+        image: Channels,
+        templates: &[Channels],
+        match_method: MatchTemplateMethod,
+        padding: bool,
+    ) -> Vec<ImageBuffer<Luma<f32>, Vec<f32>>> {
+        templates
+            .iter()
+            .map(|t| self.match_template(image.clone(), t.clone(), match_method, padding))
+            .collect()
+    }
+}
+
+/// Add the sums of the shader for the template value `t` to the ones of each
+/// position of a row, `values` being the image values under `t` at each.
+fn accumulate(
+    values: impl Iterator<Item = f32>,
+    t: f32,
+    dot: &mut [f32],
+    square: &mut [f32],
+    sqdiff: &mut [f32],
+) {
+    for (((v, dot), square), sqdiff) in values.zip(dot).zip(square).zip(sqdiff) {
+        *dot += v * t;
+        *square += v * v;
+        *sqdiff += (v - t) * (v - t);
+    }
+}
+
+/// The CPU backend, rows of the result in parallel with rayon. There are no
+/// explicit SIMD intrinsics, the sums over a row are plain loops the compiler
+/// vectorizes for single channel images. Gives the same results as the shader.
+struct CpuMatcher;
+
+impl MatchRaw for CpuMatcher {
+    fn match_raw(
+        &mut self,
+        image: &Channels,
+        template: &Channels,
+        match_method: MatchTemplateMethod,
+    ) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+        use rayon::prelude::*;
+
+        profiling::scope!("match_raw_cpu");
+        let (result_w, result_h) = (
+            image.width - template.width + 1,
+            image.height - template.height + 1,
+        );
+        let channels = image.channels as usize;
+        let image_stride = image.width as usize * channels;
+        let template_stride = template.width as usize * channels;
+        let template_square = template.data.iter().map(|v| v * v).sum::<f32>();
+
+        let mut result = vec![0.0; (result_w * result_h) as usize];
+        result
+            .par_chunks_mut(result_w as usize)
+            .enumerate()
+            .for_each(|(y, row)| {
+                // Summed for the whole row one template value at a time, so that the
+                // loops run over contiguous values for a single channel
+                let mut dot = vec![0.0; row.len()];
+                let mut square = vec![0.0; row.len()];
+                let mut sqdiff = vec![0.0; row.len()];
+                for (j, template_row) in template.data.chunks_exact(template_stride).enumerate() {
+                    let image_row = &image.data[(y + j) * image_stride..][..image_stride];
+                    for (k, &t) in template_row.iter().enumerate() {
+                        let values = &image_row[k..];
+                        match channels {
+                            1 => accumulate(
+                                values[..row.len()].iter().copied(),
+                                t,
+                                &mut dot,
+                                &mut square,
+                                &mut sqdiff,
+                            ),
+                            _ => accumulate(
+                                values.iter().copied().step_by(channels),
+                                t,
+                                &mut dot,
+                                &mut square,
+                                &mut sqdiff,
+                            ),
+                        }
+                    }
+                }
+                for (x, value) in row.iter_mut().enumerate() {
+                    let norm = (square[x] * template_square).sqrt();
+                    *value = match match_method {
+                        MatchTemplateMethod::SumOfSquaredDifference => sqdiff[x],
+                        MatchTemplateMethod::SumOfSquaredDifferenceNormed => sqdiff[x] / norm,
+                        MatchTemplateMethod::CrossCorrelation
+                        | MatchTemplateMethod::CorrelationCoefficient => dot[x],
+                        MatchTemplateMethod::CrossCorrelationNormed
+                        | MatchTemplateMethod::CorrelationCoefficientNormed => dot[x] / norm,
+                    };
+                }
+            });
+        ImageBuffer::from_vec(result_w, result_h, result).unwrap()
+    }
+
+    /// Same as the default but summing the windows with an integral image, instead
+    /// of correlating with a box kernel which is as slow as the matching itself.
+    fn subtract_mean(&mut self, image: &Channels, width: u32, height: u32) -> Channels<'static> {
+        let (image_w, image_h) = (image.width as usize, image.height as usize);
+        let (width, height) = (width as usize, height as usize);
+        let channels = image.channels as usize;
+        let area = (width * height) as f64;
+
+        let mut data = image.data.to_vec();
+        for c in 0..channels {
+            // integral[y][x] is the sum of the pixels above and left of (x, y)
+            let mut integral = vec![0.0f64; (image_w + 1) * (image_h + 1)];
+            for y in 0..image_h {
+                let mut row_sum = 0.0;
+                for x in 0..image_w {
+                    row_sum += image.data[(y * image_w + x) * channels + c] as f64;
+                    integral[(y + 1) * (image_w + 1) + x + 1] =
+                        integral[y * (image_w + 1) + x + 1] + row_sum;
+                }
+            }
+            let at = |x: usize, y: usize| integral[y * (image_w + 1) + x];
+            // The window is clipped to the image, the padding being zeros
+            for y in 0..image_h {
+                for x in 0..image_w {
+                    let (x1, y1) = ((x + width).min(image_w), (y + height).min(image_h));
+                    let sum = at(x1, y1) - at(x, y1) - at(x1, y) + at(x, y);
+                    data[(y * image_w + x) * channels + c] -= (sum / area) as f32;
+                }
+            }
+        }
+        Channels {
+            width: image.width,
+            height: image.height,
+            channels: image.channels,
+            data: Cow::Owned(data),
+            gpu: None,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Pod, Zeroable)]
@@ -151,10 +727,27 @@ struct Uniforms {
     image_height: u32,
     template_width: u32,
     template_height: u32,
+    channels: u32,
+    _padding: [u32; 3],
+}
+
+/// The device matching runs on, of a [`Context`] or of the application
+pub(crate) struct GpuDevice {
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+}
+
+impl From<Context> for GpuDevice {
+    fn from(ctx: Context) -> Self {
+        Self {
+            device: ctx.device,
+            queue: ctx.queue,
+        }
+    }
 }
 
 struct Matcher {
-    ctx: Context,
+    ctx: GpuDevice,
 
     input_buffer: Option<wgpu::Buffer>,
     template_buffer: Option<wgpu::Buffer>,
@@ -162,6 +755,13 @@ struct Matcher {
     staging_buffer: Option<wgpu::Buffer>,
     uniform_buffer: wgpu::Buffer,
 
+    // Buffers of `match_templates`, separate so the bind group above stays valid
+    batch_input_buffer: Option<wgpu::Buffer>,
+    batch_template_buffer: Option<wgpu::Buffer>,
+    batch_result_buffer: Option<wgpu::Buffer>,
+    batch_staging_buffer: Option<wgpu::Buffer>,
+    batch_uniform_buffer: Option<wgpu::Buffer>,
+
     bind_group_layout: wgpu::BindGroupLayout,
     // pipeline_layout: wgpu::PipelineLayout,
     bind_group: Option<wgpu::BindGroup>,
@@ -174,12 +774,16 @@ struct Matcher {
 
     #[cfg(feature = "profiling")]
     profiler: GpuProfiler,
+
+    /// Tells the buffers of this matcher from the ones of the previous ones
+    generation: u64,
+    /// Whether [`MatcherBackend::Auto`] runs on it
+    preferred: bool,
 }
 
 impl Matcher {
-    fn new() -> Self {
-        let ctx = pollster::block_on(Context::new());
-        let Context { device, .. } = &ctx;
+    fn new(ctx: GpuDevice, generation: u64, preferred: bool) -> Self {
+        let GpuDevice { device, .. } = &ctx;
 
         let bind_group_layout = ctx
             .device
@@ -318,6 +922,11 @@ impl Matcher {
             result_buffer: None,
             staging_buffer: None,
             uniform_buffer,
+            batch_input_buffer: None,
+            batch_template_buffer: None,
+            batch_result_buffer: None,
+            batch_staging_buffer: None,
+            batch_uniform_buffer: None,
             bind_group_layout,
             bind_group: None,
             // pipeline_layout,
@@ -329,6 +938,19 @@ impl Matcher {
             pipeline_ccoeff_normed,
             #[cfg(feature = "profiling")]
             profiler,
+            generation,
+            preferred,
+        }
+    }
+
+    fn pipeline(&self, method: MatchTemplateMethod) -> &wgpu::ComputePipeline {
+        match method {
+            MatchTemplateMethod::CrossCorrelation => &self.pipeline_ccorr,
+            MatchTemplateMethod::CrossCorrelationNormed => &self.pipeline_ccorr_normed,
+            MatchTemplateMethod::SumOfSquaredDifference => &self.pipeline_sqdiff,
+            MatchTemplateMethod::SumOfSquaredDifferenceNormed => &self.pipeline_sqdiff_normed,
+            MatchTemplateMethod::CorrelationCoefficient => &self.pipeline_ccoeff,
+            MatchTemplateMethod::CorrelationCoefficientNormed => &self.pipeline_ccoeff_normed,
         }
     }
 
@@ -360,90 +982,52 @@ impl Matcher {
             ],
         })
     }
+}
 
-    fn match_template(
-        &mut self,
-        image: &ImageBuffer<Luma<f32>, Vec<f32>>,
-        template: &ImageBuffer<Luma<f32>, Vec<f32>>,
-        match_method: MatchTemplateMethod,
-        padding: bool,
-    ) -> ImageBuffer<Luma<f32>, Vec<f32>> {
-        profiling::scope!("match_template");
+/// Where a template of [`match_templates`] is in the shared buffers
+struct BatchSlot {
+    template_offset: u64,
+    result_offset: u64,
+    uniform_offset: u64,
+    template_width: u32,
+    template_height: u32,
+    result_width: u32,
+    result_height: u32,
+}
 
-        let (image, template) = if matches!(
-            match_method,
-            MatchTemplateMethod::CorrelationCoefficient
-                | MatchTemplateMethod::CorrelationCoefficientNormed
-        ) {
-            let avg_kernel = ImageBuffer::from_pixel(
-                template.width(),
-                template.height(),
-                Luma([1.0 / (template.width() * template.height()) as f32]),
-            );
-            let avg_image = self.match_template(
-                image,
-                &avg_kernel,
-                MatchTemplateMethod::CrossCorrelation,
-                true,
-            );
-            let avg_template = self.match_template(
-                template,
-                &avg_kernel,
-                MatchTemplateMethod::CrossCorrelation,
-                true,
-            );
+impl BatchSlot {
+    fn template_size(&self, channels: u32) -> u64 {
+        (self.template_width * self.template_height * channels) as u64 * size_of::<f32>() as u64
+    }
 
-            let image = ImageBuffer::from_vec(
-                image.width(),
-                image.height(),
-                image
-                    .as_raw()
-                    .iter()
-                    .zip(avg_image.as_raw().iter())
-                    .map(|(v, avg)| v - avg)
-                    .collect(),
-            )
-            .unwrap();
-            let template = ImageBuffer::from_vec(
-                template.width(),
-                template.height(),
-                template
-                    .as_raw()
-                    .iter()
-                    .zip(avg_template.as_raw().iter())
-                    .map(|(v, avg)| v - avg)
-                    .collect(),
-            )
-            .unwrap();
+    fn result_size(&self) -> u64 {
+        (self.result_width * self.result_height) as u64 * size_of::<f32>() as u64
+    }
+}
 
-            (image, template)
-        } else {
-            (image.clone(), template.clone())
-        };
-        let image = if padding {
-            let padded_image = ImageBuffer::from_fn(
-                image.width() + template.width() - 1,
-                image.height() + template.height() - 1,
-                |x, y| {
-                    if x >= image.width() || y >= image.height() {
-                        Luma([0.0])
-                    } else {
-                        *image.get_pixel(x, y)
-                    }
-                },
-            );
-            padded_image
-        } else {
-            image.clone()
-        };
-        let image = &image;
-        let template = &template;
+fn align_to(value: u64, alignment: u64) -> u64 {
+    value.div_ceil(alignment) * alignment
+}
+
+impl MatchRaw for Matcher {
+    fn match_raw(
+        &mut self,
+        image: &Channels,
+        template: &Channels,
+        match_method: MatchTemplateMethod,
+    ) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+        profiling::scope!("match_raw");
 
         let (result_w, result_h) = (
-            image.width() - template.width() + 1,
-            image.height() - template.height() + 1,
+            image.width - template.width + 1,
+            image.height - template.height + 1,
         );
         let result_buf_sz = (result_w * result_h * size_of::<f32>() as u32) as u64;
+        // Uploaded on a previous matcher, see `crate::gpu::shutdown`
+        let resident = template
+            .gpu
+            .filter(|(_, generation)| *generation == self.generation)
+            .map(|(buffer, _)| buffer);
 
         // update buffers
         let update = {
@@ -453,15 +1037,23 @@ impl Matcher {
                 prepare_buffer_init_with_image(
                     &self.ctx,
                     &mut self.input_buffer,
-                    image,
+                    &image.data,
                     wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
                 ),
-                prepare_buffer_init_with_image(
-                    &self.ctx,
-                    &mut self.template_buffer,
-                    template,
-                    BufferUsages::STORAGE | BufferUsages::COPY_DST,
-                ),
+                match resident {
+                    Some(buffer) => prepare_buffer_init_with_size(
+                        &self.ctx,
+                        &mut self.template_buffer,
+                        buffer.size(),
+                        BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                    ),
+                    None => prepare_buffer_init_with_image(
+                        &self.ctx,
+                        &mut self.template_buffer,
+                        &template.data,
+                        BufferUsages::STORAGE | BufferUsages::COPY_DST,
+                    ),
+                },
                 prepare_buffer_init_with_size(
                     &self.ctx,
                     &mut self.result_buffer,
@@ -481,14 +1073,21 @@ impl Matcher {
 
         // update bind_group and uniforms
         if update {
-            profiling::scope!("update bind_group and uniforms");
+            profiling::scope!("update bind_group");
             self.bind_group = Some(self.create_new_bind_group());
+        }
+        {
+            profiling::scope!("update uniforms");
+            // The sizes may change without the buffers changing, i.e. from a luma
+            // image to an rgb one of a third of the width
             // let template_sq_sum = template.as_raw().iter().map(|x| x * x).sum::<f32>();
             let uniforms = Uniforms {
-                image_height: image.height(),
-                image_width: image.width(),
-                template_height: template.height(),
-                template_width: template.width(),
+                image_height: image.height,
+                image_width: image.width,
+                template_height: template.height,
+                template_width: template.width,
+                channels: image.channels,
+                _padding: [0; 3],
                 // template_sq_sum,
             };
             self.ctx
@@ -498,14 +1097,7 @@ impl Matcher {
 
         // Helper function to execute compute pass logic
         let encode_compute_pass = |pass: &mut wgpu::ComputePass<'_>| {
-            pass.set_pipeline(match match_method {
-                MatchTemplateMethod::CrossCorrelation => &self.pipeline_ccorr,
-                MatchTemplateMethod::CrossCorrelationNormed => &self.pipeline_ccorr_normed,
-                MatchTemplateMethod::SumOfSquaredDifference => &self.pipeline_sqdiff,
-                MatchTemplateMethod::SumOfSquaredDifferenceNormed => &self.pipeline_sqdiff_normed,
-                MatchTemplateMethod::CorrelationCoefficient => &self.pipeline_ccoeff,
-                MatchTemplateMethod::CorrelationCoefficientNormed => &self.pipeline_ccoeff_normed,
-            });
+            pass.set_pipeline(self.pipeline(match_method));
             pass.set_bind_group(0, self.bind_group.as_ref().unwrap(), &[]);
             pass.dispatch_workgroups(
                 (result_w as f32 / 8.0).ceil() as u32,
@@ -520,6 +1112,16 @@ impl Matcher {
             .create_command_encoder(&CommandEncoderDescriptor {
                 label: Some("encoder"),
             });
+        // A template already on the GPU is only copied, not uploaded
+        if let Some(buffer) = resident {
+            encoder.copy_buffer_to_buffer(
+                buffer,
+                0,
+                self.template_buffer.as_ref().unwrap(),
+                0,
+                buffer.size(),
+            );
+        }
 
         {
             #[cfg(feature = "profiling")]
@@ -622,11 +1224,272 @@ impl Matcher {
         profiling::finish_frame!();
         res
     }
+
+    /// Match every template on `image` with a single submission, see [`match_templates`].
+    fn match_templates(
+        &mut self,
+        image: Channels,
+        templates: &[Channels],
+        match_method: MatchTemplateMethod,
+        padding: bool,
+    ) -> Vec<ImageBuffer<Luma<f32>, Vec<f32>>> {
+        profiling::scope!("match_templates");
+        assert!(templates.iter().all(|t| t.channels == image.channels));
+
+        // The mean of the image is subtracted over a window of the size of each
+        // template, so it differs for each of them
+        if matches!(
+            match_method,
+            MatchTemplateMethod::CorrelationCoefficient
+                | MatchTemplateMethod::CorrelationCoefficientNormed
+        ) {
+            return templates
+                .iter()
+                .map(|t| self.match_template(image.clone(), t.clone(), match_method, padding))
+                .collect();
+        }
+
+        // Padding the image for the largest template works for all of them, the
+        // results are cropped back to the size of the image
+        let (width, height) = (image.width, image.height);
+        let image = if padding {
+            let max_width = templates.iter().map(|t| t.width).max().unwrap_or(1);
+            let max_height = templates.iter().map(|t| t.height).max().unwrap_or(1);
+            image.padded(width + max_width - 1, height + max_height - 1)
+        } else {
+            image
+        };
+
+        let limits = self.ctx.device.limits();
+        let storage_alignment = limits.min_storage_buffer_offset_alignment as u64;
+        let uniform_alignment = limits.min_uniform_buffer_offset_alignment as u64;
+        let (mut template_end, mut result_end, mut uniform_end) = (0, 0, 0);
+        let slots = templates
+            .iter()
+            .map(|t| {
+                let fits = t.width <= image.width && t.height <= image.height;
+                let slot = BatchSlot {
+                    template_offset: template_end,
+                    result_offset: result_end,
+                    uniform_offset: uniform_end,
+                    template_width: t.width,
+                    template_height: t.height,
+                    result_width: if fits { image.width - t.width + 1 } else { 0 },
+                    result_height: if fits { image.height - t.height + 1 } else { 0 },
+                };
+                if fits {
+                    template_end = align_to(
+                        template_end + slot.template_size(image.channels),
+                        storage_alignment,
+                    );
+                    result_end = align_to(result_end + slot.result_size(), storage_alignment);
+                    uniform_end = align_to(
+                        uniform_end + size_of::<Uniforms>() as u64,
+                        uniform_alignment,
+                    );
+                }
+                slot
+            })
+            .collect::<Vec<_>>();
+        if result_end == 0 {
+            return slots.iter().map(|_| ImageBuffer::new(0, 0)).collect();
+        }
+
+        {
+            profiling::scope!("update buffers");
+            let mut template_data = vec![0.0f32; template_end as usize / size_of::<f32>()];
+            let mut uniform_data = vec![0u8; uniform_end as usize];
+            for (slot, template) in slots.iter().zip(templates) {
+                if slot.result_width == 0 {
+                    continue;
+                }
+                let start = slot.template_offset as usize / size_of::<f32>();
+                template_data[start..start + template.data.len()].copy_from_slice(&template.data);
+                let uniforms = Uniforms {
+                    image_width: image.width,
+                    image_height: image.height,
+                    template_width: slot.template_width,
+                    template_height: slot.template_height,
+                    channels: image.channels,
+                    _padding: [0; 3],
+                };
+                let start = slot.uniform_offset as usize;
+                uniform_data[start..start + size_of::<Uniforms>()]
+                    .copy_from_slice(bytemuck::bytes_of(&uniforms));
+            }
+
+            prepare_buffer_init_with_image(
+                &self.ctx,
+                &mut self.batch_input_buffer,
+                &image.data,
+                BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            );
+            prepare_buffer_init_with_image(
+                &self.ctx,
+                &mut self.batch_template_buffer,
+                &template_data,
+                BufferUsages::STORAGE | BufferUsages::COPY_DST,
+            );
+            prepare_buffer_init_with_size(
+                &self.ctx,
+                &mut self.batch_uniform_buffer,
+                uniform_end,
+                BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            );
+            self.ctx.queue.write_buffer(
+                self.batch_uniform_buffer.as_ref().unwrap(),
+                0,
+                &uniform_data,
+            );
+            prepare_buffer_init_with_size(
+                &self.ctx,
+                &mut self.batch_result_buffer,
+                result_end,
+                BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            );
+            prepare_buffer_init_with_size(
+                &self.ctx,
+                &mut self.batch_staging_buffer,
+                result_end,
+                BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            );
+        }
+
+        fn binding(
+            buffer: &Option<wgpu::Buffer>,
+            offset: u64,
+            size: u64,
+        ) -> wgpu::BindingResource<'_> {
+            wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer: buffer.as_ref().unwrap(),
+                offset,
+                size: wgpu::BufferSize::new(size),
+            })
+        }
+        let bind_groups = slots
+            .iter()
+            .map(|slot| {
+                (slot.result_width > 0).then(|| {
+                    self.ctx.device.create_bind_group(&BindGroupDescriptor {
+                        label: Some("Matcher Batch BindGroup"),
+                        layout: &self.bind_group_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: self
+                                    .batch_input_buffer
+                                    .as_ref()
+                                    .unwrap()
+                                    .as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: binding(
+                                    &self.batch_template_buffer,
+                                    slot.template_offset,
+                                    slot.template_size(image.channels),
+                                ),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: binding(
+                                    &self.batch_result_buffer,
+                                    slot.result_offset,
+                                    slot.result_size(),
+                                ),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: binding(
+                                    &self.batch_uniform_buffer,
+                                    slot.uniform_offset,
+                                    size_of::<Uniforms>() as u64,
+                                ),
+                            },
+                        ],
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut encoder = self
+            .ctx
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("batch encoder"),
+            });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("batch compute pass"),
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(self.pipeline(match_method));
+            for (slot, bind_group) in slots.iter().zip(&bind_groups) {
+                let Some(bind_group) = bind_group else {
+                    continue;
+                };
+                pass.set_bind_group(0, bind_group, &[]);
+                pass.dispatch_workgroups(
+                    slot.result_width.div_ceil(8),
+                    slot.result_height.div_ceil(8),
+                    1,
+                );
+            }
+        }
+        encoder.copy_buffer_to_buffer(
+            self.batch_result_buffer.as_ref().unwrap(),
+            0,
+            self.batch_staging_buffer.as_ref().unwrap(),
+            0,
+            result_end,
+        );
+        {
+            profiling::scope!("submit encoder");
+            self.ctx.queue.submit(Some(encoder.finish()));
+        }
+
+        profiling::scope!("get output");
+        let staging_buffer = self.batch_staging_buffer.as_ref().unwrap();
+        let buffer_slice = staging_buffer.slice(..);
+        let (sender, receiver) = async_channel::bounded(1);
+        buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.try_send(v).unwrap());
+        self.ctx
+            .device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .unwrap();
+        let mapped = receiver.try_recv().unwrap().is_ok();
+        let results = {
+            let data = mapped.then(|| buffer_slice.get_mapped_range());
+            slots
+                .iter()
+                .map(|slot| {
+                    let (w, h) = (slot.result_width, slot.result_height);
+                    let values = match &data {
+                        Some(data) if w > 0 => {
+                            let start = slot.result_offset as usize;
+                            bytemuck::cast_slice(&data[start..start + slot.result_size() as usize])
+                                .to_vec()
+                        }
+                        _ => vec![0.0; (w * h) as usize],
+                    };
+                    let res = ImageBuffer::from_vec(w, h, values).unwrap();
+                    match padding && w > 0 {
+                        true => image::imageops::crop_imm(&res, 0, 0, width, height).to_image(),
+                        false => res,
+                    }
+                })
+                .collect()
+        };
+        if mapped {
+            staging_buffer.unmap();
+        }
+        results
+    }
 }
 
 /// returns true if buffer is updated
 fn prepare_buffer_init_with_size(
-    ctx: &Context,
+    ctx: &GpuDevice,
     buffer: &mut Option<wgpu::Buffer>,
     size: u64,
     usage: wgpu::BufferUsages,
@@ -645,34 +1508,32 @@ fn prepare_buffer_init_with_size(
 
 /// returns true if buffer is updated
 fn prepare_buffer_init_with_image(
-    ctx: &Context,
+    ctx: &GpuDevice,
     buffer: &mut Option<wgpu::Buffer>,
-    image: &ImageBuffer<Luma<f32>, Vec<f32>>,
+    data: &[f32],
     usage: wgpu::BufferUsages,
 ) -> bool {
-    let update = buffer.is_none()
-        || buffer.as_ref().unwrap().size() != (image.as_raw().len() * size_of::<f32>()) as u64;
+    let update = buffer.is_none() || buffer.as_ref().unwrap().size() != size_of_val(data) as u64;
     if update {
         *buffer = Some(
             ctx.device
                 .create_buffer_init(&wgpu::util::BufferInitDescriptor {
                     label: None,
-                    contents: bytemuck::cast_slice(&image.as_raw()),
+                    contents: bytemuck::cast_slice(data),
                     usage,
                 }),
         );
     } else {
-        ctx.queue.write_buffer(
-            buffer.as_ref().unwrap(),
-            0,
-            bytemuck::cast_slice(&image.as_raw()),
-        );
+        ctx.queue
+            .write_buffer(buffer.as_ref().unwrap(), 0, bytemuck::cast_slice(data));
     }
     update
 }
 
 #[cfg(test)]
 mod tests {
+    use image::DynamicImage;
+
     use crate::utils::save_luma32f;
 
     use super::*;
@@ -698,6 +1559,108 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_channels() {
+        let image = Rgb32FImage::from_fn(2, 2, |x, y| image::Rgb([x as f32, y as f32, 9.0]));
+        let channels = Channels::from(&image);
+        assert_eq!(channels.channel(1).as_raw(), &[0.0, 0.0, 1.0, 1.0]);
+
+        let padded = channels.padded(3, 3);
+        assert_eq!(
+            padded.channel(0).as_raw(),
+            &[0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0]
+        );
+        assert_eq!(padded.channel(2).as_raw()[..3], [9.0, 9.0, 0.0]);
+    }
+
+    #[test]
+    fn test_find_matches() {
+        let mut res = ImageBuffer::<Luma<f32>, Vec<f32>>::new(300, 200);
+        for (x, y, value) in [
+            // A cluster across the first two bands, the best pixel is in the second
+            (50, 62, 0.9),
+            (52, 63, 0.85),
+            (51, 65, 0.95),
+            (200, 150, 0.85),
+            (10, 10, 0.85),
+            // Below the threshold
+            (250, 20, 0.5),
+        ] {
+            res.put_pixel(x, y, Luma([value]));
+        }
+        let method = MatchTemplateMethod::CrossCorrelationNormed;
+        let matches = find_matches(&res, 20, 20, method, 0.8);
+        let found = matches
+            .iter()
+            .map(|m| (m.rect.x, m.rect.y, m.value))
+            .collect::<Vec<_>>();
+        assert_eq!(found, [(51, 65, 0.95), (10, 10, 0.85), (200, 150, 0.85)]);
+        for _ in 0..5 {
+            let again = find_matches(&res, 20, 20, method, 0.8);
+            assert!(again.iter().zip(&matches).all(|(a, b)| a.rect == b.rect));
+        }
+        assert!(find_matches(&ImageBuffer::new(0, 0), 20, 20, method, 0.8).is_empty());
+    }
+
+    #[test]
+    fn test_cpu_matcher() {
+        use imageproc::template_matching::MatchTemplateMethod as Imageproc;
+
+        let image =
+            image::GrayImage::from_fn(30, 20, |x, y| Luma([((x * 7 + y * 13) % 29) as u8 * 8]));
+        let template = image::imageops::crop_imm(&image, 11, 6, 5, 4).to_image();
+        let (image_f, template_f) = (
+            DynamicImage::ImageLuma8(image.clone()).to_luma32f(),
+            DynamicImage::ImageLuma8(template.clone()).to_luma32f(),
+        );
+        // imageproc works on the 0-255 values, only the normed methods compare as is
+        for (method, expected, scale) in [
+            (
+                MatchTemplateMethod::SumOfSquaredDifference,
+                Imageproc::SumOfSquaredErrors,
+                255.0 * 255.0,
+            ),
+            (
+                MatchTemplateMethod::SumOfSquaredDifferenceNormed,
+                Imageproc::SumOfSquaredErrorsNormalized,
+                1.0,
+            ),
+            (
+                MatchTemplateMethod::CrossCorrelation,
+                Imageproc::CrossCorrelation,
+                255.0 * 255.0,
+            ),
+            (
+                MatchTemplateMethod::CrossCorrelationNormed,
+                Imageproc::CrossCorrelationNormalized,
+                1.0,
+            ),
+        ] {
+            let res =
+                CpuMatcher.match_template((&image_f).into(), (&template_f).into(), method, false);
+            let expected =
+                imageproc::template_matching::match_template(&image, &template, expected);
+            assert_eq!(res.dimensions(), expected.dimensions());
+            for (a, b) in res.pixels().zip(expected.pixels()) {
+                assert!(
+                    (a.0[0] * scale - b.0[0]).abs() <= 1e-3 * b.0[0].abs().max(1.0),
+                    "{method}"
+                );
+            }
+        }
+
+        let mean = CpuMatcher.subtract_mean(&(&image_f).into(), 5, 4);
+        for (x, y) in [(0, 0), (12, 7), (27, 18)] {
+            let window =
+                (x..(x + 5).min(30)).flat_map(|x| (y..(y + 4).min(20)).map(move |y| (x, y)));
+            let sum = window
+                .map(|(x, y)| image_f.get_pixel(x, y).0[0])
+                .sum::<f32>();
+            let expected = image_f.get_pixel(x, y).0[0] - sum / 20.0;
+            assert!((mean.data[(y * 30 + x) as usize] - expected).abs() < 1e-5);
+        }
+    }
+
     #[test]
     fn foo() -> Result<(), Box<dyn Error>> {
         let angel = image::open("./assets/avatars/angel_sale#8.png")?.to_luma32f();
@@ -716,6 +1679,43 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_match_templates() -> Result<(), Box<dyn Error>> {
+        let image = image::open("./assets/in_battle.png")?.to_luma32f();
+        let templates = ["battle_deploy-card-cost1", "battle_pause"].map(|name| {
+            image::open(format!("./assets/{name}.png"))
+                .unwrap()
+                .to_luma32f()
+        });
+        let too_large = ImageBuffer::new(image.width() + 1, 1);
+
+        for method in [
+            MatchTemplateMethod::SumOfSquaredDifferenceNormed,
+            MatchTemplateMethod::CrossCorrelationNormed,
+        ] {
+            for padding in [false, true] {
+                let batch = match_templates(
+                    &image,
+                    &[&templates[0], &templates[1], &too_large],
+                    method,
+                    padding,
+                );
+                for (template, res) in templates.iter().zip(&batch) {
+                    let single = match_template(&image, template, method, padding);
+                    assert_eq!(res.dimensions(), single.dimensions());
+                    let extremes = (find_extremes(res), find_extremes(&single));
+                    assert_eq!(extremes.0.max_value_location, extremes.1.max_value_location);
+                    assert!((extremes.0.max_value - extremes.1.max_value).abs() < 1e-3);
+                }
+                assert_eq!(
+                    batch[2].dimensions(),
+                    if padding { image.dimensions() } else { (0, 0) }
+                );
+            }
+        }
+        Ok(())
+    }
+
     #[test]
     fn test_template_matching() -> Result<(), Box<dyn Error>> {
         init_profiling();
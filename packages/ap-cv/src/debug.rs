@@ -0,0 +1,146 @@
+//! Drawing what the matcher found, to see why a template did or did not match
+//!
+//! [`annotate`]: A frame with the rectangle and the score of each match drawn on it.
+//!
+//! The scores are drawn with a built-in pixel font, so no font has to be loaded.
+
+use image::{DynamicImage, Rgba, RgbaImage};
+use imageproc::{
+    drawing::{draw_filled_rect_mut, draw_hollow_rect_mut},
+    rect::Rect as ProcRect,
+};
+
+use crate::core::template_matching::Match;
+
+const MATCH_COLOR: Rgba<u8> = Rgba([255, 48, 48, 255]);
+const LABEL_BACKGROUND: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const LABEL_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+/// Thickness of the rectangles
+const BORDER: u32 = 2;
+/// Screen pixels per pixel of the font
+const GLYPH_SCALE: u32 = 2;
+const GLYPH_WIDTH: u32 = 3;
+const GLYPH_HEIGHT: u32 = 5;
+
+/// The rows of a glyph of the pixel font, the 3 low bits of each from left to
+/// right, `None` for a character it does not have.
+fn glyph(c: char) -> Option<[u8; 5]> {
+    Some(match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        'x' => [0b000, 0b101, 0b010, 0b101, 0b000],
+        ' ' => [0; 5],
+        _ => return None,
+    })
+}
+
+/// Draw `text` with its top left corner at `(x, y)` on a dark background,
+/// clipped to `image`. Characters missing from the font are skipped.
+fn draw_label(image: &mut RgbaImage, x: i32, y: i32, text: &str) {
+    let glyphs = text.chars().filter_map(glyph).collect::<Vec<_>>();
+    if glyphs.is_empty() {
+        return;
+    }
+    let advance = (GLYPH_WIDTH + 1) * GLYPH_SCALE;
+    let width = glyphs.len() as u32 * advance + GLYPH_SCALE;
+    let height = (GLYPH_HEIGHT + 2) * GLYPH_SCALE;
+    draw_filled_rect_mut(
+        image,
+        ProcRect::at(x, y).of_size(width, height),
+        LABEL_BACKGROUND,
+    );
+    for (i, rows) in glyphs.iter().enumerate() {
+        let left = x + (GLYPH_SCALE + i as u32 * advance) as i32;
+        for (row, bits) in rows.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                    continue;
+                }
+                let pixel = ProcRect::at(
+                    left + (col * GLYPH_SCALE) as i32,
+                    y + ((row as u32 + 1) * GLYPH_SCALE) as i32,
+                )
+                .of_size(GLYPH_SCALE, GLYPH_SCALE);
+                draw_filled_rect_mut(image, pixel, LABEL_COLOR);
+            }
+        }
+    }
+}
+
+/// `image` with the rectangle of each of `matches` and its score drawn on it,
+/// the score above the rectangle, or inside it at the top of the image. The
+/// scale of a match other than 1 follows its score, i.e. `0.912 x0.75`.
+pub fn annotate(image: &DynamicImage, matches: &[Match]) -> DynamicImage {
+    let mut annotated = image.to_rgba8();
+    let label_height = ((GLYPH_HEIGHT + 2) * GLYPH_SCALE) as i32;
+    for m in matches {
+        let rect = m.rect;
+        for inset in 0..BORDER {
+            if rect.width > inset * 2 && rect.height > inset * 2 {
+                let rect = ProcRect::at((rect.x + inset) as i32, (rect.y + inset) as i32)
+                    .of_size(rect.width - inset * 2, rect.height - inset * 2);
+                draw_hollow_rect_mut(&mut annotated, rect, MATCH_COLOR);
+            }
+        }
+        let mut label = format!("{:.3}", m.value);
+        if m.scale != 1.0 {
+            label.push_str(&format!(" x{:.2}", m.scale));
+        }
+        let y = match rect.y as i32 - label_height {
+            y if y >= 0 => y,
+            _ => (rect.y + BORDER) as i32,
+        };
+        draw_label(&mut annotated, rect.x as i32, y, &label);
+    }
+    DynamicImage::ImageRgba8(annotated)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::math::Rect;
+
+    use super::*;
+
+    #[test]
+    fn test_annotate() {
+        let image =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(100, 60, Rgba([128, 128, 128, 255])));
+        let m = |x, y, value| Match {
+            rect: Rect {
+                x,
+                y,
+                width: 20,
+                height: 10,
+            },
+            value,
+            scale: 1.0,
+        };
+        let annotated = annotate(&image, &[m(10, 30, 0.95), m(60, 0, -0.5)]).to_rgba8();
+        assert_eq!(annotated.dimensions(), (100, 60));
+        // The rectangles
+        assert_eq!(annotated.get_pixel(10, 35), &MATCH_COLOR);
+        assert_eq!(annotated.get_pixel(65, 0), &MATCH_COLOR);
+        assert_eq!(annotated.get_pixel(20, 35), &Rgba([128, 128, 128, 255]));
+        // The score above the first one and inside the second one
+        let label = |x0: u32, y0: u32| {
+            (x0..x0 + 20)
+                .flat_map(|x| (y0..y0 + 14).map(move |y| (x, y)))
+                .any(|(x, y)| annotated.get_pixel(x, y) == &LABEL_COLOR)
+        };
+        assert!(label(10, 16));
+        assert!(label(60, 2));
+        // Nothing drawn where there is nothing
+        assert!(!label(10, 44));
+        assert_eq!(annotate(&image, &[]).to_rgba8(), image.to_rgba8());
+    }
+}
@@ -0,0 +1,297 @@
+//! Frame differencing, to tell whether the screen is still changing
+//!
+//! [`FrameChangeDetector`]: Classify a stream of frames as stable or transitioning.
+//! [`region_delta`]: How much a part of the screen changed between two frames.
+
+use image::{DynamicImage, GrayImage, imageops::FilterType, math::Rect};
+
+/// Shrink `image` to at most `max_width` wide (keeping the aspect ratio), to make
+/// differencing cheap. Fine details do not matter when looking for transitions.
+pub fn downsample(image: &DynamicImage, max_width: u32) -> GrayImage {
+    let gray = image.to_luma8();
+    if max_width == 0 || gray.width() <= max_width {
+        return gray;
+    }
+    let height = (gray.height() as u64 * max_width as u64 / gray.width() as u64).max(1) as u32;
+    image::imageops::resize(&gray, max_width, height, FilterType::Triangle)
+}
+
+/// Mean luma of `image` in `[0, 1]`.
+pub fn mean_luma(image: &GrayImage) -> f32 {
+    let len = image.as_raw().len();
+    if len == 0 {
+        return 0.0;
+    }
+    let sum: u64 = image.as_raw().iter().map(|&v| v as u64).sum();
+    sum as f32 / len as f32 / 255.0
+}
+
+/// Mean absolute difference between two frames in `[0, 1]`, frames of different
+/// sizes are considered completely different.
+pub fn mean_delta(prev: &GrayImage, cur: &GrayImage) -> f32 {
+    if prev.dimensions() != cur.dimensions() {
+        return 1.0;
+    }
+    let len = cur.as_raw().len();
+    if len == 0 {
+        return 0.0;
+    }
+    let sum: u64 = prev
+        .as_raw()
+        .iter()
+        .zip(cur.as_raw())
+        .map(|(&a, &b)| a.abs_diff(b) as u64)
+        .sum();
+    sum as f32 / len as f32 / 255.0
+}
+
+/// [`mean_delta`] of `rect` of two frames, i.e. to tell whether an animation
+/// in it is over.
+pub fn region_delta(prev: &DynamicImage, cur: &DynamicImage, rect: Rect) -> f32 {
+    region_delta_downsampled(prev, cur, rect, 0)
+}
+
+/// [`region_delta`] with `rect` [downsampled](downsample) to at most `max_width`
+/// wide first, which is cheaper on a large region and less sensitive to noise.
+pub fn region_delta_downsampled(
+    prev: &DynamicImage,
+    cur: &DynamicImage,
+    rect: Rect,
+    max_width: u32,
+) -> f32 {
+    let crop = |image: &DynamicImage| {
+        let region = image.crop_imm(rect.x, rect.y, rect.width, rect.height);
+        downsample(&region, max_width)
+    };
+    mean_delta(&crop(prev), &crop(cur))
+}
+
+/// Bounding box of the pixels that changed by more than `threshold`.
+pub fn changed_bounds(prev: &GrayImage, cur: &GrayImage, threshold: u8) -> Option<Rect> {
+    if prev.dimensions() != cur.dimensions() {
+        return Some(Rect {
+            x: 0,
+            y: 0,
+            width: cur.width(),
+            height: cur.height(),
+        });
+    }
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, pixel) in cur.enumerate_pixels() {
+        if pixel.0[0].abs_diff(prev.get_pixel(x, y).0[0]) > threshold {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    (min_x <= max_x).then(|| Rect {
+        x: min_x,
+        y: min_y,
+        width: max_x - min_x + 1,
+        height: max_y - min_y + 1,
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameChangeOptions {
+    /// Frames are [downsampled](downsample) to this width before comparing
+    pub max_width: u32,
+    /// A pixel changed if its luma changed by more than this
+    pub pixel_threshold: u8,
+    /// A frame whose [mean luma](mean_luma) is below this is a black frame
+    pub black_threshold: f32,
+    /// Changes confined to less than this fraction of the frame, seen in consecutive
+    /// frames, are a spinner. Set to `0.0` to treat them as regular changes.
+    pub spinner_area: f32,
+    /// Number of consecutive unchanged frames for the screen to be stable
+    pub stable_frames: usize,
+}
+
+impl Default for FrameChangeOptions {
+    fn default() -> Self {
+        Self {
+            max_width: 320,
+            pixel_threshold: 24,
+            black_threshold: 0.06,
+            spinner_area: 0.05,
+            stable_frames: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameState {
+    /// Nothing changed for [`FrameChangeOptions::stable_frames`] frames
+    Stable,
+    /// The screen is changing, or has not been still for long enough
+    Changing,
+    /// A black frame, usually in between two scenes
+    Black,
+    /// Only a small region keeps changing, like a loading spinner
+    Spinner,
+}
+
+impl FrameState {
+    pub fn is_transitioning(self) -> bool {
+        self != FrameState::Stable
+    }
+}
+
+/// Classify a stream of frames as stable or transitioning.
+///
+/// ```ignore
+/// let mut detector = FrameChangeDetector::new(FrameChangeOptions::default());
+/// while detector.push(&ap.screencap()?).is_transitioning() {
+///     std::thread::sleep(Duration::from_millis(100));
+/// }
+/// ```
+pub struct FrameChangeDetector {
+    options: FrameChangeOptions,
+    prev: Option<GrayImage>,
+    /// Number of consecutive unchanged frames
+    still: usize,
+    /// Number of consecutive frames with a small change
+    spinner: usize,
+}
+
+impl FrameChangeDetector {
+    pub fn new(options: FrameChangeOptions) -> Self {
+        Self {
+            options,
+            prev: None,
+            still: 0,
+            spinner: 0,
+        }
+    }
+
+    pub fn options(&self) -> &FrameChangeOptions {
+        &self.options
+    }
+
+    /// Forget the previous frames.
+    pub fn reset(&mut self) {
+        self.prev = None;
+        self.still = 0;
+        self.spinner = 0;
+    }
+
+    pub fn push(&mut self, frame: &DynamicImage) -> FrameState {
+        let prev = self.prev.replace(downsample(frame, self.options.max_width));
+        let cur = self.prev.as_ref().unwrap();
+
+        if mean_luma(cur) < self.options.black_threshold {
+            self.still = 0;
+            self.spinner = 0;
+            return FrameState::Black;
+        }
+        let Some(prev) = prev else {
+            self.still = 0;
+            self.spinner = 0;
+            return FrameState::Changing;
+        };
+
+        let Some(bounds) = changed_bounds(&prev, cur, self.options.pixel_threshold) else {
+            self.still += 1;
+            self.spinner = 0;
+            return if self.still >= self.options.stable_frames {
+                FrameState::Stable
+            } else {
+                FrameState::Changing
+            };
+        };
+
+        self.still = 0;
+        let area = (bounds.width * bounds.height) as f32 / (cur.width() * cur.height()) as f32;
+        if area < self.options.spinner_area {
+            self.spinner += 1;
+            if self.spinner >= 2 {
+                return FrameState::Spinner;
+            }
+        } else {
+            self.spinner = 0;
+        }
+        FrameState::Changing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Luma;
+
+    use super::*;
+
+    fn frame(fill: u8, square: Option<(u32, u32)>) -> DynamicImage {
+        let mut image = GrayImage::from_pixel(100, 100, Luma([fill]));
+        if let Some((x, y)) = square {
+            for dy in 0..4 {
+                for dx in 0..4 {
+                    image.put_pixel(x + dx, y + dy, Luma([255 - fill]));
+                }
+            }
+        }
+        DynamicImage::ImageLuma8(image)
+    }
+
+    #[test]
+    fn test_deltas() {
+        let a = frame(100, None).to_luma8();
+        let b = frame(100, Some((10, 20))).to_luma8();
+        assert_eq!(mean_delta(&a, &a), 0.0);
+        assert!(mean_delta(&a, &b) > 0.0);
+        assert_eq!(changed_bounds(&a, &a, 0), None);
+        assert_eq!(
+            changed_bounds(&a, &b, 0),
+            Some(Rect {
+                x: 10,
+                y: 20,
+                width: 4,
+                height: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_region_delta() {
+        let a = frame(100, None);
+        let b = frame(100, Some((10, 20)));
+        let rect = |x, y| Rect {
+            x,
+            y,
+            width: 20,
+            height: 20,
+        };
+        assert_eq!(region_delta(&a, &b, rect(50, 50)), 0.0);
+        // 16 of the 400 pixels went from 100 to 155
+        let delta = region_delta(&a, &b, rect(0, 10));
+        assert!((delta - 16.0 * 55.0 / 400.0 / 255.0).abs() < 1e-6);
+        assert!(region_delta_downsampled(&a, &b, rect(0, 10), 10) > 0.0);
+        assert_eq!(region_delta(&a, &b, rect(100, 100)), 0.0);
+    }
+
+    #[test]
+    fn test_frame_change_detector() {
+        let mut detector = FrameChangeDetector::new(FrameChangeOptions::default());
+        assert_eq!(detector.push(&frame(0, None)), FrameState::Black);
+        assert_eq!(detector.push(&frame(0, None)), FrameState::Black);
+
+        assert_eq!(detector.push(&frame(100, None)), FrameState::Changing);
+        for _ in 0..2 {
+            assert_eq!(detector.push(&frame(100, None)), FrameState::Changing);
+        }
+        assert_eq!(detector.push(&frame(100, None)), FrameState::Stable);
+
+        assert_eq!(
+            detector.push(&frame(100, Some((50, 50)))),
+            FrameState::Changing
+        );
+        assert_eq!(
+            detector.push(&frame(100, Some((52, 50)))),
+            FrameState::Spinner
+        );
+        assert_eq!(
+            detector.push(&frame(100, Some((50, 52)))),
+            FrameState::Spinner
+        );
+    }
+}
@@ -1,3 +1,49 @@
+//! The wgpu device template matching runs on
+//!
+//! It is created the first time something is matched on the GPU, with the default
+//! [`GpuOptions`]. [`init`] creates it with other options, [`init_with_context`]
+//! shares the device of the application, i.e. a GUI drawing the results, and
+//! [`shutdown`] releases it.
+
+use crate::core::template_matching::{GpuDevice, set_gpu_device};
+
+/// How [`init`] chooses the adapter
+#[derive(Debug, Clone)]
+pub struct GpuOptions {
+    pub power_preference: wgpu::PowerPreference,
+    /// The graphics APIs to choose from, i.e. only [`wgpu::Backends::VULKAN`]
+    /// when the DX12 driver is broken
+    pub backends: wgpu::Backends,
+    /// Only take a software adapter
+    pub force_fallback_adapter: bool,
+}
+
+impl Default for GpuOptions {
+    fn default() -> Self {
+        Self {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            backends: wgpu::Backends::all(),
+            force_fallback_adapter: false,
+        }
+    }
+}
+
+impl GpuOptions {
+    /// Prefer the integrated GPU, i.e. to spare the battery of a laptop
+    pub fn low_power(mut self) -> Self {
+        self.power_preference = wgpu::PowerPreference::LowPower;
+        self
+    }
+    pub fn with_backends(mut self, backends: wgpu::Backends) -> Self {
+        self.backends = backends;
+        self
+    }
+    pub fn fallback_adapter(mut self) -> Self {
+        self.force_fallback_adapter = true;
+        self
+    }
+}
+
 pub struct Context {
     pub instance: wgpu::Instance,
     pub adapter: wgpu::Adapter,
@@ -7,15 +53,31 @@ pub struct Context {
 
 impl Context {
     pub async fn new() -> Self {
-        let instance = wgpu::Instance::default();
+        Self::try_new().await.unwrap()
+    }
+
+    /// Like [`Context::new`], failing on machines without a usable adapter, i.e.
+    /// headless CI or some VMs.
+    pub async fn try_new() -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Self::with_options(&GpuOptions::default()).await
+    }
+
+    /// Like [`Context::try_new`] on the adapter chosen by `options`.
+    pub async fn with_options(
+        options: &GpuOptions,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: options.backends,
+            ..Default::default()
+        });
 
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::HighPerformance,
+                power_preference: options.power_preference,
+                force_fallback_adapter: options.force_fallback_adapter,
                 ..Default::default()
             })
-            .await
-            .unwrap();
+            .await?;
 
         #[cfg(feature = "profiling")]
         let descriptor = wgpu::DeviceDescriptor {
@@ -27,13 +89,41 @@ impl Context {
         #[cfg(not(feature = "profiling"))]
         let descriptor = wgpu::DeviceDescriptor::default();
 
-        let (device, queue) = adapter.request_device(&descriptor).await.unwrap();
+        let (device, queue) = adapter.request_device(&descriptor).await?;
 
-        Self {
+        Ok(Self {
             instance,
             adapter,
             device,
             queue,
-        }
+        })
     }
 }
+
+/// Create the GPU state on the adapter chosen by `options`, replacing the one
+/// there was.
+///
+/// Matching on the GPU is then done on it even if it is a software adapter, which
+/// [`MatcherBackend::Auto`](crate::core::template_matching::MatcherBackend::Auto)
+/// skips by default.
+pub fn init(options: &GpuOptions) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let ctx = pollster::block_on(Context::with_options(options))?;
+    set_gpu_device(Some(ctx.into()));
+    Ok(())
+}
+
+/// Match on the `device` of the application instead of creating one, i.e. to
+/// draw the results without copying them between devices.
+pub fn init_with_context(device: wgpu::Device, queue: wgpu::Queue) {
+    set_gpu_device(Some(GpuDevice { device, queue }));
+}
+
+/// Release the GPU state, the next match on the GPU creates it again with the
+/// default options.
+///
+/// Matches in progress finish on the previous device. Templates uploaded on it,
+/// see [`UploadedTemplate`](crate::core::template_matching::UploadedTemplate),
+/// are uploaded again on each match from then on.
+pub fn shutdown() {
+    set_gpu_device(None);
+}
@@ -1,4 +1,12 @@
+pub mod cache;
+pub mod color;
 pub mod core;
+pub mod debug;
+pub mod diff;
 pub mod gpu;
 pub mod matcher;
+pub mod ocr;
+pub mod store;
 pub mod utils;
+
+pub use gpu::{GpuOptions, init, init_with_context, shutdown};
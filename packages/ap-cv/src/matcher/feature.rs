@@ -0,0 +1,353 @@
+//! Feature based matching, for templates that template matching misses because
+//! they are rotated or drawn over an animated background.
+//!
+//! Keypoints are oriented FAST corners described with rotated BRIEF, i.e. ORB
+//! without the scale pyramid. The descriptors of the template are matched to the
+//! ones of the image by Hamming distance, and a homography is fitted to the
+//! matches with RANSAC to locate the template.
+
+use std::f32::consts::PI;
+
+use image::{GrayImage, math::Rect};
+use imageproc::{
+    corners::{OrientedFastCorner, oriented_fast},
+    filter::gaussian_blur_f32,
+    geometric_transformations::Projection,
+};
+
+/// Radius of the patch the descriptor is computed on, the test points are
+/// within it whatever the orientation.
+const PATCH_RADIUS: i32 = 15;
+/// Distance of the keypoints to the edges, so the patch is always in the image
+const EDGE_RADIUS: u32 = PATCH_RADIUS as u32 + 1;
+/// Bits of a descriptor
+const DESCRIPTOR_BITS: usize = 256;
+const RANSAC_ITERATIONS: usize = 500;
+
+/// Two points, the ones a descriptor compares or the ones a match pairs
+type PointPair = ((f32, f32), (f32, f32));
+
+#[derive(Debug, Clone, Copy)]
+pub struct FeatureMatcherOptions {
+    /// Most keypoints kept on each image, the strongest corners
+    pub max_features: usize,
+    /// Intensity difference for a pixel to be a FAST corner
+    pub fast_threshold: u8,
+    /// A match is kept if its distance is below `ratio` times the one of the
+    /// second best, which drops the ambiguous ones (Lowe's ratio test)
+    pub ratio: f32,
+    /// Matches agreeing with the homography for the template to be found
+    pub min_inliers: usize,
+    /// How far in pixels a match may be from where the homography puts it to
+    /// agree with it
+    pub reprojection_error: f32,
+    /// Only search this part of the screen, see
+    /// [`MatcherOptions::region`](super::MatcherOptions::region)
+    pub region: Option<Rect>,
+}
+
+impl Default for FeatureMatcherOptions {
+    fn default() -> Self {
+        Self {
+            max_features: 500,
+            fast_threshold: 20,
+            ratio: 0.8,
+            min_inliers: 8,
+            reprojection_error: 3.0,
+            region: None,
+        }
+    }
+}
+
+impl FeatureMatcherOptions {
+    pub fn in_region(mut self, region: Rect) -> Self {
+        self.region = Some(region);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FeatureMatch {
+    /// Bounding box of the template in the image
+    pub rect: Rect,
+    /// Where the corners of the template are in the image, clockwise from the
+    /// top left
+    pub corners: [(f32, f32); 4],
+    /// Matches agreeing with the homography
+    pub inliers: usize,
+}
+
+/// A keypoint and its descriptor
+struct Feature {
+    x: f32,
+    y: f32,
+    bits: [u64; DESCRIPTOR_BITS / 64],
+}
+
+impl Feature {
+    fn distance(&self, other: &Feature) -> u32 {
+        self.bits
+            .iter()
+            .zip(other.bits)
+            .map(|(a, b)| (a ^ b).count_ones())
+            .sum()
+    }
+}
+
+/// A tiny xorshift generator, so the test points and the RANSAC samples are the
+/// same on every run.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// Standard normal sample (Box-Muller)
+    fn normal(&mut self) -> f32 {
+        let u1 = ((self.next() >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        let u2 = (self.next() >> 11) as f64 / (1u64 << 53) as f64;
+        ((-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()) as f32
+    }
+}
+
+/// The pairs of points compared by the descriptor, relative to the keypoint and
+/// within [`PATCH_RADIUS`], Gaussian distributed as in BRIEF.
+fn test_pairs() -> &'static [PointPair] {
+    static TEST_PAIRS: std::sync::OnceLock<Vec<PointPair>> = std::sync::OnceLock::new();
+    TEST_PAIRS.get_or_init(|| {
+        let mut rng = XorShift(0x2545_f491_4f6c_dd1d);
+        let max = (PATCH_RADIUS - 1) as f32;
+        let mut point = || loop {
+            let (x, y) = (rng.normal() * 6.6, rng.normal() * 6.6);
+            if x * x + y * y <= max * max {
+                return (x, y);
+            }
+        };
+        (0..DESCRIPTOR_BITS).map(|_| (point(), point())).collect()
+    })
+}
+
+fn features(image: &GrayImage, options: &FeatureMatcherOptions) -> Vec<Feature> {
+    let (width, height) = image.dimensions();
+    if width <= 2 * EDGE_RADIUS || height <= 2 * EDGE_RADIUS {
+        return Vec::new();
+    }
+    let corners = oriented_fast(
+        image,
+        Some(options.fast_threshold),
+        options.max_features,
+        EDGE_RADIUS,
+        None,
+    );
+    // The tests compare single pixels, which is only stable once smoothed
+    let smoothed = gaussian_blur_f32(image, 2.0);
+    corners
+        .iter()
+        .map(|corner| describe(&smoothed, corner))
+        .collect()
+}
+
+/// Rotated BRIEF: the test points are rotated by the orientation of the corner,
+/// so the descriptor does not change with the rotation of the image.
+fn describe(image: &GrayImage, corner: &OrientedFastCorner) -> Feature {
+    let (x, y) = (corner.corner.x as f32, corner.corner.y as f32);
+    // The orientation is counterclockwise with y up, the image has y down
+    let (sin, cos) = (-corner.orientation).sin_cos();
+    let sample = |(dx, dy): (f32, f32)| {
+        let px = x + dx * cos - dy * sin;
+        let py = y + dx * sin + dy * cos;
+        image.get_pixel(px.round() as u32, py.round() as u32).0[0]
+    };
+
+    let mut bits = [0u64; DESCRIPTOR_BITS / 64];
+    for (i, (p0, p1)) in test_pairs().iter().enumerate() {
+        if sample(*p0) < sample(*p1) {
+            bits[i / 64] |= 1 << (i % 64);
+        }
+    }
+    Feature { x, y, bits }
+}
+
+/// The matches of the template features to the image features passing the ratio
+/// test, as pairs of positions.
+fn match_features(template: &[Feature], image: &[Feature], ratio: f32) -> Vec<PointPair> {
+    template
+        .iter()
+        .filter_map(|feature| {
+            let (mut best, mut second) = ((u32::MAX, None), u32::MAX);
+            for candidate in image {
+                let distance = feature.distance(candidate);
+                if distance < best.0 {
+                    second = best.0;
+                    best = (distance, Some(candidate));
+                } else if distance < second {
+                    second = distance;
+                }
+            }
+            let (distance, candidate) = best;
+            let candidate = candidate?;
+            ((distance as f32) < ratio * second as f32)
+                .then_some(((feature.x, feature.y), (candidate.x, candidate.y)))
+        })
+        .collect()
+}
+
+/// Fit a homography to `matches` with RANSAC, returning it with its inliers.
+fn find_homography(matches: &[PointPair], reprojection_error: f32) -> Option<(Projection, usize)> {
+    if matches.len() < 4 {
+        return None;
+    }
+    let mut rng = XorShift(0x9e37_79b9_7f4a_7c15);
+    let inliers = |projection: &Projection| {
+        matches
+            .iter()
+            .filter(|(from, to)| {
+                let (x, y) = *projection * *from;
+                (x - to.0).hypot(y - to.1) <= reprojection_error
+            })
+            .count()
+    };
+
+    let mut best: Option<(Projection, usize)> = None;
+    for _ in 0..RANSAC_ITERATIONS {
+        let mut sample = [0; 4];
+        for i in 0..4 {
+            sample[i] = loop {
+                let j = rng.below(matches.len());
+                if !sample[..i].contains(&j) {
+                    break j;
+                }
+            };
+        }
+        let Some(projection) = Projection::from_control_points(
+            sample.map(|i| matches[i].0),
+            sample.map(|i| matches[i].1),
+        ) else {
+            continue;
+        };
+        let count = inliers(&projection);
+        if best.as_ref().is_none_or(|(_, best)| count > *best) {
+            best = Some((projection, count));
+        }
+        if count == matches.len() {
+            break;
+        }
+    }
+    best
+}
+
+/// Match a template by its features, see the [module docs](self).
+pub struct FeatureMatcher;
+
+impl FeatureMatcher {
+    /// Where `template` is in `image`, if enough features agree on it.
+    pub fn match_template(
+        image: &GrayImage,
+        template: &GrayImage,
+        options: &FeatureMatcherOptions,
+    ) -> Option<FeatureMatch> {
+        let template_features = features(template, options);
+        let image_features = features(image, options);
+        let matches = match_features(&template_features, &image_features, options.ratio);
+        let (projection, inliers) = find_homography(&matches, options.reprojection_error)?;
+        if inliers < options.min_inliers {
+            return None;
+        }
+
+        let (width, height) = (template.width() as f32, template.height() as f32);
+        let corners = [(0.0, 0.0), (width, 0.0), (width, height), (0.0, height)]
+            .map(|corner| projection * corner);
+        let (min_x, max_x) = corners.iter().fold((f32::MAX, f32::MIN), |(min, max), c| {
+            (min.min(c.0), max.max(c.0))
+        });
+        let (min_y, max_y) = corners.iter().fold((f32::MAX, f32::MIN), |(min, max), c| {
+            (min.min(c.1), max.max(c.1))
+        });
+        let x = min_x.clamp(0.0, image.width() as f32) as u32;
+        let y = min_y.clamp(0.0, image.height() as f32) as u32;
+        let rect = Rect {
+            x,
+            y,
+            width: (max_x.clamp(0.0, image.width() as f32) as u32).saturating_sub(x),
+            height: (max_y.clamp(0.0, image.height() as f32) as u32).saturating_sub(y),
+        };
+        if rect.width == 0 || rect.height == 0 {
+            return None;
+        }
+        Some(FeatureMatch {
+            rect,
+            corners,
+            inliers,
+        })
+    }
+
+    /// The rotation of the template in `found`, in radians clockwise.
+    pub fn rotation(found: &FeatureMatch) -> f32 {
+        let [(x0, y0), (x1, y1), ..] = found.corners;
+        (y1 - y0).atan2(x1 - x0).rem_euclid(2.0 * PI)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Luma;
+
+    use super::*;
+
+    /// A scene of scattered rectangles, with corners all over it
+    fn scene(width: u32, height: u32) -> GrayImage {
+        let mut rng = XorShift(42);
+        let mut image = GrayImage::from_pixel(width, height, Luma([40]));
+        for _ in 0..60 {
+            let (x, y) = (
+                rng.below(width as usize) as u32,
+                rng.below(height as usize) as u32,
+            );
+            let (w, h) = (8 + rng.below(30) as u32, 8 + rng.below(30) as u32);
+            let value = 80 + rng.below(170) as u8;
+            for py in y..(y + h).min(height) {
+                for px in x..(x + w).min(width) {
+                    image.put_pixel(px, py, Luma([value]));
+                }
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_feature_matcher() {
+        let image = scene(400, 300);
+        let template = image::imageops::crop_imm(&image, 120, 80, 160, 120).to_image();
+
+        let found =
+            FeatureMatcher::match_template(&image, &template, &FeatureMatcherOptions::default())
+                .unwrap();
+        assert!(found.rect.x.abs_diff(120) <= 2, "{found:?}");
+        assert!(found.rect.y.abs_diff(80) <= 2, "{found:?}");
+        assert!(found.rect.width.abs_diff(160) <= 4, "{found:?}");
+        assert!(found.rect.height.abs_diff(120) <= 4, "{found:?}");
+
+        // Rotated upside down
+        let rotated = image::imageops::rotate180(&template);
+        let found =
+            FeatureMatcher::match_template(&image, &rotated, &FeatureMatcherOptions::default())
+                .unwrap();
+        assert!(found.rect.x.abs_diff(120) <= 3, "{found:?}");
+        assert!(found.rect.y.abs_diff(80) <= 3, "{found:?}");
+        assert!((FeatureMatcher::rotation(&found) - PI).abs() < 0.1);
+
+        let blank = GrayImage::from_pixel(160, 120, Luma([40]));
+        assert!(
+            FeatureMatcher::match_template(&image, &blank, &FeatureMatcherOptions::default())
+                .is_none()
+        );
+    }
+}
@@ -3,16 +3,170 @@
 //! [`SingleMatcher`]: Match one template on an image to get one result.
 //! [`MultiMatcher`]: Match one template on an image to get multiple results.
 //! [`BestMatcher`]: Match one template on many images to get the best one.
+//! [`FeatureMatcher`]: Match one template by its features, even if rotated.
 
-use image::{ImageBuffer, Luma, math::Rect};
+use std::borrow::Cow;
+
+use image::{DynamicImage, GenericImageView, ImageBuffer, Luma, imageops::FilterType, math::Rect};
 use imageproc::template_matching::find_extremes;
 
-use crate::core::template_matching::{Match, MatchTemplateMethod, find_matches, match_template};
+pub mod feature;
+
+pub use feature::{FeatureMatch, FeatureMatcher, FeatureMatcherOptions};
+
+use crate::{
+    core::template_matching::{
+        Match, MatchTemplateMethod, find_matches, is_a_more_match_than_b, match_template,
+        match_template_rgb, match_template_uploaded, match_template_uploaded_rgb, match_templates,
+        match_templates_rgb,
+    },
+    store::Template,
+};
+
+/// The scales a template is searched at, from `min` to `max` in `steps` evenly
+/// spaced steps, i.e. for a screen of a slightly different aspect ratio than the
+/// one the template was recorded on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScaleRange {
+    pub min: f32,
+    pub max: f32,
+    pub steps: u32,
+}
+
+impl ScaleRange {
+    pub fn new(min: f32, max: f32, steps: u32) -> Self {
+        Self { min, max, steps }
+    }
+
+    pub fn scales(&self) -> impl Iterator<Item = f32> + '_ {
+        let steps = self.steps.max(1);
+        let step = match steps {
+            1 => 0.0,
+            _ => (self.max - self.min) / (steps - 1) as f32,
+        };
+        (0..steps).map(move |i| self.min + step * i as f32)
+    }
+}
+
+/// The scales of `options` with the size of the template at each, skipping the
+/// ones larger than the image. Only the template as is without scales.
+fn scaled_sizes(
+    (width, height): (u32, u32),
+    (image_width, image_height): (u32, u32),
+    options: &MatcherOptions,
+) -> Vec<(f32, (u32, u32))> {
+    let Some(scales) = options.scales else {
+        return vec![(1.0, (width, height))];
+    };
+    let sizes = scales
+        .scales()
+        .map(|scale| {
+            let size = |v: u32| ((v as f32 * scale).round() as u32).max(1);
+            (scale, (size(width), size(height)))
+        })
+        .filter(|(_, (w, h))| options.padding || (*w <= image_width && *h <= image_height))
+        .collect::<Vec<_>>();
+    match sizes.is_empty() {
+        true => vec![(1.0, (width, height))],
+        false => sizes,
+    }
+}
+
+fn resize_luma(
+    template: &ImageBuffer<Luma<f32>, Vec<f32>>,
+    width: u32,
+    height: u32,
+) -> Cow<'_, ImageBuffer<Luma<f32>, Vec<f32>>> {
+    match template.dimensions() == (width, height) {
+        true => Cow::Borrowed(template),
+        false => Cow::Owned(image::imageops::resize(
+            template,
+            width,
+            height,
+            FilterType::Triangle,
+        )),
+    }
+}
+
+fn resize_image(template: &DynamicImage, width: u32, height: u32) -> Cow<'_, DynamicImage> {
+    match template.dimensions() == (width, height) {
+        true => Cow::Borrowed(template),
+        false => Cow::Owned(template.resize_exact(width, height, FilterType::Triangle)),
+    }
+}
+
+/// The channels a template is matched on by [`match_image`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ColorMode {
+    /// Grayscale, the fastest
+    #[default]
+    Luma,
+    /// All three channels combined, to tell apart shapes only differing in color
+    Rgb,
+}
+
+/// Match `template` on `image` in the [`ColorMode`] of `options`.
+pub fn match_image(
+    image: &DynamicImage,
+    template: &DynamicImage,
+    options: &MatcherOptions,
+) -> ImageBuffer<Luma<f32>, Vec<f32>> {
+    match options.color_mode {
+        ColorMode::Luma => match_template(
+            &image.to_luma32f(),
+            &template.to_luma32f(),
+            options.method,
+            options.padding,
+        ),
+        ColorMode::Rgb => match_template_rgb(
+            &image.to_rgb32f(),
+            &template.to_rgb32f(),
+            options.method,
+            options.padding,
+        ),
+    }
+}
+
+/// [`match_image`] of each of `templates`, in one dispatch, see [`match_templates`].
+pub fn match_images(
+    image: &DynamicImage,
+    templates: &[&DynamicImage],
+    options: &MatcherOptions,
+) -> Vec<ImageBuffer<Luma<f32>, Vec<f32>>> {
+    match options.color_mode {
+        ColorMode::Luma => {
+            let templates = templates.iter().map(|t| t.to_luma32f()).collect::<Vec<_>>();
+            match_templates(
+                &image.to_luma32f(),
+                &templates.iter().collect::<Vec<_>>(),
+                options.method,
+                options.padding,
+            )
+        }
+        ColorMode::Rgb => {
+            let templates = templates.iter().map(|t| t.to_rgb32f()).collect::<Vec<_>>();
+            match_templates_rgb(
+                &image.to_rgb32f(),
+                &templates.iter().collect::<Vec<_>>(),
+                options.method,
+                options.padding,
+            )
+        }
+    }
+}
 
+#[derive(Debug, Clone, Copy)]
 pub struct MatcherOptions {
     pub method: MatchTemplateMethod,
     pub threshold: f32,
     pub padding: bool,
+    /// Only used by [`match_image`], the other functions take grayscale images
+    pub color_mode: ColorMode,
+    /// Search the template at several scales with the matchers, only as is if `None`
+    pub scales: Option<ScaleRange>,
+    /// Only search this part of the screen, which callers capturing the screen
+    /// crop before matching. The matchers take the image as is.
+    pub region: Option<Rect>,
 }
 
 impl Default for MatcherOptions {
@@ -21,6 +175,9 @@ impl Default for MatcherOptions {
             method: MatchTemplateMethod::SumOfSquaredDifferenceNormed,
             threshold: 0.2,
             padding: false,
+            color_mode: ColorMode::Luma,
+            scales: None,
+            region: None,
         }
     }
 }
@@ -51,6 +208,18 @@ impl MatcherOptions {
         self.padding = true;
         self
     }
+    pub fn with_color_mode(mut self, color_mode: ColorMode) -> Self {
+        self.color_mode = color_mode;
+        self
+    }
+    pub fn with_scales(mut self, scales: ScaleRange) -> Self {
+        self.scales = Some(scales);
+        self
+    }
+    pub fn in_region(mut self, region: Rect) -> Self {
+        self.region = Some(region);
+        self
+    }
 }
 
 /// Match one template on an image to get one result.
@@ -58,6 +227,9 @@ pub struct SingleMatcher;
 
 pub struct SingleMatcherResult {
     pub result: Option<Match>,
+    /// The best location whatever its score, what `result` is when it passes
+    /// the threshold. `None` only for a template larger than the image.
+    pub candidate: Option<Match>,
     pub matched_image: ImageBuffer<Luma<f32>, Vec<f32>>,
 }
 
@@ -67,47 +239,208 @@ impl SingleMatcher {
         template: &ImageBuffer<Luma<f32>, Vec<f32>>,
         options: &MatcherOptions,
     ) -> SingleMatcherResult {
-        use MatchTemplateMethod::*;
+        Self::match_scales(
+            template.dimensions(),
+            image.dimensions(),
+            options,
+            |w, h| {
+                let template = resize_luma(template, w, h);
+                match_template(image, &template, options.method, options.padding)
+            },
+        )
+    }
 
-        let matched_image = match_template(image, template, options.method, options.padding);
-        let extremes = find_extremes(&matched_image);
-        let result = match options.method {
-            SumOfSquaredDifference | SumOfSquaredDifferenceNormed => {
-                if extremes.min_value < options.threshold {
-                    Some(Match {
-                        rect: Rect {
-                            x: extremes.min_value_location.0,
-                            y: extremes.min_value_location.1,
-                            width: template.width(),
-                            height: template.height(),
-                        },
-                        value: extremes.min_value,
-                    })
-                } else {
-                    None
+    /// [`SingleMatcher::match_template`] in the [`ColorMode`] of `options`.
+    pub fn match_image(
+        image: &DynamicImage,
+        template: &DynamicImage,
+        options: &MatcherOptions,
+    ) -> SingleMatcherResult {
+        Self::match_scales(
+            template.dimensions(),
+            image.dimensions(),
+            options,
+            |w, h| {
+                let template = resize_image(template, w, h);
+                match_image(image, &template, options)
+            },
+        )
+    }
+
+    /// [`SingleMatcher::match_image`] of a template loaded in a
+    /// [`TemplateStore`](crate::store::TemplateStore), only resized and uploaded
+    /// again at the scales other than 1.
+    pub fn match_stored(
+        image: &DynamicImage,
+        template: &Template,
+        options: &MatcherOptions,
+    ) -> SingleMatcherResult {
+        let size = template.image.dimensions();
+        Self::match_scales(size, image.dimensions(), options, |w, h| {
+            if (w, h) != size {
+                let resized = template.image.resize_exact(w, h, FilterType::Triangle);
+                return match_image(image, &resized, options);
+            }
+            match options.color_mode {
+                ColorMode::Luma => match_template_uploaded(
+                    &image.to_luma32f(),
+                    &template.luma,
+                    options.method,
+                    options.padding,
+                ),
+                ColorMode::Rgb => match_template_uploaded_rgb(
+                    &image.to_rgb32f(),
+                    &template.rgb,
+                    options.method,
+                    options.padding,
+                ),
+            }
+        })
+    }
+
+    /// [`SingleMatcher::match_image`] of each of `templates`, all the templates at
+    /// all the scales in one dispatch, see [`match_templates`].
+    pub fn match_images(
+        image: &DynamicImage,
+        templates: &[&DynamicImage],
+        options: &MatcherOptions,
+    ) -> Vec<SingleMatcherResult> {
+        let sizes = templates
+            .iter()
+            .map(|t| scaled_sizes(t.dimensions(), image.dimensions(), options))
+            .collect::<Vec<_>>();
+        let scaled = templates
+            .iter()
+            .zip(&sizes)
+            .flat_map(|(t, sizes)| sizes.iter().map(|(_, (w, h))| resize_image(t, *w, *h)))
+            .collect::<Vec<_>>();
+        let mut matched = match_images(
+            image,
+            &scaled.iter().map(|t| t.as_ref()).collect::<Vec<_>>(),
+            options,
+        )
+        .into_iter();
+        sizes
+            .iter()
+            .map(|sizes| {
+                let results = sizes.iter().map(|&(scale, (w, h))| {
+                    Self::from_matched_image(matched.next().unwrap(), w, h, scale, options)
+                });
+                Self::best(results, options)
+            })
+            .collect()
+    }
+
+    /// The best result of matching the template at each scale of `options`,
+    /// with `match_at` taking the size of the scaled template.
+    fn match_scales(
+        template_size: (u32, u32),
+        image_size: (u32, u32),
+        options: &MatcherOptions,
+        mut match_at: impl FnMut(u32, u32) -> ImageBuffer<Luma<f32>, Vec<f32>>,
+    ) -> SingleMatcherResult {
+        let _span = tracing::debug_span!(
+            "match_template",
+            template = ?template_size,
+            image = ?image_size,
+            method = %options.method,
+        )
+        .entered();
+        let results = scaled_sizes(template_size, image_size, options)
+            .into_iter()
+            .map(|(scale, (w, h))| Self::from_matched_image(match_at(w, h), w, h, scale, options));
+        let best = Self::best(results, options);
+        tracing::trace!(
+            found = best.result.is_some(),
+            score = best.result.map(|m| m.value),
+            scale = best.result.map(|m| m.scale),
+            "matched"
+        );
+        best
+    }
+
+    /// The best of `results`, which is not empty.
+    fn best(
+        results: impl IntoIterator<Item = SingleMatcherResult>,
+        options: &MatcherOptions,
+    ) -> SingleMatcherResult {
+        let mut best: Option<SingleMatcherResult> = None;
+        for res in results {
+            // Below the threshold everywhere, the best candidate is kept
+            let better = match (&res.result, best.as_ref().map(|best| &best.result)) {
+                (_, None) => true,
+                (Some(m), Some(Some(best))) => {
+                    is_a_more_match_than_b(m.value, best.value, options.method)
                 }
+                (Some(_), Some(None)) => true,
+                (None, Some(Some(_))) => false,
+                (None, Some(None)) => {
+                    match (
+                        &res.candidate,
+                        best.as_ref().and_then(|best| best.candidate),
+                    ) {
+                        (Some(m), Some(best)) => {
+                            is_a_more_match_than_b(m.value, best.value, options.method)
+                        }
+                        (Some(_), None) => true,
+                        (None, _) => false,
+                    }
+                }
+            };
+            if better {
+                best = Some(res);
             }
+        }
+        // `scaled_sizes` is never empty
+        best.unwrap()
+    }
+
+    fn from_matched_image(
+        matched_image: ImageBuffer<Luma<f32>, Vec<f32>>,
+        template_width: u32,
+        template_height: u32,
+        scale: f32,
+        options: &MatcherOptions,
+    ) -> SingleMatcherResult {
+        use MatchTemplateMethod::*;
+
+        // A template larger than the image, see `match_templates`
+        if matched_image.width() == 0 || matched_image.height() == 0 {
+            return SingleMatcherResult {
+                result: None,
+                candidate: None,
+                matched_image,
+            };
+        }
+        let extremes = find_extremes(&matched_image);
+        let (value, (x, y), passes) = match options.method {
+            SumOfSquaredDifference | SumOfSquaredDifferenceNormed => (
+                extremes.min_value,
+                extremes.min_value_location,
+                extremes.min_value < options.threshold,
+            ),
             CrossCorrelation
             | CrossCorrelationNormed
             | CorrelationCoefficient
-            | CorrelationCoefficientNormed => {
-                if extremes.max_value > options.threshold {
-                    Some(Match {
-                        rect: Rect {
-                            x: extremes.max_value_location.0,
-                            y: extremes.max_value_location.1,
-                            width: template.width(),
-                            height: template.height(),
-                        },
-                        value: extremes.max_value,
-                    })
-                } else {
-                    None
-                }
-            }
+            | CorrelationCoefficientNormed => (
+                extremes.max_value,
+                extremes.max_value_location,
+                extremes.max_value > options.threshold,
+            ),
+        };
+        let candidate = Match {
+            rect: Rect {
+                x,
+                y,
+                width: template_width,
+                height: template_height,
+            },
+            value,
+            scale,
         };
         SingleMatcherResult {
-            result,
+            result: passes.then_some(candidate),
+            candidate: Some(candidate),
             matched_image,
         }
     }
@@ -127,14 +460,96 @@ impl MultiMatcher {
         template: &ImageBuffer<Luma<f32>, Vec<f32>>,
         options: &MatcherOptions,
     ) -> MultiMatcherResult {
-        use MatchTemplateMethod::*;
+        Self::match_scales(
+            template.dimensions(),
+            image.dimensions(),
+            options,
+            |w, h| {
+                let template = resize_luma(template, w, h);
+                match_template(image, &template, options.method, options.padding)
+            },
+        )
+    }
+
+    /// [`MultiMatcher::match_template`] in the [`ColorMode`] of `options`.
+    pub fn match_image(
+        image: &DynamicImage,
+        template: &DynamicImage,
+        options: &MatcherOptions,
+    ) -> MultiMatcherResult {
+        Self::match_scales(
+            template.dimensions(),
+            image.dimensions(),
+            options,
+            |w, h| {
+                let template = resize_image(template, w, h);
+                match_image(image, &template, options)
+            },
+        )
+    }
+
+    /// The matches at every scale of `options`, where the same spot matches at
+    /// several scales only the best one is kept. The matched image is the one of
+    /// the scale of the best match.
+    fn match_scales(
+        template_size: (u32, u32),
+        image_size: (u32, u32),
+        options: &MatcherOptions,
+        mut match_at: impl FnMut(u32, u32) -> ImageBuffer<Luma<f32>, Vec<f32>>,
+    ) -> MultiMatcherResult {
+        let mut results = scaled_sizes(template_size, image_size, options)
+            .into_iter()
+            .map(|(scale, (w, h))| Self::from_matched_image(match_at(w, h), w, h, scale, options))
+            .collect::<Vec<_>>();
+        if results.len() == 1 {
+            return results.pop().unwrap();
+        }
+
+        let mut matches = results
+            .iter()
+            .flat_map(|res| res.result.iter().copied())
+            .collect::<Vec<_>>();
+        matches.sort_by(|a, b| {
+            if is_a_more_match_than_b(a.value, b.value, options.method) {
+                std::cmp::Ordering::Less
+            } else {
+                std::cmp::Ordering::Greater
+            }
+        });
+        let mut result: Vec<Match> = Vec::new();
+        for m in matches {
+            let overlaps = result.iter().any(|kept| {
+                m.rect.x.abs_diff(kept.rect.x) < kept.rect.width.min(m.rect.width)
+                    && m.rect.y.abs_diff(kept.rect.y) < kept.rect.height.min(m.rect.height)
+            });
+            if !overlaps {
+                result.push(m);
+            }
+        }
 
-        let matched_image = match_template(image, template, options.method, options.padding);
+        let best = results
+            .iter()
+            .position(|res| res.result.first().map(|m| m.scale) == result.first().map(|m| m.scale))
+            .unwrap_or(0);
+        MultiMatcherResult {
+            result,
+            matched_image: results.swap_remove(best).matched_image,
+        }
+    }
+
+    fn from_matched_image(
+        matched_image: ImageBuffer<Luma<f32>, Vec<f32>>,
+        template_width: u32,
+        template_height: u32,
+        scale: f32,
+        options: &MatcherOptions,
+    ) -> MultiMatcherResult {
+        use MatchTemplateMethod::*;
 
         let result = find_matches(
             &matched_image,
-            template.width(),
-            template.height(),
+            template_width,
+            template_height,
             options.method,
             options.threshold,
         )
@@ -146,6 +561,7 @@ impl MultiMatcher {
             | CorrelationCoefficient
             | CorrelationCoefficientNormed => m.value > options.threshold,
         })
+        .map(|m| Match { scale, ..m })
         .collect();
 
         MultiMatcherResult {
@@ -224,6 +640,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_scaled_sizes() {
+        let scales = ScaleRange::new(0.8, 1.2, 5).scales().collect::<Vec<_>>();
+        assert_eq!(scales.len(), 5);
+        assert!((scales[2] - 1.0).abs() < 1e-6);
+
+        let options = MatcherOptions::default();
+        assert_eq!(
+            scaled_sizes((100, 50), (1920, 1080), &options),
+            [(1.0, (100, 50))]
+        );
+
+        // The scales larger than the image are skipped
+        let options = options.with_scales(ScaleRange::new(0.5, 2.0, 2));
+        assert_eq!(
+            scaled_sizes((100, 50), (150, 150), &options),
+            [(0.5, (50, 25))]
+        );
+    }
+
     #[test]
     fn test_best_matcher() {
         let images = [
@@ -0,0 +1,407 @@
+//! Reading text on the screen
+//!
+//! [`Ocr`]: Find the lines of text in an image and read them with a [`TextRecognizer`].
+//! [`GlyphRecognizer`]: A [`TextRecognizer`] for the fixed fonts of games, comparing
+//! every character to images of the glyphs.
+
+use std::path::Path;
+
+use image::{DynamicImage, GrayImage, Luma, imageops::FilterType, math::Rect};
+use imageproc::contrast::otsu_level;
+
+/// A line of text read in an image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextLine {
+    pub text: String,
+    /// Where the line is, in the coordinates of the whole image
+    pub rect: Rect,
+    /// How sure the recognizer is of `text`, in `[0, 1]`
+    pub confidence: f32,
+}
+
+/// Reads a line of text, e.g. a glyph matcher or an ONNX text-recognition model.
+pub trait TextRecognizer: Send + Sync {
+    /// Read `line`, a [binarized](binarize) image of a single line of text with the
+    /// text white on black. Returns the text and how sure it is in `[0, 1]`.
+    fn recognize(&self, line: &GrayImage) -> (String, f32);
+}
+
+/// Binarize `image` with Otsu's threshold, the text white on black.
+///
+/// Text takes less room than its background, so the smaller of the two classes
+/// is taken as the text whether it is light on dark or dark on light.
+pub fn binarize(image: &GrayImage) -> GrayImage {
+    let level = otsu_level(image);
+    let light = image.pixels().filter(|p| p.0[0] > level).count();
+    let text_is_light = light * 2 <= image.as_raw().len();
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        let light = image.get_pixel(x, y).0[0] > level;
+        Luma([if light == text_is_light { 255 } else { 0 }])
+    })
+}
+
+/// Runs of indices in `0..len` for which `filled` is true, runs separated by at
+/// most `max_gap` indices are merged.
+fn runs(len: u32, max_gap: u32, filled: impl Fn(u32) -> bool) -> Vec<(u32, u32)> {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+    for i in (0..len).filter(|&i| filled(i)) {
+        match runs.last_mut() {
+            Some((_, end)) if i - *end <= max_gap + 1 => *end = i,
+            _ => runs.push((i, i)),
+        }
+    }
+    runs
+}
+
+/// Bounding box of the white pixels of `mask` in `rect`.
+fn trim(mask: &GrayImage, rect: Rect) -> Option<Rect> {
+    let white = |x, y| mask.get_pixel(x, y).0[0] > 0;
+    let cols = runs(rect.width, u32::MAX - 1, |i| {
+        (rect.y..rect.y + rect.height).any(|y| white(rect.x + i, y))
+    });
+    let rows = runs(rect.height, u32::MAX - 1, |i| {
+        (rect.x..rect.x + rect.width).any(|x| white(x, rect.y + i))
+    });
+    let (&(x0, x1), &(y0, y1)) = (cols.first()?, rows.first()?);
+    Some(Rect {
+        x: rect.x + x0,
+        y: rect.y + y0,
+        width: x1 - x0 + 1,
+        height: y1 - y0 + 1,
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OcrOptions {
+    /// Rows of text lower than this are taken as noise
+    pub min_line_height: u32,
+    /// Text on the same row is split into several lines at horizontal gaps wider
+    /// than this many times the height of the row, i.e. two separate labels
+    pub line_gap: f32,
+    /// Lines read with a lower confidence are dropped
+    pub min_confidence: f32,
+}
+
+impl Default for OcrOptions {
+    fn default() -> Self {
+        Self {
+            min_line_height: 6,
+            line_gap: 1.5,
+            min_confidence: 0.5,
+        }
+    }
+}
+
+/// Finds the lines of text in an image and reads them with a [`TextRecognizer`].
+pub struct Ocr {
+    recognizer: Box<dyn TextRecognizer>,
+    options: OcrOptions,
+}
+
+impl Ocr {
+    pub fn new(recognizer: impl TextRecognizer + 'static) -> Self {
+        Self {
+            recognizer: Box::new(recognizer),
+            options: OcrOptions::default(),
+        }
+    }
+
+    pub fn with_options(mut self, options: OcrOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// The lines of text in `rect` of `image`, or in all of it, from top to bottom
+    /// and left to right.
+    pub fn read_text(&self, image: &DynamicImage, rect: Option<Rect>) -> Vec<TextLine> {
+        let rect = rect.unwrap_or(Rect {
+            x: 0,
+            y: 0,
+            width: image.width(),
+            height: image.height(),
+        });
+        let mask = binarize(
+            &image
+                .crop_imm(rect.x, rect.y, rect.width, rect.height)
+                .to_luma8(),
+        );
+
+        let mut lines = Vec::new();
+        let rows = runs(mask.height(), 2, |y| {
+            (0..mask.width()).any(|x| mask.get_pixel(x, y).0[0] > 0)
+        });
+        for (y0, y1) in rows {
+            let height = y1 - y0 + 1;
+            if height < self.options.min_line_height {
+                continue;
+            }
+            let max_gap = (height as f32 * self.options.line_gap) as u32;
+            let groups = runs(mask.width(), max_gap, |x| {
+                (y0..=y1).any(|y| mask.get_pixel(x, y).0[0] > 0)
+            });
+            for (x0, x1) in groups {
+                let line = Rect {
+                    x: x0,
+                    y: y0,
+                    width: x1 - x0 + 1,
+                    height,
+                };
+                let Some(line) = trim(&mask, line) else {
+                    continue;
+                };
+                let crop =
+                    image::imageops::crop_imm(&mask, line.x, line.y, line.width, line.height)
+                        .to_image();
+                let (text, confidence) = self.recognizer.recognize(&crop);
+                let text = text.trim().to_string();
+                if text.is_empty() || confidence < self.options.min_confidence {
+                    continue;
+                }
+                lines.push(TextLine {
+                    text,
+                    rect: Rect {
+                        x: rect.x + line.x,
+                        y: rect.y + line.y,
+                        ..line
+                    },
+                    confidence,
+                });
+            }
+        }
+        lines
+    }
+}
+
+/// Glyphs are compared at this size
+const GLYPH_SIZE: u32 = 16;
+
+struct Glyph {
+    ch: char,
+    /// Width over height of the glyph
+    aspect: f32,
+    pixels: Vec<f32>,
+}
+
+impl Glyph {
+    /// `mask` is trimmed to the glyph
+    fn new(ch: char, mask: &GrayImage) -> Self {
+        let resized = image::imageops::resize(mask, GLYPH_SIZE, GLYPH_SIZE, FilterType::Triangle);
+        Self {
+            ch,
+            aspect: mask.width() as f32 / mask.height() as f32,
+            pixels: resized.pixels().map(|p| p.0[0] as f32 / 255.0).collect(),
+        }
+    }
+
+    /// Similarity in `[0, 1]`, shapes of a different aspect ratio are penalized as
+    /// the resizing hides it, i.e. for `-` and `_` against `.`.
+    fn similarity(&self, other: &Glyph) -> f32 {
+        let diff = self
+            .pixels
+            .iter()
+            .zip(&other.pixels)
+            .map(|(a, b)| (a - b).abs())
+            .sum::<f32>()
+            / self.pixels.len() as f32;
+        let aspect = (self.aspect / other.aspect).ln().abs() * 0.25;
+        (1.0 - diff - aspect).max(0.0)
+    }
+}
+
+/// A [`TextRecognizer`] comparing every character to images of the glyphs of a
+/// font. Games draw their text with a few fixed fonts, a handful of glyph images
+/// cut from screenshots is enough to read numbers and labels.
+///
+/// Characters are told apart by the gaps between them, touching characters are
+/// read as one and will not be recognized.
+#[derive(Default)]
+pub struct GlyphRecognizer {
+    glyphs: Vec<Glyph>,
+}
+
+impl GlyphRecognizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an image of `ch`, dark on light or light on dark. Several images of the
+    /// same character can be added, i.e. for several fonts.
+    pub fn add_glyph(&mut self, ch: char, image: &GrayImage) {
+        let mask = binarize(image);
+        let rect = Rect {
+            x: 0,
+            y: 0,
+            width: mask.width(),
+            height: mask.height(),
+        };
+        if let Some(rect) = trim(&mask, rect) {
+            let mask = image::imageops::crop_imm(&mask, rect.x, rect.y, rect.width, rect.height)
+                .to_image();
+            self.glyphs.push(Glyph::new(ch, &mask));
+        }
+    }
+
+    pub fn with_glyph(mut self, ch: char, image: &GrayImage) -> Self {
+        self.add_glyph(ch, image);
+        self
+    }
+
+    /// Load the glyph images in `dir`, each named after its character with anything
+    /// after a `_` ignored, i.e. `7.png` and `7_bold.png`.
+    pub fn from_dir(dir: impl AsRef<Path>) -> image::ImageResult<Self> {
+        let mut recognizer = Self::new();
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let mut chars = stem.split('_').next().unwrap_or_default().chars();
+            let (Some(ch), None) = (chars.next(), chars.next()) else {
+                tracing::warn!(
+                    "skipping glyph {}, not named after a character",
+                    path.display()
+                );
+                continue;
+            };
+            recognizer.add_glyph(ch, &image::open(&path)?.to_luma8());
+        }
+        Ok(recognizer)
+    }
+}
+
+impl TextRecognizer for GlyphRecognizer {
+    fn recognize(&self, line: &GrayImage) -> (String, f32) {
+        let height = line.height();
+        let white = |x, y| line.get_pixel(x, y).0[0] > 0;
+        let chars = runs(line.width(), 0, |x| (0..height).any(|y| white(x, y)));
+
+        let mut text = String::new();
+        let mut total = 0.0;
+        let mut prev_end = None;
+        for &(x0, x1) in &chars {
+            // A gap wider than a third of the line is a space
+            if prev_end.is_some_and(|end| (x0 - end) as f32 > height as f32 / 3.0) {
+                text.push(' ');
+            }
+            prev_end = Some(x1);
+
+            let rect = Rect {
+                x: x0,
+                y: 0,
+                width: x1 - x0 + 1,
+                height,
+            };
+            let Some(rect) = trim(line, rect) else {
+                continue;
+            };
+            let mask =
+                image::imageops::crop_imm(line, rect.x, rect.y, rect.width, rect.height).to_image();
+            let glyph = Glyph::new('?', &mask);
+            let (ch, score) = self
+                .glyphs
+                .iter()
+                .map(|known| (known.ch, known.similarity(&glyph)))
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap_or(('?', 0.0));
+            text.push(ch);
+            total += score;
+        }
+        let confidence = if chars.is_empty() {
+            0.0
+        } else {
+            total / chars.len() as f32
+        };
+        (text, confidence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FONT: [(char, [&str; 7]); 4] = [
+        (
+            '0',
+            [
+                ".###.", "#...#", "#..##", "#.#.#", "##..#", "#...#", ".###.",
+            ],
+        ),
+        (
+            '1',
+            [
+                "..#..", ".##..", "..#..", "..#..", "..#..", "..#..", ".###.",
+            ],
+        ),
+        (
+            '7',
+            [
+                "#####", "....#", "...#.", "..#..", ".#...", ".#...", ".#...",
+            ],
+        ),
+        (
+            'L',
+            [
+                "#....", "#....", "#....", "#....", "#....", "#....", "#####",
+            ],
+        ),
+    ];
+    const SCALE: u32 = 3;
+
+    /// Draw `text` dark on light at `(x, y)`
+    fn draw(image: &mut GrayImage, text: &str, x: u32, y: u32) {
+        let mut x = x;
+        for ch in text.chars() {
+            if let Some((_, rows)) = FONT.iter().find(|(c, _)| *c == ch) {
+                for (row, line) in rows.iter().enumerate() {
+                    for (col, _) in line.char_indices().filter(|(_, c)| *c == '#') {
+                        for d in 0..SCALE * SCALE {
+                            image.put_pixel(
+                                x + col as u32 * SCALE + d % SCALE,
+                                y + row as u32 * SCALE + d / SCALE,
+                                Luma([30]),
+                            );
+                        }
+                    }
+                }
+            }
+            x += 6 * SCALE;
+        }
+    }
+
+    #[test]
+    fn test_read_text() {
+        let mut recognizer = GlyphRecognizer::new();
+        for (ch, _) in FONT {
+            let mut glyph = GrayImage::from_pixel(7 * SCALE, 9 * SCALE, Luma([220]));
+            draw(&mut glyph, &ch.to_string(), SCALE, SCALE);
+            recognizer.add_glyph(ch, &glyph);
+        }
+        let ocr = Ocr::new(recognizer);
+
+        let mut image = GrayImage::from_pixel(400, 120, Luma([220]));
+        draw(&mut image, "10 7", 20, 10);
+        draw(&mut image, "L1", 250, 10);
+        draw(&mut image, "701", 20, 70);
+        let lines = ocr.read_text(&DynamicImage::ImageLuma8(image.clone()), None);
+        let texts: Vec<_> = lines.iter().map(|line| line.text.as_str()).collect();
+        assert_eq!(texts, ["10 7", "L1", "701"]);
+        // The `1` starts a column in
+        assert_eq!(lines[0].rect.x, 20 + SCALE);
+        assert_eq!(lines[0].rect.y, 10);
+        assert_eq!(lines[0].rect.height, 7 * SCALE);
+        assert!(lines.iter().all(|line| line.confidence > 0.9));
+
+        // Light on dark, in a region
+        image::imageops::invert(&mut image);
+        let region = Rect {
+            x: 0,
+            y: 60,
+            width: 200,
+            height: 60,
+        };
+        let lines = ocr.read_text(&DynamicImage::ImageLuma8(image), Some(region));
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].text, "701");
+        assert_eq!(lines[0].rect.y, 70);
+    }
+}
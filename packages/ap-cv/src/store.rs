@@ -0,0 +1,376 @@
+//! Templates decoded and uploaded once, when the resources are loaded
+//!
+//! [`TemplateStore`]: Load templates by path, to match them by [`TemplateHandle`].
+//!
+//! A template can ship a variant per resolution, captured on a screen of that
+//! height, next to it in a directory named after it:
+//!
+//! ```text
+//! templates/start.png
+//! templates/720p/start.png
+//! templates/1440p/start.png
+//! ```
+//!
+//! [`TemplateStore::load_variant`] picks the one of the screen.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use image::{DynamicImage, ImageResult, imageops::FilterType};
+
+use crate::core::template_matching::UploadedTemplate;
+
+/// A template ready to be matched, in every form the matchers take
+pub struct Template {
+    pub image: DynamicImage,
+    pub luma: UploadedTemplate,
+    pub rgb: UploadedTemplate,
+    /// The file it was loaded from, the variant it was rescaled from
+    pub path: Option<PathBuf>,
+}
+
+impl Template {
+    pub fn new(image: DynamicImage) -> Self {
+        let luma = UploadedTemplate::luma(&image.to_luma32f());
+        let rgb = UploadedTemplate::rgb(&image.to_rgb32f());
+        Self {
+            image,
+            luma,
+            rgb,
+            path: None,
+        }
+    }
+
+    pub fn with_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+}
+
+/// A template of a [`TemplateStore`], cheap to clone. It stays valid once
+/// replaced by [`TemplateStore::reload`], and is freed with its last handle.
+/// Handles are equal if they are of the same template.
+#[derive(Clone)]
+pub struct TemplateHandle(Arc<Template>);
+
+impl TemplateHandle {
+    pub fn template(&self) -> Arc<Template> {
+        self.0.clone()
+    }
+}
+
+impl std::fmt::Debug for TemplateHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("TemplateHandle")
+            .field(&Arc::as_ptr(&self.0))
+            .finish()
+    }
+}
+
+impl PartialEq for TemplateHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for TemplateHandle {}
+
+impl std::hash::Hash for TemplateHandle {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.0).hash(state)
+    }
+}
+
+/// Templates keyed by path, each decoded, converted and uploaded to the GPU the
+/// first time it is loaded, so matching it skips all of that.
+#[derive(Default)]
+pub struct TemplateStore {
+    handles: RwLock<HashMap<PathBuf, TemplateHandle>>,
+    /// The variants of each template, listed the first time it is loaded
+    variants: RwLock<HashMap<PathBuf, Vec<(u32, PathBuf)>>>,
+    /// Variants rescaled to a screen height none of them was captured at, by
+    /// variant and screen height, with the height the variant was captured at
+    scaled: RwLock<HashMap<(PathBuf, u32), (u32, TemplateHandle)>>,
+}
+
+impl TemplateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The template at `path`, decoded the first time.
+    pub fn load(&self, path: impl AsRef<Path>) -> ImageResult<TemplateHandle> {
+        let path = path.as_ref();
+        if let Some(handle) = self.get(path) {
+            return Ok(handle);
+        }
+        let image = image::open(path)?;
+        Ok(self.insert(path, image))
+    }
+
+    /// Load every image of `dir`, i.e. the templates of a game at startup.
+    pub fn load_dir(&self, dir: impl AsRef<Path>) -> ImageResult<Vec<TemplateHandle>> {
+        let mut paths = std::fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+        paths
+            .iter()
+            .filter(|path| image::ImageFormat::from_path(path).is_ok())
+            .map(|path| self.load(path))
+            .collect()
+    }
+
+    /// The template at `path` for a screen `screen_height` pixels high, its short
+    /// side: the variant captured at that height, or else the closest one
+    /// rescaled to it, or else `path` itself if it has no variants.
+    ///
+    /// `screen_height` is only called for a template with variants.
+    pub fn load_variant(
+        &self,
+        path: impl AsRef<Path>,
+        screen_height: impl FnOnce() -> u32,
+    ) -> ImageResult<TemplateHandle> {
+        let path = path.as_ref();
+        let cached = self.variants.read().unwrap().get(path).cloned();
+        let variants = match cached {
+            Some(variants) => variants,
+            None => {
+                let variants = variants(path);
+                self.variants
+                    .write()
+                    .unwrap()
+                    .insert(path.to_path_buf(), variants.clone());
+                variants
+            }
+        };
+        if variants.is_empty() {
+            return self.load(path);
+        }
+
+        let height = screen_height();
+        let (variant_height, variant) = variants
+            .iter()
+            .min_by_key(|(variant_height, _)| variant_height.abs_diff(height))
+            .unwrap();
+        if *variant_height == height {
+            return self.load(variant);
+        }
+        let key = (variant.clone(), height);
+        if let Some((_, handle)) = self.scaled.read().unwrap().get(&key) {
+            return Ok(handle.clone());
+        }
+        let handle = rescale(variant, &image::open(variant)?, *variant_height, height);
+        self.scaled
+            .write()
+            .unwrap()
+            .insert(key, (*variant_height, handle.clone()));
+        Ok(handle)
+    }
+
+    /// Add `image` as the template of `path`, replacing the one loaded before.
+    pub fn insert(&self, path: impl Into<PathBuf>, image: DynamicImage) -> TemplateHandle {
+        let path = path.into();
+        let handle = TemplateHandle(Arc::new(Template::new(image).with_path(&path)));
+        self.handles.write().unwrap().insert(path, handle.clone());
+        handle
+    }
+
+    /// Decode the file at `path` again if it was loaded, i.e. after it was
+    /// edited, under every path it was loaded as, replacing the templates rescaled
+    /// from it. Returns whether it was loaded.
+    pub fn reload(&self, path: impl AsRef<Path>) -> ImageResult<bool> {
+        let path = path.as_ref();
+        let canonical = path.canonicalize().ok();
+        let paths = self
+            .handles
+            .read()
+            .unwrap()
+            .keys()
+            .filter(|loaded| {
+                *loaded == path || canonical.is_some() && loaded.canonicalize().ok() == canonical
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let scaled = self
+            .scaled
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|((variant, _), _)| {
+                variant == path || canonical.is_some() && variant.canonicalize().ok() == canonical
+            })
+            .map(|(key, (from, _))| (key.clone(), *from))
+            .collect::<Vec<_>>();
+        // A variant may have been added or removed
+        self.variants.write().unwrap().clear();
+        if paths.is_empty() && scaled.is_empty() {
+            return Ok(false);
+        }
+        let image = image::open(path)?;
+        for loaded in paths {
+            self.insert(loaded, image.clone());
+        }
+        let mut rescaled = self.scaled.write().unwrap();
+        for (key, from) in scaled {
+            let handle = rescale(&key.0, &image, from, key.1);
+            rescaled.insert(key, (from, handle));
+        }
+        Ok(true)
+    }
+
+    pub fn get(&self, path: impl AsRef<Path>) -> Option<TemplateHandle> {
+        self.handles.read().unwrap().get(path.as_ref()).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.handles.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// `image`, the variant at `variant` captured at `from` pixels high, rescaled for
+/// a screen `to` high.
+fn rescale(variant: &Path, image: &DynamicImage, from: u32, to: u32) -> TemplateHandle {
+    let scale = to as f32 / from as f32;
+    let (width, height) = (
+        ((image.width() as f32 * scale).round() as u32).max(1),
+        ((image.height() as f32 * scale).round() as u32).max(1),
+    );
+    let image = image.resize_exact(width, height, FilterType::Triangle);
+    TemplateHandle(Arc::new(Template::new(image).with_path(variant)))
+}
+
+/// The variants of the template at `path` by height, i.e. `720p/start.png` next
+/// to `start.png`, sorted by height.
+pub fn variants(path: impl AsRef<Path>) -> Vec<(u32, PathBuf)> {
+    let path = path.as_ref();
+    let (Some(name), Some(dir)) = (path.file_name(), path.parent()) else {
+        return Vec::new();
+    };
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut variants = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let height = entry
+                .file_name()
+                .to_str()?
+                .strip_suffix('p')?
+                .parse()
+                .ok()?;
+            let variant = entry.path().join(name);
+            variant.is_file().then_some((height, variant))
+        })
+        .collect::<Vec<_>>();
+    variants.sort();
+    variants
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+    use crate::matcher::{MatcherOptions, SingleMatcher};
+
+    #[test]
+    fn test_template_store() {
+        let mut screen = RgbImage::from_pixel(64, 48, Rgb([96, 96, 96]));
+        for y in 10..20 {
+            for x in 30..42 {
+                screen.put_pixel(x, y, Rgb([240, 200, 40]));
+            }
+        }
+        let screen = DynamicImage::ImageRgb8(screen);
+        let template = screen.crop_imm(26, 6, 20, 18);
+
+        let store = TemplateStore::new();
+        let handle = store.insert("button.png", template.clone());
+        assert_eq!(store.get("button.png"), Some(handle.clone()));
+        assert_eq!(store.load("button.png").unwrap(), handle);
+        assert!(store.get("other.png").is_none());
+        // Each store has its own templates
+        assert!(TemplateStore::new().get("button.png").is_none());
+
+        let options = MatcherOptions::default();
+        let expected = SingleMatcher::match_image(&screen, &template, &options)
+            .result
+            .unwrap();
+        let found = SingleMatcher::match_stored(&screen, &handle.template(), &options)
+            .result
+            .unwrap();
+        assert_eq!(found.rect, expected.rect);
+        assert_eq!((found.rect.x, found.rect.y), (26, 6));
+
+        // A template replaced stays valid until its last handle is dropped
+        let replaced = Arc::downgrade(&handle.template());
+        store.insert("button.png", template);
+        assert!(replaced.upgrade().is_some());
+        drop(handle);
+        assert!(replaced.upgrade().is_none());
+    }
+
+    #[test]
+    fn test_variants() {
+        let dir = std::env::temp_dir().join(format!("ap-variants-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (height, size) in [(720, 20), (1080, 30)] {
+            std::fs::create_dir_all(dir.join(format!("{height}p"))).unwrap();
+            DynamicImage::new_rgb8(size, size)
+                .save(dir.join(format!("{height}p/start.png")))
+                .unwrap();
+        }
+        let path = dir.join("start.png");
+        assert_eq!(variants(&path).len(), 2);
+
+        let store = TemplateStore::new();
+        let width = |height| {
+            let handle = store.load_variant(&path, || height).unwrap();
+            handle.template().image.width()
+        };
+        assert_eq!(width(720), 20);
+        assert_eq!(width(1080), 30);
+        // Rescaled from the closest one
+        assert_eq!(width(1440), 40);
+        assert_eq!(width(540), 15);
+        assert_eq!(
+            store.load_variant(&path, || 1440).unwrap(),
+            store.load_variant(&path, || 1440).unwrap()
+        );
+
+        // Rescaled again from the variant edited
+        let rescaled = store.load_variant(&path, || 1440).unwrap();
+        DynamicImage::new_rgb8(33, 33)
+            .save(dir.join("1080p/start.png"))
+            .unwrap();
+        assert!(store.reload(dir.join("1080p/start.png")).unwrap());
+        let reloaded = store.load_variant(&path, || 1440).unwrap();
+        assert_ne!(reloaded, rescaled);
+        assert_eq!(reloaded.template().image.width(), 44);
+        assert_eq!(width(1080), 33);
+
+        // Without variants the path is loaded as is, whatever the screen
+        DynamicImage::new_rgb8(8, 8)
+            .save(dir.join("icon.png"))
+            .unwrap();
+        let handle = store
+            .load_variant(dir.join("icon.png"), || unreachable!())
+            .unwrap();
+        assert_eq!(handle.template().image.width(), 8);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+/* C ABI of auto-play, implemented by the auto-play-ffi crate. */
+
+#ifndef AUTO_PLAY_H
+#define AUTO_PLAY_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define AP_OK 0
+#define AP_ERROR -1
+#define AP_BUFFER_TOO_SMALL -2
+#define AP_INVALID_ARGUMENT -3
+#define AP_CANCELLED -4
+
+#define AP_EVENT_CLICK 1
+#define AP_EVENT_SWIPE 2
+#define AP_EVENT_PRESS 3
+#define AP_EVENT_TASK_STARTED 4
+#define AP_EVENT_STEP_STARTED 5
+#define AP_EVENT_TASK_FINISHED 6
+#define AP_EVENT_CONTROLLER_SWAPPED 7
+#define AP_EVENT_RESOLUTION_CHANGED 8
+#define AP_EVENT_REPEAT_FINISHED 9
+#define AP_EVENT_STEP_FINISHED 10
+#define AP_EVENT_MATCH_RESULT 11
+#define AP_EVENT_RESOURCE_RELOADED 12
+#define AP_EVENT_GUARD 13
+
+typedef struct ApHandle ApHandle;
+
+/* Strings are only valid during the callback. */
+typedef struct ApEvent {
+    int kind;             /* one of AP_EVENT_* */
+    int32_t x;            /* click position, start of a swipe, new screen size, top left of a
+                             match, or 1 if a repeat is done */
+    int32_t y;
+    int32_t x2;           /* end of a swipe, or bottom right of a match */
+    int32_t y2;
+    uint64_t duration_ms; /* duration of a swipe or a step */
+    uint64_t index;       /* index of a step, iterations of a repeat, or 1 if a template was found */
+    const char *name;     /* name of the task, path of a reloaded file, what the guard did
+                             ("paused", "resumed" or "aborted"), or NULL */
+    const char *detail;   /* pressed key, action of a step, error of a finished step or task
+                             or of a reload, why the guard acted, or NULL */
+} ApEvent;
+
+/* Pixels owned by the caller, released with ap_image_free. */
+typedef struct ApImage {
+    uint8_t *data; /* RGBA8 pixels, width * height * 4 bytes, or NULL */
+    size_t len;
+    uint32_t width;
+    uint32_t height;
+} ApImage;
+
+typedef void (*ApEventCallback)(const ApEvent *event, void *user_data);
+
+/* Message of the last error on the calling thread, or NULL. */
+const char *ap_last_error(void);
+
+/* Return NULL on failure. */
+ApHandle *ap_connect(const char *serial);
+/* Only available on macOS, or when built with the `windows` or `linux` feature. */
+ApHandle *ap_connect_window(const char *title);
+/* The event callback is not called anymore once it returns. */
+void ap_free(ApHandle *handle);
+
+int ap_screen_size(const ApHandle *handle, uint32_t *width, uint32_t *height);
+/* Copy RGBA8 pixels into buf. width and height are always set, if buf is NULL
+ * or smaller than width * height * 4 bytes AP_BUFFER_TOO_SMALL is returned. */
+int ap_screencap(const ApHandle *handle, uint8_t *buf, size_t buf_len, uint32_t *width,
+                 uint32_t *height);
+/* Allocate the pixels, image is left empty on failure. */
+int ap_screencap_image(const ApHandle *handle, ApImage *image);
+/* Release the pixels and empty image, NULL and empty images are ignored. */
+void ap_image_free(ApImage *image);
+int ap_click(const ApHandle *handle, uint32_t x, uint32_t y);
+int ap_swipe(const ApHandle *handle, uint32_t x1, uint32_t y1, int32_t x2, int32_t y2,
+             uint64_t duration_ms);
+
+/* Block until the task is done, or return AP_CANCELLED once ap_cancel is called. */
+int ap_run_task(const ApHandle *handle, const char *path);
+int ap_run_task_toml(const ApHandle *handle, const char *toml);
+/* Called from another thread, take effect before the next step of the running task,
+ * or of the next one started if none is running. */
+int ap_cancel(const ApHandle *handle);
+int ap_pause(const ApHandle *handle);
+int ap_resume(const ApHandle *handle);
+
+/* Called from a background thread, pass NULL to remove it. It may call back into
+ * the other functions with the handle, and is not running anymore once replaced. */
+int ap_set_event_callback(const ApHandle *handle, ApEventCallback callback, void *user_data);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* AUTO_PLAY_H */
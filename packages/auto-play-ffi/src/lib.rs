@@ -0,0 +1,831 @@
+//! C ABI for auto-play, to embed it in C#/C++ frontends
+//!
+//! See `include/auto_play.h` for the declarations. Every function returning a
+//! `c_int` returns [`AP_OK`] on success or a negative error code, the message of
+//! the last error on the calling thread is available from [`ap_last_error`].
+
+use std::{
+    cell::RefCell,
+    ffi::{CStr, CString, c_char, c_int, c_void},
+    panic::{AssertUnwindSafe, catch_unwind},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+use auto_play::{AndroidController, AutoPlay, CancellationToken, Error, event::Event, task::Task};
+
+pub const AP_OK: c_int = 0;
+pub const AP_ERROR: c_int = -1;
+pub const AP_BUFFER_TOO_SMALL: c_int = -2;
+pub const AP_INVALID_ARGUMENT: c_int = -3;
+pub const AP_CANCELLED: c_int = -4;
+
+pub const AP_EVENT_CLICK: c_int = 1;
+pub const AP_EVENT_SWIPE: c_int = 2;
+pub const AP_EVENT_PRESS: c_int = 3;
+pub const AP_EVENT_TASK_STARTED: c_int = 4;
+pub const AP_EVENT_STEP_STARTED: c_int = 5;
+pub const AP_EVENT_TASK_FINISHED: c_int = 6;
+pub const AP_EVENT_CONTROLLER_SWAPPED: c_int = 7;
+pub const AP_EVENT_RESOLUTION_CHANGED: c_int = 8;
+pub const AP_EVENT_REPEAT_FINISHED: c_int = 9;
+pub const AP_EVENT_STEP_FINISHED: c_int = 10;
+pub const AP_EVENT_MATCH_RESULT: c_int = 11;
+pub const AP_EVENT_RESOURCE_RELOADED: c_int = 12;
+pub const AP_EVENT_GUARD: c_int = 13;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(msg: String) {
+    let msg = CString::new(msg.replace('\0', "")).unwrap();
+    LAST_ERROR.with(|err| *err.borrow_mut() = Some(msg));
+}
+
+/// Run `f`, turning errors and panics into an error code.
+fn ffi(f: impl FnOnce() -> anyhow::Result<c_int>) -> c_int {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(code)) => code,
+        Ok(Err(err)) => {
+            set_last_error(format!("{err:#}"));
+            match err.downcast_ref::<Error>() {
+                Some(Error::Cancelled) => AP_CANCELLED,
+                _ => AP_ERROR,
+            }
+        }
+        Err(_) => {
+            set_last_error("panicked".to_string());
+            AP_ERROR
+        }
+    }
+}
+
+/// # Safety
+///
+/// `s` should be null or a valid nul terminated string.
+unsafe fn str_arg<'a>(s: *const c_char) -> anyhow::Result<&'a str> {
+    anyhow::ensure!(!s.is_null(), "unexpected null string");
+    // SAFETY: guaranteed by the caller
+    Ok(unsafe { CStr::from_ptr(s) }.to_str()?)
+}
+
+/// # Safety
+///
+/// `handle` should be null or a handle returned by `ap_connect*` not freed yet.
+unsafe fn handle_arg<'a>(handle: *const ApHandle) -> anyhow::Result<&'a ApHandle> {
+    // SAFETY: guaranteed by the caller
+    unsafe { handle.as_ref() }.ok_or_else(|| anyhow::anyhow!("unexpected null handle"))
+}
+
+/// An [`Event`] as passed to an [`ApEventCallback`], strings are only valid
+/// during the callback.
+#[repr(C)]
+pub struct ApEvent {
+    /// One of `AP_EVENT_*`
+    pub kind: c_int,
+    /// Click position, start of a swipe, new screen size, top left of a match, or
+    /// 1 if a repeat is done
+    pub x: i32,
+    pub y: i32,
+    /// End of a swipe, or bottom right of a match
+    pub x2: i32,
+    pub y2: i32,
+    /// Duration of a swipe or a step
+    pub duration_ms: u64,
+    /// Index of a step, iterations of a repeat, or 1 if a template was found
+    pub index: u64,
+    /// Name of the task, path of a reloaded file, or null
+    pub name: *const c_char,
+    /// Pressed key, action of a step, error of a finished step or task or of a
+    /// reload, or null
+    pub detail: *const c_char,
+}
+
+pub type ApEventCallback = extern "C" fn(event: *const ApEvent, user_data: *mut c_void);
+
+#[derive(Clone, Copy)]
+struct Callback {
+    callback: ApEventCallback,
+    user_data: *mut c_void,
+}
+
+// SAFETY: the caller of `ap_set_event_callback` agrees to `user_data` being used
+// from the event thread.
+unsafe impl Send for Callback {}
+
+impl Callback {
+    fn call(&self, event: &Event) {
+        let cstring = |s: &str| CString::new(s.replace('\0', "")).unwrap();
+        let mut ev = ApEvent {
+            kind: 0,
+            x: 0,
+            y: 0,
+            x2: 0,
+            y2: 0,
+            duration_ms: 0,
+            index: 0,
+            name: std::ptr::null(),
+            detail: std::ptr::null(),
+        };
+        let (mut name, mut detail) = (None, None);
+        match event {
+            Event::Click { x, y } => {
+                ev.kind = AP_EVENT_CLICK;
+                (ev.x, ev.y) = (*x as i32, *y as i32);
+            }
+            Event::Swipe {
+                start,
+                end,
+                duration,
+            } => {
+                ev.kind = AP_EVENT_SWIPE;
+                (ev.x, ev.y) = (start.0 as i32, start.1 as i32);
+                (ev.x2, ev.y2) = *end;
+                ev.duration_ms = duration.as_millis() as u64;
+            }
+            Event::Press { key } => {
+                ev.kind = AP_EVENT_PRESS;
+                detail = Some(cstring(&format!("{key:?}")));
+            }
+            Event::TaskStarted { name: task } => {
+                ev.kind = AP_EVENT_TASK_STARTED;
+                name = Some(cstring(task));
+            }
+            Event::StepStarted {
+                task,
+                index,
+                action,
+            } => {
+                ev.kind = AP_EVENT_STEP_STARTED;
+                ev.index = *index as u64;
+                name = Some(cstring(task));
+                detail = Some(cstring(action));
+            }
+            Event::TaskFinished { name: task, error } => {
+                ev.kind = AP_EVENT_TASK_FINISHED;
+                name = Some(cstring(task));
+                detail = error.as_deref().map(cstring);
+            }
+            Event::ControllerSwapped { screen_size, .. } => {
+                ev.kind = AP_EVENT_CONTROLLER_SWAPPED;
+                (ev.x, ev.y) = (screen_size.0 as i32, screen_size.1 as i32);
+            }
+            Event::ResolutionChanged { screen_size, .. } => {
+                ev.kind = AP_EVENT_RESOLUTION_CHANGED;
+                (ev.x, ev.y) = (screen_size.0 as i32, screen_size.1 as i32);
+            }
+            Event::RepeatFinished { iterations, done } => {
+                ev.kind = AP_EVENT_REPEAT_FINISHED;
+                ev.index = *iterations as u64;
+                ev.x = *done as i32;
+            }
+            Event::StepFinished {
+                task,
+                index,
+                duration,
+                error,
+            } => {
+                ev.kind = AP_EVENT_STEP_FINISHED;
+                ev.index = *index as u64;
+                ev.duration_ms = duration.as_millis() as u64;
+                name = Some(cstring(task));
+                detail = error.as_deref().map(cstring);
+            }
+            Event::MatchResult { rect, .. } => {
+                ev.kind = AP_EVENT_MATCH_RESULT;
+                if let Some(rect) = rect {
+                    ev.index = 1;
+                    (ev.x, ev.y) = (rect.x as i32, rect.y as i32);
+                    (ev.x2, ev.y2) = ((rect.x + rect.width) as i32, (rect.y + rect.height) as i32);
+                }
+            }
+            Event::ResourceReloaded { path, error } => {
+                ev.kind = AP_EVENT_RESOURCE_RELOADED;
+                name = Some(cstring(&path.to_string_lossy()));
+                detail = error.as_deref().map(cstring);
+            }
+            Event::Guard { action, reason } => {
+                ev.kind = AP_EVENT_GUARD;
+                name = Some(cstring(&action.to_string()));
+                detail = Some(cstring(reason));
+            }
+            // Frames are not passed through the C ABI, capture the screen instead
+            Event::AnnotatedFrame { .. }
+            | Event::MatchFrame { .. }
+            | Event::MatchOverlay { .. } => return,
+        }
+        ev.name = name.as_ref().map_or(std::ptr::null(), |s| s.as_ptr());
+        ev.detail = detail.as_ref().map_or(std::ptr::null(), |s| s.as_ptr());
+        (self.callback)(&ev, self.user_data);
+    }
+}
+
+/// How often the event thread checks whether its handle was freed
+const EVENT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The callback of a handle and the thread calling it
+struct EventThread {
+    callback: Mutex<Option<Callback>>,
+    /// Held while the callback runs, without `callback` being locked so it can
+    /// call back into `ap_*`
+    calling: Mutex<()>,
+    stop: AtomicBool,
+}
+
+impl EventThread {
+    fn run(&self, events: mpsc::Receiver<Event>) {
+        while !self.stop.load(Ordering::Acquire) {
+            let event = match events.recv_timeout(EVENT_POLL_INTERVAL) {
+                Ok(event) => event,
+                Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            };
+            let _calling = self.calling.lock().unwrap();
+            // Copied out so the lock is released before calling it
+            let callback = *self.callback.lock().unwrap();
+            if let Some(callback) = callback
+                && !self.stop.load(Ordering::Acquire)
+            {
+                callback.call(&event);
+            }
+        }
+    }
+}
+
+/// An opaque handle to an [`AutoPlay`]
+pub struct ApHandle {
+    ap: Arc<AutoPlay>,
+    events: Arc<EventThread>,
+    event_thread: Option<thread::JoinHandle<()>>,
+    /// Of the task running, or the next one if none is
+    token: Mutex<CancellationToken>,
+}
+
+impl ApHandle {
+    fn new(ap: AutoPlay) -> Self {
+        let receiver = ap.subscribe();
+        let events = Arc::new(EventThread {
+            callback: Mutex::new(None),
+            calling: Mutex::new(()),
+            stop: AtomicBool::new(false),
+        });
+        let event_thread = {
+            let events = events.clone();
+            thread::spawn(move || events.run(receiver))
+        };
+        Self {
+            ap: Arc::new(ap),
+            events,
+            event_thread: Some(event_thread),
+            token: Mutex::new(CancellationToken::new()),
+        }
+    }
+
+    /// Whether this is called from the callback, which cannot wait for itself
+    fn on_event_thread(&self) -> bool {
+        self.event_thread
+            .as_ref()
+            .is_some_and(|handle| handle.thread().id() == thread::current().id())
+    }
+
+    /// Replace the callback, once the previous one is not running anymore
+    /// unless it is the one replacing itself.
+    fn set_callback(&self, callback: Option<Callback>) {
+        *self.events.callback.lock().unwrap() = callback;
+        if !self.on_event_thread() {
+            drop(self.events.calling.lock().unwrap());
+        }
+    }
+
+    /// A new token for a task about to run.
+    /// Run a task with the token, which is replaced once it is done so stopping
+    /// it does not stop the next one.
+    fn run_task(
+        &self,
+        f: impl FnOnce(&CancellationToken) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let token = self.token();
+        let res = f(&token);
+        *self.token.lock().unwrap() = CancellationToken::new();
+        res
+    }
+
+    fn token(&self) -> CancellationToken {
+        self.token.lock().unwrap().clone()
+    }
+
+    fn into_raw(self) -> *mut ApHandle {
+        Box::into_raw(Box::new(self))
+    }
+}
+
+impl Drop for ApHandle {
+    /// The callback is not called anymore once the handle is dropped.
+    fn drop(&mut self) {
+        self.events.stop.store(true, Ordering::Release);
+        let on_event_thread = self.on_event_thread();
+        if let Some(handle) = self.event_thread.take()
+            && !on_event_thread
+        {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// The message of the last error on this thread, or null. Valid until the next
+/// failing call on this thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn ap_last_error() -> *const c_char {
+    LAST_ERROR.with(|err| {
+        err.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |err| err.as_ptr())
+    })
+}
+
+/// Connect to an Android device by its adb serial, returns null on failure.
+///
+/// # Safety
+///
+/// `serial` should be a valid nul terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ap_connect(serial: *const c_char) -> *mut ApHandle {
+    let mut handle = std::ptr::null_mut();
+    ffi(|| {
+        // SAFETY: guaranteed by the caller
+        let serial = unsafe { str_arg(serial)? };
+        let controller = AndroidController::connect(serial)?;
+        handle = ApHandle::new(AutoPlay::new(controller)).into_raw();
+        Ok(AP_OK)
+    });
+    handle
+}
+
+/// Attach to a desktop window by its exact title, returns null on failure.
+///
+/// # Safety
+///
+/// `title` should be a valid nul terminated string.
+#[cfg(any(feature = "windows", feature = "linux", target_os = "macos"))]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ap_connect_window(title: *const c_char) -> *mut ApHandle {
+    let mut handle = std::ptr::null_mut();
+    ffi(|| {
+        // SAFETY: guaranteed by the caller
+        let title = unsafe { str_arg(title)? };
+        let controller = auto_play::DesktopController::from_window_title(title)?;
+        handle = ApHandle::new(AutoPlay::new(controller)).into_raw();
+        Ok(AP_OK)
+    });
+    handle
+}
+
+/// Release a handle, null is ignored. The event callback is not called anymore
+/// once it returns.
+///
+/// # Safety
+///
+/// `handle` should be null or a handle not freed yet, it is invalid afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ap_free(handle: *mut ApHandle) {
+    if !handle.is_null() {
+        // SAFETY: guaranteed by the caller
+        drop(unsafe { Box::from_raw(handle) });
+    }
+}
+
+/// # Safety
+///
+/// `handle` should be a valid handle, `width` and `height` valid pointers.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ap_screen_size(
+    handle: *const ApHandle,
+    width: *mut u32,
+    height: *mut u32,
+) -> c_int {
+    ffi(|| {
+        // SAFETY: guaranteed by the caller
+        let handle = unsafe { handle_arg(handle)? };
+        if width.is_null() || height.is_null() {
+            return Ok(AP_INVALID_ARGUMENT);
+        }
+        let (w, h) = handle.ap.screen_size();
+        // SAFETY: guaranteed by the caller
+        unsafe { (*width, *height) = (w, h) };
+        Ok(AP_OK)
+    })
+}
+
+/// Take a screenshot into `buf` as RGBA8 pixels, `width * height * 4` bytes.
+///
+/// `width` and `height` are always set to the size of the screenshot, if `buf`
+/// is null or `buf_len` is too small nothing is copied and
+/// `AP_BUFFER_TOO_SMALL` is returned, so the caller can allocate and retry.
+///
+/// # Safety
+///
+/// `handle` should be a valid handle, `buf` null or valid for `buf_len` bytes,
+/// `width` and `height` valid pointers.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ap_screencap(
+    handle: *const ApHandle,
+    buf: *mut u8,
+    buf_len: usize,
+    width: *mut u32,
+    height: *mut u32,
+) -> c_int {
+    ffi(|| {
+        // SAFETY: guaranteed by the caller
+        let handle = unsafe { handle_arg(handle)? };
+        if width.is_null() || height.is_null() {
+            return Ok(AP_INVALID_ARGUMENT);
+        }
+        let (w, h, rgba) = handle.ap.screencap_raw()?;
+        // SAFETY: guaranteed by the caller
+        unsafe { (*width, *height) = (w, h) };
+        if buf.is_null() || buf_len < rgba.len() {
+            return Ok(AP_BUFFER_TOO_SMALL);
+        }
+        // SAFETY: `buf` is valid for `buf_len >= rgba.len()` bytes
+        unsafe { std::ptr::copy_nonoverlapping(rgba.as_ptr(), buf, rgba.len()) };
+        Ok(AP_OK)
+    })
+}
+
+/// Screenshot pixels owned by the caller, see [`ap_screencap_image`]
+#[repr(C)]
+pub struct ApImage {
+    /// RGBA8 pixels, `width * height * 4` bytes, or null
+    pub data: *mut u8,
+    pub len: usize,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Take a screenshot into a buffer allocated by the library, to be released with
+/// [`ap_image_free`]. `image` is left empty on failure.
+///
+/// # Safety
+///
+/// `handle` should be a valid handle, `image` a valid pointer.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ap_screencap_image(handle: *const ApHandle, image: *mut ApImage) -> c_int {
+    ffi(|| {
+        // SAFETY: guaranteed by the caller
+        let (handle, image) = unsafe { (handle_arg(handle)?, image.as_mut()) };
+        let Some(image) = image else {
+            return Ok(AP_INVALID_ARGUMENT);
+        };
+        *image = ApImage {
+            data: std::ptr::null_mut(),
+            len: 0,
+            width: 0,
+            height: 0,
+        };
+        let (width, height, rgba) = handle.ap.screencap_raw()?;
+        let len = rgba.len();
+        *image = ApImage {
+            data: Box::into_raw(rgba.into_boxed_slice()).cast(),
+            len,
+            width,
+            height,
+        };
+        Ok(AP_OK)
+    })
+}
+
+/// Release the pixels of an image filled by [`ap_screencap_image`] and empty it,
+/// null and empty images are ignored.
+///
+/// # Safety
+///
+/// `image` should be null or an image filled by [`ap_screencap_image`] and not
+/// modified since.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ap_image_free(image: *mut ApImage) {
+    // SAFETY: guaranteed by the caller
+    let Some(image) = (unsafe { image.as_mut() }) else {
+        return;
+    };
+    if !image.data.is_null() {
+        // SAFETY: allocated as a boxed slice of `len` bytes by `ap_screencap_image`
+        drop(unsafe { Box::from_raw(std::ptr::slice_from_raw_parts_mut(image.data, image.len)) });
+    }
+    image.data = std::ptr::null_mut();
+    image.len = 0;
+}
+
+/// # Safety
+///
+/// `handle` should be a valid handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ap_click(handle: *const ApHandle, x: u32, y: u32) -> c_int {
+    ffi(|| {
+        // SAFETY: guaranteed by the caller
+        let handle = unsafe { handle_arg(handle)? };
+        handle.ap.click(x, y)?;
+        Ok(AP_OK)
+    })
+}
+
+/// # Safety
+///
+/// `handle` should be a valid handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ap_swipe(
+    handle: *const ApHandle,
+    x1: u32,
+    y1: u32,
+    x2: i32,
+    y2: i32,
+    duration_ms: u64,
+) -> c_int {
+    ffi(|| {
+        // SAFETY: guaranteed by the caller
+        let handle = unsafe { handle_arg(handle)? };
+        handle.ap.swipe(
+            (x1, y1),
+            (x2, y2),
+            Duration::from_millis(duration_ms),
+            1.0,
+            1.0,
+        )?;
+        Ok(AP_OK)
+    })
+}
+
+/// Run the task in the TOML file at `path`, blocking until it is done or
+/// cancelled with [`ap_cancel`], which returns [`AP_CANCELLED`].
+///
+/// # Safety
+///
+/// `handle` should be a valid handle, `path` a valid nul terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ap_run_task(handle: *const ApHandle, path: *const c_char) -> c_int {
+    ffi(|| {
+        // SAFETY: guaranteed by the caller
+        let (handle, path) = unsafe { (handle_arg(handle)?, str_arg(path)?) };
+        let task = Task::load(path)?;
+        handle.run_task(|token| task.execute_cancellable(&handle.ap, token))?;
+        Ok(AP_OK)
+    })
+}
+
+/// Run a task given as TOML source, blocking until it is done.
+///
+/// # Safety
+///
+/// `handle` should be a valid handle, `toml` a valid nul terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ap_run_task_toml(handle: *const ApHandle, toml: *const c_char) -> c_int {
+    ffi(|| {
+        // SAFETY: guaranteed by the caller
+        let (handle, toml) = unsafe { (handle_arg(handle)?, str_arg(toml)?) };
+        let task = Task::from_toml(toml)?;
+        handle.run_task(|token| task.execute_cancellable(&handle.ap, token))?;
+        Ok(AP_OK)
+    })
+}
+
+/// Stop the task running on `handle` before its next step, from another thread,
+/// or the next one started if none is running.
+///
+/// # Safety
+///
+/// `handle` should be a valid handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ap_cancel(handle: *const ApHandle) -> c_int {
+    ffi(|| {
+        // SAFETY: guaranteed by the caller
+        unsafe { handle_arg(handle)? }.token().cancel();
+        Ok(AP_OK)
+    })
+}
+
+/// Hold the task running on `handle` before its next step until [`ap_resume`],
+/// or the next one started if none is running.
+///
+/// # Safety
+///
+/// `handle` should be a valid handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ap_pause(handle: *const ApHandle) -> c_int {
+    ffi(|| {
+        // SAFETY: guaranteed by the caller
+        unsafe { handle_arg(handle)? }.token().pause();
+        Ok(AP_OK)
+    })
+}
+
+/// # Safety
+///
+/// `handle` should be a valid handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ap_resume(handle: *const ApHandle) -> c_int {
+    ffi(|| {
+        // SAFETY: guaranteed by the caller
+        unsafe { handle_arg(handle)? }.token().resume();
+        Ok(AP_OK)
+    })
+}
+
+/// Call `callback` with every event of `handle`, from a background thread.
+/// Passing a null callback removes it.
+///
+/// # Safety
+///
+/// `handle` should be a valid handle, `user_data` should be usable from another
+/// thread until the callback is replaced or the handle is freed. The callback
+/// may call back into `ap_*` with `handle`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn ap_set_event_callback(
+    handle: *const ApHandle,
+    callback: Option<ApEventCallback>,
+    user_data: *mut c_void,
+) -> c_int {
+    ffi(|| {
+        // SAFETY: guaranteed by the caller
+        let handle = unsafe { handle_arg(handle)? };
+        handle.set_callback(callback.map(|callback| Callback {
+            callback,
+            user_data,
+        }));
+        Ok(AP_OK)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use auto_play::{ControllerTrait, controller::Key};
+
+    use super::*;
+
+    struct DummyController;
+
+    impl ControllerTrait for DummyController {
+        fn screen_size(&self) -> (u32, u32) {
+            (2, 1)
+        }
+
+        fn screencap_raw(&self) -> anyhow::Result<(u32, u32, Vec<u8>)> {
+            Ok((2, 1, vec![1, 2, 3, 4, 5, 6, 7, 8]))
+        }
+
+        fn screencap(&self) -> anyhow::Result<auto_play::DynamicImage> {
+            unimplemented!()
+        }
+
+        fn click(&self, _x: u32, _y: u32) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn swipe(
+            &self,
+            _start: (u32, u32),
+            _end: (i32, i32),
+            _duration: Duration,
+            _slope_in: f32,
+            _slope_out: f32,
+        ) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn press(&self, _key: Key) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_screencap() {
+        let handle = ApHandle::new(AutoPlay::new(DummyController)).into_raw();
+        let (mut width, mut height) = (0, 0);
+        unsafe {
+            let res = ap_screencap(handle, std::ptr::null_mut(), 0, &mut width, &mut height);
+            assert_eq!(res, AP_BUFFER_TOO_SMALL);
+            assert_eq!((width, height), (2, 1));
+
+            let mut buf = vec![0; (width * height * 4) as usize];
+            let res = ap_screencap(handle, buf.as_mut_ptr(), buf.len(), &mut width, &mut height);
+            assert_eq!(res, AP_OK);
+            assert_eq!(buf, [1, 2, 3, 4, 5, 6, 7, 8]);
+            ap_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_screencap_image() {
+        let handle = ApHandle::new(AutoPlay::new(DummyController)).into_raw();
+        let mut image = ApImage {
+            data: std::ptr::null_mut(),
+            len: 0,
+            width: 0,
+            height: 0,
+        };
+        unsafe {
+            assert_eq!(ap_screencap_image(handle, &mut image), AP_OK);
+            assert_eq!((image.width, image.height), (2, 1));
+            let pixels = std::slice::from_raw_parts(image.data, image.len);
+            assert_eq!(pixels, [1, 2, 3, 4, 5, 6, 7, 8]);
+            ap_image_free(&mut image);
+            assert!(image.data.is_null());
+            ap_image_free(&mut image);
+            assert_eq!(
+                ap_screencap_image(handle, std::ptr::null_mut()),
+                AP_INVALID_ARGUMENT
+            );
+            ap_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        unsafe {
+            assert!(ap_connect(std::ptr::null()).is_null());
+            let err = CStr::from_ptr(ap_last_error()).to_str().unwrap();
+            assert_eq!(err, "unexpected null string");
+            assert_eq!(ap_click(std::ptr::null(), 0, 0), AP_ERROR);
+        }
+    }
+
+    #[test]
+    fn test_cancel_before_start() {
+        let toml = CString::new("name = \"click\"\n[[steps]]\nClick = { x = 1, y = 0 }").unwrap();
+        let handle = ApHandle::new(AutoPlay::new(DummyController)).into_raw();
+        unsafe {
+            // Stops the next task, and only that one
+            assert_eq!(ap_cancel(handle), AP_OK);
+            assert_eq!(ap_run_task_toml(handle, toml.as_ptr()), AP_CANCELLED);
+            assert_eq!(ap_run_task_toml(handle, toml.as_ptr()), AP_OK);
+            ap_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_event_callback() {
+        extern "C" fn callback(event: *const ApEvent, user_data: *mut c_void) {
+            let tx = unsafe { &*(user_data as *const mpsc::Sender<(c_int, i32, i32)>) };
+            let event = unsafe { &*event };
+            tx.send((event.kind, event.x, event.y)).unwrap();
+        }
+
+        let (tx, rx) = mpsc::channel::<(c_int, i32, i32)>();
+        let handle = ApHandle::new(AutoPlay::new(DummyController)).into_raw();
+        unsafe {
+            let user_data = &tx as *const _ as *mut c_void;
+            assert_eq!(
+                ap_set_event_callback(handle, Some(callback), user_data),
+                AP_OK
+            );
+            assert_eq!(ap_click(handle, 3, 4), AP_OK);
+            let event = rx.recv_timeout(Duration::from_secs(1)).unwrap();
+            assert_eq!(event, (AP_EVENT_CLICK, 3, 4));
+            ap_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_event_callback_reentrant() {
+        struct Data {
+            handle: *mut ApHandle,
+            tx: mpsc::Sender<c_int>,
+        }
+        extern "C" fn callback(event: *const ApEvent, user_data: *mut c_void) {
+            let data = unsafe { &*(user_data as *const Data) };
+            // Removing itself from the callback does not wait for itself
+            let res = unsafe { ap_set_event_callback(data.handle, None, std::ptr::null_mut()) };
+            data.tx.send(res).unwrap();
+            data.tx.send(unsafe { &*event }.kind).unwrap();
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let handle = ApHandle::new(AutoPlay::new(DummyController)).into_raw();
+        let data = Data { handle, tx };
+        unsafe {
+            let user_data = &data as *const _ as *mut c_void;
+            assert_eq!(
+                ap_set_event_callback(handle, Some(callback), user_data),
+                AP_OK
+            );
+            assert_eq!(ap_click(handle, 3, 4), AP_OK);
+            assert_eq!(rx.recv_timeout(Duration::from_secs(1)).unwrap(), AP_OK);
+            assert_eq!(
+                rx.recv_timeout(Duration::from_secs(1)).unwrap(),
+                AP_EVENT_CLICK
+            );
+            // Removed, so not called anymore
+            assert_eq!(ap_click(handle, 3, 4), AP_OK);
+            ap_free(handle);
+        }
+        // The event thread is joined, so nothing can be sent anymore
+        drop(data);
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+}
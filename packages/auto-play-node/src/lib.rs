@@ -0,0 +1,253 @@
+//! Node.js bindings for auto-play
+//!
+//! Everything touching the device runs on the libuv thread pool and returns a
+//! `Promise`, so it does not block the event loop of an Electron UI:
+//!
+//! ```js
+//! const { connect } = require("auto-play");
+//!
+//! const ap = await connect("127.0.0.1:16384");
+//! await ap.click(100, 100);
+//! const png = await ap.screencap();
+//! ap.close();
+//! ```
+use std::{
+    io::Cursor,
+    sync::{Arc, Mutex},
+};
+
+use auto_play::{
+    AndroidController, AutoPlay, MatchTemplateMethod, MatcherOptions, action::Action, task::Task,
+};
+use napi::{Env, Error, Result, Status, Task as NapiTask, bindgen_prelude::*};
+use napi_derive::napi;
+
+fn to_napi_err(err: anyhow::Error) -> Error {
+    Error::new(Status::GenericFailure, format!("{err:#}"))
+}
+
+/// A blocking job run on the libuv thread pool.
+pub struct Job<T> {
+    f: Option<Box<dyn FnOnce() -> anyhow::Result<T> + Send>>,
+}
+
+impl<T> Job<T>
+where
+    Self: NapiTask,
+{
+    fn new(f: impl FnOnce() -> anyhow::Result<T> + Send + 'static) -> AsyncTask<Self> {
+        AsyncTask::new(Self {
+            f: Some(Box::new(f)),
+        })
+    }
+}
+
+impl<T: ToNapiValue + TypeName + Send + 'static> NapiTask for Job<T> {
+    type Output = T;
+    type JsValue = T;
+
+    fn compute(&mut self) -> Result<T> {
+        let f = self.f.take().expect("a job is only computed once");
+        f().map_err(to_napi_err)
+    }
+
+    fn resolve(&mut self, _env: Env, output: T) -> Result<T> {
+        Ok(output)
+    }
+}
+
+#[napi(object)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl From<image::math::Rect> for Rect {
+    fn from(rect: image::math::Rect) -> Self {
+        Self {
+            x: rect.x,
+            y: rect.y,
+            width: rect.width,
+            height: rect.height,
+        }
+    }
+}
+
+#[napi(object)]
+pub struct RawImage {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA8 pixels
+    pub data: Buffer,
+}
+
+#[napi(object)]
+pub struct MatchOptions {
+    /// One of `SumOfSquaredDifference`, `SumOfSquaredDifferenceNormed`,
+    /// `CrossCorrelation`, `CrossCorrelationNormed`, `CorrelationCoefficient`,
+    /// `CorrelationCoefficientNormed`
+    pub method: Option<String>,
+    pub threshold: Option<f64>,
+    pub padding: Option<bool>,
+}
+
+fn matcher_options(options: Option<MatchOptions>) -> Result<MatcherOptions> {
+    let Some(options) = options else {
+        return Ok(MatcherOptions::default());
+    };
+    let mut res = match options.method.as_deref() {
+        None => MatcherOptions::default(),
+        Some(method) => {
+            let method = MatchTemplateMethod::ALL
+                .into_iter()
+                .find(|m| format!("{m:?}") == method)
+                .ok_or_else(|| {
+                    Error::new(Status::InvalidArg, format!("unknown method {method}"))
+                })?;
+            MatcherOptions::method_default(method)
+        }
+    };
+    if let Some(threshold) = options.threshold {
+        res.threshold = threshold as f32;
+    }
+    if let Some(padding) = options.padding {
+        res.padding = padding;
+    }
+    Ok(res)
+}
+
+/// Connect to an Android device by its adb serial.
+#[napi(ts_return_type = "Promise<AutoPlay>")]
+pub fn connect(serial: String) -> AsyncTask<Job<JsAutoPlay>> {
+    Job::new(move || {
+        let controller = AndroidController::connect(&serial)?;
+        Ok(JsAutoPlay::new(AutoPlay::new(controller)))
+    })
+}
+
+/// Attach to a desktop window by its exact title.
+#[cfg(any(feature = "windows", feature = "linux", target_os = "macos"))]
+#[napi(ts_return_type = "Promise<AutoPlay>")]
+pub fn connect_window(title: String) -> AsyncTask<Job<JsAutoPlay>> {
+    Job::new(move || {
+        let controller = auto_play::DesktopController::from_window_title(&title)?;
+        Ok(JsAutoPlay::new(AutoPlay::new(controller)))
+    })
+}
+
+/// An [`AutoPlay`] owned by JavaScript, released by `close()` instead of
+/// whenever the garbage collector gets to it.
+#[napi(js_name = "AutoPlay")]
+pub struct JsAutoPlay {
+    inner: Mutex<Option<Arc<AutoPlay>>>,
+}
+
+impl JsAutoPlay {
+    fn new(ap: AutoPlay) -> Self {
+        Self {
+            inner: Mutex::new(Some(Arc::new(ap))),
+        }
+    }
+
+    fn ap(&self) -> Result<Arc<AutoPlay>> {
+        self.inner
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| Error::new(Status::GenericFailure, "AutoPlay is closed"))
+    }
+
+    /// Run `f` with the inner [`AutoPlay`] on the thread pool.
+    fn job<T>(
+        &self,
+        f: impl FnOnce(&AutoPlay) -> anyhow::Result<T> + Send + 'static,
+    ) -> Result<AsyncTask<Job<T>>>
+    where
+        Job<T>: NapiTask,
+    {
+        let ap = self.ap()?;
+        Ok(Job::new(move || f(&ap)))
+    }
+}
+
+#[napi]
+impl JsAutoPlay {
+    /// Release the controller. Calling it more than once is a no-op.
+    #[napi]
+    pub fn close(&self) {
+        self.inner.lock().unwrap().take();
+    }
+
+    #[napi(getter)]
+    pub fn closed(&self) -> bool {
+        self.inner.lock().unwrap().is_none()
+    }
+
+    /// `[width, height]` of the screen
+    #[napi]
+    pub fn screen_size(&self) -> Result<Vec<u32>> {
+        let (width, height) = self.ap()?.screen_size();
+        Ok(vec![width, height])
+    }
+
+    /// Take a screenshot, resolves to PNG encoded bytes.
+    #[napi(ts_return_type = "Promise<Buffer>")]
+    pub fn screencap(&self) -> Result<AsyncTask<Job<Buffer>>> {
+        self.job(|ap| {
+            let mut png = Vec::new();
+            ap.screencap()?
+                .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)?;
+            Ok(png.into())
+        })
+    }
+
+    /// Take a screenshot as RGBA8 pixels, skipping the PNG encoding.
+    #[napi(ts_return_type = "Promise<RawImage>")]
+    pub fn screencap_raw(&self) -> Result<AsyncTask<Job<RawImage>>> {
+        self.job(|ap| {
+            let (width, height, data) = ap.screencap_raw()?;
+            Ok(RawImage {
+                width,
+                height,
+                data: data.into(),
+            })
+        })
+    }
+
+    #[napi(ts_return_type = "Promise<void>")]
+    pub fn click(&self, x: u32, y: u32) -> Result<AsyncTask<Job<()>>> {
+        self.job(move |ap| ap.click(x, y))
+    }
+
+    /// Find `template` (encoded image bytes) on the screen, resolves to `null` if
+    /// it is not found.
+    #[napi(ts_return_type = "Promise<Rect | null>")]
+    pub fn find_template(
+        &self,
+        template: Buffer,
+        options: Option<MatchOptions>,
+    ) -> Result<AsyncTask<Job<Option<Rect>>>> {
+        let options = matcher_options(options)?;
+        let template = image::load_from_memory(&template).map_err(|err| {
+            Error::new(
+                Status::InvalidArg,
+                format!("failed to decode template: {err}"),
+            )
+        })?;
+        self.job(move |ap| Ok(ap.find_image(&template, &options)?.map(Rect::from)))
+    }
+
+    /// Load a task from a TOML file and run it.
+    #[napi(ts_return_type = "Promise<void>")]
+    pub fn run_task(&self, path: String) -> Result<AsyncTask<Job<()>>> {
+        self.job(move |ap| Task::load(path)?.execute(ap))
+    }
+
+    /// Run a task given as TOML source.
+    #[napi(ts_return_type = "Promise<void>")]
+    pub fn run_task_toml(&self, toml: String) -> Result<AsyncTask<Job<()>>> {
+        self.job(move |ap| Task::from_toml(&toml)?.execute(ap))
+    }
+}
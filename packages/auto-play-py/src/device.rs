@@ -0,0 +1,48 @@
+use std::collections::BTreeMap;
+
+use auto_play::adb::{DeviceInfo, host};
+use pyo3::prelude::*;
+
+use crate::error::to_py_err;
+
+/// A device known to the adb server, see [`list_devices`].
+#[pyclass(name = "DeviceInfo", module = "auto_play", frozen, get_all)]
+pub struct PyDeviceInfo {
+    serial: String,
+    /// `device`, `offline`, `unauthorized`, ...
+    state: String,
+    model: Option<String>,
+    /// Everything `adb devices -l` reports, like `product` and `transport_id`
+    info: BTreeMap<String, String>,
+}
+
+impl From<DeviceInfo> for PyDeviceInfo {
+    fn from(info: DeviceInfo) -> Self {
+        Self {
+            model: info.model().map(str::to_owned),
+            serial: info.serial,
+            state: info.state,
+            info: info.info,
+        }
+    }
+}
+
+#[pymethods]
+impl PyDeviceInfo {
+    fn __repr__(&self) -> String {
+        format!(
+            "DeviceInfo(serial={:?}, state={:?}, model={:?})",
+            self.serial, self.state, self.model
+        )
+    }
+}
+
+/// List the devices known to the local adb server, including offline and
+/// unauthorized ones.
+#[pyfunction]
+pub fn list_devices(py: Python<'_>) -> PyResult<Vec<PyDeviceInfo>> {
+    let devices = py
+        .detach(|| -> anyhow::Result<_> { Ok(host::connect_default()?.devices_long()?) })
+        .map_err(to_py_err)?;
+    Ok(devices.into_iter().map(PyDeviceInfo::from).collect())
+}
@@ -0,0 +1,86 @@
+use auto_play::{Error, adb::AdbError};
+use pyo3::{create_exception, exceptions::PyRuntimeError, prelude::*};
+
+create_exception!(
+    auto_play,
+    AutoPlayError,
+    PyRuntimeError,
+    "Base class of all auto_play errors."
+);
+create_exception!(
+    auto_play,
+    DeviceNotFound,
+    AutoPlayError,
+    "The device to connect to does not exist."
+);
+create_exception!(
+    auto_play,
+    TemplateNotFound,
+    AutoPlayError,
+    "The template is not on the screen."
+);
+create_exception!(
+    auto_play,
+    Timeout,
+    AutoPlayError,
+    "Waiting for something gave up."
+);
+create_exception!(
+    auto_play,
+    Cancelled,
+    AutoPlayError,
+    "The task was cancelled."
+);
+create_exception!(
+    auto_play,
+    Unsupported,
+    AutoPlayError,
+    "The controller can not do it, i.e. launch an app on a desktop window."
+);
+create_exception!(
+    auto_play,
+    CaptureError,
+    AutoPlayError,
+    "Failed to capture the screen."
+);
+
+/// Convert an error to the most specific Python exception found in its chain.
+pub fn to_py_err(err: anyhow::Error) -> PyErr {
+    // Exceptions raised by Python callbacks are passed through as is.
+    let err = match err.downcast::<PyErr>() {
+        Ok(err) => return err,
+        Err(err) => err,
+    };
+    let msg = format!("{err:#}");
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<Error>() {
+            return match err {
+                Error::Capture(_) => CaptureError::new_err(msg),
+                Error::TemplateNotFound => TemplateNotFound::new_err(msg),
+                Error::Unsupported(_) => Unsupported::new_err(msg),
+                Error::Timeout(_) => Timeout::new_err(msg),
+                Error::Cancelled => Cancelled::new_err(msg),
+            };
+        }
+        if let Some(err) = cause.downcast_ref::<AdbError>() {
+            match err {
+                AdbError::DeviceNotFound(_) => return DeviceNotFound::new_err(msg),
+                AdbError::Timeout => return Timeout::new_err(msg),
+                _ => {}
+            }
+        }
+    }
+    AutoPlayError::new_err(msg)
+}
+
+pub fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    let py = m.py();
+    m.add("AutoPlayError", py.get_type::<AutoPlayError>())?;
+    m.add("DeviceNotFound", py.get_type::<DeviceNotFound>())?;
+    m.add("TemplateNotFound", py.get_type::<TemplateNotFound>())?;
+    m.add("Timeout", py.get_type::<Timeout>())?;
+    m.add("Cancelled", py.get_type::<Cancelled>())?;
+    m.add("Unsupported", py.get_type::<Unsupported>())?;
+    m.add("CaptureError", py.get_type::<CaptureError>())?;
+    Ok(())
+}
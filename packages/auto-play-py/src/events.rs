@@ -0,0 +1,107 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+use auto_play::event::Event;
+use pyo3::{
+    prelude::*,
+    types::{PyDict, PyTuple},
+};
+use serde_json::Value;
+
+/// How often the thread of a subscription checks whether it was cancelled
+const POLL: Duration = Duration::from_millis(100);
+
+/// `event` as a dict, its [JSON](Event::to_json) with the arrays as tuples,
+/// `None` for the frames.
+fn to_dict<'py>(py: Python<'py>, event: &Event) -> PyResult<Option<Bound<'py, PyAny>>> {
+    event.to_json().map(|json| to_py(py, &json)).transpose()
+}
+
+fn to_py<'py>(py: Python<'py>, value: &Value) -> PyResult<Bound<'py, PyAny>> {
+    Ok(match value {
+        Value::Null => py.None().into_bound(py),
+        Value::Bool(b) => b.into_pyobject(py)?.to_owned().into_any(),
+        Value::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(n), _) => n.into_pyobject(py)?.into_any(),
+            (None, Some(n)) => n.into_pyobject(py)?.into_any(),
+            _ => n.as_f64().unwrap_or(f64::NAN).into_pyobject(py)?.into_any(),
+        },
+        Value::String(s) => s.into_pyobject(py)?.into_any(),
+        Value::Array(values) => PyTuple::new(
+            py,
+            values
+                .iter()
+                .map(|value| to_py(py, value))
+                .collect::<PyResult<Vec<_>>>()?,
+        )?
+        .into_any(),
+        Value::Object(map) => {
+            let dict = PyDict::new(py);
+            for (key, value) in map {
+                dict.set_item(key, to_py(py, value)?)?;
+            }
+            dict.into_any()
+        }
+    })
+}
+
+/// A callback receiving the events of an `AutoPlay` on a thread of its own, see
+/// `AutoPlay.on_event`. It stops when cancelled or when the `AutoPlay` is
+/// closed.
+#[pyclass(name = "EventSubscription", module = "auto_play")]
+pub struct PyEventSubscription {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl PyEventSubscription {
+    /// Call `callback(event)` with every event received on `events`.
+    pub fn spawn(events: mpsc::Receiver<Event>, callback: Py<PyAny>) -> PyResult<Self> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        {
+            let cancelled = cancelled.clone();
+            thread::Builder::new()
+                .name("py-events".to_string())
+                .spawn(move || {
+                    while !cancelled.load(Ordering::Relaxed) {
+                        let event = match events.recv_timeout(POLL) {
+                            Ok(event) => event,
+                            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                        };
+                        Python::attach(|py| {
+                            let res = to_dict(py, &event).and_then(|dict| match dict {
+                                Some(dict) => callback.call1(py, (dict,)).map(drop),
+                                None => Ok(()),
+                            });
+                            // Nothing to raise it to, it is shown as Python does
+                            // for the exceptions of its own threads
+                            if let Err(err) = res {
+                                err.print(py);
+                            }
+                        });
+                    }
+                })?;
+        }
+        Ok(Self { cancelled })
+    }
+}
+
+#[pymethods]
+impl PyEventSubscription {
+    /// Stop calling the callback, the event it is handling is the last one.
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    #[getter]
+    fn cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
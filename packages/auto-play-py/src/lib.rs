@@ -0,0 +1,411 @@
+//! Python bindings for auto-play
+//!
+//! ```python
+//! import auto_play
+//!
+//! with auto_play.AutoPlay.connect("127.0.0.1:16384") as ap:
+//!     ap.click(100, 100)
+//!     png = ap.screencap()
+//!     try:
+//!         ap.wait_and_click_image(template, 5000)
+//!     except auto_play.Timeout:
+//!         ...
+//!
+//!     ap.load_resource("resources")
+//!     ap.on_event(lambda event: print(event["type"]))
+//!     ap.run_task("daily")
+//! ```
+use std::{
+    collections::HashMap,
+    io::Cursor,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use auto_play::{
+    AndroidController, AutoPlay, DynamicImage, Error, MatcherOptions, action::Action,
+    resource::Resource, task::Task,
+};
+use pyo3::{
+    prelude::*,
+    types::{PyBytes, PyType},
+};
+
+use device::PyDeviceInfo;
+use error::{AutoPlayError, to_py_err};
+use events::PyEventSubscription;
+use matcher::{PyMatchTemplateMethod, PyMatcherOptions};
+use nav::PyNavGraph;
+
+mod device;
+mod error;
+mod events;
+mod matcher;
+mod nav;
+
+fn load_template(template: &[u8]) -> PyResult<DynamicImage> {
+    image::load_from_memory(template)
+        .map_err(|err| AutoPlayError::new_err(format!("failed to decode template: {err}")))
+}
+
+fn matcher_options(options: Option<PyMatcherOptions>) -> MatcherOptions {
+    options.map(|options| options.inner).unwrap_or_default()
+}
+
+/// `(x, y, width, height)`
+type PyRect = (u32, u32, u32, u32);
+
+fn to_py_rect(rect: image::math::Rect) -> PyRect {
+    (rect.x, rect.y, rect.width, rect.height)
+}
+
+/// An [`AutoPlay`] instance owned by Python.
+///
+/// The underlying controller (and the MaaTouch process or capture thread it owns)
+/// is released by [`PyAutoPlay::close`] or when leaving a `with` block, instead of
+/// whenever the interpreter decides to collect the object. Calls still running on
+/// other threads keep it alive until they return.
+#[pyclass(name = "AutoPlay", module = "auto_play")]
+pub struct PyAutoPlay {
+    inner: Mutex<Option<Arc<AutoPlay>>>,
+}
+
+impl PyAutoPlay {
+    fn new(ap: AutoPlay) -> Self {
+        Self::from_arc(Arc::new(ap))
+    }
+
+    fn from_arc(ap: Arc<AutoPlay>) -> Self {
+        Self {
+            inner: Mutex::new(Some(ap)),
+        }
+    }
+
+    fn ap(&self) -> PyResult<Arc<AutoPlay>> {
+        self.inner
+            .lock()
+            .unwrap()
+            .clone()
+            .ok_or_else(|| AutoPlayError::new_err("AutoPlay is closed"))
+    }
+
+    /// Run `f` with the inner [`AutoPlay`], failing if it is already closed.
+    fn with_ap<R>(&self, f: impl FnOnce(&AutoPlay) -> anyhow::Result<R>) -> PyResult<R> {
+        let ap = self.ap()?;
+        f(&ap).map_err(to_py_err)
+    }
+}
+
+#[pymethods]
+impl PyAutoPlay {
+    /// Connect to an Android device by its adb serial.
+    #[classmethod]
+    fn connect(_cls: &Bound<'_, PyType>, py: Python<'_>, serial: &str) -> PyResult<Self> {
+        let controller = py
+            .detach(|| AndroidController::connect(serial))
+            .map_err(to_py_err)?;
+        Ok(Self::new(AutoPlay::new(controller)))
+    }
+
+    /// Attach to a desktop window by its exact title.
+    #[cfg(any(feature = "windows", feature = "linux", target_os = "macos"))]
+    #[classmethod]
+    fn connect_window(_cls: &Bound<'_, PyType>, py: Python<'_>, title: &str) -> PyResult<Self> {
+        let controller = py
+            .detach(|| auto_play::DesktopController::from_window_title(title))
+            .map_err(to_py_err)?;
+        Ok(Self::new(AutoPlay::new(controller)))
+    }
+
+    /// Release the controller. Calling it more than once is a no-op.
+    fn close(&self, py: Python<'_>) {
+        let ap = self.inner.lock().unwrap().take();
+        // Dropping the controller waits for pending touch commands, so do it without the GIL.
+        py.detach(move || drop(ap));
+    }
+
+    #[getter]
+    fn closed(&self) -> bool {
+        self.inner.lock().unwrap().is_none()
+    }
+
+    fn __enter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __exit__(
+        &self,
+        py: Python<'_>,
+        _exc_type: Option<Bound<'_, PyAny>>,
+        _exc_value: Option<Bound<'_, PyAny>>,
+        _traceback: Option<Bound<'_, PyAny>>,
+    ) -> bool {
+        self.close(py);
+        false
+    }
+
+    /// `(width, height)` of the screen
+    fn screen_size(&self) -> PyResult<(u32, u32)> {
+        self.with_ap(|ap| Ok(ap.screen_size()))
+    }
+
+    /// Take a screenshot, returned as PNG encoded bytes.
+    fn screencap<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let png = py.detach(|| {
+            self.with_ap(|ap| {
+                let screen = ap.screencap()?;
+                let mut png = Vec::new();
+                screen.write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)?;
+                Ok(png)
+            })
+        })?;
+        Ok(PyBytes::new(py, &png))
+    }
+
+    /// Take a screenshot as JPEG encoded bytes, smaller and quicker than a PNG,
+    /// with `quality` from 1 to 100.
+    #[pyo3(signature = (quality = 80))]
+    fn screencap_jpeg<'py>(&self, py: Python<'py>, quality: u8) -> PyResult<Bound<'py, PyBytes>> {
+        let jpeg = py.detach(|| self.with_ap(|ap| ap.screencap_jpeg(quality)))?;
+        Ok(PyBytes::new(py, &jpeg))
+    }
+
+    /// Take a screenshot as `(width, height, rgba_bytes)`, skipping the PNG encoding.
+    fn screencap_raw<'py>(&self, py: Python<'py>) -> PyResult<(u32, u32, Bound<'py, PyBytes>)> {
+        let (width, height, rgba) = py.detach(|| self.with_ap(|ap| ap.screencap_raw()))?;
+        Ok((width, height, PyBytes::new(py, &rgba)))
+    }
+
+    fn click(&self, py: Python<'_>, x: u32, y: u32) -> PyResult<()> {
+        py.detach(|| self.with_ap(|ap| ap.click(x, y)))
+    }
+
+    fn long_press(&self, py: Python<'_>, x: u32, y: u32, duration_ms: u64) -> PyResult<()> {
+        py.detach(|| self.with_ap(|ap| ap.long_press(x, y, Duration::from_millis(duration_ms))))
+    }
+
+    fn double_click(&self, py: Python<'_>, x: u32, y: u32) -> PyResult<()> {
+        py.detach(|| self.with_ap(|ap| ap.double_click(x, y)))
+    }
+
+    /// Type `text` into the focused field.
+    fn input_text(&self, py: Python<'_>, text: &str) -> PyResult<()> {
+        py.detach(|| self.with_ap(|ap| ap.input_text(text)))
+    }
+
+    #[pyo3(signature = (start, end, duration_ms, slope_in = 1.0, slope_out = 1.0))]
+    fn swipe(
+        &self,
+        py: Python<'_>,
+        start: (u32, u32),
+        end: (i32, i32),
+        duration_ms: u64,
+        slope_in: f32,
+        slope_out: f32,
+    ) -> PyResult<()> {
+        py.detach(|| {
+            self.with_ap(|ap| {
+                ap.swipe(
+                    start,
+                    end,
+                    Duration::from_millis(duration_ms),
+                    slope_in,
+                    slope_out,
+                )
+            })
+        })
+    }
+
+    /// Find `template` (encoded image bytes) on the screen, returns `(x, y, width, height)`.
+    #[pyo3(signature = (template, options = None))]
+    fn find_image(
+        &self,
+        py: Python<'_>,
+        template: &[u8],
+        options: Option<PyMatcherOptions>,
+    ) -> PyResult<Option<PyRect>> {
+        let template = load_template(template)?;
+        let options = matcher_options(options);
+        py.detach(|| self.with_ap(|ap| Ok(ap.find_image(&template, &options)?.map(to_py_rect))))
+    }
+
+    /// Find `template` (encoded image bytes) on the screen, returns
+    /// `((x, y, width, height), score)`, the score as of the method of `options`.
+    #[pyo3(signature = (template, options = None))]
+    fn find_template(
+        &self,
+        py: Python<'_>,
+        template: &[u8],
+        options: Option<PyMatcherOptions>,
+    ) -> PyResult<Option<(PyRect, f32)>> {
+        let template = load_template(template)?;
+        let options = matcher_options(options);
+        py.detach(|| {
+            self.with_ap(|ap| {
+                let found = ap.match_image(&template, &options)?;
+                Ok(found.map(|m| (to_py_rect(m.rect), m.value)))
+            })
+        })
+    }
+
+    /// Click the center of `template`, raises `TemplateNotFound` if it is not on the screen.
+    #[pyo3(signature = (template, options = None))]
+    fn click_image(
+        &self,
+        py: Python<'_>,
+        template: &[u8],
+        options: Option<PyMatcherOptions>,
+    ) -> PyResult<()> {
+        let template = load_template(template)?;
+        let options = matcher_options(options);
+        py.detach(|| {
+            self.with_ap(|ap| {
+                if !ap.click_image(&template, &options)? {
+                    Err(Error::TemplateNotFound)?;
+                }
+                Ok(())
+            })
+        })
+    }
+
+    /// Wait for `template` to show up, raises `Timeout` if it does not within `timeout_ms`.
+    #[pyo3(signature = (template, timeout_ms, options = None))]
+    fn wait_for_image(
+        &self,
+        py: Python<'_>,
+        template: &[u8],
+        timeout_ms: u64,
+        options: Option<PyMatcherOptions>,
+    ) -> PyResult<PyRect> {
+        let template = load_template(template)?;
+        let options = matcher_options(options);
+        let timeout = Duration::from_millis(timeout_ms);
+        py.detach(|| {
+            self.with_ap(|ap| {
+                let rect = ap
+                    .wait_for_image(&template, &options, timeout)?
+                    .ok_or(Error::Timeout(timeout))?;
+                Ok(to_py_rect(rect))
+            })
+        })
+    }
+
+    /// The node of `graph` the screen is currently on, if any.
+    fn current_node(
+        &self,
+        py: Python<'_>,
+        graph: PyRef<'_, PyNavGraph>,
+    ) -> PyResult<Option<String>> {
+        graph
+            .with_graph(py, self.ap()?, |graph, ap| Ok(graph.current_node(ap)))
+            .map_err(to_py_err)
+    }
+
+    /// Navigate from the current node of `graph` to `node`.
+    fn navigate_to(
+        &self,
+        py: Python<'_>,
+        graph: PyRef<'_, PyNavGraph>,
+        node: &str,
+    ) -> PyResult<()> {
+        graph
+            .with_graph(py, self.ap()?, |graph, ap| graph.navigate_to(ap, node))
+            .map_err(to_py_err)
+    }
+
+    /// Register `callback(ap)` as an action that tasks can run with
+    /// `PluginAction = { name = "<name>" }`.
+    fn register_action(&self, name: String, callback: Py<PyAny>) -> PyResult<()> {
+        let ap = self.ap()?;
+        let handle = Arc::downgrade(&ap);
+        ap.plugins().register(name, move |_| {
+            let ap = handle
+                .upgrade()
+                .ok_or_else(|| anyhow::anyhow!("AutoPlay is closed"))?;
+            Python::attach(|py| {
+                callback.call1(py, (PyAutoPlay::from_arc(ap),))?;
+                Ok(())
+            })
+        });
+        Ok(())
+    }
+
+    fn unregister_action(&self, name: &str) -> PyResult<bool> {
+        self.with_ap(|ap| Ok(ap.plugins().unregister(name)))
+    }
+
+    /// Load the tasks of the directory `path`, to run them by name with
+    /// `run_task`.
+    fn load_resource(&self, py: Python<'_>, path: std::path::PathBuf) -> PyResult<()> {
+        py.detach(|| {
+            self.with_ap(|ap| {
+                ap.set_resource(Resource::load(path)?);
+                Ok(())
+            })
+        })
+    }
+
+    /// The names of the tasks loaded by `load_resource`.
+    fn tasks(&self) -> PyResult<Vec<String>> {
+        self.with_ap(|ap| {
+            Ok(ap.resource().names().map(str::to_string).collect())
+        })
+    }
+
+    /// Run the task `name` loaded by `load_resource`.
+    fn run_task(&self, py: Python<'_>, name: &str) -> PyResult<()> {
+        py.detach(|| {
+            self.with_ap(|ap| ap.run_task_with(name, HashMap::new()))
+        })
+    }
+
+    /// Call `callback(event)` with every event from now on, a dict with its kind
+    /// in `"type"`, i.e. `{"type": "click", "x": 1, "y": 2}`. It is called on
+    /// another thread, until the subscription returned is cancelled.
+    fn on_event(&self, callback: Py<PyAny>) -> PyResult<PyEventSubscription> {
+        let events = self.ap()?.subscribe();
+        PyEventSubscription::spawn(events, callback)
+    }
+
+    /// Load a task from a TOML file and run it.
+    fn run_task_file(&self, py: Python<'_>, path: std::path::PathBuf) -> PyResult<()> {
+        let task = Task::load(path).map_err(to_py_err)?;
+        py.detach(|| self.with_ap(|ap| task.execute(ap)))
+    }
+
+    /// Like [`PyAutoPlay::wait_for_image`], then click the center of it.
+    #[pyo3(signature = (template, timeout_ms, options = None))]
+    fn wait_and_click_image(
+        &self,
+        py: Python<'_>,
+        template: &[u8],
+        timeout_ms: u64,
+        options: Option<PyMatcherOptions>,
+    ) -> PyResult<()> {
+        let template = load_template(template)?;
+        let options = matcher_options(options);
+        let timeout = Duration::from_millis(timeout_ms);
+        py.detach(|| {
+            self.with_ap(|ap| {
+                if !ap.wait_and_click_image(&template, &options, timeout)? {
+                    Err(Error::Timeout(timeout))?;
+                }
+                Ok(())
+            })
+        })
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "auto_play")]
+fn auto_play_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyAutoPlay>()?;
+    m.add_class::<PyMatcherOptions>()?;
+    m.add_class::<PyMatchTemplateMethod>()?;
+    m.add_class::<PyNavGraph>()?;
+    m.add_class::<PyEventSubscription>()?;
+    m.add_class::<PyDeviceInfo>()?;
+    m.add_function(wrap_pyfunction!(device::list_devices, m)?)?;
+    error::register(m)?;
+    Ok(())
+}
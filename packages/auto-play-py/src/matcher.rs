@@ -0,0 +1,113 @@
+use auto_play::{MatchTemplateMethod, MatcherOptions};
+use pyo3::prelude::*;
+
+#[pyclass(name = "MatchTemplateMethod", module = "auto_play", eq, eq_int)]
+#[derive(Clone, Copy, PartialEq)]
+pub enum PyMatchTemplateMethod {
+    SumOfSquaredDifference,
+    SumOfSquaredDifferenceNormed,
+    CrossCorrelation,
+    CrossCorrelationNormed,
+    CorrelationCoefficient,
+    CorrelationCoefficientNormed,
+}
+
+impl From<PyMatchTemplateMethod> for MatchTemplateMethod {
+    fn from(value: PyMatchTemplateMethod) -> Self {
+        match value {
+            PyMatchTemplateMethod::SumOfSquaredDifference => Self::SumOfSquaredDifference,
+            PyMatchTemplateMethod::SumOfSquaredDifferenceNormed => {
+                Self::SumOfSquaredDifferenceNormed
+            }
+            PyMatchTemplateMethod::CrossCorrelation => Self::CrossCorrelation,
+            PyMatchTemplateMethod::CrossCorrelationNormed => Self::CrossCorrelationNormed,
+            PyMatchTemplateMethod::CorrelationCoefficient => Self::CorrelationCoefficient,
+            PyMatchTemplateMethod::CorrelationCoefficientNormed => {
+                Self::CorrelationCoefficientNormed
+            }
+        }
+    }
+}
+
+impl From<MatchTemplateMethod> for PyMatchTemplateMethod {
+    fn from(value: MatchTemplateMethod) -> Self {
+        match value {
+            MatchTemplateMethod::SumOfSquaredDifference => Self::SumOfSquaredDifference,
+            MatchTemplateMethod::SumOfSquaredDifferenceNormed => Self::SumOfSquaredDifferenceNormed,
+            MatchTemplateMethod::CrossCorrelation => Self::CrossCorrelation,
+            MatchTemplateMethod::CrossCorrelationNormed => Self::CrossCorrelationNormed,
+            MatchTemplateMethod::CorrelationCoefficient => Self::CorrelationCoefficient,
+            MatchTemplateMethod::CorrelationCoefficientNormed => Self::CorrelationCoefficientNormed,
+        }
+    }
+}
+
+#[pymethods]
+impl PyMatchTemplateMethod {
+    fn __str__(&self) -> String {
+        MatchTemplateMethod::from(*self).to_string()
+    }
+}
+
+/// Python side of [`MatcherOptions`].
+///
+/// When only `method` is given, the threshold defaults to the one suited for that method
+/// (see [`MatcherOptions::method_default`]).
+#[pyclass(name = "MatcherOptions", module = "auto_play")]
+#[derive(Clone)]
+pub struct PyMatcherOptions {
+    pub inner: MatcherOptions,
+}
+
+#[pymethods]
+impl PyMatcherOptions {
+    #[new]
+    #[pyo3(signature = (method = None, threshold = None, padding = false))]
+    fn new(method: Option<PyMatchTemplateMethod>, threshold: Option<f32>, padding: bool) -> Self {
+        let mut inner = method
+            .map(|method| MatcherOptions::method_default(method.into()))
+            .unwrap_or_default();
+        if let Some(threshold) = threshold {
+            inner.threshold = threshold;
+        }
+        inner.padding = padding;
+        Self { inner }
+    }
+
+    #[getter]
+    fn method(&self) -> PyMatchTemplateMethod {
+        self.inner.method.into()
+    }
+
+    #[setter]
+    fn set_method(&mut self, method: PyMatchTemplateMethod) {
+        self.inner.method = method.into();
+    }
+
+    #[getter]
+    fn threshold(&self) -> f32 {
+        self.inner.threshold
+    }
+
+    #[setter]
+    fn set_threshold(&mut self, threshold: f32) {
+        self.inner.threshold = threshold;
+    }
+
+    #[getter]
+    fn padding(&self) -> bool {
+        self.inner.padding
+    }
+
+    #[setter]
+    fn set_padding(&mut self, padding: bool) {
+        self.inner.padding = padding;
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "MatcherOptions(method={}, threshold={}, padding={})",
+            self.inner.method, self.inner.threshold, self.inner.padding
+        )
+    }
+}
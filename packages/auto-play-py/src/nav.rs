@@ -0,0 +1,188 @@
+use std::sync::Arc;
+
+use auto_play::{
+    AutoPlay, DynamicImage, MatcherOptions,
+    nav::{NavGraph, Node},
+};
+use pyo3::{
+    exceptions::{PyTypeError, PyValueError},
+    prelude::*,
+    types::PyBytes,
+};
+
+use crate::{PyAutoPlay, load_template, matcher::PyMatcherOptions, matcher_options};
+
+enum Checker {
+    /// `callback(ap) -> bool`
+    Callback(Py<PyAny>),
+    /// The node is the current one when the template is on the screen.
+    Template(Arc<DynamicImage>, MatcherOptions),
+}
+
+impl Checker {
+    fn clone_ref(&self, py: Python<'_>) -> Self {
+        match self {
+            Self::Callback(callback) => Self::Callback(callback.clone_ref(py)),
+            Self::Template(template, options) => Self::Template(template.clone(), *options),
+        }
+    }
+}
+
+/// Call `callback` with a handle to `ap`.
+fn call(py: Python<'_>, callback: &Py<PyAny>, ap: &Arc<AutoPlay>) -> PyResult<Py<PyAny>> {
+    callback.call1(py, (PyAutoPlay::from_arc(ap.clone()),))
+}
+
+/// A navigation graph described from Python.
+///
+/// Nodes are screens, optionally with a checker telling whether it is the current
+/// one, edges are callbacks moving from one screen to another:
+///
+/// ```python
+/// graph = auto_play.NavGraph()
+/// graph.add_node("main", open("main.png", "rb").read())
+/// graph.add_node("settings", lambda ap: ap.find_image(gear) is not None)
+/// graph.add_edge("main", "settings", lambda ap: ap.click(1200, 40))
+/// ap.navigate_to(graph, "settings")
+/// ```
+#[pyclass(name = "NavGraph", module = "auto_play")]
+#[derive(Default)]
+pub struct PyNavGraph {
+    nodes: Vec<(String, Option<Checker>)>,
+    edges: Vec<(String, String, Py<PyAny>)>,
+}
+
+impl PyNavGraph {
+    fn contains_node(&self, name: &str) -> bool {
+        self.nodes.iter().any(|(node, _)| node == name)
+    }
+
+    fn clone_ref(&self, py: Python<'_>) -> Self {
+        Self {
+            nodes: self
+                .nodes
+                .iter()
+                .map(|(name, checker)| (name.clone(), checker.as_ref().map(|c| c.clone_ref(py))))
+                .collect(),
+            edges: self
+                .edges
+                .iter()
+                .map(|(from, to, action)| (from.clone(), to.clone(), action.clone_ref(py)))
+                .collect(),
+        }
+    }
+
+    /// Build a [`NavGraph`] whose callbacks get a handle to `ap`.
+    fn build(self, ap: &Arc<AutoPlay>) -> NavGraph {
+        let mut graph = NavGraph::new();
+        for (name, checker) in self.nodes {
+            let node = match checker {
+                None => Node::new(),
+                Some(Checker::Template(template, options)) => Node::with_checker(move |ap| {
+                    matches!(ap.find_image(&template, &options), Ok(Some(_)))
+                }),
+                Some(Checker::Callback(callback)) => {
+                    let ap = ap.clone();
+                    Node::with_checker(move |_| {
+                        Python::attach(|py| {
+                            match call(py, &callback, &ap)
+                                .and_then(|res| res.bind(py).extract::<bool>())
+                            {
+                                Ok(res) => res,
+                                Err(err) => {
+                                    err.write_unraisable(py, Some(callback.bind(py)));
+                                    false
+                                }
+                            }
+                        })
+                    })
+                }
+            };
+            graph.insert_node(name, node);
+        }
+        for (from, to, action) in self.edges {
+            let ap = ap.clone();
+            graph.insert_edge(
+                from,
+                to,
+                Box::new(move |_| {
+                    Python::attach(|py| call(py, &action, &ap))?;
+                    Ok(())
+                }),
+            );
+        }
+        graph
+    }
+
+    /// Run `f` on a [`NavGraph`] built from a snapshot of this one, without the GIL.
+    pub fn with_graph<R: Send>(
+        &self,
+        py: Python<'_>,
+        ap: Arc<AutoPlay>,
+        f: impl FnOnce(&NavGraph, &AutoPlay) -> anyhow::Result<R> + Send,
+    ) -> anyhow::Result<R> {
+        let graph = self.clone_ref(py);
+        py.detach(move || f(&graph.build(&ap), &ap))
+    }
+}
+
+#[pymethods]
+impl PyNavGraph {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a node, `checker` is either a `callback(ap) -> bool` or a template
+    /// (encoded image bytes) to look for on the screen.
+    #[pyo3(signature = (name, checker = None, options = None))]
+    fn add_node(
+        &mut self,
+        name: String,
+        checker: Option<Bound<'_, PyAny>>,
+        options: Option<PyMatcherOptions>,
+    ) -> PyResult<()> {
+        if self.contains_node(&name) {
+            return Err(PyValueError::new_err(format!("node {name} already exists")));
+        }
+        let checker = match checker {
+            None => None,
+            Some(checker) => {
+                if let Ok(template) = checker.cast::<PyBytes>() {
+                    let template = load_template(template.as_bytes())?;
+                    Some(Checker::Template(
+                        Arc::new(template),
+                        matcher_options(options),
+                    ))
+                } else if checker.is_callable() {
+                    Some(Checker::Callback(checker.unbind()))
+                } else {
+                    return Err(PyTypeError::new_err(
+                        "checker should be a callable or template bytes",
+                    ));
+                }
+            }
+        };
+        self.nodes.push((name, checker));
+        Ok(())
+    }
+
+    /// Add an edge, `action(ap)` is called to move from `from_` to `to`.
+    fn add_edge(&mut self, from_: String, to: String, action: Bound<'_, PyAny>) -> PyResult<()> {
+        for name in [&from_, &to] {
+            if !self.contains_node(name) {
+                return Err(PyValueError::new_err(format!("unknown node {name}")));
+            }
+        }
+        if !action.is_callable() {
+            return Err(PyTypeError::new_err("action should be a callable"));
+        }
+        self.edges.push((from_, to, action.unbind()));
+        Ok(())
+    }
+
+    #[getter]
+    fn nodes(&self) -> Vec<String> {
+        self.nodes.iter().map(|(name, _)| name.clone()).collect()
+    }
+}
@@ -1,41 +1,286 @@
-use std::time::Duration;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
+use anyhow::Context;
+use ap_controller::{AnchoredPoint, AnchoredRect, ControllerTrait};
 use serde::{Deserialize, Serialize};
 
-use ap_controller::ControllerTrait;
+use crate::{
+    MatchTemplateMethod, cancel::CancellationToken, humanize::Humanize, validate::Validator,
+};
+
+/// What the steps of a running task run with, passed down to the steps they contain
+#[derive(Clone)]
+pub struct ExecContext<'a> {
+    pub ap: &'a crate::AutoPlay,
+    /// Of the outermost task, checked between the steps and the iterations of
+    /// the steps containing others
+    pub token: CancellationToken,
+    /// The policy of the innermost task with one, else the one of `ap` when the
+    /// outermost task started
+    pub humanize: Option<Humanize>,
+}
+
+impl<'a> ExecContext<'a> {
+    /// Outside of a task, with a token nothing cancels
+    pub fn new(ap: &'a crate::AutoPlay) -> Self {
+        Self::with_token(ap, CancellationToken::new())
+    }
+
+    pub fn with_token(ap: &'a crate::AutoPlay, token: CancellationToken) -> Self {
+        Self {
+            ap,
+            token,
+            humanize: ap.humanize(),
+        }
+    }
+
+    /// [`AutoPlay::click`](crate::AutoPlay::click) with the policy of the context
+    pub fn click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+        self.ap.click_with(x, y, self.humanize)
+    }
+
+    /// [`AutoPlay::click_rect`](crate::AutoPlay::click_rect) with the policy of the context
+    pub fn click_rect(&self, rect: image::math::Rect) -> anyhow::Result<()> {
+        self.ap.click_rect_with(rect, self.humanize)
+    }
+
+    /// [`AutoPlay::swipe`](crate::AutoPlay::swipe) with the policy of the context
+    pub fn swipe(
+        &self,
+        start: (u32, u32),
+        end: (i32, i32),
+        duration: Duration,
+        slope_in: f32,
+        slope_out: f32,
+    ) -> anyhow::Result<()> {
+        self.ap
+            .swipe_with(start, end, duration, slope_in, slope_out, self.humanize)
+    }
+}
 
 #[typetag::serde]
-pub trait Action {
+pub trait Action: Send + Sync {
     fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()>;
+
+    /// [`Action::execute`] as a step of a running task. The actions containing
+    /// steps run them with `ctx`, so they stop with the task.
+    fn execute_in(&self, ctx: &ExecContext) -> anyhow::Result<()> {
+        self.execute(ctx.ap)
+    }
+
+    /// Report what is wrong with the step without running it, i.e. a missing
+    /// template, and validate the steps it contains.
+    fn validate(&self, _validator: &mut Validator) {}
+}
+
+/// A point of the screen, in pixels as `{ x = 100, y = 200 }` or `[100, 200]`, or
+/// anchored to the layout of the game as
+/// `{ anchor = "bottom-right", x = 0.95, y = 0.9 }`, see [`ap_controller::anchor`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(untagged, from = "PointRepr")]
+pub enum Point {
+    Anchored(AnchoredPoint),
+    Pixels { x: i32, y: i32 },
+}
+
+/// The forms a [`Point`] is written in
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PointRepr {
+    Anchored(AnchoredPoint),
+    Pixels { x: i32, y: i32 },
+    Pair(i32, i32),
+}
+
+impl From<PointRepr> for Point {
+    fn from(repr: PointRepr) -> Self {
+        match repr {
+            PointRepr::Anchored(point) => Point::Anchored(point),
+            PointRepr::Pixels { x, y } | PointRepr::Pair(x, y) => Point::Pixels { x, y },
+        }
+    }
+}
+
+impl Point {
+    /// The point on a screen where the game is drawn in `content`
+    pub fn resolve_in(&self, content: image::math::Rect) -> (i32, i32) {
+        match *self {
+            Point::Anchored(point) => point.resolve(content),
+            Point::Pixels { x, y } => (x, y),
+        }
+    }
+
+    /// The point on the screen of `ap`, possibly off it.
+    pub fn resolve(&self, ap: &crate::AutoPlay) -> anyhow::Result<(i32, i32)> {
+        match *self {
+            Point::Anchored(point) => ap.controller().resolve_point(point),
+            Point::Pixels { x, y } => Ok((x, y)),
+        }
+    }
+
+    /// [`Point::resolve`], failing if it is off the screen.
+    fn resolve_on_screen(&self, ap: &crate::AutoPlay) -> anyhow::Result<(u32, u32)> {
+        let (x, y) = self.resolve(ap)?;
+        let (width, height) = ap.screen_size();
+        match (u32::try_from(x), u32::try_from(y)) {
+            (Ok(x), Ok(y)) if x < width && y < height => Ok((x, y)),
+            _ => anyhow::bail!("({x}, {y}) is outside the {width}x{height} screen"),
+        }
+    }
+}
+
+impl From<(u32, u32)> for Point {
+    fn from((x, y): (u32, u32)) -> Self {
+        Point::Pixels {
+            x: x as i32,
+            y: y as i32,
+        }
+    }
+}
+
+impl From<(i32, i32)> for Point {
+    fn from((x, y): (i32, i32)) -> Self {
+        Point::Pixels { x, y }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Click {
+    #[serde(flatten)]
+    pub at: Point,
+}
+
+#[typetag::serde]
+impl Action for Click {
+    fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
+        self.execute_in(&ExecContext::new(ap))
+    }
+
+    fn execute_in(&self, ctx: &ExecContext) -> anyhow::Result<()> {
+        let (x, y) = self.at.resolve_on_screen(ctx.ap)?;
+        ctx.click(x, y)
+    }
+
+    fn validate(&self, validator: &mut Validator) {
+        validator.position(self.at);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LongPress {
     pub x: u32,
     pub y: u32,
+    pub duration: Duration,
 }
 
 #[typetag::serde]
-impl Action for Click {
+impl Action for LongPress {
     fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
-        ap.click(self.x, self.y)
+        ap.long_press(self.x, self.y, self.duration)
+    }
+
+    fn validate(&self, validator: &mut Validator) {
+        validator.point(self.x, self.y);
     }
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DoubleClick {
+    pub x: u32,
+    pub y: u32,
+}
+
+#[typetag::serde]
+impl Action for DoubleClick {
+    fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
+        ap.double_click(self.x, self.y)
+    }
+
+    fn validate(&self, validator: &mut Validator) {
+        validator.point(self.x, self.y);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Key {
     Escape,
+    Enter,
+    Backspace,
+    Delete,
+    Tab,
+    Space,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    VolumeUp,
+    VolumeDown,
+    VolumeMute,
+    PlayPause,
+    NextTrack,
+    PrevTrack,
+    /// A letter, digit or symbol
+    Char(char),
+    // Android only
+    Back,
+    /// Go to the home screen, unlike [`Key::Home`]
+    HomeScreen,
+    AppSwitch,
+    Menu,
+    Power,
 }
 
 impl Into<ap_controller::Key> for Key {
     fn into(self) -> ap_controller::Key {
+        use ap_controller::android::keycode::*;
+
         match self {
             Key::Escape => ap_controller::Key::Escape,
+            Key::Enter => ap_controller::Key::Return,
+            Key::Backspace => ap_controller::Key::Backspace,
+            Key::Delete => ap_controller::Key::Delete,
+            Key::Tab => ap_controller::Key::Tab,
+            Key::Space => ap_controller::Key::Space,
+            Key::Up => ap_controller::Key::UpArrow,
+            Key::Down => ap_controller::Key::DownArrow,
+            Key::Left => ap_controller::Key::LeftArrow,
+            Key::Right => ap_controller::Key::RightArrow,
+            Key::Home => ap_controller::Key::Home,
+            Key::End => ap_controller::Key::End,
+            Key::PageUp => ap_controller::Key::PageUp,
+            Key::PageDown => ap_controller::Key::PageDown,
+            Key::VolumeUp => ap_controller::Key::VolumeUp,
+            Key::VolumeDown => ap_controller::Key::VolumeDown,
+            Key::VolumeMute => ap_controller::Key::VolumeMute,
+            Key::PlayPause => ap_controller::Key::MediaPlayPause,
+            Key::NextTrack => ap_controller::Key::MediaNextTrack,
+            Key::PrevTrack => ap_controller::Key::MediaPrevTrack,
+            Key::Char(c) => ap_controller::Key::Unicode(c),
+            // Taken as android key codes by the android controller
+            Key::Back => ap_controller::Key::Other(KEYCODE_BACK),
+            Key::HomeScreen => ap_controller::Key::Other(KEYCODE_HOME),
+            Key::AppSwitch => ap_controller::Key::Other(KEYCODE_APP_SWITCH),
+            Key::Menu => ap_controller::Key::Other(KEYCODE_MENU),
+            Key::Power => ap_controller::Key::Other(KEYCODE_POWER),
         }
     }
 }
 
 impl Key {
+    pub fn is_android_only(self) -> bool {
+        matches!(
+            self,
+            Key::Back | Key::HomeScreen | Key::AppSwitch | Key::Menu | Key::Power
+        )
+    }
+
     pub fn press(self) -> Press {
         Press { key: self }
     }
@@ -49,14 +294,36 @@ pub struct Press {
 #[typetag::serde]
 impl Action for Press {
     fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
-        ap.controller().press(self.key.into())
+        if self.key.is_android_only() {
+            // `Key::Other` means something else to the controllers without key events
+            anyhow::ensure!(
+                ap.controller().supports_keyevents(),
+                "{:?} can only be pressed by a controller supporting key events",
+                self.key
+            );
+        }
+        ap.press(self.key.into())
+    }
+}
+
+/// Types `text` into the focused field, see [`ap_controller::ControllerTrait::input_text`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputText {
+    pub text: String,
+}
+
+#[typetag::serde]
+impl Action for InputText {
+    fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
+        ap.input_text(&self.text)
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Swipe {
-    pub start: (u32, u32),
-    pub end: (i32, i32),
+    pub start: Point,
+    /// It may be off the screen, to fling
+    pub end: Point,
     pub duration: Duration,
     pub slope_in: f32,
     pub slope_out: f32,
@@ -65,14 +332,792 @@ pub struct Swipe {
 #[typetag::serde]
 impl Action for Swipe {
     fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
-        ap.swipe(
-            self.start,
-            self.end,
+        self.execute_in(&ExecContext::new(ap))
+    }
+
+    fn execute_in(&self, ctx: &ExecContext) -> anyhow::Result<()> {
+        ctx.swipe(
+            self.start.resolve_on_screen(ctx.ap)?,
+            self.end.resolve(ctx.ap)?,
             self.duration,
             self.slope_in,
             self.slope_out,
         )
     }
+
+    fn validate(&self, validator: &mut Validator) {
+        validator.position(self.start);
+    }
+}
+
+/// A part of the screen, `{ x = 1600, y = 0, width = 320, height = 180 }` in a task,
+/// or anchored to the layout of the game like a [`Point`] as
+/// `{ anchor = "top-right", x = 0.8, y = 0.0, width = 0.2, height = 0.2 }`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum Region {
+    Anchored(AnchoredRect),
+    Pixels {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+}
+
+impl Region {
+    /// The rect on the screen of `ap`
+    pub fn resolve(&self, ap: &crate::AutoPlay) -> anyhow::Result<image::math::Rect> {
+        match *self {
+            Region::Anchored(rect) => ap.controller().resolve_rect(rect),
+            Region::Pixels {
+                x,
+                y,
+                width,
+                height,
+            } => Ok(image::math::Rect {
+                x,
+                y,
+                width,
+                height,
+            }),
+        }
+    }
+}
+
+/// How [`ClickMatchTemplate`] looks for its template, `strategy = "feature"` in a task.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchStrategy {
+    /// Template matching, see [`crate::AutoPlay::find_image`]
+    #[default]
+    Template,
+    /// Feature matching, for rotated templates or animated backgrounds, see
+    /// [`crate::AutoPlay::find_image_features`]. `threshold` is not used.
+    Feature,
+}
+
+/// Clicks the center of `template` on the screen, failing if it is not there.
+/// With a `region` only that part of the screen is captured and searched.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClickMatchTemplate {
+    pub template: PathBuf,
+    #[serde(default)]
+    pub threshold: Option<f32>,
+    #[serde(default)]
+    pub region: Option<Region>,
+    #[serde(default)]
+    pub strategy: MatchStrategy,
+    /// Reuse the result of the last match if the screen did not change, see
+    /// [`crate::AutoPlay::find_image_cached`]. Only for [`MatchStrategy::Template`].
+    #[serde(default)]
+    pub cache: bool,
+    /// The match method by its name, i.e. `ccoeff_normed`, with its default
+    /// threshold if there is no `threshold`. Only for [`MatchStrategy::Template`].
+    #[serde(default, with = "method_name")]
+    pub method: Option<MatchTemplateMethod>,
+    /// Click this far from the center of the match, i.e. on a button next to a
+    /// label matched instead
+    #[serde(default)]
+    pub offset: Option<(i32, i32)>,
+    /// Click the match at this index in reading order, from 0, among all of
+    /// them, see [`crate::AutoPlay::find_all_images`]. Only for
+    /// [`MatchStrategy::Template`], without `cache`.
+    #[serde(default)]
+    pub nth: Option<usize>,
+}
+
+/// [`MatchTemplateMethod`] by its name in a task
+mod method_name {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    use crate::MatchTemplateMethod;
+
+    pub fn serialize<S: Serializer>(
+        method: &Option<MatchTemplateMethod>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match method {
+            Some(method) => serializer.serialize_some(&method.to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<MatchTemplateMethod>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|name| name.parse().map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+impl ClickMatchTemplate {
+    /// Click the center of `rect` moved by the offset.
+    fn click(&self, ctx: &ExecContext, rect: image::math::Rect) -> anyhow::Result<()> {
+        let Some((dx, dy)) = self.offset else {
+            return ctx.click_rect(rect);
+        };
+        let (width, height) = ctx.ap.screen_size();
+        let x = (rect.x + rect.width / 2).saturating_add_signed(dx);
+        let y = (rect.y + rect.height / 2).saturating_add_signed(dy);
+        ctx.click(
+            x.min(width.saturating_sub(1)),
+            y.min(height.saturating_sub(1)),
+        )
+    }
+}
+
+#[typetag::serde]
+impl Action for ClickMatchTemplate {
+    fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
+        self.execute_in(&ExecContext::new(ap))
+    }
+
+    fn execute_in(&self, ctx: &ExecContext) -> anyhow::Result<()> {
+        let ap = ctx.ap;
+        // Only decoded the first time the step runs, see `AutoPlay::templates`
+        let handle = ap.load_template(&self.template)?;
+        let template = handle.template();
+        let found = match self.strategy {
+            MatchStrategy::Template => {
+                let mut options = match self.method {
+                    Some(method) => crate::MatcherOptions::method_default(method),
+                    None => crate::MatcherOptions::default(),
+                };
+                if let Some(threshold) = self.threshold {
+                    options = options.with_threshold(threshold);
+                }
+                if let Some(region) = self.region {
+                    options = options.in_region(region.resolve(ap)?);
+                }
+                match (self.nth, self.cache) {
+                    (Some(nth), _) => ap
+                        .find_all_images(&template.image, &options)?
+                        .get(nth)
+                        .map(|m| m.rect),
+                    (None, cache) => ap.find_template_reporting(&handle, &options, cache)?,
+                }
+            }
+            MatchStrategy::Feature => {
+                anyhow::ensure!(
+                    self.method.is_none() && self.nth.is_none(),
+                    "method and nth are only for the template strategy"
+                );
+                let mut options = crate::FeatureMatcherOptions::default();
+                if let Some(region) = self.region {
+                    options = options.in_region(region.resolve(ap)?);
+                }
+                ap.find_image_features(&template.image, &options)?
+            }
+        };
+        match found {
+            Some(rect) => self.click(ctx, rect),
+            None => match self.nth {
+                Some(nth) => anyhow::bail!(
+                    "{} is not on the screen {} times",
+                    self.template.display(),
+                    nth + 1
+                ),
+                None => anyhow::bail!("{} is not on the screen", self.template.display()),
+            },
+        }
+    }
+
+    fn validate(&self, validator: &mut Validator) {
+        validator.template(&self.template);
+        if let Some(region) = self.region {
+            validator.region(region);
+        }
+    }
+}
+
+fn default_drag_duration_ms() -> u64 {
+    500
+}
+
+fn default_slope() -> f32 {
+    1.0
+}
+
+/// Drags from the center of `from` to the center of `to` on the screen, i.e. an
+/// item onto its slot, failing if either of them is not there. The slopes ease
+/// the swipe as in [`Swipe`].
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SwipeMatchTemplate {
+    pub from: PathBuf,
+    pub to: PathBuf,
+    #[serde(default)]
+    pub threshold: Option<f32>,
+    /// Where both templates are searched
+    #[serde(default)]
+    pub region: Option<Region>,
+    #[serde(default = "default_drag_duration_ms")]
+    pub duration_ms: u64,
+    #[serde(default = "default_slope")]
+    pub slope_in: f32,
+    #[serde(default = "default_slope")]
+    pub slope_out: f32,
+}
+
+impl SwipeMatchTemplate {
+    /// The center of `template` on the screen.
+    fn find(&self, ap: &crate::AutoPlay, template: &Path) -> anyhow::Result<(u32, u32)> {
+        let handle = ap.load_template(template)?;
+        let mut options = crate::MatcherOptions::default();
+        if let Some(threshold) = self.threshold {
+            options = options.with_threshold(threshold);
+        }
+        if let Some(region) = self.region {
+            options = options.in_region(region.resolve(ap)?);
+        }
+        let rect = ap
+            .find_template(&handle, &options)?
+            .ok_or_else(|| anyhow::anyhow!("{} is not on the screen", template.display()))?;
+        Ok((rect.x + rect.width / 2, rect.y + rect.height / 2))
+    }
+}
+
+#[typetag::serde]
+impl Action for SwipeMatchTemplate {
+    fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
+        self.execute_in(&ExecContext::new(ap))
+    }
+
+    fn execute_in(&self, ctx: &ExecContext) -> anyhow::Result<()> {
+        let start = self.find(ctx.ap, &self.from)?;
+        let end = self.find(ctx.ap, &self.to)?;
+        ctx.swipe(
+            start,
+            (end.0 as i32, end.1 as i32),
+            Duration::from_millis(self.duration_ms),
+            self.slope_in,
+            self.slope_out,
+        )
+    }
+
+    fn validate(&self, validator: &mut Validator) {
+        validator.template(&self.from);
+        validator.template(&self.to);
+        if let Some(region) = self.region {
+            validator.region(region);
+        }
+    }
+}
+
+/// Which way [`ScrollFind`] scrolls, `direction = "up"` in a task. Scrolling
+/// down swipes up, to show what is below.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ScrollDirection {
+    #[default]
+    Down,
+    Up,
+    Left,
+    Right,
+}
+
+fn default_max_scrolls() -> usize {
+    10
+}
+
+fn default_scroll_distance() -> f32 {
+    0.5
+}
+
+fn default_settle_ms() -> u64 {
+    300
+}
+
+/// Scrolls until `template` is on the screen, i.e. an entry further down a list,
+/// then clicks it if `click` is set. It fails if it is still not there after
+/// `max_scrolls`, and reports the scrolls made as the iterations of the step with
+/// [`Event::RepeatFinished`](crate::event::Event::RepeatFinished).
+///
+/// The swipes go through the center of `region`, of the screen without one, for
+/// `distance` of its height (or width) and are followed by `settle_ms` for the
+/// list to stop moving.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ScrollFind {
+    pub template: PathBuf,
+    #[serde(default)]
+    pub threshold: Option<f32>,
+    /// Where the template is searched and the swipes are made
+    #[serde(default)]
+    pub region: Option<Region>,
+    #[serde(default)]
+    pub direction: ScrollDirection,
+    #[serde(default = "default_max_scrolls")]
+    pub max_scrolls: usize,
+    #[serde(default = "default_scroll_distance")]
+    pub distance: f32,
+    #[serde(default = "default_drag_duration_ms")]
+    pub duration_ms: u64,
+    #[serde(default = "default_slope")]
+    pub slope_in: f32,
+    #[serde(default = "default_slope")]
+    pub slope_out: f32,
+    #[serde(default = "default_settle_ms")]
+    pub settle_ms: u64,
+    #[serde(default)]
+    pub click: bool,
+}
+
+impl ScrollFind {
+    /// The start and the end of a swipe within `area`.
+    fn swipe_points(&self, area: image::math::Rect) -> ((u32, u32), (i32, i32)) {
+        let (cx, cy) = (area.x + area.width / 2, area.y + area.height / 2);
+        let distance = self.distance.clamp(0.0, 1.0);
+        let dx = (area.width as f32 * distance / 2.0) as u32;
+        let dy = (area.height as f32 * distance / 2.0) as u32;
+        let (start, end) = match self.direction {
+            ScrollDirection::Down => ((cx, cy + dy), (cx, cy - dy)),
+            ScrollDirection::Up => ((cx, cy - dy), (cx, cy + dy)),
+            ScrollDirection::Right => ((cx + dx, cy), (cx - dx, cy)),
+            ScrollDirection::Left => ((cx - dx, cy), (cx + dx, cy)),
+        };
+        (start, (end.0 as i32, end.1 as i32))
+    }
+}
+
+#[typetag::serde]
+impl Action for ScrollFind {
+    fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
+        self.execute_in(&ExecContext::new(ap))
+    }
+
+    /// Stops with the task between the scrolls.
+    fn execute_in(&self, ctx: &ExecContext) -> anyhow::Result<()> {
+        let ap = ctx.ap;
+        let handle = ap.load_template(&self.template)?;
+        let mut options = crate::MatcherOptions::default();
+        if let Some(threshold) = self.threshold {
+            options = options.with_threshold(threshold);
+        }
+        if let Some(region) = self.region {
+            options = options.in_region(region.resolve(ap)?);
+        }
+        let area = match self.region {
+            Some(region) => region.resolve(ap)?,
+            None => {
+                let (width, height) = ap.screen_size();
+                image::math::Rect {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                }
+            }
+        };
+        let (start, end) = self.swipe_points(area);
+
+        let mut scrolls = 0;
+        let found = loop {
+            ctx.token
+                .check()
+                .with_context(|| format!("stopped after {scrolls} scrolls"))?;
+            if let Some(rect) = ap.find_template(&handle, &options)? {
+                break Some(rect);
+            }
+            if scrolls == self.max_scrolls {
+                break None;
+            }
+            ctx.swipe(
+                start,
+                end,
+                Duration::from_millis(self.duration_ms),
+                self.slope_in,
+                self.slope_out,
+            )?;
+            std::thread::sleep(Duration::from_millis(self.settle_ms));
+            scrolls += 1;
+        };
+        ap.events().emit(crate::event::Event::RepeatFinished {
+            iterations: scrolls,
+            done: found.is_some(),
+        });
+        let Some(rect) = found else {
+            anyhow::bail!(
+                "{} is not on the screen after {scrolls} scrolls",
+                self.template.display()
+            );
+        };
+        if self.click {
+            ctx.click_rect(rect)?;
+        }
+        Ok(())
+    }
+
+    fn validate(&self, validator: &mut Validator) {
+        validator.template(&self.template);
+        if let Some(region) = self.region {
+            validator.region(region);
+        }
+    }
+}
+
+/// Clicks the center of the first line of text containing `text`, failing if
+/// there is none. Needs an OCR engine, see [`crate::AutoPlay::set_ocr`].
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClickMatchText {
+    pub text: String,
+    #[serde(default)]
+    pub region: Option<Region>,
+}
+
+#[typetag::serde]
+impl Action for ClickMatchText {
+    fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
+        self.execute_in(&ExecContext::new(ap))
+    }
+
+    fn execute_in(&self, ctx: &ExecContext) -> anyhow::Result<()> {
+        let ap = ctx.ap;
+        let Some(line) = ap.find_text(
+            &self.text,
+            self.region.map(|region| region.resolve(ap)).transpose()?,
+        )?
+        else {
+            anyhow::bail!("{:?} is not on the screen", self.text);
+        };
+        ctx.click_rect(line.rect)
+    }
+
+    fn validate(&self, validator: &mut Validator) {
+        if let Some(region) = self.region {
+            validator.region(region);
+        }
+    }
+}
+
+fn default_timeout_ms() -> u64 {
+    10_000
+}
+
+/// Waits for a line of text containing `text`, failing with [`crate::Error::Timeout`]
+/// after `timeout_ms`. Needs an OCR engine, see [`crate::AutoPlay::set_ocr`].
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WaitText {
+    pub text: String,
+    #[serde(default)]
+    pub region: Option<Region>,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+#[typetag::serde]
+impl Action for WaitText {
+    fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
+        let timeout = Duration::from_millis(self.timeout_ms);
+        match ap.wait_for_text(
+            &self.text,
+            self.region.map(|region| region.resolve(ap)).transpose()?,
+            timeout,
+        )? {
+            Some(_) => Ok(()),
+            None => Err(crate::Error::Timeout(timeout).into()),
+        }
+    }
+
+    fn validate(&self, validator: &mut Validator) {
+        if let Some(region) = self.region {
+            validator.region(region);
+        }
+    }
+}
+
+fn default_stable_threshold() -> f32 {
+    0.01
+}
+
+/// Waits until `region` of the screen, all of it without one, stops changing,
+/// failing with [`crate::Error::Timeout`] after `timeout_ms`. It is still once the
+/// mean change between two captures is at most `threshold`, from 0 to 1.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WaitStable {
+    #[serde(default)]
+    pub region: Option<Region>,
+    #[serde(default = "default_stable_threshold")]
+    pub threshold: f32,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+#[typetag::serde]
+impl Action for WaitStable {
+    fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
+        ap.wait_region_stable(
+            self.region.map(|region| region.resolve(ap)).transpose()?,
+            self.threshold,
+            Duration::from_millis(self.timeout_ms),
+        )
+    }
+
+    fn validate(&self, validator: &mut Validator) {
+        if let Some(region) = self.region {
+            validator.region(region);
+        }
+    }
+}
+
+fn default_color_tolerance() -> u8 {
+    10
+}
+
+/// Fails unless the pixel at `(x, y)` is `color` (`"#RRGGBB"`), each channel within
+/// `tolerance`. With a `region` its average color is checked instead.
+///
+/// As a step it stops a task that is not where it expects to be, and as a
+/// [`Node::with_precondition`](crate::nav::Node::with_precondition) it tells
+/// whether a node is the current one.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CheckColor {
+    #[serde(default)]
+    pub x: u32,
+    #[serde(default)]
+    pub y: u32,
+    #[serde(default)]
+    pub region: Option<Region>,
+    pub color: String,
+    #[serde(default = "default_color_tolerance")]
+    pub tolerance: u8,
+}
+
+impl CheckColor {
+    /// Whether the color is there, failing only if it can not be checked.
+    pub fn check(&self, ap: &crate::AutoPlay) -> anyhow::Result<bool> {
+        let Some(expected) = ap_cv::color::parse_hex(&self.color) else {
+            anyhow::bail!("invalid color {:?}, expected #RRGGBB", self.color);
+        };
+        match self.region {
+            Some(region) => ap.check_region_color(region.resolve(ap)?, expected, self.tolerance),
+            None => ap.check_color((self.x, self.y), expected, self.tolerance),
+        }
+    }
+}
+
+#[typetag::serde]
+impl Action for CheckColor {
+    fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
+        if !self.check(ap)? {
+            match self.region {
+                Some(region) => anyhow::bail!("{region:?} is not {}", self.color),
+                None => anyhow::bail!("({}, {}) is not {}", self.x, self.y, self.color),
+            }
+        }
+        Ok(())
+    }
+
+    fn validate(&self, validator: &mut Validator) {
+        validator.color(&self.color);
+        match self.region {
+            Some(region) => validator.region(region),
+            None => validator.point(self.x, self.y),
+        }
+    }
+}
+
+/// Traits of a device, those left out match any device. Devices without a
+/// profile match none.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct DeviceCondition {
+    #[serde(default)]
+    pub abi: Option<String>,
+    #[serde(default)]
+    pub min_sdk: Option<u32>,
+    #[serde(default)]
+    pub max_sdk: Option<u32>,
+    #[serde(default)]
+    pub min_density: Option<u32>,
+}
+
+impl DeviceCondition {
+    pub fn matches(&self, profile: &ap_controller::DeviceProfile) -> bool {
+        self.abi.as_ref().is_none_or(|abi| *abi == profile.abi)
+            && self.min_sdk.is_none_or(|sdk| profile.sdk >= sdk)
+            && self.max_sdk.is_none_or(|sdk| profile.sdk <= sdk)
+            && self
+                .min_density
+                .is_none_or(|density| profile.density.is_some_and(|d| d >= density))
+    }
+}
+
+/// What an [`If`] step checks
+#[derive(Serialize, Deserialize)]
+pub enum Condition {
+    /// `template` is on the screen, `{ Template = { template = "ok.png" } }` in a task
+    Template {
+        template: PathBuf,
+        #[serde(default)]
+        threshold: Option<f32>,
+        #[serde(default)]
+        region: Option<Region>,
+    },
+    /// The [`CheckColor`] step would pass
+    Color(CheckColor),
+    /// The device has the traits given, `{ Device = { min_sdk = 30 } }` in a task,
+    /// see [`crate::AutoPlay::device_profile`]
+    Device(DeviceCondition),
+    /// The action succeeds, i.e. a plugin or a task run to find out
+    Succeeds(Box<dyn Action>),
+    Not(Box<Condition>),
+}
+
+impl Condition {
+    pub fn check(&self, ap: &crate::AutoPlay) -> anyhow::Result<bool> {
+        self.check_in(&ExecContext::new(ap))
+    }
+
+    /// [`Condition::check`] running [`Condition::Succeeds`] with `ctx`.
+    pub fn check_in(&self, ctx: &ExecContext) -> anyhow::Result<bool> {
+        let ap = ctx.ap;
+        match self {
+            Condition::Template {
+                template,
+                threshold,
+                region,
+            } => {
+                let handle = ap.load_template(template)?;
+                let mut options = crate::MatcherOptions::default();
+                if let Some(threshold) = threshold {
+                    options = options.with_threshold(*threshold);
+                }
+                if let Some(region) = region {
+                    options = options.in_region(region.resolve(ap)?);
+                }
+                Ok(ap.find_template(&handle, &options)?.is_some())
+            }
+            Condition::Color(check) => check.check(ap),
+            Condition::Device(cond) => Ok(ap
+                .device_profile()
+                .is_some_and(|profile| cond.matches(&profile))),
+            // Being cancelled is not the action failing, it stops the task
+            Condition::Succeeds(action) => match action.execute_in(ctx) {
+                Ok(()) => Ok(true),
+                Err(err) if matches!(err.downcast_ref(), Some(crate::Error::Cancelled)) => Err(err),
+                Err(_) => Ok(false),
+            },
+            Condition::Not(cond) => cond.check_in(ctx).map(|res| !res),
+        }
+    }
+
+    pub fn validate(&self, validator: &mut Validator) {
+        match self {
+            Condition::Template {
+                template, region, ..
+            } => {
+                validator.template(template);
+                if let Some(region) = region {
+                    validator.region(*region);
+                }
+            }
+            Condition::Color(check) => check.validate(validator),
+            Condition::Device(_) => {}
+            Condition::Succeeds(action) => action.validate(validator),
+            Condition::Not(cond) => cond.validate(validator),
+        }
+    }
+}
+
+/// Runs `then_steps` if `cond` holds and `else_steps` otherwise, failing at the
+/// first step that fails:
+///
+/// ```toml
+/// [[steps]]
+/// [steps.If]
+/// cond = { Template = { template = "assets/reward.png" } }
+/// then_steps = [{ Click = { x = 960, y = 540 } }]
+/// else_steps = [{ WaitAction = { ms = 1000 } }]
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct If {
+    pub cond: Condition,
+    #[serde(default)]
+    pub then_steps: Vec<Box<dyn Action>>,
+    #[serde(default)]
+    pub else_steps: Vec<Box<dyn Action>>,
+}
+
+#[typetag::serde]
+impl Action for If {
+    fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
+        self.execute_in(&ExecContext::new(ap))
+    }
+
+    fn execute_in(&self, ctx: &ExecContext) -> anyhow::Result<()> {
+        let steps = match self.cond.check_in(ctx)? {
+            true => &self.then_steps,
+            false => &self.else_steps,
+        };
+        steps.iter().try_for_each(|step| {
+            ctx.token.check()?;
+            step.execute_in(ctx)
+        })
+    }
+
+    fn validate(&self, validator: &mut Validator) {
+        self.cond.validate(validator);
+        validator.steps("then_steps", &self.then_steps);
+        validator.steps("else_steps", &self.else_steps);
+    }
+}
+
+fn default_max_iters() -> usize {
+    100
+}
+
+/// Runs `steps` again and again until `until` holds, at most `max_iters` times,
+/// i.e. to farm until out of stamina. The condition is checked before each
+/// iteration, and the iterations are reported with [`Event::RepeatFinished`].
+///
+/// Reaching the cap is not a failure, a failing step is.
+///
+/// [`Event::RepeatFinished`]: crate::event::Event::RepeatFinished
+#[derive(Serialize, Deserialize)]
+pub struct Repeat {
+    pub until: Condition,
+    #[serde(default = "default_max_iters")]
+    pub max_iters: usize,
+    pub steps: Vec<Box<dyn Action>>,
+}
+
+#[typetag::serde]
+impl Action for Repeat {
+    fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
+        self.execute_in(&ExecContext::new(ap))
+    }
+
+    fn execute_in(&self, ctx: &ExecContext) -> anyhow::Result<()> {
+        let mut iterations = 0;
+        let done = loop {
+            ctx.token
+                .check()
+                .with_context(|| format!("stopped before iteration {iterations}"))?;
+            if self.until.check_in(ctx)? {
+                break true;
+            }
+            if iterations == self.max_iters {
+                break false;
+            }
+            self.steps
+                .iter()
+                .try_for_each(|step| {
+                    ctx.token.check()?;
+                    step.execute_in(ctx)
+                })
+                .with_context(|| format!("in iteration {iterations}"))?;
+            iterations += 1;
+        };
+        ctx.ap
+            .events()
+            .emit(crate::event::Event::RepeatFinished { iterations, done });
+        Ok(())
+    }
+
+    fn validate(&self, validator: &mut Validator) {
+        self.until.validate(validator);
+        validator.steps("steps", &self.steps);
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -88,6 +1133,20 @@ impl Action for WaitAction {
     }
 }
 
+/// Call `f` with the [`AppManagement`](ap_controller::AppManagement) of the
+/// controller, failing with [`crate::Error::Unsupported`] if it has none.
+fn with_apps<R>(
+    ap: &crate::AutoPlay,
+    f: impl FnOnce(&dyn ap_controller::AppManagement) -> anyhow::Result<R>,
+) -> anyhow::Result<R> {
+    let controller = ap.controller();
+    let apps = controller
+        .app_management()
+        .ok_or(crate::Error::Unsupported("manage apps"))?;
+    f(apps)
+}
+
+/// Starts the app `package`, or an activity of it as `<package>/<activity>`.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LaunchAppAction {
     pub package: String,
@@ -96,10 +1155,66 @@ pub struct LaunchAppAction {
 #[typetag::serde]
 impl Action for LaunchAppAction {
     fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
-        use ap_controller::AndroidController;
-        let android = ap
-            .controller_ref::<AndroidController>()
-            .ok_or_else(|| anyhow::anyhow!("not an android controller"))?;
-        android.launch_app(&self.package)
+        with_apps(ap, |apps| apps.launch_app(&self.package))
+    }
+}
+
+/// Force-stops the app `package`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StopApp {
+    pub package: String,
+}
+
+#[typetag::serde]
+impl Action for StopApp {
+    fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
+        with_apps(ap, |apps| apps.stop_app(&self.package))
+    }
+}
+
+/// Waits until the app `package` is in the foreground, i.e. after launching it,
+/// failing with [`crate::Error::Timeout`] after `timeout_ms`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WaitFocus {
+    pub package: String,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+#[typetag::serde]
+impl Action for WaitFocus {
+    fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
+        let timeout = Duration::from_millis(self.timeout_ms);
+        with_apps(ap, |apps| {
+            let start = std::time::Instant::now();
+            while start.elapsed() < timeout {
+                if apps.current_app()?.as_deref() == Some(self.package.as_str()) {
+                    return Ok(());
+                }
+                std::thread::sleep(Duration::from_millis(500));
+            }
+            Err(crate::Error::Timeout(timeout).into())
+        })
+    }
+}
+
+/// Runs the plugin registered as `name` in [`AutoPlay::plugins`](crate::AutoPlay::plugins).
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PluginAction {
+    pub name: String,
+}
+
+#[typetag::serde]
+impl Action for PluginAction {
+    fn execute(&self, ap: &crate::AutoPlay) -> anyhow::Result<()> {
+        let plugin = ap
+            .plugins()
+            .get(&self.name)
+            .ok_or_else(|| anyhow::anyhow!("plugin {} is not registered", self.name))?;
+        plugin(ap)
+    }
+
+    fn validate(&self, validator: &mut Validator) {
+        validator.plugin(&self.name);
     }
 }
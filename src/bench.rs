@@ -0,0 +1,737 @@
+//! Measure the full automation loop, screencap → preprocess → match → click, to
+//! compare capture methods and matching backends on a given setup.
+//!
+//! ```ignore
+//! let configs = [
+//!     BenchConfig::new(CaptureMethod::Raw, MatchBackend::Gpu),
+//!     BenchConfig::new(CaptureMethod::AdbPng, MatchBackend::Gpu),
+//!     BenchConfig::new(CaptureMethod::Raw, MatchBackend::Cpu),
+//! ];
+//! for (config, res) in bench_all(&ap, &template, &configs) {
+//!     println!("{config}: {:?}", res?.mean().total);
+//! }
+//! ```
+//!
+//! [`probe_latency`] measures every stage on its own instead, with the p50 and
+//! p95 of each capture method, matching backend and input method. Its
+//! [`LatencyReport`] can be saved as JSON and compared with a later one to spot
+//! regressions:
+//!
+//! ```ignore
+//! let report = probe_latency(&ap, &ProbeOptions::default().with_tap(Some((10, 10))))?;
+//! for regression in report.regressions(&baseline, 0.2) {
+//!     println!("{regression}");
+//! }
+//! ```
+
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use ap_adb::command::local_service::{Input, ScreenCapPng};
+use ap_controller::ControllerTrait;
+use image::{DynamicImage, GrayImage, ImageBuffer, Luma, RgbaImage, math::Rect};
+use imageproc::template_matching::{self, find_extremes};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AndroidController, AutoPlay, MatchTemplateMethod, MatcherOptions, cv::matcher::SingleMatcher,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureMethod {
+    /// [`AutoPlay::screencap_raw`], RGBA pixels as they come from the controller
+    Raw,
+    /// [`AutoPlay::screencap`]
+    Decoded,
+    /// `screencap -p` through an adb socket, Android only
+    AdbPng,
+    /// `adb exec-out screencap` as a child process, Android only
+    AdbProcess,
+}
+
+impl Display for CaptureMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            CaptureMethod::Raw => "raw",
+            CaptureMethod::Decoded => "decoded",
+            CaptureMethod::AdbPng => "adb-png",
+            CaptureMethod::AdbProcess => "adb-process",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchBackend {
+    /// The wgpu matcher of [`cv`](crate::cv)
+    Gpu,
+    /// `imageproc` on 8 bit luma, only supports the squared difference and cross
+    /// correlation methods. Thresholds of the non normed methods are on a different
+    /// scale than the GPU ones.
+    Cpu,
+}
+
+impl Display for MatchBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            MatchBackend::Gpu => "gpu",
+            MatchBackend::Cpu => "cpu",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    pub capture: CaptureMethod,
+    pub backend: MatchBackend,
+    pub options: MatcherOptions,
+    /// Click the match, off by default to leave the device alone
+    pub click: bool,
+    pub iterations: usize,
+    /// Iterations run before measuring, i.e. to initialize the GPU
+    pub warmup: usize,
+}
+
+impl BenchConfig {
+    pub fn new(capture: CaptureMethod, backend: MatchBackend) -> Self {
+        Self {
+            capture,
+            backend,
+            options: MatcherOptions::default(),
+            click: false,
+            iterations: 10,
+            warmup: 1,
+        }
+    }
+
+    pub fn with_options(mut self, options: MatcherOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_click(mut self, click: bool) -> Self {
+        self.click = click;
+        self
+    }
+
+    pub fn with_iterations(mut self, iterations: usize) -> Self {
+        self.iterations = iterations;
+        self
+    }
+}
+
+impl Display for BenchConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}/{}/{}",
+            self.capture, self.backend, self.options.method
+        )
+    }
+}
+
+/// Timings of a single iteration of the loop
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LoopTimings {
+    pub capture: Duration,
+    /// Decoding and converting to the format of the matcher
+    pub preprocess: Duration,
+    pub matching: Duration,
+    /// Zero if clicking is off or nothing matched
+    pub click: Duration,
+    pub total: Duration,
+    pub matched: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub samples: Vec<LoopTimings>,
+}
+
+impl BenchResult {
+    /// Mean of every stage, `matched` is whether most iterations matched.
+    pub fn mean(&self) -> LoopTimings {
+        let len = self.samples.len().max(1) as u32;
+        let sum =
+            |f: fn(&LoopTimings) -> Duration| self.samples.iter().map(f).sum::<Duration>() / len;
+        LoopTimings {
+            capture: sum(|t| t.capture),
+            preprocess: sum(|t| t.preprocess),
+            matching: sum(|t| t.matching),
+            click: sum(|t| t.click),
+            total: sum(|t| t.total),
+            matched: self.samples.iter().filter(|t| t.matched).count() * 2 > self.samples.len(),
+        }
+    }
+
+    /// The `p`th percentile of the total time, with `p` in `[0, 1]`.
+    pub fn percentile(&self, p: f32) -> Duration {
+        percentile(self.samples.iter().map(|t| t.total).collect(), p)
+    }
+}
+
+/// The `p`th percentile of `durations`, with `p` in `[0, 1]`, zero if empty.
+fn percentile(mut durations: Vec<Duration>, p: f32) -> Duration {
+    durations.sort();
+    let idx = ((durations.len().max(1) - 1) as f32 * p.clamp(0.0, 1.0)).round() as usize;
+    durations.get(idx).copied().unwrap_or_default()
+}
+
+/// Run the loop of `config` looking for `template`.
+pub fn bench_loop(
+    ap: &AutoPlay,
+    template: &DynamicImage,
+    config: &BenchConfig,
+) -> anyhow::Result<BenchResult> {
+    if config.backend == MatchBackend::Cpu {
+        cpu_method(config.options.method)?;
+    }
+    let template = Template::new(template, config.backend);
+    for _ in 0..config.warmup {
+        run_once(ap, &template, config)?;
+    }
+    let samples = (0..config.iterations)
+        .map(|_| run_once(ap, &template, config))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(BenchResult { samples })
+}
+
+/// [`bench_loop`] every config, a failing one does not stop the others.
+pub fn bench_all<'a>(
+    ap: &AutoPlay,
+    template: &DynamicImage,
+    configs: &'a [BenchConfig],
+) -> Vec<(&'a BenchConfig, anyhow::Result<BenchResult>)> {
+    configs
+        .iter()
+        .map(|config| (config, bench_loop(ap, template, config)))
+        .collect()
+}
+
+enum Template {
+    Gpu(ImageBuffer<Luma<f32>, Vec<f32>>),
+    Cpu(GrayImage),
+}
+
+impl Template {
+    fn new(template: &DynamicImage, backend: MatchBackend) -> Self {
+        match backend {
+            MatchBackend::Gpu => Self::Gpu(template.to_luma32f()),
+            MatchBackend::Cpu => Self::Cpu(template.to_luma8()),
+        }
+    }
+}
+
+/// A captured frame, decoding is left to the preprocess stage.
+enum Frame {
+    Rgba(u32, u32, Vec<u8>),
+    Image(DynamicImage),
+    Png(Vec<u8>),
+}
+
+fn capture(ap: &AutoPlay, method: CaptureMethod) -> anyhow::Result<Frame> {
+    let android = |f: fn(&AndroidController) -> anyhow::Result<Frame>| {
+        ap.with_controller(f)
+            .unwrap_or_else(|| anyhow::bail!("capture method {method} needs an android controller"))
+    };
+    // Measure a capture rather than a frame reused from the cache
+    ap.controller().invalidate_frame();
+    match method {
+        CaptureMethod::Raw => {
+            let (width, height, rgba) = ap.screencap_raw()?;
+            Ok(Frame::Rgba(width, height, rgba))
+        }
+        CaptureMethod::Decoded => Ok(Frame::Image(ap.screencap()?)),
+        CaptureMethod::AdbPng => android(|android| {
+            Ok(Frame::Png(
+                android
+                    .device()
+                    .execute_command_by_socket(ScreenCapPng::new())?,
+            ))
+        }),
+        CaptureMethod::AdbProcess => android(|android| {
+            let data = android
+                .device()
+                .execute_command_by_process("exec-out screencap")?;
+            anyhow::ensure!(data.len() >= 12, "screencap output is too short");
+            let width = u32::from_le_bytes(data[0..4].try_into().unwrap());
+            let height = u32::from_le_bytes(data[4..8].try_into().unwrap());
+            let len = (width * height * 4) as usize;
+            // The header is 12 or 16 bytes depending on the Android version
+            anyhow::ensure!(data.len() >= len + 12, "screencap output is too short");
+            Ok(Frame::Rgba(
+                width,
+                height,
+                data[data.len() - len..].to_vec(),
+            ))
+        }),
+    }
+}
+
+fn preprocess(frame: Frame) -> anyhow::Result<DynamicImage> {
+    Ok(match frame {
+        Frame::Rgba(width, height, rgba) => DynamicImage::ImageRgba8(
+            RgbaImage::from_raw(width, height, rgba)
+                .ok_or_else(|| anyhow::anyhow!("screencap size mismatch"))?,
+        ),
+        Frame::Image(image) => image,
+        Frame::Png(png) => image::load_from_memory(&png)?,
+    })
+}
+
+fn cpu_method(
+    method: MatchTemplateMethod,
+) -> anyhow::Result<template_matching::MatchTemplateMethod> {
+    use template_matching::MatchTemplateMethod as Cpu;
+    Ok(match method {
+        MatchTemplateMethod::SumOfSquaredDifference => Cpu::SumOfSquaredErrors,
+        MatchTemplateMethod::SumOfSquaredDifferenceNormed => Cpu::SumOfSquaredErrorsNormalized,
+        MatchTemplateMethod::CrossCorrelation => Cpu::CrossCorrelation,
+        MatchTemplateMethod::CrossCorrelationNormed => Cpu::CrossCorrelationNormalized,
+        method => anyhow::bail!("{method} is not supported on the cpu"),
+    })
+}
+
+/// Match `template` on the CPU, with the same thresholding as [`SingleMatcher`].
+fn match_cpu(
+    screen: &GrayImage,
+    template: &GrayImage,
+    options: &MatcherOptions,
+) -> anyhow::Result<Option<Rect>> {
+    let res = template_matching::match_template(screen, template, cpu_method(options.method)?);
+    let extremes = find_extremes(&res);
+    let (matched, (x, y)) = match options.method {
+        MatchTemplateMethod::SumOfSquaredDifference
+        | MatchTemplateMethod::SumOfSquaredDifferenceNormed => (
+            extremes.min_value < options.threshold,
+            extremes.min_value_location,
+        ),
+        _ => (
+            extremes.max_value > options.threshold,
+            extremes.max_value_location,
+        ),
+    };
+    Ok(matched.then_some(Rect {
+        x,
+        y,
+        width: template.width(),
+        height: template.height(),
+    }))
+}
+
+fn run_once(
+    ap: &AutoPlay,
+    template: &Template,
+    config: &BenchConfig,
+) -> anyhow::Result<LoopTimings> {
+    let mut timings = LoopTimings::default();
+    let start = Instant::now();
+
+    let frame = capture(ap, config.capture)?;
+    timings.capture = start.elapsed();
+
+    let t = Instant::now();
+    let screen = preprocess(frame)?;
+    let rect = match template {
+        Template::Gpu(template) => {
+            let screen = screen.to_luma32f();
+            timings.preprocess = t.elapsed();
+            let t = Instant::now();
+            let res = SingleMatcher::match_template(&screen, template, &config.options);
+            timings.matching = t.elapsed();
+            res.result.map(|m| m.rect)
+        }
+        Template::Cpu(template) => {
+            let screen = screen.to_luma8();
+            timings.preprocess = t.elapsed();
+            let t = Instant::now();
+            let res = match_cpu(&screen, template, &config.options)?;
+            timings.matching = t.elapsed();
+            res
+        }
+    };
+
+    timings.matched = rect.is_some();
+    if let Some(rect) = rect
+        && config.click
+    {
+        let t = Instant::now();
+        ap.click_rect(rect)?;
+        timings.click = t.elapsed();
+    }
+    timings.total = start.elapsed();
+    Ok(timings)
+}
+
+// ===== Latency probe =====
+
+/// How an input is sent, for [`probe_latency`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputMethod {
+    /// A click of the controller, with its own backend (maatouch, `SendInput`...)
+    Controller,
+    /// `input tap` through an adb socket, Android only
+    AdbSocket,
+    /// `adb shell input tap` as a child process, Android only
+    AdbProcess,
+}
+
+impl Display for InputMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            InputMethod::Controller => "controller",
+            InputMethod::AdbSocket => "adb-socket",
+            InputMethod::AdbProcess => "adb-process",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ProbeOptions {
+    /// Measures of every stage
+    pub samples: usize,
+    /// Runs of every stage before measuring, i.e. to initialize the GPU
+    pub warmup: usize,
+    pub captures: Vec<CaptureMethod>,
+    pub backends: Vec<MatchBackend>,
+    pub inputs: Vec<InputMethod>,
+    /// Where to tap to measure the inputs, `None` to leave the device alone and
+    /// skip them
+    pub tap: Option<(u32, u32)>,
+}
+
+impl Default for ProbeOptions {
+    fn default() -> Self {
+        Self {
+            samples: 20,
+            warmup: 1,
+            captures: vec![
+                CaptureMethod::Raw,
+                CaptureMethod::Decoded,
+                CaptureMethod::AdbPng,
+                CaptureMethod::AdbProcess,
+            ],
+            backends: vec![MatchBackend::Gpu, MatchBackend::Cpu],
+            inputs: vec![
+                InputMethod::Controller,
+                InputMethod::AdbSocket,
+                InputMethod::AdbProcess,
+            ],
+            tap: None,
+        }
+    }
+}
+
+impl ProbeOptions {
+    pub fn with_samples(mut self, samples: usize) -> Self {
+        self.samples = samples;
+        self
+    }
+
+    pub fn with_tap(mut self, tap: Option<(u32, u32)>) -> Self {
+        self.tap = tap;
+        self
+    }
+}
+
+/// The latency of a stage, in milliseconds
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StageLatency {
+    /// i.e. `capture/raw`, `match/gpu` or `input/adb-socket`
+    pub stage: String,
+    pub samples: usize,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+    /// Why it could not be measured, i.e. an Android only method on Windows
+    pub error: Option<String>,
+}
+
+impl StageLatency {
+    fn new(stage: String, samples: Vec<Duration>) -> Self {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+        Self {
+            stage,
+            samples: samples.len(),
+            p50_ms: ms(percentile(samples.clone(), 0.5)),
+            p95_ms: ms(percentile(samples.clone(), 0.95)),
+            max_ms: ms(samples.into_iter().max().unwrap_or_default()),
+            error: None,
+        }
+    }
+
+    fn failed(stage: String, err: anyhow::Error) -> Self {
+        Self {
+            error: Some(format!("{err:#}")),
+            ..Self::new(stage, Vec::new())
+        }
+    }
+
+    fn measured(stage: String, res: anyhow::Result<Vec<Duration>>) -> Self {
+        match res {
+            Ok(samples) => Self::new(stage, samples),
+            Err(err) => Self::failed(stage, err),
+        }
+    }
+}
+
+/// The result of [`probe_latency`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LatencyReport {
+    /// The serial of the device, if it has one
+    pub device: Option<String>,
+    pub stages: Vec<StageLatency>,
+}
+
+/// A stage slower than in a baseline, see [`LatencyReport::regressions`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regression {
+    pub stage: String,
+    pub baseline_ms: f64,
+    pub p50_ms: f64,
+}
+
+impl Display for Regression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: p50 {:.1}ms -> {:.1}ms (+{:.0}%)",
+            self.stage,
+            self.baseline_ms,
+            self.p50_ms,
+            (self.p50_ms / self.baseline_ms - 1.0) * 100.0
+        )
+    }
+}
+
+impl LatencyReport {
+    pub fn stage(&self, stage: &str) -> Option<&StageLatency> {
+        self.stages.iter().find(|s| s.stage == stage)
+    }
+
+    /// The stages whose p50 is more than `tolerance` (i.e. `0.2` for 20%) above
+    /// the one of the same stage in `baseline`. Stages missing or failed in
+    /// either report are left out.
+    pub fn regressions(&self, baseline: &LatencyReport, tolerance: f64) -> Vec<Regression> {
+        self.stages
+            .iter()
+            .filter(|stage| stage.error.is_none())
+            .filter_map(|stage| {
+                let base = baseline.stage(&stage.stage)?;
+                (base.error.is_none()
+                    && base.p50_ms > 0.0
+                    && stage.p50_ms > base.p50_ms * (1.0 + tolerance))
+                    .then(|| Regression {
+                        stage: stage.stage.clone(),
+                        baseline_ms: base.p50_ms,
+                        p50_ms: stage.p50_ms,
+                    })
+            })
+            .collect()
+    }
+}
+
+impl Display for LatencyReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "stage\tp50\tp95\tmax")?;
+        for stage in &self.stages {
+            match &stage.error {
+                Some(err) => writeln!(f, "{}\terror: {err}", stage.stage)?,
+                None => writeln!(
+                    f,
+                    "{}\t{:.1}ms\t{:.1}ms\t{:.1}ms",
+                    stage.stage, stage.p50_ms, stage.p95_ms, stage.max_ms
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Time `f` `samples` times after `warmup` runs, stopping at its first error.
+fn measure(
+    warmup: usize,
+    samples: usize,
+    mut f: impl FnMut() -> anyhow::Result<()>,
+) -> anyhow::Result<Vec<Duration>> {
+    for _ in 0..warmup {
+        f()?;
+    }
+    (0..samples)
+        .map(|_| {
+            let start = Instant::now();
+            f()?;
+            Ok(start.elapsed())
+        })
+        .collect()
+}
+
+fn tap(ap: &AutoPlay, method: InputMethod, (x, y): (u32, u32)) -> anyhow::Result<()> {
+    let android = |f: &dyn Fn(&AndroidController) -> anyhow::Result<()>| {
+        ap.with_controller(f)
+            .unwrap_or_else(|| anyhow::bail!("input method {method} needs an android controller"))
+    };
+    match method {
+        InputMethod::Controller => ap.controller().click(x, y),
+        InputMethod::AdbSocket => {
+            android(&|android| Ok(android.device().input(Input::Tap(x, y))?))
+        }
+        InputMethod::AdbProcess => android(&|android| {
+            android
+                .device()
+                .execute_command_by_process(&format!("shell input tap {x} {y}"))?;
+            Ok(())
+        }),
+    }
+}
+
+/// Measure every capture method, matching backend and input method of `options`
+/// on its own, see the [module](self) docs. A method that fails is reported
+/// with its error without stopping the others.
+///
+/// Captures are timed until the frame is an image, decoding included. Matching
+/// looks for a crop of the center of the screen on a capture taken beforehand.
+pub fn probe_latency(ap: &AutoPlay, options: &ProbeOptions) -> anyhow::Result<LatencyReport> {
+    let mut stages = Vec::new();
+    for &method in &options.captures {
+        let stage = format!("capture/{method}");
+        let res = measure(options.warmup, options.samples, || {
+            preprocess(capture(ap, method)?)?;
+            Ok(())
+        });
+        stages.push(StageLatency::measured(stage, res));
+    }
+
+    let screen = ap.screencap()?;
+    let (width, height) = (screen.width().min(64), screen.height().min(64));
+    let template = screen.crop_imm(
+        (screen.width() - width) / 2,
+        (screen.height() - height) / 2,
+        width,
+        height,
+    );
+    for &backend in &options.backends {
+        let stage = format!("match/{backend}");
+        let matcher = MatcherOptions::default();
+        let res = match Template::new(&template, backend) {
+            Template::Gpu(template) => {
+                let screen = screen.to_luma32f();
+                measure(options.warmup, options.samples, || {
+                    SingleMatcher::match_template(&screen, &template, &matcher);
+                    Ok(())
+                })
+            }
+            Template::Cpu(template) => {
+                let screen = screen.to_luma8();
+                measure(options.warmup, options.samples, || {
+                    match_cpu(&screen, &template, &matcher)?;
+                    Ok(())
+                })
+            }
+        };
+        stages.push(StageLatency::measured(stage, res));
+    }
+
+    if let Some(point) = options.tap {
+        for &method in &options.inputs {
+            let stage = format!("input/{method}");
+            let res = measure(options.warmup, options.samples, || tap(ap, method, point));
+            stages.push(StageLatency::measured(stage, res));
+        }
+    }
+
+    Ok(LatencyReport {
+        device: ap
+            .controller()
+            .device_profile()
+            .map(|profile| profile.serial.clone()),
+        stages,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_match_cpu() {
+        let mut screen = GrayImage::new(64, 48);
+        for (x, y, pixel) in screen.enumerate_pixels_mut() {
+            pixel.0[0] = ((x * x * 31 + y * y * 17 + x * y * 7) % 251) as u8;
+        }
+        let template = image::imageops::crop_imm(&screen, 20, 10, 8, 8).to_image();
+        let options =
+            MatcherOptions::method_default(MatchTemplateMethod::SumOfSquaredDifferenceNormed);
+        let rect = match_cpu(&screen, &template, &options).unwrap().unwrap();
+        assert_eq!((rect.x, rect.y, rect.width, rect.height), (20, 10, 8, 8));
+
+        let options = MatcherOptions::method_default(MatchTemplateMethod::CorrelationCoefficient);
+        assert!(match_cpu(&screen, &template, &options).is_err());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let result = BenchResult {
+            samples: (1..=10)
+                .map(|ms| LoopTimings {
+                    total: Duration::from_millis(ms),
+                    ..Default::default()
+                })
+                .collect(),
+        };
+        assert_eq!(result.percentile(0.0), Duration::from_millis(1));
+        assert_eq!(result.percentile(0.5), Duration::from_millis(6));
+        assert_eq!(result.percentile(1.0), Duration::from_millis(10));
+        assert_eq!(result.mean().total, Duration::from_micros(5500));
+    }
+
+    #[test]
+    fn test_regressions() {
+        let stage = |name: &str, ms: u64| {
+            StageLatency::new(
+                name.to_string(),
+                (0..5).map(|_| Duration::from_millis(ms)).collect(),
+            )
+        };
+        let baseline = LatencyReport {
+            device: None,
+            stages: vec![
+                stage("capture/raw", 20),
+                stage("match/gpu", 10),
+                StageLatency::failed("capture/adb-png".to_string(), anyhow::anyhow!("no adb")),
+            ],
+        };
+        assert_eq!(baseline.stages[0].p50_ms, 20.0);
+        assert_eq!(baseline.stages[2].samples, 0);
+
+        let report = LatencyReport {
+            device: None,
+            stages: vec![
+                stage("capture/raw", 30),
+                stage("match/gpu", 11),
+                stage("capture/adb-png", 90),
+                stage("input/controller", 5),
+            ],
+        };
+        let regressions = report.regressions(&baseline, 0.2);
+        assert_eq!(
+            regressions,
+            [Regression {
+                stage: "capture/raw".to_string(),
+                baseline_ms: 20.0,
+                p50_ms: 30.0,
+            }]
+        );
+        assert_eq!(
+            regressions[0].to_string(),
+            "capture/raw: p50 20.0ms -> 30.0ms (+50%)"
+        );
+
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(
+            serde_json::from_str::<LatencyReport>(&json).unwrap(),
+            report
+        );
+    }
+}
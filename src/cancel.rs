@@ -0,0 +1,91 @@
+//! Stop or pause a running task from another thread, i.e. the buttons of a GUI
+//!
+//! ```ignore
+//! let token = CancellationToken::new();
+//! let handle = {
+//!     let (ap, token) = (ap.clone(), token.clone());
+//!     thread::spawn(move || task.execute_cancellable(&ap, &token))
+//! };
+//! token.pause();
+//! token.resume();
+//! token.cancel();
+//! ```
+
+use std::sync::{
+    Arc, Condvar, Mutex,
+    atomic::{AtomicBool, Ordering},
+};
+
+use crate::Error;
+
+#[derive(Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    paused: Mutex<bool>,
+    changed: Condvar,
+}
+
+/// Shared between the thread running a task and the ones controlling it, the
+/// task checks it between its steps with [`CancellationToken::check`].
+///
+/// The step in progress always finishes, a task is stopped or paused before its
+/// next step.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+impl std::fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .field("paused", &self.is_paused())
+            .finish()
+    }
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop the task at its next step, even if it is paused.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        // Under the lock so a task about to wait does not miss it
+        let _paused = self.inner.paused.lock().unwrap();
+        self.inner.changed.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Hold the task before its next step until [`CancellationToken::resume`].
+    pub fn pause(&self) {
+        *self.inner.paused.lock().unwrap() = true;
+    }
+
+    pub fn resume(&self) {
+        *self.inner.paused.lock().unwrap() = false;
+        self.inner.changed.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.inner.paused.lock().unwrap()
+    }
+
+    /// Block while paused, then fail with [`Error::Cancelled`] if cancelled.
+    pub fn check(&self) -> Result<(), Error> {
+        let paused = self.inner.paused.lock().unwrap();
+        let _paused = self
+            .inner
+            .changed
+            .wait_while(paused, |paused| *paused && !self.is_cancelled())
+            .unwrap();
+        if self.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,387 @@
+//! A terminal dashboard of devices running tasks, behind the `dashboard`
+//! feature, i.e. to follow a [`Fleet`](crate::fleet::Fleet) on a headless server
+//!
+//! Every device shows the tasks it is running, its current step, its last
+//! template match and how many steps went through, with the recent errors of
+//! all of them below, from the [events](crate::event) of each [`AutoPlay`].
+//!
+//! ```ignore
+//! let mut dashboard = Dashboard::new();
+//! for name in fleet.names() {
+//!     dashboard.add(name, &fleet.get(name).unwrap());
+//! }
+//! let token = CancellationToken::new();
+//! thread::scope(|s| {
+//!     s.spawn(|| {
+//!         fleet.run_all(&task);
+//!         token.cancel();
+//!     });
+//!     dashboard.run(&token)
+//! })?;
+//! ```
+
+use std::{
+    collections::VecDeque,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use ratatui::{
+    Terminal,
+    buffer::Buffer,
+    layout::Rect,
+    prelude::CrosstermBackend,
+    style::Stylize,
+    text::{Line, Span},
+    widgets::{Paragraph, Widget},
+};
+
+use crate::{AutoPlay, CancellationToken, event::Event};
+
+/// How often the dashboard is drawn
+const REFRESH: Duration = Duration::from_millis(250);
+/// Errors kept for the bottom of the dashboard
+const MAX_ERRORS: usize = 8;
+
+/// The step a device is running
+#[derive(Debug, Clone)]
+pub struct StepStatus {
+    pub task: String,
+    pub index: usize,
+    pub action: String,
+    pub started: Instant,
+}
+
+/// The last template match of a device
+#[derive(Debug, Clone, Copy)]
+pub struct MatchStatus {
+    pub found: Option<(u32, u32)>,
+    pub score: Option<f32>,
+}
+
+/// What a device is doing, as told by its events
+#[derive(Debug, Clone, Default)]
+pub struct DeviceStatus {
+    pub screen_size: (u32, u32),
+    /// The tasks running, the outermost first
+    pub tasks: Vec<String>,
+    pub step: Option<StepStatus>,
+    pub steps_ok: usize,
+    pub steps_failed: usize,
+    pub last_match: Option<MatchStatus>,
+    /// The last task that finished and its error
+    pub last_task: Option<(String, Option<String>)>,
+}
+
+impl DeviceStatus {
+    /// Follow `event`, returning the error it reports if any.
+    pub fn apply(&mut self, event: &Event) -> Option<String> {
+        match event {
+            Event::TaskStarted { name } => self.tasks.push(name.clone()),
+            Event::StepStarted {
+                task,
+                index,
+                action,
+            } => {
+                self.step = Some(StepStatus {
+                    task: task.clone(),
+                    index: *index,
+                    action: action.clone(),
+                    started: Instant::now(),
+                });
+            }
+            Event::StepFinished {
+                task, index, error, ..
+            } => {
+                self.step = None;
+                match error {
+                    Some(error) => {
+                        self.steps_failed += 1;
+                        return Some(format!("{task} #{index}: {error}"));
+                    }
+                    None => self.steps_ok += 1,
+                }
+            }
+            Event::TaskFinished { name, error } => {
+                if let Some(pos) = self.tasks.iter().rposition(|task| task == name) {
+                    self.tasks.remove(pos);
+                }
+                self.last_task = Some((name.clone(), error.clone()));
+            }
+            Event::MatchResult { rect, score, .. } => {
+                self.last_match = Some(MatchStatus {
+                    found: rect.map(|rect| (rect.x, rect.y)),
+                    score: *score,
+                });
+            }
+            Event::ControllerSwapped { screen_size, .. }
+            | Event::ResolutionChanged { screen_size, .. } => self.screen_size = *screen_size,
+            Event::ResourceReloaded {
+                path,
+                error: Some(error),
+            } => return Some(format!("failed to reload {}: {error}", path.display())),
+            Event::Guard { action, reason } => {
+                return Some(format!("{action} by the guard: {reason}"));
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// One line describing the device, without its name
+    fn describe(&self) -> Line<'static> {
+        let (width, height) = self.screen_size;
+        let mut line = Line::from(format!("{width}x{height}  "));
+        if self.tasks.is_empty() {
+            line.push_span(match &self.last_task {
+                Some((name, None)) => format!("{name} done").green(),
+                Some((name, Some(_))) => format!("{name} failed").red(),
+                None => "idle".dim(),
+            });
+        } else {
+            line.push_span(self.tasks.join(" > ").cyan());
+        }
+        if let Some(step) = &self.step {
+            let elapsed = step.started.elapsed().as_secs_f32();
+            line.push_span(format!("  #{} {} {elapsed:.1}s", step.index, step.action));
+        }
+        line.push_span(format!("  steps {} ok", self.steps_ok));
+        if self.steps_failed > 0 {
+            line.push_span(" ");
+            line.push_span(format!("{} failed", self.steps_failed).red());
+        }
+        match self.last_match {
+            Some(MatchStatus {
+                found: Some((x, y)),
+                score,
+            }) => {
+                line.push_span(format!("  match at ({x}, {y})"));
+                if let Some(score) = score {
+                    line.push_span(format!(" {score:.3}"));
+                }
+            }
+            Some(MatchStatus { found: None, .. }) => line.push_span("  no match"),
+            None => {}
+        }
+        line
+    }
+}
+
+struct Device {
+    name: String,
+    events: mpsc::Receiver<Event>,
+    status: DeviceStatus,
+}
+
+/// The devices followed and their recent errors, see the [module](self) docs.
+#[derive(Default)]
+pub struct Dashboard {
+    devices: Vec<Device>,
+    /// `(device, error)`, the latest last
+    errors: VecDeque<(String, String)>,
+}
+
+impl Dashboard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follow the events of `ap` from now on, shown as `name`.
+    pub fn add(&mut self, name: impl Into<String>, ap: &AutoPlay) {
+        self.devices.push(Device {
+            name: name.into(),
+            events: ap.subscribe(),
+            status: DeviceStatus {
+                screen_size: ap.screen_size(),
+                ..Default::default()
+            },
+        });
+    }
+
+    pub fn status(&self, name: &str) -> Option<&DeviceStatus> {
+        self.devices
+            .iter()
+            .find(|device| device.name == name)
+            .map(|device| &device.status)
+    }
+
+    /// The recent errors as `(device, error)`, the latest last
+    pub fn errors(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.errors
+            .iter()
+            .map(|(device, error)| (device.as_str(), error.as_str()))
+    }
+
+    /// Apply the events received since the last update.
+    pub fn update(&mut self) {
+        for device in &mut self.devices {
+            for event in device.events.try_iter() {
+                if let Some(error) = device.status.apply(&event) {
+                    if self.errors.len() == MAX_ERRORS {
+                        self.errors.pop_front();
+                    }
+                    self.errors.push_back((device.name.clone(), error));
+                }
+            }
+        }
+    }
+
+    /// The lines of the dashboard
+    pub fn lines(&self) -> Vec<Line<'static>> {
+        let name_width = self
+            .devices
+            .iter()
+            .map(|device| device.name.len())
+            .max()
+            .unwrap_or(0);
+        let mut lines = vec![Line::from("devices".bold())];
+        for device in &self.devices {
+            let mut line = Line::from(vec![
+                Span::raw("  "),
+                format!("{:name_width$}", device.name).bold(),
+                Span::raw("  "),
+            ]);
+            line.spans.extend(device.status.describe().spans);
+            lines.push(line);
+        }
+        if self.devices.is_empty() {
+            lines.push(Line::from("  none".dim()));
+        }
+        lines.push(Line::default());
+        lines.push(Line::from("recent errors".bold()));
+        for (device, error) in &self.errors {
+            lines.push(Line::from(vec![
+                Span::raw("  "),
+                device.clone().red(),
+                Span::raw(format!("  {error}")),
+            ]));
+        }
+        if self.errors.is_empty() {
+            lines.push(Line::from("  none".dim()));
+        }
+        lines
+    }
+
+    /// Draw the dashboard on the terminal until `token` is cancelled, then leave
+    /// its last state on the screen.
+    pub fn run(&mut self, token: &CancellationToken) -> anyhow::Result<()> {
+        let mut terminal = Terminal::new(CrosstermBackend::new(std::io::stdout()))?;
+        terminal.clear()?;
+        let res = (|| -> anyhow::Result<()> {
+            loop {
+                let stopping = token.is_cancelled();
+                self.update();
+                terminal.draw(|frame| frame.render_widget(&*self, frame.area()))?;
+                if stopping {
+                    return Ok(());
+                }
+                std::thread::sleep(REFRESH);
+            }
+        })();
+        terminal.show_cursor()?;
+        res
+    }
+}
+
+/// The lines of the dashboard, cut to the width of `area`
+impl Widget for &Dashboard {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        Paragraph::new(self.lines()).render(area, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_status() {
+        let mut status = DeviceStatus::default();
+        let events = [
+            Event::TaskStarted {
+                name: "daily".to_string(),
+            },
+            Event::TaskStarted {
+                name: "mail".to_string(),
+            },
+            Event::StepStarted {
+                task: "mail".to_string(),
+                index: 0,
+                action: "ClickMatchTemplate".to_string(),
+            },
+            Event::MatchResult {
+                template_size: (10, 10),
+                rect: Some(image::math::Rect {
+                    x: 5,
+                    y: 6,
+                    width: 10,
+                    height: 10,
+                }),
+                score: Some(0.98),
+            },
+        ];
+        for event in &events {
+            assert_eq!(status.apply(event), None);
+        }
+        assert_eq!(status.tasks, ["daily", "mail"]);
+        assert_eq!(status.step.as_ref().unwrap().action, "ClickMatchTemplate");
+        assert_eq!(status.last_match.unwrap().found, Some((5, 6)));
+        let line = status.describe().to_string();
+        assert!(line.contains("daily > mail"), "{line}");
+        assert!(line.contains("match at (5, 6) 0.980"), "{line}");
+
+        let error = status.apply(&Event::StepFinished {
+            task: "mail".to_string(),
+            index: 0,
+            duration: Duration::from_millis(10),
+            error: Some("not found".to_string()),
+        });
+        assert_eq!(error.as_deref(), Some("mail #0: not found"));
+        status.apply(&Event::TaskFinished {
+            name: "mail".to_string(),
+            error: Some("not found".to_string()),
+        });
+        assert_eq!(status.tasks, ["daily"]);
+        assert!(status.step.is_none());
+        assert_eq!((status.steps_ok, status.steps_failed), (0, 1));
+    }
+
+    #[test]
+    fn test_render() {
+        let mut dashboard = Dashboard::new();
+        dashboard.devices.push(Device {
+            name: "emulator-5554".to_string(),
+            events: mpsc::channel().1,
+            status: DeviceStatus {
+                screen_size: (1280, 720),
+                ..Default::default()
+            },
+        });
+        let area = Rect::new(0, 0, 40, 5);
+        let mut buf = Buffer::empty(area);
+        dashboard.render(area, &mut buf);
+        let lines = (0..area.height)
+            .map(|y| {
+                let line = (0..area.width)
+                    .map(|x| buf[(x, y)].symbol())
+                    .collect::<String>();
+                line.trim_end().to_string()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            [
+                "devices",
+                "  emulator-5554  1280x720  idle  steps 0",
+                "",
+                "recent errors",
+                "  none",
+            ]
+        );
+        assert!(
+            buf[(0, 0)]
+                .modifier
+                .contains(ratatui::style::Modifier::BOLD)
+        );
+    }
+}
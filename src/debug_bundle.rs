@@ -0,0 +1,352 @@
+//! Bug-report bundles of failed steps
+//!
+//! With [`AutoPlay::set_debug_bundle`], a task failing writes a zip to
+//! [`DebugBundleOptions::dir`] holding what is needed to tell why. Only the task
+//! run by the caller writes one, not the tasks nested in it or run to check a
+//! condition, which are expected to fail:
+//!
+//! | File             | Content                                                  |
+//! |------------------|----------------------------------------------------------|
+//! | `summary.json`   | the task, the step, its error, the device profile and the recent matches |
+//! | `screen.png`     | the screen when it failed                                |
+//! | `templates/N-*`  | the files of the recent matches, `N` as in the summary   |
+//! | `log.txt`        | the recent log lines, from a [`RecentLogs`] writer       |
+//! | `logcat.txt`     | the end of the logcat, with [`AutoPlay::set_report_logcat`] |
+//!
+//! A bundle is meant to be attached to an issue, so it can be
+//! [redacted](Redaction): the serial of the device and the given strings are
+//! replaced in every text and regions of the screen blanked out.
+//!
+//! ```ignore
+//! let logs = RecentLogs::new(200);
+//! tracing_subscriber::registry()
+//!     .with(tracing_subscriber::fmt::layer())
+//!     .with(tracing_subscriber::fmt::layer().with_ansi(false).with_writer(logs.clone()))
+//!     .init();
+//! ap.set_debug_bundle(Some(DebugBundleOptions::new("bundles").with_logs(logs)));
+//! ```
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use image::{DynamicImage, math::Rect};
+use serde::Serialize;
+use serde_json::json;
+use tracing::{info, warn};
+use zip::{ZipWriter, write::SimpleFileOptions};
+
+use crate::AutoPlay;
+
+/// How many matches are kept for the next bundle, the last ones
+pub const RECENT_MATCHES: usize = 8;
+/// How much of the logcat goes in a bundle
+const BUNDLE_LOGCAT_LINES: usize = 500;
+const REDACTED: &str = "<redacted>";
+
+/// What to hide from a bundle
+#[derive(Debug, Clone, Default)]
+pub struct Redaction {
+    /// Replaced in every text besides the serial, i.e. the name of an account
+    pub strings: Vec<String>,
+    /// Blanked out on the screen, i.e. where the name of the player is drawn
+    pub regions: Vec<Rect>,
+}
+
+impl Redaction {
+    fn text(&self, text: &str, serial: Option<&str>) -> String {
+        serial
+            .into_iter()
+            .chain(self.strings.iter().map(String::as_str))
+            .filter(|s| !s.is_empty())
+            .fold(text.to_string(), |text, s| text.replace(s, REDACTED))
+    }
+
+    fn screen(&self, screen: &mut image::RgbaImage) {
+        for region in &self.regions {
+            for y in region.y..(region.y + region.height).min(screen.height()) {
+                for x in region.x..(region.x + region.width).min(screen.width()) {
+                    screen.put_pixel(x, y, image::Rgba([0, 0, 0, 255]));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DebugBundleOptions {
+    /// Where the bundles are written, created if needed
+    pub dir: PathBuf,
+    /// `None` to keep everything as is
+    pub redact: Option<Redaction>,
+    /// The lines logged before the failure
+    pub logs: Option<RecentLogs>,
+}
+
+impl DebugBundleOptions {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            redact: None,
+            logs: None,
+        }
+    }
+
+    pub fn with_redaction(mut self, redact: Option<Redaction>) -> Self {
+        self.redact = redact;
+        self
+    }
+
+    pub fn with_logs(mut self, logs: RecentLogs) -> Self {
+        self.logs = Some(logs);
+        self
+    }
+}
+
+/// The last lines written to it, as a [`MakeWriter`](tracing_subscriber::fmt::MakeWriter)
+/// of a `tracing` layer, see the [module](self) docs.
+#[derive(Debug, Clone)]
+pub struct RecentLogs {
+    lines: Arc<Mutex<VecDeque<String>>>,
+    capacity: usize,
+}
+
+impl RecentLogs {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// The lines kept, the oldest first
+    pub fn lines(&self) -> Vec<String> {
+        self.lines.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// A line being formatted by a `tracing` layer, kept once written in full
+pub struct RecentLogsWriter {
+    logs: RecentLogs,
+    line: Vec<u8>,
+}
+
+impl Write for RecentLogsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.line.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for RecentLogsWriter {
+    fn drop(&mut self) {
+        let line = String::from_utf8_lossy(&self.line).trim_end().to_string();
+        if line.is_empty() || self.logs.capacity == 0 {
+            return;
+        }
+        let mut lines = self.logs.lines.lock().unwrap();
+        if lines.len() == self.logs.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+}
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for RecentLogs {
+    type Writer = RecentLogsWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RecentLogsWriter {
+            logs: self.clone(),
+            line: Vec::new(),
+        }
+    }
+}
+
+/// A template matched while a debug bundle is set
+#[derive(Debug, Clone)]
+pub struct RecentMatch {
+    /// The file of a template of [`AutoPlay::templates`], `None` for an image
+    /// passed as is
+    pub template: Option<PathBuf>,
+    pub template_size: (u32, u32),
+    /// Where it was found, in screen coordinates
+    pub rect: Option<Rect>,
+    pub score: Option<f32>,
+}
+
+/// The step of the task run by the caller that failed
+pub(crate) struct Failure<'a> {
+    pub task: &'a str,
+    pub index: usize,
+    pub action: &'a str,
+    pub error: &'a anyhow::Error,
+}
+
+/// Write the bundle of `failure` if `ap` has a debug bundle set. Failing to
+/// write it is only logged.
+pub(crate) fn write_on_failure(ap: &AutoPlay, failure: &Failure) {
+    let Some(options) = ap.debug_bundle() else {
+        return;
+    };
+    match write(ap, &options, failure) {
+        Ok(path) => info!("wrote a debug bundle to {}", path.display()),
+        Err(err) => warn!("failed to write a debug bundle: {err:#}"),
+    }
+}
+
+/// Write the bundle of `failure` in `options.dir`, see the [module](self) docs.
+pub(crate) fn write(
+    ap: &AutoPlay,
+    options: &DebugBundleOptions,
+    failure: &Failure,
+) -> anyhow::Result<PathBuf> {
+    let profile = ap.device_profile();
+    let serial = profile.as_ref().map(|profile| profile.serial.clone());
+    let redact = |text: &str| match &options.redact {
+        Some(redaction) => redaction.text(text, serial.as_deref()),
+        None => text.to_string(),
+    };
+
+    std::fs::create_dir_all(&options.dir)
+        .with_context(|| format!("failed to create {}", options.dir.display()))?;
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let name: String = failure
+        .task
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let path = options.dir.join(format!("{name}-{millis}.zip"));
+    let mut zip = ZipWriter::new(
+        File::create(&path).with_context(|| format!("failed to create {}", path.display()))?,
+    );
+    let file_options = SimpleFileOptions::default();
+
+    let screen = match ap.screencap() {
+        Ok(screen) => {
+            let mut screen = screen.into_rgba8();
+            if let Some(redaction) = &options.redact {
+                redaction.screen(&mut screen);
+            }
+            zip.start_file("screen.png", file_options)?;
+            zip.write_all(&encode_png(&DynamicImage::ImageRgba8(screen))?)?;
+            None
+        }
+        Err(err) => Some(format!("{err:#}")),
+    };
+
+    let recent_matches = ap.recent_matches();
+    let mut matches = Vec::new();
+    for (i, recent) in recent_matches.iter().enumerate() {
+        // Read again from the file, the matches do not keep the images
+        let file = recent.template.as_ref().and_then(|path| {
+            let name = path.file_name()?.to_string_lossy();
+            let content = std::fs::read(path)
+                .inspect_err(|err| warn!("failed to read {}: {err}", path.display()))
+                .ok()?;
+            Some((format!("templates/{i}-{name}"), content))
+        });
+        if let Some((name, content)) = &file {
+            zip.start_file(name.as_str(), file_options)?;
+            zip.write_all(content)?;
+        }
+        matches.push(json!({
+            "template": recent.template.as_ref().map(|path| redact(&path.display().to_string())),
+            "file": file.map(|(name, _)| name),
+            "template_size": [recent.template_size.0, recent.template_size.1],
+            "rect": recent.rect.map(|r| [r.x, r.y, r.width, r.height]),
+            "score": recent.score,
+        }));
+    }
+
+    if let Some(logs) = &options.logs {
+        zip.start_file("log.txt", file_options)?;
+        for line in logs.lines() {
+            writeln!(zip, "{}", redact(&line))?;
+        }
+    }
+    if let Some(filter) = ap.report_logcat() {
+        match ap.logcat_dump(&filter, BUNDLE_LOGCAT_LINES) {
+            Ok(entries) => {
+                zip.start_file("logcat.txt", file_options)?;
+                for entry in entries {
+                    writeln!(zip, "{}", redact(&entry.to_string()))?;
+                }
+            }
+            Err(err) => warn!("failed to dump logcat: {err:#}"),
+        }
+    }
+
+    let mut profile = profile.map(serde_json::to_value).transpose()?;
+    if options.redact.is_some()
+        && let Some(serial) = profile
+            .as_mut()
+            .and_then(|profile| profile.get_mut("serial"))
+    {
+        *serial = json!(REDACTED);
+    }
+    let summary = Summary {
+        task: failure.task,
+        index: failure.index,
+        action: failure.action,
+        error: redact(&format!("{:#}", failure.error)),
+        screen_size: ap.screen_size(),
+        screen_error: screen,
+        profile,
+        matches,
+        created_at_ms: millis as u64,
+    };
+    zip.start_file("summary.json", file_options)?;
+    zip.write_all(serde_json::to_string_pretty(&summary)?.as_bytes())?;
+    zip.finish()?;
+    Ok(path)
+}
+
+#[derive(Serialize)]
+struct Summary<'a> {
+    task: &'a str,
+    index: usize,
+    action: &'a str,
+    error: String,
+    screen_size: (u32, u32),
+    /// Why there is no `screen.png`
+    screen_error: Option<String>,
+    profile: Option<serde_json::Value>,
+    matches: Vec<serde_json::Value>,
+    created_at_ms: u64,
+}
+
+fn encode_png(image: &DynamicImage) -> anyhow::Result<Vec<u8>> {
+    let mut png = Vec::new();
+    image.write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png)?;
+    Ok(png)
+}
+
+/// The files of the bundle at `path`, for the tests
+#[cfg(test)]
+pub(crate) fn read(path: &std::path::Path) -> std::collections::BTreeMap<String, Vec<u8>> {
+    use std::io::Read;
+
+    let mut zip = zip::ZipArchive::new(File::open(path).unwrap()).unwrap();
+    (0..zip.len())
+        .map(|i| {
+            let mut file = zip.by_index(i).unwrap();
+            let mut content = Vec::new();
+            file.read_to_end(&mut content).unwrap();
+            (file.name().to_string(), content)
+        })
+        .collect()
+}
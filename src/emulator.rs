@@ -0,0 +1,460 @@
+//! Starting and stopping Android emulators, to run tasks without launching them
+//! by hand
+//!
+//! An [`Emulator`] drives the command line tool an emulator is installed with:
+//! `MuMuManager.exe` for MuMu 12, `ldconsole.exe` for LDPlayer and
+//! `HD-Player.exe` with `bluestacks.conf` for BlueStacks 5. Its instances are
+//! numbered as in the multi-instance manager of the emulator.
+//!
+//! ```ignore
+//! let emulator = Emulator::detect(EmulatorKind::MuMu).context("MuMu is not installed")?;
+//! let serial = emulator.start(1, BOOT_TIMEOUT)?;
+//! let ap = AutoPlay::new(AndroidController::connect(&serial)?);
+//! // or in one go
+//! let ap = AutoPlay::connect_emulator(EmulatorKind::MuMu, 1)?;
+//! ```
+//!
+//! Emulators are looked for where they install by default, or in the directory
+//! of `AP_MUMU_DIR`, `AP_LDPLAYER_DIR` or `AP_BLUESTACKS_DIR` if set.
+
+use std::{
+    fmt,
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use tracing::{debug, info};
+
+use crate::Error;
+
+/// How long [`Emulator::start`] waits for Android to boot by default
+pub const BOOT_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Between two checks while waiting for an instance
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EmulatorKind {
+    MuMu,
+    LDPlayer,
+    BlueStacks,
+}
+
+impl EmulatorKind {
+    pub const ALL: [EmulatorKind; 3] = [
+        EmulatorKind::MuMu,
+        EmulatorKind::LDPlayer,
+        EmulatorKind::BlueStacks,
+    ];
+
+    /// The variable overriding where it is installed
+    fn env_var(self) -> &'static str {
+        match self {
+            EmulatorKind::MuMu => "AP_MUMU_DIR",
+            EmulatorKind::LDPlayer => "AP_LDPLAYER_DIR",
+            EmulatorKind::BlueStacks => "AP_BLUESTACKS_DIR",
+        }
+    }
+
+    /// Where it installs by default
+    fn default_dirs(self) -> &'static [&'static str] {
+        match self {
+            EmulatorKind::MuMu => &[
+                r"C:\Program Files\Netease\MuMuPlayer-12.0",
+                r"D:\Program Files\Netease\MuMuPlayer-12.0",
+            ],
+            EmulatorKind::LDPlayer => &[r"C:\LDPlayer\LDPlayer9", r"D:\LDPlayer\LDPlayer9"],
+            EmulatorKind::BlueStacks => &[r"C:\Program Files\BlueStacks_nxt"],
+        }
+    }
+
+    /// The command line tool, relative to where it is installed
+    fn consoles(self) -> &'static [&'static str] {
+        match self {
+            EmulatorKind::MuMu => &["shell/MuMuManager.exe", "nx_main/MuMuManager.exe"],
+            EmulatorKind::LDPlayer => &["ldconsole.exe"],
+            EmulatorKind::BlueStacks => &["HD-Player.exe"],
+        }
+    }
+}
+
+impl fmt::Display for EmulatorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EmulatorKind::MuMu => "mumu",
+            EmulatorKind::LDPlayer => "ldplayer",
+            EmulatorKind::BlueStacks => "bluestacks",
+        })
+    }
+}
+
+impl FromStr for EmulatorKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EmulatorKind::ALL
+            .into_iter()
+            .find(|kind| kind.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                anyhow::anyhow!("unknown emulator {s:?}, expected mumu, ldplayer or bluestacks")
+            })
+    }
+}
+
+/// An instance of an emulator
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instance {
+    pub index: usize,
+    pub name: String,
+    pub running: bool,
+    /// On `127.0.0.1`, `None` until it is known, i.e. MuMu before it started
+    pub adb_port: Option<u16>,
+}
+
+impl Instance {
+    pub fn serial(&self) -> Option<String> {
+        self.adb_port.map(|port| format!("127.0.0.1:{port}"))
+    }
+}
+
+/// An installed emulator, see the [module](self) docs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Emulator {
+    pub kind: EmulatorKind,
+    pub dir: PathBuf,
+}
+
+impl Emulator {
+    /// The emulator installed in `dir`, not checked.
+    pub fn new(kind: EmulatorKind, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            kind,
+            dir: dir.into(),
+        }
+    }
+
+    /// The emulator of `kind` if it is installed, see the [module](self) docs.
+    pub fn detect(kind: EmulatorKind) -> Option<Self> {
+        let dirs = std::env::var_os(kind.env_var())
+            .map(PathBuf::from)
+            .into_iter()
+            .chain(kind.default_dirs().iter().map(PathBuf::from));
+        dirs.map(|dir| Self::new(kind, dir))
+            .find(|emulator| emulator.console().is_some())
+    }
+
+    /// Every emulator installed
+    pub fn detect_all() -> Vec<Self> {
+        EmulatorKind::ALL
+            .into_iter()
+            .filter_map(Self::detect)
+            .collect()
+    }
+
+    fn console(&self) -> Option<PathBuf> {
+        self.kind
+            .consoles()
+            .iter()
+            .map(|console| self.dir.join(console))
+            .find(|console| console.is_file())
+    }
+
+    /// Run the command line tool and return its output.
+    fn run<I, S>(&self, args: I) -> anyhow::Result<String>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<std::ffi::OsStr>,
+    {
+        let console = self
+            .console()
+            .with_context(|| format!("{} is not installed in {}", self.kind, self.dir.display()))?;
+        let mut command = Command::new(&console);
+        command.args(args);
+        debug!("running {command:?}");
+        let output = command
+            .output()
+            .with_context(|| format!("failed to run {}", console.display()))?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "{command:?} failed with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Where BlueStacks keeps its instances
+    fn bluestacks_conf(&self) -> PathBuf {
+        let conf = self.dir.join("bluestacks.conf");
+        if conf.is_file() {
+            return conf;
+        }
+        let data = std::env::var_os("ProgramData").unwrap_or_else(|| r"C:\ProgramData".into());
+        Path::new(&data)
+            .join("BlueStacks_nxt")
+            .join("bluestacks.conf")
+    }
+
+    pub fn instances(&self) -> anyhow::Result<Vec<Instance>> {
+        match self.kind {
+            EmulatorKind::MuMu => parse_mumu_info(&self.run(["info", "-v", "all"])?),
+            EmulatorKind::LDPlayer => Ok(parse_ldplayer_list(&self.run(["list2"])?)),
+            EmulatorKind::BlueStacks => {
+                let path = self.bluestacks_conf();
+                let conf = std::fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                let mut instances = parse_bluestacks_conf(&conf);
+                // The configuration does not tell, an instance listens for adb when it runs
+                for instance in &mut instances {
+                    instance.running = instance.adb_port.is_some_and(is_listening);
+                }
+                Ok(instances)
+            }
+        }
+    }
+
+    pub fn instance(&self, index: usize) -> anyhow::Result<Instance> {
+        self.instances()?
+            .into_iter()
+            .find(|instance| instance.index == index)
+            .with_context(|| format!("{} has no instance {index}", self.kind))
+    }
+
+    /// Launch the instance `index`, without waiting for it to boot.
+    pub fn launch(&self, index: usize) -> anyhow::Result<()> {
+        info!("launching {} instance {index}", self.kind);
+        let index_arg = index.to_string();
+        match self.kind {
+            EmulatorKind::MuMu => self.run(["control", "-v", &index_arg, "launch"])?,
+            EmulatorKind::LDPlayer => self.run(["launch", "--index", &index_arg])?,
+            EmulatorKind::BlueStacks => {
+                let instance = self.instance(index)?;
+                // The player runs as long as the instance, it is left running
+                let console = self.console().context("BlueStacks is not installed")?;
+                Command::new(&console)
+                    .args(["--instance", &instance.name])
+                    .spawn()
+                    .with_context(|| format!("failed to run {}", console.display()))?;
+                String::new()
+            }
+        };
+        Ok(())
+    }
+
+    pub fn stop(&self, index: usize) -> anyhow::Result<()> {
+        info!("stopping {} instance {index}", self.kind);
+        let index_arg = index.to_string();
+        match self.kind {
+            EmulatorKind::MuMu => self.run(["control", "-v", &index_arg, "shutdown"])?,
+            EmulatorKind::LDPlayer => self.run(["quit", "--index", &index_arg])?,
+            EmulatorKind::BlueStacks => {
+                // BlueStacks has no command for it, Android is powered off instead
+                let serial = self
+                    .instance(index)?
+                    .serial()
+                    .context("the instance has no adb port")?;
+                ap_adb::connect(&serial)?.shell("reboot -p")?;
+                String::new()
+            }
+        };
+        Ok(())
+    }
+
+    /// Launch the instance `index` unless it is running, and wait for Android to
+    /// boot, failing with [`Error::Timeout`] after `timeout`. Returns the serial
+    /// to connect to.
+    pub fn start(&self, index: usize, timeout: Duration) -> anyhow::Result<String> {
+        let deadline = Instant::now() + timeout;
+        if !self.instance(index)?.running {
+            self.launch(index)?;
+        }
+        // MuMu only tells the port once the instance started
+        let serial = loop {
+            if let Some(serial) = self.instance(index)?.serial() {
+                break serial;
+            }
+            if Instant::now() >= deadline {
+                return Err(Error::Timeout(timeout).into());
+            }
+            thread::sleep(POLL_INTERVAL);
+        };
+        wait_for_boot(&serial, deadline.saturating_duration_since(Instant::now()))?;
+        Ok(serial)
+    }
+}
+
+/// Wait until `serial` is connected and `sys.boot_completed` is set, failing
+/// with [`Error::Timeout`] after `timeout`.
+pub fn wait_for_boot(serial: &str, timeout: Duration) -> anyhow::Result<()> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let booted = ap_adb::connect(serial)
+            .and_then(|device| device.property("sys.boot_completed"))
+            .map(|value| value.as_deref() == Some("1"));
+        match booted {
+            Ok(true) => {
+                info!("{serial} booted");
+                return Ok(());
+            }
+            Ok(false) => debug!("{serial} is booting"),
+            Err(err) => debug!("{serial} is not connected yet: {err}"),
+        }
+        if Instant::now() >= deadline {
+            return Err(Error::Timeout(timeout).into());
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn is_listening(port: u16) -> bool {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    TcpStream::connect_timeout(&addr, Duration::from_millis(200)).is_ok()
+}
+
+/// The output of `MuMuManager.exe info -v all`, an object for a single instance
+/// and an object of them by index otherwise.
+fn parse_mumu_info(output: &str) -> anyhow::Result<Vec<Instance>> {
+    let info: serde_json::Value =
+        serde_json::from_str(output).context("invalid output of MuMuManager")?;
+    let infos = match info.get("index") {
+        Some(_) => vec![&info],
+        None => info
+            .as_object()
+            .map(|infos| infos.values().collect())
+            .unwrap_or_default(),
+    };
+    let mut instances = infos
+        .into_iter()
+        .filter_map(|info| {
+            let index = match &info["index"] {
+                serde_json::Value::String(index) => index.parse().ok()?,
+                index => index.as_u64()? as usize,
+            };
+            Some(Instance {
+                index,
+                name: info["name"].as_str().unwrap_or_default().to_string(),
+                running: info["is_android_started"].as_bool().unwrap_or(false),
+                adb_port: info["adb_port"].as_u64().map(|port| port as u16),
+            })
+        })
+        .collect::<Vec<_>>();
+    instances.sort_by_key(|instance| instance.index);
+    Ok(instances)
+}
+
+/// The output of `ldconsole.exe list2`, a line per instance as
+/// `index,title,top window,bind window,android started,pid,vbox pid,...`
+fn parse_ldplayer_list(output: &str) -> Vec<Instance> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields = line.trim().split(',').collect::<Vec<_>>();
+            let index = fields.first()?.parse::<usize>().ok()?;
+            Some(Instance {
+                index,
+                name: fields.get(1)?.to_string(),
+                running: fields.get(4) == Some(&"1"),
+                // The ports of the instances follow each other, two by two
+                adb_port: Some(5555 + 2 * index as u16),
+            })
+        })
+        .collect()
+}
+
+/// The instances of `bluestacks.conf`, of lines `bst.instance.<name>.<key>="<value>"`.
+/// The first instance is `Nougat64` (or `Pie64`...), the next ones are numbered
+/// as `Nougat64_1`.
+fn parse_bluestacks_conf(conf: &str) -> Vec<Instance> {
+    let mut instances = Vec::<(String, Option<u16>)>::new();
+    for line in conf.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let Some(key) = key.strip_prefix("bst.instance.") else {
+            continue;
+        };
+        let Some((name, key)) = key.split_once('.') else {
+            continue;
+        };
+        let pos = match instances.iter().position(|(n, _)| n == name) {
+            Some(pos) => pos,
+            None => {
+                instances.push((name.to_string(), None));
+                instances.len() - 1
+            }
+        };
+        if key == "status.adb_port" {
+            instances[pos].1 = value.trim_matches('"').parse().ok();
+        }
+    }
+    let number = |name: &str| {
+        name.rsplit_once('_')
+            .and_then(|(_, n)| n.parse::<usize>().ok())
+            .unwrap_or(0)
+    };
+    instances.sort_by_key(|(name, _)| (number(name), name.clone()));
+    instances
+        .into_iter()
+        .enumerate()
+        .map(|(index, (name, adb_port))| Instance {
+            index,
+            name,
+            running: false,
+            adb_port,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_instances() {
+        let single =
+            r#"{"index":"0","name":"MuMu","is_android_started":false,"is_process_started":false}"#;
+        let instances = parse_mumu_info(single).unwrap();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].serial(), None);
+
+        let all = r#"{
+            "1": {"index":"1","name":"farm","is_android_started":true,"adb_host_ip":"127.0.0.1","adb_port":16416},
+            "0": {"index":"0","name":"MuMu","is_android_started":false}
+        }"#;
+        let instances = parse_mumu_info(all).unwrap();
+        assert_eq!(instances[1].index, 1);
+        assert!(instances[1].running);
+        assert_eq!(instances[1].serial().as_deref(), Some("127.0.0.1:16416"));
+
+        let list = "0,LDPlayer,0,0,0,-1,-1,1280,720,240\r\n1,LDPlayer-1,657602,1115452,1,7608,9024,1280,720,240\r\n";
+        let instances = parse_ldplayer_list(list);
+        assert_eq!(instances.len(), 2);
+        assert!(!instances[0].running);
+        assert_eq!(instances[1].name, "LDPlayer-1");
+        assert!(instances[1].running);
+        assert_eq!(instances[1].adb_port, Some(5557));
+
+        let conf = r#"
+bst.feature.rooting="0"
+bst.instance.Nougat64_1.display_name="BlueStacks App Player 1"
+bst.instance.Nougat64_1.status.adb_port="5565"
+bst.instance.Nougat64.display_name="BlueStacks App Player"
+bst.instance.Nougat64.status.adb_port="5555"
+"#;
+        let instances = parse_bluestacks_conf(conf);
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].name, "Nougat64");
+        assert_eq!(instances[0].adb_port, Some(5555));
+        assert_eq!(instances[1].index, 1);
+        assert_eq!(instances[1].adb_port, Some(5565));
+
+        assert_eq!("MuMu".parse::<EmulatorKind>().unwrap(), EmulatorKind::MuMu);
+        assert!("nox".parse::<EmulatorKind>().is_err());
+    }
+}
@@ -0,0 +1,29 @@
+use std::time::Duration;
+
+use thiserror::Error;
+
+/// Errors with a meaning callers may want to handle, everything else is a plain
+/// [`anyhow::Error`]. Look for them with [`anyhow::Error::downcast_ref`].
+#[derive(Error, Debug)]
+pub enum Error {
+    /// The controller failed to capture the screen
+    #[error("failed to capture the screen")]
+    Capture(#[source] anyhow::Error),
+
+    /// The template is not on the screen
+    #[error("template not found on the screen")]
+    TemplateNotFound,
+
+    /// The controller can not do something, i.e. launch an app on a desktop
+    /// window, see [`ap_controller::capability`]
+    #[error("the controller can not {0}")]
+    Unsupported(&'static str),
+
+    /// Waiting for something gave up
+    #[error("timed out after {0:?}")]
+    Timeout(Duration),
+
+    /// The task was cancelled, see [`CancellationToken`](crate::cancel::CancellationToken)
+    #[error("cancelled")]
+    Cancelled,
+}
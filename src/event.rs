@@ -0,0 +1,217 @@
+//! Events emitted by [`AutoPlay`](crate::AutoPlay) while it is driving a device.
+//!
+//! Anything that wants to follow a run, like the [recorder](crate::recorder),
+//! gets its own channel from [`AutoPlay::subscribe`](crate::AutoPlay::subscribe).
+//!
+//! Events serialize with their kind in `"type"`, i.e. `{"type": "click", "x": 1,
+//! "y": 2}`, the durations in milliseconds and the rectangles as `[x, y, width,
+//! height]`. The frames do not, see [`Event::to_json`].
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex, mpsc},
+    time::Duration,
+};
+
+use image::{DynamicImage, math::Rect};
+use serde::Serialize;
+
+use crate::{Match, controller::Key, guard::GuardAction, report::Annotation};
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Click {
+        x: u32,
+        y: u32,
+    },
+    Swipe {
+        start: (u32, u32),
+        end: (i32, i32),
+        #[serde(rename = "duration_ms", serialize_with = "ser::millis")]
+        duration: Duration,
+    },
+    Press {
+        #[serde(serialize_with = "ser::debug")]
+        key: Key,
+    },
+    TaskStarted {
+        name: String,
+    },
+    /// The `index`th step of task `task` is about to run, `action` is its type name
+    StepStarted {
+        task: String,
+        index: usize,
+        action: String,
+    },
+    /// The `index`th step of task `task` is over, after `duration`
+    StepFinished {
+        task: String,
+        index: usize,
+        #[serde(rename = "duration_ms", serialize_with = "ser::millis")]
+        duration: Duration,
+        /// The error it failed with, if any
+        error: Option<String>,
+    },
+    TaskFinished {
+        name: String,
+        /// The error it failed with, if any
+        error: Option<String>,
+    },
+    /// The controller was replaced, see [`AutoPlay::swap_controller`](crate::AutoPlay::swap_controller)
+    ControllerSwapped {
+        /// Resolution of the new controller
+        screen_size: (u32, u32),
+        scale_factor: f32,
+    },
+    /// The resolution of the controller changed, i.e. the window was resized,
+    /// see [`ControllerTrait::resolution_changed`](ap_controller::ControllerTrait::resolution_changed)
+    ResolutionChanged {
+        screen_size: (u32, u32),
+        scale_factor: f32,
+    },
+    /// A [`Repeat`](crate::action::Repeat) step is over after `iterations`,
+    /// `done` if its condition was met rather than its cap reached. A
+    /// [`ScrollFind`](crate::action::ScrollFind) step reports its scrolls the same way.
+    RepeatFinished {
+        iterations: usize,
+        done: bool,
+    },
+    /// A template of `template_size` was matched on the screen, `rect` is where
+    /// it was found, in screen coordinates
+    #[serde(rename = "match")]
+    MatchResult {
+        template_size: (u32, u32),
+        #[serde(serialize_with = "ser::rect")]
+        rect: Option<Rect>,
+        /// The [`Match::value`](crate::Match::value) of what was found, `None`
+        /// for a match of features
+        score: Option<f32>,
+    },
+    /// The part of the screen at `offset` a template was matched on, with the
+    /// same result as the [`Event::MatchResult`] following it. Only emitted while
+    /// a [session](crate::session) is recording.
+    #[serde(skip)]
+    MatchFrame {
+        frame: Arc<DynamicImage>,
+        offset: (u32, u32),
+        template_size: (u32, u32),
+        rect: Option<Rect>,
+    },
+    /// A template of `template_size` was not found, `frame` is the part of the
+    /// screen at `offset` it was matched on with the best `candidate` and its
+    /// score drawn on it, see [`AutoPlay::match_overlay`](crate::AutoPlay::match_overlay).
+    /// Only emitted while someone subscribes.
+    MatchOverlay {
+        #[serde(skip)]
+        frame: Arc<DynamicImage>,
+        offset: (u32, u32),
+        template_size: (u32, u32),
+        /// As `{"rect", "score", "scale"}`
+        #[serde(serialize_with = "ser::candidate")]
+        candidate: Option<Match>,
+    },
+    /// The screen after a step of [`Task::execute_with_report`](crate::task::Task::execute_with_report)
+    /// with what the step did on it, the same as in the report
+    #[serde(skip)]
+    AnnotatedFrame {
+        task: String,
+        index: usize,
+        frame: Arc<DynamicImage>,
+        annotations: Vec<Annotation>,
+    },
+    /// A watched file changed and was loaded again, see
+    /// [`Resource::watch`](crate::resource::Resource::watch)
+    ResourceReloaded {
+        path: PathBuf,
+        /// The error it failed with, the previous version is kept then
+        error: Option<String>,
+    },
+    /// The [battery guard](crate::guard) paused, resumed or aborted the task,
+    /// `reason` says about what, i.e. `battery at 12%, below 15%`
+    Guard {
+        action: GuardAction,
+        reason: String,
+    },
+}
+
+impl Event {
+    /// The event as JSON, see the [module](self) docs, `None` for the frames.
+    pub fn to_json(&self) -> Option<serde_json::Value> {
+        match self {
+            Event::MatchFrame { .. } | Event::AnnotatedFrame { .. } => None,
+            event => Some(serde_json::to_value(event).expect("events serialize to JSON")),
+        }
+    }
+}
+
+/// How the fields of an [`Event`] without a `Serialize` of their own serialize
+mod ser {
+    use std::{fmt::Debug, time::Duration};
+
+    use image::math::Rect;
+    use serde::{Serialize, Serializer, ser::SerializeMap};
+
+    use crate::Match;
+
+    pub fn millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub fn debug<T: Debug, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{value:?}"))
+    }
+
+    fn rect_array(rect: Rect) -> [u32; 4] {
+        [rect.x, rect.y, rect.width, rect.height]
+    }
+
+    pub fn rect<S: Serializer>(rect: &Option<Rect>, serializer: S) -> Result<S::Ok, S::Error> {
+        rect.map(rect_array).serialize(serializer)
+    }
+
+    pub fn candidate<S: Serializer>(
+        candidate: &Option<Match>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let Some(candidate) = candidate else {
+            return serializer.serialize_none();
+        };
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("rect", &rect_array(candidate.rect))?;
+        map.serialize_entry("score", &candidate.value)?;
+        map.serialize_entry("scale", &candidate.scale)?;
+        map.end()
+    }
+}
+
+#[derive(Default)]
+pub struct EventBus {
+    subscribers: Mutex<Vec<mpsc::Sender<Event>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn subscribe(&self) -> mpsc::Receiver<Event> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Whether there may be someone to send an event to, to skip building the
+    /// costly ones otherwise. The ones that hung up are only dropped on [`EventBus::emit`].
+    pub fn has_subscribers(&self) -> bool {
+        !self.subscribers.lock().unwrap().is_empty()
+    }
+
+    /// Send `event` to every subscriber, dropping the ones that hung up.
+    pub fn emit(&self, event: Event) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(event.clone()).is_ok());
+    }
+}
@@ -0,0 +1,282 @@
+//! Run the same tasks on several devices at once, i.e. the dailies of a few
+//! emulator instances
+//!
+//! ```ignore
+//! let mut fleet = Fleet::new().with_match_concurrency(2);
+//! for serial in ["emulator-5554", "emulator-5556", "emulator-5558"] {
+//!     fleet.connect(serial)?;
+//! }
+//! let report = fleet.run_all(&Task::load("daily.toml")?);
+//! for (device, report) in report.failed() {
+//!     println!("{device}: {}", report.error.as_deref().unwrap_or_default());
+//! }
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Condvar, Mutex},
+    thread,
+};
+
+use crate::{
+    AndroidController, AutoPlay, CancellationToken,
+    report::{ExecutionReport, TaskStatus},
+    task::Task,
+};
+
+/// Bounds the template matches running at once on the GPU, shared by the
+/// [`AutoPlay`]s of a [`Fleet`], see [`AutoPlay::set_match_limiter`].
+pub struct MatchLimiter {
+    max: usize,
+    running: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl MatchLimiter {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max: max.max(1),
+            running: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Wait for a match to finish if `max` are running, the returned permit lets
+    /// the next one start when dropped.
+    pub fn acquire(&self) -> MatchPermit<'_> {
+        let mut running = self.running.lock().unwrap();
+        while *running >= self.max {
+            running = self.freed.wait(running).unwrap();
+        }
+        *running += 1;
+        MatchPermit(self)
+    }
+}
+
+/// A match running, see [`MatchLimiter::acquire`]
+pub struct MatchPermit<'a>(&'a MatchLimiter);
+
+impl Drop for MatchPermit<'_> {
+    fn drop(&mut self) {
+        *self.0.running.lock().unwrap() -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+/// The [`AutoPlay`] of each device, by name
+#[derive(Default)]
+pub struct Fleet {
+    devices: BTreeMap<String, Arc<AutoPlay>>,
+    limiter: Option<Arc<MatchLimiter>>,
+}
+
+impl Fleet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run at most `max` template matches at once across the devices, so they
+    /// do not all wait on the GPU together.
+    pub fn with_match_concurrency(mut self, max: usize) -> Self {
+        let limiter = Arc::new(MatchLimiter::new(max));
+        for ap in self.devices.values() {
+            ap.set_match_limiter(Some(limiter.clone()));
+        }
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Add a device named `name`, failing if there is one already.
+    pub fn add(&mut self, name: impl Into<String>, ap: AutoPlay) -> anyhow::Result<Arc<AutoPlay>> {
+        let name = name.into();
+        if self.devices.contains_key(&name) {
+            anyhow::bail!("device {name} is already in the fleet");
+        }
+        ap.set_match_limiter(self.limiter.clone());
+        let ap = Arc::new(ap);
+        self.devices.insert(name, ap.clone());
+        Ok(ap)
+    }
+
+    /// Connect to the Android device `serial` and add it under that name.
+    pub fn connect(&mut self, serial: &str) -> anyhow::Result<Arc<AutoPlay>> {
+        self.add(serial, AutoPlay::new(AndroidController::connect(serial)?))
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Arc<AutoPlay>> {
+        let ap = self.devices.remove(name)?;
+        ap.set_match_limiter(None);
+        Some(ap)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<AutoPlay>> {
+        self.devices.get(name).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.devices.keys().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// Run `task` on every device at once, see [`Fleet::run_on`].
+    pub fn run_all(&self, task: &Task) -> FleetReport {
+        let names = self.names().collect::<Vec<_>>();
+        self.run_on(&names, task, &CancellationToken::new())
+            .expect("every device is in the fleet")
+    }
+
+    /// Run `task` on the devices `names` at once, each on its own thread, until
+    /// they are all done or `token` is cancelled. A device failing does not stop
+    /// the others.
+    pub fn run_on(
+        &self,
+        names: &[&str],
+        task: &Task,
+        token: &CancellationToken,
+    ) -> anyhow::Result<FleetReport> {
+        let devices = names
+            .iter()
+            .map(|name| {
+                self.devices
+                    .get(*name)
+                    .map(|ap| (*name, ap))
+                    .ok_or_else(|| anyhow::anyhow!("unknown device {name}"))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let reports = thread::scope(|s| {
+            let handles = devices
+                .into_iter()
+                .map(|(name, ap)| {
+                    let handle =
+                        s.spawn(move || task.execute_with_report_cancellable(ap, false, token));
+                    (name, handle)
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|(name, handle)| {
+                    let report = handle.join().unwrap_or_else(|_| {
+                        let mut report = ExecutionReport::new(&task.name);
+                        report.error = Some("panicked".to_string());
+                        report
+                    });
+                    (name.to_string(), report)
+                })
+                .collect()
+        });
+        Ok(FleetReport { reports })
+    }
+}
+
+/// The [`ExecutionReport`] of each device a task ran on
+#[derive(Debug)]
+pub struct FleetReport {
+    pub reports: BTreeMap<String, ExecutionReport>,
+}
+
+impl FleetReport {
+    pub fn is_success(&self) -> bool {
+        self.reports.values().all(ExecutionReport::is_success)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ExecutionReport> {
+        self.reports.get(name)
+    }
+
+    /// The devices the task failed or was cancelled on
+    pub fn failed(&self) -> impl Iterator<Item = (&str, &ExecutionReport)> {
+        self.reports
+            .iter()
+            .filter(|(_, report)| !report.is_success())
+            .map(|(name, report)| (name.as_str(), report))
+    }
+
+    /// How many devices ended with each status
+    pub fn count(&self, status: TaskStatus) -> usize {
+        self.reports
+            .values()
+            .filter(|report| report.status() == status)
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::testing::DummyController;
+
+    #[test]
+    fn test_fleet() {
+        let mut fleet = Fleet::new().with_match_concurrency(1);
+        for name in ["a", "b", "c"] {
+            fleet
+                .add(name, AutoPlay::new(DummyController::new(1920, 1080)))
+                .unwrap();
+        }
+        assert!(
+            fleet
+                .add("a", AutoPlay::new(DummyController::new(1920, 1080)))
+                .is_err()
+        );
+
+        let task =
+            Task::from_toml("name = \"daily\"\n[[steps]]\nClick = { x = 1, y = 2 }").unwrap();
+        let report = fleet.run_all(&task);
+        assert!(report.is_success());
+        assert_eq!(report.count(TaskStatus::Succeeded), 3);
+        for name in ["a", "b", "c"] {
+            let clicks = fleet
+                .get(name)
+                .unwrap()
+                .with_controller(DummyController::clicks)
+                .unwrap();
+            assert_eq!(clicks, [(1, 2)]);
+        }
+
+        let task =
+            Task::from_toml("name = \"bad\"\n[[steps]]\nClick = { x = 5000, y = 2 }").unwrap();
+        let report = fleet
+            .run_on(&["a", "c"], &task, &CancellationToken::new())
+            .unwrap();
+        assert_eq!(
+            report.failed().map(|(name, _)| name).collect::<Vec<_>>(),
+            ["a", "c"]
+        );
+        assert!(report.get("b").is_none());
+        assert!(
+            fleet
+                .run_on(&["d"], &task, &CancellationToken::new())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_match_limiter() {
+        let limiter = MatchLimiter::new(2);
+        let (running, max) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        thread::scope(|s| {
+            for _ in 0..6 {
+                s.spawn(|| {
+                    let _permit = limiter.acquire();
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    max.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(10));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert_eq!(max.load(Ordering::SeqCst), 2);
+    }
+}
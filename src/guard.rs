@@ -0,0 +1,363 @@
+//! Battery and temperature guardrails for long runs
+//!
+//! A [`BatteryGuard`] polls `dumpsys battery` on a thread of its own while a
+//! task runs, and pauses it through its [`CancellationToken`] when the battery
+//! runs low or the device gets too hot, or cancels it if told to
+//! [abort](TripAction::Abort). A paused task resumes once the device
+//! [recovered](ResumePolicy::Recovered), past thresholds a bit further than the
+//! ones that paused it so it does not flap around them. Every decision is
+//! reported with [`Event::Guard`].
+//!
+//! ```ignore
+//! let token = CancellationToken::new();
+//! let guard = BatteryGuard::start(ap.clone(), token.clone(), GuardOptions::default());
+//! task.execute_cancellable(&ap, &token)?;
+//! guard.stop();
+//! ```
+//!
+//! Only Android devices report their battery, see [`AutoPlay::battery`].
+
+use std::{
+    fmt,
+    sync::{Arc, mpsc},
+    thread,
+    time::Duration,
+};
+
+use serde::Serialize;
+use tracing::{debug, info, warn};
+
+use crate::{AutoPlay, adb::dumpsys::BatteryState, cancel::CancellationToken, event::Event};
+
+/// What to do with the task when a threshold is crossed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TripAction {
+    /// Hold it before its next step, see [`ResumePolicy`]
+    #[default]
+    Pause,
+    /// Cancel it, it fails with [`Error::Cancelled`](crate::Error::Cancelled)
+    Abort,
+}
+
+/// When a task paused by the guard carries on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResumePolicy {
+    /// Once the battery is back to `min_battery` percent and the temperature
+    /// down to `max_temperature` degrees Celsius
+    Recovered {
+        min_battery: u32,
+        max_temperature: f32,
+    },
+    /// Only when resumed through the token, i.e. by the user
+    Manual,
+}
+
+impl Default for ResumePolicy {
+    fn default() -> Self {
+        ResumePolicy::Recovered {
+            min_battery: 30,
+            max_temperature: 40.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GuardOptions {
+    /// Between two polls of the battery
+    pub interval: Duration,
+    /// In percent, `None` to not watch the level
+    pub min_battery: Option<u32>,
+    /// In degrees Celsius, `None` to not watch the temperature
+    pub max_temperature: Option<f32>,
+    pub on_trip: TripAction,
+    pub resume: ResumePolicy,
+}
+
+impl Default for GuardOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(60),
+            min_battery: Some(15),
+            max_temperature: Some(45.0),
+            on_trip: TripAction::default(),
+            resume: ResumePolicy::default(),
+        }
+    }
+}
+
+/// The percentage of a battery
+fn percent(battery: &BatteryState) -> Option<u32> {
+    battery
+        .fraction()
+        .map(|fraction| (fraction * 100.0).round() as u32)
+}
+
+impl GuardOptions {
+    /// Why `battery` crosses a threshold, `None` if it does not.
+    fn violation(&self, battery: &BatteryState) -> Option<String> {
+        if let (Some(min), Some(level)) = (self.min_battery, percent(battery))
+            && level < min
+        {
+            return Some(format!("battery at {level}%, below {min}%"));
+        }
+        if let (Some(max), Some(temperature)) = (self.max_temperature, battery.temperature)
+            && temperature > max
+        {
+            return Some(format!("battery at {temperature:.1}°C, above {max:.1}°C"));
+        }
+        None
+    }
+
+    /// Why a paused task can carry on, `None` while it has to wait.
+    fn recovery(&self, battery: &BatteryState) -> Option<String> {
+        let ResumePolicy::Recovered {
+            min_battery,
+            max_temperature,
+        } = self.resume
+        else {
+            return None;
+        };
+        let level = percent(battery);
+        if self.min_battery.is_some() && level.is_none_or(|level| level < min_battery) {
+            return None;
+        }
+        let temperature = battery.temperature;
+        if self.max_temperature.is_some() && temperature.is_none_or(|t| t > max_temperature) {
+            return None;
+        }
+        Some(format!(
+            "battery at {}%, {:.1}°C",
+            level.unwrap_or_default(),
+            temperature.unwrap_or_default()
+        ))
+    }
+}
+
+/// What the guard did, reported with [`Event::Guard`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GuardAction {
+    Paused,
+    Resumed,
+    Aborted,
+}
+
+impl fmt::Display for GuardAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            GuardAction::Paused => "paused",
+            GuardAction::Resumed => "resumed",
+            GuardAction::Aborted => "aborted",
+        })
+    }
+}
+
+/// Watches the battery until stopped or dropped, see the [module](self) docs.
+pub struct BatteryGuard {
+    /// Dropped to stop the thread
+    stop: Option<mpsc::Sender<()>>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl BatteryGuard {
+    pub fn start(ap: Arc<AutoPlay>, token: CancellationToken, options: GuardOptions) -> Self {
+        let probe = {
+            let ap = ap.clone();
+            move || ap.battery()
+        };
+        Self::spawn(probe, move |event| ap.events().emit(event), token, options)
+    }
+
+    fn spawn(
+        mut probe: impl FnMut() -> anyhow::Result<BatteryState> + Send + 'static,
+        emit: impl Fn(Event) + Send + 'static,
+        token: CancellationToken,
+        options: GuardOptions,
+    ) -> Self {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let handle = thread::spawn(move || {
+            // Whether the task is paused by the guard
+            let mut tripped = false;
+            loop {
+                if token.is_cancelled() {
+                    return;
+                }
+                // Resumed by someone else, it may be paused again
+                if tripped && !token.is_paused() {
+                    tripped = false;
+                }
+                match probe() {
+                    Ok(battery) => {
+                        debug!("battery: {battery:?}");
+                        if let Some(reason) = options.violation(&battery) {
+                            if !tripped {
+                                let action = match options.on_trip {
+                                    TripAction::Pause => {
+                                        token.pause();
+                                        tripped = true;
+                                        GuardAction::Paused
+                                    }
+                                    TripAction::Abort => {
+                                        token.cancel();
+                                        GuardAction::Aborted
+                                    }
+                                };
+                                warn!("{action} the task: {reason}");
+                                emit(Event::Guard { action, reason });
+                            }
+                        } else if tripped && let Some(reason) = options.recovery(&battery) {
+                            info!("resuming the task: {reason}");
+                            token.resume();
+                            tripped = false;
+                            emit(Event::Guard {
+                                action: GuardAction::Resumed,
+                                reason,
+                            });
+                        }
+                    }
+                    Err(err) => warn!("failed to read the battery: {err:#}"),
+                }
+                match stopped.recv_timeout(options.interval) {
+                    Err(mpsc::RecvTimeoutError::Timeout) => {}
+                    _ => return,
+                }
+            }
+        });
+        Self {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+
+    /// Stop watching, a task it paused stays paused.
+    pub fn stop(mut self) {
+        self.finish();
+    }
+
+    fn finish(&mut self) {
+        self.stop.take();
+        if let Some(handle) = self.handle.take()
+            && handle.join().is_err()
+        {
+            warn!("battery guard thread panicked");
+        }
+    }
+}
+
+impl Drop for BatteryGuard {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, sync::Mutex, time::Instant};
+
+    use crate::adb::dumpsys::BatteryStatus;
+
+    use super::*;
+
+    fn battery(level: u32, temperature: f32) -> BatteryState {
+        BatteryState {
+            level: Some(level),
+            scale: Some(100),
+            status: BatteryStatus::Discharging,
+            ac_powered: false,
+            usb_powered: false,
+            wireless_powered: false,
+            temperature: Some(temperature),
+            voltage: None,
+        }
+    }
+
+    /// Run a guard over `states`, one per poll, and return what it did.
+    fn run(
+        states: Vec<BatteryState>,
+        options: GuardOptions,
+        token: &CancellationToken,
+    ) -> Vec<GuardAction> {
+        let polls = states.len();
+        let states = Arc::new(Mutex::new(VecDeque::from(states)));
+        let probe = {
+            let states = states.clone();
+            move || {
+                states
+                    .lock()
+                    .unwrap()
+                    .pop_front()
+                    .ok_or_else(|| anyhow::anyhow!("no more states"))
+            }
+        };
+        let actions = Arc::new(Mutex::new(Vec::new()));
+        let emit = {
+            let actions = actions.clone();
+            move |event| {
+                if let Event::Guard { action, .. } = event {
+                    actions.lock().unwrap().push(action);
+                }
+            }
+        };
+        let options = GuardOptions {
+            interval: Duration::from_millis(5),
+            ..options
+        };
+        let guard = BatteryGuard::spawn(probe, emit, token.clone(), options);
+        let start = Instant::now();
+        while !states.lock().unwrap().is_empty()
+            && !token.is_cancelled()
+            && start.elapsed() < Duration::from_secs(5)
+        {
+            thread::sleep(Duration::from_millis(1));
+        }
+        // The last state is taken before it is acted on
+        thread::sleep(Duration::from_millis(20));
+        guard.stop();
+        assert!(polls > 0);
+        actions.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn test_battery_guard() {
+        let token = CancellationToken::new();
+        let actions = run(
+            vec![
+                battery(50, 30.0),
+                battery(50, 46.0),
+                battery(50, 43.0),
+                battery(50, 39.0),
+                battery(10, 39.0),
+            ],
+            GuardOptions::default(),
+            &token,
+        );
+        assert_eq!(
+            actions,
+            [
+                GuardAction::Paused,
+                GuardAction::Resumed,
+                GuardAction::Paused
+            ]
+        );
+        assert!(token.is_paused());
+
+        let token = CancellationToken::new();
+        let options = GuardOptions {
+            resume: ResumePolicy::Manual,
+            ..Default::default()
+        };
+        let actions = run(vec![battery(10, 30.0), battery(90, 30.0)], options, &token);
+        assert_eq!(actions, [GuardAction::Paused]);
+        assert!(token.is_paused());
+
+        let token = CancellationToken::new();
+        let options = GuardOptions {
+            on_trip: TripAction::Abort,
+            max_temperature: None,
+            ..Default::default()
+        };
+        let actions = run(vec![battery(50, 60.0), battery(5, 30.0)], options, &token);
+        assert_eq!(actions, [GuardAction::Aborted]);
+        assert!(token.is_cancelled());
+    }
+}
@@ -0,0 +1,162 @@
+//! Random delays and jitter to act less like a script
+//!
+//! Set on an [`AutoPlay`](crate::AutoPlay) with
+//! [`set_humanize`](crate::AutoPlay::set_humanize), a [`Humanize`] policy is
+//! applied to everything it does: a random delay before every step but the
+//! first, clicks moved around the point aimed at, or spread over the rect of a
+//! match, and swipes of random speed and easing. A task overrides it for its
+//! steps with a table of its own, the fields it leaves out take their defaults:
+//!
+//! ```toml
+//! name = "daily"
+//!
+//! [humanize]
+//! step_delay_ms = [200, 800]
+//! click_jitter = 4.0
+//! ```
+
+use std::time::Duration;
+
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Humanize {
+    /// The random delay before each step but the first, `[min, max]`
+    pub step_delay_ms: (u64, u64),
+    /// Standard deviation of the distance from a click to the point aimed at,
+    /// in pixels
+    pub click_jitter: f32,
+    /// Standard deviation of a click in the rect of a match, as a fraction of
+    /// its size. The clicks stay in the rect.
+    pub rect_jitter: f32,
+    /// How much faster or slower a swipe may be, `0.2` for up to 20%
+    pub swipe_speed_jitter: f32,
+    /// How much the easing slopes of a swipe may change, see
+    /// [`ControllerTrait::swipe`](ap_controller::ControllerTrait::swipe)
+    pub swipe_slope_jitter: f32,
+}
+
+impl Default for Humanize {
+    fn default() -> Self {
+        Self {
+            step_delay_ms: (100, 400),
+            click_jitter: 2.0,
+            rect_jitter: 0.15,
+            swipe_speed_jitter: 0.2,
+            swipe_slope_jitter: 0.3,
+        }
+    }
+}
+
+/// A sample of the standard normal distribution, by the Box-Muller transform
+fn standard_normal(rng: &mut impl Rng) -> f32 {
+    let u1 = rng.random_range(f32::EPSILON..1.0);
+    let u2 = rng.random::<f32>();
+    (-2.0 * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos()
+}
+
+/// `value` moved by `delta`, within `min..=max`
+fn shift(value: u32, delta: f32, min: u32, max: u32) -> u32 {
+    (value as f32 + delta).round().clamp(min as f32, max as f32) as u32
+}
+
+impl Humanize {
+    pub fn step_delay(&self) -> Duration {
+        let (min, max) = self.step_delay_ms;
+        Duration::from_millis(rand::rng().random_range(min.min(max)..=max.max(min)))
+    }
+
+    /// Where to click to hit `(x, y)` on a screen of `screen_size`.
+    pub fn click_point(&self, (x, y): (u32, u32), screen_size: (u32, u32)) -> (u32, u32) {
+        let mut rng = rand::rng();
+        let (width, height) = screen_size;
+        let dx = standard_normal(&mut rng) * self.click_jitter;
+        let dy = standard_normal(&mut rng) * self.click_jitter;
+        (
+            shift(x, dx, 0, width.saturating_sub(1)),
+            shift(y, dy, 0, height.saturating_sub(1)),
+        )
+    }
+
+    /// Where to click in `rect`, around its center.
+    pub fn rect_point(&self, rect: image::math::Rect) -> (u32, u32) {
+        let mut rng = rand::rng();
+        let dx = standard_normal(&mut rng) * self.rect_jitter * rect.width as f32;
+        let dy = standard_normal(&mut rng) * self.rect_jitter * rect.height as f32;
+        (
+            shift(
+                rect.x + rect.width / 2,
+                dx,
+                rect.x,
+                rect.x + rect.width.saturating_sub(1),
+            ),
+            shift(
+                rect.y + rect.height / 2,
+                dy,
+                rect.y,
+                rect.y + rect.height.saturating_sub(1),
+            ),
+        )
+    }
+
+    /// The duration and the easing slopes of a swipe, randomized.
+    pub fn swipe(&self, duration: Duration, slope_in: f32, slope_out: f32) -> (Duration, f32, f32) {
+        let mut rng = rand::rng();
+        let mut jitter = |amount: f32| match amount > 0.0 {
+            true => rng.random_range(-amount..=amount),
+            false => 0.0,
+        };
+        let speed = 1.0 + jitter(self.swipe_speed_jitter);
+        let slope_in = (slope_in + jitter(self.swipe_slope_jitter)).max(0.0);
+        let slope_out = (slope_out + jitter(self.swipe_slope_jitter)).max(0.0);
+        (duration.mul_f32(speed.max(0.0)), slope_in, slope_out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_humanize() {
+        let humanize = Humanize {
+            step_delay_ms: (10, 20),
+            ..Default::default()
+        };
+        let rect = image::math::Rect {
+            x: 100,
+            y: 50,
+            width: 40,
+            height: 10,
+        };
+        for _ in 0..1000 {
+            let delay = humanize.step_delay();
+            assert!((10..=20).contains(&(delay.as_millis() as u64)));
+
+            let (x, y) = humanize.rect_point(rect);
+            assert!((100..140).contains(&x) && (50..60).contains(&y));
+
+            let (x, y) = humanize.click_point((0, 719), (1280, 720));
+            assert!(x < 1280 && y < 720);
+
+            let (duration, slope_in, _) = humanize.swipe(Duration::from_millis(500), 1.0, 1.0);
+            assert!((400..=600).contains(&duration.as_millis()));
+            assert!((0.7..=1.3).contains(&slope_in));
+        }
+
+        let still = Humanize {
+            click_jitter: 0.0,
+            swipe_speed_jitter: 0.0,
+            ..Default::default()
+        };
+        assert_eq!(still.click_point((10, 20), (1280, 720)), (10, 20));
+        let (duration, ..) = still.swipe(Duration::from_millis(500), 1.0, 1.0);
+        assert_eq!(duration, Duration::from_millis(500));
+
+        let humanize = toml::from_str::<Humanize>("step_delay_ms = [200, 800]").unwrap();
+        assert_eq!(humanize.step_delay_ms, (200, 800));
+        assert_eq!(humanize.click_jitter, Humanize::default().click_jitter);
+    }
+}
@@ -3,26 +3,130 @@ pub use ap_controller as controller;
 pub use ap_cv as cv;
 
 pub mod action;
+pub mod bench;
+pub mod cancel;
+#[cfg(feature = "dashboard")]
+pub mod dashboard;
+pub mod debug_bundle;
+pub mod emulator;
+pub mod error;
+pub mod event;
+pub mod fleet;
+pub mod guard;
+pub mod humanize;
+pub mod live_view;
+pub mod macro_recorder;
 pub mod nav;
+pub mod plugin;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod recorder;
+pub mod report;
+pub mod resource;
+pub mod schedule;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod session;
+pub mod shm;
+pub mod task;
+pub mod telemetry;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "update")]
+pub mod update;
+pub mod validate;
 
 // Re-export the Controller trait and concrete implementations
 pub use controller::{AndroidController, Controller, ControllerTrait};
 
+#[cfg(any(feature = "windows", feature = "linux", target_os = "macos"))]
+pub use controller::DesktopController;
+#[cfg(feature = "linux")]
+pub use controller::LinuxController;
+#[cfg(target_os = "macos")]
+pub use controller::MacController;
 #[cfg(feature = "windows")]
 pub use controller::WindowsController;
 
 // Re-export specific items users might need frequently
 pub use adb::Device;
+pub use cancel::CancellationToken;
+pub use error::Error;
 pub use image::DynamicImage;
 
 // Export CV related options for matching
-pub use cv::core::template_matching::MatchTemplateMethod;
-pub use cv::matcher::MatcherOptions;
+pub use cv::cache::MatchCache;
+pub use cv::core::template_matching::{Match, MatchTemplateMethod};
+pub use cv::diff::{FrameChangeOptions, FrameState};
+pub use cv::matcher::{ColorMode, FeatureMatcherOptions, MatcherOptions, ScaleRange};
+pub use cv::ocr::{GlyphRecognizer, Ocr, OcrOptions, TextLine, TextRecognizer};
+pub use cv::store::{TemplateHandle, TemplateStore};
 
-use cv::matcher::SingleMatcher;
+use action::Action;
+use cv::cache::MatchKey;
+use cv::diff::FrameChangeDetector;
+use cv::matcher::{FeatureMatcher, MultiMatcher, SingleMatcher};
+use debug_bundle::{DebugBundleOptions, RecentMatch};
+use event::{Event, EventBus};
+use fleet::MatchLimiter;
+use humanize::Humanize;
+use plugin::PluginRegistry;
+use resource::Resource;
+use shm::FramePublisher;
 use std::any::Any;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, mpsc};
 use std::time::Duration;
 
+/// `m` found in the part of the screen at `offset`, in screen coordinates
+fn on_screen(m: Match, offset: (u32, u32)) -> Match {
+    Match {
+        rect: image::math::Rect {
+            x: m.rect.x + offset.0,
+            y: m.rect.y + offset.1,
+            ..m.rect
+        },
+        ..m
+    }
+}
+
+/// A template to match, decoded by the caller or loaded in [`AutoPlay::templates`]
+#[derive(Clone, Copy)]
+enum Needle<'a> {
+    Image(&'a DynamicImage),
+    Stored(&'a cv::store::Template),
+}
+
+impl<'a> Needle<'a> {
+    fn image(self) -> &'a DynamicImage {
+        match self {
+            Needle::Image(image) => image,
+            Needle::Stored(template) => &template.image,
+        }
+    }
+
+    fn path(self) -> Option<&'a Path> {
+        match self {
+            Needle::Image(_) => None,
+            Needle::Stored(template) => template.path.as_deref(),
+        }
+    }
+
+    fn match_on(
+        self,
+        screen: &DynamicImage,
+        options: &MatcherOptions,
+    ) -> cv::matcher::SingleMatcherResult {
+        match self {
+            Needle::Image(image) => SingleMatcher::match_image(screen, image, options),
+            Needle::Stored(template) => SingleMatcher::match_stored(screen, template, options),
+        }
+    }
+}
+
 /// The main entry point for automation tasks.
 ///
 /// `AutoPlay` integrates device control (via `ap-controller`) and computer vision (via `ap-cv`)
@@ -42,42 +146,313 @@ use std::time::Duration;
 /// auto_play.click_image(&template, &MatcherOptions::default())?;
 /// ```
 pub struct AutoPlay {
-    controller: Controller,
+    controller: RwLock<Arc<Controller>>,
+    plugins: PluginRegistry,
+    events: EventBus,
+    frame_publisher: Mutex<Option<FramePublisher>>,
+    ocr: RwLock<Option<Arc<Ocr>>>,
+    match_cache: Mutex<MatchCache>,
+    templates: TemplateStore,
+    resource: RwLock<Arc<Resource>>,
+    match_limiter: RwLock<Option<Arc<MatchLimiter>>>,
+    humanize: RwLock<Option<Humanize>>,
+    /// The logcat filter of the reports of failed tasks, see [`AutoPlay::set_report_logcat`]
+    report_logcat: RwLock<Option<String>>,
+    /// Sessions recording the screens matched on, see [`AutoPlay::record_match_frames`]
+    match_frames: AtomicUsize,
+    debug_bundle: RwLock<Option<Arc<DebugBundleOptions>>>,
+    /// The last matches, kept while a debug bundle is set
+    recent_matches: Mutex<VecDeque<RecentMatch>>,
 }
 
 impl AutoPlay {
-    pub fn new<T: ControllerTrait + Any + Send + 'static>(controller: T) -> Self {
+    pub fn new<T: ControllerTrait + Any + Send + Sync + 'static>(controller: T) -> Self {
         Self {
-            controller: Controller::new(controller),
+            controller: RwLock::new(Arc::new(Controller::new(controller))),
+            plugins: PluginRegistry::new(),
+            events: EventBus::new(),
+            frame_publisher: Mutex::new(None),
+            ocr: RwLock::new(None),
+            match_cache: Mutex::new(MatchCache::default()),
+            templates: TemplateStore::new(),
+            resource: RwLock::new(Arc::new(Resource::default())),
+            match_limiter: RwLock::new(None),
+            humanize: RwLock::new(None),
+            report_logcat: RwLock::new(None),
+            match_frames: AtomicUsize::new(0),
+            debug_bundle: RwLock::new(None),
+            recent_matches: Mutex::new(VecDeque::new()),
         }
     }
 
-    pub fn controller(&self) -> &Controller {
-        &self.controller
+    /// Start the instance `index` of an installed emulator unless it is running,
+    /// wait for it to boot and connect to it, see [`emulator`].
+    pub fn connect_emulator(kind: emulator::EmulatorKind, index: usize) -> anyhow::Result<Self> {
+        let emulator = emulator::Emulator::detect(kind)
+            .ok_or_else(|| anyhow::anyhow!("{kind} is not installed"))?;
+        let serial = emulator.start(index, emulator::BOOT_TIMEOUT)?;
+        Ok(Self::new(AndroidController::connect(&serial)?))
+    }
+
+    /// The current controller, it stays usable after being swapped out by
+    /// [`AutoPlay::swap_controller`].
+    pub fn controller(&self) -> Arc<Controller> {
+        self.controller.read().unwrap().clone()
     }
 
-    pub fn controller_ref<T: ControllerTrait + 'static>(&self) -> Option<&T> {
-        self.controller.downcast_ref::<T>()
+    /// Replace the controller, i.e. to continue on a desktop window after the
+    /// Android device disconnected, and return the previous one.
+    ///
+    /// Actions and tasks in progress carry on with the new controller from their
+    /// next call. Its resolution and scale factor are detected right away and
+    /// reported to subscribers with [`Event::ControllerSwapped`].
+    pub fn swap_controller<T: ControllerTrait + Any + Send + Sync + 'static>(
+        &self,
+        controller: T,
+    ) -> Arc<Controller> {
+        let controller = Controller::new(controller);
+        let screen_size = controller.screen_size();
+        let scale_factor = controller.scale_factor();
+        let prev = std::mem::replace(&mut *self.controller.write().unwrap(), Arc::new(controller));
+        self.events.emit(Event::ControllerSwapped {
+            screen_size,
+            scale_factor,
+        });
+        prev
+    }
+
+    /// Actions registered at runtime, see [`action::PluginAction`].
+    pub fn plugins(&self) -> &PluginRegistry {
+        &self.plugins
+    }
+
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    /// Receive the [`Event`]s of everything done from now on.
+    pub fn subscribe(&self) -> mpsc::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Call `f` with the current controller if it is a `T`.
+    pub fn with_controller<T: ControllerTrait + 'static, R>(
+        &self,
+        f: impl FnOnce(&T) -> R,
+    ) -> Option<R> {
+        self.controller().downcast_ref::<T>().map(f)
     }
 
     pub fn screen_size(&self) -> (u32, u32) {
-        self.controller.screen_size()
+        self.controller().screen_size()
     }
 
     pub fn scale_factor(&self) -> f32 {
-        self.controller.scale_factor()
+        self.controller().scale_factor()
+    }
+
+    /// What the device is, `None` for controllers that do not probe it, see
+    /// [`controller::profile`].
+    pub fn device_profile(&self) -> Option<controller::DeviceProfile> {
+        self.controller().device_profile().cloned()
+    }
+
+    /// Publish every frame captured from now on to the shared-memory region `name`,
+    /// see [`shm`]. Returns the path of the region.
+    pub fn publish_frames(&self, name: &str) -> anyhow::Result<PathBuf> {
+        let publisher = FramePublisher::create(name)?;
+        let path = publisher.path().to_path_buf();
+        *self.frame_publisher.lock().unwrap() = Some(publisher);
+        Ok(path)
+    }
+
+    /// Stop publishing frames and remove the shared-memory region.
+    pub fn stop_publishing_frames(&self) {
+        self.frame_publisher.lock().unwrap().take();
+    }
+
+    /// `rgba` is only called when publishing is enabled, to skip the conversion otherwise.
+    fn publish_frame<'a>(&self, width: u32, height: u32, rgba: impl FnOnce() -> Cow<'a, [u8]>) {
+        if let Some(publisher) = self.frame_publisher.lock().unwrap().as_mut()
+            && let Err(err) = publisher.publish(width, height, &rgba())
+        {
+            tracing::warn!("failed to publish frame: {err:#}");
+        }
+    }
+
+    /// Emit [`Event::ResolutionChanged`] if the resolution of `controller` changed
+    /// since the last capture.
+    fn check_resolution(&self, controller: &Controller) {
+        if let Some(screen_size) = controller.resolution_changed() {
+            self.events.emit(Event::ResolutionChanged {
+                screen_size,
+                scale_factor: controller.scale_factor(),
+            });
+        }
+    }
+
+    /// The screen as `(width, height, rgba_bytes)`, without decoding it into an image.
+    pub fn screencap_raw(&self) -> anyhow::Result<(u32, u32, Vec<u8>)> {
+        let controller = self.controller();
+        let (width, height, rgba) = controller.screencap_raw().map_err(Error::Capture)?;
+        self.check_resolution(&controller);
+        self.publish_frame(width, height, || Cow::Borrowed(&rgba));
+        Ok((width, height, rgba))
     }
 
     pub fn screencap(&self) -> anyhow::Result<DynamicImage> {
-        self.controller.screencap()
+        let controller = self.controller();
+        let screen = controller.screencap().map_err(Error::Capture)?;
+        self.check_resolution(&controller);
+        self.publish_frame(screen.width(), screen.height(), || match &screen {
+            DynamicImage::ImageRgba8(rgba) => Cow::Borrowed(rgba.as_raw()),
+            screen => Cow::Owned(screen.to_rgba8().into_raw()),
+        });
+        Ok(screen)
+    }
+
+    /// The part of the screen in `rect`, see [`ControllerTrait::screencap_region`].
+    /// It is not published, see [`AutoPlay::publish_frames`].
+    pub fn screencap_region(&self, rect: image::math::Rect) -> anyhow::Result<DynamicImage> {
+        let controller = self.controller();
+        let region = controller.screencap_region(rect).map_err(Error::Capture)?;
+        self.check_resolution(&controller);
+        Ok(region)
+    }
+
+    /// A JPEG of the screen with `quality` from 1 to 100, see
+    /// [`ControllerTrait::screencap_jpeg`].
+    pub fn screencap_jpeg(&self, quality: u8) -> anyhow::Result<Vec<u8>> {
+        let controller = self.controller();
+        let jpeg = controller.screencap_jpeg(quality).map_err(Error::Capture)?;
+        self.check_resolution(&controller);
+        Ok(jpeg)
+    }
+
+    /// Randomize delays, clicks and swipes with `humanize` from now on, or stop
+    /// with `None`, see [`humanize`].
+    ///
+    /// Tasks with a policy of their own use it for their steps instead.
+    pub fn set_humanize(&self, humanize: Option<Humanize>) -> Option<Humanize> {
+        std::mem::replace(&mut *self.humanize.write().unwrap(), humanize)
+    }
+
+    pub fn humanize(&self) -> Option<Humanize> {
+        *self.humanize.read().unwrap()
+    }
+
+    /// The battery of the device, failing if the controller is not an
+    /// [`AndroidController`], see [`guard`].
+    pub fn battery(&self) -> anyhow::Result<adb::dumpsys::BatteryState> {
+        self.with_controller(|controller: &AndroidController| controller.device().dumpsys_battery())
+            .ok_or_else(|| anyhow::anyhow!("the controller has no battery"))?
+            .map_err(Into::into)
+    }
+
+    /// Attach the last lines of the logcat matching `filter` to the reports of the
+    /// tasks that fail from now on, i.e. `"*:E"` or `"Unity:V *:S"`, or stop with
+    /// `None`. Only Android devices have one, see [`adb::logcat`].
+    pub fn set_report_logcat(&self, filter: Option<String>) -> Option<String> {
+        std::mem::replace(&mut *self.report_logcat.write().unwrap(), filter)
+    }
+
+    pub fn report_logcat(&self) -> Option<String> {
+        self.report_logcat.read().unwrap().clone()
+    }
+
+    /// Write a bundle of the failure of the tasks failing from now on, or stop
+    /// with `None`, see [`debug_bundle`].
+    pub fn set_debug_bundle(
+        &self,
+        options: Option<DebugBundleOptions>,
+    ) -> Option<Arc<DebugBundleOptions>> {
+        if options.is_none() {
+            self.recent_matches.lock().unwrap().clear();
+        }
+        std::mem::replace(
+            &mut *self.debug_bundle.write().unwrap(),
+            options.map(Arc::new),
+        )
     }
 
+    pub fn debug_bundle(&self) -> Option<Arc<DebugBundleOptions>> {
+        self.debug_bundle.read().unwrap().clone()
+    }
+
+    /// The last matches, the oldest first, only kept while a debug bundle is set
+    pub fn recent_matches(&self) -> Vec<RecentMatch> {
+        self.recent_matches
+            .lock()
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// The last `lines` entries of the logcat of the device matching `filter`,
+    /// failing if the controller is not an [`AndroidController`].
+    pub fn logcat_dump(
+        &self,
+        filter: &str,
+        lines: usize,
+    ) -> anyhow::Result<Vec<adb::logcat::LogEntry>> {
+        self.with_controller(|controller: &AndroidController| {
+            controller.device().logcat_dump(filter, lines)
+        })
+        .ok_or_else(|| anyhow::anyhow!("the controller has no logcat"))?
+        .map_err(Into::into)
+    }
+
+    /// Click at `(x, y)`, or around it with a [`Humanize`] policy.
     pub fn click(&self, x: u32, y: u32) -> anyhow::Result<()> {
-        self.controller.click(x, y)
+        self.click_with(x, y, self.humanize())
+    }
+
+    /// [`AutoPlay::click`] with `humanize` in place of the policy set, i.e. the
+    /// one of the task running, see [`action::ExecContext`].
+    pub fn click_with(&self, x: u32, y: u32, humanize: Option<Humanize>) -> anyhow::Result<()> {
+        let (x, y) = match humanize {
+            Some(humanize) => humanize.click_point((x, y), self.screen_size()),
+            None => (x, y),
+        };
+        self.events.emit(Event::Click { x, y });
+        self.controller().click(x, y)
+    }
+
+    /// Click the center of `rect`, or somewhere in it with a [`Humanize`] policy.
+    pub fn click_rect(&self, rect: image::math::Rect) -> anyhow::Result<()> {
+        self.click_rect_with(rect, self.humanize())
+    }
+
+    /// [`AutoPlay::click_rect`] with `humanize` in place of the policy set.
+    pub fn click_rect_with(
+        &self,
+        rect: image::math::Rect,
+        humanize: Option<Humanize>,
+    ) -> anyhow::Result<()> {
+        let (x, y) = match humanize {
+            Some(humanize) => humanize.rect_point(rect),
+            None => (rect.x + rect.width / 2, rect.y + rect.height / 2),
+        };
+        self.events.emit(Event::Click { x, y });
+        self.controller().click(x, y)
+    }
+
+    pub fn long_press(&self, x: u32, y: u32, duration: Duration) -> anyhow::Result<()> {
+        self.controller().long_press(x, y, duration)
+    }
+
+    pub fn double_click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+        self.controller().double_click(x, y)
     }
 
     pub fn press(&self, key: controller::Key) -> anyhow::Result<()> {
-        self.controller.press(key)
+        self.events.emit(Event::Press { key });
+        self.controller().press(key)
+    }
+
+    pub fn input_text(&self, text: &str) -> anyhow::Result<()> {
+        self.controller().input_text(text)
     }
 
     pub fn swipe(
@@ -88,20 +463,416 @@ impl AutoPlay {
         slope_in: f32,
         slope_out: f32,
     ) -> anyhow::Result<()> {
-        self.controller
+        self.swipe_with(start, end, duration, slope_in, slope_out, self.humanize())
+    }
+
+    /// [`AutoPlay::swipe`] with `humanize` in place of the policy set.
+    pub fn swipe_with(
+        &self,
+        start: (u32, u32),
+        end: (i32, i32),
+        duration: Duration,
+        slope_in: f32,
+        slope_out: f32,
+        humanize: Option<Humanize>,
+    ) -> anyhow::Result<()> {
+        let (duration, slope_in, slope_out) = match humanize {
+            Some(humanize) => humanize.swipe(duration, slope_in, slope_out),
+            None => (duration, slope_in, slope_out),
+        };
+        self.events.emit(Event::Swipe {
+            start,
+            end,
+            duration,
+        });
+        self.controller()
             .swipe(start, end, duration, slope_in, slope_out)
     }
 
+    pub fn multi_touch(&self, gesture: controller::Gesture) -> anyhow::Result<()> {
+        self.controller().multi_touch(gesture)
+    }
+
     pub fn find_image(
         &self,
         template: &DynamicImage,
         options: &MatcherOptions,
     ) -> anyhow::Result<Option<image::math::Rect>> {
+        Ok(self.match_image(template, options)?.map(|m| m.rect))
+    }
+
+    /// [`AutoPlay::find_image`] with the score of the match and the scale of the
+    /// template it was found at.
+    pub fn match_image(
+        &self,
+        template: &DynamicImage,
+        options: &MatcherOptions,
+    ) -> anyhow::Result<Option<Match>> {
+        self.match_needle(Needle::Image(template), options, false)
+    }
+
+    /// `report_miss` as in [`AutoPlay::find_template_reporting`]
+    fn match_needle(
+        &self,
+        template: Needle,
+        options: &MatcherOptions,
+        report_miss: bool,
+    ) -> anyhow::Result<Option<Match>> {
+        // Only capture and match the region, the cost is in the size of the screen
+        let (screen, offset) = match options.region {
+            Some(region) => (self.screencap_region(region)?, (region.x, region.y)),
+            None => (self.screencap()?, (0, 0)),
+        };
+        let res = self.limit_matching(|| template.match_on(&screen, options));
+        let found = res.result.map(|m| on_screen(m, offset));
+        self.matched(
+            &screen,
+            offset,
+            template,
+            found.map(|m| m.rect),
+            found.map(|m| m.value),
+        );
+        if found.is_none() && report_miss {
+            self.report_missed_match(&screen, offset, template, res.candidate);
+        }
+        Ok(found)
+    }
+
+    /// The screen with the best location of `template` drawn on it with its
+    /// score, see [`cv::debug::annotate`], and that location in screen
+    /// coordinates, whether or not it passes the threshold of `options`. To see
+    /// why [`AutoPlay::find_image`] did not find it, the screen is captured and
+    /// matched again.
+    pub fn match_overlay(
+        &self,
+        template: &DynamicImage,
+        options: &MatcherOptions,
+    ) -> anyhow::Result<(DynamicImage, Option<Match>)> {
         let screen = self.screencap()?;
-        let screen_luma = screen.to_luma32f();
-        let template_luma = template.to_luma32f();
-        let res = SingleMatcher::match_template(&screen_luma, &template_luma, options);
-        Ok(res.result.map(|m| m.rect))
+        let res = self.limit_matching(|| match options.region {
+            Some(region) => SingleMatcher::match_image(
+                &screen.crop_imm(region.x, region.y, region.width, region.height),
+                template,
+                options,
+            ),
+            None => SingleMatcher::match_image(&screen, template, options),
+        });
+        let offset = options.region.map_or((0, 0), |region| (region.x, region.y));
+        let candidate = res.candidate.map(|m| on_screen(m, offset));
+        let overlay = cv::debug::annotate(&screen, candidate.as_slice());
+        Ok((overlay, candidate))
+    }
+
+    /// [`AutoPlay::find_template`], or [`AutoPlay::find_template_cached`] with
+    /// `cached`, reporting a miss with [`Event::MatchOverlay`] if someone
+    /// subscribes. The overlay is drawn on the frame the template was matched on,
+    /// a cached miss is not reported again as the screen did not change since.
+    pub(crate) fn find_template_reporting(
+        &self,
+        template: &TemplateHandle,
+        options: &MatcherOptions,
+        cached: bool,
+    ) -> anyhow::Result<Option<image::math::Rect>> {
+        let template = template.template();
+        match cached {
+            true => self.find_needle_cached(Needle::Stored(&template), options, true),
+            false => Ok(self
+                .match_needle(Needle::Stored(&template), options, true)?
+                .map(|m| m.rect)),
+        }
+    }
+
+    /// Emit the [`Event::MatchOverlay`] of `template` not found on `screen`, the
+    /// part of the screen at `offset` it was matched on, with the best `candidate`
+    /// there.
+    fn report_missed_match(
+        &self,
+        screen: &DynamicImage,
+        offset: (u32, u32),
+        template: Needle,
+        candidate: Option<Match>,
+    ) {
+        if !self.events.has_subscribers() {
+            return;
+        }
+        let frame = cv::debug::annotate(screen, candidate.as_slice());
+        self.events.emit(Event::MatchOverlay {
+            frame: Arc::new(frame),
+            offset,
+            template_size: (template.image().width(), template.image().height()),
+            candidate: candidate.map(|m| on_screen(m, offset)),
+        });
+    }
+
+    /// Share `limiter` with other instances to bound the template matches they run
+    /// at once, see [`fleet::Fleet::with_match_concurrency`].
+    pub fn set_match_limiter(&self, limiter: Option<Arc<MatchLimiter>>) {
+        *self.match_limiter.write().unwrap() = limiter;
+    }
+
+    /// Run the match `f` once the [match limiter](AutoPlay::set_match_limiter)
+    /// lets it.
+    fn limit_matching<R>(&self, f: impl FnOnce() -> R) -> R {
+        let limiter = self.match_limiter.read().unwrap().clone();
+        let _permit = limiter.as_ref().map(|limiter| limiter.acquire());
+        f()
+    }
+
+    /// Report the result of matching `template` with [`Event::MatchResult`], and
+    /// with [`Event::MatchFrame`] while a [session](session) records them.
+    /// `screen` is the part of the screen searched, at `offset`.
+    fn matched(
+        &self,
+        screen: &DynamicImage,
+        offset: (u32, u32),
+        template: Needle,
+        rect: Option<image::math::Rect>,
+        score: Option<f32>,
+    ) -> Option<image::math::Rect> {
+        let template_size = (template.image().width(), template.image().height());
+        tracing::debug!(
+            template = ?template_size,
+            found = ?rect.map(|rect| (rect.x, rect.y)),
+            score,
+            "matched"
+        );
+        if self.match_frames.load(Ordering::Relaxed) > 0 {
+            self.events.emit(Event::MatchFrame {
+                frame: Arc::new(screen.clone()),
+                offset,
+                template_size,
+                rect,
+            });
+        }
+        if self.debug_bundle.read().unwrap().is_some() {
+            let mut recent = self.recent_matches.lock().unwrap();
+            if recent.len() == debug_bundle::RECENT_MATCHES {
+                recent.pop_front();
+            }
+            recent.push_back(RecentMatch {
+                template: template.path().map(Path::to_path_buf),
+                template_size,
+                rect,
+                score,
+            });
+        }
+        self.events.emit(Event::MatchResult {
+            template_size,
+            rect,
+            score,
+        });
+        rect
+    }
+
+    /// Emit [`Event::MatchFrame`]s until as many calls with `false` are made.
+    pub(crate) fn record_match_frames(&self, enable: bool) {
+        match enable {
+            true => self.match_frames.fetch_add(1, Ordering::Relaxed),
+            false => self.match_frames.fetch_sub(1, Ordering::Relaxed),
+        };
+    }
+
+    /// [`AutoPlay::find_image`] of each of `templates` on a single capture, all
+    /// matched in one GPU dispatch, see [`cv::core::template_matching::match_templates`].
+    pub fn find_images(
+        &self,
+        templates: &[&DynamicImage],
+        options: &MatcherOptions,
+    ) -> anyhow::Result<Vec<Option<image::math::Rect>>> {
+        let (screen, (offset_x, offset_y)) = match options.region {
+            Some(region) => (self.screencap_region(region)?, (region.x, region.y)),
+            None => (self.screencap()?, (0, 0)),
+        };
+        let res = self.limit_matching(|| SingleMatcher::match_images(&screen, templates, options));
+        Ok(res
+            .into_iter()
+            .zip(templates)
+            .map(|(res, template)| {
+                self.matched(
+                    &screen,
+                    (offset_x, offset_y),
+                    Needle::Image(template),
+                    res.result.map(|m| image::math::Rect {
+                        x: m.rect.x + offset_x,
+                        y: m.rect.y + offset_y,
+                        ..m.rect
+                    }),
+                    res.result.map(|m| m.value),
+                )
+            })
+            .collect())
+    }
+
+    /// Templates loaded once for all the matches, i.e. the ones of the tasks.
+    pub fn templates(&self) -> &TemplateStore {
+        &self.templates
+    }
+
+    /// The template at `path` in [`AutoPlay::templates`], the variant for the
+    /// resolution of the screen if it has some, see [`TemplateStore::load_variant`].
+    pub fn load_template(&self, path: &Path) -> anyhow::Result<TemplateHandle> {
+        self.templates
+            .load_variant(path, || {
+                let (width, height) = self.screen_size();
+                width.min(height)
+            })
+            .map_err(|err| anyhow::anyhow!("failed to load template {}: {err}", path.display()))
+    }
+
+    /// The tasks loaded, see [`Resource::watch`] to reload them as they are edited.
+    pub fn resource(&self) -> Arc<Resource> {
+        self.resource.read().unwrap().clone()
+    }
+
+    /// Replace the tasks loaded, the tasks running carry on with the previous ones.
+    pub fn set_resource(&self, resource: Resource) {
+        *self.resource.write().unwrap() = Arc::new(resource);
+    }
+
+    /// [`AutoPlay::find_image`] of a template of [`AutoPlay::templates`], which is
+    /// neither decoded nor uploaded again.
+    pub fn find_template(
+        &self,
+        template: &TemplateHandle,
+        options: &MatcherOptions,
+    ) -> anyhow::Result<Option<image::math::Rect>> {
+        Ok(self.match_template(template, options)?.map(|m| m.rect))
+    }
+
+    /// [`AutoPlay::match_image`] of a template of [`AutoPlay::templates`].
+    pub fn match_template(
+        &self,
+        template: &TemplateHandle,
+        options: &MatcherOptions,
+    ) -> anyhow::Result<Option<Match>> {
+        self.match_needle(Needle::Stored(&template.template()), options, false)
+    }
+
+    /// [`AutoPlay::find_image`] reusing the result of a previous call if the screen
+    /// did not change since, see [`AutoPlay::match_cache`].
+    pub fn find_image_cached(
+        &self,
+        template: &DynamicImage,
+        options: &MatcherOptions,
+    ) -> anyhow::Result<Option<image::math::Rect>> {
+        self.find_needle_cached(Needle::Image(template), options, false)
+    }
+
+    /// [`AutoPlay::find_image_cached`] of a template of [`AutoPlay::templates`].
+    pub fn find_template_cached(
+        &self,
+        template: &TemplateHandle,
+        options: &MatcherOptions,
+    ) -> anyhow::Result<Option<image::math::Rect>> {
+        self.find_needle_cached(Needle::Stored(&template.template()), options, false)
+    }
+
+    fn find_needle_cached(
+        &self,
+        template: Needle,
+        options: &MatcherOptions,
+        report_miss: bool,
+    ) -> anyhow::Result<Option<image::math::Rect>> {
+        let (screen, offset) = match options.region {
+            Some(region) => (self.screencap_region(region)?, (region.x, region.y)),
+            None => (self.screencap()?, (0, 0)),
+        };
+        // Not locked while matching, which would hold up the other threads
+        let key = MatchKey::new(&screen, template.image(), options);
+        let cached = self.match_cache.lock().unwrap().get(&key);
+        // The candidate only of a match just run, a cached one was reported then
+        let (res, candidate) = match cached {
+            Some(res) => (res, None),
+            None => {
+                let res = self.limit_matching(|| template.match_on(&screen, options));
+                self.match_cache.lock().unwrap().insert(key, res.result);
+                (res.result, Some(res.candidate))
+            }
+        };
+        let found = self.matched(
+            &screen,
+            offset,
+            template,
+            res.map(|m| on_screen(m, offset).rect),
+            res.map(|m| m.value),
+        );
+        if let Some(candidate) = candidate
+            && found.is_none()
+            && report_miss
+        {
+            self.report_missed_match(&screen, offset, template, candidate);
+        }
+        Ok(found)
+    }
+
+    /// The results of [`AutoPlay::find_image_cached`], to change its capacity or
+    /// clear it, i.e. after something changed the screen without the hash noticing.
+    pub fn match_cache(&self) -> std::sync::MutexGuard<'_, MatchCache> {
+        self.match_cache.lock().unwrap()
+    }
+
+    /// Like [`AutoPlay::find_image`] but matching the features of `template`,
+    /// for when it is rotated or over an animated background.
+    pub fn find_image_features(
+        &self,
+        template: &DynamicImage,
+        options: &FeatureMatcherOptions,
+    ) -> anyhow::Result<Option<image::math::Rect>> {
+        let (screen, (offset_x, offset_y)) = match options.region {
+            Some(region) => (self.screencap_region(region)?, (region.x, region.y)),
+            None => (self.screencap()?, (0, 0)),
+        };
+        let res = FeatureMatcher::match_template(&screen.to_luma8(), &template.to_luma8(), options);
+        Ok(self.matched(
+            &screen,
+            (offset_x, offset_y),
+            Needle::Image(template),
+            res.map(|m| image::math::Rect {
+                x: m.rect.x + offset_x,
+                y: m.rect.y + offset_y,
+                ..m.rect
+            }),
+            None,
+        ))
+    }
+
+    /// Every match of `template` on the screen in reading order, from top to
+    /// bottom then left to right, i.e. the entries of a list. Rows are bands of
+    /// the height of the template, a match is in the one of its center.
+    pub fn find_all_images(
+        &self,
+        template: &DynamicImage,
+        options: &MatcherOptions,
+    ) -> anyhow::Result<Vec<Match>> {
+        let (screen, (offset_x, offset_y)) = match options.region {
+            Some(region) => (self.screencap_region(region)?, (region.x, region.y)),
+            None => (self.screencap()?, (0, 0)),
+        };
+        let res = self.limit_matching(|| MultiMatcher::match_image(&screen, template, options));
+        let mut found = res
+            .result
+            .into_iter()
+            .map(|m| Match {
+                rect: image::math::Rect {
+                    x: m.rect.x + offset_x,
+                    y: m.rect.y + offset_y,
+                    ..m.rect
+                },
+                ..m
+            })
+            .collect::<Vec<_>>();
+        // Reported as the best of them
+        let best = found.first().copied();
+        self.matched(
+            &screen,
+            (offset_x, offset_y),
+            Needle::Image(template),
+            best.map(|m| m.rect),
+            best.map(|m| m.value),
+        );
+        let row_height = template.height().max(1);
+        found.sort_by_key(|m| ((m.rect.y + m.rect.height / 2) / row_height, m.rect.x));
+        Ok(found)
     }
 
     pub fn find_image_default(
@@ -117,8 +888,46 @@ impl AutoPlay {
         options: &MatcherOptions,
     ) -> anyhow::Result<bool> {
         if let Some(rect) = self.find_image(template, options)? {
-            self.controller
-                .click(rect.x + rect.width / 2, rect.y + rect.height / 2)?;
+            self.click_rect(rect)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn click_image_cached(
+        &self,
+        template: &DynamicImage,
+        options: &MatcherOptions,
+    ) -> anyhow::Result<bool> {
+        if let Some(rect) = self.find_image_cached(template, options)? {
+            self.click_rect(rect)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn click_template(
+        &self,
+        template: &TemplateHandle,
+        options: &MatcherOptions,
+    ) -> anyhow::Result<bool> {
+        if let Some(rect) = self.find_template(template, options)? {
+            self.click_rect(rect)?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    pub fn click_image_features(
+        &self,
+        template: &DynamicImage,
+        options: &FeatureMatcherOptions,
+    ) -> anyhow::Result<bool> {
+        if let Some(rect) = self.find_image_features(template, options)? {
+            self.click_rect(rect)?;
             Ok(true)
         } else {
             Ok(false)
@@ -155,4 +964,201 @@ impl AutoPlay {
         }
         Ok(None)
     }
+
+    /// Whether the pixel at `point` is `expected`, see [`cv::color::check_color`].
+    /// Only that pixel is captured.
+    pub fn check_color(
+        &self,
+        point: (u32, u32),
+        expected: image::Rgb<u8>,
+        tolerance: u8,
+    ) -> anyhow::Result<bool> {
+        let (x, y) = point;
+        let pixel = self.screencap_region(image::math::Rect {
+            x,
+            y,
+            width: 1,
+            height: 1,
+        })?;
+        Ok(cv::color::check_color(&pixel, (0, 0), expected, tolerance))
+    }
+
+    /// Whether the average color of `region` is `expected`, see
+    /// [`cv::color::check_region_color`]. Only the region is captured.
+    pub fn check_region_color(
+        &self,
+        region: image::math::Rect,
+        expected: image::Rgb<u8>,
+        tolerance: u8,
+    ) -> anyhow::Result<bool> {
+        let image = self.screencap_region(region)?;
+        let rect = image::math::Rect {
+            x: 0,
+            y: 0,
+            ..region
+        };
+        Ok(cv::color::check_region_color(
+            &image, rect, expected, tolerance,
+        ))
+    }
+
+    /// Set the engine [`AutoPlay::read_text`] reads with.
+    pub fn set_ocr(&self, ocr: Ocr) {
+        *self.ocr.write().unwrap() = Some(Arc::new(ocr));
+    }
+
+    /// The lines of text on the screen, or in `region` of it. Fails if no engine
+    /// was set with [`AutoPlay::set_ocr`].
+    pub fn read_text(&self, region: Option<image::math::Rect>) -> anyhow::Result<Vec<TextLine>> {
+        let Some(ocr) = self.ocr.read().unwrap().clone() else {
+            anyhow::bail!("no OCR engine, set one with AutoPlay::set_ocr");
+        };
+        // Only capture the region, like find_image
+        let lines = match region {
+            Some(region) => ocr
+                .read_text(&self.screencap_region(region)?, None)
+                .into_iter()
+                .map(|line| TextLine {
+                    rect: image::math::Rect {
+                        x: line.rect.x + region.x,
+                        y: line.rect.y + region.y,
+                        ..line.rect
+                    },
+                    ..line
+                })
+                .collect(),
+            None => ocr.read_text(&self.screencap()?, None),
+        };
+        Ok(lines)
+    }
+
+    /// The first line of text containing `text`, see [`AutoPlay::read_text`].
+    pub fn find_text(
+        &self,
+        text: &str,
+        region: Option<image::math::Rect>,
+    ) -> anyhow::Result<Option<TextLine>> {
+        Ok(self
+            .read_text(region)?
+            .into_iter()
+            .find(|line| line.text.contains(text)))
+    }
+
+    pub fn wait_for_text(
+        &self,
+        text: &str,
+        region: Option<image::math::Rect>,
+        timeout: Duration,
+    ) -> anyhow::Result<Option<TextLine>> {
+        let start = std::time::Instant::now();
+        while start.elapsed() < timeout {
+            if let Some(line) = self.find_text(text, region)? {
+                return Ok(Some(line));
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        Ok(None)
+    }
+
+    /// Wait until the screen stops changing, i.e. a loading screen or a scene
+    /// transition is over. Fails with [`Error::Timeout`] if it does not within `timeout`.
+    pub fn wait_stable(
+        &self,
+        options: &FrameChangeOptions,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        let mut detector = FrameChangeDetector::new(*options);
+        let start = std::time::Instant::now();
+        while start.elapsed() < timeout {
+            if detector.push(&self.screencap()?) == FrameState::Stable {
+                return Ok(());
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        Err(Error::Timeout(timeout).into())
+    }
+
+    /// Wait until `region` of the screen, all of it for `None`, stops changing,
+    /// i.e. an animation in it is over: until its
+    /// [delta](cv::diff::region_delta) between two captures is at most
+    /// `threshold`. Fails with [`Error::Timeout`] if it does not within `timeout`.
+    pub fn wait_region_stable(
+        &self,
+        region: Option<image::math::Rect>,
+        threshold: f32,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        // Downsampled as by `FrameChangeDetector`, fine details do not matter
+        let capture = || -> anyhow::Result<_> {
+            let image = match region {
+                Some(rect) => self.screencap_region(rect)?,
+                None => self.screencap()?,
+            };
+            Ok(cv::diff::downsample(&image, 320))
+        };
+        let start = std::time::Instant::now();
+        let mut prev = capture()?;
+        while start.elapsed() < timeout {
+            std::thread::sleep(Duration::from_millis(100));
+            let cur = capture()?;
+            if cv::diff::mean_delta(&prev, &cur) <= threshold {
+                return Ok(());
+            }
+            prev = cur;
+        }
+        Err(Error::Timeout(timeout).into())
+    }
+
+    /// Run the task `name` of [`AutoPlay::resource`] with the parameters in `args`,
+    /// i.e. to farm another stage with the same task. See
+    /// [`task::Task::from_toml_with`].
+    pub fn run_task_with(
+        &self,
+        name: &str,
+        args: HashMap<String, task::Value>,
+    ) -> anyhow::Result<()> {
+        self.resource().task_with(name, &args)?.execute(self)
+    }
+
+    /// [`AutoPlay::run_task_with`] for the task file at `path`, which is not in
+    /// the resource.
+    pub fn run_task_file_with(
+        &self,
+        path: impl AsRef<std::path::Path>,
+        args: &HashMap<String, task::Value>,
+    ) -> anyhow::Result<()> {
+        task::Task::load_with(path, args)?.execute(self)
+    }
+
+    /// The problems of the task `name` of [`AutoPlay::resource`] found without
+    /// running it: missing templates and tasks, plugins not registered, points and
+    /// regions outside the screen... The tasks it runs by name are checked too.
+    ///
+    /// Its parameters take their defaults, see [`AutoPlay::validate_task_with`]
+    /// for a task with required ones.
+    pub fn validate_task(&self, name: &str) -> Vec<validate::Issue> {
+        self.validate_task_with(name, &HashMap::new())
+    }
+
+    /// [`AutoPlay::validate_task`] with the parameters in `args`.
+    pub fn validate_task_with(
+        &self,
+        name: &str,
+        args: &HashMap<String, task::Value>,
+    ) -> Vec<validate::Issue> {
+        self.validator().validate_named(name, args)
+    }
+
+    /// The problems of the edges of the navigation graph of [`AutoPlay::resource`],
+    /// see [`AutoPlay::validate_task`].
+    pub fn validate_nav(&self) -> Vec<validate::Issue> {
+        self.validator().validate_nav()
+    }
+
+    fn validator(&self) -> validate::Validator {
+        validate::Validator::new()
+            .with_screen_size(self.screen_size())
+            .with_plugins(self.plugins().names())
+            .with_resource(self.resource())
+    }
 }
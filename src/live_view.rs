@@ -0,0 +1,132 @@
+//! JPEG frames of the screen for a monitoring UI, with the recent clicks, swipes
+//! and matches drawn on them
+//!
+//! Frames come from the frame cache of the controller, so watching does not take
+//! screenshots of its own while a task is capturing anyway. The HTTP server of
+//! the `server` feature streams them over a WebSocket.
+//!
+//! ```ignore
+//! let mut view = LiveView::new(ap.clone(), LiveViewOptions::default());
+//! loop {
+//!     let jpeg = view.next_frame()?;
+//!     // send it to the UI
+//! }
+//! ```
+
+use std::{
+    sync::{Arc, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
+
+use ap_controller::capture::encode_jpeg;
+use image::imageops::FilterType;
+
+use crate::{AutoPlay, event::Event, recorder::Overlay};
+
+#[derive(Debug, Clone)]
+pub struct LiveViewOptions {
+    pub fps: u32,
+    /// JPEG quality, from 1 to 100
+    pub quality: u8,
+    /// Frames wider than this are scaled down
+    pub max_width: Option<u32>,
+}
+
+impl Default for LiveViewOptions {
+    fn default() -> Self {
+        Self {
+            fps: 5,
+            quality: 70,
+            max_width: Some(1280),
+        }
+    }
+}
+
+/// The screen of an [`AutoPlay`] at a steady frame rate, see the
+/// [module](self) docs.
+pub struct LiveView {
+    ap: Arc<AutoPlay>,
+    events: mpsc::Receiver<Event>,
+    overlay: Overlay,
+    options: LiveViewOptions,
+    start: Instant,
+    /// Frames returned so far
+    frames: u64,
+}
+
+impl LiveView {
+    pub fn new(ap: Arc<AutoPlay>, options: LiveViewOptions) -> Self {
+        Self {
+            events: ap.subscribe(),
+            ap,
+            overlay: Overlay::with_matches(),
+            options,
+            start: Instant::now(),
+            frames: 0,
+        }
+    }
+
+    /// Wait until the next frame is due and return it JPEG encoded. A frame that
+    /// took long to capture delays the next one rather than piling them up.
+    pub fn next_frame(&mut self) -> anyhow::Result<Vec<u8>> {
+        anyhow::ensure!(self.options.fps > 0, "fps should be greater than 0");
+        let interval = Duration::from_secs_f64(1.0 / self.options.fps as f64);
+        let due = interval.mul_f64(self.frames as f64);
+        thread::sleep(due.saturating_sub(self.start.elapsed()));
+        if self.start.elapsed() > due + interval {
+            self.frames = (self.start.elapsed().as_secs_f64() * self.options.fps as f64) as u64;
+        }
+        self.frames += 1;
+        self.capture()
+    }
+
+    /// The current frame JPEG encoded, without waiting.
+    pub fn capture(&mut self) -> anyhow::Result<Vec<u8>> {
+        let now = self.start.elapsed();
+        for event in self.events.try_iter() {
+            self.overlay.push(now, &event);
+        }
+        let mut frame = ap_controller::recorder::capture(self.ap.controller().as_ref())?;
+        self.overlay.draw(&mut frame, now);
+
+        let frame = match self.options.max_width {
+            Some(max_width) if frame.width() > max_width => {
+                let height = frame.height() as u64 * max_width as u64 / frame.width() as u64;
+                image::imageops::resize(&frame, max_width, height as u32, FilterType::Triangle)
+            }
+            _ => frame,
+        };
+        encode_jpeg(&frame, self.options.quality)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::DummyController;
+
+    #[test]
+    fn test_live_view() {
+        let ap = Arc::new(AutoPlay::new(DummyController::new(64, 32)));
+        let mut view = LiveView::new(
+            ap.clone(),
+            LiveViewOptions {
+                fps: 50,
+                quality: 90,
+                max_width: Some(32),
+            },
+        );
+        let frame = image::load_from_memory(&view.next_frame().unwrap()).unwrap();
+        let frame = frame.to_rgb8();
+        assert_eq!(frame.dimensions(), (32, 16));
+        let [r, g, b] = frame.get_pixel(16, 8).0;
+        assert!(r < 32 && g < 32 && b < 32);
+
+        // The click is drawn on the next frame, scaled down with it
+        ap.click(32, 16).unwrap();
+        let frame = image::load_from_memory(&view.next_frame().unwrap()).unwrap();
+        let [r, _, _] = frame.to_rgb8().get_pixel(16, 8).0;
+        assert!(r > 128);
+    }
+}
@@ -0,0 +1,760 @@
+//! Draft a task by doing it once: the taps, swipes and keys of the user are
+//! turned into steps, each tap into a [`ClickMatchTemplate`] of what was under
+//! it, cropped from the screen right before.
+//!
+//! The inputs come from an [`InputSource`]:
+//!
+//! - [`DesktopInput`]: the clicks and drags on the window, by polling the cursor
+//! - [`AndroidInput`]: the touches and the navigation keys of the device, read
+//!   with `getevent`
+//!
+//! ```ignore
+//! let ap = AutoPlay::new(AndroidController::connect(serial)?);
+//! let mut source = AndroidInput::start(&ap)?;
+//! let mut recorder = MacroRecorder::new("daily", "tasks/daily", MacroOptions::default());
+//! recorder.run(&ap, &mut source, &token)?;
+//! let path = recorder.finish()?; // tasks/daily/daily.toml
+//! ```
+//!
+//! The task is a draft: the templates are cropped with a guess at their size,
+//! and the pauses of the user become [`WaitAction`]s.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, BufReader, ErrorKind},
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use ap_adb::AdbTcpStream;
+use image::DynamicImage;
+use tracing::info;
+
+use crate::{
+    AndroidController, AutoPlay, CancellationToken,
+    action::{Action, Click, ClickMatchTemplate, Key, MatchStrategy, Swipe, WaitAction},
+    task::Task,
+};
+
+/// A touch moving further than this, in pixels of the screen, is a swipe
+const SWIPE_DISTANCE: u32 = 24;
+/// Frames kept to find the one before a tap
+const FRAMES: usize = 16;
+
+/// What the user did, in coordinates of the screen
+#[derive(Debug, Clone, PartialEq)]
+pub enum Input {
+    Tap {
+        x: u32,
+        y: u32,
+    },
+    Swipe {
+        start: (u32, u32),
+        end: (u32, u32),
+        duration: Duration,
+    },
+    Key(Key),
+}
+
+/// An [`Input`] and when it started, i.e. when the finger went down
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimedInput {
+    pub input: Input,
+    pub at: Instant,
+}
+
+/// Where a [`MacroRecorder`] gets the inputs of the user from
+pub trait InputSource {
+    /// The next input of the user, waiting at most `timeout` for it.
+    fn poll(&mut self, ap: &AutoPlay, timeout: Duration) -> anyhow::Result<Option<TimedInput>>;
+}
+
+/// Turn a press from `start` to `end` into a tap or a swipe.
+fn touch(start: (u32, u32), end: (u32, u32), duration: Duration) -> Input {
+    if start.0.abs_diff(end.0).max(start.1.abs_diff(end.1)) < SWIPE_DISTANCE {
+        Input::Tap {
+            x: start.0,
+            y: start.1,
+        }
+    } else {
+        Input::Swipe {
+            start,
+            end,
+            duration,
+        }
+    }
+}
+
+/// The clicks and drags of the left button on the window of a
+/// [`DesktopController`](crate::DesktopController), found by polling the cursor.
+/// The keys are not recorded, add them to the task by hand.
+#[cfg(any(feature = "windows", feature = "linux"))]
+#[derive(Default)]
+pub struct DesktopInput {
+    /// Where and when the button went down in the window
+    pressed: Option<((u32, u32), Instant)>,
+}
+
+#[cfg(any(feature = "windows", feature = "linux"))]
+impl DesktopInput {
+    /// Poll the cursor this often
+    const INTERVAL: Duration = Duration::from_millis(10);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(any(feature = "windows", feature = "linux"))]
+impl InputSource for DesktopInput {
+    fn poll(&mut self, ap: &AutoPlay, timeout: Duration) -> anyhow::Result<Option<TimedInput>> {
+        let deadline = Instant::now() + timeout;
+        let (width, height) = ap.screen_size();
+        loop {
+            let ((x, y), down) = ap
+                .with_controller(crate::DesktopController::pointer)
+                .ok_or_else(|| anyhow::anyhow!("not a desktop controller"))??;
+            // Clamped to the window once pressed in it, to drag out of it
+            let position = (
+                x.clamp(0, width as i32 - 1) as u32,
+                y.clamp(0, height as i32 - 1) as u32,
+            );
+            let inside = (0..width as i32).contains(&x) && (0..height as i32).contains(&y);
+            match (self.pressed, down) {
+                (None, true) if inside => self.pressed = Some((position, Instant::now())),
+                (Some((start, at)), false) => {
+                    self.pressed = None;
+                    let input = touch(start, position, at.elapsed());
+                    return Ok(Some(TimedInput { input, at }));
+                }
+                _ => {}
+            }
+            if Instant::now() >= deadline {
+                return Ok(None);
+            }
+            thread::sleep(Self::INTERVAL);
+        }
+    }
+}
+
+/// The range of an axis of an input device, from `getevent -p`
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct AxisRange {
+    min: i32,
+    max: i32,
+}
+
+impl AxisRange {
+    /// `value` from 0 to 1
+    fn normalize(self, value: i32) -> f32 {
+        let len = (self.max - self.min).max(1) as f32;
+        ((value - self.min) as f32 / len).clamp(0.0, 1.0)
+    }
+}
+
+/// A touch going on, in raw coordinates of its device
+#[derive(Debug, Clone)]
+struct Contact {
+    device: String,
+    at: Instant,
+    start: Option<(i32, i32)>,
+    released: bool,
+}
+
+/// Turns the lines of `getevent -lt` into [`TimedInput`]s: the touches of the
+/// first finger and the navigation keys.
+#[derive(Debug, Clone)]
+pub struct GeteventParser {
+    /// The ranges of the touch axes of each device, by path
+    axes: HashMap<String, (AxisRange, AxisRange)>,
+    /// The size of the screen as it is rotated
+    screen: (u32, u32),
+    /// Quarter turns of the display, as `SurfaceOrientation` in `dumpsys input`
+    orientation: u32,
+    slot: i32,
+    position: (Option<i32>, Option<i32>),
+    contact: Option<Contact>,
+}
+
+impl GeteventParser {
+    /// `description` is the output of `getevent -pl`, for the range of the
+    /// touch axes.
+    pub fn new(description: &str, screen: (u32, u32), orientation: u32) -> Self {
+        let mut axes = HashMap::new();
+        let mut device = None;
+        let mut x = None;
+        for line in description.lines() {
+            if let Some((_, path)) = line
+                .strip_prefix("add device")
+                .and_then(|l| l.split_once(':'))
+            {
+                device = Some(path.trim().to_string());
+                x = None;
+                continue;
+            }
+            let range = |code: &str| {
+                let (_, rest) = line.split_once(code)?;
+                let rest = rest.trim_start().strip_prefix(':')?;
+                let value = |name: &str| {
+                    rest.split(',')
+                        .find_map(|part| part.trim().strip_prefix(name))
+                        .and_then(|value| value.trim().parse().ok())
+                };
+                Some(AxisRange {
+                    min: value("min")?,
+                    max: value("max")?,
+                })
+            };
+            if let Some(range) = range("ABS_MT_POSITION_X") {
+                x = Some(range);
+            } else if let (Some(range), Some(x), Some(device)) =
+                (range("ABS_MT_POSITION_Y"), x, &device)
+            {
+                axes.insert(device.clone(), (x, range));
+            }
+        }
+        Self {
+            axes,
+            screen,
+            orientation: orientation % 4,
+            slot: 0,
+            position: (None, None),
+            contact: None,
+        }
+    }
+
+    /// Whether a touch screen was found in the description
+    pub fn has_touch_screen(&self) -> bool {
+        !self.axes.is_empty()
+    }
+
+    /// Raw coordinates of `device` to the screen.
+    fn to_screen(&self, device: &str, (x, y): (i32, i32)) -> Option<(u32, u32)> {
+        let (range_x, range_y) = self.axes.get(device)?;
+        let (x, y) = (range_x.normalize(x), range_y.normalize(y));
+        // The panel does not rotate with the display
+        let (x, y) = match self.orientation {
+            0 => (x, y),
+            1 => (y, 1.0 - x),
+            2 => (1.0 - x, 1.0 - y),
+            _ => (1.0 - y, x),
+        };
+        let scale = |v: f32, len: u32| ((v * len as f32) as u32).min(len.saturating_sub(1));
+        Some((scale(x, self.screen.0), scale(y, self.screen.1)))
+    }
+
+    /// Parse a line read at `now`, returns the input it completes.
+    pub fn feed(&mut self, line: &str, now: Instant) -> Option<TimedInput> {
+        // `[   1234.567890] /dev/input/event2: EV_ABS ABS_MT_POSITION_X 000001f4`
+        let line = line.split_once(']').map_or(line, |(_, rest)| rest);
+        let mut parts = line.split_whitespace();
+        let device = parts.next()?.strip_suffix(':')?;
+        let (_, code, value) = (parts.next()?, parts.next()?, parts.next()?);
+        let number = || u32::from_str_radix(value, 16).ok().map(|v| v as i32);
+
+        match code {
+            "ABS_MT_SLOT" => self.slot = number()?,
+            // Only the first finger is followed
+            _ if self.slot != 0 => {}
+            "ABS_MT_POSITION_X" => self.position.0 = number(),
+            "ABS_MT_POSITION_Y" => self.position.1 = number(),
+            "ABS_MT_TRACKING_ID" if number()? == -1 => self.release(),
+            "ABS_MT_TRACKING_ID" => self.press(device, now),
+            "BTN_TOUCH" if value == "DOWN" => self.press(device, now),
+            "BTN_TOUCH" => self.release(),
+            "SYN_REPORT" => return self.report(),
+            _ if value == "DOWN" => {
+                let key = match code {
+                    "KEY_BACK" => Key::Back,
+                    "KEY_HOMEPAGE" | "KEY_HOME" => Key::HomeScreen,
+                    "KEY_APPSELECT" => Key::AppSwitch,
+                    "KEY_MENU" => Key::Menu,
+                    "KEY_POWER" => Key::Power,
+                    "KEY_VOLUMEUP" => Key::VolumeUp,
+                    "KEY_VOLUMEDOWN" => Key::VolumeDown,
+                    _ => return None,
+                };
+                return Some(TimedInput {
+                    input: Input::Key(key),
+                    at: now,
+                });
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn press(&mut self, device: &str, now: Instant) {
+        if self.contact.is_none() {
+            self.contact = Some(Contact {
+                device: device.to_string(),
+                at: now,
+                start: None,
+                released: false,
+            });
+        }
+    }
+
+    fn release(&mut self) {
+        if let Some(contact) = &mut self.contact {
+            contact.released = true;
+        }
+    }
+
+    /// A frame of events is complete, with the positions of the touch.
+    fn report(&mut self) -> Option<TimedInput> {
+        let contact = self.contact.as_mut()?;
+        let position = match self.position {
+            (Some(x), Some(y)) => Some((x, y)),
+            _ => None,
+        };
+        if contact.start.is_none() {
+            contact.start = position;
+        }
+        if !contact.released {
+            return None;
+        }
+        let contact = self.contact.take()?;
+        let start = self.to_screen(&contact.device, contact.start?)?;
+        let end = self.to_screen(&contact.device, position?)?;
+        Some(TimedInput {
+            input: touch(start, end, contact.at.elapsed()),
+            at: contact.at,
+        })
+    }
+}
+
+/// The touches and the navigation keys of an Android device, read from
+/// `getevent` on a background thread until dropped.
+pub struct AndroidInput {
+    inputs: mpsc::Receiver<anyhow::Result<TimedInput>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl AndroidInput {
+    /// Start reading the inputs of the device of `ap`, an [`AndroidController`].
+    pub fn start(ap: &AutoPlay) -> anyhow::Result<Self> {
+        let screen = ap.screen_size();
+        let (parser, stream) = ap
+            .with_controller(|android: &AndroidController| {
+                let device = android.device();
+                let description = device.shell("getevent -pl")?.stdout;
+                let orientation = device
+                    .shell("dumpsys input")
+                    .ok()
+                    .and_then(|output| {
+                        output.stdout.lines().find_map(|line| {
+                            line.trim()
+                                .strip_prefix("SurfaceOrientation:")
+                                .and_then(|n| n.trim().parse().ok())
+                        })
+                    })
+                    .unwrap_or(0);
+                let parser = GeteventParser::new(&description, screen, orientation);
+                anyhow::ensure!(parser.has_touch_screen(), "the device has no touch screen");
+                let stream = device.exec("getevent -lt")?;
+                // Checks to stop now and then
+                stream.set_read_timeout(Some(Duration::from_millis(200)))?;
+                Ok((parser, stream))
+            })
+            .ok_or_else(|| anyhow::anyhow!("not an android controller"))??;
+
+        let (tx, inputs) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+        {
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("getevent".to_string())
+                .spawn(move || {
+                    if let Err(err) = read_getevent(stream, parser, &tx, &stop) {
+                        let _ = tx.send(Err(err));
+                    }
+                })?;
+        }
+        Ok(Self { inputs, stop })
+    }
+}
+
+/// Send the inputs of the lines of `stream` until `stop` is set or the receiver
+/// is dropped.
+fn read_getevent(
+    stream: AdbTcpStream,
+    mut parser: GeteventParser,
+    tx: &mpsc::Sender<anyhow::Result<TimedInput>>,
+    stop: &AtomicBool,
+) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    while !stop.load(Ordering::Relaxed) {
+        match reader.read_line(&mut line) {
+            Ok(0) => anyhow::bail!("getevent exited"),
+            Ok(_) => {
+                if let Some(input) = parser.feed(&line, Instant::now())
+                    && tx.send(Ok(input)).is_err()
+                {
+                    break;
+                }
+                line.clear();
+            }
+            // What was read so far stays in `line`
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+    Ok(())
+}
+
+impl InputSource for AndroidInput {
+    fn poll(&mut self, _ap: &AutoPlay, timeout: Duration) -> anyhow::Result<Option<TimedInput>> {
+        match self.inputs.recv_timeout(timeout) {
+            Ok(input) => input.map(Some),
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => anyhow::bail!("getevent stopped"),
+        }
+    }
+}
+
+impl Drop for AndroidInput {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+pub struct MacroOptions {
+    /// Sizes of the square templates tried around a tap, the smallest one with
+    /// enough detail is used and the others are saved next to it
+    pub template_sizes: Vec<u32>,
+    /// The standard deviation of the brightness a template needs, from 0 to 255
+    pub min_contrast: f32,
+    /// How often the screen is captured while waiting for the user
+    pub frame_interval: Duration,
+    /// Pauses shorter than this are not waited for
+    pub min_wait: Duration,
+}
+
+impl Default for MacroOptions {
+    fn default() -> Self {
+        Self {
+            template_sizes: vec![48, 96, 160],
+            min_contrast: 16.0,
+            frame_interval: Duration::from_millis(200),
+            min_wait: Duration::from_millis(300),
+        }
+    }
+}
+
+/// Builds a task from inputs, see the [module](self) documentation.
+pub struct MacroRecorder {
+    name: String,
+    dir: PathBuf,
+    options: MacroOptions,
+    steps: Vec<Box<dyn Action>>,
+    /// The latest screens and when they were captured, the oldest first
+    frames: VecDeque<(Instant, Arc<DynamicImage>)>,
+    /// When the last input was
+    last: Option<Instant>,
+}
+
+impl MacroRecorder {
+    /// Record the task `name`, written with its templates to `dir`.
+    pub fn new(name: impl Into<String>, dir: impl Into<PathBuf>, options: MacroOptions) -> Self {
+        Self {
+            name: name.into(),
+            dir: dir.into(),
+            options,
+            steps: Vec::new(),
+            frames: VecDeque::new(),
+            last: None,
+        }
+    }
+
+    pub fn steps(&self) -> &[Box<dyn Action>] {
+        &self.steps
+    }
+
+    /// Record the inputs of `source` on `ap` until `token` is cancelled.
+    pub fn run(
+        &mut self,
+        ap: &AutoPlay,
+        source: &mut dyn InputSource,
+        token: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        while !token.is_cancelled() {
+            self.push_frame(Instant::now(), ap.screencap()?);
+            if let Some(input) = source.poll(ap, self.options.frame_interval)? {
+                info!("recorded {:?}", input.input);
+                self.record(input)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Keep a screen captured at `at`, to crop the templates of the taps after it.
+    pub fn push_frame(&mut self, at: Instant, frame: DynamicImage) {
+        if self.frames.len() == FRAMES {
+            self.frames.pop_front();
+        }
+        self.frames.push_back((at, Arc::new(frame)));
+    }
+
+    /// The last screen captured before `at`, or else the oldest one.
+    fn frame_before(&self, at: Instant) -> Option<Arc<DynamicImage>> {
+        self.frames
+            .iter()
+            .rev()
+            .find(|(captured, _)| *captured <= at)
+            .or(self.frames.front())
+            .map(|(_, frame)| frame.clone())
+    }
+
+    /// Add the steps of `input`: a wait for the pause before it and the input.
+    pub fn record(&mut self, input: TimedInput) -> anyhow::Result<()> {
+        if let Some(last) = self.last {
+            let pause = input.at.saturating_duration_since(last);
+            if pause >= self.options.min_wait {
+                // Rounded to a tenth of a second
+                let ms = (pause.as_millis() as u64).div_ceil(100) * 100;
+                self.steps.push(Box::new(WaitAction { ms }));
+            }
+        }
+        self.last = Some(input.at);
+
+        let step: Box<dyn Action> = match input.input {
+            Input::Tap { x, y } => {
+                let step = match self.frame_before(input.at) {
+                    Some(frame) => self.template_step(&frame, x, y)?,
+                    None => None,
+                };
+                match step {
+                    Some(step) => Box::new(step),
+                    None => Box::new(Click { at: (x, y).into() }),
+                }
+            }
+            Input::Swipe {
+                start,
+                end,
+                duration,
+            } => Box::new(Swipe {
+                start: start.into(),
+                end: end.into(),
+                duration,
+                slope_in: 1.0,
+                slope_out: 1.0,
+            }),
+            Input::Key(key) => Box::new(key.press()),
+        };
+        self.steps.push(step);
+        Ok(())
+    }
+
+    /// Crop the templates around `(x, y)` on `frame` and click the one chosen,
+    /// `None` on the edge of the screen where there is nothing around it.
+    fn template_step(
+        &self,
+        frame: &DynamicImage,
+        x: u32,
+        y: u32,
+    ) -> anyhow::Result<Option<ClickMatchTemplate>> {
+        let candidates = crop_candidates(frame, x, y, &self.options.template_sizes);
+        if candidates.is_empty() {
+            return Ok(None);
+        }
+        let dir = self.dir.join("templates");
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let index = self.steps.len() + 1;
+        let chosen = candidates
+            .iter()
+            .position(|template| contrast(template) >= self.options.min_contrast)
+            .unwrap_or(candidates.len() - 1);
+
+        let template = dir.join(format!("step_{index}.png"));
+        for (i, candidate) in candidates.iter().enumerate() {
+            let path = if i == chosen {
+                template.clone()
+            } else {
+                dir.join(format!("step_{index}_{}.png", candidate.width()))
+            };
+            candidate
+                .save(&path)
+                .with_context(|| format!("failed to save {}", path.display()))?;
+        }
+        Ok(Some(ClickMatchTemplate {
+            template,
+            threshold: None,
+            region: None,
+            strategy: MatchStrategy::Template,
+            cache: false,
+            method: None,
+            offset: None,
+            nth: None,
+        }))
+    }
+
+    /// Write the task to `{dir}/{name}.toml` and return its path.
+    pub fn finish(self) -> anyhow::Result<PathBuf> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        let path = self.dir.join(format!("{}.toml", self.name));
+        let task = Task::new(self.name, self.steps);
+        let source = toml::to_string(&task).context("failed to serialize the task")?;
+        std::fs::write(&path, source)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// Squares of `sizes` centered on `(x, y)`, shrunk to fit in `frame` so the
+/// center stays where the click is. The sizes that do not fit are skipped.
+fn crop_candidates(frame: &DynamicImage, x: u32, y: u32, sizes: &[u32]) -> Vec<DynamicImage> {
+    let (width, height) = (frame.width(), frame.height());
+    if x >= width || y >= height {
+        return Vec::new();
+    }
+    let max_half = x.min(y).min(width - x).min(height - y);
+    let mut halves = sizes
+        .iter()
+        .map(|size| (size / 2).min(max_half))
+        .filter(|half| *half > 0)
+        .collect::<Vec<_>>();
+    halves.dedup();
+    halves
+        .into_iter()
+        .map(|half| frame.crop_imm(x - half, y - half, half * 2, half * 2))
+        .collect()
+}
+
+/// The standard deviation of the brightness of `image`
+fn contrast(image: &DynamicImage) -> f32 {
+    let luma = image.to_luma8();
+    let len = luma.len().max(1) as f32;
+    let mean = luma.iter().map(|v| *v as f32).sum::<f32>() / len;
+    let variance = luma.iter().map(|v| (*v as f32 - mean).powi(2)).sum::<f32>() / len;
+    variance.sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    const DESCRIPTION: &str = "\
+add device 1: /dev/input/event1
+  name:     \"gpio-keys\"
+  events:
+    KEY (0001): KEY_VOLUMEDOWN        KEY_VOLUMEUP          KEY_POWER
+add device 2: /dev/input/event2
+  name:     \"touchscreen\"
+  events:
+    ABS (0003): ABS_MT_SLOT           : value 0, min 0, max 9, fuzz 0, flat 0, resolution 0
+                ABS_MT_POSITION_X     : value 0, min 0, max 1079, fuzz 0, flat 0, resolution 0
+                ABS_MT_POSITION_Y     : value 0, min 0, max 2399, fuzz 0, flat 0, resolution 0
+                ABS_MT_TRACKING_ID    : value 0, min 0, max 65535, fuzz 0, flat 0, resolution 0
+";
+
+    fn feed(parser: &mut GeteventParser, lines: &str) -> Vec<Input> {
+        let now = Instant::now();
+        lines
+            .lines()
+            .filter_map(|line| parser.feed(line, now))
+            .map(|input| input.input)
+            .collect()
+    }
+
+    #[test]
+    fn test_getevent() {
+        let mut parser = GeteventParser::new(DESCRIPTION, (1080, 2400), 0);
+        assert!(parser.has_touch_screen());
+        let tap = "\
+[   10.000000] /dev/input/event2: EV_ABS       ABS_MT_TRACKING_ID   00000001
+[   10.000000] /dev/input/event2: EV_ABS       ABS_MT_POSITION_X    000001f4
+[   10.000000] /dev/input/event2: EV_ABS       ABS_MT_POSITION_Y    00000320
+[   10.000000] /dev/input/event2: EV_KEY       BTN_TOUCH            DOWN
+[   10.000000] /dev/input/event2: EV_SYN       SYN_REPORT           00000000
+[   10.050000] /dev/input/event2: EV_ABS       ABS_MT_POSITION_X    000001f6
+[   10.050000] /dev/input/event2: EV_SYN       SYN_REPORT           00000000
+[   10.100000] /dev/input/event2: EV_ABS       ABS_MT_TRACKING_ID   ffffffff
+[   10.100000] /dev/input/event2: EV_KEY       BTN_TOUCH            UP
+[   10.100000] /dev/input/event2: EV_SYN       SYN_REPORT           00000000
+";
+        assert_eq!(feed(&mut parser, tap), [Input::Tap { x: 500, y: 800 }]);
+
+        let swipe = "\
+[   11.000000] /dev/input/event2: EV_ABS       ABS_MT_TRACKING_ID   00000002
+[   11.000000] /dev/input/event2: EV_SYN       SYN_REPORT           00000000
+[   11.050000] /dev/input/event2: EV_ABS       ABS_MT_POSITION_Y    000000c8
+[   11.050000] /dev/input/event2: EV_SYN       SYN_REPORT           00000000
+[   11.100000] /dev/input/event2: EV_ABS       ABS_MT_TRACKING_ID   ffffffff
+[   11.100000] /dev/input/event2: EV_SYN       SYN_REPORT           00000000
+[   12.000000] /dev/input/event1: EV_KEY       KEY_BACK             DOWN
+[   12.000000] /dev/input/event1: EV_SYN       SYN_REPORT           00000000
+[   12.050000] /dev/input/event1: EV_KEY       KEY_BACK             UP
+";
+        let inputs = feed(&mut parser, swipe);
+        assert!(
+            matches!(inputs[0], Input::Swipe { start, end, .. } if start == (502, 800) && end == (502, 200))
+        );
+        assert_eq!(inputs[1..], [Input::Key(Key::Back)]);
+
+        // A landscape game, the panel is still in portrait
+        let mut parser = GeteventParser::new(DESCRIPTION, (2400, 1080), 1);
+        assert_eq!(feed(&mut parser, tap), [Input::Tap { x: 800, y: 579 }]);
+    }
+
+    #[test]
+    fn test_macro_recorder() {
+        let dir = std::env::temp_dir().join(format!("ap-macro-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut screen = RgbImage::from_pixel(320, 240, Rgb([96, 96, 96]));
+        for y in 80..160 {
+            for x in 40..120 {
+                screen.put_pixel(x, y, Rgb([240, 200, 40]));
+            }
+        }
+
+        let mut recorder = MacroRecorder::new("daily", &dir, MacroOptions::default());
+        let start = Instant::now();
+        recorder.push_frame(start, DynamicImage::ImageRgb8(screen));
+        let at = |ms| start + Duration::from_millis(ms);
+        let inputs = [
+            // The smallest template is all button
+            Input::Tap { x: 80, y: 120 },
+            // Too close to the edge of the screen for a template
+            Input::Tap { x: 0, y: 10 },
+            Input::Key(Key::Back),
+        ];
+        for (ms, input) in [0, 1000, 1100].into_iter().zip(inputs) {
+            recorder.record(TimedInput { input, at: at(ms) }).unwrap();
+        }
+        let path = recorder.finish().unwrap();
+
+        let task = Task::load(&path).unwrap();
+        assert_eq!(task.name, "daily");
+        let source = std::fs::read_to_string(&path).unwrap();
+        let steps = toml::from_str::<toml::Table>(&source).unwrap()["steps"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|step| step.as_table().unwrap().keys().next().unwrap().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            steps,
+            ["ClickMatchTemplate", "WaitAction", "Click", "Press"]
+        );
+
+        let template = image::open(dir.join("templates/step_1.png")).unwrap();
+        assert_eq!(template.width(), 96);
+        assert!(dir.join("templates/step_1_48.png").exists());
+        assert!(dir.join("templates/step_1_160.png").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
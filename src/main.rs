@@ -0,0 +1,858 @@
+//! Command line interface of auto-play, built with the `cli` feature
+//!
+//! ```text
+//! auto-play devices
+//! auto-play screencap --serial 127.0.0.1:16384 -o screen.png
+//! auto-play run --serial 127.0.0.1:16384 daily.toml --record daily.mp4
+//! auto-play run --serial 127.0.0.1:16384 daily.toml --report daily.html --logcat '*:E'
+//! auto-play run --serial 127.0.0.1:16384 farm.toml --arg stage=1-7 --arg times=5
+//! auto-play run --serial 127.0.0.1:16384 daily --resource resources --json
+//! auto-play run --serial 127.0.0.1:16384 daily.toml --debug-bundle bugs --redact
+//! auto-play bench --serial 127.0.0.1:16384 button.png
+//! auto-play validate tasks/*.toml
+//! auto-play draft --serial 127.0.0.1:16384 daily -o tasks/daily
+//! ```
+
+use std::{
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+#[cfg(feature = "update")]
+use auto_play::update::{Manifest, Source, Updater};
+use auto_play::{
+    AndroidController, AutoPlay, CancellationToken,
+    adb::host,
+    bench::{
+        BenchConfig, CaptureMethod, LatencyReport, MatchBackend, ProbeOptions, bench_all,
+        probe_latency,
+    },
+    debug_bundle::{DebugBundleOptions, RecentLogs, Redaction},
+    guard::{BatteryGuard, GuardOptions, ResumePolicy},
+    macro_recorder::{AndroidInput, InputSource, MacroOptions, MacroRecorder},
+    recorder::{Recorder, RecorderOptions},
+    resource::Resource,
+    task::Task,
+    telemetry::{MetricsExporter, RunMetrics},
+    validate::Validator,
+};
+use clap::{Args, Parser, Subcommand};
+use tracing::{error, info};
+use tracing_subscriber::prelude::*;
+
+/// How many log lines go in a debug bundle
+const DEBUG_BUNDLE_LOG_LINES: usize = 200;
+
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Print the results as JSON rather than text, for scripts
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the devices known to the adb server
+    Devices,
+    /// List the windows that can be controlled
+    Windows,
+    /// Take a screenshot
+    Screencap {
+        #[command(flatten)]
+        target: Target,
+        /// Where to save the screenshot
+        #[arg(short, long, default_value = "screencap.png")]
+        output: PathBuf,
+    },
+    /// Run a task file, or a task of the resource directory by name
+    Run {
+        #[command(flatten)]
+        target: Target,
+        task: String,
+        /// Load the tasks of this directory, the task can then be one of them
+        #[arg(long)]
+        resource: Option<PathBuf>,
+        /// A parameter of the task as `name=value`, the value is parsed as TOML
+        /// or taken as a string
+        #[arg(long = "arg", value_name = "NAME=VALUE", value_parser = parse_arg)]
+        args: Vec<(String, toml::Value)>,
+        /// Record the run to an MP4 with the actions drawn on it
+        #[arg(long)]
+        record: Option<PathBuf>,
+        /// Publish the captured frames to this shared-memory region
+        #[arg(long)]
+        publish: Option<String>,
+        /// Write an HTML report of the run, with a screenshot of every step, or a
+        /// JSON one if it ends with `.json`
+        #[arg(long)]
+        report: Option<PathBuf>,
+        /// Append the metrics of the run to this file as a line of JSON
+        #[arg(long)]
+        metrics: Option<PathBuf>,
+        /// Put the end of the logcat matching this filter in the report if the
+        /// run fails, i.e. `*:E`
+        #[arg(long, value_name = "FILTER")]
+        logcat: Option<String>,
+        /// Pause the run while the battery is below this percentage, until it
+        /// charged back to twice as much
+        #[arg(long, value_name = "PERCENT")]
+        min_battery: Option<u32>,
+        /// Pause the run while the battery is hotter than this, until it cooled
+        /// down by 5°C
+        #[arg(long, value_name = "CELSIUS")]
+        max_temperature: Option<f32>,
+        /// Write a zip with the screen, the templates, the log and the device
+        /// to this directory if a step fails, to attach to an issue
+        #[arg(long, value_name = "DIR")]
+        debug_bundle: Option<PathBuf>,
+        /// Hide the serial of the device from the debug bundle
+        #[arg(long)]
+        redact: bool,
+        /// Hide this text from the debug bundle too, i.e. the name of an account
+        #[arg(long, value_name = "TEXT")]
+        redact_text: Vec<String>,
+    },
+    /// Record the screen, to an MP4 if the output ends with `.mp4`, or else
+    /// as PNG frames in a directory
+    Record {
+        #[command(flatten)]
+        target: Target,
+        #[arg(short, long, default_value = "record")]
+        output: PathBuf,
+        /// Milliseconds between two frames
+        #[arg(long, default_value_t = 500)]
+        interval: u64,
+        /// Stop after this many seconds, record until interrupted if not set
+        #[arg(long)]
+        duration: Option<u64>,
+    },
+    /// Measure the screencap → match → click loop with every capture method and
+    /// matching backend
+    Bench {
+        #[command(flatten)]
+        target: Target,
+        /// Image to look for on the screen
+        template: PathBuf,
+        #[arg(long, default_value_t = 10)]
+        iterations: usize,
+        /// Click the match on every iteration
+        #[arg(long)]
+        click: bool,
+    },
+    /// Measure the p50 and p95 latency of every capture method, matching
+    /// backend and input method on its own, to choose backends or spot
+    /// regressions
+    Latency {
+        #[command(flatten)]
+        target: Target,
+        #[arg(long, default_value_t = 20)]
+        samples: usize,
+        /// Also measure the inputs by tapping there, which is left alone otherwise
+        #[arg(long, value_name = "X,Y", value_parser = parse_point)]
+        tap: Option<(u32, u32)>,
+        /// Save the report as JSON, to compare a later one with it
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// A report saved earlier, fail if a stage got slower than in it
+        #[arg(long)]
+        baseline: Option<PathBuf>,
+        /// How much slower a stage may get than in the baseline, in percent
+        #[arg(long, default_value_t = 20.0)]
+        tolerance: f64,
+    },
+    /// Check that task files can be loaded and that the templates they use
+    /// exist, without a device
+    Validate {
+        #[arg(required = true)]
+        tasks: Vec<PathBuf>,
+    },
+    /// Record the taps, swipes and keys of the user into a draft task, with
+    /// templates cropped around the taps, until Enter is pressed
+    Draft {
+        #[command(flatten)]
+        target: Target,
+        /// Name of the task
+        name: String,
+        /// Directory of the task and its templates, named after the task if not set
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Write the manifest of a resource pack, to publish it
+    #[cfg(feature = "update")]
+    Manifest { dir: PathBuf },
+    /// Update a resource directory from a published pack, an HTTP URL, a git
+    /// repository or a directory
+    #[cfg(feature = "update")]
+    Update { source: String, dir: PathBuf },
+    /// Run a task on several Android devices at once, following them on a
+    /// dashboard
+    #[cfg(feature = "dashboard")]
+    Dashboard {
+        task: PathBuf,
+        /// Serial of an Android device, all the devices of the adb server if
+        /// not set
+        #[arg(short, long)]
+        serial: Vec<String>,
+    },
+    /// Serve the devices over HTTP, to drive them remotely
+    #[cfg(feature = "server")]
+    Serve {
+        #[arg(long, default_value = "127.0.0.1:7878")]
+        addr: String,
+        /// Serial of an Android device to connect to from the start, more can
+        /// be connected with `POST /devices`
+        #[arg(short, long)]
+        serial: Vec<String>,
+    },
+}
+
+/// The device or window to control
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct Target {
+    /// Serial of an Android device
+    #[arg(short, long)]
+    serial: Option<String>,
+    /// Exact title of a window
+    #[arg(short, long)]
+    window: Option<String>,
+    /// A registered controller backend and its target, as `backend:target`,
+    /// i.e. `android:emulator-5554`
+    #[arg(long, value_name = "BACKEND:TARGET")]
+    controller: Option<String>,
+}
+
+impl Target {
+    fn connect(&self) -> anyhow::Result<AutoPlay> {
+        if let Some(spec) = &self.controller {
+            info!("connecting to {spec}...");
+            return Ok(AutoPlay::new(
+                auto_play::controller::registry::connect_spec(spec)?,
+            ));
+        }
+        if let Some(serial) = &self.serial {
+            info!("connecting to {serial}...");
+            return Ok(AutoPlay::new(AndroidController::connect(serial)?));
+        }
+        let title = self.window.as_deref().unwrap_or_default();
+        connect_window(title)
+    }
+}
+
+#[cfg(any(feature = "windows", feature = "linux", target_os = "macos"))]
+fn connect_window(title: &str) -> anyhow::Result<AutoPlay> {
+    info!("attaching to window {title}...");
+    Ok(AutoPlay::new(
+        auto_play::DesktopController::from_window_title(title)?,
+    ))
+}
+
+#[cfg(not(any(feature = "windows", feature = "linux", target_os = "macos")))]
+fn connect_window(_title: &str) -> anyhow::Result<AutoPlay> {
+    anyhow::bail!("auto-play is built without the `windows` or `linux` feature")
+}
+
+#[cfg(any(feature = "windows", feature = "linux", target_os = "macos"))]
+fn windows(json: bool) -> anyhow::Result<()> {
+    let titles = auto_play::DesktopController::window_titles()?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&titles)?);
+    } else {
+        for title in titles {
+            println!("{title}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(any(feature = "windows", feature = "linux", target_os = "macos")))]
+fn windows(_json: bool) -> anyhow::Result<()> {
+    anyhow::bail!("auto-play is built without the `windows` or `linux` feature")
+}
+
+fn devices(json: bool) -> anyhow::Result<()> {
+    let devices = host::connect_default()?.devices_long()?;
+    if json {
+        let devices = devices
+            .iter()
+            .map(|device| {
+                serde_json::json!({
+                    "serial": device.serial,
+                    "state": device.state,
+                    "info": device.info,
+                })
+            })
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&devices)?);
+        return Ok(());
+    }
+    if devices.is_empty() {
+        println!("no devices");
+    }
+    for device in devices {
+        println!(
+            "{}\t{}\t{}",
+            device.serial,
+            device.state,
+            device.model().unwrap_or("-")
+        );
+    }
+    Ok(())
+}
+
+fn screencap(ap: &AutoPlay, output: &Path, json: bool) -> anyhow::Result<()> {
+    let screen = ap.screencap()?;
+    screen
+        .save(output)
+        .with_context(|| format!("failed to save {}", output.display()))?;
+    if json {
+        let saved = serde_json::json!({
+            "path": output,
+            "width": screen.width(),
+            "height": screen.height(),
+        });
+        println!("{}", serde_json::to_string_pretty(&saved)?);
+    } else {
+        println!("saved to {}", output.display());
+    }
+    Ok(())
+}
+
+/// The task file at `task`, or else the task named `task` in `resource`, which
+/// is returned to be set on the device for the tasks it runs by name. Fails if the
+/// task has [issues](Validator), before anything is connected.
+fn load_task(
+    task: &str,
+    resource: Option<&Path>,
+    args: Vec<(String, toml::Value)>,
+) -> anyhow::Result<(Arc<Task>, Option<Resource>)> {
+    let resource = resource.map(Resource::load).transpose()?;
+    let args = args.into_iter().collect();
+    let loaded = match &resource {
+        Some(resource) if !Path::new(task).is_file() => Arc::new(resource.task_with(task, &args)?),
+        _ => Arc::new(Task::load_with(task, &args)?),
+    };
+    let issues = Validator::new().validate(&loaded);
+    anyhow::ensure!(
+        issues.is_empty(),
+        "{task} is invalid:\n{}",
+        issues
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+    Ok((loaded, resource))
+}
+
+fn parse_arg(arg: &str) -> Result<(String, toml::Value), String> {
+    let (name, value) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got {arg:?}"))?;
+    let value = toml::from_str::<toml::Table>(&format!("value = {value}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()));
+    Ok((name.to_string(), value))
+}
+
+fn parse_point(point: &str) -> Result<(u32, u32), String> {
+    point
+        .split_once(',')
+        .and_then(|(x, y)| Some((x.trim().parse().ok()?, y.trim().parse().ok()?)))
+        .ok_or_else(|| format!("expected X,Y, got {point:?}"))
+}
+
+/// What to produce besides running a task
+struct RunOutput<'a> {
+    record: Option<&'a Path>,
+    publish: Option<&'a str>,
+    report: Option<&'a Path>,
+    /// Where to append the metrics, with the device they are tagged with
+    metrics: Option<(&'a Path, Option<&'a str>)>,
+    /// Print the JSON report
+    json: bool,
+}
+
+fn run(
+    ap: AutoPlay,
+    task: &Task,
+    output: &RunOutput,
+    guard: Option<GuardOptions>,
+) -> anyhow::Result<()> {
+    let RunOutput {
+        record,
+        publish,
+        report,
+        metrics,
+        json,
+    } = *output;
+    if let Some(name) = publish {
+        let path = ap.publish_frames(name)?;
+        info!("publishing frames to {}", path.display());
+    }
+    let ap = Arc::new(ap);
+    let recorder = record
+        .map(|path| Recorder::start(ap.clone(), path, RecorderOptions::default()))
+        .transpose()?;
+    let token = CancellationToken::new();
+    let guard = guard.map(|options| BatteryGuard::start(ap.clone(), token.clone(), options));
+    info!("running {}...", task.name);
+    let res = if report.is_some() || metrics.is_some() || json {
+        // The JSON report has no screenshots
+        let html = report.is_some_and(|path| path.extension().is_none_or(|ext| ext != "json"));
+        let events = ap.subscribe();
+        let execution = task.execute_with_report_cancellable(&ap, html, &token);
+        if let Some((path, device)) = metrics {
+            let events = events.try_iter().collect::<Vec<_>>();
+            let mut run_metrics = RunMetrics::new(&execution, &events);
+            if let Some(device) = device {
+                run_metrics = run_metrics.with_device(device);
+            }
+            MetricsExporter::open(path)?.export(&run_metrics)?;
+            info!("metrics appended to {}", path.display());
+        }
+        if let Some(path) = report {
+            if html {
+                execution.write_html(path)?;
+            } else {
+                execution.write_json(path)?;
+            }
+            info!("report written to {}", path.display());
+        }
+        if json {
+            println!("{}", execution.to_json()?);
+        }
+        match execution.error {
+            Some(error) => Err(anyhow::anyhow!(error)),
+            None => Ok(()),
+        }
+    } else {
+        task.execute_cancellable(&ap, &token)
+    };
+    if let Some(guard) = guard {
+        guard.stop();
+    }
+    if let Some(recorder) = recorder {
+        recorder.stop()?;
+        info!("recorded to {}", record.unwrap().display());
+    }
+    res
+}
+
+fn record(
+    ap: AutoPlay,
+    output: &Path,
+    interval: Duration,
+    duration: Option<Duration>,
+) -> anyhow::Result<()> {
+    if output.extension().is_some_and(|ext| ext == "mp4") {
+        let duration =
+            duration.ok_or_else(|| anyhow::anyhow!("--duration is required to record an MP4"))?;
+        let options = RecorderOptions {
+            fps: (1000 / interval.as_millis().max(1)).max(1) as u32,
+            ..Default::default()
+        };
+        let recorder = Recorder::start(Arc::new(ap), output, options)?;
+        thread::sleep(duration);
+        recorder.stop()?;
+        println!("recorded to {}", output.display());
+        return Ok(());
+    }
+
+    std::fs::create_dir_all(output)
+        .with_context(|| format!("failed to create {}", output.display()))?;
+    let start = Instant::now();
+    let mut idx = 0;
+    while duration.is_none_or(|duration| start.elapsed() < duration) {
+        let frame_start = Instant::now();
+        let path = output.join(format!("{idx:06}.png"));
+        ap.screencap()?.save(&path)?;
+        idx += 1;
+        thread::sleep(interval.saturating_sub(frame_start.elapsed()));
+    }
+    println!("recorded {idx} frames to {}", output.display());
+    Ok(())
+}
+
+fn bench(ap: &AutoPlay, template: &Path, iterations: usize, click: bool) -> anyhow::Result<()> {
+    let template =
+        image::open(template).with_context(|| format!("failed to open {}", template.display()))?;
+    let mut configs = Vec::new();
+    for capture in [
+        CaptureMethod::Raw,
+        CaptureMethod::Decoded,
+        CaptureMethod::AdbPng,
+        CaptureMethod::AdbProcess,
+    ] {
+        for backend in [MatchBackend::Gpu, MatchBackend::Cpu] {
+            configs.push(
+                BenchConfig::new(capture, backend)
+                    .with_iterations(iterations)
+                    .with_click(click),
+            );
+        }
+    }
+    println!("config\tcapture\tpreprocess\tmatch\tclick\ttotal\tp95\tmatched");
+    let ms = |d: Duration| format!("{:.1}ms", d.as_secs_f64() * 1000.0);
+    for (config, res) in bench_all(ap, &template, &configs) {
+        match res {
+            Ok(res) => {
+                let mean = res.mean();
+                println!(
+                    "{config}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    ms(mean.capture),
+                    ms(mean.preprocess),
+                    ms(mean.matching),
+                    ms(mean.click),
+                    ms(mean.total),
+                    ms(res.percentile(0.95)),
+                    mean.matched
+                );
+            }
+            Err(err) => println!("{config}\terror: {err:#}"),
+        }
+    }
+    Ok(())
+}
+
+fn latency(
+    ap: &AutoPlay,
+    options: &ProbeOptions,
+    output: Option<&Path>,
+    baseline: Option<&Path>,
+    tolerance: f64,
+    json: bool,
+) -> anyhow::Result<()> {
+    let report = probe_latency(ap, options)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{report}");
+    }
+    if let Some(output) = output {
+        std::fs::write(output, serde_json::to_string_pretty(&report)?)
+            .with_context(|| format!("failed to write {}", output.display()))?;
+    }
+    if let Some(baseline) = baseline {
+        let baseline: LatencyReport = serde_json::from_str(
+            &std::fs::read_to_string(baseline)
+                .with_context(|| format!("failed to read {}", baseline.display()))?,
+        )?;
+        let regressions = report.regressions(&baseline, tolerance / 100.0);
+        for regression in &regressions {
+            error!("{regression}");
+        }
+        anyhow::ensure!(
+            regressions.is_empty(),
+            "{} stages got slower than in the baseline",
+            regressions.len()
+        );
+    }
+    Ok(())
+}
+
+fn validate(tasks: &[PathBuf], json: bool) -> anyhow::Result<()> {
+    let mut failed = 0;
+    let mut results = Vec::new();
+    for path in tasks {
+        match Task::load(path) {
+            Ok(task) => {
+                let issues = Validator::new().validate(&task);
+                if !issues.is_empty() {
+                    failed += 1;
+                }
+                if json {
+                    results.push(serde_json::json!({
+                        "path": path,
+                        "task": task.name,
+                        "steps": task.steps.len(),
+                        "issues": issues
+                            .iter()
+                            .map(|issue| serde_json::json!({
+                                "location": issue.location,
+                                "problem": issue.problem.to_string(),
+                            }))
+                            .collect::<Vec<_>>(),
+                    }));
+                } else if issues.is_empty() {
+                    println!(
+                        "ok\t{}\t{} ({} steps)",
+                        path.display(),
+                        task.name,
+                        task.steps.len()
+                    );
+                } else {
+                    for issue in issues {
+                        println!("error\t{}\t{issue}", path.display());
+                    }
+                }
+            }
+            Err(err) => {
+                failed += 1;
+                if json {
+                    results.push(serde_json::json!({
+                        "path": path,
+                        "error": format!("{err:#}"),
+                    }));
+                } else {
+                    println!("error\t{}\t{err:#}", path.display());
+                }
+            }
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    }
+    anyhow::ensure!(failed == 0, "{failed} of {} tasks are invalid", tasks.len());
+    Ok(())
+}
+
+#[cfg(any(feature = "windows", feature = "linux"))]
+fn desktop_input() -> anyhow::Result<Box<dyn InputSource>> {
+    Ok(Box::new(auto_play::macro_recorder::DesktopInput::new()))
+}
+
+#[cfg(not(any(feature = "windows", feature = "linux")))]
+fn desktop_input() -> anyhow::Result<Box<dyn InputSource>> {
+    anyhow::bail!("recording a window needs the `windows` or `linux` feature")
+}
+
+fn draft(ap: AutoPlay, name: &str, dir: &Path) -> anyhow::Result<()> {
+    let mut source = if ap.with_controller(|_: &AndroidController| ()).is_some() {
+        Box::new(AndroidInput::start(&ap)?)
+    } else {
+        desktop_input()?
+    };
+    let token = CancellationToken::new();
+    {
+        let token = token.clone();
+        thread::spawn(move || {
+            let _ = std::io::stdin().read_line(&mut String::new());
+            token.cancel();
+        });
+    }
+    info!("recording {name}, press Enter to stop...");
+    let mut recorder = MacroRecorder::new(name, dir, MacroOptions::default());
+    recorder.run(&ap, source.as_mut(), &token)?;
+    let steps = recorder.steps().len();
+    let path = recorder.finish()?;
+    println!("{steps} steps written to {}", path.display());
+    Ok(())
+}
+
+#[cfg(feature = "update")]
+fn manifest(dir: &Path) -> anyhow::Result<()> {
+    let manifest = Manifest::write(dir)?;
+    info!("{} files in the manifest", manifest.files.len());
+    Ok(())
+}
+
+#[cfg(feature = "update")]
+fn update(source: &str, dir: &Path) -> anyhow::Result<()> {
+    let report = Updater::new(Source::parse(source), dir).update()?;
+    if report.changed() {
+        info!(
+            "{} downloaded, {} unchanged, {} removed",
+            report.downloaded.len(),
+            report.unchanged,
+            report.removed.len()
+        );
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    // Kept for the debug bundles, without colors
+    let logs = RecentLogs::new(DEBUG_BUNDLE_LOG_LINES);
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env().unwrap_or_else(|_| "info".into()),
+        )
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(logs.clone()),
+        )
+        .init();
+
+    let cli = Cli::parse();
+    let res = match cli.command {
+        Command::Devices => devices(cli.json),
+        Command::Windows => windows(cli.json),
+        Command::Screencap { target, output } => target
+            .connect()
+            .and_then(|ap| screencap(&ap, &output, cli.json)),
+        Command::Run {
+            target,
+            task,
+            resource,
+            args,
+            record,
+            publish,
+            report,
+            metrics,
+            logcat,
+            min_battery,
+            max_temperature,
+            debug_bundle,
+            redact,
+            redact_text,
+        } => load_task(&task, resource.as_deref(), args).and_then(|(task, resource)| {
+            let ap = target.connect()?;
+            if let Some(resource) = resource {
+                ap.set_resource(resource);
+            }
+            ap.set_report_logcat(logcat);
+            if let Some(dir) = debug_bundle {
+                let redaction = (redact || !redact_text.is_empty()).then(|| Redaction {
+                    strings: redact_text,
+                    ..Default::default()
+                });
+                ap.set_debug_bundle(Some(
+                    DebugBundleOptions::new(dir)
+                        .with_redaction(redaction)
+                        .with_logs(logs),
+                ));
+            }
+            let guard =
+                (min_battery.is_some() || max_temperature.is_some()).then(|| GuardOptions {
+                    min_battery,
+                    max_temperature,
+                    resume: ResumePolicy::Recovered {
+                        min_battery: min_battery.map_or(0, |min| min.saturating_mul(2).min(100)),
+                        max_temperature: max_temperature.map_or(f32::MAX, |max| max - 5.0),
+                    },
+                    ..Default::default()
+                });
+            let output = RunOutput {
+                record: record.as_deref(),
+                publish: publish.as_deref(),
+                report: report.as_deref(),
+                metrics: metrics
+                    .as_deref()
+                    .map(|path| (path, target.serial.as_deref())),
+                json: cli.json,
+            };
+            run(ap, &task, &output, guard)
+        }),
+        Command::Record {
+            target,
+            output,
+            interval,
+            duration,
+        } => target.connect().and_then(|ap| {
+            record(
+                ap,
+                &output,
+                Duration::from_millis(interval),
+                duration.map(Duration::from_secs),
+            )
+        }),
+        Command::Bench {
+            target,
+            template,
+            iterations,
+            click,
+        } => target
+            .connect()
+            .and_then(|ap| bench(&ap, &template, iterations, click)),
+        Command::Latency {
+            target,
+            samples,
+            tap,
+            output,
+            baseline,
+            tolerance,
+        } => target.connect().and_then(|ap| {
+            latency(
+                &ap,
+                &ProbeOptions::default().with_samples(samples).with_tap(tap),
+                output.as_deref(),
+                baseline.as_deref(),
+                tolerance,
+                cli.json,
+            )
+        }),
+        Command::Validate { tasks } => validate(&tasks, cli.json),
+        Command::Draft {
+            target,
+            name,
+            output,
+        } => {
+            let dir = output.unwrap_or_else(|| PathBuf::from(&name));
+            target.connect().and_then(|ap| draft(ap, &name, &dir))
+        }
+        #[cfg(feature = "update")]
+        Command::Manifest { dir } => manifest(&dir),
+        #[cfg(feature = "update")]
+        Command::Update { source, dir } => update(&source, &dir),
+        #[cfg(feature = "dashboard")]
+        Command::Dashboard { task, serial } => dashboard(&task, serial),
+        #[cfg(feature = "server")]
+        Command::Serve { addr, serial } => serve(&addr, &serial),
+    };
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            error!("{err:#}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(feature = "dashboard")]
+fn dashboard(task: &Path, mut serials: Vec<String>) -> anyhow::Result<()> {
+    let task = Task::load(task)?;
+    if serials.is_empty() {
+        let devices = host::connect_default()?.devices_long()?;
+        serials = devices
+            .into_iter()
+            .filter(|device| device.state == "device")
+            .map(|device| device.serial)
+            .collect();
+    }
+    let mut fleet = auto_play::fleet::Fleet::new();
+    let mut dashboard = auto_play::dashboard::Dashboard::new();
+    for serial in &serials {
+        info!("connecting to {serial}...");
+        let ap = fleet.connect(serial)?;
+        dashboard.add(serial, &ap);
+    }
+    let token = CancellationToken::new();
+    let report = thread::scope(|s| {
+        let run = s.spawn(|| {
+            let report = fleet.run_all(&task);
+            token.cancel();
+            report
+        });
+        let res = dashboard.run(&token);
+        let report = run.join().expect("the fleet does not panic");
+        res.map(|()| report)
+    })?;
+    let failed = report.failed().count();
+    anyhow::ensure!(
+        failed == 0,
+        "{failed} of {} devices failed",
+        report.reports.len()
+    );
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+fn serve(addr: &str, serials: &[String]) -> anyhow::Result<()> {
+    let server = auto_play::server::Server::new(auto_play::fleet::Fleet::new());
+    for serial in serials {
+        info!("connecting to {serial}...");
+        server.fleet().connect(serial)?;
+    }
+    server.run(addr)
+}
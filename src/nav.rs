@@ -1,17 +1,169 @@
-use std::collections::HashMap;
+//! Navigate between the screens of a game
+//!
+//! A [`NavGraph`] is built in code, or declared in a `nav.toml` shipped with the
+//! resources, see [`NavGraph::from_toml`]:
+//!
+//! ```toml
+//! [[nodes]]
+//! name = "home"
+//! check = { Template = { template = "home.png" } }
+//!
+//! [[nodes]]
+//! name = "shop"
+//! check = { Color = { x = 40, y = 40, color = "#ffcc00" } }
+//!
+//! [[edges]]
+//! from = "home"
+//! to = "shop"
+//! steps = [{ ClickMatchTemplate = { template = "shop.png" } }]
+//!
+//! [[edges]]
+//! from = "shop"
+//! to = "home"
+//! task = "back_home"
+//! # Through a loading screen, the default is 1
+//! cost = 5
+//!
+//! # Optional, see `NavOptions`
+//! [options]
+//! recovery_keys = ["Back"]
+//! ```
+//!
+//! [`NavGraph::navigate_to`] finds the current node itself, pressing the
+//! [recovery keys](NavOptions::recovery_keys) until it does, and checks that each
+//! edge led where it should.
 
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
 use petgraph::{algo::astar, graph::NodeIndex, visit::IntoNodeReferences, Graph};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::{
+    action::{Action, Condition, ExecContext, Key},
+    AutoPlay,
+};
+
+/// Times a path is planned again after an edge failed
+const MAX_REPLANS: usize = 3;
 
-use crate::AutoPlay;
+/// Between two checks of the destination of an edge
+const VERIFY_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Tells whether a node is the current one, checkers run in parallel
+type Checker = Box<dyn Fn(&AutoPlay) -> bool + Send + Sync>;
 
 pub struct Node {
-    checker: Option<Box<dyn Fn(&AutoPlay) -> bool>>,
+    checker: Option<Checker>,
+}
+
+impl Node {
+    /// A node that is never detected as the current one.
+    pub fn new() -> Self {
+        Self { checker: None }
+    }
+
+    /// A node that is the current one when `checker` returns `true`.
+    pub fn with_checker(checker: impl Fn(&AutoPlay) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            checker: Some(Box::new(checker)),
+        }
+    }
+
+    /// A node that is the current one when `action` succeeds, i.e. a
+    /// [`CheckColor`](crate::action::CheckColor). Failing is expected, so a task
+    /// failing there writes no debug bundle.
+    pub fn with_precondition(action: impl Action + 'static) -> Self {
+        Self::with_checker(move |ap| action.execute_in(&ExecContext::new(ap)).is_ok())
+    }
+}
+
+impl Default for Node {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Moves from one node to another, failing if it could not
+pub type Edge = Box<dyn Fn(&AutoPlay) -> anyhow::Result<()>>;
+
+/// How often an edge was taken, see [`NavGraph::edge_stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EdgeStats {
+    pub attempts: u32,
+    /// The action failed or the destination was not detected after it
+    pub failures: u32,
+}
+
+impl EdgeStats {
+    /// The chance it succeeds, 1/2 before it was taken and then moving towards
+    /// the rate observed.
+    pub fn success_rate(&self) -> f32 {
+        (self.attempts - self.failures + 1) as f32 / (self.attempts + 2) as f32
+    }
+}
+
+struct EdgeData {
+    run: Edge,
+    cost: f32,
+    attempts: AtomicU32,
+    failures: AtomicU32,
+}
+
+impl EdgeData {
+    fn stats(&self) -> EdgeStats {
+        EdgeStats {
+            attempts: self.attempts.load(Ordering::Relaxed),
+            failures: self.failures.load(Ordering::Relaxed),
+        }
+    }
+
+    /// The cost expected when retrying it until it succeeds, so a fast edge that
+    /// keeps failing ends up avoided.
+    fn expected_cost(&self) -> f32 {
+        self.cost / self.stats().success_rate()
+    }
+}
+
+/// How a [`NavGraph`] finds the current node and checks its edges, the
+/// `[options]` of a `nav.toml`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NavOptions {
+    /// Pressed in turn while no node is detected, to close whatever is in the
+    /// way, i.e. `[Back, HomeScreen]` on Android
+    pub recovery_keys: Vec<Key>,
+    /// Keys pressed before giving up
+    pub recovery_attempts: usize,
+    /// Time for the screen to settle after a key
+    pub settle_ms: u64,
+    /// How long the destination of an edge is given to be detected
+    pub verify_timeout_ms: u64,
+}
+
+impl Default for NavOptions {
+    fn default() -> Self {
+        Self {
+            recovery_keys: vec![Key::Escape],
+            recovery_attempts: 3,
+            settle_ms: 1000,
+            verify_timeout_ms: 5000,
+        }
+    }
 }
 
 pub struct NavGraph {
     ids: HashMap<String, NodeIndex<u32>>,
     names: HashMap<NodeIndex<u32>, String>,
-    inner: Graph<Node, Box<dyn Fn(&AutoPlay) -> anyhow::Result<()>>>,
+    inner: Graph<Node, EdgeData>,
+    options: NavOptions,
 }
 
 impl Default for NavGraph {
@@ -20,6 +172,7 @@ impl Default for NavGraph {
             ids: HashMap::new(),
             names: HashMap::new(),
             inner: Graph::new(),
+            options: NavOptions::default(),
         }
     }
 }
@@ -36,47 +189,308 @@ impl NavGraph {
         self.names.insert(idx, id.to_string());
     }
 
-    pub fn insert_edge(
+    pub fn insert_edge(&mut self, from: impl AsRef<str>, to: impl AsRef<str>, edge: Edge) {
+        self.insert_edge_with_cost(from, to, 1.0, edge);
+    }
+
+    /// An edge taking `cost` to go through, i.e. its duration in seconds, the
+    /// path with the lowest total is taken. [`NavGraph::insert_edge`] costs 1.
+    ///
+    /// Panics if `cost` is negative or NaN, which the path search cannot weigh.
+    pub fn insert_edge_with_cost(
         &mut self,
         from: impl AsRef<str>,
         to: impl AsRef<str>,
-        edge: Box<dyn Fn(&AutoPlay) -> anyhow::Result<()>>,
+        cost: f32,
+        edge: Edge,
     ) {
         let from = from.as_ref();
         let to = to.as_ref();
+        assert!(
+            cost >= 0.0,
+            "the cost of the edge from {from} to {to} is {cost}, it cannot be negative or NaN"
+        );
         let from_index = self.ids.get(from).unwrap().clone();
         let to_index = self.ids.get(to).unwrap().clone();
-        self.inner.add_edge(from_index, to_index, edge);
+        self.inner.add_edge(
+            from_index,
+            to_index,
+            EdgeData {
+                run: edge,
+                cost,
+                attempts: AtomicU32::new(0),
+                failures: AtomicU32::new(0),
+            },
+        );
     }
 
-    pub fn current_node(&self, ap: &AutoPlay) -> Option<String> {
+    /// How the cheapest edge from `from` to `to` fared so far.
+    pub fn edge_stats(&self, from: impl AsRef<str>, to: impl AsRef<str>) -> Option<EdgeStats> {
+        let from = *self.ids.get(from.as_ref())?;
+        let to = *self.ids.get(to.as_ref())?;
+        self.edge(from, to).map(EdgeData::stats)
+    }
+
+    /// The edge from `from` to `to` with the lowest expected cost
+    fn edge(&self, from: NodeIndex<u32>, to: NodeIndex<u32>) -> Option<&EdgeData> {
         self.inner
+            .edges_connecting(from, to)
+            .map(|edge| edge.weight())
+            .min_by(|a, b| a.expected_cost().total_cmp(&b.expected_cost()))
+    }
+
+    pub fn contains_node(&self, id: impl AsRef<str>) -> bool {
+        self.ids.contains_key(id.as_ref())
+    }
+
+    pub fn options(&self) -> &NavOptions {
+        &self.options
+    }
+
+    pub fn set_options(&mut self, options: NavOptions) {
+        self.options = options;
+    }
+
+    /// The first node, in insertion order, whose checker passes. The checkers
+    /// all run at once, each on its own thread.
+    pub fn current_node(&self, ap: &AutoPlay) -> Option<String> {
+        self.detect(ap).map(|idx| self.names[&idx].clone())
+    }
+
+    fn detect(&self, ap: &AutoPlay) -> Option<NodeIndex<u32>> {
+        let checkers = self
+            .inner
             .node_references()
-            .find(|(_, n)| n.checker.as_ref().map(|c| c(ap)).unwrap_or(false))
-            .map(|(idx, _)| self.names[&idx].clone())
+            .filter_map(|(idx, n)| n.checker.as_ref().map(|c| (idx, c)))
+            .collect::<Vec<_>>();
+        if let [(idx, checker)] = checkers[..] {
+            return checker(ap).then_some(idx);
+        }
+        thread::scope(|s| {
+            let handles = checkers
+                .into_iter()
+                .map(|(idx, checker)| (idx, s.spawn(move || checker(ap))))
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|(idx, handle)| (idx, handle.join().unwrap_or(false)))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .find_map(|(idx, found)| found.then_some(idx))
+        })
+    }
+
+    /// The current node, pressing the [recovery keys](NavOptions::recovery_keys)
+    /// until one is detected.
+    fn locate(&self, ap: &AutoPlay) -> anyhow::Result<NodeIndex<u32>> {
+        let mut keys = self.options.recovery_keys.iter().cycle();
+        for attempt in 0..=self.options.recovery_attempts {
+            if let Some(idx) = self.detect(ap) {
+                return Ok(idx);
+            }
+            let Some(key) = keys.next() else { break };
+            if attempt == self.options.recovery_attempts {
+                break;
+            }
+            debug!("no node detected, pressing {key:?}");
+            ap.press((*key).into())?;
+            thread::sleep(Duration::from_millis(self.options.settle_ms));
+        }
+        anyhow::bail!("failed to detect the current node")
     }
 
+    /// Whether `idx` is detected within [`NavOptions::verify_timeout_ms`], or
+    /// has no checker.
+    fn verify(&self, ap: &AutoPlay, idx: NodeIndex<u32>) -> bool {
+        let Some(checker) = &self.inner[idx].checker else {
+            return true;
+        };
+        let deadline = Instant::now() + Duration::from_millis(self.options.verify_timeout_ms);
+        loop {
+            if checker(ap) {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(VERIFY_INTERVAL);
+        }
+    }
+
+    /// Take the cheapest path from `from` to `to`, weighing the cost of each edge
+    /// by how often it failed, see [`EdgeStats::success_rate`]. When an edge
+    /// fails or its destination is not detected after it, the current node is
+    /// located again and the path planned again from there.
     pub fn nav(
         &self,
         ap: &AutoPlay,
         from: impl AsRef<str>,
         to: impl AsRef<str>,
     ) -> anyhow::Result<()> {
-        let from = self.ids.get(from.as_ref()).unwrap();
-        let to = self.ids.get(to.as_ref()).unwrap();
-        let (cost, path) = astar(&self.inner, *from, |n| n == *to, |_| 1, |_| 0)
-            .ok_or(anyhow::anyhow!("unreachable"))?;
-        println!("cost: {cost}, path: {:?}", path);
-        for e in path.windows(2).map(|idxs| {
-            self.inner
-                .edges_connecting(idxs[0], idxs[1])
-                .next()
-                .unwrap()
-        }) {
-            (e.weight())(ap)?;
+        let from = *self.index(from.as_ref())?;
+        let to = *self.index(to.as_ref())?;
+        self.walk(ap, from, to)
+    }
+
+    fn walk(
+        &self,
+        ap: &AutoPlay,
+        mut from: NodeIndex<u32>,
+        to: NodeIndex<u32>,
+    ) -> anyhow::Result<()> {
+        let mut replans = 0;
+        'plan: loop {
+            let (cost, path) = astar(
+                &self.inner,
+                from,
+                |n| n == to,
+                |e| e.weight().expected_cost(),
+                |_| 0.0,
+            )
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "{} is unreachable from {}",
+                    self.names[&to],
+                    self.names[&from]
+                )
+            })?;
+            debug!("cost: {cost}, path: {path:?}");
+            for idxs in path.windows(2) {
+                let edge = self.edge(idxs[0], idxs[1]).unwrap();
+                edge.attempts.fetch_add(1, Ordering::Relaxed);
+                let name = &self.names[&idxs[1]];
+                let err = match (edge.run)(ap) {
+                    Ok(()) if self.verify(ap, idxs[1]) => continue,
+                    Ok(()) => anyhow::anyhow!("failed to reach {name}"),
+                    Err(err) => err.context(format!("failed to go to {name}")),
+                };
+                edge.failures.fetch_add(1, Ordering::Relaxed);
+                replans += 1;
+                if replans > MAX_REPLANS {
+                    return Err(err);
+                }
+                warn!("{err:#}, locating the current node");
+                from = self.locate(ap)?;
+                continue 'plan;
+            }
+            return Ok(());
+        }
+    }
+
+    /// Navigate from the current node to `to`, locating it first, see
+    /// [`NavOptions`].
+    pub fn navigate_to(&self, ap: &AutoPlay, to: impl AsRef<str>) -> anyhow::Result<()> {
+        let to = *self.index(to.as_ref())?;
+        let from = self.locate(ap)?;
+        self.walk(ap, from, to)
+    }
+
+    fn index(&self, id: &str) -> anyhow::Result<&NodeIndex<u32>> {
+        self.ids
+            .get(id)
+            .ok_or_else(|| anyhow::anyhow!("unknown node {id}"))
+    }
+
+    /// A graph declared in TOML, see the [module](self) documentation.
+    ///
+    /// A node is the current one when its `check`, a [`Condition`], holds. An
+    /// edge runs its `steps`, or the `task` of that name in
+    /// [`AutoPlay::resource`] when it is taken.
+    pub fn from_toml(source: &str) -> anyhow::Result<Self> {
+        let spec = toml::from_str::<NavSpec>(source).context("failed to parse nav graph")?;
+        let mut graph = Self::new();
+        graph.set_options(spec.options);
+        for node in spec.nodes {
+            if graph.contains_node(&node.name) {
+                anyhow::bail!("node {} is defined twice", node.name);
+            }
+            let checker = match node.check {
+                Some(cond) => Node::with_checker(move |ap| cond.check(ap).unwrap_or(false)),
+                None => Node::new(),
+            };
+            graph.insert_node(&node.name, checker);
+        }
+        for edge in spec.edges {
+            for node in [&edge.from, &edge.to] {
+                graph
+                    .index(node)
+                    .with_context(|| format!("in the edge from {} to {}", edge.from, edge.to))?;
+            }
+            anyhow::ensure!(
+                edge.cost >= 0.0,
+                "the cost of the edge from {} to {} is {}, it cannot be negative or NaN",
+                edge.from,
+                edge.to,
+                edge.cost
+            );
+            let run: Edge = match (edge.steps, edge.task) {
+                (Some(steps), None) => {
+                    Box::new(move |ap| steps.iter().try_for_each(|step| step.execute(ap)))
+                }
+                (None, Some(task)) => Box::new(move |ap| {
+                    ap.resource()
+                        .task(&task)
+                        .ok_or_else(|| anyhow::anyhow!("unknown task {task}"))?
+                        .execute(ap)
+                }),
+                _ => anyhow::bail!(
+                    "the edge from {} to {} needs either steps or a task",
+                    edge.from,
+                    edge.to
+                ),
+            };
+            graph.insert_edge_with_cost(&edge.from, &edge.to, edge.cost, run);
         }
-        Ok(())
+        Ok(graph)
     }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::from_toml(&source).with_context(|| format!("in {}", path.display()))
+    }
+}
+
+/// The TOML form of a [`NavGraph`]
+#[derive(Deserialize)]
+struct NavSpec {
+    #[serde(default)]
+    nodes: Vec<NodeSpec>,
+    #[serde(default)]
+    edges: Vec<EdgeSpec>,
+    #[serde(default)]
+    options: NavOptions,
+}
+
+#[derive(Deserialize)]
+struct NodeSpec {
+    name: String,
+    #[serde(default)]
+    check: Option<Condition>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct EdgeSpec {
+    pub from: String,
+    pub to: String,
+    #[serde(default = "default_cost")]
+    cost: f32,
+    #[serde(default)]
+    pub steps: Option<Vec<Box<dyn Action>>>,
+    #[serde(default)]
+    pub task: Option<String>,
+}
+
+fn default_cost() -> f32 {
+    1.0
+}
+
+/// The edges of a graph of [`NavGraph::from_toml`], without building it, for the
+/// [`Validator`](crate::validate::Validator).
+pub(crate) fn edge_specs(source: &str) -> anyhow::Result<Vec<EdgeSpec>> {
+    let spec = toml::from_str::<NavSpec>(source).context("failed to parse nav graph")?;
+    Ok(spec.edges)
 }
 
 #[cfg(test)]
@@ -114,9 +528,26 @@ mod tests {
             todo!()
         }
 
-        fn press(&self, _key: ap_controller::Key) -> anyhow::Result<()> {
+        fn long_press(
+            &self,
+            _x: u32,
+            _y: u32,
+            _duration: std::time::Duration,
+        ) -> anyhow::Result<()> {
+            todo!()
+        }
+
+        fn double_click(&self, _x: u32, _y: u32) -> anyhow::Result<()> {
+            todo!()
+        }
+
+        fn input_text(&self, _text: &str) -> anyhow::Result<()> {
             todo!()
         }
+
+        fn press(&self, _key: ap_controller::Key) -> anyhow::Result<()> {
+            Ok(())
+        }
     }
 
     #[test]
@@ -152,4 +583,225 @@ mod tests {
         );
         let _ = graph.nav(&ap, "start", "end");
     }
+
+    #[test]
+    fn test_navigate_to() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let ap = AutoPlay::new(DummyController);
+        let visited = Rc::new(RefCell::new(Vec::new()));
+        let mut graph = NavGraph::new();
+        graph.insert_node("start", Node::new());
+        graph.insert_node("mid", Node::with_checker(|_| true));
+        graph.insert_node("end", Node::new());
+        for (from, to) in [("start", "mid"), ("mid", "end")] {
+            let visited = visited.clone();
+            graph.insert_edge(
+                from,
+                to,
+                Box::new(move |_| {
+                    visited.borrow_mut().push(to);
+                    Ok(())
+                }),
+            );
+        }
+        graph.navigate_to(&ap, "end").unwrap();
+        assert_eq!(*visited.borrow(), ["end"]);
+        assert!(graph.navigate_to(&ap, "nowhere").is_err());
+    }
+
+    #[test]
+    fn test_precondition() {
+        use crate::action::{CheckColor, WaitAction};
+
+        let ap = AutoPlay::new(DummyController);
+        let mut graph = NavGraph::new();
+        let invalid = CheckColor {
+            x: 0,
+            y: 0,
+            region: None,
+            color: "red".to_string(),
+            tolerance: 0,
+        };
+        graph.insert_node("invalid", Node::with_precondition(invalid));
+        graph.insert_node("waited", Node::with_precondition(WaitAction { ms: 0 }));
+        assert_eq!(graph.current_node(&ap).as_deref(), Some("waited"));
+    }
+
+    #[test]
+    fn test_from_toml() {
+        let ap = AutoPlay::new(DummyController);
+        let graph = NavGraph::from_toml(
+            r##"
+[[nodes]]
+name = "home"
+
+[[nodes]]
+name = "shop"
+check = { Succeeds = { WaitAction = { ms = 0 } } }
+
+[[edges]]
+from = "shop"
+to = "home"
+steps = [{ WaitAction = { ms = 0 } }]
+
+[[nodes]]
+name = "bag"
+
+[[edges]]
+from = "home"
+to = "bag"
+task = "open_bag"
+cost = 2
+
+[options]
+recovery_attempts = 0
+"##,
+        )
+        .unwrap();
+        assert_eq!(graph.options().recovery_attempts, 0);
+        assert_eq!(graph.options().recovery_keys, [Key::Escape]);
+        assert_eq!(graph.current_node(&ap).as_deref(), Some("shop"));
+        graph.navigate_to(&ap, "home").unwrap();
+        // The task is looked up in the resource when the edge is taken, it is
+        // tried again from the shop until giving up
+        let err = graph.nav(&ap, "home", "bag").unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "failed to go to bag: unknown task open_bag"
+        );
+        let stats = graph.edge_stats("home", "bag").unwrap();
+        assert_eq!((stats.attempts, stats.failures), (4, 4));
+
+        let err = NavGraph::from_toml("[[edges]]\nfrom = \"a\"\nto = \"b\"\ntask = \"t\"")
+            .err()
+            .unwrap();
+        assert_eq!(
+            format!("{err:#}"),
+            "in the edge from a to b: unknown node a"
+        );
+        let err =
+            NavGraph::from_toml("[[nodes]]\nname = \"a\"\n[[edges]]\nfrom = \"a\"\nto = \"a\"")
+                .err()
+                .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "the edge from a to a needs either steps or a task"
+        );
+        for cost in ["-1", "nan"] {
+            let err = NavGraph::from_toml(&format!(
+                "[[nodes]]\nname = \"a\"\n[[edges]]\nfrom = \"a\"\nto = \"a\"\ncost = {cost}\nsteps = []"
+            ))
+            .err()
+            .unwrap();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "the cost of the edge from a to a is {}, it cannot be negative or NaN",
+                    cost.parse::<f32>().unwrap()
+                )
+            );
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be negative or NaN")]
+    fn test_negative_cost() {
+        let mut graph = NavGraph::new();
+        graph.insert_node("a", Node::new());
+        graph.insert_edge_with_cost("a", "a", -1.0, Box::new(|_| Ok(())));
+    }
+
+    #[test]
+    fn test_navigate_verify() {
+        use std::sync::{
+            Arc, Mutex,
+            atomic::{AtomicUsize, Ordering},
+        };
+
+        use crate::event::Event;
+
+        let ap = AutoPlay::new(DummyController);
+        // 0 is a dialog covering everything, closed by pressing a key, 1 home,
+        // 2 the shop
+        let screen = Arc::new(AtomicUsize::new(0));
+        let events = Arc::new(Mutex::new(ap.subscribe()));
+        let mut graph = NavGraph::new();
+        graph.set_options(NavOptions {
+            settle_ms: 0,
+            verify_timeout_ms: 0,
+            ..Default::default()
+        });
+        for (name, id) in [("home", 1), ("shop", 2)] {
+            let (screen, events) = (screen.clone(), events.clone());
+            graph.insert_node(
+                name,
+                Node::with_checker(move |_| {
+                    let events = events.lock().unwrap();
+                    if events
+                        .try_iter()
+                        .any(|event| matches!(event, Event::Press { .. }))
+                    {
+                        let _ = screen.compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst);
+                    }
+                    screen.load(Ordering::SeqCst) == id
+                }),
+            );
+        }
+        // The first tap is missed, the path is planned again from home
+        let taps = Arc::new(AtomicUsize::new(0));
+        graph.insert_edge("home", "shop", {
+            let (screen, taps) = (screen.clone(), taps.clone());
+            Box::new(move |_| {
+                if taps.fetch_add(1, Ordering::SeqCst) > 0 {
+                    screen.store(2, Ordering::SeqCst);
+                }
+                Ok(())
+            })
+        });
+
+        assert!(graph.current_node(&ap).is_none());
+        graph.navigate_to(&ap, "shop").unwrap();
+        assert_eq!(taps.load(Ordering::SeqCst), 2);
+        assert_eq!(graph.current_node(&ap).as_deref(), Some("shop"));
+
+        // Giving up once the keys are used up
+        screen.store(0, Ordering::SeqCst);
+        graph.set_options(NavOptions {
+            recovery_attempts: 0,
+            ..graph.options().clone()
+        });
+        let err = graph.navigate_to(&ap, "shop").unwrap_err();
+        assert_eq!(err.to_string(), "failed to detect the current node");
+    }
+
+    #[test]
+    fn test_weighted_edges() {
+        use std::sync::{Arc, Mutex};
+
+        let ap = AutoPlay::new(DummyController);
+        let taken = Arc::new(Mutex::new(Vec::new()));
+        let mut graph = NavGraph::new();
+        graph.insert_node("a", Node::with_checker(|_| true));
+        graph.insert_node("b", Node::new());
+        graph.insert_node("c", Node::new());
+        let edge = |name: &'static str, res: fn() -> anyhow::Result<()>| -> Edge {
+            let taken = taken.clone();
+            Box::new(move |_| {
+                taken.lock().unwrap().push(name);
+                res()
+            })
+        };
+        graph.insert_edge("a", "b", edge("a-b", || anyhow::bail!("missed")));
+        graph.insert_edge("b", "c", edge("b-c", || Ok(())));
+        graph.insert_edge_with_cost("a", "c", 2.2, edge("a-c", || Ok(())));
+
+        // Through b as it is cheaper, then straight to c once a-b failed
+        graph.nav(&ap, "a", "c").unwrap();
+        assert_eq!(*taken.lock().unwrap(), ["a-b", "a-c"]);
+        let stats = graph.edge_stats("a", "b").unwrap();
+        assert_eq!((stats.attempts, stats.failures), (1, 1));
+        assert_eq!(stats.success_rate(), 1.0 / 3.0);
+        assert_eq!(graph.edge_stats("a", "c").unwrap().failures, 0);
+    }
 }
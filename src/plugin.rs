@@ -0,0 +1,49 @@
+//! Named actions registered at runtime.
+//!
+//! A plugin is a plain callback that receives the [`AutoPlay`] executing it, so
+//! logic that is hard to express declaratively (or lives in another language, like
+//! the Python bindings) can still be referenced from a task by name through
+//! [`PluginAction`](crate::action::PluginAction).
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use crate::AutoPlay;
+
+pub type PluginFn = Arc<dyn Fn(&AutoPlay) -> anyhow::Result<()> + Send + Sync>;
+
+#[derive(Default)]
+pub struct PluginRegistry {
+    plugins: RwLock<HashMap<String, PluginFn>>,
+}
+
+impl PluginRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register `f` as `name`, replacing any plugin previously registered with the same name.
+    pub fn register(
+        &self,
+        name: impl Into<String>,
+        f: impl Fn(&AutoPlay) -> anyhow::Result<()> + Send + Sync + 'static,
+    ) {
+        self.plugins
+            .write()
+            .unwrap()
+            .insert(name.into(), Arc::new(f));
+    }
+
+    pub fn unregister(&self, name: &str) -> bool {
+        self.plugins.write().unwrap().remove(name).is_some()
+    }
+
+    pub fn get(&self, name: &str) -> Option<PluginFn> {
+        self.plugins.read().unwrap().get(name).cloned()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.plugins.read().unwrap().keys().cloned().collect()
+    }
+}
@@ -0,0 +1,194 @@
+//! Steps written in Python, behind the `python` feature.
+//!
+//! A `python` step names a function as `module:function`, it is imported from
+//! `sys.path` (which includes the working directory) and called with a
+//! `Context` to read the screen and act on the device:
+//!
+//! ```toml
+//! [[steps]]
+//! python = "daily:collect_mail"
+//! ```
+//!
+//! ```python
+//! def collect_mail(ctx):
+//!     width, height = ctx.screen_size
+//!     if find_badge(ctx.screencap()):
+//!         ctx.click(width - 80, 60)
+//! ```
+//!
+//! The function fails the step by raising, or by returning `False`. The logic
+//! stays in Python while the task runs from Rust, for a step the actions can not
+//! express; in a Python script, `AutoPlay.register_action` does the same with a
+//! callable.
+
+use std::{cell::Cell, io::Cursor, time::Duration};
+
+use pyo3::{exceptions::PyRuntimeError, prelude::*, types::PyBytes};
+use serde::{Deserialize, Serialize};
+
+use crate::{AutoPlay, action::Action};
+
+/// Calls the Python function `module:function`, see the [module](self) docs.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(transparent)]
+pub struct PythonAction {
+    pub target: String,
+}
+
+impl PythonAction {
+    pub fn new(target: impl Into<String>) -> Self {
+        Self {
+            target: target.into(),
+        }
+    }
+
+    fn split(&self) -> anyhow::Result<(&str, &str)> {
+        self.target
+            .split_once(':')
+            .filter(|(module, function)| !module.is_empty() && !function.is_empty())
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "invalid python step {:?}, expected module:function",
+                    self.target
+                )
+            })
+    }
+}
+
+#[typetag::serde(name = "python")]
+impl Action for PythonAction {
+    fn execute(&self, ap: &AutoPlay) -> anyhow::Result<()> {
+        let (module, function) = self.split()?;
+        Python::initialize();
+        Python::attach(|py| {
+            let sys_path = py.import("sys")?.getattr("path")?;
+            if !sys_path.contains("")? {
+                sys_path.call_method1("insert", (0, ""))?;
+            }
+            let function = py.import(module)?.getattr(function)?;
+            let context = Bound::new(py, Context { ap: Cell::new(ap) })?;
+            let res = function.call1((&context,));
+            // The context may outlive the call if the function keeps it
+            context.borrow().ap.set(std::ptr::null());
+            if let Ok(false) = res?.extract::<bool>() {
+                anyhow::bail!("{} returned False", self.target);
+            }
+            Ok(())
+        })
+    }
+}
+
+/// What a [`PythonAction`] function acts on, valid during the call.
+#[pyclass(name = "Context", module = "auto_play", unsendable)]
+struct Context {
+    ap: Cell<*const AutoPlay>,
+}
+
+impl Context {
+    fn with_ap<T: Send>(
+        &self,
+        py: Python<'_>,
+        f: impl FnOnce(&AutoPlay) -> anyhow::Result<T> + Send,
+    ) -> PyResult<T> {
+        // SAFETY: set to the `AutoPlay` of the step while its function runs and
+        // reset to null once it returns
+        let ap = unsafe { self.ap.get().as_ref() }
+            .ok_or_else(|| PyRuntimeError::new_err("the context is only valid during its step"))?;
+        py.detach(|| f(ap))
+            .map_err(|err| PyRuntimeError::new_err(format!("{err:#}")))
+    }
+}
+
+#[pymethods]
+impl Context {
+    #[getter]
+    fn screen_size(&self, py: Python<'_>) -> PyResult<(u32, u32)> {
+        self.with_ap(py, |ap| Ok(ap.screen_size()))
+    }
+
+    /// Take a screenshot, PNG encoded.
+    fn screencap<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let png = self.with_ap(py, |ap| {
+            let mut png = Vec::new();
+            ap.screencap()?
+                .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)?;
+            Ok(png)
+        })?;
+        Ok(PyBytes::new(py, &png))
+    }
+
+    /// Take a screenshot as `(width, height, rgba_bytes)`.
+    fn screencap_raw<'py>(&self, py: Python<'py>) -> PyResult<(u32, u32, Bound<'py, PyBytes>)> {
+        let (width, height, rgba) = self.with_ap(py, |ap| ap.screencap_raw())?;
+        Ok((width, height, PyBytes::new(py, &rgba)))
+    }
+
+    fn click(&self, py: Python<'_>, x: u32, y: u32) -> PyResult<()> {
+        self.with_ap(py, |ap| ap.click(x, y))
+    }
+
+    #[pyo3(signature = (start, end, duration_ms, slope_in = 1.0, slope_out = 1.0))]
+    fn swipe(
+        &self,
+        py: Python<'_>,
+        start: (u32, u32),
+        end: (i32, i32),
+        duration_ms: u64,
+        slope_in: f32,
+        slope_out: f32,
+    ) -> PyResult<()> {
+        self.with_ap(py, |ap| {
+            ap.swipe(
+                start,
+                end,
+                Duration::from_millis(duration_ms),
+                slope_in,
+                slope_out,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{task::Task, testing::DummyController};
+
+    #[test]
+    fn test_python_action() {
+        let dir = std::env::temp_dir().join(format!("ap-python-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("ap_python_test.py"),
+            "def corner(ctx):\n    w, h = ctx.screen_size\n    ctx.click(w - 1, h - 1)\n\n\
+             def refuse(ctx):\n    return False\n",
+        )
+        .unwrap();
+        Python::initialize();
+        Python::attach(|py| {
+            let sys_path = py.import("sys")?.getattr("path")?;
+            sys_path.call_method1("insert", (0, dir.to_str())).map(drop)
+        })
+        .unwrap();
+
+        let ap = AutoPlay::new(DummyController::new(1920, 1080));
+        let task = Task::from_toml("name = \"py\"\n[[steps]]\npython = \"ap_python_test:corner\"")
+            .unwrap();
+        task.execute(&ap).unwrap();
+        let clicks = ap.with_controller(DummyController::clicks).unwrap();
+        assert_eq!(clicks, [(1919, 1079)]);
+
+        assert!(
+            PythonAction::new("ap_python_test:refuse")
+                .execute(&ap)
+                .is_err()
+        );
+        assert!(
+            PythonAction::new("ap_python_test:missing")
+                .execute(&ap)
+                .is_err()
+        );
+        assert!(PythonAction::new("ap_python_test").execute(&ap).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
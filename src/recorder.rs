@@ -0,0 +1,576 @@
+//! Record a run to an MP4 with the executed actions drawn on top of it.
+//!
+//! Clicks and swipes are drawn on the frames, task steps and every other
+//! [`Event`] go to a subtitle track along with their timestamps. Encoding is done
+//! by `ffmpeg`, which has to be in `PATH` (or set with [`RecorderOptions::ffmpeg`]),
+//! see [`ap_controller::recorder`] to record a controller without an [`AutoPlay`].
+//!
+//! ```ignore
+//! let ap = Arc::new(AutoPlay::new(controller));
+//! let recorder = Recorder::start(ap.clone(), "run.mp4", RecorderOptions::default())?;
+//! task.execute(&ap)?;
+//! recorder.stop()?;
+//!
+//! // Or a video of each task run, from when it starts to when it finishes
+//! let recorder = Recorder::per_task(ap.clone(), "videos", RecorderOptions::default())?;
+//! ```
+
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::Context;
+use ap_controller::recorder::VideoEncoder;
+use image::{Rgba, RgbaImage};
+use imageproc::{
+    drawing::{
+        draw_filled_circle_mut, draw_hollow_circle_mut, draw_hollow_rect_mut, draw_line_segment_mut,
+    },
+    rect::Rect as ProcRect,
+};
+use tracing::{info, warn};
+
+pub use ap_controller::recorder::RecorderOptions;
+
+use crate::{AutoPlay, event::Event};
+
+/// How long a click marker stays on the screen
+const CLICK_MARKER: Duration = Duration::from_millis(500);
+/// How long a swipe path stays on the screen after the swipe is done
+const SWIPE_TRAIL: Duration = Duration::from_millis(500);
+/// How long a match stays outlined, when matches are shown
+const MATCH_MARKER: Duration = Duration::from_secs(1);
+/// How long a subtitle of a single action is shown
+const EVENT_CUE: Duration = Duration::from_secs(1);
+/// How long to wait for a task to start between two checks to stop
+const IDLE_POLL: Duration = Duration::from_millis(100);
+
+const CLICK_COLOR: Rgba<u8> = Rgba([255, 48, 48, 255]);
+const SWIPE_COLOR: Rgba<u8> = Rgba([255, 200, 0, 255]);
+const MATCH_COLOR: Rgba<u8> = Rgba([48, 220, 96, 255]);
+
+/// Where a [`Recorder`] writes to
+enum Output {
+    File(PathBuf),
+    /// A file per task run in the directory
+    PerTask(PathBuf),
+}
+
+/// Records the screen of an [`AutoPlay`] on a background thread until stopped.
+pub struct Recorder {
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<anyhow::Result<()>>>,
+}
+
+impl Recorder {
+    /// Record everything until stopped to the MP4 at `path`.
+    pub fn start(
+        ap: Arc<AutoPlay>,
+        path: impl Into<PathBuf>,
+        options: RecorderOptions,
+    ) -> anyhow::Result<Self> {
+        Self::spawn(ap, Output::File(path.into()), options)
+    }
+
+    /// Record each task run to its own MP4 in `dir`, from its
+    /// [`Event::TaskStarted`] to its [`Event::TaskFinished`], named after the
+    /// task and when it started. The tasks a task runs are in its video.
+    pub fn per_task(
+        ap: Arc<AutoPlay>,
+        dir: impl Into<PathBuf>,
+        options: RecorderOptions,
+    ) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        Self::spawn(ap, Output::PerTask(dir), options)
+    }
+
+    fn spawn(ap: Arc<AutoPlay>, output: Output, options: RecorderOptions) -> anyhow::Result<Self> {
+        anyhow::ensure!(options.fps > 0, "fps should be greater than 0");
+        let events = ap.subscribe();
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            thread::Builder::new()
+                .name("recorder".to_string())
+                .spawn(move || record(&ap, events, &output, &options, &stop))?
+        };
+        Ok(Self {
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    /// Stop recording and wait for the video to be written.
+    pub fn stop(mut self) -> anyhow::Result<()> {
+        self.finish()
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        self.stop.store(true, Ordering::Relaxed);
+        match self.handle.take() {
+            Some(handle) => handle
+                .join()
+                .map_err(|_| anyhow::anyhow!("recorder thread panicked"))?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            warn!("failed to finish recording: {err:#}");
+        }
+    }
+}
+
+/// The screen of `ap`, from the frame cache of its controller when it has one
+fn capture(ap: &AutoPlay) -> anyhow::Result<RgbaImage> {
+    ap_controller::recorder::capture(ap.controller().as_ref())
+}
+
+/// A video being encoded, with the actions drawn on it and its subtitles
+struct Clip {
+    path: PathBuf,
+    /// Where the video is encoded to, before the subtitles are added
+    video_path: PathBuf,
+    encoder: VideoEncoder,
+    overlay: Overlay,
+    subtitles: Subtitles,
+}
+
+impl Clip {
+    fn start(ap: &AutoPlay, path: PathBuf, options: &RecorderOptions) -> anyhow::Result<Self> {
+        let frame = capture(ap)?;
+        let video_path = path.with_extension("video.mp4");
+        let mut encoder = VideoEncoder::start(&video_path, frame.dimensions(), options)?;
+        encoder.write(&frame)?;
+        Ok(Self {
+            path,
+            video_path,
+            encoder,
+            overlay: Overlay::default(),
+            subtitles: Subtitles::default(),
+        })
+    }
+
+    fn push(&mut self, event: &Event) {
+        let now = self.encoder.elapsed();
+        self.overlay.push(now, event);
+        self.subtitles.push(now, event);
+    }
+
+    fn write(&mut self, mut frame: RgbaImage) -> anyhow::Result<()> {
+        self.overlay.draw(&mut frame, self.encoder.elapsed());
+        self.encoder.write(&frame)
+    }
+
+    /// Finish the video and add the subtitles to it.
+    fn finish(mut self, options: &RecorderOptions) -> anyhow::Result<()> {
+        self.subtitles.finish(self.encoder.elapsed());
+        self.encoder.finish()?;
+        if self.subtitles.is_empty() {
+            std::fs::rename(&self.video_path, &self.path)?;
+            return Ok(());
+        }
+        let srt_path = self.path.with_extension("srt");
+        std::fs::write(&srt_path, self.subtitles.to_srt())?;
+        let status = Command::new(&options.ffmpeg)
+            .args(["-y", "-loglevel", "error", "-i"])
+            .arg(&self.video_path)
+            .arg("-i")
+            .arg(&srt_path)
+            .args(["-map", "0", "-map", "1", "-c", "copy", "-c:s", "mov_text"])
+            .arg(&self.path)
+            .status()?;
+        anyhow::ensure!(status.success(), "ffmpeg exited with {status}");
+        std::fs::remove_file(&self.video_path)?;
+        std::fs::remove_file(&srt_path)?;
+        Ok(())
+    }
+}
+
+/// A new file in `dir` for a run of `task` starting now.
+fn clip_path(dir: &Path, task: &str) -> PathBuf {
+    let name = format!("{task}-{}", chrono::Local::now().format("%Y%m%d-%H%M%S"));
+    let mut path = dir.join(format!("{name}.mp4"));
+    for n in 1.. {
+        if !path.exists() {
+            break;
+        }
+        path = dir.join(format!("{name}-{n}.mp4"));
+    }
+    path
+}
+
+fn record(
+    ap: &AutoPlay,
+    events: mpsc::Receiver<Event>,
+    output: &Output,
+    options: &RecorderOptions,
+    stop: &AtomicBool,
+) -> anyhow::Result<()> {
+    let mut clip = match output {
+        Output::File(path) => Some(Clip::start(ap, path.clone(), options)?),
+        Output::PerTask(_) => None,
+    };
+    // Tasks running, including the ones run by other tasks
+    let mut depth = 0usize;
+    let res = (|| -> anyhow::Result<()> {
+        loop {
+            // The events sent before stopping are still handled
+            let stopping = stop.load(Ordering::Relaxed);
+            let pending = match &clip {
+                Some(_) => events.try_iter().collect::<Vec<_>>(),
+                _ if stopping => events.try_iter().collect(),
+                // Nothing to capture until a task starts
+                None => match events.recv_timeout(IDLE_POLL) {
+                    Ok(event) => std::iter::once(event).chain(events.try_iter()).collect(),
+                    Err(_) => continue,
+                },
+            };
+            for event in pending {
+                match (&event, output) {
+                    (Event::TaskStarted { name }, Output::PerTask(dir)) if depth == 0 => {
+                        clip = Some(Clip::start(ap, clip_path(dir, name), options)?);
+                    }
+                    _ => {}
+                }
+                if let Some(clip) = &mut clip {
+                    clip.push(&event);
+                }
+                match event {
+                    Event::TaskStarted { .. } => depth += 1,
+                    Event::TaskFinished { .. } => {
+                        depth = depth.saturating_sub(1);
+                        if depth == 0
+                            && matches!(output, Output::PerTask(_))
+                            && let Some(clip) = clip.take()
+                        {
+                            let path = clip.path.clone();
+                            clip.finish(options)?;
+                            info!("recorded to {}", path.display());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            if stopping {
+                break;
+            }
+            if let Some(clip) = &mut clip {
+                clip.encoder.wait_next_frame();
+                clip.write(capture(ap)?)?;
+            }
+        }
+        Ok(())
+    })();
+    // A task still running is cut where the recorder stopped
+    let finished = clip.map_or(Ok(()), |clip| clip.finish(options));
+    res.and(finished)
+}
+
+/// Markers of the recent clicks and swipes, and matches if enabled.
+#[derive(Default)]
+pub(crate) struct Overlay {
+    /// `(when, event)`
+    events: Vec<(Duration, Event)>,
+    matches: bool,
+}
+
+impl Overlay {
+    /// Also outline where templates were found, see [`Event::MatchResult`].
+    pub(crate) fn with_matches() -> Self {
+        Self {
+            matches: true,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn push(&mut self, at: Duration, event: &Event) {
+        let shown = match event {
+            Event::Click { .. } | Event::Swipe { .. } => true,
+            Event::MatchResult { rect, .. } => self.matches && rect.is_some(),
+            _ => false,
+        };
+        if shown {
+            self.events.push((at, event.clone()));
+        }
+    }
+
+    pub(crate) fn draw(&mut self, frame: &mut RgbaImage, now: Duration) {
+        self.events.retain(|(at, event)| {
+            let age = now.saturating_sub(*at);
+            match *event {
+                Event::Click { x, y } if age < CLICK_MARKER => {
+                    let center = (x as i32, y as i32);
+                    draw_filled_circle_mut(frame, center, 6, CLICK_COLOR);
+                    for radius in 18..22 {
+                        draw_hollow_circle_mut(frame, center, radius, CLICK_COLOR);
+                    }
+                    true
+                }
+                Event::Swipe {
+                    start,
+                    end,
+                    duration,
+                } if age < duration + SWIPE_TRAIL => {
+                    let start = (start.0 as f32, start.1 as f32);
+                    let end = (end.0 as f32, end.1 as f32);
+                    for offset in -2..=2 {
+                        let offset = offset as f32;
+                        draw_line_segment_mut(
+                            frame,
+                            (start.0 + offset, start.1),
+                            (end.0 + offset, end.1),
+                            SWIPE_COLOR,
+                        );
+                        draw_line_segment_mut(
+                            frame,
+                            (start.0, start.1 + offset),
+                            (end.0, end.1 + offset),
+                            SWIPE_COLOR,
+                        );
+                    }
+                    draw_filled_circle_mut(frame, (start.0 as i32, start.1 as i32), 8, SWIPE_COLOR);
+                    true
+                }
+                Event::MatchResult {
+                    rect: Some(rect), ..
+                } if age < MATCH_MARKER => {
+                    for inset in 0..3 {
+                        if rect.width > inset * 2 && rect.height > inset * 2 {
+                            let rect =
+                                ProcRect::at((rect.x + inset) as i32, (rect.y + inset) as i32)
+                                    .of_size(rect.width - inset * 2, rect.height - inset * 2);
+                            draw_hollow_rect_mut(frame, rect, MATCH_COLOR);
+                        }
+                    }
+                    true
+                }
+                _ => false,
+            }
+        });
+    }
+}
+
+struct Cue {
+    start: Duration,
+    end: Option<Duration>,
+    text: String,
+}
+
+/// The subtitle track: the current step and the recent actions.
+#[derive(Default)]
+struct Subtitles {
+    cues: Vec<Cue>,
+    /// Index of the cue of the running step, it lasts until the next one starts
+    step: Option<usize>,
+}
+
+impl Subtitles {
+    fn push(&mut self, at: Duration, event: &Event) {
+        let (text, lasts) = match event {
+            Event::Click { x, y } => (format!("click ({x}, {y})"), false),
+            Event::Swipe { start, end, .. } => (format!("swipe {start:?} -> {end:?}"), false),
+            Event::Press { key } => (format!("press {key:?}"), false),
+            Event::TaskStarted { name } => (format!("task {name} started"), false),
+            Event::StepStarted {
+                task,
+                index,
+                action,
+            } => (format!("{task} #{index} {action}"), true),
+            Event::TaskFinished { name, error } => match error {
+                Some(error) => (format!("task {name} failed: {error}"), false),
+                None => (format!("task {name} finished"), false),
+            },
+            Event::ControllerSwapped { screen_size, .. } => (
+                format!("controller swapped, {}x{}", screen_size.0, screen_size.1),
+                false,
+            ),
+            Event::ResolutionChanged { screen_size, .. } => (
+                format!("resolution changed, {}x{}", screen_size.0, screen_size.1),
+                false,
+            ),
+            Event::RepeatFinished { iterations, done } => match done {
+                true => (format!("repeated {iterations} times until done"), false),
+                false => (format!("repeated {iterations} times, the cap"), false),
+            },
+            Event::StepFinished {
+                task,
+                index,
+                error: Some(error),
+                ..
+            } => (format!("{task} #{index} failed: {error}"), false),
+            Event::MatchResult {
+                rect: Some(rect), ..
+            } => (format!("found at ({}, {})", rect.x, rect.y), false),
+            Event::ResourceReloaded { path, error } => match error {
+                Some(error) => (
+                    format!("failed to reload {}: {error}", path.display()),
+                    false,
+                ),
+                None => (format!("reloaded {}", path.display()), false),
+            },
+            Event::Guard { action, reason } => (format!("{action} by the guard: {reason}"), false),
+            Event::StepFinished { .. }
+            | Event::MatchResult { .. }
+            | Event::AnnotatedFrame { .. }
+            | Event::MatchFrame { .. }
+            | Event::MatchOverlay { .. } => return,
+        };
+        if lasts || matches!(event, Event::TaskFinished { .. }) {
+            self.end_step(at);
+        }
+        self.cues.push(Cue {
+            start: at,
+            end: (!lasts).then_some(at + EVENT_CUE),
+            text: format!("[{}] {text}", format_time(at, '.')),
+        });
+        if lasts {
+            self.step = Some(self.cues.len() - 1);
+        }
+    }
+
+    fn end_step(&mut self, at: Duration) {
+        if let Some(step) = self.step.take() {
+            self.cues[step].end = Some(at);
+        }
+    }
+
+    fn finish(&mut self, at: Duration) {
+        self.end_step(at);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.cues.is_empty()
+    }
+
+    fn to_srt(&self) -> String {
+        let mut srt = String::new();
+        for (idx, cue) in self.cues.iter().enumerate() {
+            let end = cue.end.unwrap_or(cue.start + EVENT_CUE);
+            let _ = write!(
+                srt,
+                "{}\n{} --> {}\n{}\n\n",
+                idx + 1,
+                format_time(cue.start, ','),
+                format_time(end, ','),
+                cue.text
+            );
+        }
+        srt
+    }
+}
+
+/// `hh:mm:ss{sep}mmm`
+fn format_time(time: Duration, sep: char) -> String {
+    let ms = time.as_millis();
+    format!(
+        "{:02}:{:02}:{:02}{sep}{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{action::Action, task::Task, testing::DummyController};
+
+    #[test]
+    fn test_srt() {
+        let mut subtitles = Subtitles::default();
+        let step = |index| Event::StepStarted {
+            task: "daily".to_string(),
+            index,
+            action: "Click".to_string(),
+        };
+        subtitles.push(Duration::from_millis(1500), &step(0));
+        subtitles.push(Duration::from_millis(1600), &Event::Click { x: 1, y: 2 });
+        subtitles.push(Duration::from_millis(61_000), &step(1));
+        subtitles.finish(Duration::from_millis(62_000));
+
+        assert_eq!(
+            subtitles.to_srt(),
+            "1\n00:00:01,500 --> 00:01:01,000\n[00:00:01.500] daily #0 Click\n\n\
+             2\n00:00:01,600 --> 00:00:02,600\n[00:00:01.600] click (1, 2)\n\n\
+             3\n00:01:01,000 --> 00:01:02,000\n[00:01:01.000] daily #1 Click\n\n"
+        );
+    }
+
+    #[test]
+    fn test_overlay() {
+        let mut overlay = Overlay::default();
+        overlay.push(Duration::ZERO, &Event::Click { x: 50, y: 50 });
+
+        let mut frame = RgbaImage::new(100, 100);
+        overlay.draw(&mut frame, Duration::from_millis(100));
+        assert_eq!(*frame.get_pixel(50, 50), CLICK_COLOR);
+
+        let mut frame = RgbaImage::new(100, 100);
+        overlay.draw(&mut frame, CLICK_MARKER);
+        assert_eq!(*frame.get_pixel(50, 50), Rgba([0, 0, 0, 0]));
+        assert!(overlay.events.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_per_task() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("ap-recorder-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // Writes what it is given to its output, the last argument
+        let ffmpeg = dir.join("ffmpeg");
+        std::fs::write(
+            &ffmpeg,
+            "#!/bin/sh\nfor last; do :; done\n\
+             case \"$*\" in *rawvideo*) cat > \"$last\" ;; *) : > \"$last\" ;; esac\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&ffmpeg, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let ap = Arc::new(AutoPlay::new(DummyController::new(4, 4)));
+        let options = RecorderOptions {
+            ffmpeg,
+            ..Default::default()
+        };
+        let videos = dir.join("videos");
+        let recorder = Recorder::per_task(ap.clone(), &videos, options).unwrap();
+        for name in ["daily", "weekly"] {
+            let task = Task::from_toml(&format!(
+                "name = \"{name}\"\n[[steps]]\nClick = {{ x = 1, y = 2 }}"
+            ))
+            .unwrap();
+            task.execute(&ap).unwrap();
+        }
+        recorder.stop().unwrap();
+
+        let mut names = std::fs::read_dir(&videos)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names.len(), 2);
+        assert!(names[0].starts_with("daily-") && names[0].ends_with(".mp4"));
+        assert!(names[1].starts_with("weekly-") && names[1].ends_with(".mp4"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,470 @@
+//! Reports of task runs, rendered to a self-contained HTML page to review
+//! unattended runs at a glance.
+//!
+//! ```ignore
+//! let report = task.execute_with_report(&ap, true);
+//! report.write_html("daily.html")?;
+//! ```
+//!
+//! They also serialize to JSON, without the screenshots, for logs and dashboards.
+
+use std::{
+    fmt::Write as _,
+    io::Cursor,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use image::{DynamicImage, ImageFormat, Rgba, RgbaImage, imageops::FilterType, math::Rect};
+use imageproc::{
+    drawing::{draw_filled_circle_mut, draw_hollow_rect_mut, draw_line_segment_mut},
+    rect::Rect as ProcRect,
+};
+use serde::{Serialize, Serializer, ser::SerializeStruct};
+
+use crate::{adb::logcat::LogEntry, event::Event};
+
+/// Width of the thumbnails in the step list
+const THUMBNAIL_WIDTH: u32 = 240;
+
+const ANNOTATION_COLOR: Rgba<u8> = Rgba([255, 48, 48, 255]);
+
+/// Something to point out on the screenshot of a step.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Annotation {
+    Click {
+        x: u32,
+        y: u32,
+    },
+    Swipe {
+        start: (u32, u32),
+        end: (i32, i32),
+    },
+    /// A region of interest, i.e. where a template matched
+    Rect {
+        #[serde(serialize_with = "serialize_rect")]
+        rect: Rect,
+        label: String,
+    },
+}
+
+impl Annotation {
+    /// The annotation of an input or a match [`Event`], `None` for the others.
+    pub fn from_event(event: &Event) -> Option<Self> {
+        match *event {
+            Event::Click { x, y } => Some(Self::Click { x, y }),
+            Event::Swipe { start, end, .. } => Some(Self::Swipe { start, end }),
+            Event::MatchResult {
+                rect: Some(rect), ..
+            } => Some(Self::Rect {
+                rect,
+                label: "match".to_string(),
+            }),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn draw(&self, image: &mut RgbaImage) {
+        match self {
+            Annotation::Click { x, y } => {
+                let center = (*x as i32, *y as i32);
+                draw_filled_circle_mut(image, center, 8, ANNOTATION_COLOR);
+            }
+            Annotation::Swipe { start, end } => {
+                for offset in -2..=2 {
+                    let offset = offset as f32;
+                    draw_line_segment_mut(
+                        image,
+                        (start.0 as f32 + offset, start.1 as f32),
+                        (end.0 as f32 + offset, end.1 as f32),
+                        ANNOTATION_COLOR,
+                    );
+                }
+                draw_filled_circle_mut(
+                    image,
+                    (start.0 as i32, start.1 as i32),
+                    8,
+                    ANNOTATION_COLOR,
+                );
+            }
+            Annotation::Rect { rect, .. } => {
+                for inset in 0..3 {
+                    if rect.width > inset * 2 && rect.height > inset * 2 {
+                        let rect = ProcRect::at((rect.x + inset) as i32, (rect.y + inset) as i32)
+                            .of_size(rect.width - inset * 2, rect.height - inset * 2);
+                        draw_hollow_rect_mut(image, rect, ANNOTATION_COLOR);
+                    }
+                }
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Annotation::Click { x, y } => format!("click ({x}, {y})"),
+            Annotation::Swipe { start, end } => format!("swipe {start:?} -> {end:?}"),
+            Annotation::Rect { rect, label } => format!(
+                "{label} ({}, {}) {}x{}",
+                rect.x, rect.y, rect.width, rect.height
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepReport {
+    pub index: usize,
+    /// Type name of the action
+    pub action: String,
+    /// Since the start of the task
+    #[serde(rename = "started_ms", serialize_with = "serialize_millis")]
+    pub started: Duration,
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    pub duration: Duration,
+    pub error: Option<String>,
+    /// The screen right after the step
+    #[serde(skip)]
+    pub screenshot: Option<DynamicImage>,
+    pub annotations: Vec<Annotation>,
+    /// Iterations of a [`Repeat`](crate::action::Repeat) step, or scrolls of a
+    /// [`ScrollFind`](crate::action::ScrollFind) one
+    pub iterations: Option<usize>,
+}
+
+impl StepReport {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Whether a task run went through, see [`ExecutionReport::status`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+/// The outcome of a task run, see [`Task::execute_with_report`](crate::task::Task::execute_with_report).
+#[derive(Debug, Clone)]
+pub struct ExecutionReport {
+    pub task: String,
+    pub started_at: SystemTime,
+    pub duration: Duration,
+    pub steps: Vec<StepReport>,
+    pub error: Option<String>,
+    /// Whether the error is the task being cancelled
+    pub cancelled: bool,
+    /// The end of the logcat when it failed, see
+    /// [`AutoPlay::set_report_logcat`](crate::AutoPlay::set_report_logcat)
+    pub logcat: Option<Vec<LogEntry>>,
+}
+
+impl ExecutionReport {
+    pub fn new(task: impl Into<String>) -> Self {
+        Self {
+            task: task.into(),
+            started_at: SystemTime::now(),
+            duration: Duration::ZERO,
+            steps: Vec::new(),
+            error: None,
+            cancelled: false,
+            logcat: None,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+
+    pub fn status(&self) -> TaskStatus {
+        if self.is_success() {
+            TaskStatus::Succeeded
+        } else if self.cancelled {
+            TaskStatus::Cancelled
+        } else {
+            TaskStatus::Failed
+        }
+    }
+
+    /// The report as pretty-printed JSON, durations in milliseconds and the start
+    /// time in milliseconds since the Unix epoch.
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn write_json(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_json()?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    pub fn to_html(&self) -> anyhow::Result<String> {
+        let mut html = String::new();
+        let status = if self.is_success() { "ok" } else { "failed" };
+        let started_at = self
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        write!(
+            html,
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{task} - {status}</title>
+<style>{STYLE}</style>
+</head>
+<body>
+<h1>{task} <span class="{status}">{status}</span></h1>
+<p>Started <time data-ms="{started_at}"></time>, took {duration}, {steps} steps</p>
+"#,
+            task = escape(&self.task),
+            duration = format_duration(self.duration),
+            steps = self.steps.len(),
+        )?;
+        if let Some(error) = &self.error {
+            writeln!(html, "<pre class=\"error\">{}</pre>", escape(error))?;
+        }
+
+        if let Some(logcat) = &self.logcat {
+            html.push_str("<details><summary>logcat</summary><pre class=\"logcat\">");
+            for entry in logcat {
+                writeln!(html, "{}", escape(&entry.to_string()))?;
+            }
+            html.push_str("</pre></details>\n");
+        }
+
+        html.push_str("<div class=\"timeline\">\n");
+        let total = self.duration.as_secs_f64().max(f64::EPSILON);
+        for step in &self.steps {
+            writeln!(
+                html,
+                "<a href=\"#step-{index}\" class=\"{status}\" style=\"left: {left:.3}%; width: {width:.3}%\" title=\"#{index} {action} {duration}\"></a>",
+                index = step.index,
+                status = if step.is_success() { "ok" } else { "failed" },
+                left = step.started.as_secs_f64() / total * 100.0,
+                width = step.duration.as_secs_f64() / total * 100.0,
+                action = escape(&step.action),
+                duration = format_duration(step.duration),
+            )?;
+        }
+        html.push_str("</div>\n<ol class=\"steps\">\n");
+
+        for step in &self.steps {
+            writeln!(
+                html,
+                "<li id=\"step-{index}\" class=\"{status}\">\n<div class=\"info\">\n<h2>#{index} {action}</h2>\n<p>at {started}, took {duration}</p>",
+                index = step.index,
+                status = if step.is_success() { "ok" } else { "failed" },
+                action = escape(&step.action),
+                started = format_duration(step.started),
+                duration = format_duration(step.duration),
+            )?;
+            if let Some(iterations) = step.iterations {
+                writeln!(html, "<p>{iterations} iterations</p>")?;
+            }
+            if !step.annotations.is_empty() {
+                html.push_str("<ul>\n");
+                for annotation in &step.annotations {
+                    writeln!(html, "<li>{}</li>", escape(&annotation.describe()))?;
+                }
+                html.push_str("</ul>\n");
+            }
+            if let Some(error) = &step.error {
+                writeln!(
+                    html,
+                    "<details open><summary>error</summary><pre class=\"error\">{}</pre></details>",
+                    escape(error)
+                )?;
+            }
+            html.push_str("</div>\n");
+            if let Some(screenshot) = &step.screenshot {
+                let mut screenshot = screenshot.to_rgba8();
+                for annotation in &step.annotations {
+                    annotation.draw(&mut screenshot);
+                }
+                let full = data_url(&DynamicImage::ImageRgba8(screenshot))?;
+                writeln!(
+                    html,
+                    "<a href=\"{full}\" target=\"_blank\"><img src=\"{full}\" width=\"{THUMBNAIL_WIDTH}\"></a>"
+                )?;
+            }
+            html.push_str("</li>\n");
+        }
+        writeln!(html, "</ol>\n<script>{SCRIPT}</script>\n</body>\n</html>")?;
+        Ok(html)
+    }
+
+    pub fn write_html(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_html()?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+impl Serialize for ExecutionReport {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let started_at = self
+            .started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut s = serializer.serialize_struct("ExecutionReport", 7)?;
+        s.serialize_field("task", &self.task)?;
+        s.serialize_field("status", &self.status())?;
+        s.serialize_field("started_at_ms", &started_at)?;
+        s.serialize_field("duration_ms", &(self.duration.as_millis() as u64))?;
+        s.serialize_field("steps", &self.steps)?;
+        s.serialize_field("error", &self.error)?;
+        let logcat = self
+            .logcat
+            .as_ref()
+            .map(|logcat| logcat.iter().map(LogEntry::to_string).collect::<Vec<_>>());
+        s.serialize_field("logcat", &logcat)?;
+        s.end()
+    }
+}
+
+fn serialize_millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+fn serialize_rect<S: Serializer>(rect: &Rect, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut s = serializer.serialize_struct("Rect", 4)?;
+    s.serialize_field("x", &rect.x)?;
+    s.serialize_field("y", &rect.y)?;
+    s.serialize_field("width", &rect.width)?;
+    s.serialize_field("height", &rect.height)?;
+    s.end()
+}
+
+/// `image` as a JPEG `data:` URL, downscaled to 1080p at most to keep the page small.
+fn data_url(image: &DynamicImage) -> anyhow::Result<String> {
+    let image = if image.height() > 1080 {
+        image.resize(u32::MAX, 1080, FilterType::Triangle)
+    } else {
+        image.clone()
+    };
+    let mut jpeg = Vec::new();
+    DynamicImage::ImageRgb8(image.to_rgb8())
+        .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)?;
+    Ok(format!("data:image/jpeg;base64,{}", STANDARD.encode(jpeg)))
+}
+
+fn format_duration(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        format!("{}ms", duration.as_millis())
+    } else if duration < Duration::from_secs(60) {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else {
+        let secs = duration.as_secs();
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut res = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => res.push_str("&amp;"),
+            '<' => res.push_str("&lt;"),
+            '>' => res.push_str("&gt;"),
+            '"' => res.push_str("&quot;"),
+            '\'' => res.push_str("&#39;"),
+            c => res.push(c),
+        }
+    }
+    res
+}
+
+const STYLE: &str = r#"
+body { font-family: sans-serif; margin: 2em; color: #222; }
+.ok { color: #2a7d2a; }
+.failed { color: #c62828; }
+pre.error { color: #c62828; background: #fdecea; padding: 0.5em; white-space: pre-wrap; }
+pre.logcat { font-size: 0.8em; background: #f5f5f5; padding: 0.5em; overflow-x: auto; }
+.timeline { position: relative; height: 24px; background: #eee; margin: 1em 0; }
+.timeline a { position: absolute; top: 0; bottom: 0; min-width: 2px; border-right: 1px solid #fff; }
+.timeline a.ok { background: #66bb6a; }
+.timeline a.failed { background: #ef5350; }
+.steps { list-style: none; padding: 0; }
+.steps > li { display: flex; justify-content: space-between; gap: 1em; padding: 0.5em; border-bottom: 1px solid #ddd; }
+.steps > li.failed { background: #fff5f5; }
+.steps h2 { font-size: 1em; margin: 0; }
+.steps img { border: 1px solid #ccc; }
+"#;
+
+/// Shows the start time in the timezone of the reader.
+const SCRIPT: &str = r#"
+for (const time of document.querySelectorAll("time[data-ms]")) {
+    time.textContent = new Date(Number(time.dataset.ms)).toLocaleString();
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> ExecutionReport {
+        let mut report = ExecutionReport::new("daily <test>");
+        report.duration = Duration::from_millis(1500);
+        report.steps.push(StepReport {
+            index: 0,
+            action: "Click".to_string(),
+            started: Duration::ZERO,
+            duration: Duration::from_millis(500),
+            error: None,
+            screenshot: Some(DynamicImage::new_rgba8(64, 32)),
+            annotations: vec![Annotation::Click { x: 10, y: 10 }],
+            iterations: None,
+        });
+        report.steps.push(StepReport {
+            index: 1,
+            action: "WaitAction".to_string(),
+            started: Duration::from_millis(500),
+            duration: Duration::from_secs(1),
+            error: Some("timed out".to_string()),
+            screenshot: None,
+            annotations: Vec::new(),
+            iterations: None,
+        });
+        report.error = Some("task daily failed at step 1: timed out".to_string());
+        report.logcat = Some(vec![
+            LogEntry::parse("05-14 12:31:08.001 23456 23470 E Unity   : <null> reference").unwrap(),
+        ]);
+        report
+    }
+
+    #[test]
+    fn test_to_html() {
+        let html = report().to_html().unwrap();
+        assert!(html.contains("daily &lt;test&gt;"));
+        assert!(html.contains("click (10, 10)"));
+        assert!(html.contains("data:image/jpeg;base64,"));
+        assert!(html.contains("<pre class=\"error\">timed out</pre>"));
+        assert!(html.contains("left: 33.333%; width: 66.667%"));
+        assert!(html.contains("E Unity: &lt;null&gt; reference"));
+    }
+
+    #[test]
+    fn test_to_json() {
+        let json: serde_json::Value = serde_json::from_str(&report().to_json().unwrap()).unwrap();
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["duration_ms"], 1500);
+        assert_eq!(json["steps"][0]["action"], "Click");
+        assert_eq!(json["steps"][0]["annotations"][0]["click"]["x"], 10);
+        assert!(json["steps"][0].get("screenshot").is_none());
+        assert_eq!(json["steps"][1]["started_ms"], 500);
+        assert_eq!(json["steps"][1]["error"], "timed out");
+        assert_eq!(
+            json["logcat"][0],
+            "05-14 12:31:08.001 23456 23470 E Unity: <null> reference"
+        );
+    }
+}
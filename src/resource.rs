@@ -0,0 +1,344 @@
+//! The tasks of a directory, reloaded as they are edited
+//!
+//! ```ignore
+//! let ap = Arc::new(AutoPlay::new(controller));
+//! ap.set_resource(Resource::load("resources")?);
+//! // Reloads the tasks and the templates on every save
+//! let _watcher = Resource::watch(&ap)?;
+//! ap.resource().task("daily").unwrap().execute(&ap)?;
+//! ap.run_task_with("farm", HashMap::from([("stage".to_string(), "1-7".into())]))?;
+//! ap.resource().nav().unwrap().navigate_to(&ap, "shop")?;
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Context;
+use notify::{EventKind, RecursiveMode, Watcher};
+use tracing::warn;
+
+use crate::{
+    AutoPlay,
+    event::Event,
+    nav::NavGraph,
+    task::{Task, Value},
+};
+
+/// The navigation graph of a resource, at its root, see [`NavGraph::from_toml`]
+pub const NAV: &str = "nav.toml";
+
+/// The manifest of a resource pack, listing its files, which is not a task
+pub const MANIFEST: &str = "manifest.toml";
+
+/// Every task of a directory and its subdirectories, the `.toml` files, by name.
+///
+/// The templates are loaded by [`AutoPlay::templates`] the first time they are
+/// matched. The source of each task is kept, the parameters are substituted when
+/// it is run with arguments, see [`Resource::task_with`].
+///
+/// The [`NAV`] file at the root is the navigation graph, not a task.
+#[derive(Default)]
+pub struct Resource {
+    root: PathBuf,
+    tasks: BTreeMap<String, Entry>,
+    /// The source of the navigation graph, checked to be valid
+    nav: Option<String>,
+}
+
+impl Resource {
+    /// Load the tasks under `root`, failing if one of them is invalid. A task with
+    /// required parameters is only checked once it is given them.
+    pub fn load(root: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let root = root.into();
+        let mut tasks = BTreeMap::new();
+        let mut dirs = vec![root.clone()];
+        while let Some(dir) = dirs.pop() {
+            let entries = std::fs::read_dir(&dir)
+                .with_context(|| format!("failed to read {}", dir.display()))?;
+            for entry in entries {
+                let path = entry?.path();
+                if path.is_dir() {
+                    dirs.push(path);
+                } else if path.extension().is_some_and(|ext| ext == "toml")
+                    && !path.ends_with(MANIFEST)
+                    && path != root.join(NAV)
+                {
+                    let entry = Entry::load(path)?;
+                    if tasks.contains_key(&entry.name) {
+                        anyhow::bail!(
+                            "task {} is defined twice, in {}",
+                            entry.name,
+                            entry.path.display()
+                        );
+                    }
+                    tasks.insert(entry.name.clone(), entry);
+                }
+            }
+        }
+        let path = root.join(NAV);
+        let nav = if path.exists() {
+            let source = std::fs::read_to_string(&path)?;
+            NavGraph::from_toml(&source).with_context(|| format!("in {}", path.display()))?;
+            Some(source)
+        } else {
+            None
+        };
+        Ok(Self { root, tasks, nav })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// The task `name` with the defaults of its parameters, `None` if it has
+    /// required ones, see [`Resource::task_with`].
+    pub fn task(&self, name: &str) -> Option<Arc<Task>> {
+        self.tasks.get(name)?.task.clone()
+    }
+
+    /// The task `name` with the parameters in `args`, see [`Task::from_toml_with`].
+    pub fn task_with(&self, name: &str, args: &HashMap<String, Value>) -> anyhow::Result<Task> {
+        let entry = self
+            .tasks
+            .get(name)
+            .with_context(|| format!("no task named {name} in the resource"))?;
+        Task::from_toml_with(&entry.source, args)
+            .with_context(|| format!("in {}", entry.path.display()))
+    }
+
+    /// The names of the tasks, with required parameters or not.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.tasks.keys().map(String::as_str)
+    }
+
+    /// The navigation graph of the [`NAV`] file, built anew on each call as its
+    /// nodes are not shared between threads.
+    pub fn nav(&self) -> Option<NavGraph> {
+        self.nav
+            .as_ref()
+            .map(|source| NavGraph::from_toml(source).expect("checked when loaded"))
+    }
+
+    pub(crate) fn nav_source(&self) -> Option<&str> {
+        self.nav.as_deref()
+    }
+
+    /// Watch the root of the resource of `ap`. When a task changes all of them
+    /// are loaded again and swapped in at once, when a template changes it is
+    /// decoded again, see [`TemplateStore::reload`](crate::TemplateStore::reload).
+    /// Each reload is reported with [`Event::ResourceReloaded`].
+    ///
+    /// Runs until the watcher is dropped. Tasks running carry on with the version
+    /// they started with.
+    pub fn watch(ap: &Arc<AutoPlay>) -> anyhow::Result<ResourceWatcher> {
+        let root = ap.resource().root.clone();
+        // The watcher does not keep the `AutoPlay` alive
+        let weak = Arc::downgrade(ap);
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+                let event = match res {
+                    Ok(event) => event,
+                    Err(err) => return warn!("failed to watch the resource: {err}"),
+                };
+                if !matches!(
+                    event.kind,
+                    EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+                ) {
+                    return;
+                }
+                if let Some(ap) = weak.upgrade() {
+                    for path in &event.paths {
+                        reload(&ap, path);
+                    }
+                }
+            })?;
+        watcher
+            .watch(&root, RecursiveMode::Recursive)
+            .with_context(|| format!("failed to watch {}", root.display()))?;
+        Ok(ResourceWatcher { _watcher: watcher })
+    }
+}
+
+/// A task file of a [`Resource`]
+struct Entry {
+    name: String,
+    path: PathBuf,
+    source: String,
+    /// With the defaults of its parameters, `None` if one of them is required
+    task: Option<Arc<Task>>,
+}
+
+impl Entry {
+    fn load(path: PathBuf) -> anyhow::Result<Self> {
+        let source = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read task {}", path.display()))?;
+        let in_path = || format!("in {}", path.display());
+        let (name, params) = Task::declaration(&source).with_context(in_path)?;
+        let task = if params.values().all(|param| param.default.is_some()) {
+            Some(Arc::new(Task::from_toml(&source).with_context(in_path)?))
+        } else {
+            None
+        };
+        Ok(Self {
+            name,
+            path,
+            source,
+            task,
+        })
+    }
+}
+
+/// Stops watching when dropped, see [`Resource::watch`]
+pub struct ResourceWatcher {
+    _watcher: notify::RecommendedWatcher,
+}
+
+fn reload(ap: &AutoPlay, path: &Path) {
+    let res = if path.extension().is_some_and(|ext| ext == "toml") {
+        Resource::load(ap.resource().root()).map(|resource| ap.set_resource(resource))
+    } else if image::ImageFormat::from_path(path).is_ok() {
+        match ap.templates().reload(path) {
+            Ok(true) => Ok(()),
+            // Not matched yet, it is loaded when it is
+            Ok(false) => return,
+            Err(err) => Err(err.into()),
+        }
+    } else {
+        return;
+    };
+    if let Err(err) = &res {
+        warn!("failed to reload {}: {err:#}", path.display());
+    }
+    ap.events().emit(Event::ResourceReloaded {
+        path: path.to_path_buf(),
+        error: res.err().map(|err| format!("{err:#}")),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use image::DynamicImage;
+
+    use super::*;
+    use crate::testing::DummyController;
+
+    /// The next reload of `path`, skipping the other events
+    fn reloaded(events: &std::sync::mpsc::Receiver<Event>, path: &Path) -> Option<String> {
+        loop {
+            match events
+                .recv_timeout(Duration::from_secs(5))
+                .expect("no reload within 5s")
+            {
+                Event::ResourceReloaded { path: p, error } if p.ends_with(path) => return error,
+                _ => {}
+            }
+        }
+    }
+
+    /// Write `contents` at once, as editors save, so it is never seen half written
+    fn save(path: &Path, contents: &[u8]) {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, contents).unwrap();
+        std::fs::rename(&tmp, path).unwrap();
+    }
+
+    #[test]
+    fn test_watch() {
+        let root = std::env::temp_dir().join(format!("ap-resource-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(root.join("daily")).unwrap();
+        let task = root.join("daily/daily.toml");
+        std::fs::write(&task, "name = \"daily\"").unwrap();
+        let template = root.join("button.png");
+        DynamicImage::new_rgb8(4, 4).save(&template).unwrap();
+
+        let ap = Arc::new(AutoPlay::new(DummyController::new(1, 1)));
+        ap.set_resource(Resource::load(&root).unwrap());
+        assert_eq!(ap.resource().task("daily").unwrap().steps.len(), 0);
+        let handle = ap.templates().load(&template).unwrap();
+
+        let events = ap.subscribe();
+        let _watcher = Resource::watch(&ap).unwrap();
+
+        save(
+            &task,
+            b"name = \"daily\"\n[[steps]]\nClick = { x = 1, y = 2 }",
+        );
+        assert_eq!(reloaded(&events, Path::new("daily.toml")), None);
+        assert_eq!(ap.resource().task("daily").unwrap().steps.len(), 1);
+
+        // An invalid version is not swapped in. The save above may still be
+        // reloaded again first, its events come in several parts.
+        std::fs::write(&task, "name = ").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while reloaded(&events, Path::new("daily.toml")).is_none() {
+            assert!(
+                Instant::now() < deadline,
+                "the invalid version was not reloaded"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(ap.resource().task("daily").unwrap().steps.len(), 1);
+
+        let mut png = Vec::new();
+        DynamicImage::new_rgb8(8, 8)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        save(&template, &png);
+        assert_eq!(reloaded(&events, Path::new("button.png")), None);
+        let reloaded = ap.templates().get(&template).unwrap();
+        assert_ne!(reloaded, handle);
+        assert_eq!(reloaded.template().image.width(), 8);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_required_params() {
+        let root = std::env::temp_dir().join(format!("ap-resource-params-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(
+            root.join("farm.toml"),
+            r#"
+name = "farm"
+
+[params.stage]
+[params.times]
+default = 2
+
+[[steps]]
+[steps.Repeat]
+until = { Not = { Succeeds = { PluginAction = { name = "stamina" } } } }
+max_iters = "${times}"
+steps = [{ PluginAction = { name = "farm_${stage}" } }]
+"#,
+        )
+        .unwrap();
+        std::fs::write(root.join("daily.toml"), "name = \"daily\"").unwrap();
+
+        let resource = Resource::load(&root).unwrap();
+        assert_eq!(resource.names().collect::<Vec<_>>(), ["daily", "farm"]);
+        assert!(resource.task("daily").is_some());
+        // Not runnable without its stage
+        assert!(resource.task("farm").is_none());
+        let err = resource.task_with("farm", &HashMap::new()).err().unwrap();
+        assert!(
+            format!("{err:#}").contains("missing parameter stage"),
+            "{err:#}"
+        );
+
+        let args = HashMap::from([("stage".to_string(), Value::from("1-7"))]);
+        let task = resource.task_with("farm", &args).unwrap();
+        assert_eq!(task.steps.len(), 1);
+        let err = resource.task_with("weekly", &args).err().unwrap();
+        assert_eq!(err.to_string(), "no task named weekly in the resource");
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
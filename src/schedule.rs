@@ -0,0 +1,345 @@
+//! Run tasks on a schedule, i.e. the daily check-in at 5:00 and farming every
+//! time the stamina is back.
+//!
+//! ```ignore
+//! let mut scheduler = Scheduler::new().with_state("schedule.json")?;
+//! scheduler.add(Job::new(Task::load("daily.toml")?, Trigger::cron("0 5 * * *")?));
+//! scheduler.add(
+//!     Job::new(Task::load("farm.toml")?, Trigger::every(Duration::from_secs(3600)))
+//!         .overlap(OverlapPolicy::Skip),
+//! );
+//! scheduler.run_forever(&ap)?;
+//! ```
+//!
+//! Jobs run one at a time on the same [`AutoPlay`], the last run of each is kept
+//! in the state file so a run missed while the program was not running is done
+//! when it starts again.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Local};
+use tracing::{info, warn};
+
+use crate::{AutoPlay, Error, cancel::CancellationToken, task::Task};
+
+/// Longest sleep between two checks of the schedule, so a cancellation is not
+/// held up until the next job.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When a [`Job`] runs
+#[derive(Debug, Clone)]
+pub enum Trigger {
+    /// Every interval since the start of the last run, right away if it never ran
+    Interval(Duration),
+    /// At the times of a cron expression, in local time
+    Cron(Box<cron::Schedule>),
+}
+
+impl Trigger {
+    pub fn every(interval: Duration) -> Self {
+        Self::Interval(interval)
+    }
+
+    /// A cron expression, of 5 fields as in crontab or of 6 or 7 starting with
+    /// the seconds, see [`cron::Schedule`].
+    pub fn cron(expr: &str) -> anyhow::Result<Self> {
+        let expr = match expr.split_whitespace().count() {
+            5 => format!("0 {expr}"),
+            _ => expr.to_string(),
+        };
+        let schedule = cron::Schedule::from_str(&expr)
+            .with_context(|| format!("invalid cron expression {expr:?}"))?;
+        Ok(Self::Cron(Box::new(schedule)))
+    }
+
+    /// The first run after `last`, or after `now` if it never ran.
+    pub fn next(&self, last: Option<SystemTime>, now: SystemTime) -> Option<SystemTime> {
+        match self {
+            Trigger::Interval(interval) => Some(last.map_or(now, |last| last + *interval)),
+            Trigger::Cron(schedule) => {
+                let after = DateTime::<Local>::from(last.unwrap_or(now));
+                schedule.after(&after).next().map(SystemTime::from)
+            }
+        }
+    }
+}
+
+/// What to do with a run that came due while another job was running
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OverlapPolicy {
+    /// Run it once the other job is done
+    #[default]
+    Queue,
+    /// Drop it and wait for the next one
+    Skip,
+}
+
+/// A task and when to run it
+pub struct Job {
+    pub task: Task,
+    pub trigger: Trigger,
+    pub overlap: OverlapPolicy,
+    last_run: Option<SystemTime>,
+    next_run: Option<SystemTime>,
+}
+
+impl Job {
+    pub fn new(task: Task, trigger: Trigger) -> Self {
+        Self {
+            task,
+            trigger,
+            overlap: OverlapPolicy::default(),
+            last_run: None,
+            next_run: None,
+        }
+    }
+
+    pub fn overlap(mut self, overlap: OverlapPolicy) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Name of the task, jobs are told apart by it in the state file
+    pub fn name(&self) -> &str {
+        &self.task.name
+    }
+
+    pub fn last_run(&self) -> Option<SystemTime> {
+        self.last_run
+    }
+
+    /// `None` once the trigger has no more runs, i.e. a cron expression of a past year
+    pub fn next_run(&self) -> Option<SystemTime> {
+        self.next_run
+    }
+}
+
+#[derive(Default)]
+pub struct Scheduler {
+    jobs: Vec<Job>,
+    /// Where the last runs are kept
+    state: Option<PathBuf>,
+    /// Last runs of the state file, for the jobs not added yet
+    loaded: HashMap<String, u64>,
+    /// When the last run ended, the runs due before it overlapped with it
+    busy_until: Option<SystemTime>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep the last run of each job in the JSON file at `path`, loading the
+    /// ones it already has.
+    pub fn with_state(mut self, path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        if path.exists() {
+            let state = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            self.loaded = serde_json::from_str(&state)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+        }
+        self.state = Some(path);
+        Ok(self)
+    }
+
+    /// Add `job`, replacing the one of the same name.
+    pub fn add(&mut self, mut job: Job) {
+        job.last_run = self
+            .loaded
+            .get(job.name())
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(*secs));
+        job.next_run = job.trigger.next(job.last_run, SystemTime::now());
+        self.jobs.retain(|j| j.name() != job.name());
+        self.jobs.push(job);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Job> {
+        let idx = self.jobs.iter().position(|job| job.name() == name)?;
+        Some(self.jobs.remove(idx))
+    }
+
+    pub fn jobs(&self) -> &[Job] {
+        &self.jobs
+    }
+
+    /// The job to run next and when.
+    pub fn next(&self) -> Option<(&Job, SystemTime)> {
+        self.jobs
+            .iter()
+            .filter_map(|job| job.next_run.map(|next| (job, next)))
+            .min_by_key(|(_, next)| *next)
+    }
+
+    /// Run the jobs due now, the earliest first, and return how many ran. A
+    /// failing task is logged and its job runs again at its next time.
+    ///
+    /// Fails with [`Error::Cancelled`] if `token` is cancelled.
+    pub fn run_pending(
+        &mut self,
+        ap: &AutoPlay,
+        token: &CancellationToken,
+    ) -> anyhow::Result<usize> {
+        let mut count = 0;
+        loop {
+            let now = SystemTime::now();
+            let Some(idx) = self
+                .jobs
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, job)| job.next_run.map(|next| (idx, next)))
+                .filter(|(_, next)| *next <= now)
+                .min_by_key(|(_, next)| *next)
+                .map(|(idx, _)| idx)
+            else {
+                return Ok(count);
+            };
+            token.check()?;
+
+            let job = &mut self.jobs[idx];
+            let overlapped = self
+                .busy_until
+                .zip(job.next_run)
+                .is_some_and(|(busy_until, next)| next < busy_until);
+            if overlapped && job.overlap == OverlapPolicy::Skip {
+                info!("skipping {}, it came due during another run", job.name());
+                job.next_run = job.trigger.next(Some(now), now);
+                continue;
+            }
+
+            info!("running {}...", job.name());
+            let res = job.task.execute_cancellable(ap, token);
+            job.last_run = Some(now);
+            job.next_run = job.trigger.next(Some(now), now);
+            self.busy_until = Some(SystemTime::now());
+            count += 1;
+            if let Err(err) = res {
+                if matches!(err.downcast_ref::<Error>(), Some(Error::Cancelled)) {
+                    return Err(err);
+                }
+                warn!("{err:#}");
+            }
+            self.save()?;
+        }
+    }
+
+    /// Run the jobs as they come due until `token` is cancelled.
+    pub fn run_until(&mut self, ap: &AutoPlay, token: &CancellationToken) -> anyhow::Result<()> {
+        loop {
+            match self.run_pending(ap, token) {
+                Err(err) if matches!(err.downcast_ref::<Error>(), Some(Error::Cancelled)) => {
+                    return Ok(());
+                }
+                res => res?,
+            };
+            if token.is_cancelled() {
+                return Ok(());
+            }
+            let wait = self
+                .next()
+                .map(|(_, next)| next.duration_since(SystemTime::now()).unwrap_or_default())
+                .unwrap_or(POLL_INTERVAL);
+            std::thread::sleep(wait.min(POLL_INTERVAL));
+        }
+    }
+
+    /// Run the jobs as they come due, only returning if the state cannot be saved.
+    pub fn run_forever(&mut self, ap: &AutoPlay) -> anyhow::Result<()> {
+        self.run_until(ap, &CancellationToken::new())
+    }
+
+    fn save(&mut self) -> anyhow::Result<()> {
+        let Some(path) = &self.state else {
+            return Ok(());
+        };
+        for job in &self.jobs {
+            if let Some(last_run) = job.last_run {
+                let secs = last_run
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                self.loaded.insert(job.name().to_string(), secs);
+            }
+        }
+        std::fs::write(path, serde_json::to_string_pretty(&self.loaded)?)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    };
+
+    use chrono::TimeZone;
+
+    use super::*;
+    use crate::testing::DummyController;
+
+    #[test]
+    fn test_trigger() {
+        let now = SystemTime::from(Local.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap());
+
+        let daily = Trigger::cron("30 5 * * *").unwrap();
+        let next = Local.with_ymd_and_hms(2024, 1, 2, 5, 30, 0).unwrap();
+        assert_eq!(daily.next(None, now), Some(next.into()));
+        let last = SystemTime::from(Local.with_ymd_and_hms(2023, 12, 1, 5, 30, 0).unwrap());
+        assert!(daily.next(Some(last), now).unwrap() < now);
+        assert!(Trigger::cron("every day").is_err());
+
+        let hourly = Trigger::every(Duration::from_secs(3600));
+        assert_eq!(hourly.next(None, now), Some(now));
+        assert_eq!(
+            hourly.next(Some(now), now),
+            Some(now + Duration::from_secs(3600))
+        );
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let ap = AutoPlay::new(DummyController::new(1, 1));
+        let count = Arc::new(AtomicU32::new(0));
+        {
+            let count = count.clone();
+            ap.plugins().register("count", move |_| {
+                count.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            });
+        }
+        let job = || {
+            let task =
+                Task::from_toml("name = \"farm\"\n[[steps]]\nPluginAction = { name = \"count\" }")
+                    .unwrap();
+            Job::new(task, Trigger::every(Duration::from_secs(3600)))
+        };
+        let state = std::env::temp_dir().join(format!("ap-schedule-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&state);
+
+        let token = CancellationToken::new();
+        let mut scheduler = Scheduler::new().with_state(&state).unwrap();
+        scheduler.add(job());
+        assert_eq!(scheduler.run_pending(&ap, &token).unwrap(), 1);
+        assert_eq!(scheduler.run_pending(&ap, &token).unwrap(), 0);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        // The last run is kept across restarts
+        let mut scheduler = Scheduler::new().with_state(&state).unwrap();
+        scheduler.add(job());
+        assert!(scheduler.jobs()[0].last_run().is_some());
+        assert_eq!(scheduler.run_pending(&ap, &token).unwrap(), 0);
+
+        token.cancel();
+        scheduler.run_until(&ap, &token).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        std::fs::remove_file(&state).unwrap();
+    }
+}
@@ -0,0 +1,837 @@
+//! Drive devices over HTTP, behind the `server` feature, i.e. from a web
+//! dashboard or another machine
+//!
+//! Requests and responses are JSON unless noted:
+//!
+//! | Request                              | Response                                  |
+//! |--------------------------------------|-------------------------------------------|
+//! | `GET /devices`                       | the names of the devices                  |
+//! | `POST /devices` `{"serial"}`         | connect to an Android device              |
+//! | `DELETE /devices/{name}`             | disconnect it                             |
+//! | `GET /devices/{name}/screencap`      | a PNG, or a JPEG with `?format=jpeg&quality=` |
+//! | `POST /devices/{name}/click` `{"x", "y"}` |                                      |
+//! | `POST /devices/{name}/swipe` `{"start", "end", "duration_ms"}` |                 |
+//! | `POST /devices/{name}/press` `{"key"}` | a [`Key`], i.e. `"Back"`                |
+//! | `POST /devices/{name}/runs` task TOML | start the task, `{"id"}` of the run      |
+//! | `GET /devices/{name}/events`         | its events as they happen, one JSON per line |
+//! | `GET /devices/{name}/live`           | a WebSocket of [`LiveView`] JPEG frames, `?fps=` |
+//! | `GET /runs`, `GET /runs/{id}`        | the runs, `"running"` or with their report |
+//! | `POST /runs/{id}/cancel`             | stop the run before its next step         |
+//!
+//! Errors are `{"error"}` with a 4xx or 5xx status. The events are [`Event::to_json`],
+//! and only the last [`KEPT_RUNS`] finished runs are kept.
+//!
+//! ```ignore
+//! let server = Server::new(Fleet::new());
+//! server.fleet().connect("emulator-5554")?;
+//! server.run("127.0.0.1:7878")?;
+//! ```
+
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex, MutexGuard, mpsc},
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tracing::{info, warn};
+use tungstenite::{
+    Error as WsError, Message, WebSocket, handshake::derive_accept_key, protocol::Role,
+};
+
+use crate::{
+    AndroidController, AutoPlay, CancellationToken,
+    action::Key,
+    event::Event,
+    fleet::Fleet,
+    live_view::{LiveView, LiveViewOptions},
+    report::ExecutionReport,
+    task::Task,
+};
+
+/// Headers larger than this are refused
+const MAX_HEADERS: usize = 16 * 1024;
+/// Bodies larger than this are refused
+const MAX_BODY: usize = 16 * 1024 * 1024;
+/// How often an event stream sends an empty line when there is no event, to
+/// notice the client is gone
+const HEARTBEAT: Duration = Duration::from_secs(10);
+/// How long a live view waits for the messages of its client between two frames
+const CLIENT_POLL: Duration = Duration::from_millis(1);
+/// Of `GET /devices/{name}/screencap?format=jpeg` without a `quality`
+const DEFAULT_JPEG_QUALITY: u8 = 80;
+/// How many finished runs are kept for `GET /runs`, the oldest ones are
+/// forgotten when a run starts
+pub const KEPT_RUNS: usize = 64;
+
+/// A task started with `POST /devices/{name}/runs`
+struct Run {
+    id: u64,
+    device: String,
+    task: String,
+    token: CancellationToken,
+    /// `None` while it is running
+    report: Option<ExecutionReport>,
+}
+
+impl Run {
+    fn to_json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "device": self.device,
+            "task": self.task,
+            "status": match &self.report {
+                Some(report) => json!(report.status()),
+                None => json!("running"),
+            },
+            "report": self.report,
+        })
+    }
+}
+
+/// Serves the devices of a [`Fleet`] over HTTP, see the [module](self) docs.
+pub struct Server {
+    fleet: Mutex<Fleet>,
+    runs: Mutex<Runs>,
+}
+
+/// The runs by id, the oldest first
+#[derive(Default)]
+struct Runs {
+    next_id: u64,
+    runs: VecDeque<Arc<Mutex<Run>>>,
+}
+
+impl Runs {
+    fn get(&self, id: u64) -> Option<Arc<Mutex<Run>>> {
+        self.runs
+            .iter()
+            .find(|run| run.lock().unwrap().id == id)
+            .cloned()
+    }
+
+    /// Forget the oldest finished runs past [`KEPT_RUNS`].
+    fn prune(&mut self) {
+        let mut finished = self
+            .runs
+            .iter()
+            .filter(|run| run.lock().unwrap().report.is_some())
+            .count();
+        self.runs.retain(|run| {
+            if finished > KEPT_RUNS && run.lock().unwrap().report.is_some() {
+                finished -= 1;
+                return false;
+            }
+            true
+        });
+    }
+}
+
+impl Server {
+    pub fn new(fleet: Fleet) -> Arc<Self> {
+        Arc::new(Self {
+            fleet: Mutex::new(fleet),
+            runs: Mutex::new(Runs::default()),
+        })
+    }
+
+    pub fn fleet(&self) -> MutexGuard<'_, Fleet> {
+        self.fleet.lock().unwrap()
+    }
+
+    /// Listen on `addr` and serve until the listener fails.
+    pub fn run(self: &Arc<Self>, addr: impl ToSocketAddrs) -> anyhow::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        info!("listening on {}", listener.local_addr()?);
+        self.serve(listener)
+    }
+
+    /// Serve the connections of `listener`, each on its own thread.
+    pub fn serve(self: &Arc<Self>, listener: TcpListener) -> anyhow::Result<()> {
+        for stream in listener.incoming() {
+            let stream = stream?;
+            let server = self.clone();
+            thread::Builder::new()
+                .name("server".to_string())
+                .spawn(move || {
+                    if let Err(err) = server.handle(stream) {
+                        warn!("failed to handle request: {err:#}");
+                    }
+                })?;
+        }
+        Ok(())
+    }
+
+    fn handle(&self, mut stream: TcpStream) -> anyhow::Result<()> {
+        let request = match Request::read(&mut stream) {
+            Ok(request) => request,
+            Err(err) => {
+                return Response::error(400, format!("{err:#}")).write(&mut stream);
+            }
+        };
+        let path = request.path.split('/').filter(|s| !s.is_empty());
+        let path = path.collect::<Vec<_>>();
+        let response = match (request.method.as_str(), path.as_slice()) {
+            ("GET", ["devices", name, "events"]) => match self.device(name) {
+                Ok(ap) => return stream_events(&mut stream, ap.subscribe()),
+                Err(err) => Err(err),
+            },
+            ("GET", ["devices", name, "live"]) => match self.device(name) {
+                Ok(ap) => return stream_live(&mut stream, &request, ap),
+                Err(err) => Err(err),
+            },
+            (method, path) => self.route(method, path, &request),
+        };
+        response
+            .unwrap_or_else(|err| Response::error(err.status, err.message))
+            .write(&mut stream)
+    }
+
+    fn route(&self, method: &str, path: &[&str], request: &Request) -> Result<Response, HttpError> {
+        match (method, path) {
+            ("GET", ["devices"]) => Ok(Response::json(json!(
+                self.fleet().names().collect::<Vec<_>>()
+            ))),
+            ("POST", ["devices"]) => {
+                #[derive(Deserialize)]
+                struct Connect {
+                    serial: String,
+                }
+                let Connect { serial } = request.json()?;
+                // Not holding the fleet while connecting, which takes a while
+                let ap = AutoPlay::new(AndroidController::connect(&serial)?);
+                self.fleet().add(&serial, ap)?;
+                Ok(Response::json(json!({ "name": serial })).with_status(201))
+            }
+            ("DELETE", ["devices", name]) => {
+                self.fleet()
+                    .remove(name)
+                    .ok_or_else(|| HttpError::not_found(format!("unknown device {name}")))?;
+                Ok(Response::empty())
+            }
+            ("GET", ["devices", name, "screencap"]) => {
+                let device = self.device(name)?;
+                let quality = match request.query("quality") {
+                    Some(quality) => quality.parse().map_err(|err| {
+                        HttpError::bad_request(format!("invalid quality {quality}: {err}"))
+                    })?,
+                    None => DEFAULT_JPEG_QUALITY,
+                };
+                let (body, content_type) = match request.query("format") {
+                    None | Some("png") => {
+                        let mut png = Vec::new();
+                        device
+                            .screencap()?
+                            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+                            .map_err(anyhow::Error::from)?;
+                        (png, "image/png")
+                    }
+                    Some("jpeg" | "jpg") => (device.screencap_jpeg(quality)?, "image/jpeg"),
+                    Some(format) => {
+                        return Err(HttpError::bad_request(format!(
+                            "unsupported format {format}, expected png or jpeg"
+                        )));
+                    }
+                };
+                Ok(Response::new(200, content_type, body))
+            }
+            ("POST", ["devices", name, "click"]) => {
+                #[derive(Deserialize)]
+                struct Click {
+                    x: u32,
+                    y: u32,
+                }
+                let Click { x, y } = request.json()?;
+                self.device(name)?.click(x, y)?;
+                Ok(Response::empty())
+            }
+            ("POST", ["devices", name, "swipe"]) => {
+                #[derive(Deserialize)]
+                struct Swipe {
+                    start: (u32, u32),
+                    end: (i32, i32),
+                    #[serde(default = "default_swipe_ms")]
+                    duration_ms: u64,
+                }
+                let swipe = request.json::<Swipe>()?;
+                let duration = Duration::from_millis(swipe.duration_ms);
+                self.device(name)?
+                    .swipe(swipe.start, swipe.end, duration, 1.0, 1.0)?;
+                Ok(Response::empty())
+            }
+            ("POST", ["devices", name, "press"]) => {
+                #[derive(Deserialize)]
+                struct Press {
+                    key: Key,
+                }
+                let Press { key } = request.json()?;
+                self.device(name)?.press(key.into())?;
+                Ok(Response::empty())
+            }
+            ("POST", ["devices", name, "runs"]) => {
+                let source = std::str::from_utf8(&request.body)
+                    .map_err(|err| HttpError::bad_request(err.to_string()))?;
+                let task = Task::from_toml(source)
+                    .map_err(|err| HttpError::bad_request(format!("{err:#}")))?;
+                let id = self.start_run(name, task)?;
+                Ok(Response::json(json!({ "id": id })).with_status(201))
+            }
+            ("GET", ["runs"]) => {
+                let runs = self.runs.lock().unwrap();
+                let runs = runs.runs.iter().map(|run| run.lock().unwrap().to_json());
+                Ok(Response::json(Value::Array(runs.collect())))
+            }
+            ("GET", ["runs", id]) => Ok(Response::json(
+                self.run_by_id(id)?.lock().unwrap().to_json(),
+            )),
+            ("POST", ["runs", id, "cancel"]) => {
+                self.run_by_id(id)?.lock().unwrap().token.cancel();
+                Ok(Response::empty())
+            }
+            _ => Err(HttpError::not_found(format!(
+                "no route for {method} {}",
+                request.path
+            ))),
+        }
+    }
+
+    fn device(&self, name: &str) -> Result<Arc<AutoPlay>, HttpError> {
+        self.fleet()
+            .get(name)
+            .ok_or_else(|| HttpError::not_found(format!("unknown device {name}")))
+    }
+
+    fn run_by_id(&self, id: &str) -> Result<Arc<Mutex<Run>>, HttpError> {
+        let runs = self.runs.lock().unwrap();
+        id.parse::<u64>()
+            .ok()
+            .and_then(|id| runs.get(id))
+            .ok_or_else(|| HttpError::not_found(format!("unknown run {id}")))
+    }
+
+    /// Run `task` on the device `name` in the background.
+    fn start_run(&self, name: &str, task: Task) -> Result<u64, HttpError> {
+        let ap = self.device(name)?;
+        let token = CancellationToken::new();
+        let run = {
+            let mut runs = self.runs.lock().unwrap();
+            runs.prune();
+            let run = Arc::new(Mutex::new(Run {
+                id: runs.next_id,
+                device: name.to_string(),
+                task: task.name.clone(),
+                token: token.clone(),
+                report: None,
+            }));
+            runs.next_id += 1;
+            runs.runs.push_back(run.clone());
+            run
+        };
+        let id = run.lock().unwrap().id;
+        thread::Builder::new()
+            .name(format!("run-{id}"))
+            .spawn(move || {
+                let report = task.execute_with_report_cancellable(&ap, false, &token);
+                run.lock().unwrap().report = Some(report);
+            })
+            .map_err(anyhow::Error::from)?;
+        Ok(id)
+    }
+}
+
+fn default_swipe_ms() -> u64 {
+    200
+}
+
+/// Write `events` as newline delimited JSON in a chunked response, until the
+/// client goes away or the device is removed.
+fn stream_events(stream: &mut TcpStream, events: mpsc::Receiver<Event>) -> anyhow::Result<()> {
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\n\
+          Transfer-Encoding: chunked\r\nConnection: close\r\n\r\n",
+    )?;
+    let mut write_chunk = |chunk: &[u8]| -> std::io::Result<()> {
+        write!(stream, "{:x}\r\n", chunk.len())?;
+        stream.write_all(chunk)?;
+        stream.write_all(b"\r\n")?;
+        stream.flush()
+    };
+    loop {
+        let line = match events.recv_timeout(HEARTBEAT) {
+            Ok(event) => match event.to_json() {
+                Some(event) => format!("{event}\n"),
+                None => continue,
+            },
+            Err(mpsc::RecvTimeoutError::Timeout) => "\n".to_string(),
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        };
+        // The client closing the connection is how a stream normally ends
+        if write_chunk(line.as_bytes()).is_err() {
+            return Ok(());
+        }
+    }
+    stream.write_all(b"0\r\n\r\n")?;
+    Ok(())
+}
+
+/// Push the frames of a [`LiveView`] of `ap` as binary WebSocket messages, until
+/// the client closes the WebSocket or goes away. `fps`, `quality` and
+/// `max_width` can be set in the query.
+fn stream_live(stream: &mut TcpStream, request: &Request, ap: Arc<AutoPlay>) -> anyhow::Result<()> {
+    let Some(key) = request.header("sec-websocket-key") else {
+        return Response::error(400, "expected a WebSocket upgrade".to_string()).write(stream);
+    };
+    let mut options = LiveViewOptions::default();
+    let res = (|| -> anyhow::Result<()> {
+        if let Some(fps) = request.query("fps") {
+            options.fps = fps.parse()?;
+            anyhow::ensure!(options.fps > 0, "fps should be greater than 0");
+        }
+        if let Some(quality) = request.query("quality") {
+            options.quality = quality.parse()?;
+        }
+        if let Some(max_width) = request.query("max_width") {
+            options.max_width = Some(max_width.parse()?);
+        }
+        Ok(())
+    })();
+    if let Err(err) = res {
+        return Response::error(400, format!("invalid live view options: {err:#}")).write(stream);
+    }
+
+    let accept = derive_accept_key(key.as_bytes());
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {accept}\r\n\r\n"
+    )?;
+    // Only waiting on the client for a moment between two frames
+    stream.set_read_timeout(Some(CLIENT_POLL))?;
+    let mut ws = WebSocket::from_raw_socket(stream, Role::Server, None);
+    let mut view = LiveView::new(ap, options);
+    loop {
+        // The pings are answered and the close acknowledged while reading
+        loop {
+            match ws.read() {
+                Ok(_) => continue,
+                Err(WsError::Io(err))
+                    if matches!(
+                        err.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    break;
+                }
+                // Closed by the client, or gone
+                Err(_) => return Ok(()),
+            }
+        }
+        let frame = view.next_frame()?;
+        // The client going away without closing is how a stream often ends
+        if ws.send(Message::Binary(frame.into())).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    /// Without the query
+    path: String,
+    query: Option<String>,
+    /// With lowercase names
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn read(stream: &mut TcpStream) -> anyhow::Result<Self> {
+        let mut buf = Vec::new();
+        let mut chunk = [0; 4096];
+        loop {
+            let n = stream.read(&mut chunk)?;
+            anyhow::ensure!(n > 0, "connection closed before the end of the headers");
+            buf.extend_from_slice(&chunk[..n]);
+
+            let mut headers = [httparse::EMPTY_HEADER; 32];
+            let mut request = httparse::Request::new(&mut headers);
+            let httparse::Status::Complete(len) = request.parse(&buf)? else {
+                anyhow::ensure!(buf.len() <= MAX_HEADERS, "headers too large");
+                continue;
+            };
+            let headers = request
+                .headers
+                .iter()
+                .map(|h| {
+                    let value = String::from_utf8_lossy(h.value).trim().to_string();
+                    (h.name.to_ascii_lowercase(), value)
+                })
+                .collect::<Vec<_>>();
+            let (path, query) = match request.path.unwrap_or("/").split_once('?') {
+                Some((path, query)) => (path.to_string(), Some(query.to_string())),
+                None => (request.path.unwrap_or("/").to_string(), None),
+            };
+            let method = request.method.unwrap_or_default().to_string();
+
+            let mut request = Self {
+                method,
+                path,
+                query,
+                headers,
+                body: Vec::new(),
+            };
+            let content_length = request
+                .header("content-length")
+                .map(|value| value.parse::<usize>())
+                .transpose()
+                .map_err(|_| anyhow::anyhow!("invalid content-length"))?
+                .unwrap_or(0);
+            anyhow::ensure!(content_length <= MAX_BODY, "body too large");
+
+            let mut body = buf.split_off(len);
+            if body.len() < content_length {
+                let mut rest = vec![0; content_length - body.len()];
+                stream.read_exact(&mut rest)?;
+                body.extend(rest);
+            }
+            body.truncate(content_length);
+            request.body = body;
+            return Ok(request);
+        }
+    }
+
+    /// The value of the header `name`, in lowercase
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find_map(|(key, value)| (key == name).then_some(value.as_str()))
+    }
+
+    /// The value of `name` in the query, not percent decoded
+    fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .as_deref()?
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find_map(|(key, value)| (key == name).then_some(value))
+    }
+
+    fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T, HttpError> {
+        serde_json::from_slice(&self.body).map_err(|err| HttpError::bad_request(err.to_string()))
+    }
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    fn json(value: impl Serialize) -> Self {
+        let body = serde_json::to_vec(&value).expect("values serialize to JSON");
+        Self::new(200, "application/json", body)
+    }
+
+    fn empty() -> Self {
+        Self::new(204, "application/json", Vec::new())
+    }
+
+    fn error(status: u16, message: String) -> Self {
+        Self::json(json!({ "error": message })).with_status(status)
+    }
+
+    fn with_status(mut self, status: u16) -> Self {
+        self.status = status;
+        self
+    }
+
+    fn write(&self, stream: &mut TcpStream) -> anyhow::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            201 => "Created",
+            204 => "No Content",
+            400 => "Bad Request",
+            404 => "Not Found",
+            _ => "Internal Server Error",
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status,
+            self.content_type,
+            self.body.len()
+        )?;
+        stream.write_all(&self.body)?;
+        stream.flush()?;
+        Ok(())
+    }
+}
+
+/// A failed request, a device error is a 500
+struct HttpError {
+    status: u16,
+    message: String,
+}
+
+impl HttpError {
+    fn bad_request(message: String) -> Self {
+        Self {
+            status: 400,
+            message,
+        }
+    }
+
+    fn not_found(message: String) -> Self {
+        Self {
+            status: 404,
+            message,
+        }
+    }
+}
+
+impl From<anyhow::Error> for HttpError {
+    fn from(err: anyhow::Error) -> Self {
+        Self {
+            status: 500,
+            message: format!("{err:#}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use image::math::Rect;
+
+    use super::*;
+    use crate::{guard::GuardAction, testing::DummyController};
+
+    fn get(url: &str) -> Result<Vec<u8>, ureq::Error> {
+        ureq::get(url).call()?.body_mut().read_to_vec()
+    }
+
+    fn post(url: &str, body: &str) -> Result<String, ureq::Error> {
+        ureq::post(url).send(body)?.body_mut().read_to_string()
+    }
+
+    #[test]
+    fn test_server() {
+        let mut fleet = Fleet::new();
+        let ap = fleet
+            .add("dummy", AutoPlay::new(DummyController::new(4, 2)))
+            .unwrap();
+        let clicks = || ap.with_controller(DummyController::clicks).unwrap();
+        let server = Server::new(fleet);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        {
+            let server = server.clone();
+            thread::spawn(move || server.serve(listener));
+        }
+
+        let devices = get(&format!("{url}/devices")).unwrap();
+        assert_eq!(devices, br#"["dummy"]"#);
+        let png = get(&format!("{url}/devices/dummy/screencap")).unwrap();
+        let screen = image::load_from_memory(&png).unwrap();
+        assert_eq!((screen.width(), screen.height()), (4, 2));
+        let jpeg = get(&format!("{url}/devices/dummy/screencap?format=jpeg")).unwrap();
+        assert_eq!(
+            image::guess_format(&jpeg).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+        let small = get(&format!(
+            "{url}/devices/dummy/screencap?format=jpeg&quality=10"
+        ))
+        .unwrap();
+        assert_eq!(
+            image::guess_format(&small).unwrap(),
+            image::ImageFormat::Jpeg
+        );
+        for query in ["format=jpeg&quality=high", "format=webp"] {
+            assert!(matches!(
+                get(&format!("{url}/devices/dummy/screencap?{query}")),
+                Err(ureq::Error::StatusCode(400))
+            ));
+        }
+
+        post(&format!("{url}/devices/dummy/click"), r#"{"x": 1, "y": 1}"#).unwrap();
+        assert_eq!(clicks(), [(1, 1)]);
+        assert!(matches!(
+            post(&format!("{url}/devices/dummy/click"), r#"{"x": 1}"#),
+            Err(ureq::Error::StatusCode(400))
+        ));
+        assert!(matches!(
+            get(&format!("{url}/devices/other/screencap")),
+            Err(ureq::Error::StatusCode(404))
+        ));
+
+        let task = "name = \"remote\"\n[[steps]]\nClick = { x = 3, y = 1 }";
+        let run = post(&format!("{url}/devices/dummy/runs"), task).unwrap();
+        assert_eq!(run, r#"{"id":0}"#);
+        let start = Instant::now();
+        let run = loop {
+            let run = get(&format!("{url}/runs/0")).unwrap();
+            let run = serde_json::from_slice::<Value>(&run).unwrap();
+            if run["status"] != "running" {
+                break run;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(run["status"], "succeeded");
+        assert_eq!(run["task"], "remote");
+        assert_eq!(clicks(), [(1, 1), (3, 1)]);
+        assert!(matches!(
+            get(&format!("{url}/runs/1")),
+            Err(ureq::Error::StatusCode(404))
+        ));
+    }
+
+    #[test]
+    fn test_live() {
+        let mut fleet = Fleet::new();
+        fleet
+            .add("dummy", AutoPlay::new(DummyController::new(4, 2)))
+            .unwrap();
+        let server = Server::new(fleet);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || server.serve(listener));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "GET /devices/dummy/live?fps=20 HTTP/1.1\r\nHost: {addr}\r\nUpgrade: websocket\r\n\
+             Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n"
+        )
+        .unwrap();
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            stream.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 101"));
+        assert!(response.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        let mut ws = WebSocket::from_raw_socket(stream, Role::Client, None);
+        let mut frames = 0;
+        ws.send(Message::Ping(b"ping".to_vec().into())).unwrap();
+        let mut pong = false;
+        while frames < 2 || !pong {
+            match ws.read().unwrap() {
+                Message::Binary(jpeg) => {
+                    let frame = image::load_from_memory(&jpeg).unwrap();
+                    assert_eq!((frame.width(), frame.height()), (4, 2));
+                    frames += 1;
+                }
+                Message::Pong(payload) => {
+                    assert_eq!(&payload[..], b"ping");
+                    pong = true;
+                }
+                message => panic!("unexpected {message:?}"),
+            }
+        }
+        // The server acknowledges the close and stops sending
+        ws.close(None).unwrap();
+        loop {
+            match ws.read() {
+                Ok(Message::Binary(_) | Message::Close(_)) => continue,
+                Ok(message) => panic!("unexpected {message:?}"),
+                Err(err) => {
+                    assert!(matches!(err, WsError::ConnectionClosed), "{err}");
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_event_json() {
+        let event = Event::Click { x: 1, y: 2 }.to_json().unwrap();
+        assert_eq!(event, json!({ "type": "click", "x": 1, "y": 2 }));
+        let event = Event::TaskFinished {
+            name: "daily".to_string(),
+            error: None,
+        }
+        .to_json()
+        .unwrap();
+        assert_eq!(event["error"], Value::Null);
+        let event = Event::Swipe {
+            start: (1, 2),
+            end: (3, -4),
+            duration: Duration::from_millis(300),
+        }
+        .to_json()
+        .unwrap();
+        assert_eq!(
+            event,
+            json!({ "type": "swipe", "start": [1, 2], "end": [3, -4], "duration_ms": 300 })
+        );
+        let event = Event::MatchResult {
+            template_size: (2, 2),
+            rect: Some(Rect {
+                x: 1,
+                y: 0,
+                width: 2,
+                height: 2,
+            }),
+            score: Some(0.5),
+        }
+        .to_json()
+        .unwrap();
+        assert_eq!(event["type"], "match");
+        assert_eq!(event["rect"], json!([1, 0, 2, 2]));
+        let event = Event::Guard {
+            action: GuardAction::Paused,
+            reason: "low battery".to_string(),
+        }
+        .to_json()
+        .unwrap();
+        assert_eq!(event["action"], "paused");
+        let frame = Event::MatchFrame {
+            frame: Arc::new(image::DynamicImage::new_rgba8(1, 1)),
+            offset: (0, 0),
+            template_size: (1, 1),
+            rect: None,
+        };
+        assert_eq!(frame.to_json(), None);
+    }
+
+    #[test]
+    fn test_prune_runs() {
+        let mut runs = Runs::default();
+        for id in 0..KEPT_RUNS as u64 + 2 {
+            let report = (id != 1).then(|| ExecutionReport::new("task"));
+            runs.runs.push_back(Arc::new(Mutex::new(Run {
+                id,
+                device: "dummy".to_string(),
+                task: "task".to_string(),
+                token: CancellationToken::new(),
+                report,
+            })));
+        }
+        runs.prune();
+        // The oldest finished one goes, the running one stays
+        assert_eq!(runs.runs.len(), KEPT_RUNS + 1);
+        assert!(runs.get(0).is_none());
+        assert!(runs.get(1).is_some());
+        assert!(runs.get(KEPT_RUNS as u64 + 1).is_some());
+    }
+}
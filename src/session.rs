@@ -0,0 +1,390 @@
+//! Keep what happened during a run on disk, to find out why it failed while no
+//! one was watching.
+//!
+//! Each [`Session`] writes to its own directory under a root:
+//!
+//! - `events.jsonl`: every [event](Event) but the frames, one JSON object
+//!   per line as in [`Event::to_json`] with its time since the start in `t_ms`
+//! - `matches/`: the screen of each match attempt with the match drawn on it
+//! - `manifest.json`: the tasks run and what is in the directory, written when
+//!   the session stops
+//!
+//! The oldest sessions are removed once the root grows past
+//! [`SessionOptions::max_total_size`].
+//!
+//! ```ignore
+//! let ap = Arc::new(AutoPlay::new(controller));
+//! let session = Session::start(ap.clone(), "sessions", SessionOptions::default())?;
+//! let res = task.execute(&ap);
+//! let dir = session.stop()?;
+//! ```
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::Serialize;
+use serde_json::{Value, json};
+use tracing::warn;
+
+use crate::{AutoPlay, event::Event, report::Annotation};
+
+pub const MANIFEST: &str = "manifest.json";
+
+pub struct SessionOptions {
+    /// Save the screen of every match attempt, a polling step saves one per try
+    pub screenshots: bool,
+    /// Total size in bytes of the sessions under the root, the oldest ones are
+    /// removed past it
+    pub max_total_size: u64,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        Self {
+            screenshots: true,
+            max_total_size: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// What a session recorded, its `manifest.json`
+#[derive(Debug, Default, Serialize)]
+pub struct SessionManifest {
+    pub started_at_ms: u64,
+    pub duration_ms: u64,
+    pub tasks: Vec<TaskRecord>,
+    /// Clicks, swipes and key presses
+    pub inputs: usize,
+    pub matches: usize,
+    /// Relative to the directory of the session
+    pub screenshots: Vec<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaskRecord {
+    pub name: String,
+    /// Since the start of the session
+    pub started_ms: u64,
+    pub error: Option<String>,
+}
+
+/// Records an [`AutoPlay`] on a background thread until stopped, see the
+/// [module](self) documentation.
+pub struct Session {
+    ap: Arc<AutoPlay>,
+    dir: PathBuf,
+    screenshots: bool,
+    stop: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<anyhow::Result<()>>>,
+}
+
+impl Session {
+    /// Start recording to a new directory under `root`, named after the time.
+    pub fn start(
+        ap: Arc<AutoPlay>,
+        root: impl AsRef<Path>,
+        options: SessionOptions,
+    ) -> anyhow::Result<Self> {
+        let root = root.as_ref();
+        std::fs::create_dir_all(root)
+            .with_context(|| format!("failed to create {}", root.display()))?;
+        prune(root, options.max_total_size, None)?;
+        let name = chrono::Local::now().format("%Y%m%d-%H%M%S").to_string();
+        let mut dir = root.join(&name);
+        for n in 1.. {
+            if !dir.exists() {
+                break;
+            }
+            dir = root.join(format!("{name}-{n}"));
+        }
+        std::fs::create_dir_all(dir.join("matches"))?;
+
+        let events = ap.subscribe();
+        if options.screenshots {
+            ap.record_match_frames(true);
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let (dir, stop, root) = (dir.clone(), stop.clone(), root.to_path_buf());
+            thread::Builder::new()
+                .name("session".to_string())
+                .spawn(move || {
+                    record(events, &dir, options.screenshots, &stop)?;
+                    prune(&root, options.max_total_size, Some(&dir))
+                })?
+        };
+        Ok(Self {
+            ap,
+            dir,
+            screenshots: options.screenshots,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Stop recording and wait for the manifest to be written, returns the
+    /// directory of the session.
+    pub fn stop(mut self) -> anyhow::Result<PathBuf> {
+        self.finish()?;
+        Ok(self.dir.clone())
+    }
+
+    fn finish(&mut self) -> anyhow::Result<()> {
+        let Some(handle) = self.handle.take() else {
+            return Ok(());
+        };
+        if self.screenshots {
+            self.ap.record_match_frames(false);
+        }
+        self.stop.store(true, Ordering::Relaxed);
+        handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("session thread panicked"))?
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            warn!("failed to finish the session: {err:#}");
+        }
+    }
+}
+
+fn record(
+    events: mpsc::Receiver<Event>,
+    dir: &Path,
+    screenshots: bool,
+    stop: &AtomicBool,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let mut manifest = SessionManifest {
+        started_at_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        ..Default::default()
+    };
+    let mut log = BufWriter::new(File::create(dir.join("events.jsonl"))?);
+    loop {
+        // The events sent before stopping are still written
+        let event = match events.recv_timeout(Duration::from_millis(100)) {
+            Ok(event) => event,
+            Err(mpsc::RecvTimeoutError::Timeout) if !stop.load(Ordering::Relaxed) => continue,
+            Err(_) => match events.try_recv() {
+                Ok(event) => event,
+                Err(_) => break,
+            },
+        };
+        let t_ms = start.elapsed().as_millis() as u64;
+        let line = match &event {
+            Event::Click { .. } | Event::Swipe { .. } | Event::Press { .. } => {
+                manifest.inputs += 1;
+                event.to_json()
+            }
+            Event::TaskStarted { name } => {
+                manifest.tasks.push(TaskRecord {
+                    name: name.clone(),
+                    started_ms: t_ms,
+                    error: None,
+                });
+                event.to_json()
+            }
+            Event::TaskFinished { name, error } => {
+                if let Some(task) = manifest.tasks.iter_mut().rev().find(|t| t.name == *name) {
+                    task.error = error.clone();
+                }
+                event.to_json()
+            }
+            Event::MatchFrame {
+                frame,
+                offset,
+                template_size,
+                rect,
+            } => {
+                manifest.matches += 1;
+                let path = Path::new("matches").join(format!(
+                    "{:04}-{}.png",
+                    manifest.matches,
+                    if rect.is_some() { "found" } else { "missed" }
+                ));
+                let mut image = frame.to_rgba8();
+                if let Some(rect) = rect {
+                    let rect = image::math::Rect {
+                        x: rect.x - offset.0,
+                        y: rect.y - offset.1,
+                        ..*rect
+                    };
+                    Annotation::Rect {
+                        rect,
+                        label: "match".to_string(),
+                    }
+                    .draw(&mut image);
+                }
+                match image.save(dir.join(&path)) {
+                    Ok(()) => manifest.screenshots.push(path.clone()),
+                    Err(err) => warn!("failed to save {}: {err}", path.display()),
+                }
+                // As the match it stands for, without a score, with its screenshot
+                let mut line = Event::MatchResult {
+                    template_size: *template_size,
+                    rect: *rect,
+                    score: None,
+                }
+                .to_json();
+                if let Some(Value::Object(line)) = &mut line {
+                    line.insert("screenshot".to_string(), json!(path));
+                }
+                line
+            }
+            // Already logged with its screen
+            Event::MatchResult { .. } if screenshots => continue,
+            Event::MatchResult { .. } => {
+                manifest.matches += 1;
+                event.to_json()
+            }
+            _ => event.to_json(),
+        };
+        // The frames of the live views and the reports have no line
+        let Some(Value::Object(mut line)) = line else {
+            continue;
+        };
+        line.insert("t_ms".to_string(), json!(t_ms));
+        serde_json::to_writer(&mut log, &line)?;
+        log.write_all(b"\n")?;
+    }
+    log.flush()?;
+    manifest.duration_ms = start.elapsed().as_millis() as u64;
+    let file = File::create(dir.join(MANIFEST))?;
+    serde_json::to_writer_pretty(file, &manifest)?;
+    Ok(())
+}
+
+/// Remove the oldest sessions under `root` until they add up to `max_size`,
+/// never `keep`.
+fn prune(root: &Path, max_size: u64, keep: Option<&Path>) -> anyhow::Result<()> {
+    let mut sessions = std::fs::read_dir(root)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_dir())
+        .map(|path| (dir_size(&path), path))
+        .collect::<Vec<_>>();
+    // Named after their start time, the oldest first
+    sessions.sort_by(|a, b| a.1.cmp(&b.1));
+    let mut total = sessions.iter().map(|(size, _)| size).sum::<u64>();
+    for (size, path) in sessions {
+        if total <= max_size {
+            break;
+        }
+        if Some(path.as_path()) == keep {
+            continue;
+        }
+        std::fs::remove_dir_all(&path)
+            .with_context(|| format!("failed to remove {}", path.display()))?;
+        total -= size;
+    }
+    Ok(())
+}
+
+fn dir_size(dir: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::DynamicImage;
+
+    use super::*;
+    use crate::{action::Action, task::Task, testing::DummyController};
+
+    #[test]
+    fn test_session() {
+        let root = std::env::temp_dir().join(format!("ap-session-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        // An old session, too big to be kept
+        std::fs::create_dir_all(root.join("20000101-000000")).unwrap();
+        std::fs::write(root.join("20000101-000000/events.jsonl"), vec![b'x'; 4096]).unwrap();
+
+        let ap = Arc::new(AutoPlay::new(DummyController::new(4, 4)));
+        let options = SessionOptions {
+            max_total_size: 1024,
+            ..Default::default()
+        };
+        let session = Session::start(ap.clone(), &root, options).unwrap();
+        assert!(!root.join("20000101-000000").exists());
+
+        let task =
+            Task::from_toml("name = \"daily\"\n[[steps]]\nClick = { x = 1, y = 2 }").unwrap();
+        task.execute(&ap).unwrap();
+        let rect = image::math::Rect {
+            x: 12,
+            y: 10,
+            width: 4,
+            height: 4,
+        };
+        ap.events().emit(Event::MatchFrame {
+            frame: Arc::new(DynamicImage::new_rgb8(20, 20)),
+            offset: (10, 5),
+            template_size: (4, 4),
+            rect: Some(rect),
+        });
+        let dir = session.stop().unwrap();
+
+        let log = std::fs::read_to_string(dir.join("events.jsonl")).unwrap();
+        let types = log
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["type"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                "task_started",
+                "step_started",
+                "click",
+                "step_finished",
+                "task_finished",
+                "match"
+            ]
+        );
+        let click = serde_json::from_str::<serde_json::Value>(log.lines().nth(2).unwrap()).unwrap();
+        assert_eq!(click["x"], 1);
+        assert!(click["t_ms"].is_u64());
+        let manifest: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join(MANIFEST)).unwrap()).unwrap();
+        assert_eq!(manifest["tasks"][0]["name"], "daily");
+        assert_eq!(manifest["inputs"], 1);
+        assert_eq!(manifest["screenshots"][0], "matches/0001-found.png");
+        let screenshot = image::open(dir.join("matches/0001-found.png"))
+            .unwrap()
+            .to_rgba8();
+        // The match is drawn where it is in the part of the screen searched
+        assert_ne!(screenshot.get_pixel(2, 5), &image::Rgba([0, 0, 0, 255]));
+        assert_eq!(screenshot.get_pixel(0, 0), &image::Rgba([0, 0, 0, 255]));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
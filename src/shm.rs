@@ -0,0 +1,230 @@
+//! Publish captured frames to shared memory, for overlays, separate vision
+//! processes or other languages to read them without any IPC serialization.
+//!
+//! A region is a file mapped into memory, under `/dev/shm` on Linux and the
+//! temp directory elsewhere, see [`shm_path`]. It starts with a 64 bytes header,
+//! all fields little endian, followed by the RGBA8 pixels:
+//!
+//! | offset | type     | field                                         |
+//! |--------|----------|-----------------------------------------------|
+//! | 0      | `[u8;8]` | magic, `b"APFRAME\0"`                         |
+//! | 8      | `u32`    | version, currently `1`                        |
+//! | 12     | `u32`    | width                                         |
+//! | 16     | `u32`    | height                                        |
+//! | 20     | `u32`    | reserved                                      |
+//! | 24     | `u64`    | sequence, odd while a frame is being written  |
+//! | 32     | `u64`    | capture time, milliseconds since unix epoch   |
+//! | 40     | `u64`    | length of the pixels in bytes                 |
+//!
+//! A reader copies the pixels out and retries if the sequence was odd or changed
+//! in the meantime. The region grows when a bigger frame is published, so readers
+//! should remap when the length goes past their mapping.
+
+use std::{
+    fs::{File, OpenOptions},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use memmap2::{Mmap, MmapMut};
+
+pub const MAGIC: &[u8; 8] = b"APFRAME\0";
+pub const VERSION: u32 = 1;
+pub const HEADER_LEN: usize = 64;
+
+const WIDTH: usize = 12;
+const HEIGHT: usize = 16;
+const SEQ: usize = 24;
+const TIMESTAMP: usize = 32;
+const LEN: usize = 40;
+
+/// Where the region named `name` lives.
+pub fn shm_path(name: &str) -> PathBuf {
+    let dir = Path::new("/dev/shm");
+    if cfg!(target_os = "linux") && dir.is_dir() {
+        dir.join(name)
+    } else {
+        std::env::temp_dir().join(name)
+    }
+}
+
+/// The sequence of the region mapped at `region`.
+///
+/// # Safety
+///
+/// `region` should point to a live mapping of at least [`HEADER_LEN`] bytes.
+unsafe fn load_seq(region: *const u8, order: Ordering) -> u64 {
+    // SAFETY: mappings are page aligned so the sequence is 8 bytes aligned, and it
+    // is only ever accessed atomically.
+    unsafe { AtomicU64::from_ptr(region.add(SEQ) as *mut u64).load(order) }
+}
+
+/// # Safety
+///
+/// See [`load_seq`].
+unsafe fn store_seq(region: *mut u8, seq: u64, order: Ordering) {
+    // SAFETY: see `load_seq`
+    unsafe { AtomicU64::from_ptr(region.add(SEQ) as *mut u64).store(seq, order) }
+}
+
+/// Writes frames into a shared-memory region, which is removed on drop.
+pub struct FramePublisher {
+    path: PathBuf,
+    file: File,
+    mmap: MmapMut,
+}
+
+impl FramePublisher {
+    pub fn create(name: &str) -> anyhow::Result<Self> {
+        let path = shm_path(name);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        file.set_len(HEADER_LEN as u64)?;
+        // SAFETY: the file is owned by this publisher, readers only map it read only.
+        let mut mmap = unsafe { MmapMut::map_mut(&file)? };
+        mmap[..8].copy_from_slice(MAGIC);
+        mmap[8..12].copy_from_slice(&VERSION.to_le_bytes());
+        Ok(Self { path, file, mmap })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn publish(&mut self, width: u32, height: u32, rgba: &[u8]) -> anyhow::Result<()> {
+        let len = HEADER_LEN + rgba.len();
+        if self.mmap.len() < len {
+            self.mmap.flush()?;
+            self.file.set_len(len as u64)?;
+            // SAFETY: see `create`
+            self.mmap = unsafe { MmapMut::map_mut(&self.file)? };
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        // SAFETY: the mapping is at least `HEADER_LEN` long
+        let start = unsafe { load_seq(self.mmap.as_ptr(), Ordering::Relaxed) } + 1;
+        unsafe { store_seq(self.mmap.as_mut_ptr(), start, Ordering::Relaxed) };
+        std::sync::atomic::fence(Ordering::Release);
+        self.mmap[WIDTH..WIDTH + 4].copy_from_slice(&width.to_le_bytes());
+        self.mmap[HEIGHT..HEIGHT + 4].copy_from_slice(&height.to_le_bytes());
+        self.mmap[TIMESTAMP..TIMESTAMP + 8].copy_from_slice(&timestamp.to_le_bytes());
+        self.mmap[LEN..LEN + 8].copy_from_slice(&(rgba.len() as u64).to_le_bytes());
+        self.mmap[HEADER_LEN..len].copy_from_slice(rgba);
+        unsafe { store_seq(self.mmap.as_mut_ptr(), start + 1, Ordering::Release) };
+        Ok(())
+    }
+}
+
+impl Drop for FramePublisher {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// A frame read by [`FrameReader`]
+#[derive(Debug)]
+pub struct SharedFrame {
+    pub seq: u64,
+    pub width: u32,
+    pub height: u32,
+    /// Milliseconds since unix epoch
+    pub timestamp: u64,
+    pub rgba: Vec<u8>,
+}
+
+/// Reads the frames of a [`FramePublisher`], possibly from another process.
+pub struct FrameReader {
+    file: File,
+    mmap: Mmap,
+}
+
+impl FrameReader {
+    pub fn open(name: &str) -> anyhow::Result<Self> {
+        let path = shm_path(name);
+        let file =
+            File::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
+        // SAFETY: the pixels are only trusted when the sequence did not change while copying them.
+        let mmap = unsafe { Mmap::map(&file)? };
+        anyhow::ensure!(
+            mmap.len() >= HEADER_LEN && &mmap[..8] == MAGIC,
+            "{} is not a frame region",
+            path.display()
+        );
+        Ok(Self { file, mmap })
+    }
+
+    /// The latest frame, `None` if nothing is published yet.
+    pub fn read(&mut self) -> anyhow::Result<Option<SharedFrame>> {
+        loop {
+            // SAFETY: the mapping is checked to be at least `HEADER_LEN` long in `open`
+            let start = unsafe { load_seq(self.mmap.as_ptr(), Ordering::Acquire) };
+            if start == 0 {
+                return Ok(None);
+            }
+            if start % 2 == 1 {
+                std::thread::yield_now();
+                continue;
+            }
+            let u32_at =
+                |mmap: &Mmap, at: usize| u32::from_le_bytes(mmap[at..at + 4].try_into().unwrap());
+            let u64_at =
+                |mmap: &Mmap, at: usize| u64::from_le_bytes(mmap[at..at + 8].try_into().unwrap());
+            let len = u64_at(&self.mmap, LEN) as usize;
+            if self.mmap.len() < HEADER_LEN + len {
+                // SAFETY: see `open`
+                self.mmap = unsafe { Mmap::map(&self.file)? };
+                continue;
+            }
+            let frame = SharedFrame {
+                seq: start,
+                width: u32_at(&self.mmap, WIDTH),
+                height: u32_at(&self.mmap, HEIGHT),
+                timestamp: u64_at(&self.mmap, TIMESTAMP),
+                rgba: self.mmap[HEADER_LEN..HEADER_LEN + len].to_vec(),
+            };
+            std::sync::atomic::fence(Ordering::Acquire);
+            if unsafe { load_seq(self.mmap.as_ptr(), Ordering::Relaxed) } == start {
+                return Ok(Some(frame));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publish_and_read() {
+        let name = format!("auto-play-test-{}", std::process::id());
+        let mut publisher = FramePublisher::create(&name).unwrap();
+        let mut reader = FrameReader::open(&name).unwrap();
+        assert!(reader.read().unwrap().is_none());
+
+        publisher.publish(2, 1, &[1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        let frame = reader.read().unwrap().unwrap();
+        assert_eq!((frame.width, frame.height), (2, 1));
+        assert_eq!(frame.rgba, [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        // Grows the region
+        publisher.publish(4, 4, &[9; 64]).unwrap();
+        let next = reader.read().unwrap().unwrap();
+        assert!(next.seq > frame.seq);
+        assert_eq!((next.width, next.height), (4, 4));
+        assert_eq!(next.rgba, [9; 64]);
+
+        let path = publisher.path().to_path_buf();
+        drop(publisher);
+        assert!(!path.exists());
+    }
+}
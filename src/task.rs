@@ -0,0 +1,1491 @@
+//! Tasks are named sequences of [`Action`]s, usually authored as TOML:
+//!
+//! ```toml
+//! name = "daily"
+//!
+//! [[steps]]
+//! Click = { x = 100, y = 200 }
+//!
+//! [[steps]]
+//! WaitAction = { ms = 500 }
+//!
+//! [[steps]]
+//! PluginAction = { name = "claim_rewards" }
+//! ```
+//!
+//! Tasks can declare parameters, referenced as `${name}` anywhere in their steps
+//! and given when the task is loaded with [`Task::load_with`]. A string that is
+//! only a reference takes the value as is, so numbers stay numbers:
+//!
+//! ```toml
+//! name = "farm"
+//!
+//! [params.stage]
+//! [params.times]
+//! default = 1
+//!
+//! [[steps]]
+//! [steps.Repeat]
+//! until = { Not = { Succeeds = { PluginAction = { name = "stamina" } } } }
+//! max_iters = "${times}"
+//! steps = [{ PluginAction = { name = "farm_${stage}" } }]
+//! ```
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::Arc,
+    time::Instant,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info_span, warn};
+
+use crate::{
+    AutoPlay, Error,
+    action::{Action, ExecContext},
+    cancel::CancellationToken,
+    debug_bundle,
+    event::Event,
+    humanize::Humanize,
+    report::{Annotation, ExecutionReport, StepReport},
+    validate::Validator,
+};
+
+pub use toml::Value;
+
+/// How much of the logcat goes in the report of a failed task
+const REPORT_LOGCAT_LINES: usize = 500;
+
+/// A parameter of a [`Task`]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Param {
+    /// Taken when no argument is given, the parameter is required without one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Task {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, Param>,
+    /// Used for the steps instead of the policy of the [`AutoPlay`], see
+    /// [`crate::humanize`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub humanize: Option<Humanize>,
+    #[serde(default)]
+    pub steps: Vec<Box<dyn Action>>,
+}
+
+impl Task {
+    pub fn new(name: impl Into<String>, steps: Vec<Box<dyn Action>>) -> Self {
+        Self {
+            name: name.into(),
+            params: BTreeMap::new(),
+            humanize: None,
+            steps,
+        }
+    }
+
+    /// Parse a task, its parameters all take their defaults.
+    pub fn from_toml(source: &str) -> anyhow::Result<Self> {
+        Self::from_toml_with(source, &HashMap::new())
+    }
+
+    /// Parse a task with the parameters in `args`, failing if one of them is not
+    /// declared or if a parameter without a default is missing.
+    pub fn from_toml_with(source: &str, args: &HashMap<String, Value>) -> anyhow::Result<Self> {
+        let mut task = toml::from_str::<toml::Table>(source).context("failed to parse task")?;
+        let params = remove_params(&mut task)?;
+        if let Some(name) = args.keys().find(|name| !params.contains_key(*name)) {
+            anyhow::bail!("unknown parameter {name}");
+        }
+        let args = params
+            .iter()
+            .map(|(name, param)| {
+                let value = args.get(name).or(param.default.as_ref());
+                let value = value.with_context(|| format!("missing parameter {name}"))?;
+                Ok((name.as_str(), value))
+            })
+            .collect::<anyhow::Result<HashMap<_, _>>>()?;
+
+        let mut task = Value::Table(task);
+        substitute(&mut task, &args)?;
+        let mut task = task.try_into::<Self>().context("failed to parse task")?;
+        task.params = params;
+        Ok(task)
+    }
+
+    /// The name and the parameters of a task, without substituting the parameters
+    /// nor parsing the steps.
+    pub fn declaration(source: &str) -> anyhow::Result<(String, BTreeMap<String, Param>)> {
+        let mut task = toml::from_str::<toml::Table>(source).context("failed to parse task")?;
+        let params = remove_params(&mut task)?;
+        let name = match task.remove("name") {
+            Some(Value::String(name)) => name,
+            Some(_) => anyhow::bail!("the name of the task is not a string"),
+            None => anyhow::bail!("the task has no name"),
+        };
+        Ok((name, params))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Self::load_with(path, &HashMap::new())
+    }
+
+    /// [`Task::load`] with the parameters in `args`, see [`Task::from_toml_with`].
+    pub fn load_with(
+        path: impl AsRef<Path>,
+        args: &HashMap<String, Value>,
+    ) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read task {}", path.display()))?;
+        Self::from_toml_with(&source, args).with_context(|| format!("in {}", path.display()))
+    }
+}
+
+/// Take the parameters out of a task, which are not substituted themselves.
+fn remove_params(task: &mut toml::Table) -> anyhow::Result<BTreeMap<String, Param>> {
+    match task.remove("params") {
+        Some(params) => params.try_into().context("failed to parse params"),
+        None => Ok(BTreeMap::new()),
+    }
+}
+
+/// Replace the `${name}` references in the strings of `value`.
+fn substitute(value: &mut Value, args: &HashMap<&str, &Value>) -> anyhow::Result<()> {
+    match value {
+        Value::String(s) => {
+            let whole = s
+                .strip_prefix("${")
+                .and_then(|name| name.strip_suffix('}'))
+                .filter(|name| !name.contains('}'));
+            if let Some(name) = whole {
+                let arg = args.get(name);
+                *value = (*arg.with_context(|| format!("undefined parameter {name}"))?).clone();
+                return Ok(());
+            }
+            let mut res = String::with_capacity(s.len());
+            let mut rest = s.as_str();
+            while let Some(start) = rest.find("${") {
+                res.push_str(&rest[..start]);
+                let end = rest[start..]
+                    .find('}')
+                    .with_context(|| format!("unclosed reference in {s:?}"))?;
+                let name = &rest[start + 2..start + end];
+                match args.get(name) {
+                    Some(Value::String(arg)) => res.push_str(arg),
+                    Some(arg) => res.push_str(&arg.to_string()),
+                    None => anyhow::bail!("undefined parameter {name}"),
+                }
+                rest = &rest[start + end + 1..];
+            }
+            res.push_str(rest);
+            *s = res;
+        }
+        Value::Array(values) => {
+            for value in values {
+                substitute(value, args)?;
+            }
+        }
+        Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                substitute(value, args)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+impl Task {
+    /// Run the steps in `parent`, the context of the task running this one if
+    /// any, calling `after_step` with the start and the result of each. The token
+    /// is checked before each step, and the [`Humanize`] policy of the task
+    /// replaces the one of `parent` for its steps.
+    fn run(
+        &self,
+        parent: &ExecContext,
+        mut after_step: impl FnMut(usize, &dyn Action, Instant, &anyhow::Result<()>),
+    ) -> anyhow::Result<()> {
+        let _span = info_span!("task", task = %self.name).entered();
+        let ctx = ExecContext {
+            humanize: self.humanize.or(parent.humanize),
+            ..parent.clone()
+        };
+        let (ap, token) = (ctx.ap, &ctx.token);
+        ap.events().emit(Event::TaskStarted {
+            name: self.name.clone(),
+        });
+        let res = self.steps.iter().enumerate().try_for_each(|(idx, step)| {
+            if let Some(humanize) = ctx.humanize.filter(|_| idx > 0) {
+                std::thread::sleep(humanize.step_delay());
+            }
+            token
+                .check()
+                .with_context(|| format!("task {} stopped before step {idx}", self.name))?;
+            let _span = info_span!("step", index = idx, action = step.typetag_name()).entered();
+            ap.events().emit(Event::StepStarted {
+                task: self.name.clone(),
+                index: idx,
+                action: step.typetag_name().to_string(),
+            });
+            let start = Instant::now();
+            let res = step.execute_in(&ctx);
+            let duration = start.elapsed();
+            if res.is_err() {
+                // It may have missed with a content area detected on a screen
+                // being drawn
+                ap.controller().redetect_content_area();
+            }
+            let error = res.as_ref().err().map(|err| format!("{err:#}"));
+            debug!(
+                duration_ms = duration.as_millis() as u64,
+                error = error.as_deref(),
+                "step finished"
+            );
+            ap.events().emit(Event::StepFinished {
+                task: self.name.clone(),
+                index: idx,
+                duration,
+                error,
+            });
+            after_step(idx, step.as_ref(), start, &res);
+            res.with_context(|| format!("task {} failed at step {idx}", self.name))
+        });
+        ap.events().emit(Event::TaskFinished {
+            name: self.name.clone(),
+            error: res.as_ref().err().map(|err| format!("{err:#}")),
+        });
+        res
+    }
+
+    /// [`Task::run`] as the task run by the caller, writing a debug bundle if a
+    /// step fails, see [`debug_bundle`]. The tasks nested in it do not.
+    fn run_top_level(
+        &self,
+        ap: &AutoPlay,
+        token: &CancellationToken,
+        mut after_step: impl FnMut(usize, &dyn Action, Instant, &anyhow::Result<()>),
+    ) -> anyhow::Result<()> {
+        let mut failed = None;
+        let res = self.run(
+            &ExecContext::with_token(ap, token.clone()),
+            |index, step, start, res| {
+                if res.is_err() {
+                    failed = Some((index, step.typetag_name()));
+                }
+                after_step(index, step, start, res);
+            },
+        );
+        if let (Err(err), Some((index, action))) = (&res, failed)
+            && !matches!(err.downcast_ref::<Error>(), Some(Error::Cancelled))
+        {
+            debug_bundle::write_on_failure(
+                ap,
+                &debug_bundle::Failure {
+                    task: &self.name,
+                    index,
+                    action,
+                    error: err,
+                },
+            );
+        }
+        res
+    }
+
+    /// Run the task until it is done or `token` is cancelled, which fails with
+    /// [`Error::Cancelled`].
+    pub fn execute_cancellable(
+        &self,
+        ap: &AutoPlay,
+        token: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        self.run_top_level(ap, token, |_, _, _, _| {})
+    }
+
+    /// Run the task and report how each step went, with a screenshot taken after
+    /// each of them if `screenshots` is set. The report holds the error if it failed.
+    pub fn execute_with_report(&self, ap: &AutoPlay, screenshots: bool) -> ExecutionReport {
+        self.execute_with_report_cancellable(ap, screenshots, &CancellationToken::new())
+    }
+
+    /// [`Task::execute_with_report`] until `token` is cancelled.
+    pub fn execute_with_report_cancellable(
+        &self,
+        ap: &AutoPlay,
+        screenshots: bool,
+        token: &CancellationToken,
+    ) -> ExecutionReport {
+        let events = ap.subscribe();
+        let mut report = ExecutionReport::new(&self.name);
+        let start = Instant::now();
+        let res = self.run_top_level(ap, token, |index, step, step_start, res| {
+            let duration = step_start.elapsed();
+            let screenshot = screenshots
+                .then(|| {
+                    ap.screencap()
+                        .inspect_err(|err| warn!("failed to take screenshot: {err:#}"))
+                        .ok()
+                })
+                .flatten();
+            let step_events = events.try_iter().collect::<Vec<_>>();
+            let annotations = step_events
+                .iter()
+                .filter_map(Annotation::from_event)
+                .collect::<Vec<_>>();
+            if let Some(screenshot) = &screenshot {
+                ap.events().emit(Event::AnnotatedFrame {
+                    task: self.name.clone(),
+                    index,
+                    frame: Arc::new(screenshot.clone()),
+                    annotations: annotations.clone(),
+                });
+            }
+            report.steps.push(StepReport {
+                index,
+                action: step.typetag_name().to_string(),
+                started: step_start - start,
+                duration,
+                error: res.as_ref().err().map(|err| format!("{err:#}")),
+                screenshot,
+                annotations,
+                // The last one is of the outermost repeat
+                iterations: step_events.iter().rev().find_map(|event| match event {
+                    Event::RepeatFinished { iterations, .. } => Some(*iterations),
+                    _ => None,
+                }),
+            });
+        });
+        report.duration = start.elapsed();
+        if let Err(err) = res {
+            report.cancelled = matches!(err.downcast_ref::<Error>(), Some(Error::Cancelled));
+            report.error = Some(format!("{err:#}"));
+            if let Some(filter) = ap.report_logcat().filter(|_| !report.cancelled) {
+                report.logcat = ap
+                    .logcat_dump(&filter, REPORT_LOGCAT_LINES)
+                    .inspect_err(|err| warn!("failed to dump logcat: {err:#}"))
+                    .ok();
+            }
+        }
+        report
+    }
+}
+
+#[typetag::serde]
+impl Action for Task {
+    fn execute(&self, ap: &AutoPlay) -> anyhow::Result<()> {
+        self.execute_cancellable(ap, &CancellationToken::new())
+    }
+
+    /// A nested task stops with the one running it.
+    fn execute_in(&self, ctx: &ExecContext) -> anyhow::Result<()> {
+        self.run(ctx, |_, _, _, _| {})
+    }
+
+    fn validate(&self, validator: &mut Validator) {
+        validator.steps(&self.name, &self.steps);
+    }
+}
+
+/// Runs the task `name` of [`AutoPlay::resource`] as a step, with the parameters
+/// in `args`:
+///
+/// ```toml
+/// [[steps]]
+/// RunTask = { name = "farm", args = { stage = "1-7" } }
+/// ```
+#[derive(Serialize, Deserialize)]
+pub struct RunTask {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub args: HashMap<String, Value>,
+}
+
+#[typetag::serde]
+impl Action for RunTask {
+    fn execute(&self, ap: &AutoPlay) -> anyhow::Result<()> {
+        self.execute_in(&ExecContext::new(ap))
+    }
+
+    /// Looked up when it runs, so it is the version the resource has then.
+    fn execute_in(&self, ctx: &ExecContext) -> anyhow::Result<()> {
+        ctx.ap
+            .resource()
+            .task_with(&self.name, &self.args)?
+            .execute_in(ctx)
+    }
+
+    fn validate(&self, validator: &mut Validator) {
+        validator.task(&self.name, &self.args);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc, Mutex,
+        atomic::{AtomicU32, Ordering},
+    };
+
+    use ap_controller::{AppManagement, ControllerTrait};
+
+    use super::*;
+
+    /// The start and the end of a swipe
+    type SwipePoints = ((u32, u32), (i32, i32));
+
+    #[derive(Default)]
+    struct DummyController {
+        clicks: Mutex<Vec<(u32, u32)>>,
+        swipes: Mutex<Vec<SwipePoints>>,
+        /// The apps running, the foreground one last, if it can manage them
+        apps: Option<Mutex<Vec<String>>>,
+        screen: Option<image::DynamicImage>,
+        /// How many times the screen was captured
+        captures: std::sync::atomic::AtomicUsize,
+        profile: Option<ap_controller::DeviceProfile>,
+    }
+
+    impl AppManagement for DummyController {
+        fn launch_app(&self, app: &str) -> anyhow::Result<()> {
+            self.apps
+                .as_ref()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .push(app.to_string());
+            Ok(())
+        }
+
+        fn stop_app(&self, app: &str) -> anyhow::Result<()> {
+            let mut apps = self.apps.as_ref().unwrap().lock().unwrap();
+            apps.retain(|running| running != app);
+            Ok(())
+        }
+
+        fn current_app(&self) -> anyhow::Result<Option<String>> {
+            Ok(self.apps.as_ref().unwrap().lock().unwrap().last().cloned())
+        }
+    }
+
+    impl ControllerTrait for DummyController {
+        fn screen_size(&self) -> (u32, u32) {
+            (1920, 1080)
+        }
+
+        fn app_management(&self) -> Option<&dyn AppManagement> {
+            self.apps.as_ref().map(|_| self as &dyn AppManagement)
+        }
+
+        fn device_profile(&self) -> Option<&ap_controller::DeviceProfile> {
+            self.profile.as_ref()
+        }
+
+        fn screencap_raw(&self) -> anyhow::Result<(u32, u32, Vec<u8>)> {
+            unimplemented!()
+        }
+
+        fn screencap(&self) -> anyhow::Result<image::DynamicImage> {
+            self.captures
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Ok(self.screen.clone().unwrap())
+        }
+
+        fn click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+            self.clicks.lock().unwrap().push((x, y));
+            Ok(())
+        }
+
+        fn swipe(
+            &self,
+            start: (u32, u32),
+            end: (i32, i32),
+            _duration: std::time::Duration,
+            _slope_in: f32,
+            _slope_out: f32,
+        ) -> anyhow::Result<()> {
+            self.swipes.lock().unwrap().push((start, end));
+            Ok(())
+        }
+
+        fn long_press(
+            &self,
+            _x: u32,
+            _y: u32,
+            _duration: std::time::Duration,
+        ) -> anyhow::Result<()> {
+            unimplemented!()
+        }
+
+        fn double_click(&self, _x: u32, _y: u32) -> anyhow::Result<()> {
+            unimplemented!()
+        }
+
+        fn input_text(&self, _text: &str) -> anyhow::Result<()> {
+            unimplemented!()
+        }
+
+        fn press(&self, _key: ap_controller::Key) -> anyhow::Result<()> {
+            unimplemented!()
+        }
+    }
+
+    const TASK: &str = r#"
+name = "test"
+
+[[steps]]
+Click = { x = 1, y = 2 }
+
+[[steps]]
+PluginAction = { name = "count" }
+
+[[steps]]
+Click = { x = 3, y = 4 }
+"#;
+
+    #[test]
+    fn test_task_with_plugin() {
+        let ap = AutoPlay::new(DummyController::default());
+        let count = Arc::new(AtomicU32::new(0));
+        {
+            let count = count.clone();
+            ap.plugins().register("count", move |ap| {
+                count.fetch_add(1, Ordering::SeqCst);
+                ap.click(5, 6)
+            });
+        }
+
+        let task = Task::from_toml(TASK).unwrap();
+        task.execute(&ap).unwrap();
+
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+        let clicks = ap
+            .with_controller(|controller: &DummyController| {
+                controller.clicks.lock().unwrap().clone()
+            })
+            .unwrap();
+        assert_eq!(clicks, [(1, 2), (5, 6), (3, 4)]);
+    }
+
+    const IF_TASK: &str = r#"
+name = "if"
+
+[[steps]]
+[steps.If]
+cond = { Succeeds = { PluginAction = { name = "yes" } } }
+then_steps = [{ Click = { x = 1, y = 2 } }]
+else_steps = [{ Click = { x = 3, y = 4 } }]
+
+[[steps]]
+[steps.If]
+cond = { Succeeds = { PluginAction = { name = "no" } } }
+then_steps = [{ Click = { x = 5, y = 6 } }]
+
+[[steps]]
+[steps.If]
+cond = { Not = { Succeeds = { PluginAction = { name = "no" } } } }
+then_steps = [{ Click = { x = 7, y = 8 } }, { Click = { x = 9, y = 10 } }]
+"#;
+
+    #[test]
+    fn test_if() {
+        let ap = AutoPlay::new(DummyController::default());
+        ap.plugins().register("yes", |_| Ok(()));
+        ap.plugins()
+            .register("no", |_| Err(anyhow::anyhow!("not there")));
+
+        let task = Task::from_toml(IF_TASK).unwrap();
+        task.execute(&ap).unwrap();
+
+        let clicks = ap
+            .with_controller(|controller: &DummyController| {
+                controller.clicks.lock().unwrap().clone()
+            })
+            .unwrap();
+        assert_eq!(clicks, [(1, 2), (7, 8), (9, 10)]);
+    }
+
+    const REPEAT_TASK: &str = r#"
+name = "repeat"
+
+[[steps]]
+[steps.Repeat]
+until = { Not = { Succeeds = { PluginAction = { name = "stamina" } } } }
+steps = [{ PluginAction = { name = "farm" } }]
+
+[[steps]]
+[steps.Repeat]
+until = { Succeeds = { PluginAction = { name = "never" } } }
+max_iters = 2
+steps = [{ Click = { x = 1, y = 2 } }]
+"#;
+
+    #[test]
+    fn test_repeat() {
+        let ap = AutoPlay::new(DummyController::default());
+        let stamina = Arc::new(AtomicU32::new(3));
+        {
+            let stamina = stamina.clone();
+            ap.plugins()
+                .register("stamina", move |_| match stamina.load(Ordering::SeqCst) {
+                    0 => Err(anyhow::anyhow!("out of stamina")),
+                    _ => Ok(()),
+                });
+        }
+        {
+            let stamina = stamina.clone();
+            ap.plugins().register("farm", move |_| {
+                stamina.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            });
+        }
+        ap.plugins()
+            .register("never", |_| Err(anyhow::anyhow!("not yet")));
+
+        let task = Task::from_toml(REPEAT_TASK).unwrap();
+        let report = task.execute_with_report(&ap, false);
+        assert!(report.is_success(), "{:?}", report.error);
+        assert_eq!(stamina.load(Ordering::SeqCst), 0);
+        assert_eq!(report.steps[0].iterations, Some(3));
+        assert_eq!(report.steps[1].iterations, Some(2));
+        assert_eq!(report.steps[1].annotations.len(), 2);
+    }
+
+    const PARAMS_TASK: &str = r#"
+name = "farm"
+
+[params.stage]
+[params.times]
+default = 2
+
+[[steps]]
+[steps.Repeat]
+until = { Succeeds = { PluginAction = { name = "never" } } }
+max_iters = "${times}"
+steps = [{ PluginAction = { name = "farm_${stage}" } }]
+"#;
+
+    #[test]
+    fn test_params() {
+        let ap = AutoPlay::new(DummyController::default());
+        let count = Arc::new(AtomicU32::new(0));
+        {
+            let count = count.clone();
+            ap.plugins().register("farm_1-7", move |_| {
+                count.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            });
+        }
+        ap.plugins()
+            .register("never", |_| Err(anyhow::anyhow!("not yet")));
+
+        let args = |args: &[(&str, Value)]| {
+            args.iter()
+                .map(|(name, value)| (name.to_string(), value.clone()))
+                .collect::<HashMap<_, _>>()
+        };
+        let stage = ("stage", Value::from("1-7"));
+        Task::from_toml_with(PARAMS_TASK, &args(std::slice::from_ref(&stage)))
+            .unwrap()
+            .execute(&ap)
+            .unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+        Task::from_toml_with(PARAMS_TASK, &args(&[stage.clone(), ("times", 3.into())]))
+            .unwrap()
+            .execute(&ap)
+            .unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 5);
+
+        // By name, from the resource
+        let root = std::env::temp_dir().join(format!("ap-task-params-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("farm.toml"), PARAMS_TASK).unwrap();
+        ap.set_resource(crate::resource::Resource::load(&root).unwrap());
+        ap.run_task_with("farm", args(std::slice::from_ref(&stage)))
+            .unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 7);
+        let daily = r#"
+name = "daily"
+steps = [{ RunTask = { name = "farm", args = { stage = "1-7", times = 1 } } }]
+"#;
+        Task::from_toml(daily).unwrap().execute(&ap).unwrap();
+        assert_eq!(count.load(Ordering::SeqCst), 8);
+        let err = ap.run_task_with("weekly", HashMap::new()).unwrap_err();
+        assert_eq!(err.to_string(), "no task named weekly in the resource");
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let err = Task::from_toml(PARAMS_TASK).err().unwrap();
+        assert_eq!(err.to_string(), "missing parameter stage");
+        let err = Task::from_toml_with(PARAMS_TASK, &args(&[stage, ("level", 1.into())]))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "unknown parameter level");
+    }
+
+    #[test]
+    fn test_swap_controller() {
+        let ap = AutoPlay::new(DummyController::default());
+        let events = ap.subscribe();
+        ap.click(1, 2).unwrap();
+
+        let prev = ap.swap_controller(DummyController::default());
+        ap.click(3, 4).unwrap();
+
+        let prev = prev.downcast_ref::<DummyController>().unwrap();
+        assert_eq!(*prev.clicks.lock().unwrap(), [(1, 2)]);
+        let clicks = ap
+            .with_controller(|controller: &DummyController| {
+                controller.clicks.lock().unwrap().clone()
+            })
+            .unwrap();
+        assert_eq!(clicks, [(3, 4)]);
+
+        let events = events.try_iter().collect::<Vec<_>>();
+        assert!(matches!(
+            events[1],
+            Event::ControllerSwapped {
+                screen_size: (1920, 1080),
+                scale_factor: 1.0
+            }
+        ));
+    }
+
+    #[test]
+    fn test_missing_plugin() {
+        let ap = AutoPlay::new(DummyController::default());
+        let task = Task::from_toml(TASK).unwrap();
+        assert!(task.execute(&ap).is_err());
+    }
+
+    #[test]
+    fn test_task_events() {
+        let ap = AutoPlay::new(DummyController::default());
+        let events = ap.subscribe();
+        let task = Task::from_toml(TASK).unwrap();
+        assert!(task.execute(&ap).is_err());
+
+        let events = events.try_iter().collect::<Vec<_>>();
+        assert!(matches!(&events[0], Event::TaskStarted { name } if name == "test"));
+        assert!(
+            matches!(&events[1], Event::StepStarted { index: 0, action, .. } if action == "Click")
+        );
+        assert!(matches!(events[2], Event::Click { x: 1, y: 2 }));
+        assert!(matches!(
+            events[3],
+            Event::StepFinished {
+                index: 0,
+                error: None,
+                ..
+            }
+        ));
+        assert!(
+            matches!(&events[4], Event::StepStarted { index: 1, action, .. } if action == "PluginAction")
+        );
+        assert!(matches!(
+            &events[5],
+            Event::StepFinished {
+                index: 1,
+                error: Some(_),
+                ..
+            }
+        ));
+        assert!(matches!(
+            &events[6],
+            Event::TaskFinished { error: Some(_), .. }
+        ));
+        assert_eq!(events.len(), 7);
+    }
+
+    #[test]
+    fn test_execute_with_report() {
+        let ap = AutoPlay::new(DummyController::default());
+        let task = Task::from_toml(TASK).unwrap();
+        let report = task.execute_with_report(&ap, false);
+
+        assert!(!report.is_success());
+        assert_eq!(report.steps.len(), 2);
+        assert!(report.steps[0].is_success());
+        assert!(matches!(
+            report.steps[0].annotations[..],
+            [Annotation::Click { x: 1, y: 2 }]
+        ));
+        assert_eq!(report.steps[1].action, "PluginAction");
+        assert!(report.steps[1].error.is_some());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let ap = AutoPlay::new(DummyController::default());
+        let token = CancellationToken::new();
+        {
+            let token = token.clone();
+            ap.plugins().register("count", move |ap| {
+                // Resumed from another thread, then cancelled before the last step
+                token.pause();
+                let resume = token.clone();
+                std::thread::spawn(move || {
+                    std::thread::sleep(std::time::Duration::from_millis(20));
+                    resume.resume();
+                });
+                token.check()?;
+                token.cancel();
+                ap.click(5, 6)
+            });
+        }
+
+        let task = Task::from_toml(TASK).unwrap();
+        let report = task.execute_with_report_cancellable(&ap, false, &token);
+        assert_eq!(report.status(), crate::report::TaskStatus::Cancelled);
+        assert_eq!(report.steps.len(), 2);
+        assert!(report.steps.iter().all(StepReport::is_success));
+
+        let clicks = ap
+            .with_controller(|controller: &DummyController| {
+                controller.clicks.lock().unwrap().clone()
+            })
+            .unwrap();
+        assert_eq!(clicks, [(1, 2), (5, 6)]);
+
+        let err = task.execute_cancellable(&ap, &token).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn test_cancel_nested() {
+        let ap = AutoPlay::new(DummyController::default());
+        let token = CancellationToken::new();
+        let count = Arc::new(AtomicU32::new(0));
+        {
+            let (token, count) = (token.clone(), count.clone());
+            ap.plugins().register("count", move |_| {
+                if count.fetch_add(1, Ordering::SeqCst) == 2 {
+                    token.cancel();
+                }
+                Ok(())
+            });
+        }
+        ap.plugins()
+            .register("never", |_| Err(anyhow::anyhow!("not yet")));
+
+        // A repeat in a task in a task stops with the outermost one
+        let task = Task::from_toml(
+            r#"
+            name = "outer"
+
+            [[steps]]
+            [steps.Task]
+            name = "inner"
+
+            [[steps.Task.steps]]
+            [steps.Task.steps.Repeat]
+            until = { Succeeds = { PluginAction = { name = "never" } } }
+            max_iters = 1000
+            steps = [{ PluginAction = { name = "count" } }]
+            "#,
+        )
+        .unwrap();
+        let err = task.execute_cancellable(&ap, &token).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Cancelled)
+        ));
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_cancel_in_condition() {
+        let ap = AutoPlay::new(DummyController::default());
+        let token = CancellationToken::new();
+        {
+            let token = token.clone();
+            ap.plugins().register("stop", move |_| {
+                token.cancel();
+                Ok(())
+            });
+        }
+
+        // Cancelled before its second step, the condition neither holds nor not
+        let task = Task::from_toml(
+            r#"
+            name = "cancelled"
+
+            [[steps]]
+            [steps.If]
+            cond = { Succeeds = { Task = { name = "probe", steps = [
+                { PluginAction = { name = "stop" } },
+                { Click = { x = 1, y = 2 } },
+            ] } } }
+            then_steps = [{ Click = { x = 3, y = 4 } }]
+            else_steps = [{ Click = { x = 5, y = 6 } }]
+            "#,
+        )
+        .unwrap();
+        let err = task.execute_cancellable(&ap, &token).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Cancelled)
+        ));
+        let clicks = ap
+            .with_controller(|controller: &DummyController| {
+                controller.clicks.lock().unwrap().clone()
+            })
+            .unwrap();
+        assert!(clicks.is_empty());
+    }
+
+    const APP_TASK: &str = r#"
+name = "app"
+
+[[steps]]
+LaunchAppAction = { package = "com.example.game" }
+
+[[steps]]
+WaitFocus = { package = "com.example.game", timeout_ms = 100 }
+
+[[steps]]
+StopApp = { package = "com.example.game" }
+"#;
+
+    #[test]
+    fn test_app_actions() {
+        let ap = AutoPlay::new(DummyController {
+            apps: Some(Mutex::new(vec!["com.example.launcher".to_string()])),
+            ..Default::default()
+        });
+        let task = Task::from_toml(APP_TASK).unwrap();
+        task.execute(&ap).unwrap();
+        let apps = ap
+            .with_controller(|controller: &DummyController| {
+                controller.apps.as_ref().unwrap().lock().unwrap().clone()
+            })
+            .unwrap();
+        assert_eq!(apps, ["com.example.launcher"]);
+
+        // The launcher stays in the foreground
+        let err = Task::from_toml(&APP_TASK.replace("LaunchAppAction", "StopApp"))
+            .unwrap()
+            .execute(&ap)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Timeout(_))
+        ));
+
+        let ap = AutoPlay::new(DummyController::default());
+        let err = task.execute(&ap).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_mock_controller() {
+        use ap_controller::{
+            android::keycode::KEYCODE_BACK,
+            mock::{MockController, MockInput, Transition, Trigger},
+        };
+
+        let menu = image::math::Rect {
+            x: 100,
+            y: 0,
+            width: 20,
+            height: 20,
+        };
+        let mock = MockController::new("main", shapes(120, 40, &[(100, 0, 20, 20)]))
+            .with_screen("menu", shapes(120, 40, &[]))
+            .with_transition(Transition::new(Some("main"), Trigger::click(menu), "menu"))
+            .with_transition(Transition::new(
+                Some("menu"),
+                Trigger::Keycode(KEYCODE_BACK),
+                "main",
+            ));
+        let ap = AutoPlay::new(mock);
+        let task = Task::from_toml(
+            "name = \"menu\"\n[[steps]]\nClick = { x = 110, y = 10 }\n\
+             [[steps]]\nPress = { key = \"Back\" }\n[[steps]]\nClick = { x = 10, y = 10 }",
+        )
+        .unwrap();
+        task.execute(&ap).unwrap();
+        let (screen, inputs) = ap
+            .with_controller(|mock: &MockController| (mock.screen(), mock.inputs()))
+            .unwrap();
+        assert_eq!(screen, "main");
+        assert_eq!(
+            inputs,
+            [
+                MockInput::Click(110, 10),
+                MockInput::Keycode(KEYCODE_BACK),
+                MockInput::Click(10, 10),
+            ]
+        );
+    }
+
+    /// A black image with white rectangles `(x, y, width, height)`
+    fn shapes(width: u32, height: u32, rects: &[(u32, u32, u32, u32)]) -> image::RgbaImage {
+        let mut image = image::RgbaImage::from_pixel(width, height, image::Rgba([0, 0, 0, 255]));
+        for &(x, y, w, h) in rects {
+            for (dx, dy) in (0..w).flat_map(|dx| (0..h).map(move |dy| (dx, dy))) {
+                image.put_pixel(x + dx, y + dy, image::Rgba([255, 255, 255, 255]));
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn test_click_nth_match() {
+        // Three white squares in a row, the template is one with its black border
+        let screen = shapes(120, 40, &[(92, 12, 8, 8), (12, 12, 8, 8), (52, 12, 8, 8)]);
+        let template = shapes(12, 12, &[(2, 2, 8, 8)]);
+        let path = std::env::temp_dir().join(format!("ap-nth-{}.png", std::process::id()));
+        template.save(&path).unwrap();
+
+        let ap = AutoPlay::new(DummyController {
+            screen: Some(image::DynamicImage::ImageRgba8(screen)),
+            ..Default::default()
+        });
+        let task = |nth| {
+            Task::from_toml(&format!(
+                "name = \"nth\"\n[[steps]]\nClickMatchTemplate = {{ template = {path:?}, \
+                 method = \"sqdiff_normed\", nth = {nth}, offset = [0, 10] }}"
+            ))
+            .unwrap()
+        };
+        task(1).execute(&ap).unwrap();
+        assert!(task(3).execute(&ap).is_err());
+        std::fs::remove_file(&path).unwrap();
+
+        let clicks = ap
+            .with_controller(|controller: &DummyController| {
+                controller.clicks.lock().unwrap().clone()
+            })
+            .unwrap();
+        assert_eq!(clicks, [(56, 26)]);
+    }
+
+    #[test]
+    fn test_humanize_override() {
+        let ap = AutoPlay::new(DummyController::default());
+        let global = Arc::new(Mutex::new(Vec::new()));
+        {
+            let global = global.clone();
+            ap.plugins().register("global", move |ap| {
+                global.lock().unwrap().push(ap.humanize());
+                Ok(())
+            });
+        }
+        let task = Task::from_toml(
+            r#"
+            name = "humanized"
+
+            [humanize]
+            step_delay_ms = [30, 30]
+            click_jitter = 0.0
+
+            [[steps]]
+            Click = { x = 10, y = 20 }
+
+            [[steps]]
+            [steps.Task]
+            name = "inner"
+
+            [[steps.Task.steps]]
+            Click = { x = 30, y = 40 }
+
+            [[steps.Task.steps]]
+            PluginAction = { name = "global" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(task.humanize.unwrap().step_delay_ms, (30, 30));
+
+        let start = Instant::now();
+        task.execute(&ap).unwrap();
+        // Only waits between the steps, of the nested task too which has none of
+        // its own
+        assert!(start.elapsed() >= std::time::Duration::from_millis(60));
+        // The policy of the AutoPlay is left alone, even while the task runs
+        assert!(ap.humanize().is_none());
+        assert_eq!(*global.lock().unwrap(), [None]);
+        let clicks = ap
+            .with_controller(|controller: &DummyController| {
+                controller.clicks.lock().unwrap().clone()
+            })
+            .unwrap();
+        assert_eq!(clicks, [(10, 20), (30, 40)]);
+    }
+
+    #[test]
+    fn test_anchored_points() {
+        // Black bars all around a 16:9 game drawn at 1440x810
+        let ap = AutoPlay::new(DummyController {
+            screen: Some(image::DynamicImage::ImageRgba8(shapes(
+                1920,
+                1080,
+                &[(240, 135, 1440, 810)],
+            ))),
+            ..Default::default()
+        });
+        let task = Task::from_toml(
+            r#"
+            name = "anchored"
+
+            [[steps]]
+            Click = { x = 10, y = 20 }
+
+            [[steps]]
+            Click = { anchor = "top-left", x = 0.1, y = 0.1 }
+
+            [[steps]]
+            [steps.Swipe]
+            start = [100, 200]
+            end = { anchor = "bottom-right", x = 0.9, y = 0.9 }
+            duration = { secs = 0, nanos = 0 }
+            slope_in = 1.0
+            slope_out = 1.0
+
+            [[steps]]
+            Click = { anchor = "center", x = 2.0, y = 0.5 }
+            "#,
+        )
+        .unwrap();
+        let err = task.execute(&ap).unwrap_err();
+        assert!(format!("{err:#}").contains("outside the 1920x1080 screen"));
+
+        let (clicks, swipes) = ap
+            .with_controller(|controller: &DummyController| {
+                (
+                    controller.clicks.lock().unwrap().clone(),
+                    controller.swipes.lock().unwrap().clone(),
+                )
+            })
+            .unwrap();
+        assert_eq!(clicks, [(10, 20), (384, 216)]);
+        assert_eq!(swipes, [((100, 200), (1536, 864))]);
+
+        // Detected again once after the step failed
+        let captures = || {
+            ap.with_controller(|controller: &DummyController| {
+                controller
+                    .captures
+                    .load(std::sync::atomic::Ordering::Relaxed)
+            })
+            .unwrap()
+        };
+        let before = captures();
+        let area = ap.controller().content_area().unwrap();
+        assert_eq!((area.x, area.y, area.width), (240, 135, 1440));
+        ap.controller().content_area().unwrap();
+        assert_eq!(captures(), before + 1);
+    }
+
+    #[test]
+    fn test_device_condition() {
+        let task = Task::from_toml(
+            r#"
+            name = "device"
+
+            [[steps]]
+            [steps.If]
+            cond = { Device = { abi = "arm64-v8a", min_sdk = 30 } }
+            then_steps = [{ Click = { x = 1, y = 2 } }]
+            else_steps = [{ Click = { x = 3, y = 4 } }]
+            "#,
+        )
+        .unwrap();
+        let clicks = |profile| {
+            let ap = AutoPlay::new(DummyController {
+                profile,
+                ..Default::default()
+            });
+            task.execute(&ap).unwrap();
+            ap.with_controller(|controller: &DummyController| {
+                controller.clicks.lock().unwrap().clone()
+            })
+            .unwrap()
+        };
+        let profile = |abi: &str, sdk| ap_controller::DeviceProfile {
+            serial: "emulator-5554".to_string(),
+            abi: abi.to_string(),
+            sdk,
+            resolution: (1920, 1080),
+            density: Some(320),
+            touch: None,
+            probed_at: 0,
+        };
+        assert_eq!(clicks(Some(profile("arm64-v8a", 34))), [(1, 2)]);
+        assert_eq!(clicks(Some(profile("arm64-v8a", 29))), [(3, 4)]);
+        assert_eq!(clicks(Some(profile("x86_64", 34))), [(3, 4)]);
+        assert_eq!(clicks(None), [(3, 4)]);
+    }
+
+    #[test]
+    fn test_swipe_match_template() {
+        // A square to drag onto a bar
+        let screen = shapes(120, 60, &[(12, 12, 8, 8), (80, 30, 4, 16)]);
+        let dir = std::env::temp_dir().join(format!("ap-drag-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        shapes(12, 12, &[(2, 2, 8, 8)])
+            .save(dir.join("item.png"))
+            .unwrap();
+        shapes(8, 20, &[(2, 2, 4, 16)])
+            .save(dir.join("slot.png"))
+            .unwrap();
+        std::fs::write(dir.join("empty.png"), []).unwrap();
+
+        let ap = AutoPlay::new(DummyController {
+            screen: Some(image::DynamicImage::ImageRgba8(screen)),
+            ..Default::default()
+        });
+        let task = |to: &str| {
+            Task::from_toml(&format!(
+                "name = \"drag\"\n[[steps]]\nSwipeMatchTemplate = {{ from = {:?}, to = {:?} }}",
+                dir.join("item.png"),
+                dir.join(to),
+            ))
+            .unwrap()
+        };
+        task("slot.png").execute(&ap).unwrap();
+        assert!(task("empty.png").execute(&ap).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let swipes = ap
+            .with_controller(|controller: &DummyController| {
+                controller.swipes.lock().unwrap().clone()
+            })
+            .unwrap();
+        assert_eq!(swipes, [((16, 16), (82, 38))]);
+    }
+
+    #[test]
+    fn test_scroll_find() {
+        let screen = shapes(100, 200, &[(12, 12, 8, 8)]);
+        let dir = std::env::temp_dir().join(format!("ap-scroll-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        shapes(12, 12, &[(2, 2, 8, 8)])
+            .save(dir.join("entry.png"))
+            .unwrap();
+        shapes(8, 20, &[(2, 2, 4, 16)])
+            .save(dir.join("absent.png"))
+            .unwrap();
+
+        let ap = AutoPlay::new(DummyController {
+            screen: Some(image::DynamicImage::ImageRgba8(screen)),
+            ..Default::default()
+        });
+        let task = |template: &str| {
+            Task::from_toml(&format!(
+                "name = \"scroll\"\n[[steps]]\nScrollFind = {{ template = {:?}, \
+                 max_scrolls = 2, settle_ms = 0, click = true }}",
+                dir.join(template),
+            ))
+            .unwrap()
+        };
+        let report = task("entry.png").execute_with_report(&ap, false);
+        assert!(report.is_success());
+        assert_eq!(report.steps[0].iterations, Some(0));
+        let report = task("absent.png").execute_with_report(&ap, false);
+        assert!(!report.is_success());
+        assert_eq!(report.steps[0].iterations, Some(2));
+        // Stopped with the task running it, before scrolling
+        let token = CancellationToken::new();
+        token.cancel();
+        let err = task("absent.png").steps[0]
+            .execute_in(&ExecContext::with_token(&ap, token))
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::Cancelled)
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let (clicks, swipes) = ap
+            .with_controller(|controller: &DummyController| {
+                (
+                    controller.clicks.lock().unwrap().clone(),
+                    controller.swipes.lock().unwrap().clone(),
+                )
+            })
+            .unwrap();
+        assert_eq!(clicks, [(16, 16)]);
+        // Swiping up through the center of the screen, for half its height
+        assert_eq!(swipes, [((960, 810), (960, 270)); 2]);
+    }
+
+    #[test]
+    fn test_debug_bundle() {
+        use crate::debug_bundle::{DebugBundleOptions, RecentLogs, Redaction};
+
+        let dir = std::env::temp_dir().join(format!("ap-bundle-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        shapes(8, 20, &[(2, 2, 4, 16)])
+            .save(dir.join("absent.png"))
+            .unwrap();
+        let ap = AutoPlay::new(DummyController {
+            screen: Some(image::DynamicImage::ImageRgba8(shapes(
+                100,
+                200,
+                &[(12, 12, 8, 8)],
+            ))),
+            profile: Some(ap_controller::DeviceProfile {
+                serial: "emulator-5554".to_string(),
+                abi: "x86_64".to_string(),
+                sdk: 34,
+                resolution: (100, 200),
+                density: None,
+                touch: None,
+                probed_at: 0,
+            }),
+            ..Default::default()
+        });
+        let logs = RecentLogs::new(2);
+        {
+            use std::io::Write;
+            use tracing_subscriber::fmt::MakeWriter;
+            for line in ["first", "second on emulator-5554", "third on emulator-5554"] {
+                writeln!(logs.make_writer(), "{line}").unwrap();
+            }
+        }
+        let redaction = Redaction {
+            strings: vec!["third".to_string()],
+            regions: vec![image::math::Rect {
+                x: 0,
+                y: 0,
+                width: 50,
+                height: 50,
+            }],
+        };
+        let bundles = dir.join("bundles");
+        ap.set_debug_bundle(Some(
+            DebugBundleOptions::new(&bundles)
+                .with_redaction(Some(redaction))
+                .with_logs(logs),
+        ));
+        // Not written for a task failing as expected, checking a condition
+        let probe = Task::from_toml(&format!(
+            "name = \"probe\"\n[[steps]]\n[steps.If]\n\
+             cond = {{ Succeeds = {{ Task = {{ name = \"check\", steps = [\
+             {{ ScrollFind = {{ template = {:?}, max_scrolls = 0, settle_ms = 0 }} }}] }} }} }}",
+            dir.join("absent.png"),
+        ))
+        .unwrap();
+        probe.execute(&ap).unwrap();
+        assert!(!bundles.exists());
+        // Only the task run writes one
+        let task = Task::from_toml(&format!(
+            "name = \"outer\"\n[[steps]]\n[steps.Task]\nname = \"inner\"\n\
+             steps = [{{ ScrollFind = {{ template = {:?}, max_scrolls = 0, settle_ms = 0 }} }}]",
+            dir.join("absent.png"),
+        ))
+        .unwrap();
+        assert!(task.execute(&ap).is_err());
+
+        let paths = std::fs::read_dir(&bundles)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        assert_eq!(paths.len(), 1);
+        let files = debug_bundle::read(&paths[0]);
+        assert_eq!(
+            files.keys().map(String::as_str).collect::<Vec<_>>(),
+            [
+                "log.txt",
+                "screen.png",
+                "summary.json",
+                // Of the check, then of the task
+                "templates/0-absent.png",
+                "templates/1-absent.png",
+            ]
+        );
+        let summary: serde_json::Value = serde_json::from_slice(&files["summary.json"]).unwrap();
+        assert_eq!(summary["task"], "outer");
+        assert_eq!(summary["action"], "Task");
+        assert!(
+            summary["error"]
+                .as_str()
+                .unwrap()
+                .contains("task inner failed at step 0")
+        );
+        assert_eq!(summary["profile"]["serial"], "<redacted>");
+        assert_eq!(
+            summary["matches"][0]["template_size"],
+            serde_json::json!([8, 20])
+        );
+        assert_eq!(
+            String::from_utf8_lossy(&files["log.txt"]),
+            "second on <redacted>\n<redacted> on <redacted>\n"
+        );
+        let screen = image::load_from_memory(&files["screen.png"])
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(screen.get_pixel(14, 14), &image::Rgba([0, 0, 0, 255]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_match_overlay() {
+        let path = std::env::temp_dir().join(format!("ap-overlay-{}.png", std::process::id()));
+        shapes(8, 20, &[(2, 2, 4, 16)]).save(&path).unwrap();
+        let ap = AutoPlay::new(DummyController {
+            screen: Some(image::DynamicImage::ImageRgba8(shapes(
+                100,
+                60,
+                &[(12, 12, 8, 8)],
+            ))),
+            ..Default::default()
+        });
+        let task = Task::from_toml(&format!(
+            "name = \"overlay\"\n[[steps]]\nClickMatchTemplate = {{ template = {path:?}, \
+             region = {{ x = 10, y = 10, width = 60, height = 40 }} }}"
+        ))
+        .unwrap();
+        let captures = || {
+            ap.with_controller(|controller: &DummyController| {
+                controller
+                    .captures
+                    .load(std::sync::atomic::Ordering::Relaxed)
+            })
+            .unwrap()
+        };
+        // Not drawn without subscribers
+        assert!(!ap.events().has_subscribers());
+        assert!(task.execute(&ap).is_err());
+        let missed = captures();
+        assert!(missed > 0);
+
+        let events = ap.subscribe();
+        assert!(task.execute(&ap).is_err());
+        std::fs::remove_file(&path).unwrap();
+        // Drawn on the frame the template was matched on, not captured again
+        assert_eq!(captures(), missed * 2);
+        let (frame, offset, template_size, candidate) = events
+            .try_iter()
+            .find_map(|event| match event {
+                Event::MatchOverlay {
+                    frame,
+                    offset,
+                    template_size,
+                    candidate,
+                } => Some((frame, offset, template_size, candidate)),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!((frame.width(), frame.height()), (60, 40));
+        assert_eq!(offset, (10, 10));
+        assert_eq!(template_size, (8, 20));
+        // Above the threshold of the squared differences, somewhere in the region
+        let candidate = candidate.unwrap();
+        assert!(candidate.value >= crate::MatcherOptions::default().threshold);
+        assert!(candidate.rect.x >= 10 && candidate.rect.y >= 10);
+        let region = shapes(100, 60, &[(12, 12, 8, 8)]);
+        let region = image::imageops::crop_imm(&region, 10, 10, 60, 40).to_image();
+        assert_ne!(frame.to_rgba8(), region);
+    }
+}
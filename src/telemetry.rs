@@ -0,0 +1,262 @@
+//! Metrics of task runs as JSON lines, to analyze failures across many runs
+//!
+//! A [`RunMetrics`] is built from the [`ExecutionReport`] of a run and the
+//! [events](crate::event) received during it, with the duration, the error and
+//! the template matches of every step. A [`MetricsExporter`] appends each of
+//! them as a line of JSON to a file, to load with any tool reading NDJSON.
+//!
+//! ```ignore
+//! let exporter = MetricsExporter::open("metrics.jsonl")?;
+//! let events = ap.subscribe();
+//! let report = task.execute_with_report(&ap, false);
+//! let events = events.try_iter().collect::<Vec<_>>();
+//! exporter.export(&RunMetrics::new(&report, &events).with_device("emulator-5554"))?;
+//! ```
+//!
+//! The spans of the task runner (`task`, `step`), of template matching and of
+//! the controllers carry the same fields, for a `tracing` subscriber to export
+//! them as traces instead.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::UNIX_EPOCH,
+};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{
+    event::Event,
+    report::{ExecutionReport, TaskStatus},
+};
+
+/// A template match during a step
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MatchMetrics {
+    pub found: bool,
+    /// `None` for feature matches
+    pub score: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StepMetrics {
+    pub index: usize,
+    pub action: String,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+    /// The matches of the step, of its nested tasks included
+    pub matches: Vec<MatchMetrics>,
+}
+
+/// The metrics of a task run, see the [module](self) docs.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunMetrics {
+    pub task: String,
+    pub device: Option<String>,
+    pub status: TaskStatus,
+    pub started_at_ms: u64,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+    pub steps: Vec<StepMetrics>,
+}
+
+impl RunMetrics {
+    /// The metrics of the run of `report`, with the matches among `events`,
+    /// the events emitted while it ran.
+    pub fn new(report: &ExecutionReport, events: &[Event]) -> Self {
+        let mut steps = report
+            .steps
+            .iter()
+            .map(|step| StepMetrics {
+                index: step.index,
+                action: step.action.clone(),
+                duration_ms: step.duration.as_millis() as u64,
+                error: step.error.clone(),
+                matches: Vec::new(),
+            })
+            .collect::<Vec<_>>();
+
+        // Nested tasks run their own steps, the ones of the report are at depth 1
+        let mut depth = 0;
+        let mut current = None;
+        for event in events {
+            match event {
+                Event::TaskStarted { .. } => depth += 1,
+                Event::TaskFinished { .. } => depth -= 1,
+                Event::StepStarted { task, index, .. } if depth == 1 && *task == report.task => {
+                    current = steps.iter().position(|step| step.index == *index);
+                }
+                Event::StepFinished { task, .. } if depth == 1 && *task == report.task => {
+                    current = None;
+                }
+                Event::MatchResult { rect, score, .. } => {
+                    if let Some(step) = current.map(|current| &mut steps[current]) {
+                        step.matches.push(MatchMetrics {
+                            found: rect.is_some(),
+                            score: *score,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Self {
+            task: report.task.clone(),
+            device: None,
+            status: report.status(),
+            started_at_ms: report
+                .started_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            duration_ms: report.duration.as_millis() as u64,
+            error: report.error.clone(),
+            steps,
+        }
+    }
+
+    /// Tell which device the task ran on.
+    pub fn with_device(mut self, device: impl Into<String>) -> Self {
+        self.device = Some(device.into());
+        self
+    }
+}
+
+/// Appends [`RunMetrics`] to a file as JSON lines, it can be shared by the
+/// threads of a [`Fleet`](crate::fleet::Fleet).
+pub struct MetricsExporter {
+    file: Mutex<BufWriter<File>>,
+}
+
+impl MetricsExporter {
+    /// Append to `path`, created if it does not exist.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        Ok(Self {
+            file: Mutex::new(BufWriter::new(file)),
+        })
+    }
+
+    pub fn export(&self, metrics: &RunMetrics) -> anyhow::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        serde_json::to_writer(&mut *file, metrics)?;
+        file.write_all(b"\n")?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::report::StepReport;
+
+    fn step(index: usize, error: Option<&str>) -> StepReport {
+        StepReport {
+            index,
+            action: "ClickMatchTemplate".to_string(),
+            started: Duration::ZERO,
+            duration: Duration::from_millis(20),
+            error: error.map(str::to_string),
+            screenshot: None,
+            annotations: Vec::new(),
+            iterations: None,
+        }
+    }
+
+    fn matched(score: f32, found: bool) -> Event {
+        Event::MatchResult {
+            template_size: (10, 10),
+            rect: found.then_some(image::math::Rect {
+                x: 0,
+                y: 0,
+                width: 10,
+                height: 10,
+            }),
+            score: Some(score),
+        }
+    }
+
+    #[test]
+    fn test_run_metrics() {
+        let mut report = ExecutionReport::new("daily");
+        report.steps = vec![step(0, None), step(1, Some("not found"))];
+        report.error = Some("not found".to_string());
+        let started = |task: &str, index| Event::StepStarted {
+            task: task.to_string(),
+            index,
+            action: String::new(),
+        };
+        let finished = |task: &str, index| Event::StepFinished {
+            task: task.to_string(),
+            index,
+            duration: Duration::ZERO,
+            error: None,
+        };
+        let task = |name: &str, start| {
+            if start {
+                Event::TaskStarted {
+                    name: name.to_string(),
+                }
+            } else {
+                Event::TaskFinished {
+                    name: name.to_string(),
+                    error: None,
+                }
+            }
+        };
+        let events = [
+            task("daily", true),
+            started("daily", 0),
+            matched(0.99, true),
+            finished("daily", 0),
+            started("daily", 1),
+            // A nested task with a step of the same index
+            task("mail", true),
+            started("mail", 0),
+            matched(0.5, false),
+            finished("mail", 0),
+            task("mail", false),
+            matched(0.4, false),
+            finished("daily", 1),
+            task("daily", false),
+        ];
+
+        let metrics = RunMetrics::new(&report, &events).with_device("emulator-5554");
+        assert_eq!(metrics.status, TaskStatus::Failed);
+        assert_eq!(metrics.device.as_deref(), Some("emulator-5554"));
+        assert_eq!(metrics.steps[0].matches.len(), 1);
+        assert!(metrics.steps[0].matches[0].found);
+        let scores = metrics.steps[1]
+            .matches
+            .iter()
+            .map(|m| m.score.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(scores, [0.5, 0.4]);
+
+        let path = std::env::temp_dir().join(format!("ap-metrics-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let exporter = MetricsExporter::open(&path).unwrap();
+        exporter.export(&metrics).unwrap();
+        exporter.export(&metrics).unwrap();
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines = content.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let json: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["steps"][1]["duration_ms"], 20);
+        assert_eq!(json["steps"][1]["error"], "not found");
+    }
+}
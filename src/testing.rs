@@ -0,0 +1,65 @@
+//! A controller for the tests which do not look at the screen
+
+use std::sync::Mutex;
+
+use ap_controller::ControllerTrait;
+use image::{DynamicImage, Rgba, RgbaImage};
+
+/// A black screen of a given size which records the clicks, a click outside of
+/// the screen fails as on a device.
+pub(crate) struct DummyController {
+    size: (u32, u32),
+    clicks: Mutex<Vec<(u32, u32)>>,
+}
+
+impl DummyController {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            size: (width, height),
+            clicks: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The clicks so far, i.e. for `ap.with_controller(DummyController::clicks)`
+    pub fn clicks(&self) -> Vec<(u32, u32)> {
+        self.clicks.lock().unwrap().clone()
+    }
+}
+
+impl ControllerTrait for DummyController {
+    fn screen_size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    fn screencap_raw(&self) -> anyhow::Result<(u32, u32, Vec<u8>)> {
+        let screen = self.screencap()?.into_rgba8();
+        Ok((screen.width(), screen.height(), screen.into_raw()))
+    }
+
+    fn screencap(&self) -> anyhow::Result<DynamicImage> {
+        let (width, height) = self.size;
+        Ok(RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 255])).into())
+    }
+
+    fn click(&self, x: u32, y: u32) -> anyhow::Result<()> {
+        let (width, height) = self.size;
+        anyhow::ensure!(x < width && y < height, "({x}, {y}) is outside the screen");
+        self.clicks.lock().unwrap().push((x, y));
+        Ok(())
+    }
+
+    fn swipe(
+        &self,
+        _start: (u32, u32),
+        _end: (i32, i32),
+        _duration: std::time::Duration,
+        _slope_in: f32,
+        _slope_out: f32,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn press(&self, _key: ap_controller::Key) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
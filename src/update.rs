@@ -0,0 +1,382 @@
+//! Keep a resource directory up to date with a published resource pack
+//!
+//! A pack is a directory of tasks and templates with a [`Manifest`] at its root,
+//! `manifest.toml`, listing the SHA-256 of every file:
+//!
+//! ```toml
+//! last_updated = 1717200000
+//!
+//! [files]
+//! "daily.toml" = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+//! "templates/reward.png" = "60303ae22b998861bce3b28f33eec1be758a213c86c93c076dbe9f558c11c752"
+//! ```
+//!
+//! It is published over HTTP, in a git repository or in a local directory, see
+//! [`Source`]. [`Updater::update`] downloads what changed, verifies it and swaps
+//! the local directory for the new version at once:
+//!
+//! ```ignore
+//! let updater = Updater::new(Source::parse("https://example.com/packs/game"), "resources");
+//! if updater.update()?.changed() {
+//!     ap.set_resource(Resource::load("resources")?);
+//! }
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path, PathBuf},
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::info;
+
+pub use crate::resource::MANIFEST;
+
+/// Largest file downloaded, a template or a task is far smaller
+const MAX_FILE_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The files of a resource pack and their checksums
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Seconds since the Unix epoch, a pack is only updated to a newer one
+    pub last_updated: u64,
+    /// SHA-256 of each file in hex, by path relative to the root with `/`
+    pub files: BTreeMap<String, String>,
+}
+
+impl Manifest {
+    pub fn from_toml(source: &str) -> anyhow::Result<Self> {
+        let manifest = toml::from_str::<Self>(source).context("failed to parse manifest")?;
+        if let Some(path) = manifest.files.keys().find(|path| !is_relative(path)) {
+            anyhow::bail!("invalid path {path:?} in manifest");
+        }
+        Ok(manifest)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::from_toml(&source).with_context(|| format!("in {}", path.display()))
+    }
+
+    /// The manifest of the files under `dir`, updated now, i.e. to publish it.
+    pub fn generate(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref();
+        let mut files = BTreeMap::new();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(cur) = dirs.pop() {
+            for entry in std::fs::read_dir(&cur)
+                .with_context(|| format!("failed to read {}", cur.display()))?
+            {
+                let path = entry?.path();
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                if name.starts_with('.') || path == dir.join(MANIFEST) {
+                    continue;
+                }
+                if path.is_dir() {
+                    dirs.push(path);
+                    continue;
+                }
+                let relative = path
+                    .strip_prefix(dir)?
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                files.insert(relative, sha256(&std::fs::read(&path)?));
+            }
+        }
+        let last_updated = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Ok(Self {
+            last_updated,
+            files,
+        })
+    }
+
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    /// Write the manifest of `dir` at its root.
+    pub fn write(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref();
+        let manifest = Self::generate(dir)?;
+        let path = dir.join(MANIFEST);
+        std::fs::write(&path, manifest.to_toml()?)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(manifest)
+    }
+}
+
+/// Where a resource pack is published
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// The root of the pack, files are fetched at `{url}/{path}`
+    Http(String),
+    /// A git repository, cloned next to the local directory
+    Git(String),
+    Dir(PathBuf),
+}
+
+impl Source {
+    /// `http(s)://...` is [`Source::Http`], `git+...` or `....git` is
+    /// [`Source::Git`], anything else a local directory.
+    pub fn parse(source: &str) -> Self {
+        if let Some(url) = source.strip_prefix("git+") {
+            Self::Git(url.to_string())
+        } else if source.ends_with(".git") {
+            Self::Git(source.to_string())
+        } else if source.starts_with("http://") || source.starts_with("https://") {
+            Self::Http(source.trim_end_matches('/').to_string())
+        } else {
+            Self::Dir(PathBuf::from(source))
+        }
+    }
+}
+
+/// What [`Updater::update`] did
+#[derive(Debug, Default)]
+pub struct UpdateReport {
+    /// The files downloaded, new or changed
+    pub downloaded: Vec<String>,
+    /// The files removed from the pack
+    pub removed: Vec<String>,
+    pub unchanged: usize,
+    /// The version installed, `None` if the local one was up to date
+    pub manifest: Option<Manifest>,
+}
+
+impl UpdateReport {
+    pub fn changed(&self) -> bool {
+        self.manifest.is_some()
+    }
+}
+
+pub struct Updater {
+    source: Source,
+    local: PathBuf,
+}
+
+impl Updater {
+    pub fn new(source: Source, local: impl Into<PathBuf>) -> Self {
+        Self {
+            source,
+            local: local.into(),
+        }
+    }
+
+    /// A directory next to the local one, hidden, i.e. `.resources.staging`
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let name = self.local.file_name().unwrap_or_default().to_string_lossy();
+        self.local.with_file_name(format!(".{name}.{suffix}"))
+    }
+
+    /// The local manifest, `None` if there is none yet.
+    pub fn local_manifest(&self) -> anyhow::Result<Option<Manifest>> {
+        let path = self.local.join(MANIFEST);
+        path.exists().then(|| Manifest::load(path)).transpose()
+    }
+
+    /// Install the published version if it is newer than the local one.
+    ///
+    /// Files whose checksum did not change are copied from the local directory,
+    /// the others are downloaded and verified. The new version is assembled next
+    /// to the local directory and swapped in only once complete, so a failed
+    /// update leaves the local one as it was.
+    pub fn update(&self) -> anyhow::Result<UpdateReport> {
+        let root = self.prepare()?;
+        let remote = Manifest::from_toml(
+            std::str::from_utf8(&self.fetch(&root, MANIFEST)?).context("invalid manifest")?,
+        )?;
+        let local = self.local_manifest()?.unwrap_or_default();
+        if remote.last_updated <= local.last_updated && self.local.exists() {
+            info!("resources are up to date");
+            return Ok(UpdateReport::default());
+        }
+
+        let staging = self.sibling("staging");
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging)?;
+        }
+        let mut report = UpdateReport::default();
+        for (path, checksum) in &remote.files {
+            let dest = staging.join(path);
+            std::fs::create_dir_all(dest.parent().unwrap())?;
+            let current = self.local.join(path);
+            if local.files.get(path) == Some(checksum)
+                && std::fs::read(&current).is_ok_and(|data| sha256(&data) == *checksum)
+            {
+                std::fs::copy(&current, &dest)?;
+                report.unchanged += 1;
+                continue;
+            }
+            let data = self.fetch(&root, path)?;
+            let actual = sha256(&data);
+            if actual != *checksum {
+                std::fs::remove_dir_all(&staging)?;
+                anyhow::bail!("checksum mismatch for {path}, expected {checksum}, got {actual}");
+            }
+            std::fs::write(&dest, data)?;
+            report.downloaded.push(path.clone());
+        }
+        std::fs::write(staging.join(MANIFEST), remote.to_toml()?)?;
+        report.removed = local
+            .files
+            .keys()
+            .filter(|path| !remote.files.contains_key(*path))
+            .cloned()
+            .collect();
+
+        self.swap(&staging)?;
+        info!(
+            "resources updated, {} downloaded, {} removed",
+            report.downloaded.len(),
+            report.removed.len()
+        );
+        report.manifest = Some(remote);
+        Ok(report)
+    }
+
+    /// Replace the local directory by `staging`.
+    fn swap(&self, staging: &Path) -> anyhow::Result<()> {
+        if !self.local.exists() {
+            return Ok(std::fs::rename(staging, &self.local)?);
+        }
+        let old = self.sibling("old");
+        if old.exists() {
+            std::fs::remove_dir_all(&old)?;
+        }
+        std::fs::rename(&self.local, &old)
+            .with_context(|| format!("failed to move {}", self.local.display()))?;
+        if let Err(err) = std::fs::rename(staging, &self.local) {
+            std::fs::rename(&old, &self.local)?;
+            return Err(err).with_context(|| format!("failed to replace {}", self.local.display()));
+        }
+        std::fs::remove_dir_all(&old)?;
+        Ok(())
+    }
+
+    /// The directory of a [`Source::Git`] or [`Source::Dir`] pack, cloning or
+    /// pulling the repository.
+    fn prepare(&self) -> anyhow::Result<Option<PathBuf>> {
+        match &self.source {
+            Source::Http(_) => Ok(None),
+            Source::Dir(dir) => Ok(Some(dir.clone())),
+            Source::Git(url) => {
+                let checkout = self.sibling("git");
+                let mut git = Command::new("git");
+                if checkout.exists() {
+                    git.arg("-C").arg(&checkout).args(["pull", "--ff-only"]);
+                } else {
+                    git.args(["clone", "--depth", "1", url]).arg(&checkout);
+                }
+                let status = git.status().context("failed to run git")?;
+                anyhow::ensure!(status.success(), "failed to fetch {url}: git {status}");
+                Ok(Some(checkout))
+            }
+        }
+    }
+
+    fn fetch(&self, root: &Option<PathBuf>, path: &str) -> anyhow::Result<Vec<u8>> {
+        match (&self.source, root) {
+            (Source::Http(url), _) => {
+                let url = format!("{url}/{path}");
+                ureq::get(&url)
+                    .call()
+                    .and_then(|mut res| {
+                        res.body_mut()
+                            .with_config()
+                            .limit(MAX_FILE_SIZE)
+                            .read_to_vec()
+                    })
+                    .with_context(|| format!("failed to download {url}"))
+            }
+            (_, Some(root)) => {
+                let path = root.join(path);
+                std::fs::read(&path).with_context(|| format!("failed to read {}", path.display()))
+            }
+            (_, None) => unreachable!("prepared"),
+        }
+    }
+}
+
+fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Whether `path` stays under the root, i.e. no `..`
+fn is_relative(path: &str) -> bool {
+    Path::new(path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update() {
+        let tmp = std::env::temp_dir().join(format!("ap-update-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&tmp);
+        let (remote, local) = (tmp.join("remote"), tmp.join("resources"));
+        std::fs::create_dir_all(remote.join("templates")).unwrap();
+        std::fs::write(remote.join("daily.toml"), "name = \"daily\"").unwrap();
+        std::fs::write(remote.join("templates/ok.png"), b"png").unwrap();
+        let mut manifest = Manifest::write(&remote).unwrap();
+        assert_eq!(manifest.files.len(), 2);
+
+        let updater = Updater::new(Source::parse(remote.to_str().unwrap()), &local);
+        let report = updater.update().unwrap();
+        assert_eq!(report.downloaded, ["daily.toml", "templates/ok.png"]);
+        assert_eq!(
+            std::fs::read(local.join("templates/ok.png")).unwrap(),
+            b"png"
+        );
+        assert!(!updater.update().unwrap().changed());
+
+        // Only the changed file is downloaded, the removed one is gone
+        std::fs::write(remote.join("daily.toml"), "name = \"daily2\"").unwrap();
+        std::fs::remove_file(remote.join("templates/ok.png")).unwrap();
+        manifest.files = Manifest::generate(&remote).unwrap().files;
+        manifest.last_updated += 1;
+        std::fs::write(remote.join(MANIFEST), manifest.to_toml().unwrap()).unwrap();
+        let report = updater.update().unwrap();
+        assert_eq!(report.downloaded, ["daily.toml"]);
+        assert_eq!(report.removed, ["templates/ok.png"]);
+        assert!(!local.join("templates/ok.png").exists());
+
+        // A corrupted download leaves the local version as it was
+        manifest
+            .files
+            .insert("daily.toml".to_string(), sha256(b"other"));
+        manifest.last_updated += 1;
+        std::fs::write(remote.join(MANIFEST), manifest.to_toml().unwrap()).unwrap();
+        let err = updater.update().unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("checksum mismatch for daily.toml")
+        );
+        assert_eq!(
+            std::fs::read_to_string(local.join("daily.toml")).unwrap(),
+            "name = \"daily2\""
+        );
+
+        manifest.files.insert("../escape".to_string(), sha256(b""));
+        std::fs::write(remote.join(MANIFEST), manifest.to_toml().unwrap()).unwrap();
+        assert!(updater.update().is_err());
+
+        std::fs::remove_dir_all(&tmp).unwrap();
+    }
+}
@@ -0,0 +1,451 @@
+//! Check a task before running it, so a typo in a template path does not fail it
+//! halfway through on the device.
+//!
+//! ```ignore
+//! for issue in ap.validate_task("daily") {
+//!     println!("{issue}");
+//! }
+//! ```
+//!
+//! Each [`Action`] reports what it references through [`Action::validate`], the
+//! tasks run by name are looked up in the [`Resource`] and checked in turn.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    action::{Action, Point, Region},
+    resource::Resource,
+    task::{Task, Value},
+};
+
+/// Something wrong with a step
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// The template file is missing or can not be decoded
+    Template { path: PathBuf, error: String },
+    /// No plugin is registered with this name
+    UnknownPlugin(String),
+    /// A point outside the screen
+    OutOfScreen { x: i64, y: i64 },
+    /// A region not entirely on the screen
+    RegionOutOfScreen(Region),
+    /// A color that is not `#RRGGBB`
+    InvalidColor(String),
+    /// No task of the resource has this name
+    UnknownTask(String),
+    /// The task can not be parsed, or its parameters substituted
+    InvalidTask(String),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Template { path, error } => {
+                write!(f, "failed to load template {}: {error}", path.display())
+            }
+            Problem::UnknownPlugin(name) => write!(f, "plugin {name} is not registered"),
+            Problem::OutOfScreen { x, y } => write!(f, "({x}, {y}) is outside the screen"),
+            Problem::RegionOutOfScreen(region) => write!(f, "{region:?} is outside the screen"),
+            Problem::InvalidColor(color) => {
+                write!(f, "invalid color {color:?}, expected #RRGGBB")
+            }
+            Problem::UnknownTask(name) => write!(f, "no task named {name} in the resource"),
+            Problem::InvalidTask(error) => write!(f, "invalid task: {error}"),
+        }
+    }
+}
+
+/// A [`Problem`] and the step it is in
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    /// The steps leading to it, i.e. `daily #2 Repeat > steps #0 ClickMatchTemplate`
+    pub location: String,
+    pub problem: Problem,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.problem)
+    }
+}
+
+/// Walks the steps of a task, collecting the [`Issue`]s reported by the actions.
+///
+/// The screen, the plugins and the tasks run by name are only checked when they
+/// are known, see [`AutoPlay::validate_task`](crate::AutoPlay::validate_task) for
+/// a task about to run on a device.
+#[derive(Default)]
+pub struct Validator {
+    screen_size: Option<(u32, u32)>,
+    plugins: Option<HashSet<String>>,
+    resource: Option<Arc<Resource>>,
+    /// The tasks run by name being walked, so a task running itself is walked once
+    walking: Vec<String>,
+    /// The error of each template checked, so each is only decoded once
+    templates: HashMap<PathBuf, Option<String>>,
+    location: Vec<String>,
+    issues: Vec<Issue>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check that the points and regions are on a screen of `size`.
+    pub fn with_screen_size(mut self, size: (u32, u32)) -> Self {
+        self.screen_size = Some(size);
+        self
+    }
+
+    /// Check that the plugins referenced are among `names`.
+    pub fn with_plugins(mut self, names: impl IntoIterator<Item = String>) -> Self {
+        self.plugins = Some(names.into_iter().collect());
+        self
+    }
+
+    /// Check that the tasks run by name are in `resource`, and their steps.
+    pub fn with_resource(mut self, resource: Arc<Resource>) -> Self {
+        self.resource = Some(resource);
+        self
+    }
+
+    pub fn validate(mut self, task: &Task) -> Vec<Issue> {
+        self.steps(&task.name, &task.steps);
+        self.issues
+    }
+
+    /// Check the task `name` of the resource, with the parameters in `args`.
+    pub fn validate_named(mut self, name: &str, args: &HashMap<String, Value>) -> Vec<Issue> {
+        self.task(name, args);
+        self.issues
+    }
+
+    /// Check the edges of the navigation graph of the resource, their steps and
+    /// the tasks they run.
+    pub fn validate_nav(mut self) -> Vec<Issue> {
+        let edges = match self
+            .resource
+            .as_ref()
+            .and_then(|resource| resource.nav_source())
+        {
+            Some(source) => crate::nav::edge_specs(source).expect("checked when loaded"),
+            None => return self.issues,
+        };
+        for edge in edges {
+            let name = format!("edge {} -> {}", edge.from, edge.to);
+            if let Some(steps) = &edge.steps {
+                self.steps(&name, steps);
+            }
+            if let Some(task) = &edge.task {
+                self.location.push(name);
+                self.task(task, &HashMap::new());
+                self.location.pop();
+            }
+        }
+        self.issues
+    }
+
+    /// Check `steps`, located as `name #index` in the issues.
+    pub fn steps(&mut self, name: &str, steps: &[Box<dyn Action>]) {
+        for (idx, step) in steps.iter().enumerate() {
+            self.location
+                .push(format!("{name} #{idx} {}", step.typetag_name()));
+            step.validate(self);
+            self.location.pop();
+        }
+    }
+
+    /// Check that the task `name` run with `args` is in the resource, and its
+    /// steps. Nothing is checked without a resource.
+    pub fn task(&mut self, name: &str, args: &HashMap<String, Value>) {
+        let Some(resource) = self.resource.clone() else {
+            return;
+        };
+        if self.walking.iter().any(|walking| walking == name) {
+            return;
+        }
+        let task = if resource.names().any(|task| task == name) {
+            resource
+                .task_with(name, args)
+                .map_err(|err| Problem::InvalidTask(format!("{err:#}")))
+        } else {
+            Err(Problem::UnknownTask(name.to_string()))
+        };
+        match task {
+            Ok(task) => {
+                self.walking.push(name.to_string());
+                self.steps(&task.name, &task.steps);
+                self.walking.pop();
+            }
+            Err(problem) => {
+                self.location.push(name.to_string());
+                self.report(problem);
+                self.location.pop();
+            }
+        }
+    }
+
+    pub fn report(&mut self, problem: Problem) {
+        self.issues.push(Issue {
+            location: self.location.join(" > "),
+            problem,
+        });
+    }
+
+    /// Check that the template at `path` decodes, or each of its variants if it
+    /// has some, see [`ap_cv::store::variants`].
+    pub fn template(&mut self, path: &Path) {
+        let variants = ap_cv::store::variants(path);
+        if variants.is_empty() {
+            return self.template_file(path);
+        }
+        for (_, variant) in variants {
+            self.template_file(&variant);
+        }
+    }
+
+    fn template_file(&mut self, path: &Path) {
+        let error = self
+            .templates
+            .entry(path.to_path_buf())
+            .or_insert_with(|| image::open(path).err().map(|err| err.to_string()))
+            .clone();
+        if let Some(error) = error {
+            self.report(Problem::Template {
+                path: path.to_path_buf(),
+                error,
+            });
+        }
+    }
+
+    pub fn plugin(&mut self, name: &str) {
+        if self
+            .plugins
+            .as_ref()
+            .is_some_and(|plugins| !plugins.contains(name))
+        {
+            self.report(Problem::UnknownPlugin(name.to_string()));
+        }
+    }
+
+    pub fn point(&mut self, x: u32, y: u32) {
+        self.pixel(x.into(), y.into());
+    }
+
+    /// Check a [`Point`], an anchored one is placed on a screen without black bars.
+    pub fn position(&mut self, point: Point) {
+        if let Some((width, height)) = self.screen_size {
+            let (x, y) = point.resolve_in(image::math::Rect {
+                x: 0,
+                y: 0,
+                width,
+                height,
+            });
+            self.pixel(x.into(), y.into());
+        }
+    }
+
+    fn pixel(&mut self, x: i64, y: i64) {
+        if self.screen_size.is_some_and(|(width, height)| {
+            x < 0 || y < 0 || x >= width.into() || y >= height.into()
+        }) {
+            self.report(Problem::OutOfScreen { x, y });
+        }
+    }
+
+    /// Check a [`Region`], an anchored one needs no screen size as it is only
+    /// required to be within the layout.
+    pub fn region(&mut self, region: Region) {
+        let outside = match region {
+            Region::Anchored(rect) => !rect.is_within_layout(),
+            Region::Pixels {
+                x,
+                y,
+                width,
+                height,
+            } => self
+                .screen_size
+                .is_some_and(|(screen_width, screen_height)| {
+                    x as u64 + width as u64 > screen_width as u64
+                        || y as u64 + height as u64 > screen_height as u64
+                }),
+        };
+        if outside {
+            self.report(Problem::RegionOutOfScreen(region));
+        }
+    }
+
+    pub fn color(&mut self, color: &str) {
+        if ap_cv::color::parse_hex(color).is_none() {
+            self.report(Problem::InvalidColor(color.to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TASK: &str = r##"
+name = "daily"
+
+[[steps]]
+Click = { x = 100, y = 2000 }
+
+[[steps]]
+ClickMatchTemplate = { template = "missing.png" }
+
+[[steps]]
+[steps.If]
+cond = { Color = { x = 1, y = 1, color = "red" } }
+then_steps = [{ PluginAction = { name = "claim" } }]
+else_steps = [{ PluginAction = { name = "skip" } }]
+
+[[steps]]
+CheckColor = { region = { x = 1800, y = 0, width = 200, height = 100 }, color = "#ffffff" }
+"##;
+
+    #[test]
+    fn test_validate() {
+        let task = Task::from_toml(TASK).unwrap();
+        let issues = Validator::new()
+            .with_screen_size((1920, 1080))
+            .with_plugins(["skip".to_string()])
+            .validate(&task);
+
+        let problems = issues
+            .iter()
+            .map(|issue| (issue.location.as_str(), &issue.problem))
+            .collect::<Vec<_>>();
+        assert_eq!(problems.len(), 5, "{issues:#?}");
+        assert_eq!(
+            problems[0],
+            ("daily #0 Click", &Problem::OutOfScreen { x: 100, y: 2000 })
+        );
+        assert_eq!(problems[1].0, "daily #1 ClickMatchTemplate");
+        assert!(matches!(problems[1].1, Problem::Template { .. }));
+        assert_eq!(
+            problems[2],
+            ("daily #2 If", &Problem::InvalidColor("red".to_string()))
+        );
+        assert_eq!(
+            problems[3],
+            (
+                "daily #2 If > then_steps #0 PluginAction",
+                &Problem::UnknownPlugin("claim".to_string())
+            )
+        );
+        assert!(matches!(problems[4].1, Problem::RegionOutOfScreen(_)));
+
+        // Without a device only the files are checked
+        let issues = Validator::new().validate(&task);
+        assert_eq!(issues.len(), 2);
+    }
+
+    #[test]
+    fn test_validate_named() {
+        let root = std::env::temp_dir().join(format!("ap-validate-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        let write = |name: &str, source: &str| std::fs::write(root.join(name), source).unwrap();
+        write(
+            "daily.toml",
+            r#"
+name = "daily"
+steps = [
+    { RunTask = { name = "farm", args = { stage = "1-7" } } },
+    { RunTask = { name = "farm" } },
+    { RunTask = { name = "weekly" } },
+    { RunTask = { name = "daily" } },
+]
+"#,
+        );
+        write(
+            "farm.toml",
+            r#"
+name = "farm"
+
+[params.stage]
+
+[[steps]]
+PluginAction = { name = "farm_${stage}" }
+"#,
+        );
+        write(
+            "nav.toml",
+            r#"
+nodes = [{ name = "home" }, { name = "shop" }]
+
+[[edges]]
+from = "home"
+to = "shop"
+task = "open_shop"
+
+[[edges]]
+from = "shop"
+to = "home"
+steps = [{ Click = { x = 5000, y = 0 } }]
+"#,
+        );
+        let resource = Arc::new(Resource::load(&root).unwrap());
+        let validator = || {
+            Validator::new()
+                .with_screen_size((1920, 1080))
+                .with_plugins(["farm_1-7".to_string()])
+                .with_resource(resource.clone())
+        };
+
+        let issues = validator().validate_named("daily", &HashMap::new());
+        let problems = issues
+            .iter()
+            .map(|issue| (issue.location.as_str(), &issue.problem))
+            .collect::<Vec<_>>();
+        assert_eq!(problems.len(), 2, "{issues:#?}");
+        // Only found once it is given no stage
+        assert_eq!(problems[0].0, "daily #1 RunTask > farm");
+        assert!(
+            matches!(problems[0].1, Problem::InvalidTask(error) if error.contains("missing parameter stage")),
+            "{issues:#?}"
+        );
+        assert_eq!(
+            problems[1],
+            (
+                "daily #2 RunTask > weekly",
+                &Problem::UnknownTask("weekly".to_string())
+            )
+        );
+
+        let issues = validator().validate_named("weekly", &HashMap::new());
+        assert_eq!(
+            issues[0].to_string(),
+            "weekly: no task named weekly in the resource"
+        );
+
+        let issues = validator().validate_nav();
+        let problems = issues
+            .iter()
+            .map(|issue| (issue.location.as_str(), &issue.problem))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            [
+                (
+                    "edge home -> shop > open_shop",
+                    &Problem::UnknownTask("open_shop".to_string())
+                ),
+                (
+                    "edge shop -> home #0 Click",
+                    &Problem::OutOfScreen { x: 5000, y: 0 }
+                ),
+            ]
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}